@@ -0,0 +1,58 @@
+use criterion::Criterion;
+use futures::StreamExt;
+use tokio::runtime::Runtime;
+use tokio_util::io::ReaderStream;
+
+// Large enough to be streamed in chunks, as static files over 64 KB are
+const BENCHMARK_FILE_SIZE: usize = 8 * 1024 * 1024;
+
+// Reads a file the way the static file processor streams files that are not cached, with the standard tokio file reads
+// and, when built with the "io-uring" feature, with io_uring, so the two can be compared with
+// cargo bench --features io-uring -- file_read
+pub fn file_read_benchmark(c: &mut Criterion) {
+    let rt = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            panic!("Failed to create Tokio runtime: {}", e);
+        }
+    };
+
+    let path = std::env::temp_dir().join("gruxi-file-read-benchmark.bin");
+    let content: Vec<u8> = (0..BENCHMARK_FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    if let Err(e) = std::fs::write(&path, &content) {
+        panic!("Failed to write benchmark file: {}", e);
+    }
+    let path = path.to_string_lossy().to_string();
+
+    c.bench_function("file_read_tokio_fs_stream", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                let file = tokio::fs::File::open(&path).await.unwrap();
+                let mut stream = ReaderStream::new(file);
+                let mut read = 0;
+                while let Some(chunk) = stream.next().await {
+                    read += chunk.unwrap().len();
+                }
+                assert_eq!(read, BENCHMARK_FILE_SIZE);
+            })
+        })
+    });
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if let Some(uring_file_reader) = gruxi::file::uring_file_reader::get_uring_file_reader() {
+        c.bench_function("file_read_io_uring_stream", |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let mut receiver = uring_file_reader.read_file(&path);
+                    let mut read = 0;
+                    while let Some(chunk) = receiver.recv().await {
+                        read += chunk.unwrap().len();
+                    }
+                    assert_eq!(read, BENCHMARK_FILE_SIZE);
+                })
+            })
+        });
+    }
+
+    let _ = std::fs::remove_file(&path);
+}
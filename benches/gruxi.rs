@@ -1,5 +1,6 @@
-mod syslog_benchmark;
+mod file_read;
 mod normalized_path;
+mod syslog_benchmark;
 
 use criterion::{criterion_group, criterion_main};
 
@@ -9,6 +10,7 @@ criterion_group!(
     syslog_benchmark::syslog_benchmark_without_stdout_single,
     syslog_benchmark::syslog_benchmark_without_stdout_high_concurrency,
     normalized_path::normalized_path_benchmark,
+    file_read::file_read_benchmark,
 );
 
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
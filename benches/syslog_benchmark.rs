@@ -1,5 +1,6 @@
 use criterion::Criterion;
 use gruxi::logging::syslog::*;
+use std::panic::Location;
 use tokio::runtime::Runtime;
 
 pub fn syslog_benchmark_internal(c: &mut Criterion) {
@@ -8,13 +9,13 @@ pub fn syslog_benchmark_internal(c: &mut Criterion) {
 
     c.bench_function("syslog_internal_trace_msg", |b| {
         b.iter(|| {
-            syslog.add_log(LogType::Trace, "This is a syslog trace message for benchmarking purposes.".to_string());
+            syslog.add_log(LogType::Trace, Location::caller(), "This is a syslog trace message for benchmarking purposes.".to_string());
         })
     });
 
     c.bench_function("syslog_internal_warn_msg", |b| {
         b.iter(|| {
-            syslog.add_log(LogType::Warn, "This is a syslog warn message for benchmarking purposes.".to_string());
+            syslog.add_log(LogType::Warn, Location::caller(), "This is a syslog warn message for benchmarking purposes.".to_string());
         })
     });
 }
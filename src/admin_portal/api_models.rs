@@ -0,0 +1,318 @@
+use crate::admin_portal::login_throttle::AttemptCounterInfo;
+use crate::configuration::configuration::Configuration;
+use crate::configuration::configuration_changes::ConfigurationChanges;
+use crate::configuration::site::Site;
+use crate::core::admin_user::{UserInfo, UserRole};
+use crate::core::site_statistics::SiteStatisticsSnapshot;
+use crate::deployment::site_builder::SiteBuild;
+use crate::file::disk_cache::DiskCacheStatistics;
+use crate::http::http_server::BindingStartError;
+use crate::logging::debug_capture::CapturedExchange;
+use crate::network::dns_cache::DnsCacheEntryInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+// Typed request and response bodies of the admin API, which the OpenAPI document is generated from
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ValidationErrorResponse {
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MessageResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConfigurationReloadResponse {
+    pub success: bool,
+    pub message: String,
+    // The bindings the reload could not start, such as when their port is in use. The other bindings are serving
+    pub binding_errors: Vec<BindingStartError>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LoginResponse {
+    pub success: bool,
+    pub message: String,
+    // Only returned without cookie sessions, as the token is otherwise kept in the HttpOnly session cookie
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_cookie: Option<bool>,
+    // Only returned with cookie sessions, to be sent in the X-CSRF-Token header on state-changing requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csrf_token: Option<String>,
+    pub username: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LoginAttemptsResponse {
+    pub ips: Vec<AttemptCounterInfo>,
+    pub usernames: Vec<AttemptCounterInfo>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SetupStatusResponse {
+    pub setup_required: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SetupResponse {
+    pub success: bool,
+    pub message: String,
+    // The admin portal is served on this port once the configuration is reloaded
+    pub admin_port: u16,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BasicDataResponse {
+    pub gruxi_version: String,
+    // Whether configuration changes are rejected, as the server was started with --read-only-conf
+    pub configuration_read_only: bool,
+    // Role of the logged in user, site owners only see and edit their own sites
+    pub role: UserRole,
+    // Sites owned by the logged in user, empty for admins
+    pub site_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConfigurationSaveResponse {
+    pub success: bool,
+    pub message: String,
+    // The configuration as saved, after sanitizing
+    pub configuration: Configuration,
+    // What was changed, as listed by the configuration preview
+    pub diff: Vec<String>,
+    // Problems that do not stop the configuration from being saved, such as hostnames of HTTPS sites their certificate does not cover
+    pub warnings: Vec<String>,
+    // The internationalized hostnames of the sites, stored in punycode, mapped to their Unicode form
+    pub hostname_display_names: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConfigurationPreviewResponse {
+    pub success: bool,
+    pub changes: ConfigurationChanges,
+    // Human-readable lines describing each change, empty if nothing would change
+    pub diff: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LogFileInfo {
+    pub filename: String,
+    pub size: u64,
+    pub path: String,
+    // Hostnames of the site, for PHP error logs of sites
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LogFilesResponse {
+    pub success: bool,
+    pub files: Vec<LogFileInfo>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LogFileContentResponse {
+    pub success: bool,
+    pub filename: String,
+    // The last ~1MB of the file, if it is larger than that
+    pub content: String,
+    pub file_size: u64,
+    pub is_truncated: bool,
+    pub full_path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OperationModeRequest {
+    pub mode: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OperationModeResponse {
+    pub mode: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OperationModeChangeResponse {
+    // False if the operation mode was already set
+    pub success: bool,
+    pub message: String,
+    pub mode: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct InServiceRequest {
+    // False to make the health check answer 503, so load balancers stop sending requests
+    pub in_service: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InServiceResponse {
+    pub in_service: bool,
+    // Whether a graceful shutdown is in progress, which keeps the server out of service
+    pub draining: bool,
+    // The path the health check is answered on, empty when it is not answered
+    pub health_check_path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LogLevelsRequest {
+    // One of off, error, warn, info, debug or trace, or empty for the level of the operation mode
+    #[serde(default)]
+    pub level: String,
+    // Levels of modules that differ from it, keyed by http, fastcgi, admin or tls
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LogLevelsResponse {
+    // The set log level, empty when it follows the operation mode
+    pub level: String,
+    // The log level in effect for modules without a level of their own
+    pub effective_level: String,
+    pub modules: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DnsCacheResponse {
+    pub ttl_seconds: u64,
+    pub entries: Vec<DnsCacheEntryInfo>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DebugCaptureSiteCount {
+    pub site_id: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DebugCaptureSitesResponse {
+    pub success: bool,
+    pub sites: Vec<DebugCaptureSiteCount>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DebugCaptureResponse {
+    pub success: bool,
+    pub site_id: String,
+    pub captures: Vec<CapturedExchange>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SiteStatisticsResponse {
+    pub success: bool,
+    pub sites: Vec<SiteStatisticsSnapshot>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SiteStatisticsSiteResponse {
+    pub success: bool,
+    pub site: SiteStatisticsSnapshot,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeployResponse {
+    pub success: bool,
+    pub site_id: String,
+    // The release the started build creates, the site is switched to it once the build succeeds
+    pub release_id: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeployStatusResponse {
+    pub success: bool,
+    pub site_id: String,
+    // The release the site is served from, None if it has not been built yet
+    pub active_release_id: Option<String>,
+    pub last_build: Option<SiteBuild>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UsersResponse {
+    pub success: bool,
+    pub users: Vec<UserInfo>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SiteSaveResponse {
+    pub success: bool,
+    pub message: String,
+    // The site as saved, after sanitizing
+    pub site: Option<Site>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EmailTestRequest {
+    // Who gets the test email
+    pub to: String,
+}
+
+// A certificate obtained by an external ACME client, given either as PEM content or as the paths of its files, such as the
+// fullchain.pem and privkey.pem of certbot
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CertificateInstallRequest {
+    #[serde(default)]
+    pub certificate_pem: String,
+    #[serde(default)]
+    pub private_key_pem: String,
+    #[serde(default)]
+    pub certificate_path: String,
+    #[serde(default)]
+    pub private_key_path: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TrafficSplitRequest {
+    // Percentage of the clients getting variant B, from 0 to 100, or null to go back to the percentage in the configuration
+    pub variant_b_percentage: Option<u32>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrafficSplitResponse {
+    pub site_id: String,
+    pub enabled: bool,
+    // The percentage in use, which is the configured one unless it was changed through the admin API since the server started
+    pub variant_b_percentage: u32,
+    pub configured_variant_b_percentage: u32,
+    pub sticky_by: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DiskCacheResponse {
+    // Limits of all disk caches together, from the server settings, 0 for none
+    pub max_size_bytes: u64,
+    pub max_unused_hours: u64,
+    pub caches: Vec<DiskCacheStatistics>,
+}
+
+// Removes the cached entries of a site with the path prefix, such as "/blog/", or the exact URL, such as
+// "https://example.com/blog/?page=2", or all of them when neither is given
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct DiskCachePurgeRequest {
+    #[serde(default)]
+    pub path_prefix: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DiskCachePurgeResponse {
+    pub site_id: String,
+    pub removed_entries: usize,
+}
@@ -1,25 +1,246 @@
+use crate::admin_portal::api_models::{
+    BasicDataResponse, CertificateInstallRequest, ConfigurationPreviewResponse, ConfigurationReloadResponse, ConfigurationSaveResponse, DebugCaptureResponse, DebugCaptureSiteCount,
+    DebugCaptureSitesResponse, DeployResponse, DeployStatusResponse, DiskCachePurgeRequest, DiskCachePurgeResponse, DiskCacheResponse, DnsCacheResponse, EmailTestRequest, ErrorResponse,
+    InServiceRequest, InServiceResponse, LogFileContentResponse, LogFileInfo, LogFilesResponse, LogLevelsRequest, LogLevelsResponse, LoginAttemptsResponse, LoginResponse, MessageResponse,
+    OperationModeChangeResponse, OperationModeRequest, OperationModeResponse, SetupResponse, SetupStatusResponse, SiteSaveResponse, SiteStatisticsResponse, SiteStatisticsSiteResponse,
+    TrafficSplitRequest, TrafficSplitResponse, UsersResponse, ValidationErrorResponse,
+};
+use crate::admin_portal::login_throttle::{LoginThrottleResult, get_login_throttle};
+use crate::admin_portal::openapi::get_openapi_json;
+use crate::admin_portal::setup::{SetupError, SetupRequest, get_setup_state};
+use crate::configuration::admin_portal::AdminPortal;
+use crate::configuration::cached_configuration::get_cached_configuration;
 use crate::configuration::configuration::Configuration;
+use crate::configuration::configuration_changes::{ConfigurationChanges, get_configuration_diff};
+use crate::configuration::configuration_check::{get_certificate_hostname_warnings, get_insecure_upstream_tls_warnings};
+use crate::configuration::configuration_schema::get_configuration_schema_json;
+use crate::configuration::load_configuration::fetch_configuration_in_db;
 use crate::configuration::save_configuration::save_configuration;
-use crate::configuration::site::Site;
-use crate::core::admin_user::{LoginRequest, authenticate_user, create_session, invalidate_session, verify_session_token};
+use crate::configuration::site::{Site, get_hostname_display_names};
+use crate::core::admin_user::{
+    LoginRequest, MIN_PASSWORD_LENGTH, PASSWORD_RESET_TOKEN_LIFETIME_MINUTES, PasswordResetEmailRequest, PasswordResetRequest, Session, UserRequest, authenticate_user, create_password_reset_token,
+    create_session, delete_user, invalidate_session, list_users, reset_password_with_token, save_user, verify_session_token,
+};
+use crate::core::binary_upgrade::begin_binary_upgrade;
+use crate::core::command_line_args::cmd_read_only_configuration;
+use crate::core::email::{EmailTemplate, get_email_queue};
+use crate::core::graceful_shutdown::{get_health_check_response, is_draining, is_in_service, set_in_service};
 use crate::core::monitoring::get_monitoring_state;
+use crate::core::monitoring_history::{HistoryPage, HistoryQuery};
 use crate::core::operation_mode::{get_operation_mode_as_string, is_valid_operation_mode, set_new_operation_mode};
+use crate::core::running_state_manager::reload_configuration_and_wait;
+use crate::core::server_status::{ServerStatus, get_server_status};
+use crate::core::site_statistics::get_site_statistics;
 use crate::core::triggers::get_trigger_handler;
+use crate::deployment::git_webhook::{GitWebhookEvent, parse_git_webhook_event, verify_git_webhook};
+use crate::deployment::site_builder::{get_site_builder, is_deploy_webhook_token_valid};
 use crate::error::gruxi_error::GruxiError;
 use crate::error::gruxi_error_enums::{AdminApiError, GruxiErrorKind};
-use crate::file::normalized_path::{NormalizedPath};
+use crate::file::disk_cache::{DiskCachePurge, get_disk_cache_manager};
+use crate::file::normalized_path::NormalizedPath;
+use crate::http::etag::apply_etag;
 use crate::http::request_response::gruxi_request::GruxiRequest;
 use crate::http::request_response::gruxi_response::GruxiResponse;
-use crate::logging::syslog::{debug, error, info, trace};
+use crate::http::traffic_split::get_traffic_split_percentages;
+use crate::logging::debug_capture::get_debug_capture;
+use crate::logging::syslog::{SysLog, debug, error, info, parse_log_levels, trace, warn};
+use crate::network::dns_cache::get_dns_cache;
+use crate::network::smtp_client::{OutgoingEmail, send_email};
+use crate::tls::external_acme::validate_certificate_and_key;
 use http::HeaderValue;
-use serde::{Deserialize, Serialize};
 use serde_json;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio_util::bytes;
 
 const JSON_HEADER_VALUE: HeaderValue = HeaderValue::from_static("application/json");
+// Name of the HttpOnly cookie holding the session token when cookie sessions are enabled
+const SESSION_COOKIE_NAME: &str = "gruxi_session";
+// Header the admin portal sends the CSRF token of the session in, on state-changing requests authenticated by the session cookie
+const CSRF_TOKEN_HEADER: &str = "X-CSRF-Token";
+// Header deploy webhooks send the deploy webhook token of the site in
+const DEPLOY_TOKEN_HEADER: &str = "X-Gruxi-Deploy-Token";
 const TEXT_PLAIN_HEADER_VALUE: HeaderValue = HeaderValue::from_static("text/plain");
+// How long a configuration reload is waited for, to report the bindings it could not start
+const RELOAD_REPORT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The routes of the admin API. They are listed here rather than matched inline, so the OpenAPI document can be checked against them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdminApiRoute {
+    Login,
+    LoginAttempts,
+    GetSetup,
+    PostSetup,
+    PasswordReset,
+    PasswordResetEmail,
+    Logout,
+    Basic,
+    GetConfig,
+    PostConfig,
+    PreviewConfig,
+    Monitoring,
+    MonitoringHistory,
+    SiteStatistics,
+    SiteStatisticsSite,
+    Healthcheck,
+    Healthz,
+    ServerStatus,
+    GetInService,
+    PostInService,
+    Logs,
+    LogFile,
+    ConfigurationReload,
+    GetOperationMode,
+    PostOperationMode,
+    GetLogLevels,
+    PostLogLevels,
+    DebugCaptureSites,
+    DebugCaptureSite,
+    DeleteDebugCaptureSite,
+    OpenApi,
+    ConfigurationSchema,
+    ServerUpgrade,
+    DnsCache,
+    EmailTest,
+    PostDeploy,
+    GetDeploy,
+    DeployWebhook,
+    GitWebhook,
+    GetUsers,
+    PostUser,
+    DeleteUser,
+    GetSite,
+    PostSite,
+    InstallCertificate,
+    GetTrafficSplit,
+    PostTrafficSplit,
+    DiskCache,
+    PurgeDiskCache,
+}
+
+impl AdminApiRoute {
+    pub const ALL: [AdminApiRoute; 49] = [
+        AdminApiRoute::Login,
+        AdminApiRoute::LoginAttempts,
+        AdminApiRoute::GetSetup,
+        AdminApiRoute::PostSetup,
+        AdminApiRoute::PasswordReset,
+        AdminApiRoute::PasswordResetEmail,
+        AdminApiRoute::Logout,
+        AdminApiRoute::Basic,
+        AdminApiRoute::GetConfig,
+        AdminApiRoute::PostConfig,
+        AdminApiRoute::PreviewConfig,
+        AdminApiRoute::Monitoring,
+        AdminApiRoute::MonitoringHistory,
+        AdminApiRoute::SiteStatistics,
+        AdminApiRoute::SiteStatisticsSite,
+        AdminApiRoute::Healthcheck,
+        AdminApiRoute::Healthz,
+        AdminApiRoute::ServerStatus,
+        AdminApiRoute::GetInService,
+        AdminApiRoute::PostInService,
+        AdminApiRoute::Logs,
+        AdminApiRoute::LogFile,
+        AdminApiRoute::ConfigurationReload,
+        AdminApiRoute::GetOperationMode,
+        AdminApiRoute::PostOperationMode,
+        AdminApiRoute::GetLogLevels,
+        AdminApiRoute::PostLogLevels,
+        AdminApiRoute::DebugCaptureSites,
+        AdminApiRoute::DebugCaptureSite,
+        AdminApiRoute::DeleteDebugCaptureSite,
+        AdminApiRoute::OpenApi,
+        AdminApiRoute::ConfigurationSchema,
+        AdminApiRoute::ServerUpgrade,
+        AdminApiRoute::DnsCache,
+        AdminApiRoute::EmailTest,
+        AdminApiRoute::PostDeploy,
+        AdminApiRoute::GetDeploy,
+        AdminApiRoute::DeployWebhook,
+        AdminApiRoute::GitWebhook,
+        AdminApiRoute::GetUsers,
+        AdminApiRoute::PostUser,
+        AdminApiRoute::DeleteUser,
+        AdminApiRoute::GetSite,
+        AdminApiRoute::PostSite,
+        AdminApiRoute::InstallCertificate,
+        AdminApiRoute::GetTrafficSplit,
+        AdminApiRoute::PostTrafficSplit,
+        AdminApiRoute::DiskCache,
+        AdminApiRoute::PurgeDiskCache,
+    ];
+
+    // The method and path of the route, with path parameters in braces like in the OpenAPI document
+    pub fn get_method_and_path(&self) -> (&'static str, &'static str) {
+        match self {
+            AdminApiRoute::Login => ("POST", "/login"),
+            AdminApiRoute::LoginAttempts => ("GET", "/login-attempts"),
+            AdminApiRoute::GetSetup => ("GET", "/setup"),
+            AdminApiRoute::PostSetup => ("POST", "/setup"),
+            AdminApiRoute::PasswordReset => ("POST", "/password-reset"),
+            AdminApiRoute::PasswordResetEmail => ("POST", "/password-reset/email"),
+            AdminApiRoute::Logout => ("POST", "/logout"),
+            AdminApiRoute::Basic => ("GET", "/basic"),
+            AdminApiRoute::GetConfig => ("GET", "/config"),
+            AdminApiRoute::PostConfig => ("POST", "/config"),
+            AdminApiRoute::PreviewConfig => ("POST", "/config/preview"),
+            AdminApiRoute::Monitoring => ("GET", "/monitoring"),
+            AdminApiRoute::MonitoringHistory => ("GET", "/monitoring/history"),
+            AdminApiRoute::SiteStatistics => ("GET", "/site-statistics"),
+            AdminApiRoute::SiteStatisticsSite => ("GET", "/site-statistics/{site_id}"),
+            AdminApiRoute::Healthcheck => ("GET", "/healthcheck"),
+            AdminApiRoute::Healthz => ("GET", "/healthz"),
+            AdminApiRoute::ServerStatus => ("GET", "/server/status"),
+            AdminApiRoute::GetInService => ("GET", "/server/in-service"),
+            AdminApiRoute::PostInService => ("POST", "/server/in-service"),
+            AdminApiRoute::Logs => ("GET", "/logs"),
+            AdminApiRoute::LogFile => ("GET", "/logs/{filename}"),
+            AdminApiRoute::ConfigurationReload => ("POST", "/configuration/reload"),
+            AdminApiRoute::GetOperationMode => ("GET", "/operation-mode"),
+            AdminApiRoute::PostOperationMode => ("POST", "/operation-mode"),
+            AdminApiRoute::GetLogLevels => ("GET", "/log-levels"),
+            AdminApiRoute::PostLogLevels => ("POST", "/log-levels"),
+            AdminApiRoute::DebugCaptureSites => ("GET", "/debug-capture"),
+            AdminApiRoute::DebugCaptureSite => ("GET", "/debug-capture/{site_id}"),
+            AdminApiRoute::DeleteDebugCaptureSite => ("DELETE", "/debug-capture/{site_id}"),
+            AdminApiRoute::OpenApi => ("GET", "/api/openapi.json"),
+            AdminApiRoute::ConfigurationSchema => ("GET", "/api/configuration/schema"),
+            AdminApiRoute::ServerUpgrade => ("POST", "/server/upgrade"),
+            AdminApiRoute::DnsCache => ("GET", "/server/dns-cache"),
+            AdminApiRoute::EmailTest => ("POST", "/server/email-test"),
+            AdminApiRoute::PostDeploy => ("POST", "/deploy/{site_id}"),
+            AdminApiRoute::GetDeploy => ("GET", "/deploy/{site_id}"),
+            AdminApiRoute::DeployWebhook => ("POST", "/deploy-webhook/{site_id}"),
+            AdminApiRoute::GitWebhook => ("POST", "/git-webhook/{site_id}"),
+            AdminApiRoute::GetUsers => ("GET", "/users"),
+            AdminApiRoute::PostUser => ("POST", "/users"),
+            AdminApiRoute::DeleteUser => ("DELETE", "/users/{username}"),
+            AdminApiRoute::GetSite => ("GET", "/sites/{site_id}"),
+            AdminApiRoute::PostSite => ("POST", "/sites/{site_id}"),
+            AdminApiRoute::InstallCertificate => ("POST", "/certificates/{site_id}"),
+            AdminApiRoute::GetTrafficSplit => ("GET", "/traffic-split/{site_id}"),
+            AdminApiRoute::PostTrafficSplit => ("POST", "/traffic-split/{site_id}"),
+            AdminApiRoute::DiskCache => ("GET", "/disk-cache"),
+            AdminApiRoute::PurgeDiskCache => ("POST", "/disk-cache/purge/{site_id}"),
+        }
+    }
+
+    pub fn from_request(method: &str, path: &str) -> Option<AdminApiRoute> {
+        Self::ALL.into_iter().find(|route| {
+            let (route_method, route_path) = route.get_method_and_path();
+            if route_method != method {
+                return false;
+            }
+            // A path parameter matches a single non-empty path segment
+            match route_path.split_once('{') {
+                Some((prefix, _)) => path.strip_prefix(prefix).is_some_and(|parameter| !parameter.is_empty() && !parameter.contains('/')),
+                None => path == route_path,
+            }
+        })
+    }
+}
 
 pub async fn handle_api_routes(gruxi_request: &mut GruxiRequest, site: &Site) -> Result<GruxiResponse, GruxiError> {
     let path = gruxi_request.get_path();
@@ -39,37 +260,74 @@ pub async fn handle_api_routes(gruxi_request: &mut GruxiRequest, site: &Site) ->
     trace(format!("Handling request for admin portal with path: {}", path_cleaned));
 
     // We only want to handle a few paths in the admin portal
-    let response_result = if path_cleaned == "/login" && method == "POST" {
-        handle_login_request(gruxi_request, site).await
-    } else if path_cleaned == "/logout" && method == "POST" {
-        handle_logout_request(gruxi_request, site).await
-    } else if path_cleaned == "/basic" && method == "GET" {
-        admin_get_basic_data_endpoint(gruxi_request, site).await
-    } else if path_cleaned == "/config" && method == "GET" {
-        admin_get_configuration_endpoint(gruxi_request, site).await
-    } else if path_cleaned == "/config" && method == "POST" {
-        admin_post_configuration_endpoint(gruxi_request, site).await
-    } else if path_cleaned == "/monitoring" && method == "GET" {
-        admin_monitoring_endpoint(gruxi_request, site).await
-    } else if path_cleaned == "/healthcheck" && method == "GET" {
-        admin_healthcheck_endpoint(gruxi_request, site).await
-    } else if (path_cleaned == "/logs" || path_cleaned.starts_with("/logs/")) && method == "GET" {
-        admin_logs_endpoint(gruxi_request, site).await
-    } else if (path_cleaned == "/configuration/reload") && method == "POST" {
-        admin_post_configuration_reload(gruxi_request, site).await
-    } else if path_cleaned == "/operation-mode" && method == "GET" {
-        admin_get_operation_mode_endpoint(gruxi_request, site).await
-    } else if path_cleaned == "/operation-mode" && method == "POST" {
-        admin_post_operation_mode_endpoint(gruxi_request, site).await
-    } else {
-        // If we reach here, no matching admin API route was found
-        trace(format!("No matching admin API route found for path: {}", path_cleaned));
-        Err(GruxiError::new_with_kind_only(GruxiErrorKind::AdminApi(AdminApiError::NoRouteMatched)))
+    let response_result = match AdminApiRoute::from_request(&method, &path_cleaned) {
+        Some(AdminApiRoute::Login) => handle_login_request(gruxi_request, site).await,
+        Some(AdminApiRoute::LoginAttempts) => admin_login_attempts_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::GetSetup) => admin_get_setup_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PostSetup) => admin_post_setup_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PasswordReset) => handle_password_reset_request(gruxi_request, site).await,
+        Some(AdminApiRoute::PasswordResetEmail) => handle_password_reset_email_request(gruxi_request, site).await,
+        Some(AdminApiRoute::Logout) => handle_logout_request(gruxi_request, site).await,
+        Some(AdminApiRoute::Basic) => admin_get_basic_data_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::GetConfig) => admin_get_configuration_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PostConfig) => admin_post_configuration_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PreviewConfig) => admin_preview_configuration_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::Monitoring) => admin_monitoring_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::MonitoringHistory) => admin_monitoring_history_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::SiteStatistics) => admin_site_statistics_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::SiteStatisticsSite) => admin_site_statistics_site_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::Healthcheck) => admin_healthcheck_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::Healthz) => admin_healthz_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::ServerStatus) => admin_server_status_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::GetInService) => admin_get_in_service_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PostInService) => admin_post_in_service_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::Logs) => admin_logs_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::LogFile) => admin_log_file_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::ConfigurationReload) => admin_post_configuration_reload(gruxi_request, site).await,
+        Some(AdminApiRoute::GetOperationMode) => admin_get_operation_mode_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PostOperationMode) => admin_post_operation_mode_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::GetLogLevels) => admin_get_log_levels_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PostLogLevels) => admin_post_log_levels_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::DebugCaptureSites) => admin_debug_capture_sites_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::DebugCaptureSite) => admin_debug_capture_site_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::DeleteDebugCaptureSite) => admin_clear_debug_capture_site_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::OpenApi) => admin_openapi_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::ConfigurationSchema) => admin_configuration_schema_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::ServerUpgrade) => admin_post_server_upgrade_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::DnsCache) => admin_dns_cache_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::EmailTest) => admin_post_email_test_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PostDeploy) => admin_post_deploy_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::GetDeploy) => admin_get_deploy_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::DeployWebhook) => deploy_webhook_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::GitWebhook) => git_webhook_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::GetUsers) => admin_get_users_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PostUser) => admin_post_user_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::DeleteUser) => admin_delete_user_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::GetSite) => admin_get_site_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PostSite) => admin_post_site_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::InstallCertificate) => admin_install_certificate_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::GetTrafficSplit) => admin_get_traffic_split_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PostTrafficSplit) => admin_post_traffic_split_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::DiskCache) => admin_disk_cache_endpoint(gruxi_request, site).await,
+        Some(AdminApiRoute::PurgeDiskCache) => admin_purge_disk_cache_endpoint(gruxi_request, site).await,
+        None => {
+            // If we reach here, no matching admin API route was found
+            trace(format!("No matching admin API route found for path: {}", path_cleaned));
+            Err(GruxiError::new_with_kind_only(GruxiErrorKind::AdminApi(AdminApiError::NoRouteMatched)))
+        }
     };
 
-    response_result
+    // GET endpoints get an ETag, so the admin portal can poll with If-None-Match and get a 304 if nothing changed
+    let mut response = response_result?;
+    apply_etag(gruxi_request, &mut response).await;
+    Ok(response)
 }
 
+#[utoipa::path(post, path = "/login", tag = "Authentication", request_body = LoginRequest, responses(
+        (status = 200, description = "Logged in, with the session token or, with cookie sessions, the CSRF token", body = LoginResponse),
+        (status = 401, description = "Invalid username or password", body = ErrorResponse),
+        (status = 429, description = "Too many failed login attempts, see the Retry-After header", body = ErrorResponse),
+    ))]
 pub async fn handle_login_request(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check if this is a POST request
     if gruxi_request.get_http_method() != "POST" {
@@ -94,11 +352,34 @@ pub async fn handle_login_request(gruxi_request: &mut GruxiRequest, _admin_site:
 
     debug(format!("Login attempt for username: {}", login_request.username));
 
+    // Brute-force protection, the password is not even checked while the IP or username is backing off or locked out. The attempt counts
+    // as failed until the password turns out to be right
+    let remote_ip = gruxi_request.get_remote_ip();
+    let login_throttle = get_login_throttle();
+    if let LoginThrottleResult::Blocked(retry_after) = login_throttle.reserve_attempt(&remote_ip, &login_request.username, std::time::Instant::now()) {
+        let retry_after_seconds = retry_after.as_secs_f64().ceil() as u64;
+        info(format!(
+            "Audit: Throttled login attempt for username '{}' from {}, retry allowed in {} seconds",
+            login_request.username, remote_ip, retry_after_seconds
+        ));
+        let error_json = serde_json::json!({ "error": "Too many failed login attempts, try again later", "retry_after_seconds": retry_after_seconds });
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::TOO_MANY_REQUESTS.as_u16(), bytes::Bytes::from(error_json.to_string()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        response.headers_mut().insert(hyper::header::RETRY_AFTER, HeaderValue::from(retry_after_seconds));
+        return Ok(response);
+    }
+
     // Authenticate user
     let user = match authenticate_user(&login_request.username, &login_request.password) {
         Ok(Some(user)) => user,
         Ok(None) => {
-            info(format!("Failed login attempt for username: {}", login_request.username));
+            info(format!("Audit: Failed login attempt for username '{}' from {}", login_request.username, remote_ip));
+            if login_throttle.is_locked_out(&remote_ip, &login_request.username, std::time::Instant::now()) {
+                warn(format!(
+                    "Audit: Login locked out for username '{}' and/or {} after repeated failed attempts",
+                    login_request.username, remote_ip
+                ));
+            }
             let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Invalid username or password"}"#));
             response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
             return Ok(response);
@@ -112,7 +393,13 @@ pub async fn handle_login_request(gruxi_request: &mut GruxiRequest, _admin_site:
     };
 
     // Create session
-    let session = match create_session(&user) {
+    let admin_portal_settings = get_admin_portal_settings().await;
+    let session = match create_session(
+        &user,
+        chrono::Duration::hours(admin_portal_settings.session_absolute_timeout_hours as i64),
+        admin_portal_settings.session_max_per_user,
+        admin_portal_settings.session_token_bytes,
+    ) {
         Ok(session) => session,
         Err(e) => {
             error(format!("Failed to create session: {}", e));
@@ -123,22 +410,241 @@ pub async fn handle_login_request(gruxi_request: &mut GruxiRequest, _admin_site:
         }
     };
 
-    info(format!("Successful login for user: {}", user.username));
-
-    // Return success response with session token
-    let response_json = serde_json::json!({
-        "success": true,
-        "message": "Login successful",
-        "session_token": session.token,
-        "username": session.username,
-        "expires_at": session.expires_at.to_rfc3339()
-    });
+    login_throttle.record_success(&remote_ip, &login_request.username);
+    info(format!("Audit: Successful login for user '{}' from {}", user.username, remote_ip));
+
+    // Return success response with session token. With cookie sessions, the token is only sent in the HttpOnly cookie, so scripts cannot read it,
+    // and the client gets the CSRF token to send along with state-changing requests instead
+    let is_cookie_session = admin_portal_settings.session_cookie_enabled;
+    let login_response = LoginResponse {
+        success: true,
+        message: "Login successful".to_string(),
+        session_token: if is_cookie_session { None } else { Some(session.token.clone()) },
+        session_cookie: if is_cookie_session { Some(true) } else { None },
+        csrf_token: if is_cookie_session { Some(session.csrf_token.clone()) } else { None },
+        username: session.username.clone(),
+        expires_at: session.expires_at.to_rfc3339(),
+    };
 
-    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json.to_string()));
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&login_response).unwrap_or_default()));
     response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    if admin_portal_settings.session_cookie_enabled {
+        let max_age = (session.expires_at - session.created_at).num_seconds();
+        set_session_cookie(&mut response, &session.token, max_age, &admin_portal_settings.session_cookie_same_site);
+    }
     return Ok(response);
 }
 
+// Tells the admin portal whether to show the setup wizard instead of the login form. Needs no session, as there are no users yet during setup
+#[utoipa::path(get, path = "/setup", tag = "Setup", responses(
+        (status = 200, description = "Whether the first-run setup still has to be completed", body = SetupStatusResponse),
+    ))]
+pub async fn admin_get_setup_endpoint(_gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let setup_status = SetupStatusResponse {
+        setup_required: get_setup_state().is_setup_required(),
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&setup_status).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Creates the first admin user on a fresh install and applies the basic settings, after which setup is locked
+#[utoipa::path(post, path = "/setup", tag = "Setup", request_body = SetupRequest, responses(
+        (status = 200, description = "Setup completed and locked", body = SetupResponse),
+        (status = 400, description = "Invalid user or settings", body = ErrorResponse),
+        (status = 401, description = "Invalid setup token", body = ErrorResponse),
+        (status = 409, description = "Setup has already been completed", body = ErrorResponse),
+    ))]
+pub async fn admin_post_setup_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let setup_request: SetupRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            error(format!("Failed to parse setup request: {}", e));
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(r#"{"error": "Invalid JSON format for setup"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    let remote_ip = gruxi_request.get_remote_ip();
+    let (status, response_json) = match get_setup_state().complete_setup(&setup_request) {
+        Ok(()) => {
+            info(format!("Audit: Setup completed from {}, admin user '{}' created", remote_ip, setup_request.username));
+
+            // With a read-only configuration, the chosen settings are not applied
+            let is_read_only = cmd_read_only_configuration();
+            let admin_port = match setup_request.admin_port {
+                Some(port) if !is_read_only => port,
+                _ => get_admin_portal_settings().await.port,
+            };
+
+            // Apply the new settings, like the admin portal port and the default site
+            if !is_read_only {
+                let triggers = get_trigger_handler();
+                triggers.run_trigger("refresh_cached_configuration").await;
+                triggers.run_trigger("reload_configuration").await;
+            }
+
+            let message = if is_read_only {
+                "Setup completed, please log in. The configuration is read-only, so the admin portal port and default site settings were not applied"
+            } else {
+                "Setup completed, please log in"
+            };
+            let setup_response = SetupResponse {
+                success: true,
+                message: message.to_string(),
+                admin_port,
+            };
+            (hyper::StatusCode::OK, serde_json::to_value(&setup_response).unwrap_or_default())
+        }
+        Err(SetupError::NotAvailable) => {
+            info(format!("Audit: Setup attempted from {} after it was completed", remote_ip));
+            (hyper::StatusCode::CONFLICT, serde_json::json!({ "error": "Setup has already been completed" }))
+        }
+        Err(SetupError::InvalidToken) => {
+            warn(format!("Audit: Setup attempted with an invalid setup token from {}", remote_ip));
+            (hyper::StatusCode::UNAUTHORIZED, serde_json::json!({ "error": "Invalid setup token" }))
+        }
+        Err(SetupError::InvalidRequest(e)) => (hyper::StatusCode::BAD_REQUEST, serde_json::json!({ "error": e })),
+        Err(SetupError::Internal(e)) => {
+            error(format!("Failed to complete setup: {}", e));
+            (hyper::StatusCode::INTERNAL_SERVER_ERROR, serde_json::json!({ "error": "Internal server error" }))
+        }
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(status.as_u16(), bytes::Bytes::from(response_json.to_string()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Exchanges a one-time token from "gruxi admin reset-password <user>" for a new password. No session is needed, as it is meant for locked out admins
+#[utoipa::path(post, path = "/password-reset", tag = "Authentication", request_body = PasswordResetRequest, responses(
+        (status = 200, description = "Password changed and all sessions signed out", body = MessageResponse),
+        (status = 400, description = "Invalid request or password too short", body = ErrorResponse),
+        (status = 401, description = "Invalid or expired password reset token", body = ErrorResponse),
+    ))]
+pub async fn handle_password_reset_request(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let reset_request: PasswordResetRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            error(format!("Failed to parse password reset request: {}", e));
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(r#"{"error": "Invalid JSON format for password reset"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    if reset_request.new_password.chars().count() < MIN_PASSWORD_LENGTH {
+        let error_json = serde_json::json!({ "error": format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH) });
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_json.to_string()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    }
+
+    let remote_ip = gruxi_request.get_remote_ip();
+    let (status, response_json) = match reset_password_with_token(&reset_request.token, &reset_request.new_password) {
+        Ok(Some(username)) => {
+            info(format!("Audit: Password reset with one-time token for user '{}' from {}", username, remote_ip));
+            (
+                hyper::StatusCode::OK,
+                serde_json::json!({ "success": true, "message": "Password changed, please log in with the new password" }),
+            )
+        }
+        Ok(None) => {
+            info(format!("Audit: Password reset attempted with an invalid or expired token from {}", remote_ip));
+            (hyper::StatusCode::UNAUTHORIZED, serde_json::json!({ "error": "Invalid or expired password reset token" }))
+        }
+        Err(e) => {
+            error(format!("Failed to reset password: {}", e));
+            (hyper::StatusCode::INTERNAL_SERVER_ERROR, serde_json::json!({ "error": "Internal server error" }))
+        }
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(status.as_u16(), bytes::Bytes::from(response_json.to_string()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Emails a one-time password reset token for the user to the notification recipients in the SMTP settings, for admins locked out of the
+// portal without access to the "gruxi admin reset-password" command. The answer is the same whether or not the user exists, and the
+// requests are throttled like failed logins, so it can neither be used to find users nor to flood the recipients
+#[utoipa::path(post, path = "/password-reset/email", tag = "Authentication", request_body = PasswordResetEmailRequest, responses(
+        (status = 202, description = "A token is emailed to the notification recipients if the user exists and email is configured", body = MessageResponse),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 429, description = "Too many attempts, try again later", body = ErrorResponse),
+    ))]
+pub async fn handle_password_reset_email_request(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let email_request: PasswordResetEmailRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            error(format!("Failed to parse password reset email request: {}", e));
+            let mut response = GruxiResponse::new_with_bytes(
+                hyper::StatusCode::BAD_REQUEST.as_u16(),
+                bytes::Bytes::from(r#"{"error": "Invalid JSON format for password reset email"}"#),
+            );
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    // Counted under a name of its own, so these requests do not lock the user out of logging in
+    let remote_ip = gruxi_request.get_remote_ip();
+    let throttle_key = format!("password-reset:{}", email_request.username);
+    if let LoginThrottleResult::Blocked(retry_after) = get_login_throttle().reserve_attempt(&remote_ip, &throttle_key, std::time::Instant::now()) {
+        let retry_after_seconds = retry_after.as_secs_f64().ceil() as u64;
+        info(format!("Audit: Throttled password reset email for username '{}' from {}", email_request.username, remote_ip));
+        let error_json = serde_json::json!({ "error": "Too many attempts, try again later", "retry_after_seconds": retry_after_seconds });
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::TOO_MANY_REQUESTS.as_u16(), bytes::Bytes::from(error_json.to_string()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        response.headers_mut().insert(hyper::header::RETRY_AFTER, HeaderValue::from(retry_after_seconds));
+        return Ok(response);
+    }
+
+    let is_email_configured = {
+        let configuration = get_cached_configuration().get_configuration().await;
+        let smtp = &configuration.core.server_settings.smtp;
+        smtp.is_enabled() && !smtp.notification_recipients.is_empty()
+    };
+    if is_email_configured {
+        match create_password_reset_token(&email_request.username) {
+            Ok(reset_token) => {
+                info(format!(
+                    "Audit: Password reset token for user '{}' emailed to the notification recipients, requested from {}",
+                    reset_token.username, remote_ip
+                ));
+                let values = std::collections::BTreeMap::from([
+                    ("username", reset_token.username),
+                    ("token", reset_token.token),
+                    ("valid_minutes", PASSWORD_RESET_TOKEN_LIFETIME_MINUTES.to_string()),
+                ]);
+                get_email_queue().notify(EmailTemplate::PasswordReset, &values).await;
+            }
+            Err(e) => info(format!("Audit: Password reset email requested from {} not sent: {}", remote_ip, e)),
+        }
+    } else {
+        info(format!(
+            "Audit: Password reset email requested from {} not sent, as no mail server or notification recipients are configured",
+            remote_ip
+        ));
+    }
+
+    let response_json = serde_json::to_string(&MessageResponse {
+        success: true,
+        message: "If the user exists and email is configured, a password reset token was emailed to the administrators".to_string(),
+    });
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::ACCEPTED.as_u16(), bytes::Bytes::from(response_json.unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+#[utoipa::path(post, path = "/logout", tag = "Authentication", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "Session signed out", body = MessageResponse),
+        (status = 400, description = "No session token provided", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    ))]
 pub async fn handle_logout_request(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check if this is a POST request
     if gruxi_request.get_http_method() != "POST" {
@@ -147,10 +653,18 @@ pub async fn handle_logout_request(gruxi_request: &mut GruxiRequest, _admin_site
         return Ok(response);
     }
 
-    // Get the session token from Authorization header or request body
-    let token = get_session_token_from_request(&gruxi_request).await;
+    // Get the session token from Authorization header or session cookie
+    let token = get_session_token_from_request(gruxi_request).await;
+
+    if let Some((token, is_from_cookie)) = token {
+        // Logging out is state-changing as well, so cookie sessions must send the CSRF token
+        if is_from_cookie
+            && let Ok(Some(session)) = verify_session_token(&token, None)
+            && let Some(csrf_response) = get_csrf_rejection_response(gruxi_request, &session)
+        {
+            return Ok(csrf_response);
+        }
 
-    if let Some(token) = token {
         match invalidate_session(&token) {
             Ok(true) => {
                 info("Successfully logged out session".to_string());
@@ -160,6 +674,10 @@ pub async fn handle_logout_request(gruxi_request: &mut GruxiRequest, _admin_site
                 });
                 let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json.to_string()));
                 response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+                if is_from_cookie {
+                    let admin_portal_settings = get_admin_portal_settings().await;
+                    set_session_cookie(&mut response, "", 0, &admin_portal_settings.session_cookie_same_site);
+                }
                 Ok(response)
             }
             Ok(false) => {
@@ -181,10 +699,17 @@ pub async fn handle_logout_request(gruxi_request: &mut GruxiRequest, _admin_site
     }
 }
 
+#[utoipa::path(get, path = "/config", tag = "Configuration", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "The current configuration, with hostname_display_names mapping the internationalized hostnames of the sites, stored in punycode, to their Unicode form", body = Configuration),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
 pub async fn admin_get_configuration_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check authentication first
-    match require_authentication(&gruxi_request).await {
-        Ok(Some(_session)) => {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
             // User is authenticated, proceed with getting configuration
             debug("User authenticated, retrieving configuration".to_string());
         }
@@ -206,13 +731,21 @@ pub async fn admin_get_configuration_endpoint(gruxi_request: &mut GruxiRequest,
         Ok(cfg) => cfg,
         Err(e) => {
             error(format!("Failed to retrieve configuration from database: {}", e));
-            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(), bytes::Bytes::from(r#"{"error": "Failed to retrieve configuration"}"#));
+            let mut response = GruxiResponse::new_with_bytes(
+                hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                bytes::Bytes::from(r#"{"error": "Failed to retrieve configuration"}"#),
+            );
             response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
             return Ok(response);
         }
     };
 
-    let json_config = match serde_json::to_string_pretty(&config) {
+    // The admin portal shows internationalized hostnames in their Unicode form
+    let hostname_display_names = get_hostname_display_names(&config.sites);
+    let json_config = match serde_json::to_value(&config).and_then(|mut value| {
+        value["hostname_display_names"] = serde_json::to_value(hostname_display_names)?;
+        serde_json::to_string_pretty(&value)
+    }) {
         Ok(json) => json,
         Err(e) => {
             error(format!("Failed to serialize configuration: {}", e));
@@ -230,10 +763,17 @@ pub async fn admin_get_configuration_endpoint(gruxi_request: &mut GruxiRequest,
     return Ok(response);
 }
 
+#[utoipa::path(post, path = "/configuration/reload", tag = "Configuration", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "Configuration reloaded, with the bindings that could not be started", body = ConfigurationReloadResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
 pub async fn admin_post_configuration_reload(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check authentication first
-    match require_authentication(&gruxi_request).await {
-        Ok(Some(_session)) => {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
             // User is authenticated, proceed with reloading configuration
             debug("User authenticated, reloading configuration".to_string());
         }
@@ -249,23 +789,202 @@ pub async fn admin_post_configuration_reload(gruxi_request: &mut GruxiRequest, _
         }
     }
 
-    // Trigger the configuration cache reload
-    let triggers = get_trigger_handler();
-    triggers.run_trigger("refresh_cached_configuration").await;
-    triggers.run_trigger("reload_configuration").await;
-
     info("Configuration reload triggered by admin user".to_string());
 
-    let success_response = serde_json::json!({
-        "success": true,
-        "message": "Configuration reload initiated. Server is restarting..."
-    });
+    // Only the bindings affected by the change are restarted, and the ones failing to start are reported back
+    let reload_report = reload_configuration_and_wait(RELOAD_REPORT_TIMEOUT).await;
+    let reload_response = match reload_report {
+        Some(report) if report.binding_errors.is_empty() => ConfigurationReloadResponse {
+            success: true,
+            message: "Configuration reloaded".to_string(),
+            binding_errors: Vec::new(),
+        },
+        Some(report) => {
+            for binding_error in &report.binding_errors {
+                warn(format!(
+                    "Binding {} ({}) failed to start after reload: {}",
+                    binding_error.address, binding_error.binding_id, binding_error.error
+                ));
+            }
+            ConfigurationReloadResponse {
+                success: false,
+                message: format!("Configuration reloaded, but {} binding(s) could not be started", report.binding_errors.len()),
+                binding_errors: report.binding_errors,
+            }
+        }
+        None => ConfigurationReloadResponse {
+            success: true,
+            message: "Configuration reload initiated. Server is restarting...".to_string(),
+            binding_errors: Vec::new(),
+        },
+    };
 
-    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(success_response.to_string()));
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&reload_response).unwrap_or_default()));
     response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
     return Ok(response);
 }
 
+// Restarts into the Gruxi binary on disk, such as after installing a new version, handing over the listeners without dropping connections
+#[utoipa::path(post, path = "/server/upgrade", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 202, description = "Binary upgrade started", body = MessageResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 409, description = "An upgrade is already in progress, or upgrades are not supported on this platform", body = ErrorResponse),
+    ))]
+pub async fn admin_post_server_upgrade_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
+            debug("User authenticated, starting binary upgrade".to_string());
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    }
+
+    if let Err(e) = begin_binary_upgrade() {
+        let error_response = ErrorResponse {
+            error: "Could not start binary upgrade".to_string(),
+            details: Some(e),
+        };
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::CONFLICT.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    }
+
+    info("Binary upgrade triggered by admin user".to_string());
+
+    let upgrade_response = MessageResponse {
+        success: true,
+        message: "Binary upgrade started. The new process takes over once it is serving".to_string(),
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::ACCEPTED.as_u16(), bytes::Bytes::from(serde_json::to_string(&upgrade_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// The addresses the upstream hosts given by name currently resolve to, for debugging proxy and FastCGI connections
+#[utoipa::path(get, path = "/server/dns-cache", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "The cached upstream hosts and their addresses", body = DnsCacheResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_dns_cache_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
+            debug("User authenticated, retrieving DNS cache".to_string());
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    }
+
+    let dns_cache = get_dns_cache();
+    let dns_cache_response = DnsCacheResponse {
+        ttl_seconds: dns_cache.get_ttl_seconds(),
+        entries: dns_cache.get_entries(),
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&dns_cache_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Sends a test email right away, without the queue, so the mail server settings can be checked and any error is shown
+#[utoipa::path(post, path = "/server/email-test", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), request_body = EmailTestRequest, responses(
+        (status = 200, description = "The mail server accepted the test email", body = MessageResponse),
+        (status = 400, description = "Invalid recipient, or no mail server is configured", body = ErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "Only admins can send test email", body = ErrorResponse),
+        (status = 502, description = "The mail server did not take the test email", body = ErrorResponse),
+    ))]
+pub async fn admin_post_email_test_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+    if !session.is_admin() {
+        return Ok(get_forbidden_response());
+    }
+
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let email_test_request: EmailTestRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            let error_response = serde_json::json!({ "error": "Invalid JSON format", "details": e.to_string() });
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_response.to_string()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    let to = email_test_request.to.trim().to_string();
+    let configuration = get_cached_configuration().get_configuration().await;
+    let smtp = &configuration.core.server_settings.smtp;
+    let bad_request_error = if email_address::EmailAddress::parse_with_options(&to, email_address::Options::default().without_display_text()).is_err() {
+        Some(format!("Not a valid email address: '{}'", to))
+    } else if !smtp.is_enabled() {
+        Some("No mail server is configured in the SMTP settings".to_string())
+    } else {
+        None
+    };
+    if let Some(error) = bad_request_error {
+        let error_response = ErrorResponse { error, details: None };
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    }
+
+    let (subject, body) = EmailTemplate::Test.render(&std::collections::BTreeMap::from([("username", session.username.clone())]));
+    let email = OutgoingEmail { to: vec![to.clone()], subject, body };
+    info(format!("Audit: Test email to {} sent by {}", to, session.username));
+    let (status, response_json) = match send_email(smtp, &email).await {
+        Ok(()) => (
+            hyper::StatusCode::OK,
+            serde_json::to_string(&MessageResponse {
+                success: true,
+                message: format!("Test email sent to {}", to),
+            }),
+        ),
+        Err(e) => (
+            hyper::StatusCode::BAD_GATEWAY,
+            serde_json::to_string(&ErrorResponse {
+                error: "The mail server did not take the test email".to_string(),
+                details: Some(e.get_message().to_string()),
+            }),
+        ),
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(status.as_u16(), bytes::Bytes::from(response_json.unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+#[utoipa::path(post, path = "/config", tag = "Configuration", security(("session_token" = []), ("session_cookie" = [])), request_body = Configuration, responses(
+        (status = 200, description = "Configuration saved, or already up to date", body = ConfigurationSaveResponse),
+        (status = 400, description = "Invalid configuration", body = ValidationErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 409, description = "The configuration is read-only", body = ErrorResponse),
+    ))]
 pub async fn admin_post_configuration_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check if this is a POST request
     if gruxi_request.get_http_method() != "POST" {
@@ -275,9 +994,13 @@ pub async fn admin_post_configuration_endpoint(gruxi_request: &mut GruxiRequest,
     }
 
     // Check authentication first
-    match require_authentication(&gruxi_request).await {
-        Ok(Some(_session)) => {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
             debug("User authenticated for configuration update".to_string());
+            session
         }
         Ok(None) => {
             let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
@@ -287,15 +1010,21 @@ pub async fn admin_post_configuration_endpoint(gruxi_request: &mut GruxiRequest,
         Err(auth_response) => {
             return Ok(auth_response);
         }
+    };
+
+    // Configuration managed elsewhere must not drift from what is there
+    if cmd_read_only_configuration() {
+        info("Audit: Configuration change rejected, as the configuration is read-only".to_string());
+        return Ok(read_only_configuration_response());
     }
 
-    // Read the request body
-    if gruxi_request.get_body_size() == 0 {
+    // Read the request body, which may be chunked, so its size is only known once it has been read
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    if body_bytes.is_empty() {
         let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(r#"{"error": "Empty request body"}"#));
         response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
         return Ok(response);
     }
-    let body_bytes = gruxi_request.get_body_bytes().await;
 
     // Parse JSON body into Configuration struct
     let mut configuration: Configuration = match serde_json::from_slice(&body_bytes) {
@@ -313,102 +1042,916 @@ pub async fn admin_post_configuration_endpoint(gruxi_request: &mut GruxiRequest,
         }
     };
 
+    // Kept to tell what changed, for the audit log and the response
+    let current_configuration = fetch_configuration_in_db().ok();
+
     // Save the configuration
-    match save_configuration(&mut configuration, false) {
+    let save_result = save_configuration(&mut configuration, false);
+    let diff = match (&save_result, &current_configuration) {
+        (Ok(true), Some(current_configuration)) => get_configuration_diff(current_configuration, &configuration),
+        _ => Vec::new(),
+    };
+    let message = match save_result {
         Ok(true) => {
-            info("Configuration updated successfully".to_string());
-
-            // Serialize the sanitized configuration to return to the client
-            let config_json = match serde_json::to_value(&configuration) {
-                Ok(json) => json,
-                Err(e) => {
-                    error(format!("Failed to serialize updated configuration: {}", e));
+            info(format!("Audit: Configuration updated by {}: {}", session.username, diff.join("; ")));
+            "Configuration updated successfully. Please restart the server for changes to take effect."
+        }
+        Ok(false) => {
+            info("Configuration save requested, but no changes detected".to_string());
+            "Configuration is up to date. No changes were needed."
+        }
+        Err(validation_errors) => {
+            info(format!("Configuration validation failed: {}", validation_errors.join("; ")));
+            let error_response = ValidationErrorResponse { errors: validation_errors };
 
-                    let mut response = GruxiResponse::new_with_bytes(
-                        hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
-                        bytes::Bytes::from(r#"{"error": "Configuration saved but failed to serialize response"}"#),
-                    );
-                    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
-                    return Ok(response);
-                }
-            };
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
 
-            let success_response = serde_json::json!({
-                "success": true,
-                "message": "Configuration updated successfully. Please restart the server for changes to take effect.",
-                "configuration": config_json
-            });
+    // Certificates not matching the hostnames of their sites are only found by browsers otherwise, and upstreams without
+    // certificate verification are easily forgotten
+    let mut warnings = get_certificate_hostname_warnings(&configuration);
+    warnings.extend(get_insecure_upstream_tls_warnings(&configuration));
+    for warning in &warnings {
+        warn(format!("Configuration saved with a warning: {}", warning));
+    }
 
-            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(success_response.to_string()));
+    // Return the sanitized configuration, which is the current one even if no changes were made
+    let save_response = ConfigurationSaveResponse {
+        success: true,
+        message: message.to_string(),
+        hostname_display_names: get_hostname_display_names(&configuration.sites),
+        configuration,
+        diff,
+        warnings,
+    };
+    let response_json = match serde_json::to_string(&save_response) {
+        Ok(json) => json,
+        Err(e) => {
+            error(format!("Failed to serialize configuration: {}", e));
+            let mut response = GruxiResponse::new_with_bytes(
+                hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                bytes::Bytes::from(r#"{"error": "Failed to serialize configuration response"}"#),
+            );
             response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
             return Ok(response);
         }
-        Ok(false) => {
-            info("Configuration save requested, but no changes detected".to_string());
-
-            // Even if no changes were made, return the current configuration
-            let config_json = match serde_json::to_value(&configuration) {
-                Ok(json) => json,
-                Err(e) => {
-                    error(format!("Failed to serialize configuration: {}", e));
-                    let mut response = GruxiResponse::new_with_bytes(
-                        hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
-                        bytes::Bytes::from(r#"{"error": "Failed to serialize configuration response"}"#),
-                    );
-                    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
-                    return Ok(response);
-                }
-            };
+    };
 
-            let success_response = serde_json::json!({
-                "success": true,
-                "message": "Configuration is up to date. No changes were needed.",
-                "configuration": config_json
-            });
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
 
-            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(success_response.to_string()));
+// Tells what saving a configuration would change, without saving it, so it can be confirmed first
+#[utoipa::path(post, path = "/config/preview", tag = "Configuration", security(("session_token" = []), ("session_cookie" = [])), request_body = Configuration, responses(
+        (status = 200, description = "What would change, empty if the configuration is up to date", body = ConfigurationPreviewResponse),
+        (status = 400, description = "Invalid configuration", body = ValidationErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "Only admins can change the configuration", body = ErrorResponse),
+    ))]
+pub async fn admin_preview_configuration_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
+            debug("User authenticated for configuration preview".to_string());
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
             response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
             return Ok(response);
         }
-        Err(validation_errors) => {
-            info(format!("Configuration validation failed: {}", validation_errors.join("; ")));
-            let error_response = serde_json::json!({
-                "errors": validation_errors
-            });
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    }
 
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let mut configuration: Configuration = match serde_json::from_slice(&body_bytes) {
+        Ok(config) => config,
+        Err(e) => {
+            let error_response = serde_json::json!({ "error": "Invalid JSON format", "details": e.to_string() });
             let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_response.to_string()));
             response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
             return Ok(response);
         }
-    }
-}
+    };
 
-// Helper function to extract session token from request
-async fn get_session_token_from_request(gruxi_request: &GruxiRequest) -> Option<String> {
-    // First, check for Authorization header (Bearer token)
-    if let Some(auth_header) = gruxi_request.get_headers().get("Authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                return Some(auth_str[7..].to_string());
-            }
-        }
+    // Compared as it would be saved
+    configuration.sanitize();
+    if let Err(errors) = configuration.validate() {
+        let error_response = ValidationErrorResponse { errors };
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
     }
 
-    None
+    let current_configuration = match fetch_configuration_in_db() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            error(format!("Failed to fetch configuration: {}", e));
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(), bytes::Bytes::from(r#"{"error": "Internal server error"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+    let preview_response = ConfigurationPreviewResponse {
+        success: true,
+        changes: ConfigurationChanges::between(&current_configuration, &configuration),
+        diff: get_configuration_diff(&current_configuration, &configuration),
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&preview_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Failed login counters per IP and username, to see who is being throttled or locked out
+#[utoipa::path(get, path = "/login-attempts", tag = "Authentication", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "Failed login counters per IP and username", body = LoginAttemptsResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_login_attempts_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
+            debug("User authenticated, retrieving login attempt counters".to_string());
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    }
+
+    let (ip_counters, username_counters) = get_login_throttle().get_counters(std::time::Instant::now());
+    let login_attempts = LoginAttemptsResponse {
+        ips: ip_counters,
+        usernames: username_counters,
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&login_attempts).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// The admin users and site owners, with the sites each site owner owns
+#[utoipa::path(get, path = "/users", tag = "Users", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "All users", body = UsersResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "Only admins can manage users", body = ErrorResponse),
+    ))]
+pub async fn admin_get_users_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
+            debug("User authenticated, listing users".to_string());
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    }
+
+    let users = match list_users() {
+        Ok(users) => users,
+        Err(e) => {
+            error(format!("Failed to list users: {}", e));
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(), bytes::Bytes::from(r#"{"error": "Internal server error"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(
+        hyper::StatusCode::OK.as_u16(),
+        bytes::Bytes::from(serde_json::to_string(&UsersResponse { success: true, users }).unwrap_or_default()),
+    );
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Creates a user or updates an existing one. Updated users are logged out, so their new role and sites apply right away
+#[utoipa::path(post, path = "/users", tag = "Users", security(("session_token" = []), ("session_cookie" = [])), request_body = UserRequest, responses(
+        (status = 200, description = "User created or updated", body = MessageResponse),
+        (status = 400, description = "Invalid user, or the last admin would be lost", body = ValidationErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "Only admins can manage users", body = ErrorResponse),
+    ))]
+pub async fn admin_post_user_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+    if !session.is_admin() {
+        return Ok(get_forbidden_response());
+    }
+
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let mut user_request: UserRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            let error_response = serde_json::json!({ "error": "Invalid JSON format", "details": e.to_string() });
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_response.to_string()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+    user_request.username = user_request.username.trim().to_string();
+
+    // Site owners can only own sites that exist
+    let configuration = get_cached_configuration().get_configuration().await;
+    let unknown_site_ids: Vec<&String> = user_request.site_ids.iter().filter(|site_id| !configuration.sites.iter().any(|site| &site.id == *site_id)).collect();
+    let result = if unknown_site_ids.is_empty() {
+        save_user(&user_request)
+    } else {
+        Err(unknown_site_ids.iter().map(|site_id| format!("Site '{}' does not exist", site_id)).collect())
+    };
+
+    match result {
+        Ok(created) => {
+            let action = if created { "created" } else { "updated" };
+            info(format!(
+                "Audit: User '{}' {} with role {} by {}",
+                user_request.username,
+                action,
+                user_request.role.get_name(),
+                session.username
+            ));
+            let message_response = MessageResponse {
+                success: true,
+                message: format!("User '{}' {}", user_request.username, action),
+            };
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&message_response).unwrap_or_default()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            Ok(response)
+        }
+        Err(errors) => {
+            let error_response = ValidationErrorResponse { errors };
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            Ok(response)
+        }
+    }
+}
+
+#[utoipa::path(delete, path = "/users/{username}", tag = "Users", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("username" = String, Path, description = "Username of the user"),
+    ), responses(
+        (status = 200, description = "User deleted, with their sessions", body = MessageResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "Only admins can manage users", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 409, description = "The last admin cannot be deleted", body = ErrorResponse),
+    ))]
+pub async fn admin_delete_user_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+    if !session.is_admin() {
+        return Ok(get_forbidden_response());
+    }
+
+    let path = gruxi_request.get_path();
+    let username = path.strip_prefix("/users/").unwrap_or_default();
+    let (status, response_json) = match delete_user(username) {
+        Ok(true) => {
+            info(format!("Audit: User '{}' deleted by {}", username, session.username));
+            (
+                hyper::StatusCode::OK,
+                serde_json::to_string(&MessageResponse {
+                    success: true,
+                    message: format!("User '{}' deleted", username),
+                }),
+            )
+        }
+        Ok(false) => (
+            hyper::StatusCode::NOT_FOUND,
+            serde_json::to_string(&ErrorResponse {
+                error: "User not found".to_string(),
+                details: None,
+            }),
+        ),
+        Err(e) => (
+            hyper::StatusCode::CONFLICT,
+            serde_json::to_string(&ErrorResponse {
+                error: "Could not delete user".to_string(),
+                details: Some(e),
+            }),
+        ),
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(status.as_u16(), bytes::Bytes::from(response_json.unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// A single site, for site owners that cannot see the rest of the configuration
+#[utoipa::path(get, path = "/sites/{site_id}", tag = "Configuration", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), responses(
+        (status = 200, description = "The site as configured", body = Site),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "The site is owned by someone else", body = ErrorResponse),
+        (status = 404, description = "No such site", body = ErrorResponse),
+    ))]
+pub async fn admin_get_site_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, retrieving site".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/sites/").unwrap_or_default();
+    if !session.can_access_site(site_id) {
+        return Ok(get_forbidden_response());
+    }
+
+    let configuration = get_cached_configuration().get_configuration().await;
+    let (status, response_json) = match configuration.sites.iter().find(|site| site.id == site_id) {
+        Some(site) => (hyper::StatusCode::OK, serde_json::to_string(site)),
+        None => (
+            hyper::StatusCode::NOT_FOUND,
+            serde_json::to_string(&ErrorResponse {
+                error: "Site not found".to_string(),
+                details: None,
+            }),
+        ),
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(status.as_u16(), bytes::Bytes::from(response_json.unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Saves a single site. Site owners can change how their site is served, while the settings that reach outside of it are kept as they are
+#[utoipa::path(post, path = "/sites/{site_id}", tag = "Configuration", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), request_body = Site, responses(
+        (status = 200, description = "Site saved, or already up to date", body = SiteSaveResponse),
+        (status = 400, description = "Invalid site", body = ValidationErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "The site is owned by someone else", body = ErrorResponse),
+        (status = 404, description = "No such site", body = ErrorResponse),
+        (status = 409, description = "The configuration is read-only", body = ErrorResponse),
+    ))]
+pub async fn admin_post_site_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/sites/").unwrap_or_default().to_string();
+    if !session.can_access_site(&site_id) {
+        return Ok(get_forbidden_response());
+    }
+
+    if cmd_read_only_configuration() {
+        info("Audit: Site change rejected, as the configuration is read-only".to_string());
+        return Ok(read_only_configuration_response());
+    }
+
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let mut site: Site = match serde_json::from_slice(&body_bytes) {
+        Ok(site) => site,
+        Err(e) => {
+            let error_response = serde_json::json!({ "error": "Invalid JSON format", "details": e.to_string() });
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_response.to_string()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    let mut configuration = match fetch_configuration_in_db() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            error(format!("Failed to fetch configuration: {}", e));
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(), bytes::Bytes::from(r#"{"error": "Internal server error"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+    let Some(current_site) = configuration.sites.iter_mut().find(|current_site| current_site.id == site_id) else {
+        let error_response = ErrorResponse {
+            error: "Site not found".to_string(),
+            details: None,
+        };
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::NOT_FOUND.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    };
+    if !session.is_admin() {
+        site.keep_admin_only_settings(current_site);
+    }
+    site.id = site_id.clone();
+    *current_site = site;
+
+    let message = match save_configuration(&mut configuration, false) {
+        Ok(true) => {
+            info(format!("Audit: Site '{}' updated by {}", site_id, session.username));
+            "Site updated successfully. Please restart the server for changes to take effect."
+        }
+        Ok(false) => "Site is up to date. No changes were needed.",
+        Err(validation_errors) => {
+            info(format!("Site validation failed: {}", validation_errors.join("; ")));
+            let error_response = ValidationErrorResponse { errors: validation_errors };
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    // Return the site as saved, after sanitizing
+    let save_response = SiteSaveResponse {
+        success: true,
+        message: message.to_string(),
+        site: configuration.sites.into_iter().find(|site| site.id == site_id),
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&save_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Installs a certificate obtained by an external ACME client for a site, such as from a certbot deploy hook, and reloads the
+// configuration so its TLS bindings serve it right away. The site no longer gets certificates from the built-in ACME client
+#[utoipa::path(post, path = "/certificates/{site_id}", tag = "Configuration", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), request_body = CertificateInstallRequest, responses(
+        (status = 200, description = "Certificate installed and the configuration is reloading", body = MessageResponse),
+        (status = 400, description = "The certificate or key cannot be read, or do not belong together", body = ValidationErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "The site is owned by someone else, or only admins can install from file paths", body = ErrorResponse),
+        (status = 404, description = "No such site", body = ErrorResponse),
+        (status = 409, description = "The configuration is read-only", body = ErrorResponse),
+    ))]
+pub async fn admin_install_certificate_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/certificates/").unwrap_or_default().to_string();
+    if !session.can_access_site(&site_id) {
+        return Ok(get_forbidden_response());
+    }
+
+    if cmd_read_only_configuration() {
+        info("Audit: Certificate installation rejected, as the configuration is read-only".to_string());
+        return Ok(read_only_configuration_response());
+    }
+
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let install_request: CertificateInstallRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            let error_response = serde_json::json!({ "error": "Invalid JSON format", "details": e.to_string() });
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_response.to_string()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    // Files on the server can only be pointed at by admins, as for the other paths of a site
+    let use_paths = !install_request.certificate_path.trim().is_empty() || !install_request.private_key_path.trim().is_empty();
+    if use_paths && !session.is_admin() {
+        return Ok(get_forbidden_response());
+    }
+    let pems = if use_paths {
+        fs::read(install_request.certificate_path.trim())
+            .map_err(|e| format!("Failed to read {}: {}", install_request.certificate_path.trim(), e))
+            .and_then(|certificate| {
+                fs::read(install_request.private_key_path.trim())
+                    .map(|key| (certificate, key))
+                    .map_err(|e| format!("Failed to read {}: {}", install_request.private_key_path.trim(), e))
+            })
+    } else {
+        Ok((install_request.certificate_pem.clone().into_bytes(), install_request.private_key_pem.clone().into_bytes()))
+    };
+    if let Err(e) = pems.and_then(|(certificate, key)| validate_certificate_and_key(&certificate, &key)) {
+        let error_response = ValidationErrorResponse { errors: vec![e] };
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    }
+
+    let mut configuration = match fetch_configuration_in_db() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            error(format!("Failed to fetch configuration: {}", e));
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16(), bytes::Bytes::from(r#"{"error": "Internal server error"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+    let Some(site) = configuration.sites.iter_mut().find(|site| site.id == site_id) else {
+        let error_response = ErrorResponse {
+            error: "Site not found".to_string(),
+            details: None,
+        };
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::NOT_FOUND.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    };
+    site.tls_automatic_enabled = false;
+    if use_paths {
+        site.tls_cert_path = install_request.certificate_path.trim().to_string();
+        site.tls_key_path = install_request.private_key_path.trim().to_string();
+        site.tls_cert_content = String::new();
+        site.tls_key_content = String::new();
+    } else {
+        site.tls_cert_path = String::new();
+        site.tls_key_path = String::new();
+        site.tls_cert_content = install_request.certificate_pem;
+        site.tls_key_content = install_request.private_key_pem;
+    }
+
+    // Saved even when the paths are unchanged, as a renewed certificate is usually written to the same files
+    if let Err(errors) = save_configuration(&mut configuration, true) {
+        let error_response = ValidationErrorResponse { errors };
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    }
+    info(format!("Audit: Certificate installed for site '{}' by {}", site_id, session.username));
+
+    let triggers = get_trigger_handler();
+    triggers.run_trigger("refresh_cached_configuration").await;
+    triggers.run_trigger("reload_configuration").await;
+
+    let message_response = MessageResponse {
+        success: true,
+        message: format!("Certificate installed for site '{}'. The configuration is reloading to serve it", site_id),
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&message_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// The traffic split of a site, with the percentage of clients on variant B in use
+#[utoipa::path(get, path = "/traffic-split/{site_id}", tag = "Configuration", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), responses(
+        (status = 200, description = "The traffic split of the site", body = TrafficSplitResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "The site is owned by someone else", body = ErrorResponse),
+        (status = 404, description = "No such site", body = ErrorResponse),
+    ))]
+pub async fn admin_get_traffic_split_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/traffic-split/").unwrap_or_default().to_string();
+    if !session.can_access_site(&site_id) {
+        return Ok(get_forbidden_response());
+    }
+
+    get_traffic_split_response(&site_id).await
+}
+
+// Changes the percentage of clients on variant B until the server restarts, such as to ramp up a test or stop it right away.
+// Clients with a cookie keep their variant, unless nobody is to get it any more
+#[utoipa::path(post, path = "/traffic-split/{site_id}", tag = "Configuration", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), request_body = TrafficSplitRequest, responses(
+        (status = 200, description = "The traffic split of the site with the new percentage", body = TrafficSplitResponse),
+        (status = 400, description = "Invalid percentage", body = ErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "The site is owned by someone else", body = ErrorResponse),
+        (status = 404, description = "No such site", body = ErrorResponse),
+    ))]
+pub async fn admin_post_traffic_split_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/traffic-split/").unwrap_or_default().to_string();
+    if !session.can_access_site(&site_id) {
+        return Ok(get_forbidden_response());
+    }
+
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let traffic_split_request: TrafficSplitRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            let error_response = serde_json::json!({ "error": "Invalid JSON format", "details": e.to_string() });
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_response.to_string()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+    if traffic_split_request.variant_b_percentage.is_some_and(|percentage| percentage > 100) {
+        let error_response = ErrorResponse {
+            error: "The percentage for variant B must be between 0 and 100".to_string(),
+            details: None,
+        };
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    }
+
+    let configuration = get_cached_configuration().get_configuration().await;
+    if configuration.sites.iter().any(|site| site.id == site_id) {
+        get_traffic_split_percentages().set(&site_id, traffic_split_request.variant_b_percentage);
+        let percentage = traffic_split_request
+            .variant_b_percentage
+            .map(|p| format!("{}%", p))
+            .unwrap_or_else(|| "the configured percentage".to_string());
+        info(format!("Audit: Traffic split of site '{}' set to {} for variant B by {}", site_id, percentage, session.username));
+    }
+
+    get_traffic_split_response(&site_id).await
+}
+
+// The disk caches, such as those of the proxy processors, with their sizes
+#[utoipa::path(get, path = "/disk-cache", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "The disk caches and their limits", body = DiskCacheResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_disk_cache_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    }
+
+    let server_settings = get_cached_configuration().get_configuration().await.core.server_settings.clone();
+    let disk_cache_response = DiskCacheResponse {
+        max_size_bytes: server_settings.disk_cache_max_size_bytes,
+        max_unused_hours: server_settings.disk_cache_max_unused_hours,
+        caches: get_disk_cache_manager().get_statistics().await,
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&disk_cache_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Removes cached entries of a site from the disk caches, such as after publishing changed content the origin sent a long lifetime for
+#[utoipa::path(post, path = "/disk-cache/purge/{site_id}", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), request_body = DiskCachePurgeRequest, responses(
+        (status = 200, description = "How many cached entries were removed", body = DiskCachePurgeResponse),
+        (status = 400, description = "Both a path prefix and a URL were given, or the path prefix does not start with '/'", body = ErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "The site is owned by someone else", body = ErrorResponse),
+    ))]
+pub async fn admin_purge_disk_cache_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/disk-cache/purge/").unwrap_or_default().to_string();
+    if !session.can_access_site(&site_id) {
+        return Ok(get_forbidden_response());
+    }
+
+    // An empty body purges the whole site
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let purge_request: DiskCachePurgeRequest = if body_bytes.is_empty() {
+        DiskCachePurgeRequest::default()
+    } else {
+        match serde_json::from_slice(&body_bytes) {
+            Ok(req) => req,
+            Err(e) => {
+                let error_response = serde_json::json!({ "error": "Invalid JSON format", "details": e.to_string() });
+                let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_response.to_string()));
+                response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+                return Ok(response);
+            }
+        }
+    };
+
+    let purge = match (purge_request.path_prefix.trim(), purge_request.url.trim()) {
+        ("", "") => DiskCachePurge::All,
+        (path_prefix, "") if path_prefix.starts_with('/') => DiskCachePurge::PathPrefix(path_prefix.to_string()),
+        ("", url) => DiskCachePurge::Url(url.to_string()),
+        _ => {
+            let error_response = ErrorResponse {
+                error: "Give either a path prefix starting with '/' or a URL, or neither to purge the whole site".to_string(),
+                details: None,
+            };
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    let removed_entries = get_disk_cache_manager().purge(&site_id, &purge).await;
+    let purged = match &purge {
+        DiskCachePurge::All => "all entries".to_string(),
+        DiskCachePurge::PathPrefix(path_prefix) => format!("entries below '{}'", path_prefix),
+        DiskCachePurge::Url(url) => format!("the entry of '{}'", url),
+    };
+    info(format!(
+        "Audit: Disk cache of site '{}' purged of {} by {}, {} entries removed",
+        site_id, purged, session.username, removed_entries
+    ));
+
+    let purge_response = DiskCachePurgeResponse { site_id, removed_entries };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&purge_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+async fn get_traffic_split_response(site_id: &str) -> Result<GruxiResponse, GruxiError> {
+    let configuration = get_cached_configuration().get_configuration().await;
+    let Some(site) = configuration.sites.iter().find(|site| site.id == site_id) else {
+        let error_response = ErrorResponse {
+            error: "Site not found".to_string(),
+            details: None,
+        };
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::NOT_FOUND.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    };
+
+    let traffic_split_response = TrafficSplitResponse {
+        site_id: site.id.clone(),
+        enabled: site.traffic_split.enabled,
+        variant_b_percentage: get_traffic_split_percentages().get(site),
+        configured_variant_b_percentage: site.traffic_split.variant_b_percentage,
+        sticky_by: site.traffic_split.sticky_by.clone(),
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&traffic_split_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Gets the session token from the Authorization header (Bearer token) or, when cookie sessions are enabled, from the session cookie.
+// Returns whether the token came from the cookie, as only those requests need CSRF protection
+async fn get_session_token_from_request(gruxi_request: &GruxiRequest) -> Option<(String, bool)> {
+    // First, check for Authorization header (Bearer token)
+    if let Some(auth_header) = gruxi_request.get_headers().get("Authorization") {
+        if let Ok(auth_str) = auth_header.to_str() {
+            if auth_str.starts_with("Bearer ") {
+                return Some((auth_str[7..].to_string(), false));
+            }
+        }
+    }
+
+    if get_admin_portal_settings().await.session_cookie_enabled
+        && let Some(token) = gruxi_request.get_cookie(SESSION_COOKIE_NAME)
+        && !token.is_empty()
+    {
+        return Some((token, true));
+    }
+
+    None
+}
+
+async fn get_admin_portal_settings() -> AdminPortal {
+    get_cached_configuration().get_configuration().await.core.admin_portal.clone()
+}
+
+// Sets the session cookie, or clears it with an empty token and a max age of zero. The admin portal is only served over TLS, so the cookie is always Secure
+fn set_session_cookie(response: &mut GruxiResponse, token: &str, max_age_seconds: i64, same_site: &str) {
+    let cookie = format!("{}={}; Path=/; Max-Age={}; HttpOnly; Secure; SameSite={}", SESSION_COOKIE_NAME, token, max_age_seconds, same_site);
+    if let Ok(header_value) = HeaderValue::from_str(&cookie) {
+        response.headers_mut().append(hyper::header::SET_COOKIE, header_value);
+    }
+}
+
+// State-changing requests authenticated by the session cookie must carry the CSRF token of the session, which a cross-site request cannot know.
+// Returns the response to reject the request with, if the token is missing or wrong
+fn get_csrf_rejection_response(gruxi_request: &mut GruxiRequest, session: &Session) -> Option<GruxiResponse> {
+    let method = gruxi_request.get_http_method();
+    if method == "GET" || method == "HEAD" || method == "OPTIONS" {
+        return None;
+    }
+
+    let csrf_token = gruxi_request.get_headers().get(CSRF_TOKEN_HEADER).and_then(|v| v.to_str().ok()).unwrap_or("");
+    if !session.csrf_token.is_empty() && is_csrf_token_equal(csrf_token, &session.csrf_token) {
+        return None;
+    }
+
+    info(format!("Rejected {} request from user {} with a missing or invalid CSRF token", method, session.username));
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::FORBIDDEN.as_u16(), bytes::Bytes::from(r#"{"error": "Invalid or missing CSRF token"}"#));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Some(response)
+}
+
+// Compares in constant time, so the token cannot be guessed from response timings
+fn is_csrf_token_equal(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
 }
 
 // Helper function to verify session token and return session info
-pub fn verify_session(token: &str) -> Result<Option<crate::core::admin_user::Session>, String> {
-    verify_session_token(token)
+pub async fn verify_session(token: &str) -> Result<Option<Session>, String> {
+    let idle_timeout_minutes = get_admin_portal_settings().await.session_idle_timeout_minutes;
+    let idle_timeout = if idle_timeout_minutes > 0 {
+        Some(chrono::Duration::minutes(idle_timeout_minutes as i64))
+    } else {
+        None
+    };
+    verify_session_token(token, idle_timeout)
 }
 
 // Middleware-like function to check if request is authenticated
-pub async fn require_authentication(gruxi_request: &GruxiRequest) -> Result<Option<crate::core::admin_user::Session>, GruxiResponse> {
+pub async fn require_authentication(gruxi_request: &mut GruxiRequest) -> Result<Option<Session>, GruxiResponse> {
     let token = get_session_token_from_request(gruxi_request).await;
 
-    if let Some(token) = token {
-        match verify_session(&token) {
-            Ok(Some(session)) => Ok(Some(session)),
+    if let Some((token, is_from_cookie)) = token {
+        match verify_session(&token).await {
+            Ok(Some(session)) => {
+                if is_from_cookie && let Some(csrf_response) = get_csrf_rejection_response(gruxi_request, &session) {
+                    return Err(csrf_response);
+                }
+                Ok(Some(session))
+            }
             Ok(None) => {
                 let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Invalid or expired session"}"#));
                 response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
@@ -428,11 +1971,25 @@ pub async fn require_authentication(gruxi_request: &GruxiRequest) -> Result<Opti
     }
 }
 
+// Site owners only get to the endpoints of their own sites, the rest of the admin API is for admins
+fn get_forbidden_response() -> GruxiResponse {
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::FORBIDDEN.as_u16(), bytes::Bytes::from(r#"{"error": "Access denied"}"#));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    response
+}
+
 // Admin monitoring endpoint - returns monitoring data as JSON
+#[utoipa::path(get, path = "/monitoring", tag = "Monitoring", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "Current monitoring counters", body = Object),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
 pub async fn admin_monitoring_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check authentication first
-    match require_authentication(&gruxi_request).await {
-        Ok(Some(_session)) => {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
             debug("User authenticated, retrieving monitoring data".to_string());
         }
         Ok(None) => {
@@ -445,20 +2002,318 @@ pub async fn admin_monitoring_endpoint(gruxi_request: &mut GruxiRequest, _admin_
         }
     }
 
-    // Get monitoring data
-    let monitoring_data = get_monitoring_state().await.get_json().await;
+    // Get monitoring data
+    let monitoring_data = get_monitoring_state().await.get_json().await;
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(monitoring_data.to_string()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    return Ok(response);
+}
+
+// Historical monitoring data, filtered, downsampled and paginated server-side, so the dashboard only gets the points it draws.
+// Query parameters: site, from, to, resolution, metrics, page and page_size, see HistoryQuery
+#[utoipa::path(get, path = "/monitoring/history", tag = "Monitoring", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site" = Option<String>, Query, description = "Only the metrics of this site"),
+        ("from" = Option<i64>, Query, description = "Unix timestamp in seconds"),
+        ("to" = Option<i64>, Query, description = "Unix timestamp in seconds"),
+        ("resolution" = Option<u64>, Query, description = "Seconds per averaged point, 0 for the raw samples"),
+        ("metrics" = Option<String>, Query, description = "Comma separated metrics"),
+        ("page" = Option<usize>, Query, description = "Page, starting at 1"),
+        ("page_size" = Option<usize>, Query, description = "Points per page"),
+    ), responses(
+        (status = 200, description = "A page of the monitoring history", body = HistoryPage),
+        (status = 400, description = "Invalid query parameters", body = ErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_monitoring_history_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, retrieving monitoring history".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let history_query = match HistoryQuery::from_query_string(&gruxi_request.get_query()) {
+        Ok(history_query) => history_query,
+        Err(e) => {
+            let error_json = serde_json::json!({ "error": e });
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_json.to_string()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    // Site owners only get the history of their own sites
+    if !session.is_admin() && !history_query.site.as_deref().is_some_and(|site_id| session.can_access_site(site_id)) {
+        return Ok(get_forbidden_response());
+    }
+
+    let history_page = get_monitoring_state().await.get_history().query(&history_query);
+    let response_json = serde_json::to_string(&history_page).unwrap_or_else(|_| "{}".to_string());
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Requests served and bytes sent per site, cumulative and over the rolling windows, to see which site uses what
+#[utoipa::path(get, path = "/site-statistics", tag = "Monitoring", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "The statistics of all sites that served requests", body = SiteStatisticsResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_site_statistics_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, retrieving site statistics".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let response_json = serde_json::to_string(&SiteStatisticsResponse {
+        success: true,
+        sites: get_site_statistics()
+            .get_sites()
+            .into_iter()
+            .filter(|site_statistics| session.can_access_site(&site_statistics.site_id))
+            .collect(),
+    })
+    .unwrap_or_default();
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/site-statistics/{site_id}", tag = "Monitoring", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), responses(
+        (status = 200, description = "The statistics of the site, all zero if it has not served any requests", body = SiteStatisticsSiteResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_site_statistics_site_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, retrieving site statistics".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/site-statistics/").unwrap_or_default();
+    if !session.can_access_site(site_id) {
+        return Ok(get_forbidden_response());
+    }
+    let response_json = serde_json::to_string(&SiteStatisticsSiteResponse {
+        success: true,
+        site: get_site_statistics().get_site(site_id),
+    })
+    .unwrap_or_default();
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Get basic data on the server
+#[utoipa::path(get, path = "/basic", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "Basic server data", body = BasicDataResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_get_basic_data_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, retrieving basic data for admin portal".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let basic_data = BasicDataResponse {
+        gruxi_version: env!("CARGO_PKG_VERSION").to_string(),
+        configuration_read_only: cmd_read_only_configuration(),
+        role: session.role,
+        site_ids: session.site_ids.clone(),
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&basic_data).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    return Ok(response);
+}
+
+// Admin healthcheck endpoint - returns simple status without authentication
+#[utoipa::path(get, path = "/healthcheck", tag = "Server", responses(
+        (status = 200, description = "The server is healthy", body = String, content_type = "text/plain"),
+    ))]
+pub async fn admin_healthcheck_endpoint(_gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from("The server is healthy"));
+    response.headers_mut().insert("Content-Type", TEXT_PLAIN_HEADER_VALUE);
+    return Ok(response);
+}
+
+// Load balancer health check - answers 200 while the server is in service and 503 while it is not, without authentication
+#[utoipa::path(get, path = "/healthz", tag = "Server", responses(
+        (status = 200, description = "The server is in service", body = String, content_type = "text/plain"),
+        (status = 503, description = "The server is out of service or draining for shutdown", body = String, content_type = "text/plain"),
+    ))]
+pub async fn admin_healthz_endpoint(_gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    Ok(get_health_check_response())
+}
+
+// A short summary of the server, as shown by `gruxi status`. The admin portal listens on all interfaces and may be behind a proxy on
+// the same machine, so requests from the loopback address need a login too. `gruxi status` on the machine uses the control socket instead
+#[utoipa::path(get, path = "/server/status", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "Status of the server", body = ServerStatus),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "Only admins can see this", body = ErrorResponse),
+    ))]
+pub async fn admin_server_status_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+    if !session.is_admin() {
+        return Ok(get_forbidden_response());
+    }
+
+    let status = get_server_status().await;
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&status).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    response.headers_mut().insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(response)
+}
+
+// Whether the load balancer health check says the server is in service
+#[utoipa::path(get, path = "/server/in-service", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "Whether the server is in service", body = InServiceResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "Only admins can see this", body = ErrorResponse),
+    ))]
+pub async fn admin_get_in_service_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+    if !session.is_admin() {
+        return Ok(get_forbidden_response());
+    }
+
+    get_in_service_response().await
+}
+
+// Takes the server out of the load balancers before maintenance, or puts it back after. It keeps serving the requests that
+// come in, only the health check changes. The server is back in service after a restart
+#[utoipa::path(post, path = "/server/in-service", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), request_body = InServiceRequest, responses(
+        (status = 200, description = "Whether the server is in service now", body = InServiceResponse),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 403, description = "Only admins can change this", body = ErrorResponse),
+    ))]
+pub async fn admin_post_in_service_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+    if !session.is_admin() {
+        return Ok(get_forbidden_response());
+    }
+
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let in_service_request: InServiceRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            let error_response = serde_json::json!({ "error": "Invalid JSON format", "details": e.to_string() });
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_response.to_string()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
 
-    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(monitoring_data.to_string()));
+    if set_in_service(in_service_request.in_service) != in_service_request.in_service {
+        let state = if in_service_request.in_service { "in service" } else { "out of service" };
+        info(format!("Audit: Server set {} for load balancers by {}", state, session.username));
+    }
+
+    get_in_service_response().await
+}
+
+async fn get_in_service_response() -> Result<GruxiResponse, GruxiError> {
+    let in_service_response = InServiceResponse {
+        in_service: is_in_service(),
+        draining: is_draining(),
+        health_check_path: get_cached_configuration().get_configuration().await.core.server_settings.health_check_path.clone(),
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&in_service_response).unwrap_or_default()));
     response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
-    return Ok(response);
+    Ok(response)
 }
 
-// Get basic data on the server
-pub async fn admin_get_basic_data_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+// Admin logs endpoint - lists available log files
+#[utoipa::path(get, path = "/logs", tag = "Logs", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "The log files, including the PHP error logs of sites", body = LogFilesResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_logs_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check authentication first
-    match require_authentication(&gruxi_request).await {
-        Ok(Some(_session)) => {
-            debug("User authenticated, retrieving basic data for admin portal".to_string());
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, retrieving logs".to_string());
+            session
         }
         Ok(None) => {
             let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
@@ -468,30 +2323,28 @@ pub async fn admin_get_basic_data_endpoint(gruxi_request: &mut GruxiRequest, _ad
         Err(auth_response) => {
             return Ok(auth_response);
         }
-    }
-
-    let response_json = serde_json::json!({
-        "gruxi_version": env!("CARGO_PKG_VERSION"),
-    });
-
-    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json.to_string()));
-    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
-    return Ok(response);
-}
+    };
 
-// Admin healthcheck endpoint - returns simple status without authentication
-pub async fn admin_healthcheck_endpoint(_gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
-    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from("The server is healthy"));
-    response.headers_mut().insert("Content-Type", TEXT_PLAIN_HEADER_VALUE);
-    return Ok(response);
+    // Site owners only see the logs of their own sites
+    let allowed_paths = if session.is_admin() { None } else { Some(get_site_log_paths(&session.site_ids).await) };
+    list_log_files(allowed_paths).await
 }
 
-// Admin logs endpoint - lists available log files or returns specific log content
-pub async fn admin_logs_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+// Admin log file endpoint - returns the content of a log file
+#[utoipa::path(get, path = "/logs/{filename}", tag = "Logs", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("filename" = String, Path, description = "Name of a .log file from the list of log files"),
+    ), responses(
+        (status = 200, description = "The content of the log file, at most the last ~1MB", body = LogFileContentResponse),
+        (status = 400, description = "Invalid filename", body = ErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 404, description = "Log file not found", body = ErrorResponse),
+    ))]
+pub async fn admin_log_file_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check authentication first
-    match require_authentication(&gruxi_request).await {
-        Ok(Some(_session)) => {
-            debug("User authenticated, retrieving logs".to_string());
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, retrieving log file".to_string());
+            session
         }
         Ok(None) => {
             let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
@@ -501,28 +2354,16 @@ pub async fn admin_logs_endpoint(gruxi_request: &mut GruxiRequest, _admin_site:
         Err(auth_response) => {
             return Ok(auth_response);
         }
-    }
+    };
 
     let path = gruxi_request.get_path();
-    let path_parts: Vec<&str> = path.split('/').collect();
-
-    // Parse the request path: /logs or /logs/{filename}
-    if path_parts.len() == 2 && path_parts[1] == "logs" {
-        // List all available log files
-        list_log_files().await
-    } else if path_parts.len() == 3 && path_parts[1] == "logs" {
-        // Return specific log file content
-        let filename = path_parts[2];
-        get_log_file_content(filename).await
-    } else {
-        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(r#"{"error": "Invalid logs endpoint path"}"#));
-        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
-        return Ok(response);
-    }
+    let filename = path.strip_prefix("/logs/").unwrap_or_default();
+    let allowed_paths = if session.is_admin() { None } else { Some(get_site_log_paths(&session.site_ids).await) };
+    get_log_file_content(filename, allowed_paths).await
 }
 
 // Helper function to list all .log files in the logs directory
-async fn list_log_files() -> Result<GruxiResponse, GruxiError> {
+async fn list_log_files(allowed_paths: Option<Vec<PathBuf>>) -> Result<GruxiResponse, GruxiError> {
     let logs_dir = Path::new("logs");
 
     match fs::read_dir(logs_dir) {
@@ -539,11 +2380,12 @@ async fn list_log_files() -> Result<GruxiResponse, GruxiError> {
                                     let metadata = fs::metadata(&path);
                                     let file_size = metadata.map(|m| m.len()).unwrap_or(0);
 
-                                    log_files.push(serde_json::json!({
-                                        "filename": filename_str,
-                                        "size": file_size,
-                                        "path": path.to_string_lossy()
-                                    }));
+                                    log_files.push(LogFileInfo {
+                                        filename: filename_str.to_string(),
+                                        size: file_size,
+                                        path: path.to_string_lossy().to_string(),
+                                        site: None,
+                                    });
                                 }
                             }
                         }
@@ -551,12 +2393,39 @@ async fn list_log_files() -> Result<GruxiResponse, GruxiError> {
                 }
             }
 
-            let response_json = serde_json::json!({
-                "success": true,
-                "files": log_files
-            });
+            // Add the PHP error logs of the sites, which can be outside the logs directory
+            let logs_dir_absolute = std::path::absolute(logs_dir).ok();
+            for (site_name, path) in get_site_php_error_logs().await {
+                let filename = match path.file_name().and_then(|f| f.to_str()) {
+                    Some(filename) => filename.to_string(),
+                    None => continue,
+                };
+                if path.parent().map(|p| p.to_path_buf()) == logs_dir_absolute {
+                    if let Some(log_file) = log_files.iter_mut().find(|f| f.filename == filename) {
+                        log_file.site = Some(site_name);
+                    }
+                    continue;
+                }
+                // Files are fetched by name, so a name that is already listed cannot be told apart
+                if !path.is_file() || log_files.iter().any(|f| f.filename == filename) {
+                    continue;
+                }
+                let file_size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                log_files.push(LogFileInfo {
+                    filename,
+                    size: file_size,
+                    path: path.to_string_lossy().to_string(),
+                    site: Some(site_name),
+                });
+            }
 
-            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json.to_string()));
+            if let Some(allowed_paths) = allowed_paths {
+                log_files.retain(|log_file| fs::canonicalize(&log_file.path).is_ok_and(|path| allowed_paths.contains(&path)));
+            }
+
+            let log_files_response = LogFilesResponse { success: true, files: log_files };
+
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&log_files_response).unwrap_or_default()));
             response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
             return Ok(response);
         }
@@ -574,8 +2443,32 @@ async fn list_log_files() -> Result<GruxiResponse, GruxiError> {
     }
 }
 
+// PHP error log files of the sites, with the hostnames of the site each belongs to
+async fn get_site_php_error_logs() -> Vec<(String, PathBuf)> {
+    let config = get_cached_configuration().get_configuration().await;
+    config
+        .sites
+        .iter()
+        .filter_map(|site| site.get_php_error_log_path().map(|path| (site.hostnames.join(", "), path)))
+        .collect()
+}
+
+// Canonical paths of the access and PHP error log files of the sites, which is what site owners get to read of the logs. Compared by
+// full path, as another log with the same name can be in the logs directory
+async fn get_site_log_paths(site_ids: &[String]) -> Vec<PathBuf> {
+    let config = get_cached_configuration().get_configuration().await;
+    config
+        .sites
+        .iter()
+        .filter(|site| site_ids.contains(&site.id))
+        .flat_map(|site| [site.access_log_file.clone(), site.php_error_log_file.clone()])
+        .filter(|log_file| !log_file.is_empty())
+        .filter_map(|log_file| fs::canonicalize(log_file).ok())
+        .collect()
+}
+
 // Helper function to get log file content with 1MB limit
-async fn get_log_file_content(filename: &str) -> Result<GruxiResponse, GruxiError> {
+async fn get_log_file_content(filename: &str, allowed_paths: Option<Vec<PathBuf>>) -> Result<GruxiResponse, GruxiError> {
     // Validate filename to prevent directory traversal
     if filename.contains("..") || filename.contains("/") || filename.contains("\\") {
         let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(r#"{"error": "Invalid filename"}"#));
@@ -590,13 +2483,27 @@ async fn get_log_file_content(filename: &str) -> Result<GruxiResponse, GruxiErro
         return Ok(response);
     }
 
-    let log_path = Path::new("logs").join(filename);
+    // Log files are in the logs directory, except the PHP error logs of sites, which can be anywhere
+    let mut log_path = Path::new("logs").join(filename);
+    if !log_path.exists()
+        && let Some((_, site_log_path)) = get_site_php_error_logs()
+            .await
+            .into_iter()
+            .find(|(_, path)| path.file_name().and_then(|f| f.to_str()) == Some(filename))
+    {
+        log_path = site_log_path;
+    }
 
     if !log_path.exists() {
         let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::NOT_FOUND.as_u16(), bytes::Bytes::from(r#"{"error": "Log file not found"}"#));
         response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
         return Ok(response);
     }
+    if let Some(allowed_paths) = allowed_paths
+        && !fs::canonicalize(&log_path).is_ok_and(|path| allowed_paths.contains(&path))
+    {
+        return Ok(get_forbidden_response());
+    }
 
     match fs::metadata(&log_path) {
         Ok(metadata) => {
@@ -626,21 +2533,21 @@ async fn get_log_file_content(filename: &str) -> Result<GruxiResponse, GruxiErro
                         (content, false)
                     };
 
-                    let response_json = serde_json::json!({
-                        "success": true,
-                        "filename": filename,
-                        "content": log_content,
-                        "file_size": file_size,
-                        "is_truncated": is_truncated,
-                        "full_path": log_path.to_string_lossy(),
-                        "message": if is_truncated {
+                    let log_file_response = LogFileContentResponse {
+                        success: true,
+                        filename: filename.to_string(),
+                        content: log_content,
+                        file_size,
+                        is_truncated,
+                        full_path: log_path.to_string_lossy().to_string(),
+                        message: if is_truncated {
                             format!("File is larger than 1MB. Showing last ~1MB. Full file is available at: {}", log_path.to_string_lossy())
                         } else {
                             "".to_string()
-                        }
-                    });
+                        },
+                    };
 
-                    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json.to_string()));
+                    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&log_file_response).unwrap_or_default()));
                     response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
                     return Ok(response);
                 }
@@ -671,21 +2578,14 @@ async fn get_log_file_content(filename: &str) -> Result<GruxiResponse, GruxiErro
     }
 }
 
-// Request/Response structures for operation mode
-#[derive(Serialize, Deserialize)]
-struct OperationModeResponse {
-    mode: String,
-}
-
-#[derive(Serialize, Deserialize)]
-struct OperationModeRequest {
-    mode: String,
-}
-
 // Admin operation mode GET endpoint - returns current operation mode
+#[utoipa::path(get, path = "/operation-mode", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "The current operation mode", body = OperationModeResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
 pub async fn admin_get_operation_mode_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check authentication first
-    match require_authentication(&gruxi_request).await {
+    match require_authentication(gruxi_request).await {
         Ok(Some(_session)) => {
             debug("User authenticated, retrieving operation mode".to_string());
         }
@@ -719,6 +2619,11 @@ pub async fn admin_get_operation_mode_endpoint(gruxi_request: &mut GruxiRequest,
 }
 
 // Admin operation mode POST endpoint - changes operation mode
+#[utoipa::path(post, path = "/operation-mode", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), request_body = OperationModeRequest, responses(
+        (status = 200, description = "Operation mode set", body = OperationModeChangeResponse),
+        (status = 400, description = "Invalid operation mode", body = ErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
 pub async fn admin_post_operation_mode_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
     // Check if this is a POST request
     if gruxi_request.get_http_method() != "POST" {
@@ -728,8 +2633,11 @@ pub async fn admin_post_operation_mode_endpoint(gruxi_request: &mut GruxiRequest
     }
 
     // Check authentication first
-    match require_authentication(&gruxi_request).await {
-        Ok(Some(_session)) => {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
             debug("User authenticated for operation mode update".to_string());
         }
         Ok(None) => {
@@ -742,13 +2650,13 @@ pub async fn admin_post_operation_mode_endpoint(gruxi_request: &mut GruxiRequest
         }
     }
 
-    // Read the request body
-    if gruxi_request.get_body_size() == 0 {
+    // Read the request body, which may be chunked, so its size is only known once it has been read
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    if body_bytes.is_empty() {
         let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(r#"{"error": "Empty request body"}"#));
         response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
         return Ok(response);
     }
-    let body_bytes = gruxi_request.get_body_bytes().await;
 
     // Parse JSON body
     let mode_request: OperationModeRequest = match serde_json::from_slice(&body_bytes) {
@@ -787,13 +2695,509 @@ pub async fn admin_post_operation_mode_endpoint(gruxi_request: &mut GruxiRequest
         format!("Operation mode was already set to {}", mode_request.mode)
     };
 
-    let success_response = serde_json::json!({
-        "success": was_changed,
-        "message": return_message,
-        "mode": mode_request.mode
-    });
+    let change_response = OperationModeChangeResponse {
+        success: was_changed,
+        message: return_message,
+        mode: mode_request.mode,
+    };
 
-    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(success_response.to_string()));
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&change_response).unwrap_or_default()));
     response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
     return Ok(response);
 }
+
+fn get_log_levels_response() -> GruxiResponse {
+    let (log_level_override, effective_level, modules) = SysLog::get_log_levels();
+    let log_levels_response = LogLevelsResponse {
+        level: log_level_override.map(|level| level.get_name().to_string()).unwrap_or_default(),
+        effective_level: effective_level.get_name().to_string(),
+        modules,
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(serde_json::to_string(&log_levels_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    response
+}
+
+// The log levels in effect, globally and for the modules that have a level of their own
+#[utoipa::path(get, path = "/log-levels", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "The log levels in effect", body = LogLevelsResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_get_log_levels_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            if !session.is_admin() {
+                return Ok(get_forbidden_response());
+            }
+            debug("User authenticated, retrieving log levels".to_string());
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    }
+
+    Ok(get_log_levels_response())
+}
+
+// Changes the log levels right away, without a restart. They last until the configuration is reloaded, which applies the configured levels again
+#[utoipa::path(post, path = "/log-levels", tag = "Server", security(("session_token" = []), ("session_cookie" = [])), request_body = LogLevelsRequest, responses(
+        (status = 200, description = "Log levels set, with the levels now in effect", body = LogLevelsResponse),
+        (status = 400, description = "Invalid log level or module", body = ValidationErrorResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_post_log_levels_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+    if !session.is_admin() {
+        return Ok(get_forbidden_response());
+    }
+
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let log_levels_request: LogLevelsRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            let error_response = serde_json::json!({ "error": "Invalid JSON format", "details": e.to_string() });
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(error_response.to_string()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+
+    let level = log_levels_request.level.trim().to_lowercase();
+    let modules = log_levels_request
+        .modules
+        .iter()
+        .map(|(module, level)| (module.trim().to_lowercase(), level.trim().to_lowercase()))
+        .filter(|(_, level)| !level.is_empty())
+        .collect();
+    match parse_log_levels(&level, &modules) {
+        Ok((log_level_override, module_levels)) => {
+            SysLog::set_log_levels(log_level_override, &module_levels);
+            info(format!("Audit: Log levels set to '{}' with modules {:?} by {}", level, modules, session.username));
+            Ok(get_log_levels_response())
+        }
+        Err(errors) => {
+            let error_response = ValidationErrorResponse { errors };
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            Ok(response)
+        }
+    }
+}
+
+// Lists the sites that currently have debug captures
+#[utoipa::path(get, path = "/debug-capture", tag = "Debug capture", security(("session_token" = []), ("session_cookie" = [])), responses(
+        (status = 200, description = "The number of captures per site", body = DebugCaptureSitesResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_debug_capture_sites_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, listing debug captures".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let sites = get_debug_capture()
+        .get_capture_counts()
+        .into_iter()
+        .filter(|(site_id, _)| session.can_access_site(site_id))
+        .map(|(site_id, count)| DebugCaptureSiteCount { site_id, count })
+        .collect();
+    let response_json = serde_json::to_string(&DebugCaptureSitesResponse { success: true, sites }).unwrap_or_default();
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/debug-capture/{site_id}", tag = "Debug capture", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), responses(
+        (status = 200, description = "The captured requests and responses of the site", body = DebugCaptureResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_debug_capture_site_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, retrieving debug captures".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/debug-capture/").unwrap_or_default();
+    if !session.can_access_site(site_id) {
+        return Ok(get_forbidden_response());
+    }
+    let response_json = serde_json::to_string(&DebugCaptureResponse {
+        success: true,
+        site_id: site_id.to_string(),
+        captures: get_debug_capture().get_captures(site_id),
+    })
+    .unwrap_or_default();
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+#[utoipa::path(delete, path = "/debug-capture/{site_id}", tag = "Debug capture", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), responses(
+        (status = 200, description = "The captures of the site are cleared", body = MessageResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_clear_debug_capture_site_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, clearing debug captures".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/debug-capture/").unwrap_or_default();
+    if !session.can_access_site(site_id) {
+        return Ok(get_forbidden_response());
+    }
+    get_debug_capture().clear_captures(site_id);
+    info(format!("Debug captures cleared for site: {}", site_id));
+
+    let response_json = serde_json::to_string(&MessageResponse {
+        success: true,
+        message: "Debug captures cleared".to_string(),
+    })
+    .unwrap_or_default();
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// The OpenAPI document of the admin API, for generating clients. Needs no session, as it only describes the API
+#[utoipa::path(get, path = "/api/openapi.json", tag = "Server", responses(
+        (status = 200, description = "This OpenAPI document", body = Object),
+    ))]
+pub async fn admin_openapi_endpoint(_gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(get_openapi_json()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// JSON Schema of the configuration, with defaults and allowed values, for rendering configuration forms and validating configurations before upload.
+// Needs no session, as it only describes the configuration format
+#[utoipa::path(get, path = "/api/configuration/schema", tag = "Configuration", responses(
+        (status = 200, description = "JSON Schema (draft 2020-12) of the configuration", body = Object),
+    ))]
+pub async fn admin_configuration_schema_endpoint(_gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(get_configuration_schema_json()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+#[utoipa::path(post, path = "/deploy/{site_id}", tag = "Deployment", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), responses(
+        (status = 202, description = "The build of the site is started", body = DeployResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+        (status = 404, description = "No site with a build command or git repository has this id", body = ErrorResponse),
+        (status = 409, description = "A build of the site is already running", body = ErrorResponse),
+    ))]
+pub async fn admin_post_deploy_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, starting build".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/deploy/").unwrap_or_default();
+    if !session.can_access_site(site_id) {
+        return Ok(get_forbidden_response());
+    }
+    Ok(start_site_build(site_id, &session.username).await)
+}
+
+#[utoipa::path(get, path = "/deploy/{site_id}", tag = "Deployment", security(("session_token" = []), ("session_cookie" = [])), params(
+        ("site_id" = String, Path, description = "ID of the site"),
+    ), responses(
+        (status = 200, description = "The active release and the last build of the site", body = DeployStatusResponse),
+        (status = 401, description = "Authentication required or the session is invalid", body = ErrorResponse),
+    ))]
+pub async fn admin_get_deploy_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    // Check authentication first
+    let session = match require_authentication(gruxi_request).await {
+        Ok(Some(session)) => {
+            debug("User authenticated, retrieving build status".to_string());
+            session
+        }
+        Ok(None) => {
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Authentication required"}"#));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+        Err(auth_response) => {
+            return Ok(auth_response);
+        }
+    };
+
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/deploy/").unwrap_or_default();
+    if !session.can_access_site(site_id) {
+        return Ok(get_forbidden_response());
+    }
+    let site_builder = get_site_builder();
+    let response_json = serde_json::to_string(&DeployStatusResponse {
+        success: true,
+        site_id: site_id.to_string(),
+        active_release_id: site_builder.get_active_release_id(site_id),
+        last_build: site_builder.get_last_build(site_id),
+    })
+    .unwrap_or_default();
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+// Starts a build from a CI pipeline or a repository webhook. Needs no session, the site's deploy webhook token is sent instead
+#[utoipa::path(post, path = "/deploy-webhook/{site_id}", tag = "Deployment", params(
+        ("site_id" = String, Path, description = "ID of the site"),
+        ("X-Gruxi-Deploy-Token" = String, Header, description = "The deploy webhook token of the site"),
+    ), responses(
+        (status = 202, description = "The build of the site is started", body = DeployResponse),
+        (status = 401, description = "The token is missing or wrong, or the site has no deploy webhook", body = ErrorResponse),
+        (status = 409, description = "A build of the site is already running", body = ErrorResponse),
+    ))]
+pub async fn deploy_webhook_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/deploy-webhook/").unwrap_or_default();
+    let token = gruxi_request.get_headers().get(DEPLOY_TOKEN_HEADER).and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+
+    // Unknown sites get the same answer as wrong tokens, so the webhook does not reveal which site ids exist
+    let configuration = get_cached_configuration().get_configuration().await;
+    let is_authorized = configuration.sites.iter().any(|site| site.id == site_id && is_deploy_webhook_token_valid(site, &token));
+    if !is_authorized {
+        warn(format!("Rejected deploy webhook for site {} from {}", site_id, gruxi_request.get_remote_ip()));
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Invalid deploy token"}"#));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    }
+
+    Ok(start_site_build(site_id, "webhook").await)
+}
+
+// Deploys the site on pushes to its branch, as the push webhook of a GitHub or GitLab repository. Needs no session, the webhook is
+// signed with the git webhook secret of the site instead
+#[utoipa::path(post, path = "/git-webhook/{site_id}", tag = "Deployment", params(
+        ("site_id" = String, Path, description = "ID of the site"),
+        ("X-Hub-Signature-256" = Option<String>, Header, description = "HMAC-SHA256 signature of the body, as sent by GitHub"),
+        ("X-Gitlab-Token" = Option<String>, Header, description = "The webhook secret, as sent by GitLab"),
+    ), responses(
+        (status = 202, description = "The push is to the branch of the site, and a release of it is being built", body = DeployResponse),
+        (status = 200, description = "The event does not deploy anything, such as a ping or a push to another branch", body = MessageResponse),
+        (status = 400, description = "The event cannot be read", body = ErrorResponse),
+        (status = 401, description = "The signature or token is missing or wrong, or the site has no git webhook", body = ErrorResponse),
+        (status = 409, description = "A build of the site is already running", body = ErrorResponse),
+    ))]
+pub async fn git_webhook_endpoint(gruxi_request: &mut GruxiRequest, _admin_site: &Site) -> Result<GruxiResponse, GruxiError> {
+    let path = gruxi_request.get_path();
+    let site_id = path.strip_prefix("/git-webhook/").unwrap_or_default().to_string();
+    let body_bytes = gruxi_request.get_body_bytes().await;
+    let headers = gruxi_request.get_headers().clone();
+
+    // Unknown sites get the same answer as wrong signatures, so the webhook does not reveal which site ids exist
+    let configuration = get_cached_configuration().get_configuration().await;
+    let verify_result = match configuration.sites.iter().find(|site| site.id == site_id) {
+        Some(site) => verify_git_webhook(site, &headers, &body_bytes),
+        None => Err("No site has this id".to_string()),
+    };
+    if let Err(e) = verify_result {
+        warn(format!("Rejected git webhook for site {} from {}: {}", site_id, gruxi_request.get_remote_ip(), e));
+        let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::UNAUTHORIZED.as_u16(), bytes::Bytes::from(r#"{"error": "Invalid webhook signature"}"#));
+        response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+        return Ok(response);
+    }
+
+    let message = match parse_git_webhook_event(&headers, &body_bytes) {
+        Ok(GitWebhookEvent::Push(branch)) if configuration.sites.iter().any(|site| site.id == site_id && site.deploy_git_branch == branch) => {
+            return Ok(start_site_build(&site_id, "git push").await);
+        }
+        Ok(GitWebhookEvent::Push(branch)) => format!("Ignored push to branch {}", branch),
+        Ok(GitWebhookEvent::Ping) => "Webhook is set up".to_string(),
+        Ok(GitWebhookEvent::Other(event)) => format!("Ignored {} event", event),
+        Err(e) => {
+            let error_response = ErrorResponse { error: e, details: None };
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::BAD_REQUEST.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+            response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+            return Ok(response);
+        }
+    };
+    trace(format!("Git webhook for site {}: {}", site_id, message));
+
+    let response_json = serde_json::to_string(&MessageResponse { success: true, message }).unwrap_or_default();
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), bytes::Bytes::from(response_json));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    Ok(response)
+}
+
+async fn start_site_build(site_id: &str, triggered_by: &str) -> GruxiResponse {
+    let configuration = get_cached_configuration().get_configuration().await;
+    let (status, response_json) = match configuration.sites.iter().find(|site| site.id == site_id && site.is_deployed_in_releases()) {
+        None => (
+            hyper::StatusCode::NOT_FOUND,
+            serde_json::to_string(&ErrorResponse {
+                error: "No site with a build command or git repository found".to_string(),
+                details: None,
+            }),
+        ),
+        Some(site) => match get_site_builder().start_build(site, triggered_by) {
+            Ok(release_id) => (
+                hyper::StatusCode::ACCEPTED,
+                serde_json::to_string(&DeployResponse {
+                    success: true,
+                    site_id: site_id.to_string(),
+                    release_id,
+                }),
+            ),
+            Err(e) => (hyper::StatusCode::CONFLICT, serde_json::to_string(&ErrorResponse { error: e, details: None })),
+        },
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(status.as_u16(), bytes::Bytes::from(response_json.unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    response
+}
+
+// The response to configuration changes when the server is started with --read-only-conf
+fn read_only_configuration_response() -> GruxiResponse {
+    let error_response = ErrorResponse {
+        error: "Configuration is read-only".to_string(),
+        details: Some("The configuration is managed outside the admin portal, and Gruxi was started with --read-only-conf. Change it at its source and import it with --import-conf".to_string()),
+    };
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::CONFLICT.as_u16(), bytes::Bytes::from(serde_json::to_string(&error_response).unwrap_or_default()));
+    response.headers_mut().insert("Content-Type", JSON_HEADER_VALUE);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::admin_user::create_admin_user;
+    use crate::core::database_connection::get_database_connection;
+    use crate::database::database_schema::initialize_database;
+
+    fn create_request(method: &str, csrf_token: Option<&str>) -> GruxiRequest {
+        let mut builder = hyper::Request::builder().method(method).uri("/config").header("Cookie", format!("{}=token", SESSION_COOKIE_NAME));
+        if let Some(csrf_token) = csrf_token {
+            builder = builder.header(CSRF_TOKEN_HEADER, csrf_token);
+        }
+        GruxiRequest::new(builder.body(bytes::Bytes::new()).unwrap())
+    }
+
+    fn set_last_activity(session: &Session, last_activity_at: chrono::DateTime<chrono::Utc>) {
+        get_database_connection()
+            .unwrap()
+            .execute(format!("UPDATE sessions SET last_activity_at = '{}' WHERE id = '{}'", last_activity_at.to_rfc3339(), session.id))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_and_csrf_tokens() {
+        // A user of its own in the database the tests share
+        initialize_database().unwrap();
+        let username = format!("test-{}", uuid::Uuid::new_v4());
+        create_admin_user(&get_database_connection().unwrap(), &username, "password123").unwrap();
+
+        // Logging in issues a session token and a CSRF token of its own
+        let user = authenticate_user(&username, "password123").unwrap().unwrap();
+        let session = create_session(&user, chrono::Duration::hours(1), 0, 32).unwrap();
+        assert_eq!(session.token.len(), 64);
+        assert!(!session.csrf_token.is_empty() && session.csrf_token != session.token);
+
+        // The session is found by its token only
+        let verified = verify_session_token(&session.token, None).unwrap().unwrap();
+        assert_eq!(verified.id, session.id);
+        assert_eq!(verified.csrf_token, session.csrf_token);
+        assert!(verify_session_token("unknown", None).unwrap().is_none());
+        assert!(verify_session_token("", None).unwrap().is_none());
+
+        // State-changing requests need the CSRF token of the session, reading requests do not
+        assert!(get_csrf_rejection_response(&mut create_request("POST", Some(&session.csrf_token)), &verified).is_none());
+        assert!(get_csrf_rejection_response(&mut create_request("GET", None), &verified).is_none());
+        let rejection = get_csrf_rejection_response(&mut create_request("POST", None), &verified).unwrap();
+        assert_eq!(rejection.get_status(), 403);
+        assert!(get_csrf_rejection_response(&mut create_request("DELETE", Some("wrong")), &verified).is_some());
+        let other_session = create_session(&user, chrono::Duration::hours(1), 0, 32).unwrap();
+        assert!(get_csrf_rejection_response(&mut create_request("PUT", Some(&other_session.csrf_token)), &verified).is_some());
+
+        // The activity is written only once it is out of date
+        assert_eq!(verified.last_activity_at, session.last_activity_at);
+        let last_activity_at = chrono::Utc::now() - chrono::Duration::minutes(10);
+        set_last_activity(&session, last_activity_at);
+        let verified = verify_session_token(&session.token, Some(chrono::Duration::minutes(30))).unwrap().unwrap();
+        assert!(verified.last_activity_at > last_activity_at + chrono::Duration::minutes(9));
+        assert_eq!(verify_session_token(&session.token, None).unwrap().unwrap().last_activity_at, verified.last_activity_at);
+
+        // Sessions idle for longer than the idle timeout are rejected and removed
+        set_last_activity(&session, last_activity_at);
+        assert!(verify_session_token(&session.token, Some(chrono::Duration::minutes(5))).unwrap().is_none());
+        assert!(verify_session_token(&session.token, None).unwrap().is_none());
+        assert!(invalidate_session(&other_session.token).unwrap());
+        assert!(verify_session_token(&other_session.token, None).unwrap().is_none());
+
+        get_database_connection().unwrap().execute(format!("DELETE FROM users WHERE username = '{}'", username)).unwrap();
+    }
+}
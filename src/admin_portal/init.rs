@@ -1,6 +1,6 @@
-use crate::{core::admin_user::create_default_admin_user, logging::syslog::error};
+use crate::{admin_portal::setup::get_setup_state, core::admin_user::count_users, logging::syslog::error, logging::syslog::info};
 
-pub fn initialize_admin_site() -> Result<(), ()>{
+pub fn initialize_admin_site() -> Result<(), ()> {
     // Check if there is at least one admin user
     let connection_result = crate::core::database_connection::get_database_connection();
     let connection = match connection_result {
@@ -11,14 +11,22 @@ pub fn initialize_admin_site() -> Result<(), ()>{
         }
     };
 
-    let admin_user_result = create_default_admin_user(&connection);
-    match admin_user_result {
-        Ok(_) => (),
+    let user_count = match count_users(&connection) {
+        Ok(count) => count,
         Err(e) => {
-            error(format!("Failed to create default admin user: {}", e));
+            error(format!("Failed to check for admin users: {}", e));
             return Err(());
         }
     };
 
+    // Without admin users, the admin portal starts in setup mode, where the first admin user is created with the setup token
+    if user_count == 0 {
+        let setup_token = get_setup_state().start_setup();
+        info(format!(
+            "Gruxi has not been set up yet. Open the admin portal and complete the setup with the setup token '{}'",
+            setup_token
+        ));
+    }
+
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,241 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+// Failed logins in a row, per IP or username, before further attempts are locked out
+pub const LOGIN_MAX_FAILURES_BEFORE_LOCKOUT: u32 = 5;
+pub const LOGIN_LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+// After a failed login, the next attempt must wait 1, 2, 4... seconds, up to this
+pub const LOGIN_MAX_BACKOFF: Duration = Duration::from_secs(30);
+// Counters without failures for this long are forgotten
+pub const LOGIN_FAILURE_COUNTER_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone)]
+struct AttemptCounter {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl AttemptCounter {
+    // When the next attempt is allowed, either after the lockout or after the backoff of the last failure
+    fn get_blocked_until(&self) -> Instant {
+        if let Some(locked_until) = self.locked_until {
+            return locked_until;
+        }
+        let backoff = Duration::from_secs(1u64 << self.failures.saturating_sub(1).min(16)).min(LOGIN_MAX_BACKOFF);
+        self.last_failure + backoff
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AttemptCounterInfo {
+    pub key: String,
+    pub failures: u32,
+    pub seconds_since_last_failure: u64,
+    pub blocked_for_seconds: u64,
+    pub is_locked_out: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginThrottleResult {
+    Allowed,
+    // The attempt is rejected without checking the password, and may be retried after the duration
+    Blocked(Duration),
+}
+
+// Brute-force protection for the admin portal login, with separate counters for client IPs and usernames,
+// so neither many usernames from one IP nor one username from many IPs can be tried quickly
+#[derive(Default)]
+pub struct LoginThrottle {
+    ip_counters: Mutex<HashMap<String, AttemptCounter>>,
+    username_counters: Mutex<HashMap<String, AttemptCounter>>,
+}
+
+impl LoginThrottle {
+    pub fn new() -> Self {
+        LoginThrottle {
+            ip_counters: Mutex::new(HashMap::new()),
+            username_counters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check_attempt(&self, ip: &str, username: &str, now: Instant) -> LoginThrottleResult {
+        let ip_counters = Self::lock(&self.ip_counters);
+        let username_counters = Self::lock(&self.username_counters);
+        Self::get_result(&ip_counters, &username_counters, ip, &username.to_lowercase(), now)
+    }
+
+    // Checks an attempt and, if it is allowed, records it as failed right away, before the slow password check, so parallel attempts
+    // cannot all pass the check before the first of them has failed. A successful login clears the failure again with record_success
+    pub fn reserve_attempt(&self, ip: &str, username: &str, now: Instant) -> LoginThrottleResult {
+        let mut ip_counters = Self::lock(&self.ip_counters);
+        let mut username_counters = Self::lock(&self.username_counters);
+        let username = username.to_lowercase();
+        let result = Self::get_result(&ip_counters, &username_counters, ip, &username, now);
+        if result == LoginThrottleResult::Allowed {
+            Self::add_failure(&mut ip_counters, ip, now);
+            Self::add_failure(&mut username_counters, &username, now);
+        }
+        result
+    }
+
+    // Records a failed login. Returns true if this failure locked out the IP or the username
+    pub fn record_failure(&self, ip: &str, username: &str, now: Instant) -> bool {
+        let ip_locked = Self::add_failure(&mut Self::lock(&self.ip_counters), ip, now);
+        let username_locked = Self::add_failure(&mut Self::lock(&self.username_counters), &username.to_lowercase(), now);
+        ip_locked || username_locked
+    }
+
+    pub fn is_locked_out(&self, ip: &str, username: &str, now: Instant) -> bool {
+        let is_locked = |counter: Option<&AttemptCounter>| counter.is_some_and(|counter| counter.locked_until.is_some_and(|until| until > now));
+        is_locked(Self::lock(&self.ip_counters).get(ip)) || is_locked(Self::lock(&self.username_counters).get(&username.to_lowercase()))
+    }
+
+    pub fn record_success(&self, ip: &str, username: &str) {
+        Self::lock(&self.ip_counters).remove(ip);
+        Self::lock(&self.username_counters).remove(&username.to_lowercase());
+    }
+
+    // The current counters, for the admin API
+    pub fn get_counters(&self, now: Instant) -> (Vec<AttemptCounterInfo>, Vec<AttemptCounterInfo>) {
+        (Self::get_counter_infos(&self.ip_counters, now), Self::get_counter_infos(&self.username_counters, now))
+    }
+
+    fn lock(counters: &Mutex<HashMap<String, AttemptCounter>>) -> std::sync::MutexGuard<'_, HashMap<String, AttemptCounter>> {
+        counters.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn get_result(ip_counters: &HashMap<String, AttemptCounter>, username_counters: &HashMap<String, AttemptCounter>, ip: &str, username: &str, now: Instant) -> LoginThrottleResult {
+        match [Self::get_blocked_for(ip_counters, ip, now), Self::get_blocked_for(username_counters, username, now)]
+            .into_iter()
+            .flatten()
+            .max()
+        {
+            Some(duration) => LoginThrottleResult::Blocked(duration),
+            None => LoginThrottleResult::Allowed,
+        }
+    }
+
+    fn get_blocked_for(counters: &HashMap<String, AttemptCounter>, key: &str, now: Instant) -> Option<Duration> {
+        let blocked_until = counters.get(key)?.get_blocked_until();
+        if blocked_until > now { Some(blocked_until - now) } else { None }
+    }
+
+    fn add_failure(counters: &mut HashMap<String, AttemptCounter>, key: &str, now: Instant) -> bool {
+        // Forget old counters, so the map does not grow with every IP that ever failed a login
+        counters.retain(|_, counter| now.duration_since(counter.last_failure) < LOGIN_FAILURE_COUNTER_LIFETIME || counter.locked_until.is_some_and(|until| until > now));
+
+        let counter = counters.entry(key.to_string()).or_insert(AttemptCounter {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+
+        // A new series of failures starts after a lockout has ended
+        if counter.locked_until.is_some_and(|until| until <= now) {
+            counter.failures = 0;
+            counter.locked_until = None;
+        }
+
+        counter.failures += 1;
+        counter.last_failure = now;
+        if counter.failures >= LOGIN_MAX_FAILURES_BEFORE_LOCKOUT && counter.locked_until.is_none() {
+            counter.locked_until = Some(now + LOGIN_LOCKOUT_DURATION);
+            return true;
+        }
+        false
+    }
+
+    fn get_counter_infos(counters: &Mutex<HashMap<String, AttemptCounter>>, now: Instant) -> Vec<AttemptCounterInfo> {
+        let counters = Self::lock(counters);
+        let mut infos: Vec<AttemptCounterInfo> = counters
+            .iter()
+            .map(|(key, counter)| AttemptCounterInfo {
+                key: key.clone(),
+                failures: counter.failures,
+                seconds_since_last_failure: now.duration_since(counter.last_failure).as_secs(),
+                blocked_for_seconds: counter.get_blocked_until().saturating_duration_since(now).as_secs(),
+                is_locked_out: counter.locked_until.is_some_and(|until| until > now),
+            })
+            .collect();
+        infos.sort_by(|a, b| b.failures.cmp(&a.failures).then_with(|| a.key.cmp(&b.key)));
+        infos
+    }
+}
+
+static LOGIN_THROTTLE_SINGLETON: OnceLock<LoginThrottle> = OnceLock::new();
+
+pub fn get_login_throttle() -> &'static LoginThrottle {
+    LOGIN_THROTTLE_SINGLETON.get_or_init(LoginThrottle::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_backoff() {
+        let throttle = LoginThrottle::new();
+        let start = Instant::now();
+        assert_eq!(throttle.check_attempt("10.0.0.1", "admin", start), LoginThrottleResult::Allowed);
+
+        // The first failure blocks for 1 second, the second for 2 seconds
+        assert!(!throttle.record_failure("10.0.0.1", "admin", start));
+        assert_eq!(throttle.check_attempt("10.0.0.1", "admin", start), LoginThrottleResult::Blocked(Duration::from_secs(1)));
+        assert_eq!(throttle.check_attempt("10.0.0.1", "admin", start + Duration::from_secs(1)), LoginThrottleResult::Allowed);
+        throttle.record_failure("10.0.0.1", "admin", start + Duration::from_secs(1));
+        assert_eq!(
+            throttle.check_attempt("10.0.0.1", "admin", start + Duration::from_secs(2)),
+            LoginThrottleResult::Blocked(Duration::from_secs(1))
+        );
+
+        // The username is blocked from other IPs too, and the IP for other usernames
+        assert!(matches!(throttle.check_attempt("10.0.0.2", "ADMIN", start + Duration::from_secs(1)), LoginThrottleResult::Blocked(_)));
+        assert!(matches!(throttle.check_attempt("10.0.0.1", "other", start + Duration::from_secs(1)), LoginThrottleResult::Blocked(_)));
+
+        // A successful login clears the counters
+        throttle.record_success("10.0.0.1", "admin");
+        assert_eq!(throttle.check_attempt("10.0.0.1", "admin", start + Duration::from_secs(1)), LoginThrottleResult::Allowed);
+    }
+
+    #[test]
+    fn test_login_lockout() {
+        let throttle = LoginThrottle::new();
+        let mut now = Instant::now();
+        for attempt in 1..=LOGIN_MAX_FAILURES_BEFORE_LOCKOUT {
+            now += LOGIN_MAX_BACKOFF;
+            assert_eq!(throttle.record_failure("10.0.0.1", "admin", now), attempt == LOGIN_MAX_FAILURES_BEFORE_LOCKOUT);
+        }
+        assert_eq!(throttle.check_attempt("10.0.0.3", "admin", now), LoginThrottleResult::Blocked(LOGIN_LOCKOUT_DURATION));
+
+        let (ip_counters, username_counters) = throttle.get_counters(now);
+        assert_eq!(ip_counters[0].key, "10.0.0.1");
+        assert!(ip_counters[0].is_locked_out);
+        assert_eq!(username_counters[0].failures, LOGIN_MAX_FAILURES_BEFORE_LOCKOUT);
+
+        // After the lockout, a new failure starts a new series
+        now += LOGIN_LOCKOUT_DURATION;
+        assert_eq!(throttle.check_attempt("10.0.0.1", "admin", now), LoginThrottleResult::Allowed);
+        assert!(!throttle.record_failure("10.0.0.1", "admin", now));
+        assert_eq!(throttle.get_counters(now).0[0].failures, 1);
+    }
+
+    #[test]
+    fn test_parallel_attempts_are_reserved() {
+        let throttle = LoginThrottle::new();
+        let now = Instant::now();
+
+        // The second attempt arrives while the password of the first is still being checked
+        assert_eq!(throttle.reserve_attempt("10.0.0.1", "admin", now), LoginThrottleResult::Allowed);
+        assert_eq!(throttle.reserve_attempt("10.0.0.1", "admin", now), LoginThrottleResult::Blocked(Duration::from_secs(1)));
+        assert_eq!(throttle.reserve_attempt("10.0.0.2", "Admin", now), LoginThrottleResult::Blocked(Duration::from_secs(1)));
+        assert!(!throttle.is_locked_out("10.0.0.1", "admin", now));
+
+        // A successful login releases the reservation
+        throttle.record_success("10.0.0.1", "admin");
+        assert_eq!(throttle.reserve_attempt("10.0.0.1", "admin", now), LoginThrottleResult::Allowed);
+    }
+}
@@ -1,2 +1,6 @@
+pub mod api_models;
 pub mod http_admin_api;
-pub mod init;
\ No newline at end of file
+pub mod init;
+pub mod login_throttle;
+pub mod openapi;
+pub mod setup;
@@ -0,0 +1,143 @@
+use crate::admin_portal::http_admin_api;
+use std::sync::OnceLock;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+// The OpenAPI 3 document of the admin API, generated from the endpoint annotations and the typed request and response structs
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Gruxi Admin API", description = "API of the Gruxi admin portal"),
+    paths(
+        http_admin_api::handle_login_request,
+        http_admin_api::admin_login_attempts_endpoint,
+        http_admin_api::admin_get_setup_endpoint,
+        http_admin_api::admin_post_setup_endpoint,
+        http_admin_api::handle_password_reset_request,
+        http_admin_api::handle_password_reset_email_request,
+        http_admin_api::handle_logout_request,
+        http_admin_api::admin_get_basic_data_endpoint,
+        http_admin_api::admin_get_configuration_endpoint,
+        http_admin_api::admin_post_configuration_endpoint,
+        http_admin_api::admin_preview_configuration_endpoint,
+        http_admin_api::admin_monitoring_endpoint,
+        http_admin_api::admin_monitoring_history_endpoint,
+        http_admin_api::admin_site_statistics_endpoint,
+        http_admin_api::admin_site_statistics_site_endpoint,
+        http_admin_api::admin_healthcheck_endpoint,
+        http_admin_api::admin_healthz_endpoint,
+        http_admin_api::admin_server_status_endpoint,
+        http_admin_api::admin_get_in_service_endpoint,
+        http_admin_api::admin_post_in_service_endpoint,
+        http_admin_api::admin_logs_endpoint,
+        http_admin_api::admin_log_file_endpoint,
+        http_admin_api::admin_post_configuration_reload,
+        http_admin_api::admin_get_operation_mode_endpoint,
+        http_admin_api::admin_post_operation_mode_endpoint,
+        http_admin_api::admin_get_log_levels_endpoint,
+        http_admin_api::admin_post_log_levels_endpoint,
+        http_admin_api::admin_debug_capture_sites_endpoint,
+        http_admin_api::admin_debug_capture_site_endpoint,
+        http_admin_api::admin_clear_debug_capture_site_endpoint,
+        http_admin_api::admin_openapi_endpoint,
+        http_admin_api::admin_configuration_schema_endpoint,
+        http_admin_api::admin_post_server_upgrade_endpoint,
+        http_admin_api::admin_dns_cache_endpoint,
+        http_admin_api::admin_post_email_test_endpoint,
+        http_admin_api::admin_post_deploy_endpoint,
+        http_admin_api::admin_get_deploy_endpoint,
+        http_admin_api::deploy_webhook_endpoint,
+        http_admin_api::git_webhook_endpoint,
+        http_admin_api::admin_get_users_endpoint,
+        http_admin_api::admin_post_user_endpoint,
+        http_admin_api::admin_delete_user_endpoint,
+        http_admin_api::admin_get_site_endpoint,
+        http_admin_api::admin_post_site_endpoint,
+        http_admin_api::admin_install_certificate_endpoint,
+        http_admin_api::admin_get_traffic_split_endpoint,
+        http_admin_api::admin_post_traffic_split_endpoint,
+        http_admin_api::admin_disk_cache_endpoint,
+        http_admin_api::admin_purge_disk_cache_endpoint,
+    ),
+    modifiers(&SessionSecurity)
+)]
+pub struct AdminApiDoc;
+
+// Sessions are sent as a Bearer token, or in the session cookie when cookie sessions are enabled
+struct SessionSecurity;
+
+impl Modify for SessionSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("session_token", SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+        components.add_security_scheme("session_cookie", SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("gruxi_session"))));
+    }
+}
+
+static OPENAPI_JSON: OnceLock<String> = OnceLock::new();
+
+// The document never changes while running, so it is only generated once
+pub fn get_openapi_json() -> String {
+    OPENAPI_JSON.get_or_init(|| AdminApiDoc::openapi().to_pretty_json().unwrap_or_else(|_| "{}".to_string())).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin_portal::http_admin_api::AdminApiRoute;
+
+    fn get_documented_routes() -> Vec<(String, String)> {
+        let openapi = AdminApiDoc::openapi();
+        let mut routes = Vec::new();
+        for (path, path_item) in openapi.paths.paths.iter() {
+            let operations = [
+                ("GET", &path_item.get),
+                ("POST", &path_item.post),
+                ("PUT", &path_item.put),
+                ("DELETE", &path_item.delete),
+                ("PATCH", &path_item.patch),
+            ];
+            for (method, operation) in operations {
+                if operation.is_some() {
+                    routes.push((method.to_string(), path.clone()));
+                }
+            }
+        }
+        routes.sort();
+        routes
+    }
+
+    // Every route of the admin API must be documented, and nothing else
+    #[test]
+    fn test_openapi_covers_all_admin_routes() {
+        let mut routes: Vec<(String, String)> = AdminApiRoute::ALL
+            .iter()
+            .map(|route| {
+                let (method, path) = route.get_method_and_path();
+                (method.to_string(), path.to_string())
+            })
+            .collect();
+        routes.sort();
+        assert_eq!(get_documented_routes(), routes);
+    }
+
+    #[test]
+    fn test_admin_route_matching() {
+        for route in AdminApiRoute::ALL {
+            let (method, path) = route.get_method_and_path();
+            let example_path = path.replace("{filename}", "gruxi.log").replace("{site_id}", "site1");
+            assert_eq!(AdminApiRoute::from_request(method, &example_path), Some(route));
+        }
+        assert_eq!(AdminApiRoute::from_request("GET", "/logs/"), None);
+        assert_eq!(AdminApiRoute::from_request("GET", "/logs/a/b.log"), None);
+        assert_eq!(AdminApiRoute::from_request("PUT", "/config"), None);
+        assert_eq!(AdminApiRoute::from_request("GET", "/index.html"), None);
+    }
+
+    #[test]
+    fn test_openapi_json() {
+        let openapi: serde_json::Value = serde_json::from_str(&get_openapi_json()).unwrap();
+        assert!(openapi["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(openapi["components"]["schemas"]["Configuration"].is_object());
+        assert!(openapi["components"]["securitySchemes"]["session_token"].is_object());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::configuration::configuration::Configuration;
+use crate::configuration::load_configuration::fetch_configuration_in_db;
+use crate::configuration::save_configuration::save_configuration;
+use crate::core::admin_user::{MIN_PASSWORD_LENGTH, create_admin_user, validate_username};
+use crate::core::command_line_args::cmd_read_only_configuration;
+use crate::core::database_connection::get_database_connection;
+use serde::Deserialize;
+use std::sync::{Mutex, OnceLock};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// The first admin user and a few basic settings, sent to the setup endpoint on a fresh install
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetupRequest {
+    // The one-time token printed in the server output on startup, so only whoever runs the server can claim it
+    pub setup_token: String,
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub admin_port: Option<u16>,
+    #[serde(default)]
+    pub default_site: Option<SetupDefaultSite>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetupDefaultSite {
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+    // Web root of the static file handler of the default site
+    #[serde(default)]
+    pub web_root: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SetupError {
+    // Setup has been completed, or was never needed, and is locked
+    NotAvailable,
+    InvalidToken,
+    InvalidRequest(String),
+    Internal(String),
+}
+
+// While there are no admin users, the admin portal is in setup mode, where the first admin user can be created with the setup token.
+// Once that is done, setup is locked for good, as there is no way back to having no users
+#[derive(Default)]
+pub struct SetupState {
+    setup_token: Mutex<Option<String>>,
+}
+
+impl SetupState {
+    pub fn new() -> Self {
+        SetupState { setup_token: Mutex::new(None) }
+    }
+
+    // Enters setup mode and returns the new setup token
+    pub fn start_setup(&self) -> String {
+        let token = Uuid::new_v4().simple().to_string();
+        *self.setup_token.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(token.clone());
+        token
+    }
+
+    pub fn is_setup_required(&self) -> bool {
+        self.setup_token.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).is_some()
+    }
+
+    // Creates the first admin user and applies the chosen settings. The lock is held throughout, so concurrent requests cannot both complete setup
+    pub fn complete_setup(&self, setup_request: &SetupRequest) -> Result<(), SetupError> {
+        let mut setup_token = self.setup_token.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let expected_token = setup_token.as_deref().ok_or(SetupError::NotAvailable)?;
+        if !is_token_equal(&setup_request.setup_token, expected_token) {
+            return Err(SetupError::InvalidToken);
+        }
+
+        validate_username(&setup_request.username).map_err(SetupError::InvalidRequest)?;
+        if setup_request.password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(SetupError::InvalidRequest(format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH)));
+        }
+
+        // A read-only configuration is managed elsewhere, so only the admin user is created
+        if !cmd_read_only_configuration() {
+            let mut configuration = fetch_configuration_in_db().map_err(SetupError::Internal)?;
+            apply_setup_settings(&mut configuration, setup_request).map_err(SetupError::InvalidRequest)?;
+            save_configuration(&mut configuration, false).map_err(|errors| SetupError::InvalidRequest(errors.join("; ")))?;
+        }
+
+        let connection = get_database_connection().map_err(SetupError::Internal)?;
+        create_admin_user(&connection, &setup_request.username, &setup_request.password).map_err(SetupError::Internal)?;
+
+        *setup_token = None;
+        Ok(())
+    }
+}
+
+// Applies the settings chosen during setup to the configuration, which is validated when saved
+pub fn apply_setup_settings(configuration: &mut Configuration, setup_request: &SetupRequest) -> Result<(), String> {
+    if let Some(admin_port) = setup_request.admin_port {
+        configuration.core.admin_portal.port = admin_port;
+    }
+
+    if let Some(default_site_settings) = &setup_request.default_site {
+        let default_site = configuration.sites.iter_mut().find(|s| s.is_default).ok_or("No default site found in the configuration")?;
+        if !default_site_settings.hostnames.is_empty() {
+            default_site.hostnames = default_site_settings.hostnames.clone();
+        }
+
+        if let Some(web_root) = &default_site_settings.web_root {
+            let static_processor_ids: Vec<String> = configuration
+                .request_handlers
+                .iter()
+                .filter(|h| h.processor_type == "static" && default_site.request_handlers.contains(&h.id))
+                .map(|h| h.processor_id.clone())
+                .collect();
+            let processor = configuration
+                .static_file_processors
+                .iter_mut()
+                .find(|p| static_processor_ids.contains(&p.id))
+                .ok_or("The default site has no static file handler to set the web root for")?;
+            processor.web_root = web_root.clone();
+        }
+    }
+
+    Ok(())
+}
+
+// Compares in constant time, so the token cannot be guessed from response timings
+fn is_token_equal(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+static SETUP_STATE_SINGLETON: OnceLock<SetupState> = OnceLock::new();
+
+pub fn get_setup_state() -> &'static SetupState {
+    SETUP_STATE_SINGLETON.get_or_init(SetupState::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_setup_request(admin_port: Option<u16>, default_site: Option<SetupDefaultSite>) -> SetupRequest {
+        SetupRequest {
+            setup_token: "token".to_string(),
+            username: "admin".to_string(),
+            password: "password123".to_string(),
+            admin_port,
+            default_site,
+        }
+    }
+
+    #[test]
+    fn test_apply_setup_settings() {
+        let mut configuration = Configuration::get_default();
+        let default_site = SetupDefaultSite {
+            hostnames: vec!["example.com".to_string()],
+            web_root: Some("/var/www/example".to_string()),
+        };
+        apply_setup_settings(&mut configuration, &create_setup_request(Some(9000), Some(default_site))).unwrap();
+
+        assert_eq!(configuration.core.admin_portal.port, 9000);
+        assert_eq!(configuration.sites[0].hostnames, vec!["example.com"]);
+        assert_eq!(configuration.static_file_processors[0].web_root, "/var/www/example");
+
+        // Without settings, the configuration is left as is
+        let mut configuration = Configuration::get_default();
+        apply_setup_settings(&mut configuration, &create_setup_request(None, None)).unwrap();
+        assert_eq!(configuration.core.admin_portal.port, Configuration::get_default().core.admin_portal.port);
+    }
+
+    #[test]
+    fn test_setup_is_locked_without_token() {
+        let setup_state = SetupState::new();
+        assert!(!setup_state.is_setup_required());
+        assert_eq!(setup_state.complete_setup(&create_setup_request(None, None)), Err(SetupError::NotAvailable));
+
+        setup_state.start_setup();
+        assert!(setup_state.is_setup_required());
+        assert_eq!(setup_state.complete_setup(&create_setup_request(None, None)), Err(SetupError::InvalidToken));
+    }
+}
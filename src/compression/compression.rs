@@ -1,3 +1,4 @@
+use crate::http::http_util::add_vary_field;
 use crate::http::request_response::gruxi_body::GruxiBody::Buffered;
 use crate::http::request_response::gruxi_response::GruxiResponse;
 use crate::logging::syslog::debug;
@@ -6,6 +7,11 @@ use http::HeaderValue;
 use hyper::body::Bytes;
 use std::io::Write;
 
+// Brotli settings for responses compressed on the fly. Quality 5 is well below the maximum of 11, but far faster
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
 pub struct Compression {}
 
 impl Compression {
@@ -15,38 +21,104 @@ impl Compression {
 
     pub async fn compress_response(&self, response: &mut GruxiResponse, accepted_encodings: Vec<String>, content_encoding_header: String) {
         // We need to make sure that it is not already compressed
-        if content_encoding_header.to_lowercase() == "gzip" {
+        let content_encoding_header = content_encoding_header.trim().to_lowercase();
+        if !content_encoding_header.is_empty() && content_encoding_header != "identity" {
             return;
         }
 
-        // Check if gzip is accepted by the client
-        if !accepted_encodings.iter().any(|enc| enc.to_lowercase() == "gzip") {
+        // Brotli compresses text better than gzip, so we prefer it when the client accepts both
+        let encoding = if is_encoding_accepted(&accepted_encodings, "br") {
+            "br"
+        } else if is_encoding_accepted(&accepted_encodings, "gzip") {
+            "gzip"
+        } else {
             return;
-        }
+        };
 
-        // Perform gzip compression on the response body
+        // Perform the compression on the response body
         let body_bytes = response.get_body_bytes().await;
-        let mut gzipped_bytes = Vec::new();
-        match Self::compress_content(&body_bytes, &mut gzipped_bytes) {
-            Ok(_) => {}
-            Err(e) => {
-                // If compression fails, we just return without modifying the response
-                debug(format!("Gzip compression failed: {}", e));
-                return;
-            }
+        let mut compressed_bytes = Vec::new();
+        let compress_result = if encoding == "br" {
+            Self::compress_content_brotli(&body_bytes, &mut compressed_bytes)
+        } else {
+            Self::compress_content(&body_bytes, &mut compressed_bytes)
+        };
+        if let Err(e) = compress_result {
+            // If compression fails, we send the response uncompressed
+            debug(format!("Compression with {} failed: {}", encoding, e));
+            response.set_body(Buffered(body_bytes));
+            return;
         }
 
-        response.set_body(Buffered(Bytes::from(gzipped_bytes)));
-        response.headers_mut().insert("Content-Encoding", HeaderValue::from_static("gzip"));
-        response.headers_mut().insert("Vary", HeaderValue::from_static("Accept-Encoding"));
+        response.set_body(Buffered(Bytes::from(compressed_bytes)));
+        response.headers_mut().insert("Content-Encoding", HeaderValue::from_static(encoding));
+        add_vary_field(response, "Accept-Encoding");
     }
 
     /// Compress content using gzip
     pub fn compress_content(content: &[u8], gzip_content: &mut Vec<u8>) -> Result<(), std::io::Error> {
-
         let mut encoder = GzEncoder::new(gzip_content, flate2::Compression::default());
         encoder.write_all(content)?;
         encoder.finish()?;
         Ok(())
     }
+
+    /// Compress content using brotli, at a quality that is fast enough for responses generated per request
+    pub fn compress_content_brotli(content: &[u8], brotli_content: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        let mut encoder = brotli::CompressorWriter::new(brotli_content, BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_WINDOW_SIZE);
+        encoder.write_all(content)?;
+        encoder.flush()?;
+        Ok(())
+    }
+}
+
+// Checks if the client accepts the encoding, ignoring the quality value unless it is zero, as in "gzip;q=0"
+pub fn is_encoding_accepted(accepted_encodings: &[String], encoding: &str) -> bool {
+    accepted_encodings.iter().any(|accepted| {
+        let mut parts = accepted.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let is_rejected = parts.any(|param| param.trim().strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()) == Some(0.0));
+        (name.eq_ignore_ascii_case(encoding) || name == "*") && !is_rejected
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encodings(header: &str) -> Vec<String> {
+        header.split(',').map(|s| s.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn test_is_encoding_accepted() {
+        assert!(is_encoding_accepted(&encodings("gzip, deflate, br"), "br"));
+        assert!(is_encoding_accepted(&encodings("GZIP;q=0.8"), "gzip"));
+        assert!(!is_encoding_accepted(&encodings("gzip;q=0, deflate"), "gzip"));
+        assert!(!is_encoding_accepted(&encodings("gzip"), "br"));
+        assert!(is_encoding_accepted(&encodings("*"), "br"));
+        assert!(!is_encoding_accepted(&[], "gzip"));
+    }
+
+    #[tokio::test]
+    async fn test_compress_response_prefers_brotli() {
+        let body = "{\"requests\": 1}".repeat(200);
+
+        let mut response = GruxiResponse::new_with_bytes(200, body.clone());
+        Compression::new().compress_response(&mut response, encodings("gzip, br"), String::new()).await;
+        assert_eq!(response.get_header("Content-Encoding").unwrap(), "br");
+        let compressed = response.get_body_bytes().await;
+        let mut decompressed = Vec::new();
+        brotli::BrotliDecompress(&mut compressed.as_ref(), &mut decompressed).unwrap();
+        assert_eq!(decompressed, body.as_bytes());
+
+        let mut response = GruxiResponse::new_with_bytes(200, body.clone());
+        Compression::new().compress_response(&mut response, encodings("gzip"), String::new()).await;
+        assert_eq!(response.get_header("Content-Encoding").unwrap(), "gzip");
+
+        // Already encoded responses are left alone
+        let mut response = GruxiResponse::new_with_bytes(200, body.clone());
+        Compression::new().compress_response(&mut response, encodings("br"), "br".to_string()).await;
+        assert!(response.get_header("Content-Encoding").is_none());
+    }
 }
@@ -1,14 +1,64 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use crate::configuration::site::Site;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct AdminPortal {
     pub is_enabled: bool,
+    // Port of the TLS binding the admin portal is served on, on all interfaces
+    #[serde(default = "default_port")]
+    pub port: u16,
     pub domain_name: String,
     pub tls_automatic_enabled: bool,
     pub tls_certificate_path: Option<String>,
     pub tls_key_path: Option<String>,
+    // Cookie based sessions, with HttpOnly session cookies and CSRF tokens for state-changing requests, instead of Bearer tokens only
+    #[serde(default)]
+    pub session_cookie_enabled: bool,
+    // SameSite attribute of the session cookie, "Strict" or "Lax"
+    #[serde(default = "default_session_cookie_same_site")]
+    pub session_cookie_same_site: String,
+    // Sessions not used for this many minutes expire, 0 disables the idle timeout
+    #[serde(default)]
+    pub session_idle_timeout_minutes: u64,
+    // Sessions expire this many hours after login, regardless of activity
+    #[serde(default = "default_session_absolute_timeout_hours")]
+    pub session_absolute_timeout_hours: u64,
+    // Logging in with more sessions than this ends the oldest sessions of the user, 0 allows any number
+    #[serde(default = "default_session_max_per_user")]
+    pub session_max_per_user: u32,
+    // Random bytes in a session token, between MIN_SESSION_TOKEN_BYTES and MAX_SESSION_TOKEN_BYTES
+    #[serde(default = "default_session_token_bytes")]
+    pub session_token_bytes: u32,
+}
+
+pub const SESSION_COOKIE_SAME_SITE_VALUES: [&str; 2] = ["Strict", "Lax"];
+
+pub const DEFAULT_ADMIN_PORTAL_PORT: u16 = 8000;
+
+// 128 bits is the least to keep tokens from being guessed, more than 512 bits adds nothing
+pub const MIN_SESSION_TOKEN_BYTES: u32 = 16;
+pub const MAX_SESSION_TOKEN_BYTES: u32 = 64;
+
+fn default_port() -> u16 {
+    DEFAULT_ADMIN_PORTAL_PORT
+}
+
+fn default_session_cookie_same_site() -> String {
+    "Strict".to_string()
+}
+
+fn default_session_absolute_timeout_hours() -> u64 {
+    24
+}
+
+fn default_session_max_per_user() -> u32 {
+    10
+}
+
+fn default_session_token_bytes() -> u32 {
+    32
 }
 
 impl AdminPortal {
@@ -17,10 +67,17 @@ impl AdminPortal {
 
         AdminPortal {
             is_enabled,
+            port: DEFAULT_ADMIN_PORTAL_PORT,
             domain_name: "".to_string(),
             tls_automatic_enabled: false,
             tls_certificate_path: None,
             tls_key_path: None,
+            session_cookie_enabled: false,
+            session_cookie_same_site: default_session_cookie_same_site(),
+            session_idle_timeout_minutes: 0,
+            session_absolute_timeout_hours: default_session_absolute_timeout_hours(),
+            session_max_per_user: default_session_max_per_user(),
+            session_token_bytes: default_session_token_bytes(),
         }
     }
 
@@ -37,6 +94,14 @@ impl AdminPortal {
         if let Some(key_path) = &mut self.tls_key_path {
             *key_path = key_path.trim().to_string();
         }
+
+        // Accept any casing of the SameSite value, like "lax"
+        let same_site = self.session_cookie_same_site.trim().to_string();
+        if let Some(value) = SESSION_COOKIE_SAME_SITE_VALUES.iter().find(|v| v.eq_ignore_ascii_case(&same_site)) {
+            self.session_cookie_same_site = value.to_string();
+        } else {
+            self.session_cookie_same_site = same_site;
+        }
     }
 
     pub fn validate(&self) -> Result<(), Vec<String>> {
@@ -68,6 +133,23 @@ impl AdminPortal {
             }
         }
 
+        if self.port == 0 {
+            errors.push("Admin portal port must be between 1 and 65535".to_string());
+        }
+
+        if !SESSION_COOKIE_SAME_SITE_VALUES.contains(&self.session_cookie_same_site.as_str()) {
+            errors.push(format!("Admin portal session cookie SameSite must be one of: {}", SESSION_COOKIE_SAME_SITE_VALUES.join(", ")));
+        }
+        if self.session_absolute_timeout_hours == 0 {
+            errors.push("Admin portal session absolute timeout must be at least 1 hour".to_string());
+        }
+        if self.session_token_bytes < MIN_SESSION_TOKEN_BYTES || self.session_token_bytes > MAX_SESSION_TOKEN_BYTES {
+            errors.push(format!(
+                "Admin portal session token length must be between {} and {} bytes",
+                MIN_SESSION_TOKEN_BYTES, MAX_SESSION_TOKEN_BYTES
+            ));
+        }
+
         if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 
@@ -1,7 +1,10 @@
+use crate::http::status_page::parse_ip_range;
+use crate::network::port_manager::PortProtocol;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 #[allow(unused)]
 pub struct Binding {
     pub id: String,
@@ -9,6 +12,137 @@ pub struct Binding {
     pub port: u16,
     pub is_admin: bool,
     pub is_tls: bool,
+    // HTTP/1.1 connection handling. Idle connections are closed after the timeout, 0 max requests means unlimited
+    #[serde(default = "default_keep_alive_timeout_seconds")]
+    pub keep_alive_timeout_seconds: u64,
+    #[serde(default = "default_max_requests_per_connection")]
+    pub max_requests_per_connection: u64,
+    #[serde(default = "default_pipelining_enabled")]
+    pub pipelining_enabled: bool,
+    // HTTP/2 stream concurrency, flow control and memory limits for connections on the binding
+    #[serde(default)]
+    pub http2: Http2Limits,
+    // The HTTP versions offered, one of BINDING_PROTOCOLS, as some middleboxes in corporate networks break on HTTP/2
+    #[serde(default = "default_protocols")]
+    pub protocols: String,
+    // Without TLS, HTTP/2 is only spoken by clients that know it beforehand (h2c with prior knowledge), which can be turned off
+    #[serde(default = "default_true")]
+    pub h2c_enabled: bool,
+    // Without TLS, whether HTTP/1 requests with an absolute URI, such as "GET http://example.com/ HTTP/1.1", are accepted.
+    // Only clients talking to a proxy send those
+    #[serde(default = "default_true")]
+    pub absolute_form_enabled: bool,
+    // Answer STATUS_PAGE_PATH with connection and request counts like the nginx stub_status page, for existing monitoring scripts.
+    // Only clients with an address in the allowed IPs or ranges, such as "10.0.0.0/8", get it. See http::status_page
+    #[serde(default)]
+    pub status_page_enabled: bool,
+    #[serde(default = "default_status_page_allowed_ips")]
+    pub status_page_allowed_ips: Vec<String>,
+}
+
+// HTTP/2 with HTTP/1.1 as fallback, HTTP/1.1 only or HTTP/2 only. HTTP/3 would be added here
+pub const BINDING_PROTOCOLS: [&str; 3] = ["h2+http1", "http1", "h2"];
+
+// Limits sent to HTTP/2 clients in SETTINGS, or enforced on them. Clients that reset or cause errors on too many streams, as in rapid
+// reset attacks, get a GOAWAY with ENHANCE_YOUR_CALM and the connection is closed
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Http2Limits {
+    // SETTINGS_MAX_CONCURRENT_STREAMS
+    pub max_concurrent_streams: u32,
+    // SETTINGS_INITIAL_WINDOW_SIZE, how much request body a client can send on a stream before we have read it
+    pub initial_stream_window_size: u32,
+    // How much request body a client can send on the connection as a whole before we have read it
+    pub initial_connection_window_size: u32,
+    // SETTINGS_MAX_HEADER_LIST_SIZE, the decoded size of the headers of a request
+    pub max_header_list_size: u32,
+    // Memory a connection may use for buffered request and response data, in bytes. The connection window and header list must
+    // fit within it, and the rest is shared by the streams for buffering response data
+    pub max_connection_memory: u64,
+    // Streams opened and reset by the client before we have handled them, and streams we had to reset because of client errors
+    pub max_pending_reset_streams: u32,
+    pub max_local_error_reset_streams: u32,
+}
+
+pub const HTTP2_MIN_WINDOW_SIZE: u32 = 65_535;
+pub const HTTP2_MAX_WINDOW_SIZE: u32 = 2_147_483_647;
+const HTTP2_MIN_SEND_BUFFER_SIZE: u64 = 16 * 1024;
+// The HPACK tables the HTTP/2 implementation keeps per connection, for the headers it decodes and those it encodes, are never larger
+const HTTP2_HEADER_TABLE_SIZE: u32 = 4096;
+// SETTINGS_MAX_FRAME_SIZE, the smallest the protocol allows, so a frame is never buffered whole beyond that
+pub const HTTP2_MAX_FRAME_SIZE: u32 = 16_384;
+
+impl Default for Http2Limits {
+    fn default() -> Self {
+        Http2Limits {
+            max_concurrent_streams: 100,
+            initial_stream_window_size: 1024 * 1024,
+            initial_connection_window_size: 1024 * 1024,
+            max_header_list_size: 16 * 1024,
+            max_connection_memory: 8 * 1024 * 1024,
+            max_pending_reset_streams: 20,
+            max_local_error_reset_streams: 1024,
+        }
+    }
+}
+
+impl Http2Limits {
+    // The part of the memory budget left after the connection window, headers and HPACK tables, shared evenly by the streams
+    pub fn get_max_send_buffer_size(&self) -> usize {
+        let reserved = self.initial_connection_window_size as u64 + self.max_header_list_size as u64 + 2 * HTTP2_HEADER_TABLE_SIZE as u64;
+        let per_stream = self.max_connection_memory.saturating_sub(reserved) / self.max_concurrent_streams.max(1) as u64;
+        per_stream.max(HTTP2_MIN_SEND_BUFFER_SIZE) as usize
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.max_concurrent_streams == 0 || self.max_concurrent_streams > 10_000 {
+            errors.push("HTTP/2 max concurrent streams must be between 1 and 10000".to_string());
+        }
+        for (name, size) in [("stream", self.initial_stream_window_size), ("connection", self.initial_connection_window_size)] {
+            if !(HTTP2_MIN_WINDOW_SIZE..=HTTP2_MAX_WINDOW_SIZE).contains(&size) {
+                errors.push(format!("HTTP/2 initial {} window size must be between {} and {}", name, HTTP2_MIN_WINDOW_SIZE, HTTP2_MAX_WINDOW_SIZE));
+            }
+        }
+        if self.max_header_list_size < 1024 {
+            errors.push("HTTP/2 max header list size must be at least 1024 bytes".to_string());
+        }
+        if (self.initial_connection_window_size as u64 + self.max_header_list_size as u64) > self.max_connection_memory {
+            errors.push("HTTP/2 max connection memory must be at least the initial connection window size plus the max header list size".to_string());
+        }
+        if self.max_pending_reset_streams == 0 || self.max_local_error_reset_streams == 0 {
+            errors.push("HTTP/2 max reset streams cannot be 0".to_string());
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+pub const DEFAULT_KEEP_ALIVE_TIMEOUT_SECONDS: u64 = 60;
+pub const DEFAULT_MAX_REQUESTS_PER_CONNECTION: u64 = 1000;
+
+fn default_keep_alive_timeout_seconds() -> u64 {
+    DEFAULT_KEEP_ALIVE_TIMEOUT_SECONDS
+}
+
+fn default_max_requests_per_connection() -> u64 {
+    DEFAULT_MAX_REQUESTS_PER_CONNECTION
+}
+
+fn default_pipelining_enabled() -> bool {
+    true
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub fn default_protocols() -> String {
+    BINDING_PROTOCOLS[0].to_string()
+}
+
+pub fn default_status_page_allowed_ips() -> Vec<String> {
+    vec!["127.0.0.1".to_string(), "::1".to_string()]
 }
 
 impl Binding {
@@ -19,11 +153,47 @@ impl Binding {
             port: 80,
             is_admin: false,
             is_tls: false,
+            keep_alive_timeout_seconds: DEFAULT_KEEP_ALIVE_TIMEOUT_SECONDS,
+            max_requests_per_connection: DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+            pipelining_enabled: true,
+            http2: Http2Limits::default(),
+            protocols: default_protocols(),
+            h2c_enabled: true,
+            absolute_form_enabled: true,
+            status_page_enabled: false,
+            status_page_allowed_ips: default_status_page_allowed_ips(),
         }
     }
 
     pub fn sanitize(&mut self) {
         self.ip = self.ip.trim().to_string();
+        self.protocols = self.protocols.trim().to_lowercase();
+        self.status_page_allowed_ips = self.status_page_allowed_ips.iter().map(|ip| ip.trim().to_string()).filter(|ip| !ip.is_empty()).collect();
+    }
+
+    pub fn offers_http1(&self) -> bool {
+        self.protocols != "h2"
+    }
+
+    pub fn offers_http2(&self) -> bool {
+        self.protocols != "http1" && (self.is_tls || self.h2c_enabled)
+    }
+
+    // The protocols offered in the TLS handshake, HTTP/2 first as it is preferred
+    pub fn get_alpn_protocols(&self) -> Vec<Vec<u8>> {
+        let mut alpn_protocols = Vec::new();
+        if self.offers_http2() {
+            alpn_protocols.push(b"h2".to_vec());
+        }
+        if self.offers_http1() {
+            alpn_protocols.push(b"http/1.1".to_vec());
+        }
+        alpn_protocols
+    }
+
+    // The transports the binding listens on. HTTP/1.1 and HTTP/2 are served over TCP, HTTP/3 would add a UDP listener on the same port
+    pub fn get_listen_protocols(&self) -> Vec<PortProtocol> {
+        vec![PortProtocol::Tcp]
     }
 
     pub fn validate(&self) -> Result<(), Vec<String>> {
@@ -41,6 +211,27 @@ impl Binding {
             errors.push("Port cannot be 0".to_string());
         }
 
+        // Validate keep-alive timeout
+        if self.keep_alive_timeout_seconds == 0 || self.keep_alive_timeout_seconds > 3600 {
+            errors.push("Keep-alive timeout must be between 1 and 3600 seconds".to_string());
+        }
+
+        if let Err(http2_errors) = self.http2.validate() {
+            errors.extend(http2_errors);
+        }
+
+        if !BINDING_PROTOCOLS.contains(&self.protocols.as_str()) {
+            errors.push(format!("Protocols must be one of {}: {}", BINDING_PROTOCOLS.join(", "), self.protocols));
+        } else if !self.offers_http1() && !self.offers_http2() {
+            errors.push("A binding offering HTTP/2 only needs h2c enabled when it does not use TLS".to_string());
+        }
+
+        for allowed_ip in &self.status_page_allowed_ips {
+            if parse_ip_range(allowed_ip).is_none() {
+                errors.push(format!("Invalid IP address or range allowed for the status page: {}", allowed_ip));
+            }
+        }
+
         // Validate common TLS port usage
         if self.is_tls && self.port == 80 {
             errors.push("Port 80 is typically used for HTTP, not HTTPS. Consider using port 443 for TLS".to_string());
@@ -60,3 +251,53 @@ impl Binding {
         if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http2_limits() {
+        let mut limits = Http2Limits::default();
+        assert!(limits.validate().is_ok());
+        // 8 MiB minus the 1 MiB window, 16 KiB headers and two 4 KiB HPACK tables, shared by 100 streams
+        assert_eq!(limits.get_max_send_buffer_size(), (8 * 1024 * 1024 - 1024 * 1024 - 16 * 1024 - 2 * 4096) / 100);
+
+        limits.max_connection_memory = 1024 * 1024;
+        assert!(limits.validate().is_err());
+        limits.initial_connection_window_size = 65_535;
+        assert!(limits.validate().is_ok());
+
+        // Too many streams for the budget still get a usable buffer
+        limits.max_concurrent_streams = 10_000;
+        assert_eq!(limits.get_max_send_buffer_size(), 16 * 1024);
+
+        limits.initial_stream_window_size = 1024;
+        assert!(limits.validate().is_err());
+        limits.initial_stream_window_size = 65_535;
+        assert!(limits.validate().is_ok());
+    }
+
+    #[test]
+    fn test_protocols() {
+        let mut binding = Binding::new();
+        assert_eq!(binding.get_alpn_protocols(), vec![b"h2".to_vec(), b"http/1.1".to_vec()]);
+
+        binding.protocols = "http1".to_string();
+        assert!(!binding.offers_http2());
+        assert_eq!(binding.get_alpn_protocols(), vec![b"http/1.1".to_vec()]);
+
+        // Without TLS, HTTP/2 needs h2c
+        binding.protocols = "h2".to_string();
+        binding.h2c_enabled = false;
+        assert!(!binding.offers_http1() && !binding.offers_http2());
+        assert!(binding.validate().is_err());
+        binding.is_tls = true;
+        binding.port = 8443;
+        assert_eq!(binding.get_alpn_protocols(), vec![b"h2".to_vec()]);
+        assert!(binding.validate().is_ok());
+
+        binding.protocols = "h3".to_string();
+        assert!(binding.validate().is_err());
+    }
+}
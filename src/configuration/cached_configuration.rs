@@ -1,10 +1,10 @@
+use crate::logging::syslog::trace;
 use crate::{
-    configuration::{configuration::Configuration},
-    core::triggers::get_trigger_handler,
+    configuration::{configuration::Configuration, configuration_changes::ConfigurationChanges},
+    core::triggers::{GruxiEvent, get_trigger_handler},
 };
-use crate::logging::syslog::trace;
-use tokio::sync::RwLock;
 use std::sync::{Arc, OnceLock};
+use tokio::sync::{RwLock, broadcast};
 
 pub struct CachedConfiguration {
     pub configuration: Arc<RwLock<Configuration>>,
@@ -22,42 +22,33 @@ impl CachedConfiguration {
         self.configuration.read().await
     }
 
-    pub async fn check_if_cached_configuration_should_be_refreshed() {
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    // Subscribed before the task is spawned, so a refresh right after startup is not missed
+    pub async fn check_if_cached_configuration_should_be_refreshed(mut events: broadcast::Receiver<GruxiEvent>) {
         trace("Starting thread to monitor for configuration refresh signal for the cached configuration");
-
         let triggers = get_trigger_handler();
-        let refresh_trigger_result = triggers.get_token("refresh_cached_configuration").await;
-        let mut refresh_trigger_token = match refresh_trigger_result {
-            Some(trigger) => trigger,
-            None => {
-                panic!("Failed to get refresh_cached_configuration trigger - Configuration reload task aborted - Please report a bug");
-            }
-        };
 
         loop {
-            refresh_trigger_token.cancelled().await;
+            match events.recv().await {
+                Ok(GruxiEvent::RefreshCachedConfiguration) => {}
+                Ok(_) => continue,
+                // Events were skipped, which may have been a refresh
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
             trace("Refresh cached configuration trigger received, reloading configuration");
 
             {
                 let new_configuration = super::load_configuration::init();
                 let cached_configuration = get_cached_configuration();
                 let mut config_write_guard = cached_configuration.configuration.write().await;
+                let changes = ConfigurationChanges::between(&config_write_guard, &new_configuration);
                 *config_write_guard = new_configuration;
+                drop(config_write_guard);
 
-                // Trigger configuration_changed trigger
-                triggers.run_trigger("configuration_changed").await;
+                // Tell what changed, so subscribers can skip changes that do not concern them
+                triggers.publish(GruxiEvent::ConfigurationChanged(Some(changes))).await;
             }
 
-            // Get new token for next time
-            let refresh_trigger_result = triggers.get_token("refresh_cached_configuration").await;
-            refresh_trigger_token = match refresh_trigger_result {
-                Some(trigger) => trigger,
-                None => {
-                    panic!("Failed to get refresh_cached_configuration trigger - Configuration reload task aborted - Please report a bug");
-                }
-            };
-
             trace("Cached configuration successfully refreshed");
         }
     }
@@ -68,7 +59,7 @@ static CACHED_CONFIGURATION_SINGLETON: OnceLock<CachedConfiguration> = OnceLock:
 pub fn get_cached_configuration() -> &'static CachedConfiguration {
     CACHED_CONFIGURATION_SINGLETON.get_or_init(|| {
         let cached_config = CachedConfiguration::new();
-        tokio::spawn(CachedConfiguration::check_if_cached_configuration_should_be_refreshed());
+        tokio::spawn(CachedConfiguration::check_if_cached_configuration_should_be_refreshed(get_trigger_handler().subscribe()));
         cached_config
     })
 }
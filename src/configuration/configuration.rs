@@ -1,21 +1,35 @@
 use crate::configuration::admin_portal::AdminPortal;
+use crate::configuration::configuration_include::is_included_file;
 use crate::configuration::core::Core;
 use crate::configuration::file_cache::FileCache;
 use crate::configuration::gzip::Gzip;
 use crate::configuration::request_handler::RequestHandler;
-use crate::configuration::server_settings::ServerSettings;
+use crate::configuration::server_settings::{
+    ServerSettings, SmtpSettings, default_dns_cache_ttl_seconds, default_http2_flood_ban_seconds, default_http2_max_continuation_frames, default_http2_max_pings_per_second,
+    default_http2_max_resets_per_second, default_http2_max_settings_per_second, default_log_max_records_per_second, default_managed_port_range_end, default_managed_port_range_start,
+    default_max_open_file_handles, default_outbound_connect_timeout_seconds, default_outbound_read_timeout_seconds, default_outbound_write_timeout_seconds, default_panic_storm_maintenance_seconds,
+    default_remote_syslog_level, default_request_validation_level, default_server_header, default_upstream_pool_idle_timeout_seconds, default_upstream_pool_max_idle_per_host,
+    default_windows_event_log_level,
+};
 use crate::configuration::site::Site;
 use crate::configuration::tls_settings::TlsSettings;
-use crate::configuration::{binding::Binding, binding_site_relation::BindingSiteRelationship};
+use crate::configuration::upstream::Upstream;
+use crate::configuration::{
+    binding::{Binding, DEFAULT_KEEP_ALIVE_TIMEOUT_SECONDS, DEFAULT_MAX_REQUESTS_PER_CONNECTION, Http2Limits, default_protocols, default_status_page_allowed_ips},
+    binding_site_relation::BindingSiteRelationship,
+};
 use crate::external_connections::managed_system::php_cgi::PhpCgi;
 use crate::http::request_handlers::processor_trait::ProcessorTrait;
 use crate::http::request_handlers::processors::php_processor::PHPProcessor;
 use crate::http::request_handlers::processors::proxy_processor::ProxyProcessor;
 use crate::http::request_handlers::processors::static_files_processor::StaticFileProcessor;
+use crate::network::port_manager::{PortProtocol, get_listen_conflicts};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Configuration {
     pub version: i32,
     pub bindings: Vec<Binding>,
@@ -27,11 +41,14 @@ pub struct Configuration {
     pub static_file_processors: Vec<StaticFileProcessor>,
     pub php_processors: Vec<PHPProcessor>,
     pub proxy_processors: Vec<ProxyProcessor>,
+    // Named upstream pools, shared by the proxy processors of any site
+    #[serde(default)]
+    pub upstreams: Vec<Upstream>,
     // External systems, such as PHP-CGI instances, FastCGI handlers, etc.
     pub php_cgi_handlers: Vec<PhpCgi>,
 }
 
-pub static CURRENT_CONFIGURATION_VERSION: i32 = 4;
+pub static CURRENT_CONFIGURATION_VERSION: i32 = 49;
 
 impl Configuration {
     pub fn new() -> Self {
@@ -83,7 +100,42 @@ impl Configuration {
                         ".log".to_string(),
                         ".key".to_string(),
                         ".pem".to_string(),
-                    ]
+                    ],
+                    include: vec![],
+                    request_validation_level: default_request_validation_level(),
+                    server_header: default_server_header(),
+                    max_open_file_handles: default_max_open_file_handles(),
+                    http2_max_resets_per_second: default_http2_max_resets_per_second(),
+                    http2_max_settings_per_second: default_http2_max_settings_per_second(),
+                    http2_max_pings_per_second: default_http2_max_pings_per_second(),
+                    http2_max_continuation_frames: default_http2_max_continuation_frames(),
+                    http2_flood_ban_seconds: default_http2_flood_ban_seconds(),
+                    trusted_proxy_ips: vec![],
+                    managed_port_range_start: default_managed_port_range_start(),
+                    managed_port_range_end: default_managed_port_range_end(),
+                    managed_port_exclusions: vec![],
+                    dns_cache_ttl_seconds: default_dns_cache_ttl_seconds(),
+                    outbound_connect_timeout_seconds: default_outbound_connect_timeout_seconds(),
+                    outbound_read_timeout_seconds: default_outbound_read_timeout_seconds(),
+                    outbound_write_timeout_seconds: default_outbound_write_timeout_seconds(),
+                    upstream_pool_max_idle_per_host: default_upstream_pool_max_idle_per_host(),
+                    upstream_pool_idle_timeout_seconds: default_upstream_pool_idle_timeout_seconds(),
+                    panic_storm_max_panics_per_minute: 0,
+                    panic_storm_maintenance_seconds: default_panic_storm_maintenance_seconds(),
+                    memory_budget_bytes: 0,
+                    disk_cache_max_size_bytes: 0,
+                    disk_cache_max_unused_hours: 0,
+                    event_webhooks: Vec::new(),
+                    remote_syslog_address: String::new(),
+                    remote_syslog_level: default_remote_syslog_level(),
+                    windows_event_log_level: default_windows_event_log_level(),
+                    log_level: String::new(),
+                    log_module_levels: BTreeMap::new(),
+                    log_sample_rate: 0,
+                    log_max_records_per_second: default_log_max_records_per_second(),
+                    smtp: SmtpSettings::default(),
+                    health_check_path: String::new(),
+                    deregistration_delay_seconds: 0,
                 },
                 admin_portal: AdminPortal::new(),
                 tls_settings: TlsSettings::new(),
@@ -92,6 +144,7 @@ impl Configuration {
             static_file_processors: vec![],
             php_processors: vec![],
             proxy_processors: vec![],
+            upstreams: vec![],
             php_cgi_handlers: vec![],
         }
     }
@@ -131,6 +184,11 @@ impl Configuration {
             processor.sanitize();
         }
 
+        // Sanitize upstream pools
+        for upstream in &mut self.upstreams {
+            upstream.sanitize();
+        }
+
         // Sanitize external systems
         for php_cgi in &mut self.php_cgi_handlers {
             php_cgi.sanitize();
@@ -155,15 +213,31 @@ impl Configuration {
             }
         }
 
+        // Sites kept in files must be in a file that is included, or they would be lost on the next load
+        for site in self.sites.iter().filter(|s| !s.config_file.is_empty()) {
+            if !is_included_file(&site.config_file, &self.core.server_settings.include) {
+                errors.push(format!("Site '{}': configuration file '{}' does not match any of the include patterns", site.id, site.config_file));
+            }
+        }
+
         // Validate bindings
 
-        // First check that none of the bindings have duplicate IP/port combinations
-        let mut binding_combinations = std::collections::HashSet::new();
-        for binding in &self.bindings {
-            let combo = format!("{}:{}", binding.ip, binding.port);
-            if !binding_combinations.insert(combo) {
-                errors.push(format!("Duplicate binding for IP/Port combination: {}:{}", binding.ip, binding.port));
-            }
+        // First check that none of the bindings listen on the same port and protocol, on the same or all IP addresses
+        let listeners: Vec<(PortProtocol, std::net::IpAddr, u16)> = self
+            .bindings
+            .iter()
+            .filter_map(|binding| binding.ip.parse::<std::net::IpAddr>().ok().map(|ip| (binding, ip)))
+            .flat_map(|(binding, ip)| binding.get_listen_protocols().into_iter().map(move |protocol| (protocol, ip, binding.port)))
+            .collect();
+        errors.extend(get_listen_conflicts(&listeners));
+        // The admin portal binding is added when the configuration is loaded, on all interfaces, so no other binding may use its port
+        if self.core.admin_portal.is_enabled
+            && let Some(binding) = self
+                .bindings
+                .iter()
+                .find(|b| !b.is_admin && b.port == self.core.admin_portal.port && b.get_listen_protocols().contains(&PortProtocol::Tcp))
+        {
+            errors.push(format!("Binding {}:{} uses TCP port {} of the admin portal", binding.ip, binding.port, binding.port));
         }
         // Check the individual bindings
         for (binding_idx, binding) in self.bindings.iter().enumerate() {
@@ -221,6 +295,24 @@ impl Configuration {
             }
         }
 
+        // Validate upstream pools, which need unique names and must exist where they are used
+        let mut upstream_names = std::collections::HashSet::new();
+        for upstream in &self.upstreams {
+            if let Err(upstream_errors) = upstream.validate() {
+                for error in upstream_errors {
+                    errors.push(format!("Upstream '{}': {}", upstream.name, error));
+                }
+            }
+            if !upstream_names.insert(upstream.name.to_lowercase()) {
+                errors.push(format!("Duplicate upstream name: '{}'", upstream.name));
+            }
+        }
+        for processor in self.proxy_processors.iter().filter(|p| !p.upstream_id.is_empty()) {
+            if !self.upstreams.iter().any(|u| u.id == processor.upstream_id) {
+                errors.push(format!("Proxy Processor {}: Upstream '{}' does not exist", processor.id, processor.upstream_id));
+            }
+        }
+
         // Validate external systems
         for (_, php_cgi) in self.php_cgi_handlers.iter().enumerate() {
             if let Err(php_cgi_errors) = php_cgi.validate() {
@@ -248,7 +340,16 @@ impl Configuration {
             ip: "0.0.0.0".to_string(),
             port: 80,
             is_admin: false,
-            is_tls: false
+            is_tls: false,
+            keep_alive_timeout_seconds: DEFAULT_KEEP_ALIVE_TIMEOUT_SECONDS,
+            max_requests_per_connection: DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+            pipelining_enabled: true,
+            http2: Http2Limits::default(),
+            protocols: default_protocols(),
+            h2c_enabled: true,
+            absolute_form_enabled: true,
+            status_page_enabled: false,
+            status_page_allowed_ips: default_status_page_allowed_ips(),
         };
 
         let default_binding_tls = Binding {
@@ -256,7 +357,16 @@ impl Configuration {
             ip: "0.0.0.0".to_string(),
             port: 443,
             is_admin: false,
-            is_tls: true
+            is_tls: true,
+            keep_alive_timeout_seconds: DEFAULT_KEEP_ALIVE_TIMEOUT_SECONDS,
+            max_requests_per_connection: DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+            pipelining_enabled: true,
+            http2: Http2Limits::default(),
+            protocols: default_protocols(),
+            h2c_enabled: true,
+            absolute_form_enabled: true,
+            status_page_enabled: false,
+            status_page_allowed_ips: default_status_page_allowed_ips(),
         };
 
         // Static file processor for first site
@@ -270,6 +380,7 @@ impl Configuration {
             processor_type: "static".to_string(),
             processor_id: request1_static_processor.id.clone(),
             url_match: vec!["*".to_string()],
+            allowed_methods: Vec::new(),
         };
 
         // Sites
@@ -0,0 +1,429 @@
+use crate::configuration::configuration::Configuration;
+use crate::configuration::site::Site;
+use crate::configuration::upstream_tls::UpstreamTlsSettings;
+use crate::tls::upstream_tls::get_upstream_tls_config;
+use rustls_pki_types::ServerName;
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckSeverity {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub category: String, // configuration, bindings, certificates, web_roots, handlers or upstream_tls
+    pub subject: String,  // What was checked, such as a binding address or a processor id
+    pub severity: CheckSeverity,
+    pub message: String,
+}
+
+// The outcome of checking a configuration before starting the server with it, as printed by --check-config
+#[derive(Debug, Default, Serialize)]
+pub struct ConfigurationCheckReport {
+    pub errors: usize,
+    pub warnings: usize,
+    pub results: Vec<CheckResult>,
+}
+
+impl ConfigurationCheckReport {
+    fn add(&mut self, category: &str, subject: &str, severity: CheckSeverity, message: String) {
+        match severity {
+            CheckSeverity::Error => self.errors += 1,
+            CheckSeverity::Warning => self.warnings += 1,
+            CheckSeverity::Ok => {}
+        }
+        self.results.push(CheckResult {
+            category: category.to_string(),
+            subject: subject.to_string(),
+            severity,
+            message,
+        });
+    }
+
+    fn ok(&mut self, category: &str, subject: &str, message: impl Into<String>) {
+        self.add(category, subject, CheckSeverity::Ok, message.into());
+    }
+
+    fn warning(&mut self, category: &str, subject: &str, message: impl Into<String>) {
+        self.add(category, subject, CheckSeverity::Warning, message.into());
+    }
+
+    fn error(&mut self, category: &str, subject: &str, message: impl Into<String>) {
+        self.add(category, subject, CheckSeverity::Error, message.into());
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("Configuration check\n");
+        let mut current_category = "";
+        for result in &self.results {
+            if result.category != current_category {
+                current_category = &result.category;
+                text.push_str(&format!("\n{}:\n", current_category));
+            }
+            let severity = match result.severity {
+                CheckSeverity::Ok => "OK",
+                CheckSeverity::Warning => "WARNING",
+                CheckSeverity::Error => "ERROR",
+            };
+            text.push_str(&format!("  [{}] {}: {}\n", severity, result.subject, result.message));
+        }
+        text.push_str(&format!("\nResult: {} error(s), {} warning(s)\n", self.errors, self.warnings));
+        text
+    }
+}
+
+// Checks that a configuration can be started, beyond what Configuration::validate() covers: that the bindings can be listened on,
+// certificates can be read, web roots exist and handler executables are there. Nothing is started or written
+pub fn check_configuration(configuration: &Configuration) -> ConfigurationCheckReport {
+    let mut report = ConfigurationCheckReport::default();
+
+    match configuration.validate() {
+        Ok(_) => report.ok("configuration", "validation", "Configuration is valid"),
+        Err(errors) => {
+            for error in errors {
+                report.error("configuration", "validation", error);
+            }
+        }
+    }
+
+    check_bindings(configuration, &mut report);
+    check_certificates(configuration, &mut report);
+    check_web_roots(configuration, &mut report);
+    check_handlers(configuration, &mut report);
+    check_upstream_tls(configuration, &mut report);
+
+    report
+}
+
+fn check_bindings(configuration: &Configuration, report: &mut ConfigurationCheckReport) {
+    let mut listen_addresses: Vec<(String, bool)> = configuration
+        .bindings
+        .iter()
+        .map(|binding| {
+            let has_sites = configuration.binding_sites.iter().any(|bs| bs.binding_id == binding.id);
+            (format!("{}:{}", binding.ip, binding.port), has_sites || binding.is_admin)
+        })
+        .collect();
+
+    // The admin portal binding is added when starting, so it is not part of the stored configuration
+    if configuration.core.admin_portal.is_enabled {
+        listen_addresses.push((format!("0.0.0.0:{}", configuration.core.admin_portal.port), true));
+    }
+
+    for (address, has_sites) in listen_addresses {
+        let socket_address = match address.rsplit_once(':').and_then(|(ip, port)| Some((ip.parse::<IpAddr>().ok()?, port.parse::<u16>().ok()?))) {
+            Some((ip, port)) => SocketAddr::new(ip, port),
+            None => {
+                report.error("bindings", &address, "Invalid IP address");
+                continue;
+            }
+        };
+
+        if !has_sites {
+            report.warning("bindings", &address, "No sites are attached to the binding");
+        }
+
+        // A trial bind, released again right away. It can fail because another process (or a running Gruxi) has the port, or for missing privileges
+        match TcpListener::bind(socket_address) {
+            Ok(_) => report.ok("bindings", &address, "Address can be bound"),
+            Err(e) => report.warning("bindings", &address, format!("Address cannot be bound right now: {}", e)),
+        }
+    }
+}
+
+fn check_certificates(configuration: &Configuration, report: &mut ConfigurationCheckReport) {
+    for site in get_tls_sites(configuration) {
+        let subject = get_site_subject(site);
+
+        if site.tls_automatic_enabled {
+            report.ok("certificates", &subject, "Certificate is obtained automatically with ACME");
+            continue;
+        }
+
+        let certificate = read_pem(&site.tls_cert_path, &site.tls_cert_content);
+        let key = read_pem(&site.tls_key_path, &site.tls_key_content);
+        let (certificate, key) = match (certificate, key) {
+            (Ok(Some(certificate)), Ok(Some(key))) => (certificate, key),
+            (Ok(None), Ok(None)) => {
+                report.warning("certificates", &subject, "No certificate configured, a self-signed certificate will be generated");
+                continue;
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                report.error("certificates", &subject, e);
+                continue;
+            }
+            _ => {
+                report.error("certificates", &subject, "Both a certificate and a private key must be configured");
+                continue;
+            }
+        };
+
+        match parse_certificate_and_key(&certificate, &key) {
+            Ok(count) => report.ok("certificates", &subject, format!("Certificate chain with {} certificate(s) and private key can be loaded", count)),
+            Err(e) => {
+                report.error("certificates", &subject, e);
+                continue;
+            }
+        }
+
+        if let Ok(uncovered_hostnames) = get_uncovered_hostnames(&certificate, &site.hostnames)
+            && !uncovered_hostnames.is_empty()
+        {
+            report.warning("certificates", &subject, format!("Certificate does not cover hostname(s) {}", uncovered_hostnames.join(", ")));
+        }
+    }
+}
+
+// Warnings for the HTTPS sites with hostnames the SAN list of their certificate does not cover, which browsers would reject,
+// returned when the configuration is saved. Sites with automatic TLS or a generated certificate get one for their hostnames
+pub fn get_certificate_hostname_warnings(configuration: &Configuration) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for site in get_tls_sites(configuration).filter(|site| !site.tls_automatic_enabled) {
+        let certificate = match read_pem(&site.tls_cert_path, &site.tls_cert_content) {
+            Ok(Some(certificate)) => certificate,
+            _ => continue,
+        };
+        match get_uncovered_hostnames(&certificate, &site.hostnames) {
+            Ok(uncovered_hostnames) if uncovered_hostnames.is_empty() => {}
+            Ok(uncovered_hostnames) => {
+                warnings.push(format!("The certificate of {} does not cover hostname(s) {}", get_site_subject(site), uncovered_hostnames.join(", ")));
+            }
+            Err(e) => warnings.push(format!("The certificate of {} could not be checked: {}", get_site_subject(site), e)),
+        }
+    }
+    warnings
+}
+
+// The enabled sites on a TLS binding
+fn get_tls_sites(configuration: &Configuration) -> impl Iterator<Item = &Site> {
+    let tls_site_ids: Vec<&String> = configuration
+        .bindings
+        .iter()
+        .filter(|binding| binding.is_tls)
+        .flat_map(|binding| configuration.binding_sites.iter().filter(move |bs| bs.binding_id == binding.id).map(|bs| &bs.site_id))
+        .collect();
+    configuration.sites.iter().filter(move |site| site.is_enabled && tls_site_ids.contains(&&site.id))
+}
+
+// The hostnames of a site that are not among the subject alternative names of the leaf certificate, matching wildcard names
+// the way browsers do. The "*" hostname matches anything, so it is left out
+fn get_uncovered_hostnames(certificate: &[u8], hostnames: &[String]) -> Result<Vec<String>, String> {
+    let leaf_certificate = rustls_pemfile::certs(&mut std::io::Cursor::new(certificate))
+        .next()
+        .ok_or("No certificates found in the certificate PEM")?
+        .map_err(|e| format!("Failed to parse certificate: {}", e))?;
+    let end_entity_certificate = webpki::EndEntityCert::try_from(&leaf_certificate).map_err(|e| format!("Failed to parse certificate: {}", e))?;
+
+    Ok(hostnames
+        .iter()
+        .filter(|hostname| !hostname.is_empty() && *hostname != "*")
+        .filter(|hostname| match ServerName::try_from(hostname.as_str()) {
+            Ok(server_name) => end_entity_certificate.verify_is_valid_for_subject_name(&server_name).is_err(),
+            Err(_) => true,
+        })
+        .cloned()
+        .collect())
+}
+
+fn get_site_subject(site: &Site) -> String {
+    let hostnames = site.hostnames.join(", ");
+    if hostnames.is_empty() { format!("site {}", site.id) } else { format!("site {}", hostnames) }
+}
+
+// Gets PEM content from a file path if one is set, otherwise from the content itself. None if neither is set
+fn read_pem(path: &str, content: &str) -> Result<Option<Vec<u8>>, String> {
+    if !path.is_empty() {
+        return std::fs::read(path).map(Some).map_err(|e| format!("Failed to read {}: {}", path, e));
+    }
+    if !content.is_empty() {
+        return Ok(Some(content.as_bytes().to_vec()));
+    }
+    Ok(None)
+}
+
+fn parse_certificate_and_key(certificate: &[u8], key: &[u8]) -> Result<usize, String> {
+    let certificates: Result<Vec<_>, _> = rustls_pemfile::certs(&mut std::io::Cursor::new(certificate)).collect();
+    let certificates = certificates.map_err(|e| format!("Failed to parse certificate: {}", e))?;
+    if certificates.is_empty() {
+        return Err("No certificates found in the certificate PEM".to_string());
+    }
+    rustls_pemfile::private_key(&mut std::io::Cursor::new(key))
+        .map_err(|e| format!("Failed to parse private key: {}", e))?
+        .ok_or("No private key found in the key PEM")?;
+    Ok(certificates.len())
+}
+
+fn check_web_roots(configuration: &Configuration, report: &mut ConfigurationCheckReport) {
+    let web_roots = configuration
+        .static_file_processors
+        .iter()
+        .map(|p| (format!("static file processor {}", p.id), &p.web_root))
+        .chain(configuration.php_processors.iter().map(|p| (format!("PHP processor {}", p.id), &p.local_web_root)));
+
+    for (subject, web_root) in web_roots {
+        if Path::new(web_root).is_dir() {
+            report.ok("web_roots", &subject, format!("Web root {} exists", web_root));
+        } else {
+            report.error("web_roots", &subject, format!("Web root {} does not exist or is not a directory", web_root));
+        }
+    }
+}
+
+fn check_handlers(configuration: &Configuration, report: &mut ConfigurationCheckReport) {
+    for php_processor in &configuration.php_processors {
+        let subject = format!("PHP processor {}", php_processor.id);
+        match php_processor.served_by_type.as_str() {
+            "win-php-cgi" => match configuration.php_cgi_handlers.iter().find(|h| h.id == php_processor.php_cgi_handler_id) {
+                Some(php_cgi) if Path::new(&php_cgi.executable).is_file() => report.ok("handlers", &subject, format!("PHP-CGI executable {} exists", php_cgi.executable)),
+                Some(php_cgi) => report.error("handlers", &subject, format!("PHP-CGI executable {} does not exist", php_cgi.executable)),
+                None => report.error("handlers", &subject, format!("PHP-CGI handler {} does not exist", php_processor.php_cgi_handler_id)),
+            },
+            "php-fpm" => match php_processor.fastcgi_ip_and_port.to_socket_addrs().map(|mut addresses| addresses.next()) {
+                Ok(Some(_)) => report.ok("handlers", &subject, format!("FastCGI address {} resolves", php_processor.fastcgi_ip_and_port)),
+                Ok(None) => report.error("handlers", &subject, format!("FastCGI address {} does not resolve", php_processor.fastcgi_ip_and_port)),
+                Err(e) => report.error("handlers", &subject, format!("FastCGI address {} is invalid: {}", php_processor.fastcgi_ip_and_port, e)),
+            },
+            other => report.error("handlers", &subject, format!("Unknown PHP served by type: {}", other)),
+        }
+    }
+}
+
+fn check_upstream_tls(configuration: &Configuration, report: &mut ConfigurationCheckReport) {
+    for (subject, verify_certificates, tls_settings) in get_upstream_tls_users(configuration) {
+        if !verify_certificates && tls_settings.pinned_certificates.is_empty() {
+            report.warning("upstream_tls", &subject, "TLS certificate verification is disabled, so the connections can be intercepted");
+        }
+        if verify_certificates && !tls_settings.ca_bundle_file.is_empty() {
+            match get_upstream_tls_config(true, tls_settings) {
+                Ok(_) => report.ok("upstream_tls", &subject, format!("CA bundle {} can be loaded", tls_settings.ca_bundle_file)),
+                Err(e) => report.error("upstream_tls", &subject, e),
+            }
+        }
+    }
+}
+
+// Warnings for the upstreams connected to without verifying their certificate or checking a pinned one, returned when the
+// configuration is saved, as anyone on the network between Gruxi and the upstream could read and change the traffic
+pub fn get_insecure_upstream_tls_warnings(configuration: &Configuration) -> Vec<String> {
+    get_upstream_tls_users(configuration)
+        .into_iter()
+        .filter(|(_, verify_certificates, tls_settings)| !verify_certificates && tls_settings.pinned_certificates.is_empty())
+        .map(|(subject, _, _)| {
+            format!(
+                "TLS certificate verification is disabled for {}, so its connections can be intercepted. Pin the certificate or add a CA bundle instead",
+                subject
+            )
+        })
+        .collect()
+}
+
+// The proxy processors and the PHP processors with TLS to their FastCGI server, with whether they verify certificates and their TLS settings
+fn get_upstream_tls_users(configuration: &Configuration) -> Vec<(String, bool, &UpstreamTlsSettings)> {
+    let proxy_processors = configuration
+        .proxy_processors
+        .iter()
+        .map(|p| (format!("Proxy processor {}", p.id), p.verify_tls_certificates, &p.tls_settings));
+    let php_processors = configuration
+        .php_processors
+        .iter()
+        .filter(|p| p.fastcgi_tls_enabled)
+        .map(|p| (format!("PHP processor {}", p.id), p.fastcgi_verify_tls_certificates, &p.fastcgi_tls_settings));
+    proxy_processors.chain(php_processors).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request_handlers::processors::proxy_processor::ProxyProcessor;
+    use crate::http::request_handlers::processors::static_files_processor::StaticFileProcessor;
+
+    #[test]
+    fn test_check_configuration_reports_errors() {
+        let mut configuration = Configuration::get_default();
+        configuration.core.admin_portal.is_enabled = false;
+        assert!(!check_configuration(&configuration).has_errors());
+
+        configuration.static_file_processors.push(StaticFileProcessor::new("./does-not-exist".to_string(), vec![]));
+        let tls_binding = configuration.bindings.iter().find(|b| b.is_tls).unwrap().id.clone();
+        let site_id = configuration.binding_sites.iter().find(|bs| bs.binding_id == tls_binding).unwrap().site_id.clone();
+        let site = configuration.sites.iter_mut().find(|s| s.id == site_id).unwrap();
+        site.tls_cert_path = "./does-not-exist.pem".to_string();
+        site.tls_key_path = "./does-not-exist.key".to_string();
+
+        let report = check_configuration(&configuration);
+        assert!(report.has_errors());
+        assert!(report.results.iter().any(|r| r.category == "web_roots" && r.severity == CheckSeverity::Error));
+        assert!(report.results.iter().any(|r| r.category == "certificates" && r.severity == CheckSeverity::Error));
+        assert!(report.to_text().contains("[ERROR]"));
+    }
+
+    #[test]
+    fn test_parse_certificate_and_key() {
+        let rcgen::CertifiedKey { cert, signing_key } = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        assert_eq!(parse_certificate_and_key(cert.pem().as_bytes(), signing_key.serialize_pem().as_bytes()), Ok(1));
+        assert!(parse_certificate_and_key(signing_key.serialize_pem().as_bytes(), signing_key.serialize_pem().as_bytes()).is_err());
+        assert!(parse_certificate_and_key(cert.pem().as_bytes(), b"").is_err());
+    }
+
+    #[test]
+    fn test_certificate_hostname_warnings() {
+        let rcgen::CertifiedKey { cert, signing_key } = rcgen::generate_simple_self_signed(vec!["example.com".to_string(), "*.example.org".to_string()]).unwrap();
+        let hostnames = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+        assert_eq!(get_uncovered_hostnames(cert.pem().as_bytes(), &hostnames(&["example.com", "www.example.org", "*"])), Ok(Vec::new()));
+        assert_eq!(
+            get_uncovered_hostnames(cert.pem().as_bytes(), &hostnames(&["www.example.com", "example.org", "a.b.example.org", "example.com"])),
+            Ok(hostnames(&["www.example.com", "example.org", "a.b.example.org"]))
+        );
+        assert!(get_uncovered_hostnames(signing_key.serialize_pem().as_bytes(), &hostnames(&["example.com"])).is_err());
+
+        let mut configuration = Configuration::get_default();
+        let tls_binding = configuration.bindings.iter().find(|b| b.is_tls).unwrap().id.clone();
+        let site_id = configuration.binding_sites.iter().find(|bs| bs.binding_id == tls_binding).unwrap().site_id.clone();
+        let site = configuration.sites.iter_mut().find(|s| s.id == site_id).unwrap();
+        site.hostnames = hostnames(&["example.com", "www.example.com"]);
+        site.tls_cert_content = cert.pem();
+        site.tls_key_content = signing_key.serialize_pem();
+        let warnings = get_certificate_hostname_warnings(&configuration);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("does not cover hostname(s) www.example.com"));
+        assert!(
+            check_configuration(&configuration)
+                .results
+                .iter()
+                .any(|r| r.category == "certificates" && r.severity == CheckSeverity::Warning)
+        );
+
+        // Automatic TLS gets a certificate for the hostnames of the site
+        configuration.sites.iter_mut().find(|s| s.id == site_id).unwrap().tls_automatic_enabled = true;
+        assert!(get_certificate_hostname_warnings(&configuration).is_empty());
+    }
+
+    #[test]
+    fn test_insecure_upstream_tls_warnings() {
+        let mut configuration = Configuration::get_default();
+        let mut proxy_processor = ProxyProcessor::new();
+        proxy_processor.verify_tls_certificates = false;
+        configuration.proxy_processors.push(proxy_processor);
+        assert_eq!(get_insecure_upstream_tls_warnings(&configuration).len(), 1);
+        assert!(
+            check_configuration(&configuration)
+                .results
+                .iter()
+                .any(|r| r.category == "upstream_tls" && r.severity == CheckSeverity::Warning)
+        );
+
+        // A pinned certificate is checked even without verification
+        configuration.proxy_processors[0].tls_settings.pinned_certificates = vec!["ab".repeat(32)];
+        assert!(get_insecure_upstream_tls_warnings(&configuration).is_empty());
+    }
+}
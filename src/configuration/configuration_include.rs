@@ -0,0 +1,324 @@
+use crate::configuration::binding_site_relation::BindingSiteRelationship;
+use crate::configuration::configuration::Configuration;
+use crate::configuration::request_handler::RequestHandler;
+use crate::configuration::site::Site;
+use crate::http::request_handlers::processors::php_processor::PHPProcessor;
+use crate::http::request_handlers::processors::proxy_processor::ProxyProcessor;
+use crate::http::request_handlers::processors::static_files_processor::StaticFileProcessor;
+use crate::logging::syslog::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+// Sites can be kept in their own files instead of the database, such as one file per site in sites-enabled/*.json.
+// A site file holds one or more sites, with the request handlers, processors and binding relations they use, and is merged into the configuration when it is loaded
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SiteFile {
+    #[serde(default)]
+    pub sites: Vec<Site>,
+    #[serde(default)]
+    pub binding_sites: Vec<BindingSiteRelationship>,
+    #[serde(default)]
+    pub request_handlers: Vec<RequestHandler>,
+    #[serde(default)]
+    pub static_file_processors: Vec<StaticFileProcessor>,
+    #[serde(default)]
+    pub php_processors: Vec<PHPProcessor>,
+    #[serde(default)]
+    pub proxy_processors: Vec<ProxyProcessor>,
+}
+
+// The ids of what is kept in site files rather than in the database. Handlers and processors also used by sites in the database are kept in both
+#[derive(Debug, Default)]
+pub struct IncludedIds {
+    pub site_ids: HashSet<String>,
+    pub request_handler_ids: HashSet<String>,
+    pub processor_ids: HashSet<String>,
+}
+
+// Splits an include pattern into its directory and file name pattern, such as "sites-enabled" and "*.json"
+fn split_include_pattern(pattern: &str) -> (&str, &str) {
+    match pattern.rsplit_once('/') {
+        Some((directory, file_pattern)) => (directory, file_pattern),
+        None => (".", pattern),
+    }
+}
+
+// Matches a file name against a pattern, where * matches any number of characters
+fn matches_file_pattern(file_name: &str, pattern: &str) -> bool {
+    match pattern.split_once('*') {
+        None => file_name == pattern,
+        Some((prefix, rest)) => {
+            let Some(remaining) = file_name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=remaining.len()).filter(|i| remaining.is_char_boundary(*i)).any(|i| matches_file_pattern(&remaining[i..], rest))
+        }
+    }
+}
+
+pub fn validate_include_pattern(pattern: &str) -> Result<(), String> {
+    let (directory, file_pattern) = split_include_pattern(pattern);
+    if file_pattern.is_empty() {
+        return Err(format!("Include pattern '{}' has no file name", pattern));
+    }
+    if directory.contains('*') {
+        return Err(format!("Include pattern '{}' may only use * in the file name", pattern));
+    }
+    if !file_pattern.to_lowercase().ends_with(".json") {
+        return Err(format!("Include pattern '{}' must match .json files", pattern));
+    }
+    Ok(())
+}
+
+// Whether a site file path is matched by one of the include patterns, so it is loaded again after being saved
+pub fn is_included_file(path: &str, include_patterns: &[String]) -> bool {
+    let (directory, file_name) = split_include_pattern(path);
+    include_patterns.iter().any(|pattern| {
+        let (pattern_directory, file_pattern) = split_include_pattern(pattern);
+        pattern_directory == directory && matches_file_pattern(file_name, file_pattern)
+    })
+}
+
+// The existing files matched by the include patterns, in the order they are merged. Missing directories match nothing
+pub fn find_included_files(include_patterns: &[String]) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    for pattern in include_patterns {
+        let (directory, file_pattern) = split_include_pattern(pattern);
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Failed to read include directory {}: {}", directory, e)),
+        };
+
+        let mut pattern_files: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+            .filter_map(|entry| entry.file_name().to_str().map(|name| name.to_string()))
+            .filter(|name| matches_file_pattern(name, file_pattern))
+            .map(|name| format!("{}/{}", directory, name))
+            .collect();
+        pattern_files.sort();
+
+        for file in pattern_files {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    Ok(files)
+}
+
+// Merges the site files matched by the include patterns into the configuration
+pub fn load_included_sites(configuration: &mut Configuration) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for path in find_included_files(&configuration.core.server_settings.include)? {
+        let contents = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read site file {}: {}", path, e))?;
+        let site_file: SiteFile = serde_json::from_str(&contents).map_err(|e| format!("Failed to parse site file {}: {}", path, e))?;
+        merge_site_file(configuration, site_file, &path, &mut errors);
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors.join("; ")) }
+}
+
+// Adds the content of a site file to the configuration. Sites may not reuse the ids or hostnames of other sites,
+// while handlers and processors may be shared with other sites, as long as they are defined the same everywhere
+pub fn merge_site_file(configuration: &mut Configuration, site_file: SiteFile, path: &str, errors: &mut Vec<String>) {
+    for mut site in site_file.sites {
+        if configuration.sites.iter().any(|s| s.id == site.id) {
+            errors.push(format!("Site '{}' in {} conflicts with another site with the same id", site.id, path));
+            continue;
+        }
+        if let Some(hostname) = site.hostnames.iter().find(|h| *h != "*" && configuration.sites.iter().any(|s| s.hostnames.contains(h))) {
+            errors.push(format!("Hostname '{}' of site '{}' in {} is already used by another site", hostname, site.id, path));
+            continue;
+        }
+        site.config_file = path.to_string();
+        configuration.sites.push(site);
+    }
+
+    for relationship in site_file.binding_sites {
+        if !configuration
+            .binding_sites
+            .iter()
+            .any(|bs| bs.binding_id == relationship.binding_id && bs.site_id == relationship.site_id)
+        {
+            configuration.binding_sites.push(relationship);
+        }
+    }
+
+    merge_items(&mut configuration.request_handlers, site_file.request_handlers, |h| &h.id, "Request handler", path, errors);
+    merge_items(
+        &mut configuration.static_file_processors,
+        site_file.static_file_processors,
+        |p| &p.id,
+        "Static file processor",
+        path,
+        errors,
+    );
+    merge_items(&mut configuration.php_processors, site_file.php_processors, |p| &p.id, "PHP processor", path, errors);
+    merge_items(&mut configuration.proxy_processors, site_file.proxy_processors, |p| &p.id, "Proxy processor", path, errors);
+}
+
+fn merge_items<T: Serialize>(existing_items: &mut Vec<T>, new_items: Vec<T>, get_id: fn(&T) -> &String, kind: &str, path: &str, errors: &mut Vec<String>) {
+    for item in new_items {
+        match existing_items.iter().find(|existing| get_id(existing) == get_id(&item)) {
+            Some(existing) if serde_json::to_value(existing).ok() == serde_json::to_value(&item).ok() => {}
+            Some(_) => errors.push(format!("{} '{}' in {} conflicts with a different definition with the same id", kind, get_id(&item), path)),
+            None => existing_items.push(item),
+        }
+    }
+}
+
+// Finds what belongs in the site files, so it is not saved in the database as well
+pub fn get_included_ids(configuration: &Configuration) -> IncludedIds {
+    let mut included_ids = IncludedIds::default();
+    let (file_sites, database_sites): (Vec<&Site>, Vec<&Site>) = configuration.sites.iter().partition(|s| !s.config_file.is_empty());
+    if file_sites.is_empty() {
+        return included_ids;
+    }
+
+    included_ids.site_ids = file_sites.iter().map(|s| s.id.clone()).collect();
+    let database_handler_ids: HashSet<&String> = database_sites.iter().flat_map(|s| s.get_all_request_handler_ids()).collect();
+    included_ids.request_handler_ids = file_sites
+        .iter()
+        .flat_map(|s| s.get_all_request_handler_ids())
+        .filter(|id| !database_handler_ids.contains(id))
+        .cloned()
+        .collect();
+
+    let (file_handlers, database_handlers): (Vec<&RequestHandler>, Vec<&RequestHandler>) = configuration.request_handlers.iter().partition(|h| included_ids.request_handler_ids.contains(&h.id));
+    let database_processor_ids: HashSet<&String> = database_handlers.iter().map(|h| &h.processor_id).collect();
+    included_ids.processor_ids = file_handlers.iter().map(|h| &h.processor_id).filter(|id| !database_processor_ids.contains(id)).cloned().collect();
+
+    included_ids
+}
+
+// Builds the content of a site file, from the sites kept in it and everything they use
+pub fn build_site_file(configuration: &Configuration, path: &str) -> SiteFile {
+    let sites: Vec<Site> = configuration
+        .sites
+        .iter()
+        .filter(|s| s.config_file == path)
+        .map(|s| {
+            // The file a site is in is known from where it is loaded from
+            let mut site = s.clone();
+            site.config_file = String::new();
+            site
+        })
+        .collect();
+    let site_ids: HashSet<&String> = sites.iter().map(|s| &s.id).collect();
+    let handler_ids: HashSet<&String> = sites.iter().flat_map(|s| s.get_all_request_handler_ids()).collect();
+    let request_handlers: Vec<RequestHandler> = configuration.request_handlers.iter().filter(|h| handler_ids.contains(&h.id)).cloned().collect();
+    let processor_ids: HashSet<&String> = request_handlers.iter().map(|h| &h.processor_id).collect();
+
+    SiteFile {
+        binding_sites: configuration.binding_sites.iter().filter(|bs| site_ids.contains(&bs.site_id)).cloned().collect(),
+        static_file_processors: configuration.static_file_processors.iter().filter(|p| processor_ids.contains(&p.id)).cloned().collect(),
+        php_processors: configuration.php_processors.iter().filter(|p| processor_ids.contains(&p.id)).cloned().collect(),
+        proxy_processors: configuration.proxy_processors.iter().filter(|p| processor_ids.contains(&p.id)).cloned().collect(),
+        request_handlers,
+        sites,
+    }
+}
+
+// Writes each site file of the configuration, and removes the site files that no longer have any sites
+pub fn save_included_sites(configuration: &Configuration, previous_configuration: &Configuration) -> Result<(), String> {
+    let mut paths: Vec<&String> = configuration.sites.iter().map(|s| &s.config_file).filter(|f| !f.is_empty()).collect();
+    paths.sort();
+    paths.dedup();
+
+    for path in &paths {
+        let site_file = build_site_file(configuration, path);
+        let serialized = serde_json::to_string_pretty(&site_file).map_err(|e| format!("Failed to serialize site file {}: {}", path, e))?;
+        let is_unchanged = std::fs::read_to_string(path).is_ok_and(|contents| contents == serialized);
+        if is_unchanged {
+            continue;
+        }
+        let (directory, _) = split_include_pattern(path);
+        std::fs::create_dir_all(directory).map_err(|e| format!("Failed to create directory {}: {}", directory, e))?;
+        std::fs::write(path, serialized).map_err(|e| format!("Failed to write site file {}: {}", path, e))?;
+        info(format!("Site file saved: {}", path));
+    }
+
+    let mut removed_paths: Vec<&String> = previous_configuration.sites.iter().map(|s| &s.config_file).filter(|f| !f.is_empty() && !paths.contains(f)).collect();
+    removed_paths.dedup();
+    for path in removed_paths {
+        if std::path::Path::new(path).exists() {
+            std::fs::remove_file(path).map_err(|e| format!("Failed to remove site file {}: {}", path, e))?;
+            info(format!("Site file removed, as it no longer has any sites: {}", path));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_include_patterns() {
+        assert!(matches_file_pattern("example.json", "*.json"));
+        assert!(matches_file_pattern("site-example.json", "site-*.json"));
+        assert!(!matches_file_pattern("example.json.bak", "*.json"));
+        assert!(!matches_file_pattern("other.json", "site-*.json"));
+
+        let include_patterns = vec!["sites-enabled/*.json".to_string()];
+        assert!(is_included_file("sites-enabled/example.json", &include_patterns));
+        assert!(!is_included_file("sites-available/example.json", &include_patterns));
+        assert!(!is_included_file("example.json", &include_patterns));
+
+        assert!(validate_include_pattern("sites-enabled/*.json").is_ok());
+        assert!(validate_include_pattern("sites-*/*.json").is_err());
+        assert!(validate_include_pattern("sites-enabled/").is_err());
+        assert!(validate_include_pattern("sites-enabled/*.conf").is_err());
+    }
+
+    #[test]
+    fn test_merge_site_file_and_build_it_again() {
+        let mut configuration = Configuration::get_default();
+        let shared_handler = configuration.request_handlers[0].clone();
+
+        let mut site = Site::new();
+        site.hostnames = vec!["example.com".to_string()];
+        site.request_handlers = vec![shared_handler.id.clone()];
+        let site_file = SiteFile {
+            sites: vec![site.clone()],
+            request_handlers: vec![shared_handler.clone()],
+            ..Default::default()
+        };
+
+        let mut errors = Vec::new();
+        merge_site_file(&mut configuration, site_file, "sites-enabled/example.json", &mut errors);
+        assert!(errors.is_empty());
+        assert_eq!(configuration.sites.len(), 2);
+        assert_eq!(configuration.sites[1].config_file, "sites-enabled/example.json");
+        assert_eq!(configuration.request_handlers.len(), 1);
+
+        // The handler is also used by the default site in the database, so only the site is kept in the file alone
+        let included_ids = get_included_ids(&configuration);
+        assert!(included_ids.site_ids.contains(&site.id));
+        assert!(included_ids.request_handler_ids.is_empty());
+
+        let built_site_file = build_site_file(&configuration, "sites-enabled/example.json");
+        assert_eq!(built_site_file.sites.len(), 1);
+        assert!(built_site_file.sites[0].config_file.is_empty());
+        assert_eq!(built_site_file.request_handlers.len(), 1);
+        assert_eq!(built_site_file.static_file_processors.len(), 1);
+
+        // Reusing the id or a hostname of a site, or changing a shared handler, is a conflict
+        let mut changed_handler = shared_handler.clone();
+        changed_handler.name = "Changed".to_string();
+        let mut same_hostname_site = Site::new();
+        same_hostname_site.hostnames = vec!["example.com".to_string()];
+        let conflicting_site_file = SiteFile {
+            sites: vec![site, same_hostname_site],
+            request_handlers: vec![changed_handler],
+            ..Default::default()
+        };
+        let mut errors = Vec::new();
+        merge_site_file(&mut configuration, conflicting_site_file, "sites-enabled/other.json", &mut errors);
+        assert_eq!(errors.len(), 3);
+        assert_eq!(configuration.sites.len(), 2);
+    }
+}
@@ -0,0 +1,441 @@
+use crate::configuration::configuration::{CURRENT_CONFIGURATION_VERSION, Configuration};
+use crate::configuration::server_settings::{default_http2_flood_ban_seconds, default_upstream_pool_idle_timeout_seconds};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+// The oldest configuration version that can be upgraded, older ones must be recreated
+pub const MIN_MIGRATABLE_CONFIGURATION_VERSION: i32 = 2;
+
+type MigrateFn = fn(&mut Value) -> Result<(), String>;
+
+// A step in the upgrade of an exported configuration from one version to the next.
+// Most versions only add fields, which get their defaults when deserialized, so only renamed, moved or reinterpreted fields need a migrate function
+struct ConfigurationMigration {
+    from_version: i32,
+    description: &'static str,
+    migrate: Option<MigrateFn>,
+}
+
+const CONFIGURATION_MIGRATIONS: [ConfigurationMigration; 47] = [
+    ConfigurationMigration {
+        from_version: 2,
+        description: "PHP processor server software spoofing",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 3,
+        description: "Automatic TLS for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 4,
+        description: "Debug capture for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 5,
+        description: "API mode for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 6,
+        description: "Allowed methods for request handlers",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 7,
+        description: "Keep-alive and pipelining limits for bindings",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 8,
+        description: "Max body size for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 9,
+        description: "Path confinement for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 10,
+        description: "Cache policies for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 11,
+        description: "Language negotiation for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 12,
+        description: "Index files for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 13,
+        description: "Try files for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 14,
+        description: "SPA fallback for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 15,
+        description: "PHP error log file for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 16,
+        description: "Admin portal session settings",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 17,
+        description: "TLS fingerprints in access logs for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 18,
+        description: "HTTP/2 limits for bindings",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 19,
+        description: "HPACK dynamic table sizes for bindings",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 20,
+        description: "Middleware chain for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 21,
+        description: "Environment variables for sites and PHP-CGI handlers",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 22,
+        description: "Build before serve for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 23,
+        description: "Git deployment for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 24,
+        description: "Request timings in access logs for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 25,
+        description: "Statistics per site",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 26,
+        description: "Site owners with access to their own sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 27,
+        description: "Protocol selection for bindings",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 28,
+        description: "Traffic splitting for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 29,
+        description: "Session affinity for proxy processors",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 30,
+        description: "Named upstream pools",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 31,
+        description: "Header based routing for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 32,
+        description: "Cookie based routing for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 33,
+        description: "Response body substitutions for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 34,
+        description: "Minification of static files for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 35,
+        description: "Generated robots.txt and sitemap.xml for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 36,
+        description: "Disk cache for proxy processors",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 37,
+        description: "Disk cache for PHP processors",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 38,
+        description: "Canonical redirects for trailing slashes and case of sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 39,
+        description: "Status page for bindings",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 40,
+        description: "Upstream TLS settings for proxy and PHP processors",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 41,
+        description: "Retries of idempotent requests for proxy processors",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 42,
+        description: "Early hints for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 43,
+        description: "Link preload headers from HTML pages for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 44,
+        description: "Upload progress polling for sites",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 45,
+        description: "Early hints switch for sites",
+        migrate: Some(enable_configured_early_hints),
+    },
+    ConfigurationMigration {
+        from_version: 46,
+        description: "Upstream pool idle timeout below the outbound read timeout",
+        migrate: Some(lower_upstream_pool_idle_timeout),
+    },
+    ConfigurationMigration {
+        from_version: 47,
+        description: "HPACK dynamic table sizes left to the HTTP/2 implementation",
+        migrate: None,
+    },
+    ConfigurationMigration {
+        from_version: 48,
+        description: "HTTP/2 flood bans opt-in and trusted proxies",
+        migrate: Some(disable_default_http2_flood_bans),
+    },
+];
+
+// Upgrades a configuration in its JSON form to the current version, one version at a time.
+// Returns the version it was upgraded from, or None if it already was the current version
+pub fn migrate_configuration_json(configuration_json: &mut Value) -> Result<Option<i32>, String> {
+    let original_version = configuration_json["version"].as_i64().map(|v| v as i32).ok_or("Configuration has no version")?;
+
+    if original_version == CURRENT_CONFIGURATION_VERSION {
+        return Ok(None);
+    }
+    if original_version > CURRENT_CONFIGURATION_VERSION {
+        return Err(format!(
+            "Configuration version {} is newer than the supported version {}, it was made by a newer version of Gruxi",
+            original_version, CURRENT_CONFIGURATION_VERSION
+        ));
+    }
+    if original_version < MIN_MIGRATABLE_CONFIGURATION_VERSION {
+        return Err(format!(
+            "Configuration version {} is too old to be upgraded, the oldest supported version is {}",
+            original_version, MIN_MIGRATABLE_CONFIGURATION_VERSION
+        ));
+    }
+
+    let mut version = original_version;
+    for migration in CONFIGURATION_MIGRATIONS.iter().filter(|m| m.from_version >= original_version) {
+        if let Some(migrate) = migration.migrate {
+            migrate(configuration_json).map_err(|e| format!("Failed to upgrade configuration from version {} ({}): {}", migration.from_version, migration.description, e))?;
+        }
+        version = migration.from_version + 1;
+        configuration_json["version"] = Value::from(version);
+    }
+
+    if version != CURRENT_CONFIGURATION_VERSION {
+        return Err(format!("No configuration migration found from version {} to {}", version, CURRENT_CONFIGURATION_VERSION));
+    }
+
+    Ok(Some(original_version))
+}
+
+// Early hints were sent for every site with links configured, before they could be switched on and off
+fn enable_configured_early_hints(configuration_json: &mut Value) -> Result<(), String> {
+    for site in configuration_json["sites"].as_array_mut().into_iter().flatten().filter_map(|site| site.as_object_mut()) {
+        let has_early_hints = site
+            .get("early_hints")
+            .and_then(|early_hints| early_hints.as_array())
+            .is_some_and(|early_hints| !early_hints.is_empty());
+        site.insert("early_hints_enabled".to_string(), Value::from(has_early_hints));
+    }
+    Ok(())
+}
+
+// The upstream pool idle timeout defaulted to 90 seconds, longer than the outbound read timeout that closed the idle connections first
+fn lower_upstream_pool_idle_timeout(configuration_json: &mut Value) -> Result<(), String> {
+    let server_settings = &mut configuration_json["core"]["server_settings"];
+    if server_settings.get("upstream_pool_idle_timeout_seconds").and_then(|timeout| timeout.as_u64()) == Some(90) {
+        server_settings["upstream_pool_idle_timeout_seconds"] = Value::from(default_upstream_pool_idle_timeout_seconds());
+    }
+    Ok(())
+}
+
+// Bans used to be on by default, which also banned proxies and the admin's own address, so the old default turns them off
+fn disable_default_http2_flood_bans(configuration_json: &mut Value) -> Result<(), String> {
+    let server_settings = &mut configuration_json["core"]["server_settings"];
+    if server_settings.get("http2_flood_ban_seconds").and_then(|seconds| seconds.as_u64()) == Some(300) {
+        server_settings["http2_flood_ban_seconds"] = Value::from(default_http2_flood_ban_seconds());
+    }
+    Ok(())
+}
+
+// Parses an exported configuration, upgrading it first if it is from an older version.
+// Returns the configuration and the version it was upgraded from, if it was
+pub fn parse_configuration_json(contents: &str) -> Result<(Configuration, Option<i32>), String> {
+    let mut configuration_json: Value = serde_json::from_str(contents).map_err(|e| format!("Failed to parse configuration: {}", e))?;
+    let migrated_from_version = migrate_configuration_json(&mut configuration_json)?;
+    let configuration: Configuration = serde_json::from_value(configuration_json).map_err(|e| format!("Failed to deserialize configuration: {}", e))?;
+    Ok((configuration, migrated_from_version))
+}
+
+// Copies the original of an upgraded configuration file next to it, such as gruxi.json.v12.bak, before it is overwritten
+pub fn backup_configuration_file(path: &Path, version: i32) -> Result<PathBuf, String> {
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(format!(".v{}.bak", version));
+    let backup_path = PathBuf::from(backup_path);
+    std::fs::copy(path, &backup_path).map_err(|e| format!("Failed to back up configuration file {} to {}: {}", path.display(), backup_path.display(), e))?;
+    Ok(backup_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A configuration as exported by version 8, before path confinement, cache policies and the other later site settings
+    fn get_version_8_configuration_json() -> Value {
+        let mut configuration_json = serde_json::to_value(Configuration::get_default()).unwrap();
+        configuration_json["version"] = Value::from(8);
+        let site = configuration_json["sites"][0].as_object_mut().unwrap();
+        for field in [
+            "max_body_size",
+            "path_confinement_enabled",
+            "path_confinement_allowed_paths",
+            "cache_policies",
+            "index_files",
+            "try_files",
+            "php_error_log_file",
+        ] {
+            site.remove(field);
+        }
+        configuration_json["core"]["admin_portal"].as_object_mut().unwrap().remove("session_cookie_same_site");
+        configuration_json
+    }
+
+    #[test]
+    fn test_migrate_old_configuration() {
+        let contents = get_version_8_configuration_json().to_string();
+        let (configuration, migrated_from_version) = parse_configuration_json(&contents).unwrap();
+        assert_eq!(migrated_from_version, Some(8));
+        assert_eq!(configuration.version, CURRENT_CONFIGURATION_VERSION);
+        assert_eq!(configuration.sites[0].max_body_size, 0);
+        assert!(configuration.sites[0].cache_policies.is_empty());
+        assert_eq!(configuration.core.admin_portal.session_cookie_same_site, "Strict");
+        assert!(configuration.validate().is_ok());
+
+        // The current version is left as is
+        let contents = serde_json::to_string(&Configuration::get_default()).unwrap();
+        assert_eq!(parse_configuration_json(&contents).unwrap().1, None);
+    }
+
+    #[test]
+    fn test_migrate_early_hints_switch() {
+        let mut configuration_json = serde_json::to_value(Configuration::get_default()).unwrap();
+        configuration_json["version"] = Value::from(45);
+        let mut site = configuration_json["sites"][0].clone();
+        site["early_hints"] = serde_json::json!(["</app.css>; rel=preload; as=style"]);
+        configuration_json["sites"].as_array_mut().unwrap().push(site);
+
+        migrate_configuration_json(&mut configuration_json).unwrap();
+        assert_eq!(configuration_json["sites"][0]["early_hints_enabled"], Value::from(false));
+        assert_eq!(configuration_json["sites"][1]["early_hints_enabled"], Value::from(true));
+    }
+
+    #[test]
+    fn test_migrate_upstream_pool_idle_timeout() {
+        let mut configuration_json = serde_json::to_value(Configuration::get_default()).unwrap();
+        configuration_json["version"] = Value::from(46);
+        configuration_json["core"]["server_settings"]["upstream_pool_idle_timeout_seconds"] = Value::from(90);
+        let (configuration, _) = parse_configuration_json(&configuration_json.to_string()).unwrap();
+        assert_eq!(configuration.core.server_settings.upstream_pool_idle_timeout_seconds, 50);
+        assert!(configuration.validate().is_ok());
+
+        // Timeouts chosen by the user are kept
+        configuration_json["core"]["server_settings"]["upstream_pool_idle_timeout_seconds"] = Value::from(30);
+        let (configuration, _) = parse_configuration_json(&configuration_json.to_string()).unwrap();
+        assert_eq!(configuration.core.server_settings.upstream_pool_idle_timeout_seconds, 30);
+    }
+
+    #[test]
+    fn test_migrate_http2_flood_bans() {
+        let mut configuration_json = serde_json::to_value(Configuration::get_default()).unwrap();
+        configuration_json["version"] = Value::from(48);
+        configuration_json["core"]["server_settings"]["http2_flood_ban_seconds"] = Value::from(300);
+        let (configuration, _) = parse_configuration_json(&configuration_json.to_string()).unwrap();
+        assert_eq!(configuration.core.server_settings.http2_flood_ban_seconds, 0);
+        assert!(configuration.core.server_settings.trusted_proxy_ips.is_empty());
+
+        configuration_json["core"]["server_settings"]["http2_flood_ban_seconds"] = Value::from(60);
+        let (configuration, _) = parse_configuration_json(&configuration_json.to_string()).unwrap();
+        assert_eq!(configuration.core.server_settings.http2_flood_ban_seconds, 60);
+    }
+
+    #[test]
+    fn test_migrate_unsupported_versions() {
+        for version in [MIN_MIGRATABLE_CONFIGURATION_VERSION - 1, CURRENT_CONFIGURATION_VERSION + 1] {
+            let mut configuration_json = get_version_8_configuration_json();
+            configuration_json["version"] = Value::from(version);
+            assert!(migrate_configuration_json(&mut configuration_json).is_err());
+        }
+        assert!(migrate_configuration_json(&mut serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_migrations_reach_current_version() {
+        for (index, migration) in CONFIGURATION_MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.from_version, MIN_MIGRATABLE_CONFIGURATION_VERSION + index as i32);
+        }
+        assert_eq!(CONFIGURATION_MIGRATIONS.last().unwrap().from_version + 1, CURRENT_CONFIGURATION_VERSION);
+    }
+}
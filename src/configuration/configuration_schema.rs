@@ -0,0 +1,190 @@
+use crate::configuration::admin_portal::SESSION_COOKIE_SAME_SITE_VALUES;
+use crate::configuration::binding::Binding;
+use crate::configuration::configuration::{CURRENT_CONFIGURATION_VERSION, Configuration};
+use crate::configuration::request_handler::{PROCESSOR_TYPES, RequestHandler};
+use crate::configuration::server_settings::{REQUEST_VALIDATION_LEVELS, SERVER_HEADER_MODES};
+use crate::configuration::site::{HEADER_ROUTE_MATCH_TYPES, Site, TRAILING_SLASH_REDIRECT_MODES};
+use crate::configuration::upstream::Upstream;
+use crate::http::request_handlers::processors::php_processor::{PHP_SERVED_BY_TYPES, PHPProcessor};
+use crate::http::request_handlers::processors::proxy_processor::{LOAD_BALANCING_STRATEGIES, PROXY_TYPES, ProxyProcessor, SESSION_AFFINITY_MODES};
+use serde_json::{Map, Value, json};
+use std::sync::OnceLock;
+use utoipa::openapi::RefOr;
+use utoipa::openapi::schema::Schema;
+use utoipa::{PartialSchema, ToSchema};
+
+// String fields that only accept a fixed set of values, as (schema name, property name, allowed values)
+const ENUM_PROPERTIES: [(&str, &str, &[&str]); 10] = [
+    ("RequestHandler", "processor_type", &PROCESSOR_TYPES),
+    ("PHPProcessor", "served_by_type", &PHP_SERVED_BY_TYPES),
+    ("ProxyProcessor", "proxy_type", &PROXY_TYPES),
+    ("ProxyProcessor", "load_balancing_strategy", &LOAD_BALANCING_STRATEGIES),
+    ("ProxyProcessor", "session_affinity", &SESSION_AFFINITY_MODES),
+    ("AdminPortal", "session_cookie_same_site", &SESSION_COOKIE_SAME_SITE_VALUES),
+    ("ServerSettings", "request_validation_level", &REQUEST_VALIDATION_LEVELS),
+    ("ServerSettings", "server_header", &SERVER_HEADER_MODES),
+    ("HeaderRoute", "match_type", &HEADER_ROUTE_MATCH_TYPES),
+    ("Site", "trailing_slash_redirect", &TRAILING_SLASH_REDIRECT_MODES),
+];
+
+// Builds a JSON Schema (draft 2020-12) of the configuration, as posted to and returned from the admin API.
+// The types come from the same definitions as the OpenAPI document, with the defaults of new items and the allowed values of enum-like fields added
+pub fn build_configuration_schema() -> Value {
+    let mut schemas: Vec<(String, RefOr<Schema>)> = vec![(Configuration::name().to_string(), Configuration::schema())];
+    <Configuration as ToSchema>::schemas(&mut schemas);
+
+    let mut definitions = Map::new();
+    for (name, schema) in schemas {
+        let mut schema_json = serde_json::to_value(schema).unwrap_or(Value::Null);
+        rewrite_references(&mut schema_json);
+        definitions.insert(name, schema_json);
+    }
+
+    for (schema_name, default_value) in get_default_values() {
+        if let Some(properties) = definitions.get_mut(schema_name).and_then(|s| s.get_mut("properties")).and_then(|p| p.as_object_mut()) {
+            add_defaults(properties, &default_value);
+        }
+    }
+
+    for (schema_name, property_name, values) in ENUM_PROPERTIES {
+        if let Some(property) = definitions
+            .get_mut(schema_name)
+            .and_then(|s| s.pointer_mut(&format!("/properties/{}", property_name)))
+            .and_then(|p| p.as_object_mut())
+        {
+            // New handlers start without a processor type, which is not a valid choice, so no default is given then
+            if property.get("default").and_then(|d| d.as_str()).is_some_and(|d| !values.contains(&d)) {
+                property.remove("default");
+            }
+            property.insert("enum".to_string(), json!(values));
+        }
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Gruxi configuration",
+        "$ref": "#/$defs/Configuration",
+        "$defs": definitions,
+    })
+}
+
+static CONFIGURATION_SCHEMA_JSON: OnceLock<String> = OnceLock::new();
+
+// The schema only depends on the code, so it is only built once
+pub fn get_configuration_schema_json() -> String {
+    CONFIGURATION_SCHEMA_JSON
+        .get_or_init(|| serde_json::to_string_pretty(&build_configuration_schema()).unwrap_or_else(|_| "{}".to_string()))
+        .clone()
+}
+
+// The values new items start out with, which is what the admin portal uses when adding them
+fn get_default_values() -> Vec<(&'static str, Value)> {
+    let core = Configuration::new().core;
+    let values = vec![
+        ("Configuration", Ok(json!({ "version": CURRENT_CONFIGURATION_VERSION }))),
+        ("Binding", serde_json::to_value(Binding::new())),
+        ("Site", serde_json::to_value(Site::new())),
+        ("RequestHandler", serde_json::to_value(RequestHandler::new())),
+        ("PHPProcessor", serde_json::to_value(PHPProcessor::new())),
+        ("ProxyProcessor", serde_json::to_value(ProxyProcessor::new())),
+        ("Upstream", serde_json::to_value(Upstream::new())),
+        ("FileCache", serde_json::to_value(&core.file_cache)),
+        ("Gzip", serde_json::to_value(&core.gzip)),
+        ("ServerSettings", serde_json::to_value(&core.server_settings)),
+        ("AdminPortal", serde_json::to_value(&core.admin_portal)),
+        ("TlsSettings", serde_json::to_value(&core.tls_settings)),
+    ];
+    values.into_iter().map(|(name, value)| (name, value.unwrap_or(Value::Null))).collect()
+}
+
+fn add_defaults(properties: &mut Map<String, Value>, default_value: &Value) {
+    let Some(default_fields) = default_value.as_object() else {
+        return;
+    };
+    for (property_name, property) in properties.iter_mut() {
+        // Ids are generated for every new item, so there is no default to show
+        if property_name == "id" {
+            continue;
+        }
+        if let (Some(default_field), Some(property)) = (default_fields.get(property_name), property.as_object_mut()) {
+            property.insert("default".to_string(), default_field.clone());
+        }
+    }
+}
+
+// The OpenAPI schemas reference each other as components, which are definitions in this document
+fn rewrite_references(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, inner_value) in object.iter_mut() {
+                if key == "$ref"
+                    && let Value::String(reference) = inner_value
+                {
+                    *reference = reference.replace("#/components/schemas/", "#/$defs/");
+                } else {
+                    rewrite_references(inner_value);
+                }
+            }
+        }
+        Value::Array(array) => array.iter_mut().for_each(rewrite_references),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect_references(value: &Value, references: &mut Vec<String>) {
+        match value {
+            Value::Object(object) => {
+                for (key, inner_value) in object {
+                    match (key.as_str(), inner_value) {
+                        ("$ref", Value::String(reference)) => references.push(reference.clone()),
+                        _ => collect_references(inner_value, references),
+                    }
+                }
+            }
+            Value::Array(array) => array.iter().for_each(|v| collect_references(v, references)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_configuration_schema() {
+        let schema: Value = serde_json::from_str(&get_configuration_schema_json()).unwrap();
+        let definitions = &schema["$defs"];
+
+        for name in [
+            "Configuration",
+            "Binding",
+            "Site",
+            "RequestHandler",
+            "StaticFileProcessor",
+            "PHPProcessor",
+            "ProxyProcessor",
+            "PhpCgi",
+            "Core",
+        ] {
+            assert!(definitions[name]["properties"].is_object(), "Missing definition of {}", name);
+        }
+
+        // Every reference resolves to a definition
+        let mut references = Vec::new();
+        collect_references(&schema, &mut references);
+        assert!(!references.is_empty());
+        for reference in references {
+            let name = reference.strip_prefix("#/$defs/").unwrap_or_else(|| panic!("Unexpected reference {}", reference));
+            assert!(definitions[name].is_object(), "Unresolved reference {}", reference);
+        }
+
+        assert_eq!(definitions["RequestHandler"]["properties"]["processor_type"]["enum"], json!(["static", "php", "proxy"]));
+        assert!(definitions["RequestHandler"]["properties"]["processor_type"].get("default").is_none());
+        assert_eq!(definitions["PHPProcessor"]["properties"]["served_by_type"]["default"], json!("php-fpm"));
+        assert_eq!(definitions["ProxyProcessor"]["properties"]["load_balancing_strategy"]["enum"], json!(["round_robin"]));
+        assert_eq!(definitions["PHPProcessor"]["properties"]["request_timeout"]["default"], json!(30));
+        assert_eq!(definitions["Binding"]["properties"]["keep_alive_timeout_seconds"]["default"], json!(60));
+        assert_eq!(definitions["Configuration"]["properties"]["version"]["default"], json!(CURRENT_CONFIGURATION_VERSION));
+        assert!(definitions["Site"]["properties"]["id"].get("default").is_none());
+    }
+}
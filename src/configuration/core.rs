@@ -1,10 +1,11 @@
-use crate::configuration::tls_settings::TlsSettings;
-use crate::configuration::{admin_portal::AdminPortal, file_cache::FileCache};
 use crate::configuration::gzip::Gzip;
 use crate::configuration::server_settings::ServerSettings;
+use crate::configuration::tls_settings::TlsSettings;
+use crate::configuration::{admin_portal::AdminPortal, file_cache::FileCache};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct Core {
     pub file_cache: FileCache,
     pub gzip: Gzip,
@@ -1,3 +1,4 @@
+use crate::configuration::configuration_migration::{backup_configuration_file, parse_configuration_json};
 use crate::configuration::load_configuration::fetch_configuration_in_db;
 use std::path::PathBuf;
 
@@ -23,28 +24,25 @@ pub fn import_configuration_from_file(path: &PathBuf) -> Result<(), String> {
     // Read file contents
     let file_contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read configuration file {}: {}", path.display(), e))?;
 
-    // Load json into loose typed to validate version and possibly do version migrations later
-    let loose_typed: serde_json::Value = serde_json::from_str(&file_contents).map_err(|e| format!("Failed to parse configuration file {}: {}", path.display(), e))?;
+    // Upgrade configurations exported by older versions before deserializing them
+    let (mut configuration, migrated_from_version) = parse_configuration_json(&file_contents).map_err(|e| format!("Configuration file {}: {}", path.display(), e))?;
 
-    // Check that versions match
-    if loose_typed["version"] != crate::configuration::configuration::CURRENT_CONFIGURATION_VERSION {
-        // Here we could add version migration logic in the future
+    // Save configuration to database
+    crate::configuration::save_configuration::save_configuration(&mut configuration, false).map_err(|e| format!("Failed to save imported configuration to database: {:?}", e))?;
 
-        // If we reach here, versions do not match
-        return Err(format!(
-            "Configuration version mismatch: expected {}, found {}",
+    // Upgrade the file as well, keeping the original next to it
+    if let Some(version) = migrated_from_version {
+        let backup_path = backup_configuration_file(path, version)?;
+        let serialized = serde_json::to_string_pretty(&configuration).map_err(|e| format!("Failed to serialize configuration: {}", e))?;
+        std::fs::write(path, serialized).map_err(|e| format!("Failed to write upgraded configuration to file: {}", e))?;
+        println!(
+            "Configuration file upgraded from version {} to {}, the original is kept in {}",
+            version,
             crate::configuration::configuration::CURRENT_CONFIGURATION_VERSION,
-            loose_typed["version"].as_i64().unwrap_or(-1)
-        ));
+            backup_path.display()
+        );
     }
 
-    // Deserialize JSON to Configuration struct
-    let mut configuration: crate::configuration::configuration::Configuration =
-        serde_json::from_str(&file_contents).map_err(|e| format!("Failed to deserialize configuration from file {}: {}", path.display(), e))?;
-
-    // Save configuration to database
-    crate::configuration::save_configuration::save_configuration(&mut configuration, false).map_err(|e| format!("Failed to save imported configuration to database: {:?}", e))?;
-
     println!("Configuration successfully imported from {}", path.display());
 
     Ok(())
@@ -54,22 +52,16 @@ pub fn validate_configuration_file(path: &PathBuf) -> Result<(), String> {
     // Read file contents
     let file_contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read configuration file {}: {}", path.display(), e))?;
 
-    // Load json into loose typed to validate version
-    let loose_typed: serde_json::Value = serde_json::from_str(&file_contents).map_err(|e| format!("Failed to parse configuration file {}: {}", path.display(), e))?;
-
-    // Check that versions match
-    if loose_typed["version"] != crate::configuration::configuration::CURRENT_CONFIGURATION_VERSION {
-        return Err(format!(
-            "Configuration version mismatch: expected {}, found {}",
-            crate::configuration::configuration::CURRENT_CONFIGURATION_VERSION,
-            loose_typed["version"].as_i64().unwrap_or(-1)
-        ));
+    // Older versions are validated as they would be imported, after upgrading them
+    let (configuration, migrated_from_version) = parse_configuration_json(&file_contents).map_err(|e| format!("Configuration file {}: {}", path.display(), e))?;
+    if let Some(version) = migrated_from_version {
+        println!(
+            "Configuration file is version {}, and will be upgraded to version {} when imported",
+            version,
+            crate::configuration::configuration::CURRENT_CONFIGURATION_VERSION
+        );
     }
 
-    // Deserialize JSON to Configuration struct to ensure it's valid
-    let configuration: crate::configuration::configuration::Configuration =
-        serde_json::from_str(&file_contents).map_err(|e| format!("Failed to deserialize configuration from file {}: {}", path.display(), e))?;
-
     configuration.validate().map_err(|e| format!("Configuration validation failed: {:?}", e))?;
 
     Ok(())
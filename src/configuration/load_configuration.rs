@@ -1,18 +1,36 @@
 use crate::configuration::binding_site_relation::BindingSiteRelationship;
+use crate::configuration::configuration_include::load_included_sites;
+use crate::configuration::upstream::{Upstream, UpstreamServer};
+use crate::configuration::upstream_tls::UpstreamTlsSettings;
 use crate::database::database_migration::migrate_database;
 use crate::database::database_schema::{CURRENT_DB_SCHEMA_VERSION, get_schema_version, set_schema_version};
 use crate::external_connections::managed_system::php_cgi;
+use crate::http::middleware::middleware_chain::get_default_middlewares;
 use crate::http::request_handlers::processor_trait::ProcessorTrait;
 use crate::http::request_handlers::processors::php_processor::{self, PHPProcessor};
 use crate::http::request_handlers::processors::proxy_processor::{ProxyProcessor, ProxyProcessorRewrite};
 use crate::http::request_handlers::processors::static_files_processor::StaticFileProcessor;
 use crate::logging::syslog::{info, trace};
 use crate::{
-    configuration::{binding::Binding, configuration::Configuration, core::Core, request_handler::RequestHandler, save_configuration::save_configuration, site::HeaderKV, site::Site},
+    configuration::{
+        binding::{Binding, DEFAULT_KEEP_ALIVE_TIMEOUT_SECONDS, DEFAULT_MAX_REQUESTS_PER_CONNECTION, Http2Limits, default_protocols, default_status_page_allowed_ips},
+        configuration::Configuration,
+        core::Core,
+        request_handler::RequestHandler,
+        save_configuration::save_configuration,
+        site::BodySubstitution,
+        site::CachePolicy,
+        site::CookieRoute,
+        site::EnvironmentVariable,
+        site::HeaderKV,
+        site::HeaderRoute,
+        site::Site,
+        site::TrafficSplit,
+        site::get_default_trailing_slash_redirect,
+    },
     core::database_connection::get_database_connection,
 };
 use sqlite::Connection;
-use uuid::Uuid;
 
 // Load the configuration from the database or create a default one if it doesn't exist
 pub fn init() -> Configuration {
@@ -68,27 +86,44 @@ pub fn init() -> Configuration {
     configuration
 }
 
+// The admin portal is added on every load, with the same ids, so it is not seen as replaced when the configuration is reloaded
+const ADMIN_PORTAL_BINDING_ID: &str = "00000000-0000-4000-8000-00000000ad01";
+const ADMIN_PORTAL_SITE_ID: &str = "00000000-0000-4000-8000-00000000ad02";
+const ADMIN_PORTAL_REQUEST_HANDLER_ID: &str = "00000000-0000-4000-8000-00000000ad03";
+const ADMIN_PORTAL_STATIC_FILE_PROCESSOR_ID: &str = "00000000-0000-4000-8000-00000000ad04";
+
 fn add_admin_portal_to_configuration(configuration: &mut Configuration) {
     let admin_binding = Binding {
-        id: Uuid::new_v4().to_string(),
+        id: ADMIN_PORTAL_BINDING_ID.to_string(),
         ip: "0.0.0.0".to_string(),
-        port: 8000,
+        port: configuration.core.admin_portal.port,
         is_admin: true,
         is_tls: true,
+        keep_alive_timeout_seconds: DEFAULT_KEEP_ALIVE_TIMEOUT_SECONDS,
+        max_requests_per_connection: DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+        pipelining_enabled: true,
+        http2: Http2Limits::default(),
+        protocols: default_protocols(),
+        h2c_enabled: true,
+        absolute_form_enabled: true,
+        status_page_enabled: false,
+        status_page_allowed_ips: default_status_page_allowed_ips(),
     };
 
     // Static file processor for admin site
     let mut request_static_processor = StaticFileProcessor::new("./www-admin".to_string(), vec!["index.html".to_string()]);
+    request_static_processor.id = ADMIN_PORTAL_STATIC_FILE_PROCESSOR_ID.to_string();
     request_static_processor.initialize();
 
     // Request handler for admin site
     let request_handler = RequestHandler {
-        id: Uuid::new_v4().to_string(),
+        id: ADMIN_PORTAL_REQUEST_HANDLER_ID.to_string(),
         is_enabled: true,
         name: "Static File Handler".to_string(),
         processor_type: "static".to_string(),
         processor_id: request_static_processor.id.clone(),
         url_match: vec!["*".to_string()],
+        allowed_methods: Vec::new(),
     };
 
     // Get the admin portal configuration
@@ -102,7 +137,7 @@ fn add_admin_portal_to_configuration(configuration: &mut Configuration) {
     };
 
     let admin_site = Site {
-        id: Uuid::new_v4().to_string(),
+        id: ADMIN_PORTAL_SITE_ID.to_string(),
         hostnames: admin_hostnames,
         is_default: true,
         is_enabled: true,
@@ -116,6 +151,45 @@ fn add_admin_portal_to_configuration(configuration: &mut Configuration) {
         extra_headers: vec![],
         access_log_enabled: true,
         access_log_file: "./logs/admin-portal-access.log".to_string(),
+        debug_capture_enabled: false,
+        api_mode_enabled: false,
+        max_body_size: 0,
+        upload_progress_enabled: false,
+        path_confinement_enabled: false,
+        path_confinement_allowed_paths: vec![],
+        cache_policies: vec![],
+        language_negotiation_enabled: false,
+        default_language: "".to_string(),
+        index_files: vec![],
+        try_files: vec![],
+        spa_fallback_enabled: false,
+        spa_fallback_excluded_paths: vec![],
+        php_error_log_file: "".to_string(),
+        access_log_tls_fingerprint_enabled: false,
+        access_log_timings_enabled: false,
+        middlewares: get_default_middlewares(),
+        extra_environment: vec![],
+        build_command: "".to_string(),
+        build_source_directory: "".to_string(),
+        build_output_directory: "".to_string(),
+        build_timeout_seconds: 600,
+        deploy_webhook_token: "".to_string(),
+        deploy_git_repository: "".to_string(),
+        deploy_git_branch: "main".to_string(),
+        deploy_git_webhook_secret: "".to_string(),
+        traffic_split: TrafficSplit::default(),
+        header_routes: Vec::new(),
+        cookie_routes: Vec::new(),
+        body_substitutions: Vec::new(),
+        early_hints_enabled: false,
+        early_hints: Vec::new(),
+        minification_enabled: false,
+        preload_links_enabled: false,
+        robots_txt_enabled: false,
+        sitemap_enabled: false,
+        trailing_slash_redirect: get_default_trailing_slash_redirect(),
+        lowercase_redirect_enabled: false,
+        config_file: "".to_string(),
     };
 
     // Admin site
@@ -149,6 +223,7 @@ pub fn fetch_configuration_in_db() -> Result<Configuration, String> {
     let static_file_processors = load_static_file_processors(&connection)?;
     let php_processors = load_php_processors(&connection)?;
     let proxy_processors = load_proxy_processors(&connection)?;
+    let upstreams = load_upstreams(&connection)?;
 
     // External systems
     let php_cgi_handlers = load_php_cgi_handlers(&connection)?;
@@ -164,8 +239,13 @@ pub fn fetch_configuration_in_db() -> Result<Configuration, String> {
         static_file_processors,
         php_processors,
         proxy_processors,
+        upstreams,
         php_cgi_handlers: php_cgi_handlers,
     };
+
+    // Sites kept in their own files are part of the configuration as well
+    load_included_sites(&mut configuration)?;
+
     configuration.sanitize();
 
     Ok(configuration)
@@ -190,6 +270,14 @@ fn load_proxy_processors(connection: &Connection) -> Result<Vec<ProxyProcessor>,
         let preserve_host_header_int: i64 = statement.read(9).map_err(|e| format!("Failed to read preserve_host_header: {}", e))?;
         let forced_host_header: String = statement.read(10).map_err(|e| format!("Failed to read forced_host_header: {}", e))?;
         let verify_tls_certificates_int: i64 = statement.read(11).map_err(|e| format!("Failed to read verify_tls_certificates: {}", e))?;
+        let session_affinity: String = statement.read(12).map_err(|e| format!("Failed to read session_affinity: {}", e))?;
+        let session_affinity_cookie_name: String = statement.read(13).map_err(|e| format!("Failed to read session_affinity_cookie_name: {}", e))?;
+        let upstream_id: String = statement.read(14).map_err(|e| format!("Failed to read upstream_id: {}", e))?;
+        let cache_enabled_int: i64 = statement.read(15).map_err(|e| format!("Failed to read cache_enabled: {}", e))?;
+        let cache_max_size_mb: i64 = statement.read(16).map_err(|e| format!("Failed to read cache_max_size_mb: {}", e))?;
+        let tls_settings_str: String = statement.read(17).map_err(|e| format!("Failed to read tls_settings: {}", e))?;
+        let max_retries: i64 = statement.read(18).map_err(|e| format!("Failed to read max_retries: {}", e))?;
+        let retry_budget_seconds: i64 = statement.read(19).map_err(|e| format!("Failed to read retry_budget_seconds: {}", e))?;
 
         // Upstream servers is stored as comma separated
         let upstream_servers = parse_comma_separated_list(&upstream_servers_str, true);
@@ -197,6 +285,9 @@ fn load_proxy_processors(connection: &Connection) -> Result<Vec<ProxyProcessor>,
         // Url rewrites is stored as JSON array
         let url_rewrites: Vec<ProxyProcessorRewrite> = serde_json::from_str(&url_rewrites_str).map_err(|e| format!("Failed to parse url_rewrites JSON: {}", e))?;
 
+        // TLS settings are stored as JSON object
+        let tls_settings: UpstreamTlsSettings = serde_json::from_str(&tls_settings_str).map_err(|e| format!("Failed to parse tls_settings JSON: {}", e))?;
+
         let mut new_processor = ProxyProcessor::new();
         new_processor.id = processor_id;
         new_processor.proxy_type = proxy_type;
@@ -210,6 +301,14 @@ fn load_proxy_processors(connection: &Connection) -> Result<Vec<ProxyProcessor>,
         new_processor.preserve_host_header = preserve_host_header_int != 0;
         new_processor.forced_host_header = forced_host_header;
         new_processor.verify_tls_certificates = verify_tls_certificates_int != 0;
+        new_processor.session_affinity = session_affinity;
+        new_processor.session_affinity_cookie_name = session_affinity_cookie_name;
+        new_processor.upstream_id = upstream_id;
+        new_processor.cache_enabled = cache_enabled_int != 0;
+        new_processor.cache_max_size_mb = cache_max_size_mb as u32;
+        new_processor.tls_settings = tls_settings;
+        new_processor.max_retries = max_retries as u32;
+        new_processor.retry_budget_seconds = retry_budget_seconds as u32;
 
         new_processor.initialize();
         processors.push(new_processor);
@@ -217,6 +316,33 @@ fn load_proxy_processors(connection: &Connection) -> Result<Vec<ProxyProcessor>,
     Ok(processors)
 }
 
+fn load_upstreams(connection: &Connection) -> Result<Vec<Upstream>, String> {
+    let mut statement = connection.prepare("SELECT * FROM upstreams").map_err(|e| format!("Failed to prepare upstreams query: {}", e))?;
+
+    let mut upstreams = Vec::new();
+    while let sqlite::State::Row = statement.next().map_err(|e| format!("Failed to execute upstreams query: {}", e))? {
+        let upstream_id: String = statement.read(0).map_err(|e| format!("Failed to read upstream id: {}", e))?;
+        let name: String = statement.read(1).map_err(|e| format!("Failed to read name: {}", e))?;
+        let servers_str: String = statement.read(2).map_err(|e| format!("Failed to read servers: {}", e))?;
+        let health_check_path: String = statement.read(3).map_err(|e| format!("Failed to read health_check_path: {}", e))?;
+        let health_check_interval_seconds: i64 = statement.read(4).map_err(|e| format!("Failed to read health_check_interval_seconds: {}", e))?;
+        let health_check_timeout_seconds: i64 = statement.read(5).map_err(|e| format!("Failed to read health_check_timeout_seconds: {}", e))?;
+
+        // Servers are stored as JSON array, with their weights
+        let servers: Vec<UpstreamServer> = serde_json::from_str(&servers_str).map_err(|e| format!("Failed to parse upstream servers JSON: {}", e))?;
+
+        upstreams.push(Upstream {
+            id: upstream_id,
+            name,
+            servers,
+            health_check_path,
+            health_check_interval_seconds: health_check_interval_seconds as u32,
+            health_check_timeout_seconds: health_check_timeout_seconds as u32,
+        });
+    }
+    Ok(upstreams)
+}
+
 fn load_php_processors(connection: &Connection) -> Result<Vec<php_processor::PHPProcessor>, String> {
     let mut statement = connection
         .prepare("SELECT * FROM php_processors")
@@ -232,6 +358,14 @@ fn load_php_processors(connection: &Connection) -> Result<Vec<php_processor::PHP
         let local_web_root: String = statement.read(5).map_err(|e| format!("Failed to read local_web_root: {}", e))?;
         let fastcgi_web_root: String = statement.read(6).map_err(|e| format!("Failed to read fastcgi_web_root: {}", e))?;
         let server_software_spoof: String = statement.read(7).map_err(|e| format!("Failed to read server_software_spoof: {}", e))?;
+        let cache_enabled_int: i64 = statement.read(8).map_err(|e| format!("Failed to read cache_enabled: {}", e))?;
+        let cache_max_size_mb: i64 = statement.read(9).map_err(|e| format!("Failed to read cache_max_size_mb: {}", e))?;
+        let fastcgi_tls_enabled_int: i64 = statement.read(10).map_err(|e| format!("Failed to read fastcgi_tls_enabled: {}", e))?;
+        let fastcgi_verify_tls_certificates_int: i64 = statement.read(11).map_err(|e| format!("Failed to read fastcgi_verify_tls_certificates: {}", e))?;
+        let fastcgi_tls_settings_str: String = statement.read(12).map_err(|e| format!("Failed to read fastcgi_tls_settings: {}", e))?;
+
+        // TLS settings are stored as JSON object
+        let fastcgi_tls_settings: UpstreamTlsSettings = serde_json::from_str(&fastcgi_tls_settings_str).map_err(|e| format!("Failed to parse fastcgi_tls_settings JSON: {}", e))?;
 
         let mut new_processor = PHPProcessor::new();
         new_processor.id = processor_id;
@@ -242,6 +376,11 @@ fn load_php_processors(connection: &Connection) -> Result<Vec<php_processor::PHP
         new_processor.local_web_root = local_web_root;
         new_processor.fastcgi_web_root = fastcgi_web_root;
         new_processor.server_software_spoof = server_software_spoof;
+        new_processor.cache_enabled = cache_enabled_int != 0;
+        new_processor.cache_max_size_mb = cache_max_size_mb as u32;
+        new_processor.fastcgi_tls_enabled = fastcgi_tls_enabled_int != 0;
+        new_processor.fastcgi_verify_tls_certificates = fastcgi_verify_tls_certificates_int != 0;
+        new_processor.fastcgi_tls_settings = fastcgi_tls_settings;
 
         new_processor.initialize();
         processors.push(new_processor);
@@ -263,7 +402,12 @@ fn load_php_cgi_handlers(connection: &Connection) -> Result<Vec<php_cgi::PhpCgi>
         let concurrent_threads: i64 = statement.read(3).map_err(|e| format!("Failed to read concurrent_threads: {}", e))?;
         let executable: String = statement.read(4).map_err(|e| format!("Failed to read executable: {}", e))?;
 
-        handlers.push(php_cgi::PhpCgi::new(handler_id, name, request_timeout as u32, concurrent_threads as u32, executable));
+        // Environment variables (added in schema version 22)
+        let extra_environment_str: String = statement.read(5).map_err(|e| format!("Failed to read extra_environment: {}", e))?;
+
+        let mut handler = php_cgi::PhpCgi::new(handler_id, name, request_timeout as u32, concurrent_threads as u32, executable);
+        handler.extra_environment = parse_environment_variables(&extra_environment_str)?;
+        handlers.push(handler);
     }
 
     Ok(handlers)
@@ -322,8 +466,125 @@ fn load_core_config(connection: &Connection) -> Result<Core, String> {
             "blocked_file_patterns" => {
                 core.server_settings.blocked_file_patterns = parse_comma_separated_list(&value, true);
             }
+            "include" => {
+                core.server_settings.include = parse_comma_separated_list(&value, false);
+            }
+            "request_validation_level" => {
+                core.server_settings.request_validation_level = value;
+            }
+            "server_header" => {
+                core.server_settings.server_header = value;
+            }
+            "max_open_file_handles" => {
+                core.server_settings.max_open_file_handles = value.parse::<usize>().map_err(|e| format!("Failed to parse max_open_file_handles: {}", e))?;
+            }
+            "http2_max_resets_per_second" => {
+                core.server_settings.http2_max_resets_per_second = value.parse::<u32>().map_err(|e| format!("Failed to parse http2_max_resets_per_second: {}", e))?;
+            }
+            "http2_max_settings_per_second" => {
+                core.server_settings.http2_max_settings_per_second = value.parse::<u32>().map_err(|e| format!("Failed to parse http2_max_settings_per_second: {}", e))?;
+            }
+            "http2_max_pings_per_second" => {
+                core.server_settings.http2_max_pings_per_second = value.parse::<u32>().map_err(|e| format!("Failed to parse http2_max_pings_per_second: {}", e))?;
+            }
+            "http2_max_continuation_frames" => {
+                core.server_settings.http2_max_continuation_frames = value.parse::<u32>().map_err(|e| format!("Failed to parse http2_max_continuation_frames: {}", e))?;
+            }
+            "http2_flood_ban_seconds" => {
+                core.server_settings.http2_flood_ban_seconds = value.parse::<u64>().map_err(|e| format!("Failed to parse http2_flood_ban_seconds: {}", e))?;
+            }
+            "managed_port_range_start" => {
+                core.server_settings.managed_port_range_start = value.parse::<u16>().map_err(|e| format!("Failed to parse managed_port_range_start: {}", e))?;
+            }
+            "managed_port_range_end" => {
+                core.server_settings.managed_port_range_end = value.parse::<u16>().map_err(|e| format!("Failed to parse managed_port_range_end: {}", e))?;
+            }
+            "trusted_proxy_ips" => {
+                core.server_settings.trusted_proxy_ips = parse_comma_separated_list(&value, false);
+            }
+            "managed_port_exclusions" => {
+                core.server_settings.managed_port_exclusions = parse_comma_separated_list(&value, false);
+            }
+            "dns_cache_ttl_seconds" => {
+                core.server_settings.dns_cache_ttl_seconds = value.parse::<u64>().map_err(|e| format!("Failed to parse dns_cache_ttl_seconds: {}", e))?;
+            }
+            "outbound_connect_timeout_seconds" => {
+                core.server_settings.outbound_connect_timeout_seconds = value.parse::<u64>().map_err(|e| format!("Failed to parse outbound_connect_timeout_seconds: {}", e))?;
+            }
+            "outbound_read_timeout_seconds" => {
+                core.server_settings.outbound_read_timeout_seconds = value.parse::<u64>().map_err(|e| format!("Failed to parse outbound_read_timeout_seconds: {}", e))?;
+            }
+            "outbound_write_timeout_seconds" => {
+                core.server_settings.outbound_write_timeout_seconds = value.parse::<u64>().map_err(|e| format!("Failed to parse outbound_write_timeout_seconds: {}", e))?;
+            }
+            "upstream_pool_max_idle_per_host" => {
+                core.server_settings.upstream_pool_max_idle_per_host = value.parse::<usize>().map_err(|e| format!("Failed to parse upstream_pool_max_idle_per_host: {}", e))?;
+            }
+            "upstream_pool_idle_timeout_seconds" => {
+                core.server_settings.upstream_pool_idle_timeout_seconds = value.parse::<u64>().map_err(|e| format!("Failed to parse upstream_pool_idle_timeout_seconds: {}", e))?;
+            }
+            "panic_storm_max_panics_per_minute" => {
+                core.server_settings.panic_storm_max_panics_per_minute = value.parse::<u64>().map_err(|e| format!("Failed to parse panic_storm_max_panics_per_minute: {}", e))?;
+            }
+            "panic_storm_maintenance_seconds" => {
+                core.server_settings.panic_storm_maintenance_seconds = value.parse::<u64>().map_err(|e| format!("Failed to parse panic_storm_maintenance_seconds: {}", e))?;
+            }
+            "memory_budget_bytes" => {
+                core.server_settings.memory_budget_bytes = value.parse::<u64>().map_err(|e| format!("Failed to parse memory_budget_bytes: {}", e))?;
+            }
+            "disk_cache_max_size_bytes" => {
+                core.server_settings.disk_cache_max_size_bytes = value.parse::<u64>().map_err(|e| format!("Failed to parse disk_cache_max_size_bytes: {}", e))?;
+            }
+            "disk_cache_max_unused_hours" => {
+                core.server_settings.disk_cache_max_unused_hours = value.parse::<u64>().map_err(|e| format!("Failed to parse disk_cache_max_unused_hours: {}", e))?;
+            }
+            "event_webhooks" => {
+                if !value.is_empty() {
+                    core.server_settings.event_webhooks = serde_json::from_str(&value).map_err(|e| format!("Failed to parse event_webhooks JSON: {}", e))?;
+                }
+            }
+            "remote_syslog_address" => {
+                core.server_settings.remote_syslog_address = value;
+            }
+            "remote_syslog_level" => {
+                core.server_settings.remote_syslog_level = value;
+            }
+            "windows_event_log_level" => {
+                core.server_settings.windows_event_log_level = value;
+            }
+            "log_level" => {
+                core.server_settings.log_level = value;
+            }
+            "health_check_path" => {
+                core.server_settings.health_check_path = value;
+            }
+            "deregistration_delay_seconds" => {
+                core.server_settings.deregistration_delay_seconds = value.parse::<u64>().map_err(|e| format!("Failed to parse deregistration_delay_seconds: {}", e))?;
+            }
+            "log_sample_rate" => {
+                core.server_settings.log_sample_rate = value.parse::<u64>().map_err(|e| format!("Failed to parse log_sample_rate: {}", e))?;
+            }
+            "log_max_records_per_second" => {
+                core.server_settings.log_max_records_per_second = value.parse::<u64>().map_err(|e| format!("Failed to parse log_max_records_per_second: {}", e))?;
+            }
+            "log_module_levels" => {
+                if !value.is_empty() {
+                    core.server_settings.log_module_levels = serde_json::from_str(&value).map_err(|e| format!("Failed to parse log_module_levels JSON: {}", e))?;
+                }
+            }
+            "smtp" => {
+                if !value.is_empty() {
+                    core.server_settings.smtp = serde_json::from_str(&value).map_err(|e| format!("Failed to parse smtp JSON: {}", e))?;
+                }
+            }
 
             // Admin portal settings
+            "admin_portal_is_enabled" => {
+                core.admin_portal.is_enabled = value.parse::<bool>().map_err(|e| format!("Failed to parse admin_portal_is_enabled: {}", e))?;
+            }
+            "admin_portal_port" => {
+                core.admin_portal.port = value.parse::<u16>().map_err(|e| format!("Failed to parse admin_portal_port: {}", e))?;
+            }
             "admin_portal_domain_name" => {
                 core.admin_portal.domain_name = value;
             }
@@ -336,6 +597,24 @@ fn load_core_config(connection: &Connection) -> Result<Core, String> {
             "admin_portal_tls_key_path" => {
                 core.admin_portal.tls_key_path = Some(value);
             }
+            "admin_portal_session_cookie_enabled" => {
+                core.admin_portal.session_cookie_enabled = value.parse::<bool>().map_err(|e| format!("Failed to parse admin_portal_session_cookie_enabled: {}", e))?;
+            }
+            "admin_portal_session_cookie_same_site" => {
+                core.admin_portal.session_cookie_same_site = value;
+            }
+            "admin_portal_session_idle_timeout_minutes" => {
+                core.admin_portal.session_idle_timeout_minutes = value.parse::<u64>().map_err(|e| format!("Failed to parse admin_portal_session_idle_timeout_minutes: {}", e))?;
+            }
+            "admin_portal_session_absolute_timeout_hours" => {
+                core.admin_portal.session_absolute_timeout_hours = value.parse::<u64>().map_err(|e| format!("Failed to parse admin_portal_session_absolute_timeout_hours: {}", e))?;
+            }
+            "admin_portal_session_max_per_user" => {
+                core.admin_portal.session_max_per_user = value.parse::<u32>().map_err(|e| format!("Failed to parse admin_portal_session_max_per_user: {}", e))?;
+            }
+            "admin_portal_session_token_bytes" => {
+                core.admin_portal.session_token_bytes = value.parse::<u32>().map_err(|e| format!("Failed to parse admin_portal_session_token_bytes: {}", e))?;
+            }
 
             // TLS settings
             "tls_account_email" => {
@@ -347,6 +626,9 @@ fn load_core_config(connection: &Connection) -> Result<Core, String> {
             "tls_certificate_cache_path" => {
                 core.tls_settings.certificate_cache_path = value;
             }
+            "tls_acme_challenge_directory" => {
+                core.tls_settings.acme_challenge_directory = value;
+            }
             _ => continue,
         }
     }
@@ -364,6 +646,26 @@ fn load_bindings(connection: &Connection) -> Result<Vec<Binding>, String> {
         let port: i64 = statement.read(2).map_err(|e| format!("Failed to read port: {}", e))?;
         let is_admin: i64 = statement.read(3).map_err(|e| format!("Failed to read is_admin: {}", e))?;
         let is_tls: i64 = statement.read(4).map_err(|e| format!("Failed to read is_tls: {}", e))?;
+        // Keep-alive and pipelining limits (added in schema version 8)
+        let keep_alive_timeout_seconds: i64 = statement.read(5).map_err(|e| format!("Failed to read keep_alive_timeout_seconds: {}", e))?;
+        let max_requests_per_connection: i64 = statement.read(6).map_err(|e| format!("Failed to read max_requests_per_connection: {}", e))?;
+        let pipelining_enabled: i64 = statement.read(7).map_err(|e| format!("Failed to read pipelining_enabled: {}", e))?;
+        // HTTP/2 limits (added in schema version 19)
+        let h2_max_concurrent_streams: i64 = statement.read(8).map_err(|e| format!("Failed to read h2_max_concurrent_streams: {}", e))?;
+        let h2_initial_stream_window_size: i64 = statement.read(9).map_err(|e| format!("Failed to read h2_initial_stream_window_size: {}", e))?;
+        let h2_initial_connection_window_size: i64 = statement.read(10).map_err(|e| format!("Failed to read h2_initial_connection_window_size: {}", e))?;
+        let h2_max_header_list_size: i64 = statement.read(11).map_err(|e| format!("Failed to read h2_max_header_list_size: {}", e))?;
+        let h2_max_connection_memory: i64 = statement.read(12).map_err(|e| format!("Failed to read h2_max_connection_memory: {}", e))?;
+        let h2_max_pending_reset_streams: i64 = statement.read(13).map_err(|e| format!("Failed to read h2_max_pending_reset_streams: {}", e))?;
+        let h2_max_local_error_reset_streams: i64 = statement.read(14).map_err(|e| format!("Failed to read h2_max_local_error_reset_streams: {}", e))?;
+        // Protocol selection (added in schema version 28)
+        let protocols: String = statement.read(15).map_err(|e| format!("Failed to read protocols: {}", e))?;
+        let h2c_enabled: i64 = statement.read(16).map_err(|e| format!("Failed to read h2c_enabled: {}", e))?;
+        let absolute_form_enabled: i64 = statement.read(17).map_err(|e| format!("Failed to read absolute_form_enabled: {}", e))?;
+        // Status page (added in schema version 40)
+        let status_page_enabled: i64 = statement.read(18).map_err(|e| format!("Failed to read status_page_enabled: {}", e))?;
+        let status_page_allowed_ips_str: String = statement.read(19).map_err(|e| format!("Failed to read status_page_allowed_ips: {}", e))?;
+        let status_page_allowed_ips: Vec<String> = parse_comma_separated_list(&status_page_allowed_ips_str, false);
 
         bindings.push(Binding {
             id: binding_id,
@@ -371,6 +673,23 @@ fn load_bindings(connection: &Connection) -> Result<Vec<Binding>, String> {
             port: port as u16,
             is_admin: is_admin != 0,
             is_tls: is_tls != 0,
+            keep_alive_timeout_seconds: keep_alive_timeout_seconds as u64,
+            max_requests_per_connection: max_requests_per_connection as u64,
+            pipelining_enabled: pipelining_enabled != 0,
+            http2: Http2Limits {
+                max_concurrent_streams: h2_max_concurrent_streams as u32,
+                initial_stream_window_size: h2_initial_stream_window_size as u32,
+                initial_connection_window_size: h2_initial_connection_window_size as u32,
+                max_header_list_size: h2_max_header_list_size as u32,
+                max_connection_memory: h2_max_connection_memory as u64,
+                max_pending_reset_streams: h2_max_pending_reset_streams as u32,
+                max_local_error_reset_streams: h2_max_local_error_reset_streams as u32,
+            },
+            protocols,
+            h2c_enabled: h2c_enabled != 0,
+            absolute_form_enabled: absolute_form_enabled != 0,
+            status_page_enabled: status_page_enabled != 0,
+            status_page_allowed_ips,
         });
     }
 
@@ -415,6 +734,134 @@ fn load_sites(connection: &Connection) -> Result<Vec<Site>, String> {
         // TLS Automatic Enabled (added in schema version 4)
         let tls_automatic_enabled: i64 = statement.read(13).map_err(|e| format!("Failed to read tls_automatic_enabled: {}", e))?;
 
+        // Debug capture (added in schema version 5)
+        let debug_capture_enabled: i64 = statement.read(14).map_err(|e| format!("Failed to read debug_capture_enabled: {}", e))?;
+
+        // API mode (added in schema version 6)
+        let api_mode_enabled: i64 = statement.read(15).map_err(|e| format!("Failed to read api_mode_enabled: {}", e))?;
+
+        // Max body size (added in schema version 9)
+        let max_body_size: i64 = statement.read(16).map_err(|e| format!("Failed to read max_body_size: {}", e))?;
+
+        // Path confinement (added in schema version 10), allowed paths are comma separated
+        let path_confinement_enabled: i64 = statement.read(17).map_err(|e| format!("Failed to read path_confinement_enabled: {}", e))?;
+        let path_confinement_allowed_paths_str: String = statement.read(18).map_err(|e| format!("Failed to read path_confinement_allowed_paths: {}", e))?;
+        let path_confinement_allowed_paths: Vec<String> = parse_comma_separated_list(&path_confinement_allowed_paths_str, false);
+
+        // Cache policies is stored as JSON (added in schema version 11)
+        let cache_policies_str: String = statement.read(19).map_err(|e| format!("Failed to read cache_policies: {}", e))?;
+        let cache_policies: Vec<CachePolicy> = if cache_policies_str.is_empty() {
+            Vec::new()
+        } else {
+            serde_json::from_str(&cache_policies_str).map_err(|e| format!("Failed to parse cache_policies JSON: {}", e))?
+        };
+
+        // Language negotiation (added in schema version 12)
+        let language_negotiation_enabled: i64 = statement.read(20).map_err(|e| format!("Failed to read language_negotiation_enabled: {}", e))?;
+        let default_language: String = statement.read(21).map_err(|e| format!("Failed to read default_language: {}", e))?;
+
+        // Index files is comma separated (added in schema version 13)
+        let index_files_str: String = statement.read(22).map_err(|e| format!("Failed to read index_files: {}", e))?;
+        let index_files: Vec<String> = parse_comma_separated_list(&index_files_str, false);
+
+        // Try files is comma separated (added in schema version 14)
+        let try_files_str: String = statement.read(23).map_err(|e| format!("Failed to read try_files: {}", e))?;
+        let try_files: Vec<String> = parse_comma_separated_list(&try_files_str, false);
+
+        // SPA fallback, excluded paths are comma separated (added in schema version 15)
+        let spa_fallback_enabled: i64 = statement.read(24).map_err(|e| format!("Failed to read spa_fallback_enabled: {}", e))?;
+        let spa_fallback_excluded_paths_str: String = statement.read(25).map_err(|e| format!("Failed to read spa_fallback_excluded_paths: {}", e))?;
+        let spa_fallback_excluded_paths: Vec<String> = parse_comma_separated_list(&spa_fallback_excluded_paths_str, false);
+
+        // PHP error log file (added in schema version 16)
+        let php_error_log_file: String = statement.read(26).map_err(|e| format!("Failed to read php_error_log_file: {}", e))?;
+
+        // TLS fingerprints in access logs (added in schema version 18)
+        let access_log_tls_fingerprint_enabled: i64 = statement.read(27).map_err(|e| format!("Failed to read access_log_tls_fingerprint_enabled: {}", e))?;
+
+        // Middleware chain (added in schema version 21)
+        let middlewares_str: String = statement.read(28).map_err(|e| format!("Failed to read middlewares: {}", e))?;
+        let middlewares: Vec<String> = parse_comma_separated_list(&middlewares_str, false);
+
+        // Environment variables (added in schema version 22)
+        let extra_environment_str: String = statement.read(29).map_err(|e| format!("Failed to read extra_environment: {}", e))?;
+        let extra_environment = parse_environment_variables(&extra_environment_str)?;
+
+        // Build before serve (added in schema version 23)
+        let build_command: String = statement.read(30).map_err(|e| format!("Failed to read build_command: {}", e))?;
+        let build_source_directory: String = statement.read(31).map_err(|e| format!("Failed to read build_source_directory: {}", e))?;
+        let build_output_directory: String = statement.read(32).map_err(|e| format!("Failed to read build_output_directory: {}", e))?;
+        let build_timeout_seconds: i64 = statement.read(33).map_err(|e| format!("Failed to read build_timeout_seconds: {}", e))?;
+        let deploy_webhook_token: String = statement.read(34).map_err(|e| format!("Failed to read deploy_webhook_token: {}", e))?;
+
+        // Git deployment (added in schema version 24)
+        let deploy_git_repository: String = statement.read(35).map_err(|e| format!("Failed to read deploy_git_repository: {}", e))?;
+        let deploy_git_branch: String = statement.read(36).map_err(|e| format!("Failed to read deploy_git_branch: {}", e))?;
+        let deploy_git_webhook_secret: String = statement.read(37).map_err(|e| format!("Failed to read deploy_git_webhook_secret: {}", e))?;
+
+        // Request phase timings in access logs (added in schema version 25)
+        let access_log_timings_enabled: i64 = statement.read(38).map_err(|e| format!("Failed to read access_log_timings_enabled: {}", e))?;
+
+        // Traffic split is stored as JSON (added in schema version 29)
+        let traffic_split_str: String = statement.read(39).map_err(|e| format!("Failed to read traffic_split: {}", e))?;
+        let traffic_split: TrafficSplit = if traffic_split_str.is_empty() {
+            TrafficSplit::default()
+        } else {
+            serde_json::from_str(&traffic_split_str).map_err(|e| format!("Failed to parse traffic_split JSON: {}", e))?
+        };
+
+        // Header routes are stored as JSON (added in schema version 32)
+        let header_routes_str: String = statement.read(40).map_err(|e| format!("Failed to read header_routes: {}", e))?;
+        let header_routes: Vec<HeaderRoute> = if header_routes_str.is_empty() {
+            Vec::new()
+        } else {
+            serde_json::from_str(&header_routes_str).map_err(|e| format!("Failed to parse header_routes JSON: {}", e))?
+        };
+
+        // Cookie routes are stored as JSON (added in schema version 33)
+        let cookie_routes_str: String = statement.read(41).map_err(|e| format!("Failed to read cookie_routes: {}", e))?;
+        let cookie_routes: Vec<CookieRoute> = if cookie_routes_str.is_empty() {
+            Vec::new()
+        } else {
+            serde_json::from_str(&cookie_routes_str).map_err(|e| format!("Failed to parse cookie_routes JSON: {}", e))?
+        };
+
+        // Body substitutions are stored as JSON (added in schema version 34)
+        let body_substitutions_str: String = statement.read(42).map_err(|e| format!("Failed to read body_substitutions: {}", e))?;
+        let body_substitutions: Vec<BodySubstitution> = if body_substitutions_str.is_empty() {
+            Vec::new()
+        } else {
+            serde_json::from_str(&body_substitutions_str).map_err(|e| format!("Failed to parse body_substitutions JSON: {}", e))?
+        };
+
+        // Minification of static files (added in schema version 35)
+        let minification_enabled: i64 = statement.read(43).map_err(|e| format!("Failed to read minification_enabled: {}", e))?;
+
+        // Generated robots.txt and sitemap.xml (added in schema version 36)
+        let robots_txt_enabled: i64 = statement.read(44).map_err(|e| format!("Failed to read robots_txt_enabled: {}", e))?;
+        let sitemap_enabled: i64 = statement.read(45).map_err(|e| format!("Failed to read sitemap_enabled: {}", e))?;
+
+        // Canonical redirects (added in schema version 39)
+        let trailing_slash_redirect: String = statement.read(46).map_err(|e| format!("Failed to read trailing_slash_redirect: {}", e))?;
+        let lowercase_redirect_enabled: i64 = statement.read(47).map_err(|e| format!("Failed to read lowercase_redirect_enabled: {}", e))?;
+
+        // Early hints are stored as JSON (added in schema version 43)
+        let early_hints_str: String = statement.read(48).map_err(|e| format!("Failed to read early_hints: {}", e))?;
+        let early_hints: Vec<String> = if early_hints_str.is_empty() {
+            Vec::new()
+        } else {
+            serde_json::from_str(&early_hints_str).map_err(|e| format!("Failed to parse early_hints JSON: {}", e))?
+        };
+
+        // Link preload headers from HTML pages (added in schema version 44)
+        let preload_links_enabled: i64 = statement.read(49).map_err(|e| format!("Failed to read preload_links_enabled: {}", e))?;
+
+        // Upload progress polling (added in schema version 45)
+        let upload_progress_enabled: i64 = statement.read(50).map_err(|e| format!("Failed to read upload_progress_enabled: {}", e))?;
+
+        // Early hints switch (added in schema version 46)
+        let early_hints_enabled: i64 = statement.read(51).map_err(|e| format!("Failed to read early_hints_enabled: {}", e))?;
+
         sites.push(Site {
             id: site_id,
             hostnames,
@@ -430,6 +877,45 @@ fn load_sites(connection: &Connection) -> Result<Vec<Site>, String> {
             access_log_enabled: access_log_enabled != 0,
             access_log_file,
             extra_headers,
+            debug_capture_enabled: debug_capture_enabled != 0,
+            api_mode_enabled: api_mode_enabled != 0,
+            max_body_size: max_body_size.max(0) as u64,
+            upload_progress_enabled: upload_progress_enabled != 0,
+            path_confinement_enabled: path_confinement_enabled != 0,
+            path_confinement_allowed_paths,
+            cache_policies,
+            language_negotiation_enabled: language_negotiation_enabled != 0,
+            default_language,
+            index_files,
+            try_files,
+            spa_fallback_enabled: spa_fallback_enabled != 0,
+            spa_fallback_excluded_paths,
+            php_error_log_file,
+            access_log_tls_fingerprint_enabled: access_log_tls_fingerprint_enabled != 0,
+            access_log_timings_enabled: access_log_timings_enabled != 0,
+            middlewares,
+            extra_environment,
+            build_command,
+            build_source_directory,
+            build_output_directory,
+            build_timeout_seconds: build_timeout_seconds.max(0) as u64,
+            deploy_webhook_token,
+            deploy_git_repository,
+            deploy_git_branch,
+            deploy_git_webhook_secret,
+            traffic_split,
+            header_routes,
+            cookie_routes,
+            body_substitutions,
+            early_hints_enabled: early_hints_enabled != 0,
+            early_hints,
+            minification_enabled: minification_enabled != 0,
+            preload_links_enabled: preload_links_enabled != 0,
+            robots_txt_enabled: robots_txt_enabled != 0,
+            sitemap_enabled: sitemap_enabled != 0,
+            trailing_slash_redirect,
+            lowercase_redirect_enabled: lowercase_redirect_enabled != 0,
+            config_file: String::new(),
         });
     }
 
@@ -457,7 +943,7 @@ fn load_binding_sites_relationships(connection: &Connection) -> Result<Vec<Bindi
 fn load_request_handlers(connection: &Connection) -> Result<Vec<RequestHandler>, String> {
     let mut statement = connection
         // Select explicit columns to remain compatible with older schemas that may still have a legacy 'priority' column.
-        .prepare("SELECT id, is_enabled, name, processor_type, processor_id, url_match FROM request_handler")
+        .prepare("SELECT id, is_enabled, name, processor_type, processor_id, url_match, allowed_methods FROM request_handler")
         .map_err(|e| format!("Failed to prepare request handlers query: {}", e))?;
 
     let mut request_handlers = Vec::new();
@@ -468,9 +954,12 @@ fn load_request_handlers(connection: &Connection) -> Result<Vec<RequestHandler>,
         let processor_type: String = statement.read(3).map_err(|e| format!("Failed to read processor_type: {}", e))?;
         let processor_id: String = statement.read(4).map_err(|e| format!("Failed to read processor_id: {}", e))?;
         let url_match_str: Option<String> = statement.read(5).ok();
+        // Allowed methods (added in schema version 7)
+        let allowed_methods_str: Option<String> = statement.read(6).ok();
 
         // Parse comma-separated strings
         let url_match = parse_comma_separated_list(&url_match_str.unwrap_or_default(), false);
+        let allowed_methods = parse_comma_separated_list(&allowed_methods_str.unwrap_or_default(), false);
 
         request_handlers.push(RequestHandler {
             id: handler_id,
@@ -479,6 +968,7 @@ fn load_request_handlers(connection: &Connection) -> Result<Vec<RequestHandler>,
             processor_type,
             processor_id,
             url_match,
+            allowed_methods,
         });
     }
 
@@ -520,6 +1010,14 @@ fn parse_comma_separated_list(input: &str, to_lowercase: bool) -> Vec<String> {
     }
 }
 
+// Environment variables are kept as JSON, as their values may contain any character
+fn parse_environment_variables(input: &str) -> Result<Vec<EnvironmentVariable>, String> {
+    if input.is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_str(input).map_err(|e| format!("Failed to parse extra_environment JSON: {}", e))
+}
+
 fn parse_key_value_pairs(input: &str) -> Vec<(String, String)> {
     if input.is_empty() {
         Vec::new()
@@ -1,15 +1,22 @@
-pub mod site;
+pub mod admin_portal;
 pub mod binding;
-pub mod configuration;
 pub mod binding_site_relation;
-pub mod request_handler;
+pub mod cached_configuration;
+pub mod configuration;
+pub mod configuration_changes;
+pub mod configuration_check;
+pub mod configuration_include;
+pub mod configuration_migration;
+pub mod configuration_schema;
+pub mod core;
 pub mod file_cache;
 pub mod gzip;
-pub mod server_settings;
-pub mod core;
+pub mod import_export;
 pub mod load_configuration;
+pub mod request_handler;
 pub mod save_configuration;
-pub mod cached_configuration;
-pub mod import_export;
-pub mod admin_portal;
+pub mod server_settings;
+pub mod site;
 pub mod tls_settings;
+pub mod upstream;
+pub mod upstream_tls;
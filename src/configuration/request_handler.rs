@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
@@ -6,13 +7,14 @@ use crate::{
     core::running_state_manager::get_running_state_manager,
     error::{gruxi_error::GruxiError, gruxi_error_enums::*},
     http::{
-        request_handlers::processor_trait::ProcessorTrait,
+        http_util::{SUPPORTED_HTTP_METHODS, error_response_with_details},
+        request_handlers::{processor_trait::ProcessorTrait, processors::processor_manager::ProcessorManager},
         request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse},
     },
     logging::syslog::trace,
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct RequestHandler {
     pub id: String,             // Generated uuid, unique, so it can be referenced from sites as a handler
     pub is_enabled: bool,       // Whether it is enabled or not
@@ -22,8 +24,18 @@ pub struct RequestHandler {
     pub processor_id: String, // The processor ID
     // Match patterns
     pub url_match: Vec<String>, // /api, /admin/1*, *.php etc (use * to match all URLs)
+    // HTTP methods accepted for php/proxy handlers, empty means the default list. Static handlers always allow GET and HEAD only
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
 }
 
+// Methods static handlers can serve
+pub const STATIC_ALLOWED_METHODS: [&str; 3] = ["GET", "HEAD", "OPTIONS"];
+// Methods php and proxy handlers accept when nothing is configured
+pub const DEFAULT_ALLOWED_METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+// The processor types a handler can use
+pub const PROCESSOR_TYPES: [&str; 3] = ["static", "php", "proxy"];
+
 impl RequestHandler {
     pub fn new() -> Self {
         Self {
@@ -33,7 +45,41 @@ impl RequestHandler {
             processor_type: "".to_string(),
             processor_id: String::new(),
             url_match: vec!["*".to_string()],
+            allowed_methods: Vec::new(),
+        }
+    }
+
+    // The methods this handler accepts, in the order they are listed in the Allow header
+    pub fn get_allowed_methods(&self) -> Vec<String> {
+        let mut methods: Vec<String> = if self.processor_type == "static" {
+            STATIC_ALLOWED_METHODS.iter().map(|m| m.to_string()).collect()
+        } else if self.allowed_methods.is_empty() {
+            DEFAULT_ALLOWED_METHODS.iter().map(|m| m.to_string()).collect()
+        } else {
+            self.allowed_methods.clone()
+        };
+
+        // HEAD is always possible where GET is, and OPTIONS is always answered, by us or the backend
+        if methods.iter().any(|m| m == "GET") && !methods.iter().any(|m| m == "HEAD") {
+            let get_position = methods.iter().position(|m| m == "GET").unwrap_or(0);
+            methods.insert(get_position + 1, "HEAD".to_string());
+        }
+        if !methods.iter().any(|m| m == "OPTIONS") {
+            methods.push("OPTIONS".to_string());
+        }
+        methods
+    }
+
+    pub fn is_method_allowed(&self, http_method: &str) -> bool {
+        self.get_allowed_methods().iter().any(|m| m == http_method)
+    }
+
+    // OPTIONS is passed on to php/proxy backends (such as for CORS preflight) only when explicitly allowed, otherwise we answer it ourselves
+    pub fn forwards_options_requests(&self) -> bool {
+        if self.processor_type == "static" {
+            return false;
         }
+        self.allowed_methods.is_empty() || self.allowed_methods.iter().any(|m| m == "OPTIONS")
     }
 
     // Check URL match, can be * or /path or /path* or .html or .php*
@@ -86,6 +132,15 @@ impl RequestHandler {
 
         // Clean url match patterns: trim, remove empty, ensure proper prefix
         self.url_match = self.url_match.iter().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+
+        // Methods are case-sensitive, but users will type them in any case, so we uppercase and remove duplicates
+        let mut allowed_methods: Vec<String> = Vec::new();
+        for method in self.allowed_methods.iter().map(|s| s.trim().to_uppercase()).filter(|s| !s.is_empty()) {
+            if !allowed_methods.contains(&method) {
+                allowed_methods.push(method);
+            }
+        }
+        self.allowed_methods = allowed_methods;
     }
 
     pub fn validate(&self) -> Result<(), Vec<String>> {
@@ -114,9 +169,25 @@ impl RequestHandler {
             }
         }
 
+        // Validate allowed methods, which must be methods Gruxi handles, as others never reach the request handlers
+        for method in &self.allowed_methods {
+            if !SUPPORTED_HTTP_METHODS.contains(&method.as_str()) {
+                errors.push(format!("Allowed method '{}' is not a valid HTTP method, use one of {}", method, SUPPORTED_HTTP_METHODS.join(", ")));
+            }
+        }
+
         if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 
+    // The local web root the handler serves files from, if its processor has one (proxies do not)
+    pub fn get_local_web_root(&self, processor_manager: &ProcessorManager) -> Option<String> {
+        match self.processor_type.as_str() {
+            "static" => processor_manager.get_static_file_processor_by_id(&self.processor_id)?.get_web_root(),
+            "php" => processor_manager.get_php_processor_by_id(&self.processor_id)?.get_local_web_root(),
+            _ => None,
+        }
+    }
+
     pub async fn handle_request(&self, gruxi_request: &mut GruxiRequest, site: &Site) -> Result<GruxiResponse, GruxiError> {
         let running_state = get_running_state_manager().await.get_running_state_unlocked().await;
         let processor_manager = running_state.get_processor_manager();
@@ -176,7 +247,7 @@ impl RequestHandler {
                 match err.kind {
                     // Static file errors that we want to convey directly
                     GruxiErrorKind::StaticFileProcessor(StaticFileProcessorError::PathError(_)) => {
-                        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16()));
+                        return Ok(error_response_with_details(hyper::StatusCode::INTERNAL_SERVER_ERROR, err, gruxi_request));
                     }
                     GruxiErrorKind::StaticFileProcessor(StaticFileProcessorError::FileBlockedDueToSecurity(_)) => {
                         return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16())); // We dont want to expose that it was blocked due to security
@@ -184,28 +255,40 @@ impl RequestHandler {
 
                     // Proxy errors that we want to convey directly
                     GruxiErrorKind::ProxyProcessor(ProxyProcessorError::UpstreamUnavailable) => {
-                        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::BAD_GATEWAY.as_u16()));
+                        return Ok(error_response_with_details(hyper::StatusCode::BAD_GATEWAY, err, gruxi_request));
                     }
                     GruxiErrorKind::ProxyProcessor(ProxyProcessorError::UpstreamTimeout) => {
-                        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::GATEWAY_TIMEOUT.as_u16()));
+                        return Ok(error_response_with_details(hyper::StatusCode::GATEWAY_TIMEOUT, err, gruxi_request));
                     }
                     GruxiErrorKind::ProxyProcessor(ProxyProcessorError::ConnectionFailed) => {
-                        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::BAD_GATEWAY.as_u16()));
+                        return Ok(error_response_with_details(hyper::StatusCode::BAD_GATEWAY, err, gruxi_request));
                     }
 
                     // PHP errors that we want to convey directly
                     GruxiErrorKind::PHPProcessor(PHPProcessorError::PathError(_)) => {
-                        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16()));
+                        return Ok(error_response_with_details(hyper::StatusCode::INTERNAL_SERVER_ERROR, err, gruxi_request));
+                    }
+                    GruxiErrorKind::PHPProcessor(PHPProcessorError::FileBlockedDueToSecurity(_)) => {
+                        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16())); // We dont want to expose that it was blocked due to security
+                    }
+                    GruxiErrorKind::PHPProcessor(PHPProcessorError::RequestBody(_)) => {
+                        return Ok(error_response_with_details(hyper::StatusCode::BAD_REQUEST, err, gruxi_request));
                     }
                     GruxiErrorKind::PHPProcessor(PHPProcessorError::Timeout) => {
-                        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::GATEWAY_TIMEOUT.as_u16()));
+                        return Ok(error_response_with_details(hyper::StatusCode::GATEWAY_TIMEOUT, err, gruxi_request));
                     }
                     GruxiErrorKind::PHPProcessor(PHPProcessorError::Connection) => {
-                        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::BAD_GATEWAY.as_u16()));
+                        return Ok(error_response_with_details(hyper::StatusCode::BAD_GATEWAY, err, gruxi_request));
+                    }
+                    GruxiErrorKind::FastCgi(FastCgiError::Connection(_) | FastCgiError::Communication(_) | FastCgiError::InvalidResponse) => {
+                        return Ok(error_response_with_details(hyper::StatusCode::BAD_GATEWAY, err, gruxi_request));
+                    }
+                    GruxiErrorKind::FastCgi(FastCgiError::Timeout) => {
+                        return Ok(error_response_with_details(hyper::StatusCode::GATEWAY_TIMEOUT, err, gruxi_request));
                     }
 
                     // Other errors we have logged, but will continue to the next handler
-                    _ => response_result
+                    _ => response_result,
                 }
             }
         };
@@ -316,6 +399,54 @@ mod tests {
         assert!(errors.iter().any(|e| e.contains("Name cannot be empty")));
     }
 
+    #[test]
+    fn test_request_handler_allowed_methods_static() {
+        let mut handler = create_valid_handler();
+        handler.processor_type = "static".to_string();
+        handler.allowed_methods = vec!["POST".to_string()];
+
+        // Static handlers ignore configured methods
+        assert_eq!(handler.get_allowed_methods(), vec!["GET", "HEAD", "OPTIONS"]);
+        assert!(handler.is_method_allowed("HEAD"));
+        assert!(!handler.is_method_allowed("POST"));
+        assert!(!handler.forwards_options_requests());
+    }
+
+    #[test]
+    fn test_request_handler_allowed_methods_default_and_configured() {
+        let mut handler = create_valid_handler();
+        handler.processor_type = "proxy".to_string();
+        assert_eq!(handler.get_allowed_methods(), vec!["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]);
+        assert!(handler.forwards_options_requests());
+
+        handler.allowed_methods = vec!["GET".to_string(), "POST".to_string()];
+        assert_eq!(handler.get_allowed_methods(), vec!["GET", "HEAD", "POST", "OPTIONS"]);
+        assert!(!handler.is_method_allowed("DELETE"));
+        assert!(!handler.forwards_options_requests());
+
+        handler.allowed_methods = vec!["OPTIONS".to_string(), "PATCH".to_string()];
+        assert!(handler.is_method_allowed("PATCH"));
+        assert!(handler.forwards_options_requests());
+    }
+
+    #[test]
+    fn test_request_handler_allowed_methods_sanitize_and_validate() {
+        let mut handler = create_valid_handler();
+        handler.allowed_methods = vec![" get ".to_string(), "GET".to_string(), "".to_string(), "post".to_string()];
+        handler.sanitize();
+        assert_eq!(handler.allowed_methods, vec!["GET", "POST"]);
+        assert!(handler.validate().is_ok());
+
+        handler.allowed_methods = vec!["NOT A METHOD".to_string()];
+        let errors = handler.validate().unwrap_err();
+        assert!(errors.iter().any(|e| e.contains("not a valid HTTP method")));
+
+        // Methods outside of the ones Gruxi handles are answered with a 501 before the handler, so they cannot be allowed
+        handler.allowed_methods = vec!["PROPFIND".to_string()];
+        let errors = handler.validate().unwrap_err();
+        assert!(errors.iter().any(|e| e.contains("'PROPFIND' is not a valid HTTP method")));
+    }
+
     fn create_valid_handler() -> RequestHandler {
         RequestHandler::new()
     }
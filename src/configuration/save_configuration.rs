@@ -1,10 +1,12 @@
 use crate::configuration::binding::Binding;
 use crate::configuration::configuration::Configuration;
+use crate::configuration::configuration_include::{get_included_ids, save_included_sites};
 use crate::configuration::core::Core;
 use crate::configuration::load_configuration::fetch_configuration_in_db;
 use crate::configuration::request_handler::RequestHandler;
 use crate::configuration::site::HeaderKV;
 use crate::configuration::site::Site;
+use crate::configuration::upstream::Upstream;
 use crate::core::database_connection::get_database_connection;
 use crate::external_connections::managed_system::php_cgi::PhpCgi;
 use crate::http::request_handlers::processors::php_processor::PHPProcessor;
@@ -39,16 +41,18 @@ pub fn save_configuration(config: &mut Configuration, force: bool) -> Result<boo
     // Do the actual saving
     let connection = get_database_connection().map_err(|e| vec![format!("Failed to get database connection: {}", e)])?;
 
+    // Sites kept in their own files, and what only they use, are saved to those files instead
+    let included_ids = get_included_ids(config);
+
     // Begin transaction for atomicity
     connection.execute("BEGIN TRANSACTION").map_err(|e| vec![format!("Failed to begin transaction: {}", e)])?;
 
     // Save the schema version, clear it first
-    connection.execute("DELETE FROM gruxi WHERE gruxi_key = 'schema_version'").map_err(|e| vec![format!("Failed to clear existing schema version: {}", e)])?;
     connection
-        .execute(format!(
-            "INSERT INTO gruxi (gruxi_key, gruxi_value) VALUES ('schema_version', '{}')",
-            config.version
-        ))
+        .execute("DELETE FROM gruxi WHERE gruxi_key = 'schema_version'")
+        .map_err(|e| vec![format!("Failed to clear existing schema version: {}", e)])?;
+    connection
+        .execute(format!("INSERT INTO gruxi (gruxi_key, gruxi_value) VALUES ('schema_version', '{}')", config.version))
         .map_err(|e| vec![format!("Failed to save schema version: {}", e)])?;
 
     // Save core configuration (file cache, gzip, server settings)
@@ -64,7 +68,7 @@ pub fn save_configuration(config: &mut Configuration, force: bool) -> Result<boo
     // Clear and re-insert all sites (simpler than update/delete logic)
     connection.execute("DELETE FROM sites").map_err(|e| vec![format!("Failed to clear existing sites: {}", e)])?;
 
-    for site in &config.sites {
+    for site in config.sites.iter().filter(|s| !included_ids.site_ids.contains(&s.id)) {
         save_site(&connection, site).map_err(|e| vec![format!("Failed to save site: {}", e)])?;
     }
 
@@ -74,7 +78,7 @@ pub fn save_configuration(config: &mut Configuration, force: bool) -> Result<boo
         .execute("DELETE FROM binding_sites")
         .map_err(|e| vec![format!("Failed to clear existing binding-site relationships: {}", e)])?;
 
-    for relationship in &config.binding_sites {
+    for relationship in config.binding_sites.iter().filter(|bs| !included_ids.site_ids.contains(&bs.site_id)) {
         connection
             .execute(format!(
                 "INSERT INTO binding_sites (binding_id, site_id) VALUES ('{}', '{}')",
@@ -87,7 +91,7 @@ pub fn save_configuration(config: &mut Configuration, force: bool) -> Result<boo
     connection
         .execute("DELETE FROM request_handler")
         .map_err(|e| vec![format!("Failed to clear existing request handlers: {}", e)])?;
-    for handler in &config.request_handlers {
+    for handler in config.request_handlers.iter().filter(|h| !included_ids.request_handler_ids.contains(&h.id)) {
         save_request_handler(&connection, handler).map_err(|e| vec![format!("Failed to save request handler: {}", e)])?;
     }
 
@@ -95,7 +99,7 @@ pub fn save_configuration(config: &mut Configuration, force: bool) -> Result<boo
     connection
         .execute("DELETE FROM static_file_processors")
         .map_err(|e| vec![format!("Failed to clear existing processors: {}", e)])?;
-    for processor in &config.static_file_processors {
+    for processor in config.static_file_processors.iter().filter(|p| !included_ids.processor_ids.contains(&p.id)) {
         save_static_file_processor(&connection, processor).map_err(|e| vec![format!("Failed to save static file processor: {}", e)])?;
     }
 
@@ -103,7 +107,7 @@ pub fn save_configuration(config: &mut Configuration, force: bool) -> Result<boo
     connection
         .execute("DELETE FROM php_processors")
         .map_err(|e| vec![format!("Failed to clear existing PHP processors: {}", e)])?;
-    for processor in &config.php_processors {
+    for processor in config.php_processors.iter().filter(|p| !included_ids.processor_ids.contains(&p.id)) {
         save_php_processor(&connection, processor).map_err(|e| vec![format!("Failed to save PHP processor: {}", e)])?;
     }
 
@@ -111,11 +115,17 @@ pub fn save_configuration(config: &mut Configuration, force: bool) -> Result<boo
     connection
         .execute("DELETE FROM proxy_processors")
         .map_err(|e| vec![format!("Failed to clear existing Proxy processors: {}", e)])?;
-    for processor in &config.proxy_processors {
+    for processor in config.proxy_processors.iter().filter(|p| !included_ids.processor_ids.contains(&p.id)) {
         // Implement save_proxy_processor similarly to other save functions
         save_proxy_processor(&connection, processor).map_err(|e| vec![format!("Failed to save Proxy processor: {}", e)])?;
     }
 
+    // Save upstream pools, clear existing first
+    connection.execute("DELETE FROM upstreams").map_err(|e| vec![format!("Failed to clear existing upstreams: {}", e)])?;
+    for upstream in &config.upstreams {
+        save_upstream(&connection, upstream).map_err(|e| vec![format!("Failed to save upstream: {}", e)])?;
+    }
+
     // Save PHP-CGI handlers, clear existing first
     connection
         .execute("DELETE FROM php_cgi_handlers")
@@ -127,6 +137,8 @@ pub fn save_configuration(config: &mut Configuration, force: bool) -> Result<boo
     // Commit transaction
     connection.execute("COMMIT").map_err(|e| vec![format!("Failed to commit transaction: {}", e)])?;
 
+    save_included_sites(config, &current_config).map_err(|e| vec![e])?;
+
     info("Configuration saved successfully");
 
     Ok(true) // Changes were saved
@@ -134,10 +146,11 @@ pub fn save_configuration(config: &mut Configuration, force: bool) -> Result<boo
 
 fn save_proxy_processor(connection: &Connection, processor: &ProxyProcessor) -> Result<(), String> {
     let url_rewrites_json = serde_json::to_string(&processor.url_rewrites).map_err(|e| format!("Failed to serialize URL rewrites: {}", e))?;
+    let tls_settings_json = serde_json::to_string(&processor.tls_settings).map_err(|e| format!("Failed to serialize TLS settings: {}", e))?;
 
     connection
         .execute(format!(
-            "INSERT INTO proxy_processors (id, proxy_type, upstream_servers, load_balancing_strategy, timeout_seconds, health_check_path, health_check_interval_seconds, health_check_timeout_seconds, url_rewrites, preserve_host_header, forced_host_header, verify_tls_certificates) VALUES ('{}', '{}', '{}', '{}', {}, '{}', {}, {}, '{}', {}, '{}', {})",
+            "INSERT INTO proxy_processors (id, proxy_type, upstream_servers, load_balancing_strategy, timeout_seconds, health_check_path, health_check_interval_seconds, health_check_timeout_seconds, url_rewrites, preserve_host_header, forced_host_header, verify_tls_certificates, session_affinity, session_affinity_cookie_name, upstream_id, cache_enabled, cache_max_size_mb, tls_settings, max_retries, retry_budget_seconds) VALUES ('{}', '{}', '{}', '{}', {}, '{}', {}, {}, '{}', {}, '{}', {}, '{}', '{}', '{}', {}, {}, '{}', {}, {})",
             processor.id,
             processor.proxy_type.replace("'", "''"),
             processor.upstream_servers.join(",").replace("'", "''"),
@@ -149,17 +162,45 @@ fn save_proxy_processor(connection: &Connection, processor: &ProxyProcessor) ->
             url_rewrites_json.replace("'", "''"),
             if processor.preserve_host_header { 1 } else { 0 },
             processor.forced_host_header.replace("'", "''"),
-            if processor.verify_tls_certificates { 1 } else { 0 }
+            if processor.verify_tls_certificates { 1 } else { 0 },
+            processor.session_affinity.replace("'", "''"),
+            processor.session_affinity_cookie_name.replace("'", "''"),
+            processor.upstream_id.replace("'", "''"),
+            if processor.cache_enabled { 1 } else { 0 },
+            processor.cache_max_size_mb,
+            tls_settings_json.replace("'", "''"),
+            processor.max_retries,
+            processor.retry_budget_seconds
         ))
         .map_err(|e| format!("Failed to insert Proxy processor: {}", e))?;
 
     Ok(())
 }
 
+fn save_upstream(connection: &Connection, upstream: &Upstream) -> Result<(), String> {
+    let servers_json = serde_json::to_string(&upstream.servers).map_err(|e| format!("Failed to serialize upstream servers: {}", e))?;
+
+    connection
+        .execute(format!(
+            "INSERT INTO upstreams (id, name, servers, health_check_path, health_check_interval_seconds, health_check_timeout_seconds) VALUES ('{}', '{}', '{}', '{}', {}, {})",
+            upstream.id.replace("'", "''"),
+            upstream.name.replace("'", "''"),
+            servers_json.replace("'", "''"),
+            upstream.health_check_path.replace("'", "''"),
+            upstream.health_check_interval_seconds,
+            upstream.health_check_timeout_seconds
+        ))
+        .map_err(|e| format!("Failed to insert upstream: {}", e))?;
+
+    Ok(())
+}
+
 fn save_php_processor(connection: &Connection, processor: &PHPProcessor) -> Result<(), String> {
+    let fastcgi_tls_settings_json = serde_json::to_string(&processor.fastcgi_tls_settings).map_err(|e| format!("Failed to serialize FastCGI TLS settings: {}", e))?;
+
     connection
         .execute(format!(
-            "INSERT INTO php_processors (id, served_by_type, php_cgi_handler_id, fastcgi_ip_and_port, request_timeout, local_web_root, fastcgi_web_root, server_software_spoof) VALUES ('{}', '{}', '{}', '{}', {}, '{}', '{}', '{}')",
+            "INSERT INTO php_processors (id, served_by_type, php_cgi_handler_id, fastcgi_ip_and_port, request_timeout, local_web_root, fastcgi_web_root, server_software_spoof, cache_enabled, cache_max_size_mb, fastcgi_tls_enabled, fastcgi_verify_tls_certificates, fastcgi_tls_settings) VALUES ('{}', '{}', '{}', '{}', {}, '{}', '{}', '{}', {}, {}, {}, {}, '{}')",
             processor.id,
             processor.served_by_type.replace("'", "''"),
             processor.php_cgi_handler_id.replace("'", "''"),
@@ -167,7 +208,12 @@ fn save_php_processor(connection: &Connection, processor: &PHPProcessor) -> Resu
             processor.request_timeout,
             processor.local_web_root.replace("'", "''"),
             processor.fastcgi_web_root.replace("'", "''"),
-            processor.server_software_spoof.replace("'", "''")
+            processor.server_software_spoof.replace("'", "''"),
+            if processor.cache_enabled { 1 } else { 0 },
+            processor.cache_max_size_mb,
+            if processor.fastcgi_tls_enabled { 1 } else { 0 },
+            if processor.fastcgi_verify_tls_certificates { 1 } else { 0 },
+            fastcgi_tls_settings_json.replace("'", "''")
         ))
         .map_err(|e| format!("Failed to insert PHP processor: {}", e))?;
 
@@ -175,14 +221,17 @@ fn save_php_processor(connection: &Connection, processor: &PHPProcessor) -> Resu
 }
 
 fn save_php_cgi_handler(connection: &Connection, handler: &PhpCgi) -> Result<(), String> {
+    let extra_environment_str = serde_json::to_string(&handler.extra_environment).map_err(|e| format!("Failed to serialize environment variables: {}", e))?;
+
     connection
         .execute(format!(
-            "INSERT INTO php_cgi_handlers (id, name, request_timeout, concurrent_threads, executable) VALUES ('{}', '{}', {}, {}, '{}')",
+            "INSERT INTO php_cgi_handlers (id, name, request_timeout, concurrent_threads, executable, extra_environment) VALUES ('{}', '{}', {}, {}, '{}', '{}')",
             handler.id,
             handler.name.replace("'", "''"),
             handler.request_timeout,
             handler.concurrent_threads,
-            handler.executable.replace("'", "''")
+            handler.executable.replace("'", "''"),
+            extra_environment_str.replace("'", "''")
         ))
         .map_err(|e| format!("Failed to insert PHP-CGI handler: {}", e))?;
 
@@ -219,8 +268,48 @@ fn save_core_config(connection: &Connection, core: &Core) -> Result<(), String>
     // Save server settings
     save_server_settings(connection, "max_body_size", &core.server_settings.max_body_size.to_string())?;
     save_server_settings(connection, "blocked_file_patterns", &core.server_settings.blocked_file_patterns.join(","))?;
+    save_server_settings(connection, "include", &core.server_settings.include.join(","))?;
+    save_server_settings(connection, "request_validation_level", &core.server_settings.request_validation_level)?;
+    save_server_settings(connection, "server_header", &core.server_settings.server_header)?;
+    save_server_settings(connection, "max_open_file_handles", &core.server_settings.max_open_file_handles.to_string())?;
+    save_server_settings(connection, "http2_max_resets_per_second", &core.server_settings.http2_max_resets_per_second.to_string())?;
+    save_server_settings(connection, "http2_max_settings_per_second", &core.server_settings.http2_max_settings_per_second.to_string())?;
+    save_server_settings(connection, "http2_max_pings_per_second", &core.server_settings.http2_max_pings_per_second.to_string())?;
+    save_server_settings(connection, "http2_max_continuation_frames", &core.server_settings.http2_max_continuation_frames.to_string())?;
+    save_server_settings(connection, "http2_flood_ban_seconds", &core.server_settings.http2_flood_ban_seconds.to_string())?;
+    save_server_settings(connection, "trusted_proxy_ips", &core.server_settings.trusted_proxy_ips.join(","))?;
+    save_server_settings(connection, "managed_port_range_start", &core.server_settings.managed_port_range_start.to_string())?;
+    save_server_settings(connection, "managed_port_range_end", &core.server_settings.managed_port_range_end.to_string())?;
+    save_server_settings(connection, "managed_port_exclusions", &core.server_settings.managed_port_exclusions.join(","))?;
+    save_server_settings(connection, "dns_cache_ttl_seconds", &core.server_settings.dns_cache_ttl_seconds.to_string())?;
+    save_server_settings(connection, "outbound_connect_timeout_seconds", &core.server_settings.outbound_connect_timeout_seconds.to_string())?;
+    save_server_settings(connection, "outbound_read_timeout_seconds", &core.server_settings.outbound_read_timeout_seconds.to_string())?;
+    save_server_settings(connection, "outbound_write_timeout_seconds", &core.server_settings.outbound_write_timeout_seconds.to_string())?;
+    save_server_settings(connection, "upstream_pool_max_idle_per_host", &core.server_settings.upstream_pool_max_idle_per_host.to_string())?;
+    save_server_settings(connection, "upstream_pool_idle_timeout_seconds", &core.server_settings.upstream_pool_idle_timeout_seconds.to_string())?;
+    save_server_settings(connection, "panic_storm_max_panics_per_minute", &core.server_settings.panic_storm_max_panics_per_minute.to_string())?;
+    save_server_settings(connection, "panic_storm_maintenance_seconds", &core.server_settings.panic_storm_maintenance_seconds.to_string())?;
+    save_server_settings(connection, "memory_budget_bytes", &core.server_settings.memory_budget_bytes.to_string())?;
+    save_server_settings(connection, "disk_cache_max_size_bytes", &core.server_settings.disk_cache_max_size_bytes.to_string())?;
+    save_server_settings(connection, "disk_cache_max_unused_hours", &core.server_settings.disk_cache_max_unused_hours.to_string())?;
+    let event_webhooks_json = serde_json::to_string(&core.server_settings.event_webhooks).map_err(|e| format!("Failed to serialize event webhooks: {}", e))?;
+    save_server_settings(connection, "event_webhooks", &event_webhooks_json)?;
+    save_server_settings(connection, "remote_syslog_address", &core.server_settings.remote_syslog_address)?;
+    save_server_settings(connection, "remote_syslog_level", &core.server_settings.remote_syslog_level)?;
+    save_server_settings(connection, "windows_event_log_level", &core.server_settings.windows_event_log_level)?;
+    save_server_settings(connection, "log_level", &core.server_settings.log_level)?;
+    let log_module_levels_json = serde_json::to_string(&core.server_settings.log_module_levels).map_err(|e| format!("Failed to serialize log module levels: {}", e))?;
+    save_server_settings(connection, "log_module_levels", &log_module_levels_json)?;
+    save_server_settings(connection, "log_sample_rate", &core.server_settings.log_sample_rate.to_string())?;
+    save_server_settings(connection, "log_max_records_per_second", &core.server_settings.log_max_records_per_second.to_string())?;
+    let smtp_json = serde_json::to_string(&core.server_settings.smtp).map_err(|e| format!("Failed to serialize SMTP settings: {}", e))?;
+    save_server_settings(connection, "smtp", &smtp_json)?;
+    save_server_settings(connection, "health_check_path", &core.server_settings.health_check_path)?;
+    save_server_settings(connection, "deregistration_delay_seconds", &core.server_settings.deregistration_delay_seconds.to_string())?;
 
     // Save admin portal settings
+    save_server_settings(connection, "admin_portal_is_enabled", &core.admin_portal.is_enabled.to_string())?;
+    save_server_settings(connection, "admin_portal_port", &core.admin_portal.port.to_string())?;
     save_server_settings(connection, "admin_portal_domain_name", &core.admin_portal.domain_name.to_string())?;
 
     save_server_settings(connection, "admin_portal_tls_automatic_enabled", &core.admin_portal.tls_automatic_enabled.to_string())?;
@@ -234,11 +323,18 @@ fn save_core_config(connection: &Connection, core: &Core) -> Result<(), String>
     } else {
         save_server_settings(connection, "admin_portal_tls_key_path", "")?;
     }
+    save_server_settings(connection, "admin_portal_session_cookie_enabled", &core.admin_portal.session_cookie_enabled.to_string())?;
+    save_server_settings(connection, "admin_portal_session_cookie_same_site", &core.admin_portal.session_cookie_same_site)?;
+    save_server_settings(connection, "admin_portal_session_idle_timeout_minutes", &core.admin_portal.session_idle_timeout_minutes.to_string())?;
+    save_server_settings(connection, "admin_portal_session_absolute_timeout_hours", &core.admin_portal.session_absolute_timeout_hours.to_string())?;
+    save_server_settings(connection, "admin_portal_session_max_per_user", &core.admin_portal.session_max_per_user.to_string())?;
+    save_server_settings(connection, "admin_portal_session_token_bytes", &core.admin_portal.session_token_bytes.to_string())?;
 
     // Save TLS settings
     save_server_settings(connection, "tls_account_email", &core.tls_settings.account_email)?;
     save_server_settings(connection, "tls_use_staging_server", &core.tls_settings.use_staging_server.to_string())?;
     save_server_settings(connection, "tls_certificate_cache_path", &core.tls_settings.certificate_cache_path)?;
+    save_server_settings(connection, "tls_acme_challenge_directory", &core.tls_settings.acme_challenge_directory)?;
 
     Ok(())
 }
@@ -282,12 +378,27 @@ fn save_binding(connection: &Connection, binding: &Binding) -> Result<(), String
     // Insert binding with explicit ID (all bindings are re-inserted after DELETE FROM bindings)
     connection
         .execute(format!(
-            "INSERT INTO bindings (id, ip, port, is_admin, is_tls) VALUES ('{}', '{}', {}, {}, {})",
+            "INSERT INTO bindings (id, ip, port, is_admin, is_tls, keep_alive_timeout_seconds, max_requests_per_connection, pipelining_enabled, h2_max_concurrent_streams, h2_initial_stream_window_size, h2_initial_connection_window_size, h2_max_header_list_size, h2_max_connection_memory, h2_max_pending_reset_streams, h2_max_local_error_reset_streams, protocols, h2c_enabled, absolute_form_enabled, status_page_enabled, status_page_allowed_ips) VALUES ('{}', '{}', {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, '{}', {}, {}, {}, '{}')",
             binding.id,
             binding.ip.replace("'", "''"),
             binding.port,
             if binding.is_admin { 1 } else { 0 },
-            if binding.is_tls { 1 } else { 0 }
+            if binding.is_tls { 1 } else { 0 },
+            binding.keep_alive_timeout_seconds,
+            binding.max_requests_per_connection,
+            if binding.pipelining_enabled { 1 } else { 0 },
+            binding.http2.max_concurrent_streams,
+            binding.http2.initial_stream_window_size,
+            binding.http2.initial_connection_window_size,
+            binding.http2.max_header_list_size,
+            binding.http2.max_connection_memory,
+            binding.http2.max_pending_reset_streams,
+            binding.http2.max_local_error_reset_streams,
+            binding.protocols.replace("'", "''"),
+            if binding.h2c_enabled { 1 } else { 0 },
+            if binding.absolute_form_enabled { 1 } else { 0 },
+            if binding.status_page_enabled { 1 } else { 0 },
+            binding.status_page_allowed_ips.join(",").replace("'", "''")
         ))
         .map_err(|e| format!("Failed to insert binding: {}", e))?;
 
@@ -312,9 +423,17 @@ pub fn save_site(connection: &Connection, site: &Site) -> Result<(), String> {
             .join(",")
     };
 
+    let cache_policies_str = serde_json::to_string(&site.cache_policies).map_err(|e| format!("Failed to serialize cache policies: {}", e))?;
+    let extra_environment_str = serde_json::to_string(&site.extra_environment).map_err(|e| format!("Failed to serialize environment variables: {}", e))?;
+    let traffic_split_str = serde_json::to_string(&site.traffic_split).map_err(|e| format!("Failed to serialize traffic split: {}", e))?;
+    let header_routes_str = serde_json::to_string(&site.header_routes).map_err(|e| format!("Failed to serialize header routes: {}", e))?;
+    let cookie_routes_str = serde_json::to_string(&site.cookie_routes).map_err(|e| format!("Failed to serialize cookie routes: {}", e))?;
+    let body_substitutions_str = serde_json::to_string(&site.body_substitutions).map_err(|e| format!("Failed to serialize body substitutions: {}", e))?;
+    let early_hints_str = serde_json::to_string(&site.early_hints).map_err(|e| format!("Failed to serialize early hints: {}", e))?;
+
     connection
         .execute(format!(
-            "INSERT INTO sites (id, is_default, is_enabled, hostnames, tls_cert_path, tls_cert_content, tls_key_path, tls_key_content, request_handlers, rewrite_functions, access_log_enabled, access_log_file, extra_headers, tls_automatic_enabled) VALUES ('{}', {}, {}, '{}', '{}', '{}', '{}', '{}', '{}', '{}', {}, '{}', '{}', {})",
+            "INSERT INTO sites (id, is_default, is_enabled, hostnames, tls_cert_path, tls_cert_content, tls_key_path, tls_key_content, request_handlers, rewrite_functions, access_log_enabled, access_log_file, extra_headers, tls_automatic_enabled, debug_capture_enabled, api_mode_enabled, max_body_size, path_confinement_enabled, path_confinement_allowed_paths, cache_policies, language_negotiation_enabled, default_language, index_files, try_files, spa_fallback_enabled, spa_fallback_excluded_paths, php_error_log_file, access_log_tls_fingerprint_enabled, middlewares, extra_environment, build_command, build_source_directory, build_output_directory, build_timeout_seconds, deploy_webhook_token, deploy_git_repository, deploy_git_branch, deploy_git_webhook_secret, access_log_timings_enabled, traffic_split, header_routes, cookie_routes, body_substitutions, minification_enabled, robots_txt_enabled, sitemap_enabled, trailing_slash_redirect, lowercase_redirect_enabled, early_hints, preload_links_enabled, upload_progress_enabled, early_hints_enabled) VALUES ('{}', {}, {}, '{}', '{}', '{}', '{}', '{}', '{}', '{}', {}, '{}', '{}', {}, {}, {}, {}, {}, '{}', '{}', {}, '{}', '{}', '{}', {}, '{}', '{}', {}, '{}', '{}', '{}', '{}', '{}', {}, '{}', '{}', '{}', '{}', {}, '{}', '{}', '{}', '{}', {}, {}, {}, '{}', {}, '{}', {}, {}, {})",
             site.id,
             if site.is_default { 1 } else { 0 },
             if site.is_enabled { 1 } else { 0 },
@@ -328,7 +447,45 @@ pub fn save_site(connection: &Connection, site: &Site) -> Result<(), String> {
             if site.access_log_enabled { 1 } else { 0 },
             site.access_log_file.replace("'", "''"),
             extra_headers_str,
-            if site.tls_automatic_enabled { 1 } else { 0 }
+            if site.tls_automatic_enabled { 1 } else { 0 },
+            if site.debug_capture_enabled { 1 } else { 0 },
+            if site.api_mode_enabled { 1 } else { 0 },
+            site.max_body_size,
+            if site.path_confinement_enabled { 1 } else { 0 },
+            site.path_confinement_allowed_paths.join(",").replace("'", "''"),
+            cache_policies_str.replace("'", "''"),
+            if site.language_negotiation_enabled { 1 } else { 0 },
+            site.default_language.replace("'", "''"),
+            site.index_files.join(",").replace("'", "''"),
+            site.try_files.join(",").replace("'", "''"),
+            if site.spa_fallback_enabled { 1 } else { 0 },
+            site.spa_fallback_excluded_paths.join(",").replace("'", "''"),
+            site.php_error_log_file.replace("'", "''"),
+            if site.access_log_tls_fingerprint_enabled { 1 } else { 0 },
+            site.middlewares.join(",").replace("'", "''"),
+            extra_environment_str.replace("'", "''"),
+            site.build_command.replace("'", "''"),
+            site.build_source_directory.replace("'", "''"),
+            site.build_output_directory.replace("'", "''"),
+            site.build_timeout_seconds,
+            site.deploy_webhook_token.replace("'", "''"),
+            site.deploy_git_repository.replace("'", "''"),
+            site.deploy_git_branch.replace("'", "''"),
+            site.deploy_git_webhook_secret.replace("'", "''"),
+            if site.access_log_timings_enabled { 1 } else { 0 },
+            traffic_split_str.replace("'", "''"),
+            header_routes_str.replace("'", "''"),
+            cookie_routes_str.replace("'", "''"),
+            body_substitutions_str.replace("'", "''"),
+            if site.minification_enabled { 1 } else { 0 },
+            if site.robots_txt_enabled { 1 } else { 0 },
+            if site.sitemap_enabled { 1 } else { 0 },
+            site.trailing_slash_redirect.replace("'", "''"),
+            if site.lowercase_redirect_enabled { 1 } else { 0 },
+            early_hints_str.replace("'", "''"),
+            if site.preload_links_enabled { 1 } else { 0 },
+            if site.upload_progress_enabled { 1 } else { 0 },
+            if site.early_hints_enabled { 1 } else { 0 }
         ))
         .map_err(|e| format!("Failed to insert site: {}", e))?;
 
@@ -340,17 +497,19 @@ pub fn save_site(connection: &Connection, site: &Site) -> Result<(), String> {
 fn save_request_handler(connection: &Connection, handler: &RequestHandler) -> Result<(), String> {
     // Prepare comma-separated strings
     let url_match_str = handler.url_match.join(",");
+    let allowed_methods_str = handler.allowed_methods.join(",");
 
     // Insert request handler with comma-separated fields
     connection
         .execute(format!(
-            "INSERT INTO request_handler (id, is_enabled, name, processor_type, processor_id, url_match) VALUES ('{}', {}, '{}', '{}', '{}', '{}')",
+            "INSERT INTO request_handler (id, is_enabled, name, processor_type, processor_id, url_match, allowed_methods) VALUES ('{}', {}, '{}', '{}', '{}', '{}', '{}')",
             handler.id,
             if handler.is_enabled { 1 } else { 0 },
             handler.name.replace("'", "''"),
             handler.processor_type,
             handler.processor_id,
-            url_match_str
+            url_match_str,
+            allowed_methods_str
         ))
         .map_err(|e| format!("Failed to insert request handler: {}", e))?;
 
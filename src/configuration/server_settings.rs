@@ -1,15 +1,335 @@
+use crate::configuration::configuration_include::validate_include_pattern;
+use crate::http::status_page::parse_ip_range;
+use crate::logging::log_forwarding::parse_remote_syslog_address;
+use crate::logging::syslog::parse_log_levels;
+use crate::network::port_manager::parse_port_range;
+use email_address::{EmailAddress, Options};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ServerSettings {
     pub max_body_size: u64, // in bytes
     pub blocked_file_patterns: Vec<String>,
+    // Site files to merge into the configuration, such as sites-enabled/*.json, where * may be used in the file name
+    #[serde(default)]
+    pub include: Vec<String>,
+    // Checks against request smuggling, "off", "standard" or "strict"
+    #[serde(default = "default_request_validation_level")]
+    pub request_validation_level: String,
+    // How responses name the server, one of SERVER_HEADER_MODES. Also decides the name Gruxi gives itself in Via headers when proxying
+    #[serde(default = "default_server_header")]
+    pub server_header: String,
+    // Cap on file handles open at once for serving static files, see FileHandleLimiter
+    #[serde(default = "default_max_open_file_handles")]
+    pub max_open_file_handles: usize,
+    // Thresholds for closing HTTP/2 connections of flooding clients, see Http2FloodGuard
+    #[serde(default = "default_http2_max_resets_per_second")]
+    pub http2_max_resets_per_second: u32,
+    #[serde(default = "default_http2_max_settings_per_second")]
+    pub http2_max_settings_per_second: u32,
+    #[serde(default = "default_http2_max_pings_per_second")]
+    pub http2_max_pings_per_second: u32,
+    #[serde(default = "default_http2_max_continuation_frames")]
+    pub http2_max_continuation_frames: u32,
+    // How long a flooding client is banned, 0 to only close the connection
+    #[serde(default = "default_http2_flood_ban_seconds")]
+    pub http2_flood_ban_seconds: u64,
+    // IP addresses or ranges of the reverse proxies and load balancers in front of Gruxi. The clients behind them share their
+    // connections, so they are never banned, only the flooding connection is closed
+    #[serde(default)]
+    pub trusted_proxy_ips: Vec<String>,
+    // The ports given to the processes Gruxi starts, such as PHP-CGI, and ports or port ranges like "9100-9199" used by other software
+    #[serde(default = "default_managed_port_range_start")]
+    pub managed_port_range_start: u16,
+    #[serde(default = "default_managed_port_range_end")]
+    pub managed_port_range_end: u16,
+    #[serde(default)]
+    pub managed_port_exclusions: Vec<String>,
+    // How long the addresses of upstream hosts given by name, such as proxy and FastCGI backends, are cached, 0 to resolve them for every connection
+    #[serde(default = "default_dns_cache_ttl_seconds")]
+    pub dns_cache_ttl_seconds: u64,
+    // Timeouts of the connections to backends, such as proxy upstreams, FastCGI servers and health checks, see OutboundTimeouts
+    #[serde(default = "default_outbound_connect_timeout_seconds")]
+    pub outbound_connect_timeout_seconds: u64,
+    #[serde(default = "default_outbound_read_timeout_seconds")]
+    pub outbound_read_timeout_seconds: u64,
+    #[serde(default = "default_outbound_write_timeout_seconds")]
+    pub outbound_write_timeout_seconds: u64,
+    // Idle keep-alive connections kept per upstream host, such as a proxy upstream server, and how long they are kept for reuse,
+    // 0 connections to open one for every request. HTTP/2 upstreams get their requests multiplexed over one connection, see HttpClient.
+    // The idle timeout must be shorter than the outbound read timeout, which also runs on idle connections and would close them first
+    #[serde(default = "default_upstream_pool_max_idle_per_host")]
+    pub upstream_pool_max_idle_per_host: usize,
+    #[serde(default = "default_upstream_pool_idle_timeout_seconds")]
+    pub upstream_pool_idle_timeout_seconds: u64,
+    // Panics in request handling within a minute that put the server in maintenance mode for a while, 0 to never do so, see PanicGuard
+    #[serde(default)]
+    pub panic_storm_max_panics_per_minute: u64,
+    #[serde(default = "default_panic_storm_maintenance_seconds")]
+    pub panic_storm_maintenance_seconds: u64,
+    // Ceiling on the memory used by bodies held in memory and the file cache, 0 for no ceiling, see MemoryBudget
+    #[serde(default)]
+    pub memory_budget_bytes: u64,
+    // Ceiling on the disk space of all disk caches together, such as those of proxy processors, and how long their entries may go
+    // unused before they are removed, 0 for no ceiling or to keep them, see DiskCacheManager
+    #[serde(default)]
+    pub disk_cache_max_size_bytes: u64,
+    #[serde(default)]
+    pub disk_cache_max_unused_hours: u64,
+    // Webhooks the server lifecycle events are posted to, see EventWebhooks
+    #[serde(default)]
+    pub event_webhooks: Vec<EventWebhook>,
+    // Syslog server the log is also sent to, as "udp://host:514", "tcp://host:514" or "tls://host:6514", empty to not send it, see LogForwarder
+    #[serde(default)]
+    pub remote_syslog_address: String,
+    // The lowest severity sent to the syslog server and written to the Windows Event Log, one of LOG_LEVELS
+    #[serde(default = "default_remote_syslog_level")]
+    pub remote_syslog_level: String,
+    #[serde(default = "default_windows_event_log_level")]
+    pub windows_event_log_level: String,
+    // Level of the log file, one of LOG_LEVELS, or empty for the level of the operation mode
+    #[serde(default)]
+    pub log_level: String,
+    // Levels of modules that differ from the log level, such as {"fastcgi": "trace"}, keyed by one of LOG_MODULES
+    #[serde(default)]
+    pub log_module_levels: BTreeMap<String, String>,
+    // 1 in how many records are logged from a place in the code logging over and over, 0 to log all of them, see LogSampler
+    #[serde(default)]
+    pub log_sample_rate: u64,
+    // Log records written per second at most, 0 for no cap
+    #[serde(default = "default_log_max_records_per_second")]
+    pub log_max_records_per_second: u64,
+    // Mail server the email of Gruxi is sent through, such as alerts and certificate expiry notices, see EmailQueue
+    #[serde(default)]
+    pub smtp: SmtpSettings,
+    // Path answered on every site with 200 while the server is in service and 503 while it is not, for load balancers to check,
+    // such as "/healthz", empty to not answer it. See get_health_check_response
+    #[serde(default)]
+    pub health_check_path: String,
+    // How long the health check answers 503 before a graceful shutdown stops accepting connections, so load balancers stop
+    // sending new requests first, 0 to start draining right away
+    #[serde(default)]
+    pub deregistration_delay_seconds: u64,
+}
+
+// How the connection to the mail server is secured: "starttls" upgrades a plain connection, usually on port 587, "tls" connects
+// with TLS right away, usually on port 465, and "none" sends everything in the clear
+pub const SMTP_SECURITY_MODES: [&str; 3] = ["starttls", "tls", "none"];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SmtpSettings {
+    // Host name of the mail server, empty to not send email
+    #[serde(default)]
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    // One of SMTP_SECURITY_MODES
+    #[serde(default = "default_smtp_security")]
+    pub security: String,
+    // Logs in with AUTH PLAIN when set
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default)]
+    pub from_address: String,
+    // Who gets the notices sent by the server, such as alerts and certificate expiry notices
+    #[serde(default)]
+    pub notification_recipients: Vec<String>,
+}
+
+impl SmtpSettings {
+    pub fn is_enabled(&self) -> bool {
+        !self.host.is_empty()
+    }
+
+    fn sanitize(&mut self) {
+        self.host = self.host.trim().to_string();
+        self.security = self.security.trim().to_lowercase();
+        self.username = self.username.trim().to_string();
+        self.from_address = self.from_address.trim().to_string();
+        self.notification_recipients = self.notification_recipients.iter().map(|r| r.trim().to_string()).filter(|r| !r.is_empty()).collect();
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if !self.is_enabled() {
+            return;
+        }
+        if self.port == 0 {
+            errors.push("SMTP port cannot be 0".to_string());
+        }
+        if !SMTP_SECURITY_MODES.contains(&self.security.as_str()) {
+            errors.push(format!("SMTP security must be one of {}: {}", SMTP_SECURITY_MODES.join(", "), self.security));
+        }
+        if !self.username.is_empty() && self.security == "none" {
+            errors.push("SMTP login needs STARTTLS or TLS, so the password is not sent in the clear".to_string());
+        }
+        for address in std::iter::once(&self.from_address).chain(self.notification_recipients.iter()) {
+            if EmailAddress::parse_with_options(address, Options::default().without_display_text()).is_err() {
+                errors.push(format!("SMTP email address is not valid: '{}'", address));
+            }
+        }
+    }
+}
+
+impl Default for SmtpSettings {
+    fn default() -> Self {
+        SmtpSettings {
+            host: String::new(),
+            port: default_smtp_port(),
+            security: default_smtp_security(),
+            username: String::new(),
+            password: String::new(),
+            from_address: String::new(),
+            notification_recipients: Vec::new(),
+        }
+    }
+}
+
+pub fn default_smtp_port() -> u16 {
+    587
+}
+
+pub fn default_smtp_security() -> String {
+    "starttls".to_string()
+}
+
+// The events that can be sent to event webhooks
+pub const EVENT_WEBHOOK_EVENTS: [&str; 6] = ["startup", "shutdown", "configuration_changed", "certificate_deployed", "handler_restarted", "client_banned"];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EventWebhook {
+    pub url: String,
+    // Signs each body with HMAC-SHA256, sent in the X-Gruxi-Signature header. Empty sends them unsigned
+    #[serde(default)]
+    pub secret: String,
+    // The events sent to the webhook, all of them if empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+pub const LOG_LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+pub const REQUEST_VALIDATION_LEVELS: [&str; 3] = ["off", "standard", "strict"];
+
+pub fn default_request_validation_level() -> String {
+    "standard".to_string()
+}
+
+// "name" sends "Server: Gruxi", "version" adds the version as "Gruxi/1.2.3" and "off" sends no Server header at all
+pub const SERVER_HEADER_MODES: [&str; 3] = ["name", "version", "off"];
+
+pub fn default_server_header() -> String {
+    SERVER_HEADER_MODES[0].to_string()
+}
+
+pub fn default_max_open_file_handles() -> usize {
+    512
+}
+
+pub fn default_http2_max_resets_per_second() -> u32 {
+    100
+}
+
+pub fn default_http2_max_settings_per_second() -> u32 {
+    10
+}
+
+pub fn default_http2_max_pings_per_second() -> u32 {
+    10
+}
+
+pub fn default_http2_max_continuation_frames() -> u32 {
+    16
+}
+
+pub fn default_http2_flood_ban_seconds() -> u64 {
+    0
+}
+
+pub fn default_managed_port_range_start() -> u16 {
+    9000
+}
+
+pub fn default_managed_port_range_end() -> u16 {
+    10000
+}
+
+pub fn default_dns_cache_ttl_seconds() -> u64 {
+    60
+}
+
+pub fn default_outbound_connect_timeout_seconds() -> u64 {
+    10
+}
+
+pub fn default_outbound_read_timeout_seconds() -> u64 {
+    60
+}
+
+pub fn default_outbound_write_timeout_seconds() -> u64 {
+    60
+}
+
+pub fn default_upstream_pool_max_idle_per_host() -> usize {
+    32
+}
+
+pub fn default_upstream_pool_idle_timeout_seconds() -> u64 {
+    50
+}
+
+pub fn default_panic_storm_maintenance_seconds() -> u64 {
+    60
+}
+
+pub fn default_remote_syslog_level() -> String {
+    "info".to_string()
+}
+
+pub fn default_windows_event_log_level() -> String {
+    "off".to_string()
+}
+
+pub fn default_log_max_records_per_second() -> u64 {
+    10000
 }
 
 impl ServerSettings {
     pub fn sanitize(&mut self) {
         // Ensure blocked file patterns are lowercase for consistent matching and remove any asterisk before extension
         self.blocked_file_patterns = self.blocked_file_patterns.iter().map(|p| p.to_lowercase().replace("*", "")).collect();
+
+        // Paths use forward slashes, also on Windows
+        self.include = self.include.iter().map(|p| p.trim().replace('\\', "/")).filter(|p| !p.is_empty()).collect();
+
+        self.request_validation_level = self.request_validation_level.trim().to_lowercase();
+        self.server_header = self.server_header.trim().to_lowercase();
+
+        self.managed_port_exclusions = self.managed_port_exclusions.iter().map(|p| p.replace(' ', "")).filter(|p| !p.is_empty()).collect();
+        self.trusted_proxy_ips = self.trusted_proxy_ips.iter().map(|ip| ip.trim().to_string()).filter(|ip| !ip.is_empty()).collect();
+
+        for webhook in &mut self.event_webhooks {
+            webhook.url = webhook.url.trim().to_string();
+            webhook.events = webhook.events.iter().map(|e| e.trim().to_lowercase()).filter(|e| !e.is_empty()).collect();
+        }
+
+        self.remote_syslog_address = self.remote_syslog_address.trim().to_string();
+        self.remote_syslog_level = self.remote_syslog_level.trim().to_lowercase();
+        self.windows_event_log_level = self.windows_event_log_level.trim().to_lowercase();
+        self.log_level = self.log_level.trim().to_lowercase();
+        self.log_module_levels = self
+            .log_module_levels
+            .iter()
+            .map(|(module, level)| (module.trim().to_lowercase(), level.trim().to_lowercase()))
+            .filter(|(_, level)| !level.is_empty())
+            .collect();
+
+        self.smtp.sanitize();
+
+        self.health_check_path = self.health_check_path.trim().to_string();
     }
 
     pub fn validate(&self) -> Result<(), Vec<String>> {
@@ -22,6 +342,114 @@ impl ServerSettings {
             }
         }
 
+        for pattern in &self.include {
+            if let Err(e) = validate_include_pattern(pattern) {
+                errors.push(e);
+            }
+        }
+
+        if !REQUEST_VALIDATION_LEVELS.contains(&self.request_validation_level.as_str()) {
+            errors.push(format!(
+                "Request validation level must be one of {}: {}",
+                REQUEST_VALIDATION_LEVELS.join(", "),
+                self.request_validation_level
+            ));
+        }
+
+        if !SERVER_HEADER_MODES.contains(&self.server_header.as_str()) {
+            errors.push(format!("Server header must be one of {}: {}", SERVER_HEADER_MODES.join(", "), self.server_header));
+        }
+
+        if self.max_open_file_handles == 0 {
+            errors.push("Max open file handles cannot be 0".to_string());
+        }
+
+        for (value, name) in [
+            (self.http2_max_resets_per_second, "HTTP/2 max resets per second"),
+            (self.http2_max_settings_per_second, "HTTP/2 max SETTINGS per second"),
+            (self.http2_max_pings_per_second, "HTTP/2 max PINGs per second"),
+            (self.http2_max_continuation_frames, "HTTP/2 max CONTINUATION frames"),
+        ] {
+            if value == 0 {
+                errors.push(format!("{} cannot be 0", name));
+            }
+        }
+
+        for trusted_proxy_ip in &self.trusted_proxy_ips {
+            if parse_ip_range(trusted_proxy_ip).is_none() {
+                errors.push(format!("Invalid IP address or range for a trusted proxy: {}", trusted_proxy_ip));
+            }
+        }
+
+        if self.managed_port_range_start == 0 || self.managed_port_range_start > self.managed_port_range_end {
+            errors.push(format!(
+                "Managed port range must go from low to high and cannot include port 0: {}-{}",
+                self.managed_port_range_start, self.managed_port_range_end
+            ));
+        }
+        for exclusion in &self.managed_port_exclusions {
+            if let Err(e) = parse_port_range(exclusion) {
+                errors.push(e);
+            }
+        }
+
+        for (value, name) in [
+            (self.outbound_connect_timeout_seconds, "Outbound connect timeout"),
+            (self.outbound_read_timeout_seconds, "Outbound read timeout"),
+            (self.outbound_write_timeout_seconds, "Outbound write timeout"),
+            (self.upstream_pool_idle_timeout_seconds, "Upstream connection pool idle timeout"),
+            (self.panic_storm_maintenance_seconds, "Panic storm maintenance time"),
+        ] {
+            if value == 0 {
+                errors.push(format!("{} cannot be 0", name));
+            }
+        }
+        if self.upstream_pool_idle_timeout_seconds >= self.outbound_read_timeout_seconds {
+            errors.push(format!(
+                "Upstream connection pool idle timeout ({} seconds) must be shorter than the outbound read timeout ({} seconds)",
+                self.upstream_pool_idle_timeout_seconds, self.outbound_read_timeout_seconds
+            ));
+        }
+
+        for (idx, webhook) in self.event_webhooks.iter().enumerate() {
+            if !(webhook.url.starts_with("http://") || webhook.url.starts_with("https://")) || webhook.url.parse::<hyper::Uri>().is_err() {
+                errors.push(format!("Event webhook {} URL must be an http:// or https:// URL: {}", idx + 1, webhook.url));
+            }
+            for event in &webhook.events {
+                if !EVENT_WEBHOOK_EVENTS.contains(&event.as_str()) {
+                    errors.push(format!(
+                        "Event webhook {} has an unknown event '{}', must be one of {}",
+                        idx + 1,
+                        event,
+                        EVENT_WEBHOOK_EVENTS.join(", ")
+                    ));
+                }
+            }
+        }
+
+        if !self.remote_syslog_address.is_empty()
+            && let Err(e) = parse_remote_syslog_address(&self.remote_syslog_address)
+        {
+            errors.push(e);
+        }
+        for (level, name) in [(&self.remote_syslog_level, "Remote syslog level"), (&self.windows_event_log_level, "Windows Event Log level")] {
+            if !LOG_LEVELS.contains(&level.as_str()) {
+                errors.push(format!("{} must be one of {}: {}", name, LOG_LEVELS.join(", "), level));
+            }
+        }
+        if let Err(log_level_errors) = parse_log_levels(&self.log_level, &self.log_module_levels) {
+            errors.extend(log_level_errors);
+        }
+        if !cfg!(windows) && self.windows_event_log_level != "off" {
+            errors.push("The Windows Event Log is only available on Windows, its level must be off".to_string());
+        }
+
+        self.smtp.validate(&mut errors);
+
+        if !self.health_check_path.is_empty() && (!self.health_check_path.starts_with('/') || self.health_check_path.parse::<hyper::Uri>().is_err()) {
+            errors.push(format!("Health check path must be a path starting with '/', such as '/healthz': {}", self.health_check_path));
+        }
+
         // Validate max_body_size
         if self.max_body_size == 0 {
             errors.push("Max body size cannot be 0".to_string());
@@ -1,13 +1,182 @@
+use crate::file::file_util::check_path_confined;
+use crate::http::language_negotiation::is_valid_language_tag;
+use crate::http::middleware::middleware_chain::{MIDDLEWARES, get_default_middlewares};
+use crate::http::try_files::{TRY_FILES_URI_PLACEHOLDER, parse_try_files_status};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct HeaderKV {
     pub key: String,
     pub value: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+// Cache-Control/Expires policy for paths matching the pattern, see http::cache_policy for the pattern format
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CachePolicy {
+    pub pattern: String,
+    pub cache_control: String,
+    // Sets an Expires header this many seconds ahead, 0 for none
+    #[serde(default)]
+    pub expires_seconds: u64,
+    // Also replace the caching headers set by backends, such as PHP or proxied servers
+    #[serde(default)]
+    pub override_backend: bool,
+}
+
+// Environment variable for the handler of a site, such as APP_ENV, passed to PHP with each request
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EnvironmentVariable {
+    pub name: String,
+    pub value: String,
+}
+
+// Names must be usable as environment variables, and each may only be given once
+pub fn validate_environment_variables(variables: &[EnvironmentVariable], owner: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut unique_names = std::collections::HashSet::new();
+    for variable in variables {
+        let is_valid_name = variable.name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && variable.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_valid_name {
+            errors.push(format!(
+                "{}: Invalid environment variable name '{}', only letters, digits and underscores are allowed, not starting with a digit",
+                owner, variable.name
+            ));
+        } else if !unique_names.insert(variable.name.as_str()) {
+            errors.push(format!("{}: Duplicate environment variable '{}'", owner, variable.name));
+        }
+        if variable.value.contains('\0') {
+            errors.push(format!("{}: Environment variable '{}' cannot contain NUL characters", owner, variable.name));
+        }
+    }
+    errors
+}
+
+// The handler's variables with those of the site on top, so sites sharing a handler can set their own values
+pub fn merge_environment_variables(handler_variables: &[EnvironmentVariable], site_variables: &[EnvironmentVariable]) -> std::collections::BTreeMap<String, String> {
+    handler_variables.iter().chain(site_variables).map(|variable| (variable.name.clone(), variable.value.clone())).collect()
+}
+
+// How clients are kept on the variant they got: "cookie" sets a cookie with the variant, "client_ip" picks it by a hash of the IP address
+pub const TRAFFIC_SPLIT_STICKY_MODES: [&str; 2] = ["cookie", "client_ip"];
+
+// Splits the clients of a site between its request handlers (variant A) and a second set (variant B), such as another web root or
+// backend, for A/B tests and gradual rollouts. See http::traffic_split
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TrafficSplit {
+    #[serde(default)]
+    pub enabled: bool,
+    // Request handlers serving variant B, in place of those of the site
+    #[serde(default)]
+    pub variant_b_request_handlers: Vec<String>,
+    // Percentage of the clients getting variant B, from 0 to 100. Can be changed until the next restart through the admin API
+    #[serde(default)]
+    pub variant_b_percentage: u32,
+    // One of TRAFFIC_SPLIT_STICKY_MODES
+    #[serde(default = "get_default_traffic_split_sticky_by")]
+    pub sticky_by: String,
+    #[serde(default = "get_default_traffic_split_cookie_name")]
+    pub cookie_name: String,
+}
+
+impl Default for TrafficSplit {
+    fn default() -> Self {
+        TrafficSplit {
+            enabled: false,
+            variant_b_request_handlers: Vec::new(),
+            variant_b_percentage: 0,
+            sticky_by: get_default_traffic_split_sticky_by(),
+            cookie_name: get_default_traffic_split_cookie_name(),
+        }
+    }
+}
+
+fn get_default_traffic_split_sticky_by() -> String {
+    TRAFFIC_SPLIT_STICKY_MODES[0].to_string()
+}
+
+fn get_default_traffic_split_cookie_name() -> String {
+    "gruxi_variant".to_string()
+}
+
+// How the value of a header routing rule is compared with the request header
+pub const HEADER_ROUTE_MATCH_TYPES: [&str; 3] = ["exact", "prefix", "regex"];
+
+// Sends the requests with a matching header, such as X-API-Version: 2 or a mobile User-Agent, to other request handlers than those
+// of the site, such as another backend or web root. See http::header_routing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct HeaderRoute {
+    pub header_name: String,
+    // One of HEADER_ROUTE_MATCH_TYPES. Exact and prefix matches are case sensitive, a regex can be made case insensitive with (?i)
+    pub match_type: String,
+    pub value: String,
+    pub request_handlers: Vec<String>,
+}
+
+// Sends the requests of clients with a cookie set to a value, such as canary=1, to other request handlers than those of the site,
+// such as a staging backend, so testers can use a canary build through the production hostname. See http::cookie_routing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CookieRoute {
+    pub cookie_name: String,
+    pub value: String,
+    pub request_handlers: Vec<String>,
+    // A path, such as /_canary, that sets the cookie on the client and redirects it to the start page. Adding ?opt_out=1 removes it again.
+    // If empty, the cookie has to be set some other way
+    #[serde(default)]
+    pub opt_in_path: String,
+}
+
+pub const TRAILING_SLASH_REDIRECT_MODES: [&str; 3] = ["off", "add", "remove"];
+
+pub fn get_default_trailing_slash_redirect() -> String {
+    "off".to_string()
+}
+
+// Converts a hostname to the ASCII form clients send in SNI and the Host header, lowercased and with internationalized labels in
+// punycode, such as "bücher.example" to "xn--bcher-kva.example". Sites store their hostnames in this form, so they match requests.
+// Internationalized hostnames must otherwise be letters, digits and hyphens, as their punycode form can only be a DNS name
+pub fn hostname_to_ascii(hostname: &str) -> Result<String, String> {
+    if hostname.is_ascii() {
+        return Ok(hostname.to_ascii_lowercase());
+    }
+    idna::domain_to_ascii_strict(hostname).map_err(|_| format!("Hostname '{}' is not a valid internationalized domain name", hostname))
+}
+
+// The stored hostnames of the sites in punycode mapped to their Unicode form, such as "xn--bcher-kva.example" to "bücher.example",
+// for the admin portal to show. Hostnames that are not internationalized, or not valid punycode, are left out
+pub fn get_hostname_display_names(sites: &[Site]) -> BTreeMap<String, String> {
+    sites
+        .iter()
+        .flat_map(|site| site.hostnames.iter())
+        .filter_map(|hostname| {
+            let (unicode, result) = idna::domain_to_unicode(hostname);
+            (result.is_ok() && unicode != *hostname).then(|| (hostname.clone(), unicode))
+        })
+        .collect()
+}
+
+// Longest search text of a body substitution. Streamed responses keep this much of the body back between chunks, to find matches
+// split across them
+pub const MAX_BODY_SUBSTITUTION_SEARCH_LENGTH: usize = 1024;
+
+// Replaces a text in response bodies, such as </body> with an analytics snippet followed by </body>. The replacement can contain
+// ${request_id}, ${remote_ip} and ${hostname}. See http::body_substitution
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BodySubstitution {
+    pub search: String,
+    pub replacement: String,
+    // Media types of the responses to change, such as text/html
+    #[serde(default = "get_default_body_substitution_content_types")]
+    pub content_types: Vec<String>,
+}
+
+fn get_default_body_substitution_content_types() -> Vec<String> {
+    vec!["text/html".to_string()]
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 #[allow(unused)]
 pub struct Site {
     pub id: String,
@@ -15,6 +184,7 @@ pub struct Site {
     pub is_default: bool,
     pub is_enabled: bool,
     // Automatic TLS
+    #[serde(default)]
     pub tls_automatic_enabled: bool,
     // TLS certificate path or actual content
     pub tls_cert_path: String,
@@ -31,6 +201,130 @@ pub struct Site {
     // Logs
     pub access_log_enabled: bool,
     pub access_log_file: String,
+    // Add the JA3 and JA4 fingerprints of the TLS connection to access log entries, to recognize clients such as bots rotating IP addresses
+    #[serde(default)]
+    pub access_log_tls_fingerprint_enabled: bool,
+    // Add the time spent in each phase of the request to access log entries, to tell whether slowness is Gruxi, the backend or the client
+    #[serde(default)]
+    pub access_log_timings_enabled: bool,
+    // Debug capture of full request/response pairs, retrievable from the admin portal
+    #[serde(default)]
+    pub debug_capture_enabled: bool,
+    // API mode, where error responses are returned as RFC 7807 problem+json
+    #[serde(default)]
+    pub api_mode_enabled: bool,
+    // Max request body size in bytes for this site, 0 uses the server wide max body size
+    #[serde(default)]
+    pub max_body_size: u64,
+    // Answer polls for the progress of uploads to the site on /gruxi-upload-progress, see http::upload_manager
+    #[serde(default)]
+    pub upload_progress_enabled: bool,
+    // Confine the file paths handlers resolve to the web root and the allowed paths, with symlinks resolved
+    #[serde(default)]
+    pub path_confinement_enabled: bool,
+    #[serde(default)]
+    pub path_confinement_allowed_paths: Vec<String>,
+    // Cache policies, first matching pattern wins
+    #[serde(default)]
+    pub cache_policies: Vec<CachePolicy>,
+    // Serve language variants of static files, like /page.html.de for /page.html, based on the Accept-Language header
+    #[serde(default)]
+    pub language_negotiation_enabled: bool,
+    #[serde(default)]
+    pub default_language: String,
+    // Serve static HTML, CSS and JavaScript files minified, for sites without a build pipeline doing it. See file::minification
+    #[serde(default)]
+    pub minification_enabled: bool,
+    // Send Link preload headers for the stylesheets and scripts in the head of static HTML pages. See file::preload_links
+    #[serde(default)]
+    pub preload_links_enabled: bool,
+    // Serve a generated robots.txt and sitemap.xml, when the request handlers of the site have none. See http::sitemap
+    #[serde(default)]
+    pub robots_txt_enabled: bool,
+    #[serde(default)]
+    pub sitemap_enabled: bool,
+    // One of TRAILING_SLASH_REDIRECT_MODES: "add" redirects directories requested without a trailing slash to it, "remove" redirects
+    // other paths with a trailing slash to those without. See http::canonical_redirect
+    #[serde(default = "get_default_trailing_slash_redirect")]
+    pub trailing_slash_redirect: String,
+    // Redirect paths with uppercase letters to their lowercase form, for sites served from case insensitive filesystems
+    #[serde(default)]
+    pub lowercase_redirect_enabled: bool,
+    // Index files to look for in directories, in priority order. If empty, each processor uses its own defaults
+    #[serde(default)]
+    pub index_files: Vec<String>,
+    // Fallback routing like nginx try_files, such as "$uri", "$uri/", "/index.php?$query". Empty means disabled
+    #[serde(default)]
+    pub try_files: Vec<String>,
+    // History API fallback for single-page apps, serving /index.html for paths that are not files, except the excluded path prefixes
+    #[serde(default)]
+    pub spa_fallback_enabled: bool,
+    #[serde(default)]
+    pub spa_fallback_excluded_paths: Vec<String>,
+    // File PHP writes the errors of this site to, through the PHP error_log setting. Empty leaves it to the PHP configuration
+    #[serde(default)]
+    pub php_error_log_file: String,
+    // Middlewares the requests of the site run through, in order, see MiddlewareChain
+    #[serde(default = "get_default_middlewares")]
+    pub middlewares: Vec<String>,
+    // Environment variables passed to the handlers of the site, on top of those of the PHP-CGI handler
+    #[serde(default)]
+    pub extra_environment: Vec<EnvironmentVariable>,
+    // Command building the site with a static site generator, such as "hugo" or "npm run build", see deployment::site_builder. Empty if the site is not built by Gruxi
+    #[serde(default)]
+    pub build_command: String,
+    // Directory with the source of the site, copied into each release before the build command runs in it. Not used for sites deployed from git
+    #[serde(default)]
+    pub build_source_directory: String,
+    // Directory the build writes the site to, relative to the release, such as "public" or "dist". The site is served from it once the build succeeds
+    #[serde(default)]
+    pub build_output_directory: String,
+    #[serde(default = "get_default_build_timeout_seconds")]
+    pub build_timeout_seconds: u64,
+    // Token a webhook sends in the X-Gruxi-Deploy-Token header to start a build. Empty disables the webhook
+    #[serde(default)]
+    pub deploy_webhook_token: String,
+    // Git repository the site is deployed from, a release is checked out from the branch on each push. Empty if the site is not deployed from git
+    #[serde(default)]
+    pub deploy_git_repository: String,
+    #[serde(default = "get_default_deploy_git_branch")]
+    pub deploy_git_branch: String,
+    // Secret of the push webhook of the repository, for the signature GitHub sends or the token GitLab sends. Empty disables the webhook
+    #[serde(default)]
+    pub deploy_git_webhook_secret: String,
+    // Splitting of the clients between two sets of request handlers, for A/B tests
+    #[serde(default)]
+    pub traffic_split: TrafficSplit,
+    // Rules sending requests to other request handlers by a request header, the first matching rule is used
+    #[serde(default)]
+    pub header_routes: Vec<HeaderRoute>,
+    // Rules sending requests to other request handlers by a cookie, checked before the header routes
+    #[serde(default)]
+    pub cookie_routes: Vec<CookieRoute>,
+    // Texts replaced in the bodies of the responses, in order
+    #[serde(default)]
+    pub body_substitutions: Vec<BodySubstitution>,
+    // Send 103 Early Hints responses, with the links below and those of proxied upstreams, to HTTP/1.1 clients. See http::early_hints
+    #[serde(default)]
+    pub early_hints_enabled: bool,
+    // Link header values, such as "</css/app.css>; rel=preload; as=style", sent in a 103 Early Hints response to browsers loading a
+    // page, before the request handlers answer
+    #[serde(default)]
+    pub early_hints: Vec<String>,
+    // The included site file this site is kept in, such as sites-enabled/example.json. Empty for sites kept in the database
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub config_file: String,
+}
+
+// Minimum length of deploy webhook tokens, so they cannot be guessed
+pub const MIN_DEPLOY_WEBHOOK_TOKEN_LENGTH: usize = 16;
+
+fn get_default_build_timeout_seconds() -> u64 {
+    600
+}
+
+fn get_default_deploy_git_branch() -> String {
+    "main".to_string()
 }
 
 // Supported rewrite functions
@@ -53,13 +347,53 @@ impl Site {
             extra_headers: Vec::new(),
             access_log_enabled: false,
             access_log_file: String::new(),
+            debug_capture_enabled: false,
+            api_mode_enabled: false,
+            max_body_size: 0,
+            upload_progress_enabled: false,
+            path_confinement_enabled: false,
+            path_confinement_allowed_paths: Vec::new(),
+            cache_policies: Vec::new(),
+            language_negotiation_enabled: false,
+            default_language: String::new(),
+            index_files: Vec::new(),
+            try_files: Vec::new(),
+            spa_fallback_enabled: false,
+            spa_fallback_excluded_paths: Vec::new(),
+            php_error_log_file: String::new(),
+            middlewares: get_default_middlewares(),
+            extra_environment: Vec::new(),
+            build_command: String::new(),
+            build_source_directory: String::new(),
+            build_output_directory: String::new(),
+            build_timeout_seconds: get_default_build_timeout_seconds(),
+            deploy_webhook_token: String::new(),
+            deploy_git_repository: String::new(),
+            deploy_git_branch: get_default_deploy_git_branch(),
+            deploy_git_webhook_secret: String::new(),
+            access_log_tls_fingerprint_enabled: false,
+            access_log_timings_enabled: false,
+            traffic_split: TrafficSplit::default(),
+            header_routes: Vec::new(),
+            cookie_routes: Vec::new(),
+            body_substitutions: Vec::new(),
+            early_hints_enabled: false,
+            early_hints: Vec::new(),
+            minification_enabled: false,
+            preload_links_enabled: false,
+            robots_txt_enabled: false,
+            sitemap_enabled: false,
+            trailing_slash_redirect: get_default_trailing_slash_redirect(),
+            lowercase_redirect_enabled: false,
+            config_file: String::new(),
         }
     }
 
     pub fn sanitize(&mut self) {
-        // Trim whitespace from hostnames
+        // Trim whitespace from hostnames and convert them to punycode. Invalid ones are left as entered, for validate to report
         for hostname in &mut self.hostnames {
-            *hostname = hostname.trim().to_string();
+            let hostname_trimmed = hostname.trim();
+            *hostname = hostname_to_ascii(hostname_trimmed).unwrap_or_else(|_| hostname_trimmed.to_string());
         }
 
         // Trim whitespace from rewrite functions
@@ -67,14 +401,78 @@ impl Site {
             *func = func.trim().to_string();
         }
 
+        self.middlewares = self.middlewares.iter().map(|m| m.trim().to_lowercase()).filter(|m| !m.is_empty()).collect();
+
         // Trim whitespace from access log file
         self.access_log_file = self.access_log_file.trim().to_string();
 
+        // Site file paths use forward slashes, like the include patterns
+        self.config_file = self.config_file.trim().replace('\\', "/");
+        self.php_error_log_file = self.php_error_log_file.trim().to_string();
+
         // Trim whitespace from extra headers
         for kv in &mut self.extra_headers {
             kv.key = kv.key.trim().to_string();
             kv.value = kv.value.trim().to_string();
         }
+
+        for variable in &mut self.extra_environment {
+            variable.name = variable.name.trim().to_string();
+        }
+
+        self.build_command = self.build_command.trim().to_string();
+        self.build_source_directory = self.build_source_directory.trim().to_string();
+        self.build_output_directory = self.build_output_directory.trim().replace('\\', "/").trim_matches('/').to_string();
+        self.deploy_webhook_token = self.deploy_webhook_token.trim().to_string();
+        self.deploy_git_repository = self.deploy_git_repository.trim().to_string();
+        self.deploy_git_branch = self.deploy_git_branch.trim().to_string();
+        self.deploy_git_webhook_secret = self.deploy_git_webhook_secret.trim().to_string();
+
+        // Trim whitespace from path confinement allowed paths and remove empty ones
+        self.path_confinement_allowed_paths = self.path_confinement_allowed_paths.iter().map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+
+        // Trim whitespace from cache policies
+        for policy in &mut self.cache_policies {
+            policy.pattern = policy.pattern.trim().to_string();
+            policy.cache_control = policy.cache_control.trim().to_string();
+        }
+
+        // Language tags are case insensitive, so we keep them lowercase like the variant file extensions
+        self.default_language = self.default_language.trim().to_lowercase();
+
+        // Trim whitespace from index files and remove empty ones
+        self.index_files = self.index_files.iter().map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect();
+
+        // Trim whitespace from try_files entries and remove empty ones
+        self.try_files = self.try_files.iter().map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect();
+
+        self.trailing_slash_redirect = self.trailing_slash_redirect.trim().to_lowercase();
+
+        // Trim whitespace from SPA fallback excluded paths and remove empty ones
+        self.spa_fallback_excluded_paths = self.spa_fallback_excluded_paths.iter().map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+
+        self.traffic_split.variant_b_request_handlers = self.traffic_split.variant_b_request_handlers.iter().map(|h| h.trim().to_string()).filter(|h| !h.is_empty()).collect();
+        self.traffic_split.sticky_by = self.traffic_split.sticky_by.trim().to_lowercase();
+        self.traffic_split.cookie_name = self.traffic_split.cookie_name.trim().to_string();
+
+        for header_route in &mut self.header_routes {
+            header_route.header_name = header_route.header_name.trim().to_lowercase();
+            header_route.match_type = header_route.match_type.trim().to_lowercase();
+            header_route.request_handlers = header_route.request_handlers.iter().map(|h| h.trim().to_string()).filter(|h| !h.is_empty()).collect();
+        }
+
+        for cookie_route in &mut self.cookie_routes {
+            cookie_route.cookie_name = cookie_route.cookie_name.trim().to_string();
+            cookie_route.value = cookie_route.value.trim().to_string();
+            cookie_route.request_handlers = cookie_route.request_handlers.iter().map(|h| h.trim().to_string()).filter(|h| !h.is_empty()).collect();
+            cookie_route.opt_in_path = cookie_route.opt_in_path.trim().to_string();
+        }
+
+        for body_substitution in &mut self.body_substitutions {
+            body_substitution.content_types = body_substitution.content_types.iter().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect();
+        }
+
+        self.early_hints = self.early_hints.iter().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect();
     }
 
     pub fn validate(&self) -> Result<(), Vec<String>> {
@@ -90,6 +488,8 @@ impl Site {
                 errors.push(format!("Hostname {} cannot be empty", hostname_idx + 1));
             } else if hostname.trim() != "*" && hostname.trim().len() < 3 {
                 errors.push(format!("Hostname '{}' is too short (minimum 3 characters unless wildcard '*')", hostname.trim()));
+            } else if let Err(err_msg) = hostname_to_ascii(hostname.trim()) {
+                errors.push(err_msg);
             }
         }
 
@@ -116,6 +516,16 @@ impl Site {
             }
         }
 
+        // Middlewares must be known, and each may only be in the chain once
+        let mut unique_middlewares = std::collections::HashSet::new();
+        for middleware in &self.middlewares {
+            if !MIDDLEWARES.contains(&middleware.as_str()) {
+                errors.push(format!("Unknown middleware: '{}'", middleware));
+            } else if !unique_middlewares.insert(middleware) {
+                errors.push(format!("Duplicate middleware found: '{}'", middleware));
+            }
+        }
+
         // Validate access log configuration
         if self.access_log_enabled {
             if self.access_log_file.trim().is_empty() {
@@ -164,9 +574,274 @@ impl Site {
             }
         }
 
+        errors.extend(validate_environment_variables(&self.extra_environment, "Site environment"));
+
+        // Validate the build, the output is served, so it must stay within the release
+        if self.is_deployed_in_releases() {
+            if self.deploy_git_repository.is_empty() && self.build_source_directory.is_empty() {
+                errors.push("Build source directory cannot be empty when a build command is set".to_string());
+            }
+            if self.build_output_directory.split('/').any(|part| part == "..") || std::path::Path::new(&self.build_output_directory).is_absolute() {
+                errors.push(format!(
+                    "Build output directory '{}' must be relative to the release and cannot contain '..'",
+                    self.build_output_directory
+                ));
+            }
+            if self.build_timeout_seconds == 0 {
+                errors.push("Build timeout must be greater than 0".to_string());
+            }
+        }
+        if !self.deploy_webhook_token.is_empty() && self.deploy_webhook_token.len() < MIN_DEPLOY_WEBHOOK_TOKEN_LENGTH {
+            errors.push(format!("Deploy webhook token must be at least {} characters", MIN_DEPLOY_WEBHOOK_TOKEN_LENGTH));
+        }
+
+        // The repository and branch are passed to git, so they cannot look like options
+        if !self.deploy_git_repository.is_empty() {
+            if self.deploy_git_repository.starts_with('-') {
+                errors.push(format!("Git repository '{}' is invalid", self.deploy_git_repository));
+            }
+            if self.deploy_git_branch.is_empty() || self.deploy_git_branch.starts_with('-') || self.deploy_git_branch.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                errors.push(format!("Git branch '{}' is invalid", self.deploy_git_branch));
+            }
+        }
+        if !self.deploy_git_webhook_secret.is_empty() && self.deploy_git_webhook_secret.len() < MIN_DEPLOY_WEBHOOK_TOKEN_LENGTH {
+            errors.push(format!("Git webhook secret must be at least {} characters", MIN_DEPLOY_WEBHOOK_TOKEN_LENGTH));
+        }
+
+        // Validate path confinement allowed paths, which must be absolute, as they are not relative to any web root
+        for allowed_path in &self.path_confinement_allowed_paths {
+            if !std::path::Path::new(allowed_path).is_absolute() {
+                errors.push(format!("Path confinement allowed path '{}' must be an absolute path", allowed_path));
+            }
+            if allowed_path.contains(',') {
+                errors.push(format!("Path confinement allowed path '{}' cannot contain commas", allowed_path));
+            }
+        }
+
+        // Validate cache policies
+        for (idx, policy) in self.cache_policies.iter().enumerate() {
+            if policy.pattern != "*" && !policy.pattern.starts_with("*.") && !policy.pattern.starts_with('/') {
+                errors.push(format!(
+                    "Cache policy {} pattern '{}' must be '*', an extension like '*.css' or a path starting with '/'",
+                    idx + 1,
+                    policy.pattern
+                ));
+            }
+            if policy.cache_control.is_empty() && policy.expires_seconds == 0 {
+                errors.push(format!("Cache policy {} must set a Cache-Control value or Expires seconds", idx + 1));
+            }
+            if hyper::header::HeaderValue::from_str(&policy.cache_control).is_err() {
+                errors.push(format!("Cache policy {} Cache-Control value '{}' is not a valid header value", idx + 1, policy.cache_control));
+            }
+        }
+
+        // Validate the default language, as it becomes part of the variant file names
+        if !self.default_language.is_empty() && !is_valid_language_tag(&self.default_language) {
+            errors.push(format!("Default language '{}' is not a valid language tag, such as 'en' or 'en-us'", self.default_language));
+        }
+
+        // Validate index files, which are file names within the requested directory
+        for index_file in &self.index_files {
+            if index_file.contains("..") || index_file.contains('/') || index_file.contains('\\') || index_file.contains(',') {
+                errors.push(format!("Index file '{}' must be a plain file name, such as 'index.html'", index_file));
+            }
+        }
+
+        // Validate try_files entries, which are request paths. Only the last entry may be a status code, such as "=404"
+        for (idx, entry) in self.try_files.iter().enumerate() {
+            if entry.contains(',') || entry.contains(char::is_whitespace) {
+                errors.push(format!("Try files entry '{}' must not contain commas or whitespace", entry));
+            } else if entry.starts_with('=') {
+                if idx + 1 != self.try_files.len() || parse_try_files_status(entry).is_none() {
+                    errors.push(format!("Try files entry '{}' must be the last entry and a status code from 400 to 599, such as '=404'", entry));
+                }
+            } else if !entry.starts_with('/') && !entry.starts_with(TRY_FILES_URI_PLACEHOLDER) {
+                errors.push(format!("Try files entry '{}' must start with '/' or '{}'", entry, TRY_FILES_URI_PLACEHOLDER));
+            }
+        }
+
+        // Validate the PHP error log file, which must be a .log file, so it can be shown in the admin portal logs
+        if !self.php_error_log_file.is_empty() && !self.php_error_log_file.ends_with(".log") {
+            errors.push(format!("PHP error log file '{}' must be a file ending with '.log'", self.php_error_log_file));
+        }
+
+        // Validate SPA fallback excluded paths, which are request path prefixes
+        for excluded_path in &self.spa_fallback_excluded_paths {
+            if !excluded_path.starts_with('/') || excluded_path.contains(',') {
+                errors.push(format!("SPA fallback excluded path '{}' must start with '/' and cannot contain commas", excluded_path));
+            }
+        }
+
+        if !TRAILING_SLASH_REDIRECT_MODES.contains(&self.trailing_slash_redirect.as_str()) {
+            errors.push(format!(
+                "Trailing slash redirect must be one of {}: {}",
+                TRAILING_SLASH_REDIRECT_MODES.join(", "),
+                self.trailing_slash_redirect
+            ));
+        }
+
+        // Validate the traffic split, the cookie name becomes part of the Set-Cookie header
+        if self.traffic_split.enabled {
+            if self.traffic_split.variant_b_request_handlers.is_empty() {
+                errors.push("Traffic split needs at least one request handler for variant B".to_string());
+            }
+            if self.traffic_split.variant_b_percentage > 100 {
+                errors.push(format!("Traffic split percentage for variant B must be between 0 and 100: {}", self.traffic_split.variant_b_percentage));
+            }
+            if !TRAFFIC_SPLIT_STICKY_MODES.contains(&self.traffic_split.sticky_by.as_str()) {
+                errors.push(format!(
+                    "Traffic split sticky by must be one of {}: {}",
+                    TRAFFIC_SPLIT_STICKY_MODES.join(", "),
+                    self.traffic_split.sticky_by
+                ));
+            }
+            let is_valid_cookie_name = !self.traffic_split.cookie_name.is_empty() && self.traffic_split.cookie_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if self.traffic_split.sticky_by == "cookie" && !is_valid_cookie_name {
+                errors.push(format!("Traffic split cookie name '{}' may only contain letters, digits, '_' and '-'", self.traffic_split.cookie_name));
+            }
+        }
+
+        // Validate the header routes, the regular expressions are compiled here so a broken one is not first found on a request
+        for header_route in &self.header_routes {
+            if hyper::header::HeaderName::from_bytes(header_route.header_name.as_bytes()).is_err() {
+                errors.push(format!("Header route header name '{}' is not a valid header name", header_route.header_name));
+            }
+            if !HEADER_ROUTE_MATCH_TYPES.contains(&header_route.match_type.as_str()) {
+                errors.push(format!("Header route match type must be one of {}: {}", HEADER_ROUTE_MATCH_TYPES.join(", "), header_route.match_type));
+            }
+            if header_route.value.is_empty() {
+                errors.push(format!("Header route for header '{}' needs a value to match", header_route.header_name));
+            } else if header_route.match_type == "regex"
+                && let Err(e) = regex::Regex::new(&header_route.value)
+            {
+                errors.push(format!("Header route regex '{}' is invalid: {}", header_route.value, e));
+            }
+            if header_route.request_handlers.is_empty() {
+                errors.push(format!("Header route for header '{}' needs at least one request handler", header_route.header_name));
+            }
+        }
+
+        // Validate the cookie routes, the opt-in path sets the cookie through a Set-Cookie header
+        for cookie_route in &self.cookie_routes {
+            let is_valid_cookie_part = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+            if !is_valid_cookie_part(&cookie_route.cookie_name) {
+                errors.push(format!("Cookie route cookie name '{}' may only contain letters, digits, '_', '-' and '.'", cookie_route.cookie_name));
+            }
+            if !is_valid_cookie_part(&cookie_route.value) {
+                errors.push(format!("Cookie route value '{}' may only contain letters, digits, '_', '-' and '.'", cookie_route.value));
+            }
+            if cookie_route.request_handlers.is_empty() {
+                errors.push(format!("Cookie route for cookie '{}' needs at least one request handler", cookie_route.cookie_name));
+            }
+            if !cookie_route.opt_in_path.is_empty() && (!cookie_route.opt_in_path.starts_with('/') || cookie_route.opt_in_path.contains('?')) {
+                errors.push(format!("Cookie route opt-in path '{}' must start with '/' and cannot contain a query", cookie_route.opt_in_path));
+            }
+        }
+
+        // Validate the body substitutions, the search text bounds the memory used for streamed responses
+        for body_substitution in &self.body_substitutions {
+            if body_substitution.search.is_empty() || body_substitution.search.len() > MAX_BODY_SUBSTITUTION_SEARCH_LENGTH {
+                errors.push(format!(
+                    "Body substitution search text must be between 1 and {} bytes: '{}'",
+                    MAX_BODY_SUBSTITUTION_SEARCH_LENGTH, body_substitution.search
+                ));
+            }
+            if body_substitution.content_types.is_empty() {
+                errors.push(format!("Body substitution for '{}' needs at least one content type, such as text/html", body_substitution.search));
+            }
+            for content_type in &body_substitution.content_types {
+                if content_type
+                    .split_once('/')
+                    .is_none_or(|(kind, subtype)| kind.is_empty() || subtype.is_empty() || content_type.contains(';'))
+                {
+                    errors.push(format!("Body substitution content type '{}' must be a media type without parameters, such as text/html", content_type));
+                }
+            }
+        }
+
+        // Validate the early hints, which are sent as Link headers
+        for link in &self.early_hints {
+            if !link.starts_with('<') || !link.contains('>') || hyper::header::HeaderValue::from_str(link).is_err() {
+                errors.push(format!("Early hint '{}' must be a Link header value, such as '</css/app.css>; rel=preload; as=style'", link));
+            }
+        }
+
         if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 
+    // The request handlers of the site, both variants of a traffic split and the header and cookie routes, such as for finding what a site uses
+    pub fn get_all_request_handler_ids(&self) -> impl Iterator<Item = &String> {
+        let mut seen = std::collections::HashSet::new();
+        self.request_handlers
+            .iter()
+            .chain(self.traffic_split.variant_b_request_handlers.iter())
+            .chain(self.header_routes.iter().flat_map(|route| route.request_handlers.iter()))
+            .chain(self.cookie_routes.iter().flat_map(|route| route.request_handlers.iter()))
+            .filter(move |id| seen.insert(*id))
+    }
+
+    // The site as served with other request handlers, such as to clients on variant B of the traffic split
+    pub fn with_request_handlers(&self, request_handlers: &[String]) -> Site {
+        let mut site = self.clone();
+        site.request_handlers = request_handlers.to_vec();
+        site
+    }
+
+    // Index files for the site in priority order, falling back to the defaults of the processor if the site has none
+    pub fn get_index_files<'a>(&'a self, processor_index_files: &'a [String]) -> &'a [String] {
+        if self.index_files.is_empty() { processor_index_files } else { &self.index_files }
+    }
+
+    // Absolute path of the PHP error log file, as PHP would otherwise resolve a relative path from its own working directory
+    pub fn get_php_error_log_path(&self) -> Option<std::path::PathBuf> {
+        if self.php_error_log_file.is_empty() {
+            return None;
+        }
+        std::path::absolute(&self.php_error_log_file).ok()
+    }
+
+    // Site owners can change how their site is served, but not what it can reach on the server, so these settings are kept from the
+    // current site when a site owner saves it: the hostnames, which could be taken from other sites, the file paths and request handlers,
+    // the limits, the middlewares and environment variables handlers run with, and the commands and repositories builds run
+    pub fn keep_admin_only_settings(&mut self, current: &Site) {
+        self.id = current.id.clone();
+        self.hostnames = current.hostnames.clone();
+        self.is_default = current.is_default;
+        self.tls_cert_path = current.tls_cert_path.clone();
+        self.tls_key_path = current.tls_key_path.clone();
+        self.request_handlers = current.request_handlers.clone();
+        self.traffic_split.variant_b_request_handlers = current.traffic_split.variant_b_request_handlers.clone();
+        self.header_routes = current.header_routes.clone();
+        self.cookie_routes = current.cookie_routes.clone();
+        self.access_log_file = current.access_log_file.clone();
+        self.php_error_log_file = current.php_error_log_file.clone();
+        self.max_body_size = current.max_body_size;
+        self.path_confinement_enabled = current.path_confinement_enabled;
+        self.path_confinement_allowed_paths = current.path_confinement_allowed_paths.clone();
+        self.middlewares = current.middlewares.clone();
+        // PHP_VALUE or LD_PRELOAD would run code of the site owner in PHP-FPM or the build
+        self.extra_environment = current.extra_environment.clone();
+        self.build_command = current.build_command.clone();
+        self.build_source_directory = current.build_source_directory.clone();
+        self.build_output_directory = current.build_output_directory.clone();
+        self.build_timeout_seconds = current.build_timeout_seconds;
+        self.deploy_git_repository = current.deploy_git_repository.clone();
+        self.config_file = current.config_file.clone();
+    }
+
+    // Checks that a file path resolved by a handler is within the web root or the allowed paths, when path confinement is enabled
+    pub async fn is_path_confined(&self, web_root: &str, file_path: &str) -> bool {
+        if !self.path_confinement_enabled {
+            return true;
+        }
+        check_path_confined(web_root, &self.path_confinement_allowed_paths, file_path).await
+    }
+
+    // Whether the site is built and served in releases by Gruxi, see deployment::site_builder
+    pub fn is_deployed_in_releases(&self) -> bool {
+        !self.build_command.is_empty() || !self.deploy_git_repository.is_empty()
+    }
+
     pub fn get_rewrite_functions_hashmap(&self) -> std::collections::HashMap<String, ()> {
         let mut hashmap = std::collections::HashMap::new();
         for func in &self.rewrite_functions {
@@ -215,6 +890,34 @@ impl Site {
     }
 }
 
+#[test]
+fn test_site_internationalized_hostnames() {
+    assert_eq!(hostname_to_ascii("bücher.example").unwrap(), "xn--bcher-kva.example");
+    assert_eq!(hostname_to_ascii("Bücher.Example").unwrap(), "xn--bcher-kva.example");
+    assert_eq!(hostname_to_ascii("WWW.Example.com").unwrap(), "www.example.com");
+    assert_eq!(hostname_to_ascii("*").unwrap(), "*");
+    assert!(hostname_to_ascii("exämple/path.com").is_err());
+
+    let mut site = Site::new();
+    site.hostnames = vec!["xn--bcher-kva.example".to_string(), "example.com".to_string(), "xn--zz.example".to_string()];
+    assert_eq!(
+        get_hostname_display_names(&[site]),
+        BTreeMap::from([("xn--bcher-kva.example".to_string(), "bücher.example".to_string())])
+    );
+
+    let mut site = Site::new();
+    site.hostnames = vec![" müller.de ".to_string(), "Example.COM".to_string(), "*".to_string()];
+    site.sanitize();
+    assert_eq!(site.hostnames, vec!["xn--mller-kva.de", "example.com", "*"]);
+    assert!(site.validate().is_ok());
+
+    site.hostnames = vec!["exämple/path.com".to_string()];
+    site.sanitize();
+    assert_eq!(site.hostnames, vec!["exämple/path.com"]);
+    let errors = site.validate().unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("is not a valid internationalized domain name")));
+}
+
 #[test]
 fn test_site_validation_access_log_enabled_empty_file() {
     let mut site = Site::new();
@@ -281,6 +984,17 @@ fn test_site_validation_access_log_enabled_windows_valid_file() {
     assert!(result.is_ok(), "Valid Windows access log file should pass validation");
 }
 
+#[test]
+fn test_site_validation_php_error_log_file() {
+    let mut site = Site::new();
+    site.php_error_log_file = "./logs/mysite-php-errors.log".to_string();
+    assert!(site.validate().is_ok(), "PHP error log file ending with .log should pass validation");
+
+    site.php_error_log_file = "/var/log/php/".to_string();
+    let errors = site.validate().unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("must be a file ending with '.log'")));
+}
+
 #[test]
 fn test_site_validation_rewrite_functions_single_valid() {
     let mut site = Site::new();
@@ -399,3 +1113,169 @@ fn test_site_validation_rewrite_functions_whitespace_only() {
         "Whitespace-only rewrite function should be treated as empty"
     );
 }
+
+#[test]
+fn test_site_validation_middlewares() {
+    let mut site = Site::new();
+    site.middlewares = vec![" Compression ".to_string(), "access_log".to_string()];
+    site.sanitize();
+    assert_eq!(site.middlewares, vec!["compression", "access_log"]);
+    assert!(site.validate().is_ok());
+
+    site.middlewares = vec!["compression".to_string(), "gzip".to_string(), "compression".to_string()];
+    let errors = site.validate().unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("Unknown middleware: 'gzip'")));
+    assert!(errors.iter().any(|e| e.contains("Duplicate middleware found: 'compression'")));
+}
+
+#[test]
+fn test_site_environment_variables() {
+    let variable = |name: &str, value: &str| EnvironmentVariable {
+        name: name.to_string(),
+        value: value.to_string(),
+    };
+
+    let mut site = Site::new();
+    site.extra_environment = vec![variable(" APP_ENV ", "production"), variable("DB_PASSWORD", "a,b=c")];
+    site.sanitize();
+    assert_eq!(site.extra_environment[0].name, "APP_ENV");
+    assert!(site.validate().is_ok());
+
+    site.extra_environment = vec![variable("1ST", "x"), variable("APP-ENV", "x"), variable("APP_ENV", "x"), variable("APP_ENV", "y")];
+    let errors = site.validate().unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("Invalid environment variable name '1ST'")));
+    assert!(errors.iter().any(|e| e.contains("Invalid environment variable name 'APP-ENV'")));
+    assert!(errors.iter().any(|e| e.contains("Duplicate environment variable 'APP_ENV'")));
+
+    // The site overrides the handler
+    let merged = merge_environment_variables(&[variable("APP_ENV", "production"), variable("PHP_TZ", "UTC")], &[variable("APP_ENV", "staging")]);
+    assert_eq!(merged.get("APP_ENV").unwrap(), "staging");
+    assert_eq!(merged.get("PHP_TZ").unwrap(), "UTC");
+}
+
+#[test]
+fn test_site_build_validation() {
+    let mut site = Site::new();
+    site.build_command = " hugo ".to_string();
+    site.build_source_directory = "/srv/site".to_string();
+    site.build_output_directory = "\\public\\".to_string();
+    site.sanitize();
+    assert_eq!(site.build_command, "hugo");
+    assert_eq!(site.build_output_directory, "public");
+    assert!(site.validate().is_ok());
+
+    site.build_output_directory = "../other-site".to_string();
+    site.build_source_directory = String::new();
+    site.deploy_webhook_token = "short".to_string();
+    let errors = site.validate().unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("Build source directory cannot be empty")));
+    assert!(errors.iter().any(|e| e.contains("cannot contain '..'")));
+    assert!(errors.iter().any(|e| e.contains("Deploy webhook token must be at least")));
+}
+
+#[test]
+fn test_site_header_route_validation() {
+    let mut site = Site::new();
+    site.header_routes = vec![HeaderRoute {
+        header_name: " X-API-Version ".to_string(),
+        match_type: "Exact".to_string(),
+        value: "2".to_string(),
+        request_handlers: vec!["handler1".to_string(), " ".to_string()],
+    }];
+    site.sanitize();
+    assert_eq!(site.header_routes[0].header_name, "x-api-version");
+    assert_eq!(site.header_routes[0].match_type, "exact");
+    assert_eq!(site.header_routes[0].request_handlers, vec!["handler1"]);
+    assert!(site.validate().is_ok());
+    assert_eq!(site.get_all_request_handler_ids().collect::<Vec<_>>(), vec!["handler1"]);
+
+    site.header_routes[0].header_name = "x api".to_string();
+    site.header_routes[0].match_type = "regex".to_string();
+    site.header_routes[0].value = "(unclosed".to_string();
+    site.header_routes[0].request_handlers.clear();
+    let errors = site.validate().unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("is not a valid header name")));
+    assert!(errors.iter().any(|e| e.contains("regex '(unclosed' is invalid")));
+    assert!(errors.iter().any(|e| e.contains("needs at least one request handler")));
+}
+
+#[test]
+fn test_site_cookie_route_validation() {
+    let mut site = Site::new();
+    site.cookie_routes = vec![CookieRoute {
+        cookie_name: " canary ".to_string(),
+        value: "1".to_string(),
+        request_handlers: vec!["staging".to_string()],
+        opt_in_path: "/_canary".to_string(),
+    }];
+    site.sanitize();
+    assert!(site.validate().is_ok());
+
+    site.cookie_routes[0].value = "1; Domain=example.com".to_string();
+    site.cookie_routes[0].opt_in_path = "_canary".to_string();
+    let errors = site.validate().unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("Cookie route value")));
+    assert!(errors.iter().any(|e| e.contains("opt-in path '_canary' must start with '/'")));
+}
+
+#[test]
+fn test_site_body_substitution_validation() {
+    let mut site = Site::new();
+    site.body_substitutions = vec![BodySubstitution {
+        search: "</body>".to_string(),
+        replacement: "<script src=\"/analytics.js\"></script></body>".to_string(),
+        content_types: vec![" Text/HTML ".to_string(), "".to_string()],
+    }];
+    site.sanitize();
+    assert_eq!(site.body_substitutions[0].content_types, vec!["text/html"]);
+    assert!(site.validate().is_ok());
+
+    site.body_substitutions[0].search = "x".repeat(MAX_BODY_SUBSTITUTION_SEARCH_LENGTH + 1);
+    site.body_substitutions[0].content_types = vec!["text/html; charset=utf-8".to_string()];
+    let errors = site.validate().unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("search text must be between 1 and")));
+    assert!(errors.iter().any(|e| e.contains("must be a media type without parameters")));
+}
+
+#[test]
+fn test_site_owner_keeps_admin_only_settings() {
+    let mut current = Site::new();
+    current.hostnames = vec!["tenant.example.com".to_string()];
+    current.build_command = "hugo".to_string();
+    current.request_handlers = vec!["handler1".to_string()];
+
+    let mut changed = current.clone();
+    changed.hostnames = vec!["other-tenant.example.com".to_string()];
+    changed.build_command = "rm -rf /".to_string();
+    changed.request_handlers = vec![];
+    changed.access_log_file = "/etc/passwd".to_string();
+    changed.spa_fallback_enabled = true;
+    changed.middlewares = vec![];
+    changed.extra_environment = vec![EnvironmentVariable {
+        name: "PHP_VALUE".to_string(),
+        value: "auto_prepend_file=/tmp/shell.php".to_string(),
+    }];
+    changed.keep_admin_only_settings(&current);
+
+    assert_eq!(changed.hostnames, vec!["tenant.example.com"]);
+    assert_eq!(changed.build_command, "hugo");
+    assert_eq!(changed.request_handlers, vec!["handler1"]);
+    assert_eq!(changed.access_log_file, current.access_log_file);
+    assert_eq!(changed.middlewares, current.middlewares);
+    assert!(changed.extra_environment.is_empty());
+    // How the site is served is up to its owner
+    assert!(changed.spa_fallback_enabled);
+}
+
+#[test]
+fn test_site_validation_early_hints() {
+    let mut site = Site::new();
+    site.early_hints = vec![" </css/app.css>; rel=preload; as=style ".to_string(), " ".to_string()];
+    site.sanitize();
+    assert_eq!(site.early_hints, vec!["</css/app.css>; rel=preload; as=style"]);
+    assert!(site.validate().is_ok());
+
+    site.early_hints.push("/css/app.css".to_string());
+    let errors = site.validate().unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("Early hint '/css/app.css' must be a Link header value")));
+}
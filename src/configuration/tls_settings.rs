@@ -1,13 +1,18 @@
 use email_address::{EmailAddress, Options};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use crate::file::normalized_path::NormalizedPath;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct TlsSettings {
     pub account_email: String,
     pub use_staging_server: bool,
     pub certificate_cache_path: String,
+    // Directory an external ACME client, such as certbot, writes its HTTP-01 challenge files to. They are served at
+    // /.well-known/acme-challenge/ on every site. Empty to not serve them
+    #[serde(default)]
+    pub acme_challenge_directory: String,
 }
 
 impl TlsSettings {
@@ -16,12 +21,14 @@ impl TlsSettings {
             account_email: String::new(),
             use_staging_server: false,
             certificate_cache_path: String::new(),
+            acme_challenge_directory: String::new(),
         }
     }
 
     pub fn sanitize(&mut self) {
         self.account_email = self.account_email.trim().to_string();
         self.certificate_cache_path = self.certificate_cache_path.trim().to_string();
+        self.acme_challenge_directory = self.acme_challenge_directory.trim().to_string();
     }
 
     pub fn validate(&self) -> Result<(), Vec<String>> {
@@ -44,6 +51,10 @@ impl TlsSettings {
             }
         }
 
+        if !self.acme_challenge_directory.is_empty() && NormalizedPath::new(&self.acme_challenge_directory, "").is_err() {
+            errors.push(format!("Invalid ACME challenge directory: {}", &self.acme_challenge_directory));
+        }
+
         if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 }
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// A weight makes a server get that many requests for each one a server with weight 1 gets
+pub const MAX_UPSTREAM_SERVER_WEIGHT: u32 = 100;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct UpstreamServer {
+    pub address: String, // e.g., "http://server1:8080"
+    #[serde(default = "get_default_weight")]
+    pub weight: u32,
+}
+
+fn get_default_weight() -> u32 {
+    1
+}
+
+// A named pool of backend servers, like an nginx upstream block. Proxy processors of any site can send their requests to it,
+// so the servers and their health checks are kept in one place
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Upstream {
+    pub id: String,
+    pub name: String,
+    pub servers: Vec<UpstreamServer>,
+    // Health check settings, if the path is empty, we dont do health checks
+    pub health_check_path: String,
+    pub health_check_interval_seconds: u32,
+    pub health_check_timeout_seconds: u32,
+}
+
+impl Upstream {
+    pub fn new() -> Self {
+        Upstream {
+            id: Uuid::new_v4().to_string(),
+            name: String::new(),
+            servers: Vec::new(),
+            health_check_path: "/health".to_string(),
+            health_check_interval_seconds: 60,
+            health_check_timeout_seconds: 5,
+        }
+    }
+
+    // The servers for round robin, each listed as many times as its weight. They are interleaved, so a heavy server does not get
+    // its share of the requests all in a row
+    pub fn get_weighted_servers(&self) -> Vec<String> {
+        let max_weight = self.servers.iter().map(|s| s.weight).max().unwrap_or(0);
+        let mut servers = Vec::new();
+        for round in 0..max_weight {
+            servers.extend(self.servers.iter().filter(|s| s.weight > round).map(|s| s.address.clone()));
+        }
+        servers
+    }
+
+    pub fn sanitize(&mut self) {
+        self.name = self.name.trim().to_string();
+        for server in &mut self.servers {
+            server.address = server.address.trim().to_string();
+        }
+        self.servers.retain(|s| !s.address.is_empty());
+        self.health_check_path = self.health_check_path.trim().to_string();
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.name.is_empty() {
+            errors.push("Upstream name cannot be empty.".to_string());
+        }
+
+        if self.servers.is_empty() {
+            errors.push("At least one server must be specified.".to_string());
+        }
+        for server in &self.servers {
+            errors.extend(validate_upstream_address(&server.address));
+            if server.weight < 1 || server.weight > MAX_UPSTREAM_SERVER_WEIGHT {
+                errors.push(format!("Weight of server '{}' must be between 1 and {}.", server.address, MAX_UPSTREAM_SERVER_WEIGHT));
+            }
+        }
+
+        if !self.health_check_path.is_empty() {
+            if !self.health_check_path.starts_with('/') {
+                errors.push("Health check path must start with '/', such as '/health' or '/healthcheck/'.".to_string());
+            }
+            if self.health_check_interval_seconds < 1 {
+                errors.push("Health check interval seconds must be greater than zero.".to_string());
+            }
+            if self.health_check_timeout_seconds < 1 {
+                errors.push("Health check timeout seconds must be greater than zero.".to_string());
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+impl Default for Upstream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Upstream servers must be valid URLs, starting with http:// or https://, without a trailing slash
+pub fn validate_upstream_address(address: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    if !address.starts_with("http://") && !address.starts_with("https://") {
+        errors.push(format!("Upstream server '{}' is not a valid upstream URL. It must start with 'http://' or 'https://'.", address));
+    }
+    if address.ends_with("/") {
+        errors.push(format!("Upstream server '{}' should not end with a trailing slash '/'.", address));
+    }
+    if address.parse::<hyper::Uri>().is_err() {
+        errors.push(format!("Upstream server '{}' is not a valid URL.", address));
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_upstream(servers: &[(&str, u32)]) -> Upstream {
+        let mut upstream = Upstream::new();
+        upstream.name = "backends".to_string();
+        upstream.servers = servers
+            .iter()
+            .map(|(address, weight)| UpstreamServer {
+                address: address.to_string(),
+                weight: *weight,
+            })
+            .collect();
+        upstream
+    }
+
+    #[test]
+    fn test_weighted_servers_are_interleaved() {
+        let upstream = create_upstream(&[("http://a:80", 3), ("http://b:80", 1)]);
+        assert_eq!(upstream.get_weighted_servers(), vec!["http://a:80", "http://b:80", "http://a:80", "http://a:80"]);
+    }
+
+    #[test]
+    fn test_validate_upstream() {
+        assert!(create_upstream(&[("http://a:80", 1)]).validate().is_ok());
+        assert!(create_upstream(&[]).validate().is_err());
+        assert!(create_upstream(&[("http://a:80", 0)]).validate().is_err());
+        assert!(create_upstream(&[("a:80", 1)]).validate().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use rustls_pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// TLS settings for the connections to proxied HTTPS upstreams and to FastCGI servers over TLS, for backends with a certificate from
+// a private CA, reached by another name than the one in their certificate, or only to be trusted with one known certificate
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct UpstreamTlsSettings {
+    #[serde(default)]
+    pub ca_bundle_file: String, // PEM file with the CA certificates to trust instead of the system ones, if set
+    #[serde(default)]
+    pub server_name: String, // Sent as SNI and verified against the certificate instead of the host of the upstream address, if set
+    #[serde(default)]
+    pub pinned_certificates: Vec<String>, // SHA-256 fingerprints in hex, one of which the certificate of the upstream must have, if set
+}
+
+impl UpstreamTlsSettings {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn sanitize(&mut self) {
+        self.ca_bundle_file = self.ca_bundle_file.trim().to_string();
+        self.server_name = self.server_name.trim().to_lowercase();
+        // Fingerprints are accepted as printed by openssl, with colons and in upper case
+        self.pinned_certificates = self
+            .pinned_certificates
+            .iter()
+            .map(|fingerprint| fingerprint.trim().replace(':', "").to_lowercase())
+            .filter(|fingerprint| !fingerprint.is_empty())
+            .collect();
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if !self.server_name.is_empty() && ServerName::try_from(self.server_name.as_str()).is_err() {
+            errors.push(format!("TLS server name '{}' is not a valid hostname or IP address.", self.server_name));
+        }
+
+        for fingerprint in &self.pinned_certificates {
+            if fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+                errors.push(format!("Pinned certificate '{}' must be a SHA-256 fingerprint of 64 hexadecimal characters.", fingerprint));
+            }
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_and_validate() {
+        let mut settings = UpstreamTlsSettings {
+            ca_bundle_file: " /etc/gruxi/backend-ca.pem ".to_string(),
+            server_name: " Backend.Internal ".to_string(),
+            pinned_certificates: vec![format!(" {} ", ["AB"; 32].join(":")), " ".to_string()],
+        };
+        settings.sanitize();
+        assert_eq!(settings.ca_bundle_file, "/etc/gruxi/backend-ca.pem");
+        assert_eq!(settings.server_name, "backend.internal");
+        assert_eq!(settings.pinned_certificates, vec!["ab".repeat(32)]);
+        assert!(settings.validate().is_empty());
+
+        settings.server_name = "not a name".to_string();
+        settings.pinned_certificates.push("abcd".to_string());
+        assert_eq!(settings.validate().len(), 2);
+        assert!(UpstreamTlsSettings::default().is_default());
+    }
+}
@@ -1,11 +1,38 @@
-use chrono::{DateTime, Duration, Utc};
 use crate::logging::syslog::{error, info};
+use chrono::{DateTime, Duration, Utc};
 use random_password_generator::generate_password;
 use serde::{Deserialize, Serialize};
 use sqlite::Connection;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::core::database_connection::get_database_connection;
+use crate::configuration::admin_portal::MAX_SESSION_TOKEN_BYTES;
+use crate::core::database_connection::{get_database_connection, is_database_read_only};
+
+// Admins manage the whole server, site owners only get to the sites they are granted ownership of
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRole {
+    Admin,
+    SiteOwner,
+}
+
+impl UserRole {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            UserRole::Admin => "admin",
+            UserRole::SiteOwner => "site_owner",
+        }
+    }
+
+    pub fn from_name(role: &str) -> Option<UserRole> {
+        match role {
+            "admin" => Some(UserRole::Admin),
+            "site_owner" => Some(UserRole::SiteOwner),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
@@ -15,6 +42,7 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
     pub is_active: bool,
+    pub role: UserRole,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,57 +53,116 @@ pub struct Session {
     pub token: String,
     pub expires_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    pub last_activity_at: DateTime<Utc>,
+    // Must be sent back in the X-CSRF-Token header on state-changing requests authenticated by the session cookie
+    pub csrf_token: String,
+    pub role: UserRole,
+    // The sites a site owner owns, empty for admins
+    pub site_ids: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct LoginRequest {
+impl Session {
+    pub fn is_admin(&self) -> bool {
+        self.role == UserRole::Admin
+    }
+
+    pub fn can_access_site(&self, site_id: &str) -> bool {
+        self.is_admin() || self.site_ids.iter().any(|owned_site_id| owned_site_id == site_id)
+    }
+}
+
+// Password reset tokens can be used for this long after they are created
+pub const PASSWORD_RESET_TOKEN_LIFETIME_MINUTES: i64 = 30;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+pub const MAX_USERNAME_LENGTH: usize = 64;
+// The activity of a session is written when it is older than this, not on every request. Idle timeouts are whole minutes, so a
+// session expires at most this much early
+const SESSION_ACTIVITY_WRITE_INTERVAL_SECONDS: i64 = 60;
+
+#[derive(Debug)]
+pub struct PasswordResetToken {
+    pub token: String,
     pub username: String,
-    pub password: String,
+    pub expires_at: DateTime<Utc>,
 }
 
-pub fn create_default_admin_user(connection: &Connection) -> Result<(), String> {
-    // Check if admin user already exists
-    let mut statement = connection
-        .prepare("SELECT COUNT(*) FROM users WHERE username = 'admin'")
-        .map_err(|e| format!("Failed to prepare admin check statement: {}", e))?;
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PasswordResetRequest {
+    pub token: String,
+    pub new_password: String,
+}
 
-    let admin_exists = match statement.next() {
-        Ok(sqlite::State::Row) => {
-            let count: i64 = statement.read(0).unwrap_or(0);
-            count > 0
-        }
-        _ => false,
-    };
+// Asks for a password reset token for the user to be emailed to the notification recipients
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PasswordResetEmailRequest {
+    pub username: String,
+}
 
-    let mut need_to_clear_sessions = false;
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
 
-    if !admin_exists {
-        let random_password_result = get_random_hashed_password();
-        let (random_password, password_hash) = match random_password_result {
-            Ok(res) => res,
-            Err(_) => {
-                return Err("Failed to generate new password".to_string());
-            }
-        };
+// Creates a user, or updates the role, sites and optionally the password of an existing one
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserRequest {
+    pub username: String,
+    // Required for new users, existing users keep their password when it is left out
+    #[serde(default)]
+    pub password: Option<String>,
+    pub role: UserRole,
+    // The sites a site owner owns, ignored for admins
+    #[serde(default)]
+    pub site_ids: Vec<String>,
+}
 
-        let created_at = Utc::now().to_rfc3339();
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserInfo {
+    pub username: String,
+    pub role: UserRole,
+    pub site_ids: Vec<String>,
+    pub is_active: bool,
+    pub created_at: String,
+    pub last_login: Option<String>,
+}
 
-        connection
-            .execute(format!(
-                "INSERT INTO users (username, password_hash, created_at, is_active) VALUES ('{}', '{}', '{}', 1)",
-                "admin", password_hash, created_at
-            ))
-            .map_err(|e| format!("Failed to create default admin user: {}", e))?;
+pub fn count_users(connection: &Connection) -> Result<i64, String> {
+    let mut statement = connection.prepare("SELECT COUNT(*) FROM users").map_err(|e| format!("Failed to prepare user count statement: {}", e))?;
+    match statement.next().map_err(|e| format!("Failed to execute user count query: {}", e))? {
+        sqlite::State::Row => statement.read::<i64, _>(0).map_err(|e| format!("Failed to read user count: {}", e)),
+        sqlite::State::Done => Ok(0),
+    }
+}
 
-        info(format!("Default admin user created with username 'admin' and password '{}'", random_password));
-        need_to_clear_sessions = true;
+// Usernames are kept to characters that are safe in logs and URLs
+pub fn validate_username(username: &str) -> Result<(), String> {
+    if username.is_empty() || username.len() > MAX_USERNAME_LENGTH {
+        return Err(format!("Username must be between 1 and {} characters", MAX_USERNAME_LENGTH));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '@') {
+        return Err("Username may only contain letters, digits and the characters - _ . @".to_string());
     }
+    Ok(())
+}
 
-    if need_to_clear_sessions {
-        // Invalidate all existing sessions for admin user
-        invalidate_sessions_for_user(connection, "admin")?;
+pub fn create_admin_user(connection: &Connection, username: &str, password: &str) -> Result<(), String> {
+    validate_username(username)?;
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH));
     }
 
+    let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| format!("Failed to hash password: {}", e))?;
+    connection
+        .execute(format!(
+            "INSERT INTO users (username, password_hash, created_at, is_active) VALUES ('{}', '{}', '{}', 1)",
+            username,
+            password_hash,
+            Utc::now().to_rfc3339()
+        ))
+        .map_err(|e| format!("Failed to create admin user: {}", e))?;
+
+    info(format!("Admin user created with username '{}'", username));
     Ok(())
 }
 
@@ -107,6 +194,98 @@ pub fn reset_admin_password() -> Result<String, String> {
     Ok(random_password)
 }
 
+// Creates a one-time token that can be exchanged for a new password of the user through the admin API, for admins locked out of the portal.
+// Any earlier tokens for the user stop working
+pub fn create_password_reset_token(username: &str) -> Result<PasswordResetToken, String> {
+    let connection = get_database_connection()?;
+
+    let mut statement = connection
+        .prepare("SELECT COUNT(*) FROM users WHERE username = ?")
+        .map_err(|e| format!("Failed to prepare user check statement: {}", e))?;
+    statement.bind((1, username)).map_err(|e| format!("Failed to bind username: {}", e))?;
+    let user_exists = match statement.next().map_err(|e| format!("Failed to execute user check query: {}", e))? {
+        sqlite::State::Row => statement.read::<i64, _>(0).unwrap_or(0) > 0,
+        sqlite::State::Done => false,
+    };
+    drop(statement);
+    if !user_exists {
+        return Err(format!("User '{}' does not exist", username));
+    }
+
+    let created_at = Utc::now();
+    let reset_token = PasswordResetToken {
+        token: Uuid::new_v4().simple().to_string(),
+        username: username.to_string(),
+        expires_at: created_at + Duration::minutes(PASSWORD_RESET_TOKEN_LIFETIME_MINUTES),
+    };
+
+    let escaped_username = username.replace("'", "''");
+    connection
+        .execute(format!(
+            "DELETE FROM password_reset_tokens WHERE username = '{}' OR expires_at < '{}'",
+            escaped_username,
+            created_at.to_rfc3339()
+        ))
+        .map_err(|e| format!("Failed to remove old password reset tokens: {}", e))?;
+    connection
+        .execute(format!(
+            "INSERT INTO password_reset_tokens (token, username, expires_at, created_at) VALUES ('{}', '{}', '{}', '{}')",
+            reset_token.token,
+            escaped_username,
+            reset_token.expires_at.to_rfc3339(),
+            created_at.to_rfc3339()
+        ))
+        .map_err(|e| format!("Failed to create password reset token: {}", e))?;
+
+    info(format!("Password reset token created for user: {}", username));
+    Ok(reset_token)
+}
+
+// Exchanges a password reset token for a new password. The token can only be used once, and all existing sessions are
+// invalidated, as they may belong to whoever the admin was locked out by. Returns the username, or None if the token is invalid or expired
+pub fn reset_password_with_token(token: &str, new_password: &str) -> Result<Option<String>, String> {
+    if new_password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH));
+    }
+
+    let connection = get_database_connection()?;
+    let mut statement = connection
+        .prepare("SELECT username, expires_at FROM password_reset_tokens WHERE token = ?")
+        .map_err(|e| format!("Failed to prepare password reset token statement: {}", e))?;
+    statement.bind((1, token)).map_err(|e| format!("Failed to bind password reset token: {}", e))?;
+
+    let (username, expires_at_str): (String, String) = match statement.next().map_err(|e| format!("Failed to execute password reset token query: {}", e))? {
+        sqlite::State::Row => (
+            statement.read(0).map_err(|e| format!("Failed to read username: {}", e))?,
+            statement.read(1).map_err(|e| format!("Failed to read expires_at: {}", e))?,
+        ),
+        sqlite::State::Done => return Ok(None),
+    };
+    drop(statement);
+
+    // The token is used up, whether or not it is still valid
+    connection
+        .execute(format!("DELETE FROM password_reset_tokens WHERE token = '{}'", token.replace("'", "''")))
+        .map_err(|e| format!("Failed to remove password reset token: {}", e))?;
+
+    let expires_at = DateTime::parse_from_rfc3339(&expires_at_str)
+        .map_err(|e| format!("Failed to parse expires_at: {}", e))?
+        .with_timezone(&Utc);
+    if expires_at <= Utc::now() {
+        return Ok(None);
+    }
+
+    let password_hash = bcrypt::hash(new_password, bcrypt::DEFAULT_COST).map_err(|e| format!("Failed to hash password: {}", e))?;
+    let escaped_username = username.replace("'", "''");
+    connection
+        .execute(format!("UPDATE users SET password_hash = '{}' WHERE username = '{}'", password_hash, escaped_username))
+        .map_err(|e| format!("Failed to update password: {}", e))?;
+    connection.execute("DELETE FROM sessions").map_err(|e| format!("Failed to invalidate sessions: {}", e))?;
+
+    info(format!("Password reset with token for user: {}, all sessions invalidated", username));
+    Ok(Some(username))
+}
+
 fn get_random_hashed_password() -> Result<(String, String), ()> {
     let random_password = generate_password(true, true, false, 20);
     let password_hash_result = bcrypt::hash(&random_password, bcrypt::DEFAULT_COST);
@@ -114,7 +293,7 @@ fn get_random_hashed_password() -> Result<(String, String), ()> {
         Ok(hash) => hash,
         Err(_) => {
             error("Failed to hash password");
-            return Err(())
+            return Err(());
         }
     };
     Ok((random_password, password_hash))
@@ -124,7 +303,7 @@ pub fn authenticate_user(username: &str, password: &str) -> Result<Option<User>,
     let connection = get_database_connection()?;
 
     let mut statement = connection
-        .prepare("SELECT id, username, password_hash, created_at, last_login, is_active FROM users WHERE username = ? AND is_active = 1")
+        .prepare("SELECT id, username, password_hash, created_at, last_login, is_active, role FROM users WHERE username = ? AND is_active = 1")
         .map_err(|e| format!("Failed to prepare authentication statement: {}", e))?;
 
     statement.bind((1, username)).map_err(|e| format!("Failed to bind username: {}", e))?;
@@ -138,6 +317,9 @@ pub fn authenticate_user(username: &str, password: &str) -> Result<Option<User>,
             let last_login_str: Option<String> = statement.read(4).map_err(|e| format!("Failed to read last_login: {}", e))?;
             let is_active: i64 = statement.read(5).map_err(|e| format!("Failed to read is_active: {}", e))?;
             let is_active = is_active != 0;
+            let role_str: String = statement.read(6).map_err(|e| format!("Failed to read role: {}", e))?;
+            // An unknown role gets the least access
+            let role = UserRole::from_name(&role_str).unwrap_or(UserRole::SiteOwner);
 
             // Verify password
             let password_valid = bcrypt::verify(password, &password_hash).map_err(|e| format!("Failed to verify password: {}", e))?;
@@ -165,6 +347,7 @@ pub fn authenticate_user(username: &str, password: &str) -> Result<Option<User>,
                     created_at,
                     last_login,
                     is_active,
+                    role,
                 }))
             } else {
                 Ok(None) // Invalid password
@@ -174,13 +357,19 @@ pub fn authenticate_user(username: &str, password: &str) -> Result<Option<User>,
     }
 }
 
-pub fn create_session(user: &User) -> Result<Session, String> {
+// Creates a session for the user, which expires after the absolute timeout regardless of activity. With a maximum number of sessions,
+// the oldest sessions of the user beyond it are ended
+pub fn create_session(user: &User, absolute_timeout: Duration, max_sessions: u32, token_bytes: u32) -> Result<Session, String> {
+    if is_database_read_only() {
+        return Err("Sessions cannot be created, as the database is read-only".to_string());
+    }
     let connection = get_database_connection()?;
+    let site_ids = get_owned_site_ids(&connection, user.id)?;
 
     let session_id = Uuid::new_v4().to_string();
-    let token = Uuid::new_v4().to_string();
+    let token = generate_session_token(token_bytes);
     let created_at = Utc::now();
-    let expires_at = created_at + Duration::hours(24); // Session expires in 24 hours
+    let expires_at = created_at + absolute_timeout;
 
     let session = Session {
         id: session_id.clone(),
@@ -189,37 +378,73 @@ pub fn create_session(user: &User) -> Result<Session, String> {
         token: token.clone(),
         expires_at,
         created_at,
+        last_activity_at: created_at,
+        csrf_token: Uuid::new_v4().to_string(),
+        role: user.role,
+        site_ids,
     };
 
     connection
         .execute(format!(
-            "INSERT INTO sessions (id, user_id, username, token, expires_at, created_at) VALUES ('{}', {}, '{}', '{}', '{}', '{}')",
+            "INSERT INTO sessions (id, user_id, username, token, expires_at, created_at, last_activity_at, csrf_token) VALUES ('{}', {}, '{}', '{}', '{}', '{}', '{}', '{}')",
             session.id,
             session.user_id,
             session.username,
             session.token,
             session.expires_at.to_rfc3339(),
-            session.created_at.to_rfc3339()
+            session.created_at.to_rfc3339(),
+            session.last_activity_at.to_rfc3339(),
+            session.csrf_token
         ))
         .map_err(|e| format!("Failed to create session: {}", e))?;
 
     info(format!("Created session for user: {}", user.username));
+    if max_sessions > 0 {
+        end_oldest_sessions(&connection, user, max_sessions)?;
+    }
     Ok(session)
 }
 
-pub fn verify_session_token(token: &str) -> Result<Option<Session>, String> {
-    let connection = get_database_connection()?;
+// Hex encoded random bytes from the OS seeded generator
+fn generate_session_token(token_bytes: u32) -> String {
+    let mut bytes = vec![0u8; token_bytes as usize];
+    rand::fill(bytes.as_mut_slice());
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn end_oldest_sessions(connection: &Connection, user: &User, max_sessions: u32) -> Result<(), String> {
+    connection
+        .execute(format!(
+            "DELETE FROM sessions WHERE user_id = {0} AND id NOT IN (SELECT id FROM sessions WHERE user_id = {0} ORDER BY created_at DESC LIMIT {1})",
+            user.id, max_sessions
+        ))
+        .map_err(|e| format!("Failed to end the oldest sessions of user {}: {}", user.username, e))?;
+    let ended = connection.change_count();
+    if ended > 0 {
+        info(format!("Ended the {} oldest session(s) of user {}, who has more than {} sessions", ended, user.username, max_sessions));
+    }
+    Ok(())
+}
 
-    // Clean up expired sessions first
-    cleanup_expired_sessions(&connection)?;
+// Verifies the session token and records the activity, at most once a minute. With an idle timeout, sessions not used within it are expired and removed.
+// Expired sessions nobody uses any more are removed by the session cleanup task
+pub fn verify_session_token(token: &str, idle_timeout: Option<Duration>) -> Result<Option<Session>, String> {
+    // No token we hand out is longer, so such a token is not looked up
+    if token.is_empty() || token.len() > MAX_SESSION_TOKEN_BYTES as usize * 2 {
+        return Ok(None);
+    }
+    let connection = get_database_connection()?;
 
     let mut statement = connection
-        .prepare("SELECT id, user_id, username, token, expires_at, created_at FROM sessions WHERE token = ?")
+        .prepare(
+            "SELECT sessions.id, sessions.user_id, sessions.username, sessions.token, sessions.expires_at, sessions.created_at, sessions.last_activity_at, sessions.csrf_token, users.role \
+             FROM sessions JOIN users ON users.id = sessions.user_id WHERE sessions.token = ? AND users.is_active = 1",
+        )
         .map_err(|e| format!("Failed to prepare session verification statement: {}", e))?;
 
     statement.bind((1, token)).map_err(|e| format!("Failed to bind session token: {}", e))?;
 
-    match statement.next().map_err(|e| format!("Failed to execute session verification query: {}", e))? {
+    let session = match statement.next().map_err(|e| format!("Failed to execute session verification query: {}", e))? {
         sqlite::State::Row => {
             let id: String = statement.read(0).map_err(|e| format!("Failed to read session id: {}", e))?;
             let user_id: i64 = statement.read(1).map_err(|e| format!("Failed to read user_id: {}", e))?;
@@ -227,6 +452,9 @@ pub fn verify_session_token(token: &str) -> Result<Option<Session>, String> {
             let session_token: String = statement.read(3).map_err(|e| format!("Failed to read token: {}", e))?;
             let expires_at_str: String = statement.read(4).map_err(|e| format!("Failed to read expires_at: {}", e))?;
             let created_at_str: String = statement.read(5).map_err(|e| format!("Failed to read created_at: {}", e))?;
+            let last_activity_at_str: String = statement.read(6).map_err(|e| format!("Failed to read last_activity_at: {}", e))?;
+            let csrf_token: String = statement.read(7).map_err(|e| format!("Failed to read csrf_token: {}", e))?;
+            let role_str: String = statement.read(8).map_err(|e| format!("Failed to read role: {}", e))?;
 
             let expires_at = DateTime::parse_from_rfc3339(&expires_at_str)
                 .map_err(|e| format!("Failed to parse expires_at: {}", e))?
@@ -236,22 +464,54 @@ pub fn verify_session_token(token: &str) -> Result<Option<Session>, String> {
                 .map_err(|e| format!("Failed to parse created_at: {}", e))?
                 .with_timezone(&Utc);
 
-            // Check if session is still valid (not expired)
-            if expires_at > Utc::now() {
-                Ok(Some(Session {
-                    id,
-                    user_id,
-                    username,
-                    token: session_token,
-                    expires_at,
-                    created_at,
-                }))
-            } else {
-                Ok(None) // Session expired
+            // Sessions created before activity was tracked count as active since they were created
+            let last_activity_at = DateTime::parse_from_rfc3339(&last_activity_at_str).map(|t| t.with_timezone(&Utc)).unwrap_or(created_at);
+
+            Session {
+                id,
+                user_id,
+                username,
+                token: session_token,
+                expires_at,
+                created_at,
+                last_activity_at,
+                csrf_token,
+                // An unknown role gets the least access
+                role: UserRole::from_name(&role_str).unwrap_or(UserRole::SiteOwner),
+                site_ids: Vec::new(),
             }
         }
-        sqlite::State::Done => Ok(None), // Session not found
+        sqlite::State::Done => return Ok(None), // Session not found
+    };
+    drop(statement);
+    // Ownership is looked up on every request, so changes to it apply to existing sessions right away
+    let session = Session {
+        site_ids: get_owned_site_ids(&connection, session.user_id)?,
+        ..session
+    };
+
+    // Check if session is still valid (not expired)
+    let now = Utc::now();
+    if session.expires_at <= now {
+        return Ok(None);
+    }
+    if let Some(idle_timeout) = idle_timeout
+        && session.last_activity_at + idle_timeout <= now
+    {
+        info(format!("Session for user {} expired after being idle", session.username));
+        invalidate_session(&session.token)?;
+        return Ok(None);
     }
+
+    // The session is only written when its recorded activity is out of date
+    if now - session.last_activity_at < Duration::seconds(SESSION_ACTIVITY_WRITE_INTERVAL_SECONDS) {
+        return Ok(Some(session));
+    }
+    connection
+        .execute(format!("UPDATE sessions SET last_activity_at = '{}' WHERE id = '{}'", now.to_rfc3339(), session.id.replace("'", "''")))
+        .map_err(|e| format!("Failed to update session activity: {}", e))?;
+
+    Ok(Some(Session { last_activity_at: now, ..session }))
 }
 
 pub fn invalidate_session(token: &str) -> Result<bool, String> {
@@ -282,42 +542,181 @@ pub fn invalidate_session(token: &str) -> Result<bool, String> {
     }
 }
 
-fn cleanup_expired_sessions(connection: &Connection) -> Result<(), String> {
-    let now = Utc::now().to_rfc3339();
-    connection
-        .execute(format!("DELETE FROM sessions WHERE expires_at < '{}'", now))
-        .map_err(|e| format!("Failed to cleanup expired sessions: {}", e))?;
-
-    Ok(())
+fn get_owned_site_ids(connection: &Connection, user_id: i64) -> Result<Vec<String>, String> {
+    let mut statement = connection
+        .prepare("SELECT site_id FROM site_owners WHERE user_id = ? ORDER BY site_id")
+        .map_err(|e| format!("Failed to prepare site owners statement: {}", e))?;
+    statement.bind((1, user_id)).map_err(|e| format!("Failed to bind user id: {}", e))?;
+    let mut site_ids = Vec::new();
+    while let sqlite::State::Row = statement.next().map_err(|e| format!("Failed to execute site owners query: {}", e))? {
+        site_ids.push(statement.read::<String, _>(0).map_err(|e| format!("Failed to read site_id: {}", e))?);
+    }
+    Ok(site_ids)
 }
 
-pub fn cleanup_all_expired_sessions() -> Result<u64, String> {
+pub fn list_users() -> Result<Vec<UserInfo>, String> {
     let connection = get_database_connection()?;
+    let mut statement = connection
+        .prepare("SELECT id, username, role, is_active, created_at, last_login FROM users ORDER BY username")
+        .map_err(|e| format!("Failed to prepare user list statement: {}", e))?;
+
+    let mut users = Vec::new();
+    while let sqlite::State::Row = statement.next().map_err(|e| format!("Failed to execute user list query: {}", e))? {
+        let user_id: i64 = statement.read(0).map_err(|e| format!("Failed to read user id: {}", e))?;
+        let role_str: String = statement.read(2).map_err(|e| format!("Failed to read role: {}", e))?;
+        let is_active: i64 = statement.read(3).map_err(|e| format!("Failed to read is_active: {}", e))?;
+        users.push(UserInfo {
+            username: statement.read(1).map_err(|e| format!("Failed to read username: {}", e))?,
+            role: UserRole::from_name(&role_str).unwrap_or(UserRole::SiteOwner),
+            site_ids: get_owned_site_ids(&connection, user_id)?,
+            is_active: is_active != 0,
+            created_at: statement.read(4).map_err(|e| format!("Failed to read created_at: {}", e))?,
+            last_login: statement.read(5).map_err(|e| format!("Failed to read last_login: {}", e))?,
+        });
+    }
+    Ok(users)
+}
 
-    // First count the expired sessions
-    let now = Utc::now().to_rfc3339();
+fn get_user_id(connection: &Connection, username: &str) -> Result<Option<i64>, String> {
     let mut statement = connection
-        .prepare("SELECT COUNT(*) FROM sessions WHERE expires_at < ?")
-        .map_err(|e| format!("Failed to prepare expired sessions count statement: {}", e))?;
+        .prepare("SELECT id FROM users WHERE username = ?")
+        .map_err(|e| format!("Failed to prepare user lookup statement: {}", e))?;
+    statement.bind((1, username)).map_err(|e| format!("Failed to bind username: {}", e))?;
+    match statement.next().map_err(|e| format!("Failed to execute user lookup query: {}", e))? {
+        sqlite::State::Row => Ok(Some(statement.read::<i64, _>(0).map_err(|e| format!("Failed to read user id: {}", e))?)),
+        sqlite::State::Done => Ok(None),
+    }
+}
 
-    statement.bind((1, now.as_str())).map_err(|e| format!("Failed to bind expiration time: {}", e))?;
+fn count_admins(connection: &Connection) -> Result<i64, String> {
+    let mut statement = connection
+        .prepare("SELECT COUNT(*) FROM users WHERE role = 'admin' AND is_active = 1")
+        .map_err(|e| format!("Failed to prepare admin count statement: {}", e))?;
+    match statement.next().map_err(|e| format!("Failed to execute admin count query: {}", e))? {
+        sqlite::State::Row => statement.read::<i64, _>(0).map_err(|e| format!("Failed to read admin count: {}", e)),
+        sqlite::State::Done => Ok(0),
+    }
+}
 
-    let expired_count = match statement.next().map_err(|e| format!("Failed to execute expired sessions count query: {}", e))? {
-        sqlite::State::Row => {
-            let count: i64 = statement.read(0).map_err(|e| format!("Failed to read expired sessions count: {}", e))?;
-            count as u64
+// Creates the user or updates an existing one. Sessions of an updated user are invalidated, so they log in again with their new access.
+// Returns true if the user was created
+pub fn save_user(user_request: &UserRequest) -> Result<bool, Vec<String>> {
+    let mut errors = Vec::new();
+    if let Err(e) = validate_username(&user_request.username) {
+        errors.push(e);
+    }
+    if let Some(password) = &user_request.password
+        && password.chars().count() < MIN_PASSWORD_LENGTH
+    {
+        errors.push(format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH));
+    }
+    if user_request.role == UserRole::SiteOwner && user_request.site_ids.is_empty() {
+        errors.push("Site owners must own at least one site".to_string());
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let connection = get_database_connection().map_err(|e| vec![e])?;
+    let escaped_username = user_request.username.replace("'", "''");
+    let existing_user_id = get_user_id(&connection, &user_request.username).map_err(|e| vec![e])?;
+    let user_id = match existing_user_id {
+        Some(user_id) => {
+            // The last admin cannot be turned into a site owner, or nobody could manage the server anymore
+            if user_request.role != UserRole::Admin && count_admins(&connection).map_err(|e| vec![e])? <= 1 && is_admin_user(&connection, user_id).map_err(|e| vec![e])? {
+                return Err(vec!["The last admin cannot be made a site owner".to_string()]);
+            }
+            connection
+                .execute(format!("UPDATE users SET role = '{}' WHERE id = {}", user_request.role.get_name(), user_id))
+                .map_err(|e| vec![format!("Failed to update user: {}", e)])?;
+            if let Some(password) = &user_request.password {
+                let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| vec![format!("Failed to hash password: {}", e)])?;
+                connection
+                    .execute(format!("UPDATE users SET password_hash = '{}' WHERE id = {}", password_hash, user_id))
+                    .map_err(|e| vec![format!("Failed to update password: {}", e)])?;
+            }
+            invalidate_sessions_for_user(&connection, &escaped_username).map_err(|e| vec![e])?;
+            user_id
+        }
+        None => {
+            let password = user_request.password.as_deref().ok_or_else(|| vec!["A password is required for new users".to_string()])?;
+            let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| vec![format!("Failed to hash password: {}", e)])?;
+            connection
+                .execute(format!(
+                    "INSERT INTO users (username, password_hash, created_at, is_active, role) VALUES ('{}', '{}', '{}', 1, '{}')",
+                    escaped_username,
+                    password_hash,
+                    Utc::now().to_rfc3339(),
+                    user_request.role.get_name()
+                ))
+                .map_err(|e| vec![format!("Failed to create user: {}", e)])?;
+            get_user_id(&connection, &user_request.username)
+                .map_err(|e| vec![e])?
+                .ok_or_else(|| vec!["Failed to create user".to_string()])?
         }
-        sqlite::State::Done => 0,
     };
 
-    // Drop statement before using connection again
-    drop(statement);
+    // Admins have access to all sites, so only site owners keep a list
+    connection
+        .execute(format!("DELETE FROM site_owners WHERE user_id = {}", user_id))
+        .map_err(|e| vec![format!("Failed to update site owners: {}", e)])?;
+    if user_request.role == UserRole::SiteOwner {
+        for site_id in &user_request.site_ids {
+            connection
+                .execute(format!("INSERT OR IGNORE INTO site_owners (user_id, site_id) VALUES ({}, '{}')", user_id, site_id.replace("'", "''")))
+                .map_err(|e| vec![format!("Failed to update site owners: {}", e)])?;
+        }
+    }
+
+    info(format!("User '{}' saved with role {}", user_request.username, user_request.role.get_name()));
+    Ok(existing_user_id.is_none())
+}
+
+fn is_admin_user(connection: &Connection, user_id: i64) -> Result<bool, String> {
+    let mut statement = connection
+        .prepare("SELECT role FROM users WHERE id = ?")
+        .map_err(|e| format!("Failed to prepare user role statement: {}", e))?;
+    statement.bind((1, user_id)).map_err(|e| format!("Failed to bind user id: {}", e))?;
+    match statement.next().map_err(|e| format!("Failed to execute user role query: {}", e))? {
+        sqlite::State::Row => Ok(statement.read::<String, _>(0).map_err(|e| format!("Failed to read role: {}", e))? == UserRole::Admin.get_name()),
+        sqlite::State::Done => Ok(false),
+    }
+}
+
+// Deletes the user with their sessions and site ownership. Returns false if there is no such user
+pub fn delete_user(username: &str) -> Result<bool, String> {
+    let connection = get_database_connection()?;
+    let user_id = match get_user_id(&connection, username)? {
+        Some(user_id) => user_id,
+        None => return Ok(false),
+    };
+    if is_admin_user(&connection, user_id)? && count_admins(&connection)? <= 1 {
+        return Err("The last admin cannot be deleted".to_string());
+    }
 
-    // Delete expired sessions
     connection
-        .execute(format!("DELETE FROM sessions WHERE expires_at < '{}'", now))
-        .map_err(|e| format!("Failed to cleanup expired sessions: {}", e))?;
+        .execute(format!("DELETE FROM users WHERE id = {}", user_id))
+        .map_err(|e| format!("Failed to delete user: {}", e))?;
+    info(format!("User '{}' deleted", username));
+    Ok(true)
+}
+
+// Removes the sessions past their absolute timeout, and with an idle timeout, the ones not used within it
+pub fn cleanup_all_expired_sessions(idle_timeout: Option<Duration>) -> Result<u64, String> {
+    let connection = get_database_connection()?;
+
+    let now = Utc::now();
+    let mut query = format!("DELETE FROM sessions WHERE expires_at < '{}'", now.to_rfc3339());
+    if let Some(idle_timeout) = idle_timeout {
+        // Sessions created before activity was tracked count as active since they were created
+        query.push_str(&format!(
+            " OR (CASE last_activity_at WHEN '' THEN created_at ELSE last_activity_at END) < '{}'",
+            (now - idle_timeout).to_rfc3339()
+        ));
+    }
+    connection.execute(query).map_err(|e| format!("Failed to cleanup expired sessions: {}", e))?;
 
+    let expired_count = connection.change_count() as u64;
     if expired_count > 0 {
         info(format!("Cleaned up {} expired sessions", expired_count));
     }
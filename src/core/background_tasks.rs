@@ -1,10 +1,32 @@
+use crate::core::email::{get_email_queue, run_email_alerts};
 use crate::core::monitoring::get_monitoring_state;
-use crate::core::os_signal::start_os_signal_handling;
+use crate::core::site_statistics::get_site_statistics;
+use crate::core::triggers::get_trigger_handler;
+use crate::database::database_maintenance::start_database_maintenance_task;
+use crate::file::disk_cache::start_disk_cache_eviction_task;
+use crate::http::sitemap::start_sitemap_refresh_task;
+use crate::tls::certificate_expiry::start_certificate_expiry_task;
 
+// The tasks every server needs, whether it runs as the gruxi binary or embedded in another application. Signal handling and the
+// control socket are left to GruxServerBuilder::handle_os_signals, as they belong to the process
 pub async fn start_background_tasks() {
-    // Start the OS signal handling
-    start_os_signal_handling();
-
     // Init monitoring and start background task
     get_monitoring_state().await.initialize_monitoring();
+
+    // Load the site statistics of the last run and save them periodically
+    get_site_statistics().initialize();
+
+    // Send the queued email in the background, such as alerts about handlers and notices about certificates about to expire
+    get_email_queue().initialize();
+    tokio::spawn(run_email_alerts(get_trigger_handler().subscribe()));
+    start_certificate_expiry_task();
+
+    // Remove expired sessions and compact the database now and then
+    start_database_maintenance_task();
+
+    // Keep the generated sitemaps up to date with the files of the sites
+    start_sitemap_refresh_task();
+
+    // Keep the disk caches within their size and age limits
+    start_disk_cache_eviction_task();
 }
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+
+use crate::http::request_handlers::processors::proxy_helpers::no_verifier::NoVerifier;
+use crate::tls::tls_config::tls_config;
+
+// Settings for a single benchmark run, as given on the command line
+#[derive(Debug, Clone)]
+pub struct BenchmarkSettings {
+    pub url: String,
+    pub connections: usize,
+    pub duration: Duration,
+    pub http2: bool,
+    pub insecure: bool,
+}
+
+// Result from a single worker, merged into the final report afterwards
+#[derive(Debug, Default)]
+pub struct BenchmarkWorkerResult {
+    pub latencies_micros: Vec<u64>,
+    pub status_codes: BTreeMap<u16, u64>,
+    pub errors: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct BenchmarkReport {
+    pub total_requests: u64,
+    pub errors: u64,
+    pub bytes_received: u64,
+    pub elapsed: Duration,
+    pub status_codes: BTreeMap<u16, u64>,
+    pub latency_min_micros: u64,
+    pub latency_avg_micros: u64,
+    pub latency_p50_micros: u64,
+    pub latency_p90_micros: u64,
+    pub latency_p99_micros: u64,
+    pub latency_max_micros: u64,
+}
+
+impl BenchmarkReport {
+    pub fn from_worker_results(results: Vec<BenchmarkWorkerResult>, elapsed: Duration) -> Self {
+        let mut report = BenchmarkReport { elapsed, ..Default::default() };
+        let mut latencies: Vec<u64> = Vec::new();
+
+        for result in results {
+            report.errors += result.errors;
+            report.bytes_received += result.bytes_received;
+            for (status, count) in result.status_codes {
+                *report.status_codes.entry(status).or_insert(0) += count;
+            }
+            latencies.extend(result.latencies_micros);
+        }
+
+        report.total_requests = latencies.len() as u64;
+        if latencies.is_empty() {
+            return report;
+        }
+
+        latencies.sort_unstable();
+        let sum: u64 = latencies.iter().sum();
+        report.latency_min_micros = latencies[0];
+        report.latency_max_micros = latencies[latencies.len() - 1];
+        report.latency_avg_micros = sum / latencies.len() as u64;
+        report.latency_p50_micros = percentile(&latencies, 50.0);
+        report.latency_p90_micros = percentile(&latencies, 90.0);
+        report.latency_p99_micros = percentile(&latencies, 99.0);
+        report
+    }
+
+    pub fn requests_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds <= 0.0 {
+            return 0.0;
+        }
+        self.total_requests as f64 / seconds
+    }
+
+    pub fn print(&self, settings: &BenchmarkSettings) {
+        let protocol = if settings.http2 { "HTTP/2" } else { "HTTP/1.1" };
+        println!("Benchmark of {} ({}, {} connections, {:.1}s)", settings.url, protocol, settings.connections, self.elapsed.as_secs_f64());
+        println!("  Requests:      {}", self.total_requests);
+        println!("  Errors:        {}", self.errors);
+        println!("  Throughput:    {:.2} req/s", self.requests_per_second());
+        println!("  Transfer:      {:.2} KB/s", self.bytes_received as f64 / 1024.0 / self.elapsed.as_secs_f64().max(0.001));
+        println!(
+            "  Latency:       min {} / avg {} / max {}",
+            format_micros(self.latency_min_micros),
+            format_micros(self.latency_avg_micros),
+            format_micros(self.latency_max_micros)
+        );
+        println!(
+            "  Percentiles:   p50 {} / p90 {} / p99 {}",
+            format_micros(self.latency_p50_micros),
+            format_micros(self.latency_p90_micros),
+            format_micros(self.latency_p99_micros)
+        );
+        for (status, count) in &self.status_codes {
+            println!("  Status {}:    {}", status, count);
+        }
+    }
+}
+
+// Nearest-rank percentile on an already sorted list
+fn percentile(sorted: &[u64], pct: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    let index = rank.clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+fn format_micros(micros: u64) -> String {
+    if micros >= 1_000_000 {
+        format!("{:.2}s", micros as f64 / 1_000_000.0)
+    } else if micros >= 1_000 {
+        format!("{:.2}ms", micros as f64 / 1_000.0)
+    } else {
+        format!("{}us", micros)
+    }
+}
+
+// Parses durations like "30s", "500ms", "2m" or a plain number of seconds
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, multiplier_ms) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 1)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1_000)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60_000)
+    } else if let Some(n) = s.strip_suffix('h') {
+        (n, 3_600_000)
+    } else {
+        (s, 1_000)
+    };
+
+    let value: u64 = number.trim().parse().map_err(|_| format!("Invalid duration: {}", s))?;
+    if value == 0 {
+        return Err("Duration must be greater than zero".to_string());
+    }
+    Ok(Duration::from_millis(value * multiplier_ms))
+}
+
+pub async fn run_benchmark(settings: BenchmarkSettings) -> Result<BenchmarkReport, String> {
+    let uri: hyper::Uri = settings.url.parse().map_err(|e| format!("Invalid url '{}': {}", settings.url, e))?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") => {}
+        _ => return Err(format!("Url must start with http:// or https://: {}", settings.url)),
+    }
+    if settings.connections == 0 {
+        return Err("Number of connections must be at least 1".to_string());
+    }
+
+    let mut client_tls_config = tls_config();
+    if settings.insecure {
+        client_tls_config.dangerous().set_certificate_verifier(Arc::new(NoVerifier));
+    }
+
+    let connector_builder = hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(client_tls_config).https_or_http();
+    let connector = if settings.http2 {
+        connector_builder.enable_http2().build()
+    } else {
+        connector_builder.enable_http1().build()
+    };
+
+    let start = Instant::now();
+    let deadline = start + settings.duration;
+    let mut handles = Vec::with_capacity(settings.connections);
+
+    for _ in 0..settings.connections {
+        // Each worker gets its own client, so its own connection pool and thereby its own connection
+        let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new()).http2_only(settings.http2).pool_max_idle_per_host(1).build(connector.clone());
+        let uri = uri.clone();
+
+        handles.push(tokio::spawn(async move {
+            let mut result = BenchmarkWorkerResult::default();
+            while Instant::now() < deadline {
+                let request = match hyper::Request::builder().method("GET").uri(uri.clone()).body(Empty::<Bytes>::new()) {
+                    Ok(request) => request,
+                    Err(_) => {
+                        result.errors += 1;
+                        break;
+                    }
+                };
+
+                let request_start = Instant::now();
+                let remaining = deadline.saturating_duration_since(request_start);
+                let response = tokio::time::timeout(remaining, async {
+                    let response = client.request(request).await.map_err(|_| ())?;
+                    let status = response.status().as_u16();
+                    let body = response.into_body().collect().await.map_err(|_| ())?;
+                    Ok::<(u16, usize), ()>((status, body.to_bytes().len()))
+                })
+                .await;
+
+                match response {
+                    Ok(Ok((status, body_size))) => {
+                        result.latencies_micros.push(request_start.elapsed().as_micros() as u64);
+                        *result.status_codes.entry(status).or_insert(0) += 1;
+                        result.bytes_received += body_size as u64;
+                    }
+                    Ok(Err(_)) => result.errors += 1,
+                    // Deadline reached while the request was in flight, so it is not counted
+                    Err(_) => break,
+                }
+            }
+            result
+        }));
+    }
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        match handle.await {
+            Ok(result) => results.push(result),
+            Err(e) => return Err(format!("Benchmark worker failed: {}", e)),
+        }
+    }
+
+    Ok(BenchmarkReport::from_worker_results(results, start.elapsed()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("abc").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&values, 50.0), 50);
+        assert_eq!(percentile(&values, 90.0), 90);
+        assert_eq!(percentile(&values, 99.0), 99);
+        assert_eq!(percentile(&values, 100.0), 100);
+        assert_eq!(percentile(&[], 50.0), 0);
+    }
+
+    #[test]
+    fn test_report_merges_worker_results() {
+        let worker1 = BenchmarkWorkerResult {
+            latencies_micros: vec![100, 200],
+            status_codes: BTreeMap::from([(200, 2)]),
+            errors: 0,
+            bytes_received: 10,
+        };
+        let worker2 = BenchmarkWorkerResult {
+            latencies_micros: vec![300],
+            status_codes: BTreeMap::from([(200, 1)]),
+            errors: 1,
+            bytes_received: 0,
+        };
+
+        let report = BenchmarkReport::from_worker_results(vec![worker1, worker2], Duration::from_secs(1));
+        assert_eq!(report.total_requests, 3);
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.bytes_received, 10);
+        assert_eq!(report.status_codes.get(&200), Some(&3));
+        assert_eq!(report.latency_min_micros, 100);
+        assert_eq!(report.latency_max_micros, 300);
+        assert_eq!(report.latency_avg_micros, 200);
+        assert_eq!(report.requests_per_second(), 3.0);
+    }
+}
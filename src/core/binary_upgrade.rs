@@ -0,0 +1,255 @@
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::core::graceful_shutdown::drain_and_shutdown;
+use crate::http::holding_listener::{HoldingMode, get_holding_listeners};
+use crate::logging::syslog::{error, info, trace, warn};
+use dashmap::DashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+#[cfg(unix)]
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+// Restarting into a new Gruxi binary without closing the listeners. The new process is started with the listening sockets of this one,
+// and once it is serving on them, this process stops accepting connections, finishes the requests in progress and exits.
+// Triggered by SIGUSR2 or the admin API. Listeners from systemd socket activation (LISTEN_FDS) are taken over the same way at startup.
+
+// The file descriptors of the listening sockets handed over to the new process, comma separated
+pub const LISTEN_FDS_ENV: &str = "GRUXI_LISTEN_FDS";
+// The new process writes a byte to this file descriptor once it is serving
+pub const UPGRADE_READY_FD_ENV: &str = "GRUXI_UPGRADE_READY_FD";
+
+// systemd passes the sockets from file descriptor 3 and up
+#[cfg(unix)]
+const SYSTEMD_LISTEN_FDS_START: i32 = 3;
+#[cfg(unix)]
+const UPGRADE_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+static UPGRADE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+static ACTIVE_LISTENERS: OnceLock<DashMap<SocketAddr, Weak<TcpListener>>> = OnceLock::new();
+
+// The listeners of the bindings, to hand over in an upgrade. Listeners of bindings that were removed are gone once dropped
+pub fn register_active_listener(address: SocketAddr, listener: &Arc<TcpListener>) {
+    ACTIVE_LISTENERS.get_or_init(DashMap::new).insert(address, Arc::downgrade(listener));
+}
+
+// Starts the upgrade in the background. Fails right away if an upgrade is already running or not supported
+pub fn begin_binary_upgrade() -> Result<(), String> {
+    if !cfg!(unix) {
+        return Err("Binary upgrades are only supported on Unix".to_string());
+    }
+    if UPGRADE_IN_PROGRESS.swap(true, Ordering::SeqCst) {
+        return Err("A binary upgrade is already in progress".to_string());
+    }
+
+    tokio::spawn(async {
+        match run_binary_upgrade().await {
+            Ok(pid) => {
+                info(format!("New Gruxi process {} is serving, draining connections before shutting down", pid));
+                drain_and_shutdown().await;
+            }
+            Err(e) => {
+                error(format!("Binary upgrade failed, continuing with the current process: {}", e));
+                UPGRADE_IN_PROGRESS.store(false, Ordering::SeqCst);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn run_binary_upgrade() -> Result<u32, String> {
+    Err("Binary upgrades are only supported on Unix".to_string())
+}
+
+// Starts the new process with the listeners and waits for it to be serving
+#[cfg(unix)]
+async fn run_binary_upgrade() -> Result<u32, String> {
+    use std::io::Read;
+    use std::os::fd::{AsRawFd, RawFd};
+    use std::os::unix::process::CommandExt;
+
+    let listeners: Vec<Arc<TcpListener>> = ACTIVE_LISTENERS.get_or_init(DashMap::new).iter().filter_map(|entry| entry.value().upgrade()).collect();
+    if listeners.is_empty() {
+        return Err("There are no listeners to hand over".to_string());
+    }
+    let listener_fds: Vec<RawFd> = listeners.iter().map(|listener| listener.as_raw_fd()).collect();
+
+    let (mut ready_reader, ready_writer) = std::io::pipe().map_err(|e| format!("Failed to create the ready pipe: {}", e))?;
+    let ready_fd = ready_writer.as_raw_fd();
+
+    let executable = std::env::current_exe().map_err(|e| format!("Failed to get the path of the Gruxi binary: {}", e))?;
+    let mut command = std::process::Command::new(&executable);
+    command
+        .args(std::env::args_os().skip(1))
+        .env(LISTEN_FDS_ENV, listener_fds.iter().map(|fd| fd.to_string()).collect::<Vec<String>>().join(","))
+        .env(UPGRADE_READY_FD_ENV, ready_fd.to_string())
+        .env_remove("LISTEN_FDS")
+        .env_remove("LISTEN_PID")
+        .env_remove("LISTEN_FDNAMES");
+
+    // The file descriptors are close-on-exec, which is cleared in the new process only, between fork and exec
+    let mut inherited_fds = listener_fds.clone();
+    inherited_fds.push(ready_fd);
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &inherited_fds {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+
+    info(format!("Starting binary upgrade to {} with {} listeners", executable.display(), listener_fds.len()));
+    let mut child = command.spawn().map_err(|e| format!("Failed to start {}: {}", executable.display(), e))?;
+    let pid = child.id();
+    drop(ready_writer);
+
+    // The read ends with nothing read if the new process exits before it is serving
+    let ready_read = tokio::task::spawn_blocking(move || {
+        let mut buffer = [0u8; 1];
+        ready_reader.read(&mut buffer).map(|bytes_read| bytes_read == 1)
+    });
+    match tokio::time::timeout(UPGRADE_READY_TIMEOUT, ready_read).await {
+        Ok(Ok(Ok(true))) => Ok(pid),
+        Ok(_) => {
+            let status = child.try_wait().ok().flatten().map(|s| s.to_string()).unwrap_or_else(|| "still running".to_string());
+            let _ = child.kill();
+            Err(format!("New process {} stopped before it was serving ({})", pid, status))
+        }
+        Err(_) => {
+            let _ = child.kill();
+            Err(format!("New process {} was not serving within {} seconds", pid, UPGRADE_READY_TIMEOUT.as_secs()))
+        }
+    }
+}
+
+// Holds the listeners passed on from the previous process or systemd for the bindings, so the server starts on them instead of binding
+pub async fn adopt_inherited_listeners() {
+    let inherited_listeners = take_inherited_listeners();
+    if inherited_listeners.is_empty() {
+        return;
+    }
+
+    let configuration = get_cached_configuration().get_configuration().await;
+    for listener in inherited_listeners {
+        let Ok(address) = listener.local_addr() else {
+            continue;
+        };
+        let binding = configuration.bindings.iter().find(|b| b.ip.parse::<IpAddr>().is_ok_and(|ip| SocketAddr::new(ip, b.port) == address));
+        let Some(binding) = binding else {
+            warn(format!("Closing inherited listener on {}, as no binding uses that address", address));
+            continue;
+        };
+
+        let listener = match listener.set_nonblocking(true).and_then(|_| TcpListener::from_std(listener)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn(format!("Failed to take over inherited listener on {}: {}", address, e));
+                continue;
+            }
+        };
+        info(format!("Taking over inherited listener on {}", address));
+        let mode = if binding.is_tls { HoldingMode::Backlog } else { HoldingMode::Http };
+        get_holding_listeners().hold(address, Arc::new(listener), mode);
+    }
+}
+
+#[cfg(not(unix))]
+fn take_inherited_listeners() -> Vec<std::net::TcpListener> {
+    Vec::new()
+}
+
+#[cfg(unix)]
+fn take_inherited_listeners() -> Vec<std::net::TcpListener> {
+    use std::os::fd::{FromRawFd, RawFd};
+
+    let fds: Vec<RawFd> = if let Ok(value) = std::env::var(LISTEN_FDS_ENV) {
+        value.split(',').filter_map(|fd| fd.trim().parse().ok()).collect()
+    } else if std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(std::process::id()) {
+        let count = std::env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<i32>().ok()).unwrap_or(0);
+        (SYSTEMD_LISTEN_FDS_START..SYSTEMD_LISTEN_FDS_START + count).collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut listeners = Vec::new();
+    for fd in fds {
+        // Only sockets are taken, so a variable inherited by mistake cannot make us close a file we do not own
+        if !is_socket(fd) {
+            warn(format!("Inherited file descriptor {} is not a socket, ignoring it", fd));
+            continue;
+        }
+        // Not passed on to the processes we start, such as PHP-CGI
+        set_close_on_exec(fd);
+        trace(format!("Inherited listening socket on file descriptor {}", fd));
+        listeners.push(unsafe { std::net::TcpListener::from_raw_fd(fd) });
+    }
+    listeners
+}
+
+// Tells the previous process we are serving, so it can start draining
+pub fn notify_upgrade_ready() {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::fd::{FromRawFd, RawFd};
+
+        static NOTIFIED: AtomicBool = AtomicBool::new(false);
+        let Some(fd) = std::env::var(UPGRADE_READY_FD_ENV).ok().and_then(|fd| fd.parse::<RawFd>().ok()) else {
+            return;
+        };
+        if NOTIFIED.swap(true, Ordering::SeqCst) || !is_pipe(fd) {
+            return;
+        }
+        let mut ready_pipe = unsafe { std::fs::File::from_raw_fd(fd) };
+        if let Err(e) = ready_pipe.write_all(&[1]) {
+            warn(format!("Failed to tell the previous process that we are serving: {}", e));
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_socket(fd: i32) -> bool {
+    get_file_type(fd) == Some(libc::S_IFSOCK)
+}
+
+#[cfg(unix)]
+fn is_pipe(fd: i32) -> bool {
+    get_file_type(fd) == Some(libc::S_IFIFO)
+}
+
+#[cfg(unix)]
+fn get_file_type(fd: i32) -> Option<libc::mode_t> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } == -1 {
+        return None;
+    }
+    Some(stat.st_mode & libc::S_IFMT)
+}
+
+#[cfg(unix)]
+fn set_close_on_exec(fd: i32) {
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_type_checks() {
+        let (reader, _writer) = std::io::pipe().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+        use std::os::fd::AsRawFd;
+        assert!(is_pipe(reader.as_raw_fd()));
+        assert!(!is_socket(reader.as_raw_fd()));
+        assert!(is_socket(listener.as_raw_fd()));
+        assert!(!is_socket(-1));
+    }
+}
@@ -1,11 +1,23 @@
-use std::{path::PathBuf, sync::OnceLock};
+use std::{path::PathBuf, sync::OnceLock, time::Duration};
 
 use clap::{Arg, ArgMatches, Command};
 
-use crate::{configuration::import_export::{export_configuration_to_file, import_configuration_from_file}, core::admin_user::reset_admin_password};
+use crate::{
+    configuration::configuration_check::check_configuration,
+    configuration::import_export::{export_configuration_to_file, import_configuration_from_file},
+    core::admin_user::{create_password_reset_token, reset_admin_password},
+    core::benchmark::{BenchmarkSettings, parse_duration, run_benchmark},
+    core::control_socket::{CONTROL_COMMANDS, send_control_command},
+    core::database_connection::{is_database_read_only, set_database_path, set_database_read_only},
+    core::server_status::fetch_server_status,
+};
 
 pub fn load_command_line_args() -> ArgMatches {
     // Parse command line args
+    get_command().get_matches()
+}
+
+fn get_command() -> Command {
     Command::new("Gruxi")
         .version(env!("CARGO_PKG_VERSION"))
         .allow_external_subcommands(true)
@@ -44,12 +56,39 @@ pub fn load_command_line_args() -> ArgMatches {
                 .value_parser(clap::value_parser!(PathBuf))
                 .value_parser(validate_existing_file),
         )
+        .arg(
+            Arg::new("check-configuration")
+                .long("check-config")
+                .help("Check that the configuration can be started (bindings, certificates, web roots and handlers), print a report and exit, non-zero on errors")
+                .value_name("FORMAT")
+                .num_args(0..=1)
+                .default_missing_value("text")
+                .value_parser(["text", "json"]),
+        )
         .arg(
             Arg::new("disable-admin-portal")
                 .long("disable-admin-portal")
                 .help("Disable the admin portal")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("read-only-configuration")
+                .long("read-only-conf")
+                .help("Make the configuration read-only in the admin portal, for configuration managed elsewhere, such as with git or Ansible")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("database")
+                .long("db")
+                .help("Path of the database, ./db/gruxi.db by default")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("database-read-only")
+                .long("db-read-only")
+                .help("Open the database read-only, such as one in the image of a diskless container. The configuration cannot be changed then")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("benchmark")
                 .long("bench")
@@ -57,7 +96,53 @@ pub fn load_command_line_args() -> ArgMatches {
                 .hide(true)
                 .action(clap::ArgAction::SetTrue),
         )
-        .get_matches()
+        .subcommand(
+            Command::new("admin").about("Admin portal user management").subcommand_required(true).subcommand(
+                Command::new("reset-password")
+                    .about("Create a one-time token for setting a new password for the user through the admin portal")
+                    .arg(Arg::new("username").help("The user to reset the password for").required(true)),
+            ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Run a load test against a Gruxi instance (or any HTTP server) and report throughput and latency")
+                .arg(Arg::new("url").long("url").help("The url to request, such as http://localhost/").required(true))
+                .arg(
+                    Arg::new("connections")
+                        .short('c')
+                        .long("connections")
+                        .help("Number of concurrent connections")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("duration")
+                        .short('d')
+                        .long("duration")
+                        .help("Duration of the benchmark, such as 30s, 500ms or 2m")
+                        .default_value("10s")
+                        .value_parser(parse_duration),
+                )
+                .arg(Arg::new("http2").long("http2").help("Use HTTP/2 instead of HTTP/1.1").action(clap::ArgAction::SetTrue))
+                .arg(Arg::new("insecure").short('k').long("insecure").help("Do not verify TLS certificates").action(clap::ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("status")
+                .about("Print a summary of the Gruxi running on this machine: version, uptime, bindings, sites, connections and the last reload")
+                .arg(Arg::new("url").long("url").help("The status url, by default the one of the admin portal in the local configuration"))
+                .arg(Arg::new("token").long("token").help("Session token of an admin, for the status url of the admin portal"))
+                .arg(Arg::new("json").long("json").help("Print the status as JSON").action(clap::ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("control")
+                .about("Send a command to the Gruxi running from this directory through its control socket, which works without the admin portal")
+                .arg(Arg::new("command").required(true).value_parser(CONTROL_COMMANDS).help("The command to send"))
+                .arg(
+                    Arg::new("arguments")
+                        .num_args(0..)
+                        .help("Arguments of the command: on or off for in-service, a level or default followed by module=level pairs for log-level"),
+                ),
+        )
 }
 
 fn validate_existing_file(s: &str) -> Result<PathBuf, String> {
@@ -86,9 +171,23 @@ pub fn cmd_disable_admin_portal() -> bool {
     cli.get_flag("disable-admin-portal")
 }
 
+// The configuration can then only be changed with --import-conf, and the admin API rejects changes with 409 Conflict
+pub fn cmd_read_only_configuration() -> bool {
+    let cli = get_command_line_args();
+    cli.get_flag("read-only-configuration") || is_database_read_only()
+}
+
 pub fn check_for_command_line_actions() {
     let cli = get_command_line_args();
 
+    // The database options apply to the actions below as well
+    if let Some(path) = cli.get_one::<PathBuf>("database") {
+        set_database_path(&path.to_string_lossy());
+    }
+    if cli.get_flag("database-read-only") {
+        set_database_read_only(true);
+    }
+
     if cmd_should_reset_admin_password() {
         let random_password_result = reset_admin_password();
 
@@ -139,6 +238,185 @@ pub fn check_for_command_line_actions() {
         }
         std::process::exit(0);
     }
+
+    // Check for dry-run check of the configuration
+    if let Some(format) = cli.get_one::<String>("check-configuration") {
+        std::process::exit(run_configuration_check(format));
+    }
+
+    // Check for password reset token
+    if let Some(admin_args) = cli.subcommand_matches("admin")
+        && let Some(reset_args) = admin_args.subcommand_matches("reset-password")
+    {
+        let username = reset_args.get_one::<String>("username").cloned().unwrap_or_default();
+        // The token table may be new, as we run before the normal database initialization
+        if let Err(e) = crate::database::database_schema::initialize_database() {
+            eprintln!("Failed to initialize database: {}", e);
+            std::process::exit(1);
+        }
+        match create_password_reset_token(&username) {
+            Ok(reset_token) => {
+                println!("Password reset token for user '{}': {}", reset_token.username, reset_token.token);
+                println!(
+                    "It can be used once until {}, by POSTing {{\"token\": \"...\", \"new_password\": \"...\"}} to /password-reset on the admin portal",
+                    reset_token.expires_at.to_rfc3339()
+                );
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Failed to create password reset token: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Check for benchmark
+    if let Some(bench_args) = cli.subcommand_matches("bench") {
+        let settings = BenchmarkSettings {
+            url: bench_args.get_one::<String>("url").cloned().unwrap_or_default(),
+            connections: bench_args.get_one::<usize>("connections").copied().unwrap_or(10),
+            duration: bench_args.get_one::<Duration>("duration").copied().unwrap_or(Duration::from_secs(10)),
+            http2: bench_args.get_flag("http2"),
+            insecure: bench_args.get_flag("insecure"),
+        };
+        std::process::exit(run_benchmark_blocking(settings));
+    }
+
+    // Check for a command to the running server
+    if let Some(control_args) = cli.subcommand_matches("control") {
+        let mut command_line = control_args.get_one::<String>("command").cloned().unwrap_or_default();
+        for argument in control_args.get_many::<String>("arguments").unwrap_or_default() {
+            command_line.push(' ');
+            command_line.push_str(argument);
+        }
+        std::process::exit(run_control_command_blocking(command_line));
+    }
+
+    // Check for status of the running server
+    if let Some(status_args) = cli.subcommand_matches("status") {
+        let url = status_args.get_one::<String>("url").cloned();
+        let token = status_args.get_one::<String>("token").cloned();
+        std::process::exit(run_status_blocking(url, token, status_args.get_flag("json")));
+    }
+}
+
+// Loads the stored configuration, including included site files, and checks it without starting any listeners.
+// Returns the exit code, 1 if the configuration has errors
+fn run_configuration_check(format: &str) -> i32 {
+    // We run before the normal database initialization, so it may need to be created or migrated
+    if let Err(e) = crate::database::database_schema::initialize_database() {
+        eprintln!("Failed to initialize database: {}", e);
+        return 1;
+    }
+    if crate::database::database_schema::get_schema_version() == 0 {
+        eprintln!("No configuration found, Gruxi will create a default configuration on first start");
+        return 1;
+    }
+    let configuration = match crate::configuration::load_configuration::fetch_configuration_in_db() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            return 1;
+        }
+    };
+
+    let report = check_configuration(&configuration);
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+    } else {
+        print!("{}", report.to_text());
+    }
+
+    if report.has_errors() { 1 } else { 0 }
+}
+
+// We are called from within the main tokio runtime, so the benchmark gets its own runtime on a separate thread
+fn run_benchmark_blocking(settings: BenchmarkSettings) -> i32 {
+    let join_result = std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_multi_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Failed to start benchmark runtime: {}", e);
+                return 1;
+            }
+        };
+        match runtime.block_on(run_benchmark(settings.clone())) {
+            Ok(report) => {
+                report.print(&settings);
+                0
+            }
+            Err(e) => {
+                eprintln!("Benchmark failed: {}", e);
+                1
+            }
+        }
+    })
+    .join();
+
+    join_result.unwrap_or(1)
+}
+
+// Like the benchmark, the status request gets its own runtime, as we are called from within the main tokio runtime
+fn run_status_blocking(url: Option<String>, token: Option<String>, json: bool) -> i32 {
+    let join_result = std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Failed to start status runtime: {}", e);
+                return 1;
+            }
+        };
+        match runtime.block_on(fetch_server_status(url, token)) {
+            Ok(status) => {
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&status).unwrap_or_default());
+                } else {
+                    print!("{}", status.to_text());
+                }
+                0
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        }
+    })
+    .join();
+
+    join_result.unwrap_or(1)
+}
+
+fn run_control_command_blocking(command_line: String) -> i32 {
+    let join_result = std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Failed to start control runtime: {}", e);
+                return 1;
+            }
+        };
+        match runtime.block_on(send_control_command(&command_line)) {
+            Ok(response) => {
+                if let Some(status) = &response.status {
+                    print!("{}", status.to_text());
+                }
+                if !response.message.is_empty() {
+                    println!("{}", response.message);
+                }
+                for binding_error in &response.binding_errors {
+                    println!("  {} ({}): {}", binding_error.address, binding_error.binding_id, binding_error.error);
+                }
+                if response.success { 0 } else { 1 }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        }
+    })
+    .join();
+
+    join_result.unwrap_or(1)
 }
 
 static COMMAND_LINE_ARGS_SINGLETON: OnceLock<ArgMatches> = OnceLock::new();
@@ -146,3 +424,10 @@ static COMMAND_LINE_ARGS_SINGLETON: OnceLock<ArgMatches> = OnceLock::new();
 pub fn get_command_line_args() -> &'static ArgMatches {
     COMMAND_LINE_ARGS_SINGLETON.get_or_init(|| load_command_line_args())
 }
+
+// Gruxi running inside another process, such as a test binary, must not parse the arguments of that process
+// Returns false if the command line args were already loaded
+pub fn init_command_line_args_from(args: &[&str]) -> bool {
+    let command_line_args = get_command().get_matches_from(std::iter::once("gruxi").chain(args.iter().copied()));
+    COMMAND_LINE_ARGS_SINGLETON.set(command_line_args).is_ok()
+}
@@ -0,0 +1,281 @@
+use crate::core::graceful_shutdown::{drain_and_shutdown, set_in_service};
+use crate::core::running_state_manager::reload_configuration_and_wait;
+use crate::core::server_status::{ServerStatus, get_server_status};
+use crate::http::http_server::BindingStartError;
+use crate::logging::syslog::{SysLog, info, parse_log_levels, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+// The commands the CLI can send to the running server, one per connection as a line such as "log-level debug"
+pub const CONTROL_COMMANDS: [&str; 5] = ["status", "reload", "drain", "in-service", "log-level"];
+
+// Next to the database, as the CLI is run from the same directory to find that too
+#[cfg(unix)]
+pub const CONTROL_SOCKET_PATH: &str = "./gruxi.sock";
+
+#[cfg(windows)]
+pub const CONTROL_PIPE_NAME: &str = r"\\.\pipe\gruxi-control";
+
+// A command is a single short line, anything longer is not one
+const MAX_COMMAND_LENGTH: u64 = 4096;
+
+// How long a configuration reload is waited for, to report the bindings it could not start
+const RELOAD_REPORT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The CLI gives up on a server that does not answer within this, which leaves room for a reload to finish
+const CONTROL_REQUEST_TIMEOUT: Duration = Duration::from_secs(45);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub success: bool,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ServerStatus>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub binding_errors: Vec<BindingStartError>,
+}
+
+impl ControlResponse {
+    fn new(success: bool, message: impl Into<String>) -> Self {
+        ControlResponse {
+            success,
+            message: message.into(),
+            status: None,
+            binding_errors: Vec::new(),
+        }
+    }
+}
+
+// Runs a command from the control socket. Only local users allowed to manage the server get here, so there is no login
+pub async fn execute_control_command(command_line: &str) -> ControlResponse {
+    let mut parts = command_line.split_whitespace();
+    let command = parts.next().unwrap_or_default();
+    let arguments: Vec<&str> = parts.collect();
+
+    match (command, arguments.as_slice()) {
+        ("status", []) => {
+            let mut response = ControlResponse::new(true, "");
+            response.status = Some(get_server_status().await);
+            response
+        }
+        ("reload", []) => {
+            info("Audit: Configuration reload triggered through the control socket");
+            match reload_configuration_and_wait(RELOAD_REPORT_TIMEOUT).await {
+                Some(report) if report.binding_errors.is_empty() => ControlResponse::new(true, "Configuration reloaded"),
+                Some(report) => {
+                    let mut response = ControlResponse::new(false, format!("Configuration reloaded, but {} binding(s) could not be started", report.binding_errors.len()));
+                    response.binding_errors = report.binding_errors;
+                    response
+                }
+                None => ControlResponse::new(true, "Configuration reload initiated, but it did not finish within the timeout"),
+            }
+        }
+        ("drain", []) => {
+            info("Audit: Shutdown requested through the control socket, finishing the requests in progress before shutting down");
+            tokio::spawn(drain_and_shutdown());
+            ControlResponse::new(true, "Draining, the server shuts down once the requests in progress are done")
+        }
+        ("in-service", [state @ ("on" | "off")]) => {
+            let in_service = *state == "on";
+            let was_in_service = set_in_service(in_service);
+            info(format!("Audit: Server set {} service through the control socket", if in_service { "in" } else { "out of" }));
+            let message = match (was_in_service, in_service) {
+                (true, true) => "Server was already in service",
+                (false, false) => "Server was already out of service",
+                (_, true) => "Server is in service again",
+                (_, false) => "Server is out of service, the health check answers 503 while requests are still served",
+            };
+            ControlResponse::new(true, message)
+        }
+        ("log-level", [level, modules @ ..]) => set_log_levels(level, modules),
+        _ => ControlResponse::new(
+            false,
+            format!(
+                "Unknown command '{}'. Commands are status, reload, drain, in-service <on|off> and log-level <level|default> [module=level ...]",
+                command_line.trim()
+            ),
+        ),
+    }
+}
+
+// "default" goes back to the level of the operation mode, like an empty level in the admin API
+fn set_log_levels(level: &str, modules: &[&str]) -> ControlResponse {
+    let level = if level.eq_ignore_ascii_case("default") { String::new() } else { level.to_lowercase() };
+    let mut module_levels = BTreeMap::new();
+    for module in modules {
+        match module.split_once('=') {
+            Some((module, module_level)) => {
+                module_levels.insert(module.to_lowercase(), module_level.to_lowercase());
+            }
+            None => return ControlResponse::new(false, format!("Module levels are given as module=level: {}", module)),
+        }
+    }
+
+    match parse_log_levels(&level, &module_levels) {
+        Ok((log_level_override, parsed_module_levels)) => {
+            SysLog::set_log_levels(log_level_override, &parsed_module_levels);
+            info(format!("Audit: Log levels set to '{}' with modules {:?} through the control socket", level, module_levels));
+            let (_, effective_level, _) = SysLog::get_log_levels();
+            ControlResponse::new(true, format!("Log level is now {}", effective_level.get_name()))
+        }
+        Err(errors) => ControlResponse::new(false, errors.join(", ")),
+    }
+}
+
+// Reads one command, answers it as a line of JSON and closes the connection
+async fn handle_control_connection<S: AsyncRead + AsyncWrite + Unpin>(stream: S) {
+    let mut reader = BufReader::new(stream);
+    let mut command_line = String::new();
+    if let Err(e) = (&mut reader).take(MAX_COMMAND_LENGTH).read_line(&mut command_line).await {
+        warn(format!("Failed to read from the control socket: {}", e));
+        return;
+    }
+
+    let response = execute_control_command(&command_line).await;
+    let mut response_line = serde_json::to_string(&response).unwrap_or_default();
+    response_line.push('\n');
+    let mut stream = reader.into_inner();
+    if let Err(e) = stream.write_all(response_line.as_bytes()).await {
+        warn(format!("Failed to answer on the control socket: {}", e));
+    }
+    let _ = stream.shutdown().await;
+}
+
+// Listens for commands from the CLI on a Unix socket only our user can connect to. Connections are also checked by the
+// credentials of the connecting process, as the permissions of a socket file are not honored everywhere
+#[cfg(unix)]
+pub async fn start_control_socket() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use tokio::net::{UnixListener, UnixStream};
+
+    let path = Path::new(CONTROL_SOCKET_PATH);
+    if path.exists() {
+        // In a binary upgrade, the new process takes the socket over from the one it replaces
+        let is_binary_upgrade = std::env::var(crate::core::binary_upgrade::UPGRADE_READY_FD_ENV).is_ok();
+        if !is_binary_upgrade && UnixStream::connect(path).await.is_ok() {
+            warn(format!("Another Gruxi is answering on the control socket {}, so this one has no control socket", CONTROL_SOCKET_PATH));
+            return;
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn(format!("Failed to create the control socket {}: {}", CONTROL_SOCKET_PATH, e));
+            return;
+        }
+    };
+    if let Err(e) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)) {
+        warn(format!("Failed to restrict the permissions of the control socket {}: {}", CONTROL_SOCKET_PATH, e));
+    }
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn(format!("Failed to accept a control socket connection: {}", e));
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            // Root and the user we run as may manage the server
+            let peer_uid = stream.peer_cred().map(|credentials| credentials.uid());
+            match peer_uid {
+                Ok(uid) if uid == 0 || uid == unsafe { libc::geteuid() } => {
+                    tokio::spawn(handle_control_connection(stream));
+                }
+                Ok(uid) => warn(format!("Refused control socket connection from user {}", uid)),
+                Err(e) => warn(format!("Refused control socket connection, as its credentials could not be read: {}", e)),
+            }
+        }
+    });
+}
+
+// Listens for commands from the CLI on a named pipe. Its default security only lets the user who created it, administrators
+// and LocalSystem write to it, so others cannot send commands, and clients on other machines are refused
+#[cfg(windows)]
+pub async fn start_control_socket() {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = match ServerOptions::new().first_pipe_instance(true).reject_remote_clients(true).create(CONTROL_PIPE_NAME) {
+        Ok(server) => server,
+        Err(e) => {
+            warn(format!("Failed to create the control pipe {}, it may be used by another Gruxi: {}", CONTROL_PIPE_NAME, e));
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = server.connect().await {
+                warn(format!("Failed to accept a control pipe connection: {}", e));
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+            // A new instance of the pipe takes the next client, while this one is answered
+            let next_server = match ServerOptions::new().reject_remote_clients(true).create(CONTROL_PIPE_NAME) {
+                Ok(next_server) => next_server,
+                Err(e) => {
+                    warn(format!("Failed to create the control pipe {}, no more commands are taken: {}", CONTROL_PIPE_NAME, e));
+                    handle_control_connection(server).await;
+                    return;
+                }
+            };
+            tokio::spawn(handle_control_connection(std::mem::replace(&mut server, next_server)));
+        }
+    });
+}
+
+// Sends a command to the server running from this directory and returns its answer
+pub async fn send_control_command(command_line: &str) -> Result<ControlResponse, String> {
+    tokio::time::timeout(CONTROL_REQUEST_TIMEOUT, async {
+        #[cfg(unix)]
+        let stream = tokio::net::UnixStream::connect(CONTROL_SOCKET_PATH)
+            .await
+            .map_err(|e| format!("Could not connect to the control socket {}, is Gruxi running from this directory? {}", CONTROL_SOCKET_PATH, e))?;
+        #[cfg(windows)]
+        let stream = tokio::net::windows::named_pipe::ClientOptions::new()
+            .open(CONTROL_PIPE_NAME)
+            .map_err(|e| format!("Could not connect to the control pipe {}, is Gruxi running? {}", CONTROL_PIPE_NAME, e))?;
+
+        exchange_control_command(stream, command_line).await
+    })
+    .await
+    .map_err(|_| format!("Gruxi did not answer the control command within {} seconds", CONTROL_REQUEST_TIMEOUT.as_secs()))?
+}
+
+async fn exchange_control_command<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, command_line: &str) -> Result<ControlResponse, String> {
+    stream
+        .write_all(format!("{}\n", command_line.trim()).as_bytes())
+        .await
+        .map_err(|e| format!("Failed to send the control command: {}", e))?;
+    let mut response_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response_line)
+        .await
+        .map_err(|e| format!("Failed to read the answer to the control command: {}", e))?;
+    serde_json::from_str(&response_line).map_err(|e| format!("Invalid answer to the control command: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_control_commands_over_a_connection() {
+        let (client, server) = tokio::io::duplex(8192);
+        tokio::spawn(handle_control_connection(server));
+        let response = exchange_control_command(client, "in-service maybe").await.unwrap();
+        assert!(!response.success);
+        assert!(response.message.starts_with("Unknown command 'in-service maybe'"));
+
+        assert!(!execute_control_command("unknown").await.success);
+        assert!(!execute_control_command("log-level loud").await.success);
+        assert!(!execute_control_command("log-level info tls").await.success);
+    }
+}
@@ -1,8 +1,77 @@
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const DATABASE_PATH: &str = "./db/gruxi.db";
+
+static DATABASE_PATH_SINGLETON: OnceLock<String> = OnceLock::new();
+
+// Set with --db-read-only, for diskless containers with the database in the image. Nothing is written to the database then,
+// so the configuration cannot be changed, the admin portal cannot be logged in to and statistics are not kept
+static DATABASE_READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+pub fn get_database_path() -> &'static str {
+    DATABASE_PATH_SINGLETON.get_or_init(|| DATABASE_PATH.to_string())
+}
+
+// Uses another database than the default, such as a temporary one for tests. Must be called before the first connection is opened
+// Returns false if the database path was already in use
+pub fn set_database_path(path: &str) -> bool {
+    DATABASE_PATH_SINGLETON.set(path.to_string()).is_ok()
+}
+
+pub fn is_database_read_only() -> bool {
+    DATABASE_READ_ONLY.load(Ordering::Relaxed)
+}
+
+// Must be called before the first connection is opened
+pub fn set_database_read_only(read_only: bool) {
+    DATABASE_READ_ONLY.store(read_only, Ordering::Relaxed);
+}
 
 pub fn get_database_connection() -> Result<sqlite::Connection, String> {
-    let mut connection = sqlite::open("./db/gruxi.db").map_err(|e| format!("Failed to open database connection: {}", e))?;
+    if is_database_read_only() {
+        return get_read_only_database_connection();
+    }
+    let mut connection = sqlite::open(get_database_path()).map_err(|e| format!("Failed to open database connection: {}", e))?;
     connection.set_busy_timeout(500).map_err(|e| format!("Failed to set busy timeout: {}", e))?;
     connection.execute("PRAGMA journal_mode=WAL;").map_err(|e| format!("Failed to enable WAL journal mode: {}", e))?;
     connection.execute("PRAGMA foreign_keys=ON;").map_err(|e| format!("Failed to enable foreign key support: {}", e))?;
     Ok(connection)
 }
+
+// Opened as immutable, so no lock, journal or WAL index files are created next to it, which works on a read-only file system.
+// Changes still in the WAL file are not seen, the database maintenance or stopping Gruxi writes them to the database file
+fn get_read_only_database_connection() -> Result<sqlite::Connection, String> {
+    let flags = sqlite::OpenFlags::new().with_read_only().with_uri();
+    let mut connection = sqlite::Connection::open_with_flags(get_read_only_database_uri(get_database_path()), flags).map_err(|e| format!("Failed to open read-only database connection: {}", e))?;
+    connection.set_busy_timeout(500).map_err(|e| format!("Failed to set busy timeout: {}", e))?;
+    Ok(connection)
+}
+
+fn get_read_only_database_uri(path: &str) -> String {
+    let escaped_path: String = path
+        .chars()
+        .map(|c| match c {
+            '%' => "%25".to_string(),
+            '?' => "%3f".to_string(),
+            '#' => "%23".to_string(),
+            '\\' => "/".to_string(),
+            c => c.to_string(),
+        })
+        .collect();
+    // Windows paths with a drive letter are given as file:/C:/...
+    let is_drive_path = escaped_path.as_bytes().get(1) == Some(&b':') && escaped_path.as_bytes()[0].is_ascii_alphabetic();
+    format!("file:{}{}?mode=ro&immutable=1", if is_drive_path { "/" } else { "" }, escaped_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_only_database_uri() {
+        assert_eq!(get_read_only_database_uri("./db/gruxi.db"), "file:./db/gruxi.db?mode=ro&immutable=1");
+        assert_eq!(get_read_only_database_uri("/data/what?#100%.db"), "file:/data/what%3f%23100%25.db?mode=ro&immutable=1");
+        assert_eq!(get_read_only_database_uri("C:\\Gruxi\\gruxi.db"), "file:/C:/Gruxi/gruxi.db?mode=ro&immutable=1");
+    }
+}
@@ -0,0 +1,244 @@
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::core::triggers::GruxiEvent;
+use crate::logging::syslog::{info, warn};
+use crate::network::smtp_client::{OutgoingEmail, SmtpError, send_email};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::{Semaphore, broadcast, mpsc};
+
+// Email waiting to be sent. When the mail server is away for long, new email is dropped rather than piling up
+const EMAIL_QUEUE_SIZE: usize = 1000;
+// Email being sent or waiting to be tried again at the same time. The rest waits in the queue, so a mail server that is away
+// holds up a few sends rather than every email getting a task of its own
+const MAX_CONCURRENT_SENDS: usize = 4;
+// Email failing with a temporary error is tried again after these delays, as mail servers may greylist or restart
+const RETRY_DELAYS: [Duration; 4] = [Duration::from_secs(30), Duration::from_secs(120), Duration::from_secs(600), Duration::from_secs(1800)];
+
+static EMAIL_QUEUE: OnceLock<EmailQueue> = OnceLock::new();
+
+pub fn get_email_queue() -> &'static EmailQueue {
+    EMAIL_QUEUE.get_or_init(EmailQueue::new)
+}
+
+// The email the server can send, each with a subject and body where {{name}} is replaced by the value given for name
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmailTemplate {
+    Alert,
+    CertificateExpiry,
+    PasswordReset,
+    Test,
+}
+
+impl EmailTemplate {
+    fn get_subject_and_body(&self) -> (&'static str, &'static str) {
+        match self {
+            EmailTemplate::Alert => ("[Gruxi {{server}}] {{title}}", "{{message}}\n\nTime: {{time}}\nServer: {{server}}\n"),
+            EmailTemplate::CertificateExpiry => (
+                "[Gruxi {{server}}] Certificate for {{domains}} expires in {{days}} days",
+                "The TLS certificate for {{domains}} expires on {{expires_at}}, in {{days}} days.\n\nRenew or replace it before then, or clients will no longer trust the sites using it.\n\nServer: {{server}}\n",
+            ),
+            EmailTemplate::PasswordReset => (
+                "[Gruxi {{server}}] Password reset",
+                "A password reset was requested for the admin user {{username}}.\n\nUse this token to set a new password within {{valid_minutes}} minutes: {{token}}\n\nIf you did not request it, nothing needs to be done.\n",
+            ),
+            EmailTemplate::Test => (
+                "[Gruxi {{server}}] Test email",
+                "This is a test email from Gruxi, sent by {{username}}. The mail server settings work.\n\nServer: {{server}}\n",
+            ),
+        }
+    }
+
+    // The subject and body with the values filled in. The server name and the time are always available
+    pub fn render(&self, values: &BTreeMap<&str, String>) -> (String, String) {
+        let mut values = values.clone();
+        values.entry("server").or_insert_with(get_server_name);
+        values.entry("time").or_insert_with(|| chrono::Utc::now().to_rfc3339());
+        let (subject, body) = self.get_subject_and_body();
+        (render_template(subject, &values), render_template(body, &values))
+    }
+}
+
+// Replaces each {{name}} with its value. Placeholders without a value are left as they are, so a missing value is easy to spot
+pub fn render_template(template: &str, values: &BTreeMap<&str, String>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after_start = &rest[start + 2..];
+        match after_start.find("}}") {
+            Some(end) => {
+                let name = after_start[..end].trim();
+                match values.get(name) {
+                    Some(value) => rendered.push_str(value),
+                    None => rendered.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after_start[end + 2..];
+            }
+            None => {
+                rendered.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+// Sends email in the background, trying again when the mail server fails for a while, so features sending email do not wait for it
+pub struct EmailQueue {
+    sender: mpsc::Sender<OutgoingEmail>,
+    // Taken by the task sending the email when it starts
+    receiver: Mutex<Option<mpsc::Receiver<OutgoingEmail>>>,
+}
+
+impl EmailQueue {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(EMAIL_QUEUE_SIZE);
+        EmailQueue {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    // Queues the email rendered from the template for the recipients
+    pub fn queue(&self, to: Vec<String>, template: EmailTemplate, values: &BTreeMap<&str, String>) -> Result<(), String> {
+        if to.is_empty() {
+            return Err("The email has no recipients".to_string());
+        }
+        let (subject, body) = template.render(values);
+        self.sender.try_send(OutgoingEmail { to, subject, body }).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => "The email queue is full".to_string(),
+            mpsc::error::TrySendError::Closed(_) => "The email queue is closed".to_string(),
+        })
+    }
+
+    // Queues a notice for the notification recipients in the SMTP settings. Does nothing if no mail server or recipients are configured
+    pub async fn notify(&self, template: EmailTemplate, values: &BTreeMap<&str, String>) {
+        let configuration = get_cached_configuration().get_configuration().await;
+        let smtp = &configuration.core.server_settings.smtp;
+        if !smtp.is_enabled() || smtp.notification_recipients.is_empty() {
+            return;
+        }
+        if let Err(e) = self.queue(smtp.notification_recipients.clone(), template, values) {
+            warn(format!("Failed to queue email notice: {}", e));
+        }
+    }
+
+    // Starts sending the queued email, a few at a time so one waiting to be tried again does not hold up the others
+    pub fn initialize(&'static self) {
+        let Some(mut receiver) = self.receiver.lock().ok().and_then(|mut receiver| receiver.take()) else {
+            return;
+        };
+        tokio::spawn(async move {
+            let sends = Arc::new(Semaphore::new(MAX_CONCURRENT_SENDS));
+            while let Some(email) = receiver.recv().await {
+                let Ok(permit) = sends.clone().acquire_owned().await else {
+                    break;
+                };
+                tokio::spawn(async move {
+                    send_with_retry(email).await;
+                    drop(permit);
+                });
+            }
+        });
+    }
+}
+
+impl Default for EmailQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Emails the notification recipients about events that need attention, such as a handler process that had to be restarted.
+// Runs until shutdown
+pub async fn run_email_alerts(mut events: broadcast::Receiver<GruxiEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(GruxiEvent::Shutdown) | Err(broadcast::error::RecvError::Closed) => break,
+            Ok(event) => {
+                if let Some(values) = get_alert_values(&event) {
+                    get_email_queue().notify(EmailTemplate::Alert, &values).await;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => warn(format!("Email alerts missed {} events", skipped)),
+        }
+    }
+}
+
+// The title and message of the alert for the event, None for events that are not emailed
+fn get_alert_values(event: &GruxiEvent) -> Option<BTreeMap<&'static str, String>> {
+    match event {
+        GruxiEvent::HandlerRestarted { handler_id, reason } => Some(BTreeMap::from([
+            ("title", format!("Handler {} restarted", handler_id)),
+            ("message", format!("The handler {} was restarted: {}", handler_id, reason)),
+        ])),
+        _ => None,
+    }
+}
+
+// The mail server settings are read for each attempt, so email waiting to be tried again uses the settings as they are by then
+async fn send_with_retry(email: OutgoingEmail) {
+    let mut retry_delays = RETRY_DELAYS.iter();
+    loop {
+        let configuration = get_cached_configuration().get_configuration().await;
+        let error = match send_email(&configuration.core.server_settings.smtp, &email).await {
+            Ok(()) => {
+                info(format!("Sent email '{}' to {}", email.subject, email.to.join(", ")));
+                return;
+            }
+            Err(e) => e,
+        };
+        match (&error, retry_delays.next()) {
+            (SmtpError::Temporary(message), Some(delay)) => {
+                warn(format!("Failed to send email '{}', trying again in {} seconds: {}", email.subject, delay.as_secs(), message));
+                tokio::time::sleep(*delay).await;
+            }
+            _ => {
+                warn(format!("Failed to send email '{}' to {}, giving up: {}", email.subject, email.to.join(", "), error.get_message()));
+                return;
+            }
+        }
+    }
+}
+
+fn get_server_name() -> String {
+    std::env::var("HOSTNAME").or_else(|_| std::env::var("COMPUTERNAME")).unwrap_or_else(|_| "server".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_templates() {
+        let values = BTreeMap::from([("name", "Gruxi".to_string()), ("count", "3".to_string())]);
+        assert_eq!(render_template("Hello {{name}}, {{ count }} new", &values), "Hello Gruxi, 3 new");
+        assert_eq!(render_template("Hello {{unknown}} {{name", &values), "Hello {{unknown}} {{name");
+
+        let values = BTreeMap::from([("domains", "example.com".to_string()), ("days", "7".to_string()), ("server", "web1".to_string())]);
+        let (subject, body) = EmailTemplate::CertificateExpiry.render(&values);
+        assert_eq!(subject, "[Gruxi web1] Certificate for example.com expires in 7 days");
+        assert!(body.contains("in 7 days"));
+    }
+
+    #[test]
+    fn test_alerts_for_events() {
+        let event = GruxiEvent::HandlerRestarted {
+            handler_id: "php-cgi-1".to_string(),
+            reason: "it stopped responding".to_string(),
+        };
+        let (subject, body) = EmailTemplate::Alert.render(&get_alert_values(&event).unwrap());
+        assert!(subject.ends_with("] Handler php-cgi-1 restarted"));
+        assert!(body.starts_with("The handler php-cgi-1 was restarted: it stopped responding"));
+        assert!(get_alert_values(&GruxiEvent::Started).is_none());
+    }
+
+    #[test]
+    fn test_queue_needs_recipients() {
+        let queue = EmailQueue::new();
+        assert!(queue.queue(Vec::new(), EmailTemplate::Test, &BTreeMap::new()).is_err());
+        assert!(queue.queue(vec!["ops@example.com".to_string()], EmailTemplate::Test, &BTreeMap::new()).is_ok());
+    }
+}
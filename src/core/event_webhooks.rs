@@ -0,0 +1,193 @@
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::configuration::server_settings::EventWebhook;
+use crate::core::running_state_manager::get_running_state_manager;
+use crate::core::triggers::GruxiEvent;
+use crate::http::request_response::body_error::BodyError;
+use crate::logging::syslog::{trace, warn};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use ring::hmac;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// Header with the HMAC-SHA256 of the body made with the secret of the webhook, as "sha256=<hex>"
+const SIGNATURE_HEADER: &str = "X-Gruxi-Signature";
+const EVENT_HEADER: &str = "X-Gruxi-Event";
+const DELIVERY_HEADER: &str = "X-Gruxi-Delivery";
+// Failed deliveries are tried again a few times, as the receiver may restart along with what it reacts to
+const DELIVERY_ATTEMPTS: u32 = 3;
+const DELIVERY_RETRY_DELAY: Duration = Duration::from_secs(2);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+// At shutdown there is only one attempt, with a shorter timeout, so the webhooks cannot hold up the shutdown
+const SHUTDOWN_DELIVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Posts the lifecycle events of the server to the event webhooks in the server settings, so automation and chat alerts can react
+// to them without polling the admin API. Runs until shutdown, returning once the shutdown event is delivered
+pub async fn run_event_webhooks(mut events: broadcast::Receiver<GruxiEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(GruxiEvent::Shutdown) => {
+                send_event(&GruxiEvent::Shutdown).await;
+                break;
+            }
+            Ok(event) => send_event(&event).await,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn(format!("Event webhooks missed {} events", skipped));
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+// The name and details of the event as sent to the webhooks, None for events that are not sent
+pub fn get_event_payload(event: &GruxiEvent) -> Option<(&'static str, serde_json::Value)> {
+    match event {
+        GruxiEvent::Started => Some(("startup", serde_json::json!({ "version": env!("CARGO_PKG_VERSION") }))),
+        GruxiEvent::Shutdown => Some(("shutdown", serde_json::json!({}))),
+        GruxiEvent::ConfigurationChanged(changes) => Some(("configuration_changed", serde_json::json!({ "changes": changes }))),
+        GruxiEvent::CertificateDeployed { domains } => Some(("certificate_deployed", serde_json::json!({ "domains": domains }))),
+        GruxiEvent::HandlerRestarted { handler_id, reason } => Some(("handler_restarted", serde_json::json!({ "handler_id": handler_id, "reason": reason }))),
+        GruxiEvent::ClientBanned { ip, seconds, reason } => Some(("client_banned", serde_json::json!({ "ip": ip, "seconds": seconds, "reason": reason }))),
+        _ => None,
+    }
+}
+
+pub fn build_webhook_body(event_name: &str, data: serde_json::Value, delivery_id: &str) -> String {
+    serde_json::json!({
+        "id": delivery_id,
+        "event": event_name,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "data": data,
+    })
+    .to_string()
+}
+
+pub fn sign_webhook_body(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let signature: String = hmac::sign(&key, body).as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", signature)
+}
+
+fn is_subscribed(webhook: &EventWebhook, event_name: &str) -> bool {
+    webhook.events.is_empty() || webhook.events.iter().any(|event| event == event_name)
+}
+
+async fn send_event(event: &GruxiEvent) {
+    let Some((event_name, data)) = get_event_payload(event) else {
+        return;
+    };
+    let configuration = get_cached_configuration().get_configuration().await;
+    let webhooks: Vec<EventWebhook> = configuration
+        .core
+        .server_settings
+        .event_webhooks
+        .iter()
+        .filter(|webhook| is_subscribed(webhook, event_name))
+        .cloned()
+        .collect();
+    if webhooks.is_empty() {
+        return;
+    }
+
+    // The same delivery id for all webhooks, so receivers getting the event through several of them can tell
+    let delivery_id = uuid::Uuid::new_v4().to_string();
+    let body = Bytes::from(build_webhook_body(event_name, data, &delivery_id));
+    let is_shutdown = *event == GruxiEvent::Shutdown;
+    let deliveries = webhooks.into_iter().map(|webhook| {
+        let body = body.clone();
+        let delivery_id = delivery_id.clone();
+        tokio::spawn(async move { deliver(&webhook, event_name, &delivery_id, body, is_shutdown).await })
+    });
+    let deliveries: Vec<_> = deliveries.collect();
+
+    // At shutdown the deliveries are waited for, as the process exits afterwards
+    if is_shutdown {
+        futures::future::join_all(deliveries).await;
+    }
+}
+
+async fn deliver(webhook: &EventWebhook, event_name: &str, delivery_id: &str, body: Bytes, is_shutdown: bool) {
+    // The client is taken out of the running state, so the running state is not held while waiting on the webhook
+    let client = get_running_state_manager().await.get_running_state_unlocked().await.get_http_client().get_client(true);
+    let (attempts, timeout) = if is_shutdown { (1, SHUTDOWN_DELIVERY_TIMEOUT) } else { (DELIVERY_ATTEMPTS, DELIVERY_TIMEOUT) };
+
+    let mut last_error = String::new();
+    for attempt in 1..=attempts {
+        let mut request_builder = hyper::Request::post(&webhook.url)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .header(hyper::header::USER_AGENT, format!("Gruxi/{}", env!("CARGO_PKG_VERSION")))
+            .header(EVENT_HEADER, event_name)
+            .header(DELIVERY_HEADER, delivery_id);
+        if !webhook.secret.is_empty() {
+            request_builder = request_builder.header(SIGNATURE_HEADER, sign_webhook_body(&webhook.secret, &body));
+        }
+        let request_body: BoxBody<Bytes, BodyError> = BoxBody::new(Full::new(body.clone()).map_err(|never| -> BodyError { match never {} }));
+        let request = match request_builder.body(request_body) {
+            Ok(request) => request,
+            Err(e) => {
+                warn(format!("Event webhook {} cannot be sent to: {}", webhook.url, e));
+                return;
+            }
+        };
+
+        match tokio::time::timeout(timeout, client.request(request)).await {
+            Ok(Ok(response)) if response.status().is_success() => {
+                trace(format!("Sent {} event to webhook {}", event_name, webhook.url));
+                return;
+            }
+            Ok(Ok(response)) => last_error = format!("answered with status {}", response.status()),
+            Ok(Err(e)) => last_error = e.to_string(),
+            Err(_) => last_error = format!("did not answer within {} seconds", timeout.as_secs()),
+        }
+        if attempt < attempts {
+            tokio::time::sleep(DELIVERY_RETRY_DELAY).await;
+        }
+    }
+    warn(format!("Failed to send {} event to webhook {} after {} attempts: {}", event_name, webhook.url, attempts, last_error));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_payloads() {
+        let (name, data) = get_event_payload(&GruxiEvent::ClientBanned {
+            ip: "10.0.0.1".to_string(),
+            seconds: 60,
+            reason: "HTTP/2 rapid reset".to_string(),
+        })
+        .unwrap();
+        assert_eq!(name, "client_banned");
+        assert_eq!(data["ip"], "10.0.0.1");
+        assert_eq!(data["seconds"], 60);
+
+        let body: serde_json::Value = serde_json::from_str(&build_webhook_body(name, data, "delivery-1")).unwrap();
+        assert_eq!(body["event"], "client_banned");
+        assert_eq!(body["id"], "delivery-1");
+        assert_eq!(body["data"]["reason"], "HTTP/2 rapid reset");
+
+        assert_eq!(get_event_payload(&GruxiEvent::Started).unwrap().0, "startup");
+        assert!(get_event_payload(&GruxiEvent::ReopenLogs).is_none());
+    }
+
+    #[test]
+    fn test_signature_and_subscriptions() {
+        // The HMAC-SHA256 test vector of RFC 4231, test case 2
+        assert_eq!(
+            sign_webhook_body("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let mut webhook = EventWebhook {
+            url: "https://hooks.example.com/gruxi".to_string(),
+            secret: String::new(),
+            events: Vec::new(),
+        };
+        assert!(is_subscribed(&webhook, "shutdown"));
+        webhook.events = vec!["startup".to_string(), "client_banned".to_string()];
+        assert!(is_subscribed(&webhook, "client_banned"));
+        assert!(!is_subscribed(&webhook, "shutdown"));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::core::monitoring::get_monitoring_state;
+use crate::core::site_statistics::get_site_statistics;
+use crate::core::triggers::get_trigger_handler;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::logging::syslog::{info, warn};
+use hyper::header::HeaderValue;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Connections still open after this are closed by the shutdown
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+// Cleared through the admin API to take the server out of the load balancers, such as before maintenance. Not kept across restarts
+static IN_SERVICE: AtomicBool = AtomicBool::new(true);
+
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::SeqCst)
+}
+
+// Whether the health check tells load balancers to send requests here. A server draining for shutdown is never in service
+pub fn is_in_service() -> bool {
+    IN_SERVICE.load(Ordering::SeqCst) && !is_draining()
+}
+
+// Returns whether the server was in service before
+pub fn set_in_service(in_service: bool) -> bool {
+    IN_SERVICE.swap(in_service, Ordering::SeqCst)
+}
+
+// The answer to the health check path of the server settings. It only tells whether the server wants requests, so operators can
+// drain it from a load balancer while it keeps serving the requests that still come in
+pub fn get_health_check_response() -> GruxiResponse {
+    let mut response = if is_in_service() {
+        GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), "OK")
+    } else {
+        GruxiResponse::new_with_bytes(hyper::StatusCode::SERVICE_UNAVAILABLE.as_u16(), "Out of service")
+    };
+    response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+    response.headers_mut().insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+// Stops accepting connections, lets the connections finish the requests in progress and then shuts down.
+// Called again while draining, such as by a second Ctrl+C, it shuts down right away
+pub async fn drain_and_shutdown() {
+    let triggers = get_trigger_handler();
+    if DRAINING.swap(true, Ordering::SeqCst) {
+        info("Already draining, shutting down right away");
+        triggers.run_trigger("shutdown").await;
+        return;
+    }
+
+    // The health check answers 503 from now on, and requests are still served until the load balancers have noticed
+    let deregistration_delay_seconds = get_cached_configuration().get_configuration().await.core.server_settings.deregistration_delay_seconds;
+    if deregistration_delay_seconds > 0 {
+        info(format!("Waiting {} seconds for load balancers to stop sending requests before draining", deregistration_delay_seconds));
+        tokio::time::sleep(Duration::from_secs(deregistration_delay_seconds)).await;
+    }
+
+    triggers.run_trigger("drain").await;
+
+    let monitoring_state = get_monitoring_state().await;
+    let drain_result = tokio::time::timeout(DRAIN_TIMEOUT, async {
+        while monitoring_state.get_requests_in_queue() > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    if drain_result.is_err() {
+        warn(format!(
+            "{} connections still open after draining for {} seconds, closing them",
+            monitoring_state.get_requests_in_queue(),
+            DRAIN_TIMEOUT.as_secs()
+        ));
+    }
+
+    // The requests served since the last periodic save would be lost otherwise
+    if let Err(e) = get_site_statistics().save_to_database() {
+        warn(e);
+    }
+
+    triggers.run_trigger("shutdown").await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_check_follows_in_service() {
+        assert_eq!(get_health_check_response().get_status(), 200);
+        assert!(set_in_service(false));
+        assert_eq!(get_health_check_response().get_status(), 503);
+        assert!(!set_in_service(true));
+        assert_eq!(get_health_check_response().get_status(), 200);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::admin_portal::init::initialize_admin_site;
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::configuration::configuration::Configuration;
+use crate::configuration::configuration_changes::ConfigurationChanges;
+use crate::configuration::save_configuration::save_configuration;
+use crate::core::background_tasks::start_background_tasks;
+use crate::core::binary_upgrade::{adopt_inherited_listeners, notify_upgrade_ready};
+use crate::core::command_line_args::init_command_line_args_from;
+use crate::core::control_socket::start_control_socket;
+use crate::core::database_connection::{get_database_path, is_database_read_only, set_database_path, set_database_read_only};
+use crate::core::event_webhooks::run_event_webhooks;
+use crate::core::operation_mode::{get_operation_mode, is_valid_operation_mode, set_new_operation_mode};
+use crate::core::os_signal::start_os_signal_handling;
+use crate::core::running_state_manager::get_running_state_manager;
+use crate::core::server_status::get_server_status;
+use crate::core::triggers::{GruxiEvent, get_trigger_handler};
+use crate::database::database_migration::migrate_database;
+use crate::database::database_schema::{CURRENT_DB_SCHEMA_VERSION, get_schema_version, initialize_database, set_schema_version};
+use crate::http::holding_listener::get_holding_listeners;
+use crate::logging::syslog::{error, info, warn};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+// Runs Gruxi inside another Rust application, which is also how the gruxi binary runs it:
+//
+//     let server = GruxServer::builder().configuration(configuration).start().await?;
+//     let mut events = server.subscribe();
+//     ...
+//     server.stop().await;
+//     server.wait().await;
+//
+// The server keeps its state in process wide singletons, so it can only be started once per process.
+
+static SERVER_STARTED: AtomicBool = AtomicBool::new(false);
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GruxServerEvent {
+    // The stored configuration was loaded again, with what changed compared to the previous one
+    ConfigurationChanged(ConfigurationChanges),
+    // The running state was rebuilt from a changed configuration
+    ConfigurationReloaded,
+    OperationModeChanged,
+    // The shutdown was triggered, by stop(), the admin portal, a signal or a binary upgrade
+    Stopping,
+    Stopped,
+}
+
+pub struct GruxServerBuilder {
+    configuration: Option<Configuration>,
+    database_path: Option<String>,
+    database_read_only: bool,
+    operation_mode: Option<String>,
+    handle_os_signals: bool,
+}
+
+pub struct GruxServer {
+    events: broadcast::Sender<GruxServerEvent>,
+    main_loop: JoinHandle<()>,
+}
+
+impl GruxServer {
+    pub fn builder() -> GruxServerBuilder {
+        GruxServerBuilder {
+            configuration: None,
+            database_path: None,
+            database_read_only: false,
+            operation_mode: None,
+            handle_os_signals: false,
+        }
+    }
+
+    // Events happening after this call are received. The receiver lags, rather than blocking the server, if it is not read
+    pub fn subscribe(&self) -> broadcast::Receiver<GruxServerEvent> {
+        self.events.subscribe()
+    }
+
+    // Reloads the configuration from the database and rebuilds the running state, like saving the configuration in the admin portal
+    pub async fn reload_configuration(&self) {
+        let triggers = get_trigger_handler();
+        triggers.run_trigger("refresh_cached_configuration").await;
+        triggers.run_trigger("reload_configuration").await;
+    }
+
+    // Triggers the shutdown. Use wait() to wait for the server to have stopped
+    pub async fn stop(&self) {
+        get_trigger_handler().run_trigger("shutdown").await;
+    }
+
+    pub async fn wait(self) {
+        if let Err(e) = self.main_loop.await {
+            error(format!("Main loop task exited with error: {}", e));
+        }
+    }
+}
+
+impl GruxServerBuilder {
+    // Replaces the stored configuration with this one when the server starts. Without it, the configuration in the database is used,
+    // or a default configuration on the first run
+    pub fn configuration(mut self, configuration: Configuration) -> Self {
+        self.configuration = Some(configuration);
+        self
+    }
+
+    // Defaults to ./db/gruxi.db
+    pub fn database_path(mut self, path: &str) -> Self {
+        self.database_path = Some(path.to_string());
+        self
+    }
+
+    // Opens the database read-only, such as one in the image of a diskless container. The configuration cannot be changed then,
+    // the admin portal cannot be logged in to and statistics are not kept
+    pub fn database_read_only(mut self, read_only: bool) -> Self {
+        self.database_read_only = read_only;
+        self
+    }
+
+    // DEV, DEBUG, PRODUCTION or ULTIMATE. Without it, the operation mode in the database is used
+    pub fn operation_mode(mut self, operation_mode: &str) -> Self {
+        self.operation_mode = Some(operation_mode.to_string());
+        self
+    }
+
+    // Shut down on SIGTERM/SIGINT, reload on SIGHUP and take commands from the CLI on the control socket. Off by default, as the
+    // signals and the control socket belong to the embedding application
+    pub fn handle_os_signals(mut self, handle_os_signals: bool) -> Self {
+        self.handle_os_signals = handle_os_signals;
+        self
+    }
+
+    // Starts the server on the current tokio runtime and returns once all bindings are accepting requests, or an error if they are not
+    // within the startup timeout, in which case the server is shut down again
+    pub async fn start(self) -> Result<GruxServer, String> {
+        if SERVER_STARTED.swap(true, Ordering::SeqCst) {
+            return Err("Gruxi has already been started in this process".to_string());
+        }
+
+        // The binary loads the command line before starting, an embedding application has no Gruxi command line
+        init_command_line_args_from(&[]);
+
+        if let Some(database_path) = &self.database_path
+            && !set_database_path(database_path)
+        {
+            return Err("The database was already opened, so the database path can no longer be changed".to_string());
+        }
+        if self.database_read_only {
+            set_database_read_only(true);
+        }
+        if is_database_read_only() && self.configuration.is_some() {
+            return Err("A configuration cannot be saved to a read-only database".to_string());
+        }
+
+        // Initialize database tables and migrations
+        initialize_database().map_err(|e| format!("Failed to initialize database: {}", e))?;
+
+        if let Some(mut configuration) = self.configuration {
+            let schema_version = get_schema_version();
+            if schema_version > 0 && schema_version < CURRENT_DB_SCHEMA_VERSION {
+                migrate_database();
+            }
+            save_configuration(&mut configuration, true).map_err(|errors| format!("Invalid configuration: {}", errors.join(", ")))?;
+            set_schema_version(CURRENT_DB_SCHEMA_VERSION)?;
+        }
+
+        if let Some(operation_mode) = &self.operation_mode {
+            if !is_valid_operation_mode(operation_mode) {
+                return Err(format!("Invalid operation mode: {}", operation_mode));
+            }
+            set_new_operation_mode(operation_mode.to_string());
+        }
+
+        info(format!("Starting Gruxi {}", env!("CARGO_PKG_VERSION")));
+        info(format!("Operation mode: {:?}", get_operation_mode()));
+        if is_database_read_only() {
+            warn(format!(
+                "The database {} is read-only, so the configuration cannot be changed, the admin portal cannot be logged in to and statistics are not kept",
+                get_database_path()
+            ));
+        }
+
+        // Load the configuration early to catch any errors
+        let listen_addresses: Vec<SocketAddr> = get_cached_configuration()
+            .get_configuration()
+            .await
+            .bindings
+            .iter()
+            .filter_map(|b| b.ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, b.port)))
+            .collect();
+
+        initialize_admin_site().map_err(|_| "Failed to initialize admin site".to_string())?;
+
+        // Open the listeners right away, answering 503 until the server is ready. Listeners handed over by a previous process are used as they are
+        adopt_inherited_listeners().await;
+        crate::http::http_server::hold_bindings_during_startup().await;
+
+        // Take signals and commands from the CLI, such as reload and status, without going through the admin portal
+        if self.handle_os_signals {
+            start_os_signal_handling();
+            start_control_socket().await;
+        }
+
+        // Start tasks that run in the background
+        start_background_tasks().await;
+
+        // Start the running state, which are all the configuration dependent parts, and the main http server
+        get_running_state_manager().await;
+        crate::http::http_server::initialize_server().await;
+
+        // The bindings are serving once they have taken over their listener from holding
+        let started = tokio::time::timeout(STARTUP_TIMEOUT, async {
+            while listen_addresses.iter().any(|address| get_holding_listeners().is_holding(address)) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        if started.is_err() {
+            get_trigger_handler().run_trigger("shutdown").await;
+            return Err(format!("Not all bindings started within the startup timeout of {} seconds", STARTUP_TIMEOUT.as_secs()));
+        }
+
+        // In a binary upgrade, the previous process drains and exits once we are serving
+        notify_upgrade_ready();
+
+        // Startup banner, with what is being served
+        for line in get_server_status().await.to_text().lines() {
+            info(line.to_string());
+        }
+
+        // Subscribed before the main loop is spawned, so no event is missed in between
+        let (events, _) = broadcast::channel(16);
+        let event_webhooks = tokio::spawn(run_event_webhooks(get_trigger_handler().subscribe()));
+        let main_loop = tokio::spawn(run_main_loop(events.clone(), get_trigger_handler().subscribe(), event_webhooks));
+        get_trigger_handler().publish(GruxiEvent::Started).await;
+
+        Ok(GruxServer { events, main_loop })
+    }
+}
+
+// Rebuilds the running state when the configuration changes, until shutdown.
+// The events are taken from the event bus, as the tokens of the triggers are renewed when they fire, which could be missed between two events
+async fn run_main_loop(events: broadcast::Sender<GruxServerEvent>, mut gruxi_events: broadcast::Receiver<GruxiEvent>, event_webhooks: JoinHandle<()>) {
+    let running_state_manager = get_running_state_manager().await;
+
+    loop {
+        match gruxi_events.recv().await {
+            // Published once the cached configuration is refreshed, with what changed, so the reload works from the refreshed configuration
+            Ok(GruxiEvent::ConfigurationChanged(changes)) => {
+                if let Some(changes) = &changes {
+                    let _ = events.send(GruxServerEvent::ConfigurationChanged(changes.clone()));
+                }
+                running_state_manager.reload_running_state(changes).await;
+                let _ = events.send(GruxServerEvent::ConfigurationReloaded);
+            }
+            Ok(GruxiEvent::OperationModeChanged) => {
+                let _ = events.send(GruxServerEvent::OperationModeChanged);
+            }
+            Ok(GruxiEvent::Shutdown) | Err(broadcast::error::RecvError::Closed) => break,
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                error(format!("Main loop missed {} events, as it was busy for too long", skipped));
+            }
+        }
+    }
+
+    let _ = events.send(GruxServerEvent::Stopping);
+
+    // The shutdown event webhooks are sent before the server stops, their delivery is limited by its own timeout
+    let _ = event_webhooks.await;
+
+    // Waiting a little while to allow graceful shutdown
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let _ = events.send(GruxServerEvent::Stopped);
+}
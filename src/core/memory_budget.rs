@@ -0,0 +1,170 @@
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// Bodies smaller than this are always admitted, so the ceiling only turns away the requests that could exhaust the memory
+pub const MEMORY_BUDGET_SMALL_BODY_BYTES: u64 = 64 * 1024;
+
+static MEMORY_BUDGET_SINGLETON: OnceLock<MemoryBudget> = OnceLock::new();
+
+pub fn get_memory_budget() -> &'static MemoryBudget {
+    MEMORY_BUDGET_SINGLETON.get_or_init(|| MemoryBudget::new(0))
+}
+
+// Accounts for the memory used by bodies held in memory, such as request bodies and responses being compressed, and by the file cache,
+// against a ceiling. Large bodies that do not fit under the ceiling are turned away, rather than the process running out of memory
+pub struct MemoryBudget {
+    // 0 for no ceiling, in which case the memory is only accounted for
+    max_bytes: AtomicU64,
+    buffered_body_bytes: AtomicU64,
+    // Updated from the monitoring thread, as the file cache does not track its size itself
+    cache_bytes: AtomicU64,
+    reservations_rejected: AtomicUsize,
+}
+
+// Memory reserved for a body, released when dropped
+pub struct MemoryReservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryReservation<'_> {
+    pub fn get_bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    // Reserves more memory for a body that grows while it is read, such as a chunked request body. Returns false when the body
+    // has become large and no longer fits under the ceiling, keeping what was reserved before
+    pub fn try_grow(&mut self, bytes: u64) -> bool {
+        if !self.budget.reserve_bytes(bytes, self.bytes.saturating_add(bytes)) {
+            return false;
+        }
+        self.bytes += bytes;
+        true
+    }
+}
+
+impl Drop for MemoryReservation<'_> {
+    fn drop(&mut self) {
+        self.budget.buffered_body_bytes.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(max_bytes: u64) -> Self {
+        MemoryBudget {
+            max_bytes: AtomicU64::new(max_bytes),
+            buffered_body_bytes: AtomicU64::new(0),
+            cache_bytes: AtomicU64::new(0),
+            reservations_rejected: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn set_max_bytes(&self, max_bytes: u64) {
+        self.max_bytes.store(max_bytes, Ordering::Relaxed);
+    }
+
+    pub fn set_cache_bytes(&self, cache_bytes: u64) {
+        self.cache_bytes.store(cache_bytes, Ordering::Relaxed);
+    }
+
+    pub fn get_used_bytes(&self) -> u64 {
+        self.buffered_body_bytes.load(Ordering::Relaxed).saturating_add(self.cache_bytes.load(Ordering::Relaxed))
+    }
+
+    // Whether this many bytes more still fit under the ceiling, such as before storing a file in the cache
+    pub fn has_room_for(&self, bytes: u64) -> bool {
+        let max_bytes = self.max_bytes.load(Ordering::Relaxed);
+        max_bytes == 0 || self.get_used_bytes().saturating_add(bytes) <= max_bytes
+    }
+
+    // Reserves memory for a body about to be held in memory. Returns None when a large body does not fit under the ceiling
+    pub fn try_reserve(&self, bytes: u64) -> Option<MemoryReservation<'_>> {
+        if !self.reserve_bytes(bytes, bytes) {
+            return None;
+        }
+        Some(MemoryReservation { budget: self, bytes })
+    }
+
+    // Adds the bytes to the buffered body bytes, unless the body they are for, of body_bytes in total, is large and does not fit
+    fn reserve_bytes(&self, bytes: u64, body_bytes: u64) -> bool {
+        let max_bytes = self.max_bytes.load(Ordering::Relaxed);
+        let is_subject_to_ceiling = max_bytes > 0 && body_bytes >= MEMORY_BUDGET_SMALL_BODY_BYTES;
+
+        let reserved = self.buffered_body_bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |buffered_body_bytes| {
+            let used_bytes = buffered_body_bytes.saturating_add(self.cache_bytes.load(Ordering::Relaxed));
+            if is_subject_to_ceiling && used_bytes.saturating_add(bytes) > max_bytes {
+                None
+            } else {
+                Some(buffered_body_bytes + bytes)
+            }
+        });
+        if reserved.is_err() {
+            self.reservations_rejected.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    pub fn get_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "max_bytes": self.max_bytes.load(Ordering::Relaxed),
+            "used_bytes": self.get_used_bytes(),
+            "buffered_body_bytes": self.buffered_body_bytes.load(Ordering::Relaxed),
+            "cache_bytes": self.cache_bytes.load(Ordering::Relaxed),
+            "reservations_rejected": self.reservations_rejected.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_large_bodies_are_rejected_above_the_ceiling() {
+        let budget = MemoryBudget::new(1024 * 1024);
+        budget.set_cache_bytes(256 * 1024);
+
+        let first = budget.try_reserve(512 * 1024).unwrap();
+        assert_eq!(budget.get_used_bytes(), 768 * 1024);
+        assert!(budget.try_reserve(512 * 1024).is_none());
+        assert!(!budget.has_room_for(512 * 1024));
+
+        // Small bodies are still admitted, but counted
+        let small = budget.try_reserve(1024).unwrap();
+        assert_eq!(budget.get_used_bytes(), 769 * 1024);
+        drop(small);
+
+        drop(first);
+        assert_eq!(budget.get_used_bytes(), 256 * 1024);
+        assert!(budget.try_reserve(512 * 1024).is_some());
+        assert_eq!(budget.get_json()["reservations_rejected"], 1);
+    }
+
+    #[test]
+    fn test_growing_bodies_are_rejected_above_the_ceiling() {
+        let budget = MemoryBudget::new(256 * 1024);
+        let mut reservation = budget.try_reserve(0).unwrap();
+
+        // A body is admitted while it is small, and counted as it grows
+        assert!(reservation.try_grow(32 * 1024));
+        assert!(reservation.try_grow(32 * 1024));
+        assert_eq!(budget.get_used_bytes(), 64 * 1024);
+        assert!(reservation.try_grow(128 * 1024));
+        assert!(!reservation.try_grow(128 * 1024));
+        assert_eq!(budget.get_used_bytes(), 192 * 1024);
+
+        drop(reservation);
+        assert_eq!(budget.get_used_bytes(), 0);
+    }
+
+    #[test]
+    fn test_no_ceiling_only_accounts() {
+        let budget = MemoryBudget::new(0);
+        let reservation = budget.try_reserve(u32::MAX as u64).unwrap();
+        assert_eq!(budget.get_used_bytes(), u32::MAX as u64);
+        assert!(budget.has_room_for(u64::MAX));
+        drop(reservation);
+        assert_eq!(budget.get_used_bytes(), 0);
+    }
+}
@@ -1,10 +1,22 @@
-pub mod operation_mode;
-pub mod command_line_args;
 pub mod admin_user;
+pub mod background_tasks;
+pub mod benchmark;
+pub mod command_line_args;
 pub mod database_connection;
 pub mod monitoring;
-pub mod background_tasks;
+pub mod monitoring_history;
+pub mod operation_mode;
 pub mod os_signal;
 pub mod running_state;
 pub mod running_state_manager;
 pub mod triggers;
+
+pub mod binary_upgrade;
+pub mod control_socket;
+pub mod email;
+pub mod event_webhooks;
+pub mod graceful_shutdown;
+pub mod grux_server;
+pub mod memory_budget;
+pub mod server_status;
+pub mod site_statistics;
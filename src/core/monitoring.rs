@@ -1,5 +1,15 @@
+use crate::core::memory_budget::get_memory_budget;
+use crate::core::monitoring_history::{MonitoringHistory, MonitoringSample, SiteSample};
 use crate::core::{running_state_manager::get_running_state_manager, triggers::get_trigger_handler};
+use crate::file::file_handle_limiter::get_file_handle_limiter;
+use crate::http::http2_flood_guard::get_temporary_bans;
+use crate::http::panic_guard::get_panic_guard;
+use crate::http::request_response::request_timings::get_request_timing_stats;
+use crate::logging::log_sampling::get_log_sampler;
 use crate::logging::syslog::{debug, trace};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use tokio::{select, sync::OnceCell};
 
@@ -12,6 +22,27 @@ pub struct MonitoringState {
     file_cache_enabled: AtomicBool,
     file_cache_current_items: AtomicUsize,
     file_cache_max_items: AtomicUsize,
+    keep_alive_reused_requests: AtomicUsize,
+    keep_alive_max_requests_reached: AtomicUsize,
+    keep_alive_timeouts: AtomicUsize,
+    pipelined_requests: AtomicUsize,
+    pipelined_requests_rejected: AtomicUsize,
+    requests_rejected_body_too_large: AtomicUsize,
+    requests_panicked: AtomicUsize,
+    tls_handshake_failures: DashMap<(String, String), usize>, // Keyed by binding address and reason
+    requests_rejected_invalid: DashMap<String, usize>,        // Keyed by reason
+    http2_floods: DashMap<String, usize>,                     // Keyed by reason
+    connections_rejected_banned: AtomicUsize,
+    connections_accepted: AtomicUsize,
+    connections_handled: AtomicUsize,
+    connections_active: AtomicUsize,
+    connections_reading: AtomicUsize,
+    requests_writing: AtomicUsize,
+    upstream_connections_opened: DashMap<String, usize>, // Keyed by upstream host and port
+    upstream_retries: DashMap<String, usize>,            // Keyed by the upstream server that could not be connected to
+    site_requests_served: DashMap<String, usize>,
+    site_requests_served_last: DashMap<String, usize>,
+    history: MonitoringHistory,
 }
 
 impl MonitoringState {
@@ -28,6 +59,27 @@ impl MonitoringState {
             file_cache_enabled: AtomicBool::new(configuration.core.file_cache.is_enabled),
             file_cache_current_items: AtomicUsize::new(0), // Updated from monitoring thread
             file_cache_max_items: AtomicUsize::new(configuration.core.file_cache.cache_item_size),
+            keep_alive_reused_requests: AtomicUsize::new(0), // Updated from http server
+            keep_alive_max_requests_reached: AtomicUsize::new(0),
+            keep_alive_timeouts: AtomicUsize::new(0),
+            pipelined_requests: AtomicUsize::new(0),
+            pipelined_requests_rejected: AtomicUsize::new(0),
+            requests_rejected_body_too_large: AtomicUsize::new(0), // Updated from request handling
+            requests_panicked: AtomicUsize::new(0),                // Updated from http server
+            tls_handshake_failures: DashMap::new(),                // Updated from http server
+            requests_rejected_invalid: DashMap::new(),             // Updated from http server
+            http2_floods: DashMap::new(),                          // Updated from http server
+            connections_rejected_banned: AtomicUsize::new(0),
+            connections_accepted: AtomicUsize::new(0), // Updated from http server
+            connections_handled: AtomicUsize::new(0),
+            connections_active: AtomicUsize::new(0),
+            connections_reading: AtomicUsize::new(0),
+            requests_writing: AtomicUsize::new(0),
+            upstream_connections_opened: DashMap::new(), // Updated from the HTTP client
+            upstream_retries: DashMap::new(),            // Updated from the proxy processor
+            site_requests_served: DashMap::new(),        // Updated from request handling
+            site_requests_served_last: DashMap::new(),   // Updated from monitoring thread
+            history: MonitoringHistory::new(),           // Updated from monitoring thread
         }
     }
 
@@ -70,6 +122,7 @@ impl MonitoringState {
                 let file_reader_cache = unlocked_running_state.get_file_reader_cache();
 
                 monitoring_state.file_cache_current_items.store(file_reader_cache.get_current_item_count() as usize, Ordering::Relaxed);
+                get_memory_budget().set_cache_bytes(file_reader_cache.get_current_content_bytes());
 
                 // Clone the configuration values we need, then drop the guard
                 let (file_cache_enabled, file_cache_max_items) = {
@@ -81,6 +134,9 @@ impl MonitoringState {
                 monitoring_state.file_cache_max_items.store(file_cache_max_items, Ordering::Relaxed);
             }
 
+            // Keep a sample of the data for the history
+            monitoring_state.add_history_sample(requests_per_sec, update_interval_seconds).await;
+
             trace("Monitoring data updated");
 
             select! {
@@ -101,6 +157,44 @@ impl MonitoringState {
         }
     }
 
+    async fn add_history_sample(&self, requests_per_sec: f64, update_interval_seconds: usize) {
+        let mut sites = HashMap::new();
+        for entry in self.site_requests_served.iter() {
+            let (site_id, requests_served) = (entry.key().clone(), *entry.value());
+            let last_requests = self.site_requests_served_last.insert(site_id.clone(), requests_served).unwrap_or(0);
+            let site_requests_per_sec = requests_served.saturating_sub(last_requests) as f64 / update_interval_seconds as f64;
+            sites.insert(
+                site_id,
+                SiteSample {
+                    requests_served,
+                    requests_per_sec: site_requests_per_sec,
+                },
+            );
+        }
+
+        self.history.add_sample(MonitoringSample {
+            timestamp: chrono::Utc::now().timestamp(),
+            requests_served: self.get_requests_served(),
+            requests_per_sec,
+            // Unlike the live data, no monitoring request is in progress when the sample is taken
+            requests_in_progress: self.requests_in_progress.load(Ordering::Relaxed),
+            file_cache_items: self.file_cache_current_items.load(Ordering::Relaxed),
+            sites,
+        });
+    }
+
+    pub fn get_history(&self) -> &MonitoringHistory {
+        &self.history
+    }
+
+    pub fn increment_site_requests_served(&self, site_id: &str) {
+        if let Some(mut requests_served) = self.site_requests_served.get_mut(site_id) {
+            *requests_served += 1;
+            return;
+        }
+        *self.site_requests_served.entry(site_id.to_string()).or_insert(0) += 1;
+    }
+
     pub fn increment_requests_served(&self) {
         self.requests_served.fetch_add(1, Ordering::Relaxed);
     }
@@ -117,6 +211,162 @@ impl MonitoringState {
         self.requests_in_progress.fetch_sub(1, Ordering::Relaxed);
     }
 
+    pub fn get_uptime_seconds(&self) -> u64 {
+        self.server_start_time.elapsed().as_secs()
+    }
+
+    pub fn get_requests_in_queue(&self) -> usize {
+        self.requests_in_progress.load(Ordering::Relaxed)
+    }
+
+    // Request served on an already used keep-alive connection
+    pub fn increment_keep_alive_reused_requests(&self) {
+        self.keep_alive_reused_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_keep_alive_max_requests_reached(&self) {
+        self.keep_alive_max_requests_reached.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_keep_alive_timeouts(&self) {
+        self.keep_alive_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_pipelined_requests(&self) {
+        self.pipelined_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_pipelined_requests_rejected(&self) {
+        self.pipelined_requests_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Request rejected with 413 because the body exceeded the max body size
+    pub fn increment_requests_rejected_body_too_large(&self) {
+        self.requests_rejected_body_too_large.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Request whose handling panicked and was answered with 500
+    pub fn increment_requests_panicked(&self) {
+        self.requests_panicked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // TLS handshake that failed on a binding, such as for an unknown SNI hostname or a client without a common protocol version
+    pub fn increment_tls_handshake_failures(&self, binding_address: &str, reason: &str) {
+        *self.tls_handshake_failures.entry((binding_address.to_string(), reason.to_string())).or_insert(0) += 1;
+    }
+
+    // Request rejected with 400 by the request validation against request smuggling
+    pub fn increment_requests_rejected_invalid(&self, reason: &str) {
+        *self.requests_rejected_invalid.entry(reason.to_string()).or_insert(0) += 1;
+    }
+
+    // HTTP/2 connection closed with GOAWAY for flooding us, see Http2FloodGuard
+    pub fn increment_http2_floods(&self, reason: &str) {
+        *self.http2_floods.entry(reason.to_string()).or_insert(0) += 1;
+    }
+
+    // Connection dropped right after accept, as the client is temporarily banned
+    pub fn increment_connections_rejected_banned(&self) {
+        self.connections_rejected_banned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Connection accepted on a binding, whether it is then served or dropped
+    pub fn increment_connections_accepted(&self) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Connection that is served, counted as active until the guard is dropped
+    pub fn track_active_connection(&'static self) -> MonitoringCounterGuard {
+        self.connections_handled.fetch_add(1, Ordering::Relaxed);
+        MonitoringCounterGuard::new(&self.connections_active)
+    }
+
+    // Connection in the TLS handshake, before any request can be read from it
+    pub fn track_connection_reading(&'static self) -> MonitoringCounterGuard {
+        MonitoringCounterGuard::new(&self.connections_reading)
+    }
+
+    // Request being handled, until its response has been written to the client
+    pub fn track_request_writing(&'static self) -> MonitoringCounterGuard {
+        MonitoringCounterGuard::new(&self.requests_writing)
+    }
+
+    // The counts of the nginx stub_status page. Connections neither in the handshake nor with a request in progress are waiting,
+    // and as HTTP/2 connections can have several requests in progress, writing can exceed the active connections
+    pub fn get_connection_stats(&self) -> ConnectionStats {
+        let active = self.connections_active.load(Ordering::Relaxed);
+        let reading = self.connections_reading.load(Ordering::Relaxed);
+        let writing = self.requests_writing.load(Ordering::Relaxed);
+        ConnectionStats {
+            active,
+            accepts: self.connections_accepted.load(Ordering::Relaxed),
+            handled: self.connections_handled.load(Ordering::Relaxed),
+            requests: self.get_requests_served(),
+            reading,
+            writing,
+            waiting: active.saturating_sub(reading).saturating_sub(writing),
+        }
+    }
+
+    // New connection to an upstream, made when none of its pooled connections could be reused
+    pub fn increment_upstream_connections_opened(&self, upstream: &str) {
+        *self.upstream_connections_opened.entry(upstream.to_string()).or_insert(0) += 1;
+    }
+
+    // A request sent again after connecting to this upstream server failed. Many of them point to a failing upstream
+    pub fn increment_upstream_retries(&self, upstream: &str) {
+        *self.upstream_retries.entry(upstream.to_string()).or_insert(0) += 1;
+    }
+
+    fn get_upstream_retries_json(&self) -> serde_json::Value {
+        let upstreams: HashMap<String, usize> = self.upstream_retries.iter().map(|entry| (entry.key().clone(), *entry.value())).collect();
+        let total: usize = upstreams.values().sum();
+        serde_json::json!({ "total": total, "upstreams": upstreams })
+    }
+
+    fn get_upstream_connections_opened_json(&self) -> serde_json::Value {
+        let upstreams: HashMap<String, usize> = self.upstream_connections_opened.iter().map(|entry| (entry.key().clone(), *entry.value())).collect();
+        let total: usize = upstreams.values().sum();
+        serde_json::json!({ "total": total, "upstreams": upstreams })
+    }
+
+    fn get_http2_floods_json(&self) -> serde_json::Value {
+        let reasons: HashMap<String, usize> = self.http2_floods.iter().map(|entry| (entry.key().clone(), *entry.value())).collect();
+        let total: usize = reasons.values().sum();
+        serde_json::json!({
+            "total": total,
+            "reasons": reasons,
+            "banned_ips": get_temporary_bans().get_banned_count(),
+            "connections_rejected_banned": self.connections_rejected_banned.load(Ordering::Relaxed),
+        })
+    }
+
+    fn get_requests_rejected_invalid_json(&self) -> serde_json::Value {
+        let reasons: HashMap<String, usize> = self.requests_rejected_invalid.iter().map(|entry| (entry.key().clone(), *entry.value())).collect();
+        let total: usize = reasons.values().sum();
+        serde_json::json!({ "total": total, "reasons": reasons })
+    }
+
+    fn get_tls_handshake_failures_json(&self) -> serde_json::Value {
+        let mut total = 0;
+        let mut bindings: HashMap<String, HashMap<String, usize>> = HashMap::new();
+        for entry in self.tls_handshake_failures.iter() {
+            let ((binding_address, reason), count) = (entry.key(), *entry.value());
+            total += count;
+            bindings.entry(binding_address.clone()).or_default().insert(reason.clone(), count);
+        }
+
+        let bindings: serde_json::Map<String, serde_json::Value> = bindings
+            .into_iter()
+            .map(|(binding_address, reasons)| {
+                let binding_total: usize = reasons.values().sum();
+                (binding_address, serde_json::json!({ "total": binding_total, "reasons": reasons }))
+            })
+            .collect();
+
+        serde_json::json!({ "total": total, "bindings": bindings })
+    }
+
     pub async fn get_json(&self) -> serde_json::Value {
         let monitoring_state = get_monitoring_state().await;
 
@@ -128,15 +378,64 @@ impl MonitoringState {
             "requests_per_sec": f64::from_bits(monitoring_state.requests_served_per_sec.load(Ordering::Relaxed) as u64),
             "requests_in_progress": requests_in_progress,
             "uptime_seconds": monitoring_state.server_start_time.elapsed().as_secs(),
+            "requests_rejected_body_too_large": monitoring_state.requests_rejected_body_too_large.load(Ordering::Relaxed),
+            "requests_panicked": monitoring_state.requests_panicked.load(Ordering::Relaxed),
+            "panic_guard": get_panic_guard().get_json(),
+            "tls_handshake_failures": monitoring_state.get_tls_handshake_failures_json(),
+            "requests_rejected_invalid": monitoring_state.get_requests_rejected_invalid_json(),
+            "http2_floods": monitoring_state.get_http2_floods_json(),
+            "connections": monitoring_state.get_connection_stats(),
+            "upstream_connections_opened": monitoring_state.get_upstream_connections_opened_json(),
+            "upstream_retries": monitoring_state.get_upstream_retries_json(),
+            "file_handles": get_file_handle_limiter().get_json(),
+            "memory_budget": get_memory_budget().get_json(),
+            "logging": get_log_sampler().get_json(),
+            "request_timings": get_request_timing_stats().get_json(),
             "file_cache": {
                 "enabled": monitoring_state.file_cache_enabled.load(Ordering::Relaxed),
                 "current_items": monitoring_state.file_cache_current_items.load(Ordering::Relaxed),
                 "max_items": monitoring_state.file_cache_max_items.load(Ordering::Relaxed),
+            },
+            "keep_alive": {
+                "reused_requests": monitoring_state.keep_alive_reused_requests.load(Ordering::Relaxed),
+                "max_requests_reached": monitoring_state.keep_alive_max_requests_reached.load(Ordering::Relaxed),
+                "timeouts": monitoring_state.keep_alive_timeouts.load(Ordering::Relaxed),
+                "pipelined_requests": monitoring_state.pipelined_requests.load(Ordering::Relaxed),
+                "pipelined_requests_rejected": monitoring_state.pipelined_requests_rejected.load(Ordering::Relaxed),
             }
         })
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStats {
+    pub active: usize,
+    pub accepts: usize,
+    pub handled: usize,
+    pub requests: usize,
+    pub reading: usize,
+    pub writing: usize,
+    pub waiting: usize,
+}
+
+// Decrements the counter it was created for when dropped, so connections and requests ending in any way are counted out
+pub struct MonitoringCounterGuard {
+    counter: &'static AtomicUsize,
+}
+
+impl MonitoringCounterGuard {
+    fn new(counter: &'static AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        MonitoringCounterGuard { counter }
+    }
+}
+
+impl Drop for MonitoringCounterGuard {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 static CURRENT_STATE_SINGLETON: OnceCell<MonitoringState> = OnceCell::const_new();
 
 pub async fn get_monitoring_state() -> &'static MonitoringState {
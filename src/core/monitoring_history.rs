@@ -0,0 +1,290 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+// Samples are taken by the monitoring task every 10 seconds, so this keeps 24 hours of history in memory
+pub const MAX_HISTORY_SAMPLES: usize = 8640;
+pub const DEFAULT_HISTORY_PAGE_SIZE: usize = 500;
+pub const MAX_HISTORY_PAGE_SIZE: usize = 5000;
+
+// Metrics available in the history. Only the request metrics are tracked per site
+pub const HISTORY_METRICS: [&str; 4] = ["requests_served", "requests_per_sec", "requests_in_progress", "file_cache_items"];
+pub const SITE_HISTORY_METRICS: [&str; 2] = ["requests_served", "requests_per_sec"];
+
+#[derive(Debug, Clone, Default)]
+pub struct SiteSample {
+    pub requests_served: usize,
+    pub requests_per_sec: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MonitoringSample {
+    // Unix timestamp in seconds
+    pub timestamp: i64,
+    pub requests_served: usize,
+    pub requests_per_sec: f64,
+    pub requests_in_progress: usize,
+    pub file_cache_items: usize,
+    pub sites: HashMap<String, SiteSample>,
+}
+
+impl MonitoringSample {
+    fn get_metric(&self, site: Option<&str>, metric: &str) -> Option<f64> {
+        if let Some(site_id) = site {
+            let site_sample = self.sites.get(site_id)?;
+            return match metric {
+                "requests_served" => Some(site_sample.requests_served as f64),
+                "requests_per_sec" => Some(site_sample.requests_per_sec),
+                _ => None,
+            };
+        }
+        match metric {
+            "requests_served" => Some(self.requests_served as f64),
+            "requests_per_sec" => Some(self.requests_per_sec),
+            "requests_in_progress" => Some(self.requests_in_progress as f64),
+            "file_cache_items" => Some(self.file_cache_items as f64),
+            _ => None,
+        }
+    }
+}
+
+// The query parameters of the history endpoint, like "?site=<id>&from=1700000000&to=1700003600&resolution=60&metrics=requests_per_sec&page=2&page_size=100"
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryQuery {
+    pub site: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    // Width in seconds of the buckets the samples are averaged into, zero keeps the raw samples
+    pub resolution: u64,
+    pub metrics: Vec<String>,
+    // Pages start at 1
+    pub page: usize,
+    pub page_size: usize,
+}
+
+impl HistoryQuery {
+    pub fn from_query_string(query: &str) -> Result<Self, String> {
+        let mut history_query = HistoryQuery {
+            site: None,
+            from: None,
+            to: None,
+            resolution: 0,
+            metrics: Vec::new(),
+            page: 1,
+            page_size: DEFAULT_HISTORY_PAGE_SIZE,
+        };
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = urlencoding::decode(value).map_err(|_| format!("Invalid value for '{}'", key))?.to_string();
+            match key {
+                "site" => history_query.site = Some(value).filter(|v| !v.is_empty()),
+                "from" => history_query.from = Some(value.parse().map_err(|_| "'from' must be a unix timestamp in seconds".to_string())?),
+                "to" => history_query.to = Some(value.parse().map_err(|_| "'to' must be a unix timestamp in seconds".to_string())?),
+                "resolution" => history_query.resolution = value.parse().map_err(|_| "'resolution' must be a number of seconds".to_string())?,
+                "metrics" => history_query.metrics = value.split(',').map(|m| m.trim().to_string()).filter(|m| !m.is_empty()).collect(),
+                "page" => history_query.page = value.parse().map_err(|_| "'page' must be a positive number".to_string())?,
+                "page_size" => history_query.page_size = value.parse().map_err(|_| "'page_size' must be a positive number".to_string())?,
+                _ => return Err(format!("Unknown query parameter '{}'", key)),
+            }
+        }
+
+        if let (Some(from), Some(to)) = (history_query.from, history_query.to)
+            && from > to
+        {
+            return Err("'from' must not be after 'to'".to_string());
+        }
+        if history_query.page == 0 {
+            return Err("'page' must be a positive number".to_string());
+        }
+        if history_query.page_size == 0 || history_query.page_size > MAX_HISTORY_PAGE_SIZE {
+            return Err(format!("'page_size' must be between 1 and {}", MAX_HISTORY_PAGE_SIZE));
+        }
+
+        let available_metrics: &[&str] = if history_query.site.is_some() { &SITE_HISTORY_METRICS } else { &HISTORY_METRICS };
+        if history_query.metrics.is_empty() {
+            history_query.metrics = available_metrics.iter().map(|m| m.to_string()).collect();
+        }
+        if let Some(metric) = history_query.metrics.iter().find(|m| !available_metrics.contains(&m.as_str())) {
+            return Err(format!("Unknown metric '{}', available metrics are: {}", metric, available_metrics.join(", ")));
+        }
+
+        Ok(history_query)
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryPoint {
+    pub timestamp: i64,
+    pub values: HashMap<String, f64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryPage {
+    pub site: Option<String>,
+    pub resolution: u64,
+    pub page: usize,
+    pub page_size: usize,
+    pub total_points: usize,
+    pub total_pages: usize,
+    pub points: Vec<HistoryPoint>,
+}
+
+// Timestamp of a downsampling bucket, with the sum and count of each metric in it
+type HistoryBucket = (i64, HashMap<String, (f64, usize)>);
+
+#[derive(Default)]
+pub struct MonitoringHistory {
+    samples: Mutex<VecDeque<MonitoringSample>>,
+}
+
+impl MonitoringHistory {
+    pub fn new() -> Self {
+        MonitoringHistory { samples: Mutex::new(VecDeque::new()) }
+    }
+
+    pub fn add_sample(&self, sample: MonitoringSample) {
+        let mut samples = self.samples.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if samples.len() >= MAX_HISTORY_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    // Filters the samples by time, downsamples them into buckets of the requested resolution by averaging, and returns the requested page
+    pub fn query(&self, query: &HistoryQuery) -> HistoryPage {
+        let site = query.site.as_deref();
+        let mut points: Vec<HistoryPoint> = Vec::new();
+        let mut bucket: Option<HistoryBucket> = None;
+
+        let samples = self.samples.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for sample in samples.iter() {
+            if query.from.is_some_and(|from| sample.timestamp < from) || query.to.is_some_and(|to| sample.timestamp > to) {
+                continue;
+            }
+            // Sites only get samples once they have served requests
+            if site.is_some_and(|site_id| !sample.sites.contains_key(site_id)) {
+                continue;
+            }
+
+            let bucket_timestamp = if query.resolution > 0 {
+                sample.timestamp - sample.timestamp.rem_euclid(query.resolution as i64)
+            } else {
+                sample.timestamp
+            };
+            if bucket.as_ref().is_some_and(|(timestamp, _)| *timestamp != bucket_timestamp)
+                && let Some(finished_bucket) = bucket.take()
+            {
+                points.push(Self::average_bucket(finished_bucket));
+            }
+
+            let (_, sums) = bucket.get_or_insert_with(|| (bucket_timestamp, HashMap::new()));
+            for metric in &query.metrics {
+                if let Some(value) = sample.get_metric(site, metric) {
+                    let (sum, count) = sums.entry(metric.clone()).or_insert((0.0, 0));
+                    *sum += value;
+                    *count += 1;
+                }
+            }
+        }
+        drop(samples);
+        if let Some(finished_bucket) = bucket.take() {
+            points.push(Self::average_bucket(finished_bucket));
+        }
+
+        let total_points = points.len();
+        let total_pages = total_points.div_ceil(query.page_size);
+        let points = points.into_iter().skip((query.page - 1).saturating_mul(query.page_size)).take(query.page_size).collect();
+
+        HistoryPage {
+            site: query.site.clone(),
+            resolution: query.resolution,
+            page: query.page,
+            page_size: query.page_size,
+            total_points,
+            total_pages,
+            points,
+        }
+    }
+
+    fn average_bucket((timestamp, sums): HistoryBucket) -> HistoryPoint {
+        let values = sums.into_iter().map(|(metric, (sum, count))| (metric, sum / count as f64)).collect();
+        HistoryPoint { timestamp, values }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_history() -> MonitoringHistory {
+        let history = MonitoringHistory::new();
+        for i in 0..12 {
+            let mut sites = HashMap::new();
+            if i >= 6 {
+                sites.insert(
+                    "site1".to_string(),
+                    SiteSample {
+                        requests_served: i,
+                        requests_per_sec: 1.0,
+                    },
+                );
+            }
+            history.add_sample(MonitoringSample {
+                timestamp: 1000 + (i as i64) * 10,
+                requests_served: i * 10,
+                requests_per_sec: i as f64,
+                requests_in_progress: 1,
+                file_cache_items: 5,
+                sites,
+            });
+        }
+        history
+    }
+
+    #[test]
+    fn test_history_query_parsing() {
+        let query = HistoryQuery::from_query_string("site=site1&from=1000&to=2000&resolution=60&metrics=requests_per_sec&page=2&page_size=10").unwrap();
+        assert_eq!(query.site.as_deref(), Some("site1"));
+        assert_eq!((query.from, query.to, query.resolution, query.page, query.page_size), (Some(1000), Some(2000), 60, 2, 10));
+        assert_eq!(query.metrics, vec!["requests_per_sec"]);
+
+        assert_eq!(HistoryQuery::from_query_string("").unwrap().metrics.len(), HISTORY_METRICS.len());
+        assert!(HistoryQuery::from_query_string("from=2000&to=1000").is_err());
+        assert!(HistoryQuery::from_query_string("page=0").is_err());
+        assert!(HistoryQuery::from_query_string("page_size=100000").is_err());
+        assert!(HistoryQuery::from_query_string("metrics=cpu").is_err());
+        assert!(HistoryQuery::from_query_string("site=site1&metrics=file_cache_items").is_err());
+        assert!(HistoryQuery::from_query_string("limit=5").is_err());
+    }
+
+    #[test]
+    fn test_history_downsampling_and_pagination() {
+        let history = create_history();
+
+        // Timestamps 1000 to 1110, so 60 second buckets start at 960, 1020 and 1080
+        let query = HistoryQuery::from_query_string("resolution=60&metrics=requests_per_sec").unwrap();
+        let page = history.query(&query);
+        assert_eq!(page.total_points, 3);
+        assert_eq!(page.points[0].timestamp, 960);
+        assert_eq!(page.points[0].values["requests_per_sec"], 0.5);
+        assert_eq!(page.points[1].values["requests_per_sec"], 4.5);
+
+        let query = HistoryQuery::from_query_string("from=1020&to=1050&page=2&page_size=3").unwrap();
+        let page = history.query(&query);
+        assert_eq!((page.total_points, page.total_pages), (4, 2));
+        assert_eq!(page.points.len(), 1);
+        assert_eq!(page.points[0].timestamp, 1050);
+    }
+
+    #[test]
+    fn test_history_site_filter() {
+        let history = create_history();
+        let query = HistoryQuery::from_query_string("site=site1").unwrap();
+        let page = history.query(&query);
+        assert_eq!(page.total_points, 6);
+        assert_eq!(page.points[0].values["requests_served"], 6.0);
+        assert!(history.query(&HistoryQuery::from_query_string("site=other").unwrap()).points.is_empty());
+    }
+}
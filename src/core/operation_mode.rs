@@ -15,6 +15,23 @@ pub enum OperationMode {
     ULTIMATE,
 }
 
+impl OperationMode {
+    // Error responses include the error details and a backtrace, which should never be shown to visitors in production
+    pub fn shows_error_details(&self) -> bool {
+        matches!(self, OperationMode::DEV | OperationMode::DEBUG)
+    }
+
+    // File contents are cached in memory, except in development mode, so changed files are served right away
+    pub fn uses_file_cache(&self) -> bool {
+        *self != OperationMode::DEV
+    }
+
+    // Test and development servers should not be indexed, so generated robots.txt files keep crawlers out of them
+    pub fn allows_crawlers(&self) -> bool {
+        matches!(self, OperationMode::PRODUCTION | OperationMode::ULTIMATE)
+    }
+}
+
 pub fn load_operation_mode() -> OperationMode {
     // Parse command line args
     let mut opmode = cmd_get_operation_mode();
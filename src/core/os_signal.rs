@@ -1,54 +1,74 @@
+#[cfg(unix)]
+use crate::core::binary_upgrade::begin_binary_upgrade;
+use crate::core::graceful_shutdown::drain_and_shutdown;
+#[cfg(unix)]
 use crate::core::triggers::get_trigger_handler;
 use crate::logging::syslog::{error, info};
 #[cfg(windows)]
-use tokio::signal;
+use tokio::signal::windows;
 
 #[cfg(unix)]
 use tokio::signal::unix::{SignalKind, signal};
 
+// SIGTERM and SIGINT stop gracefully, SIGHUP reloads the configuration, SIGUSR1 reopens the log files after they were rotated
+// and SIGUSR2 starts a binary upgrade
 #[cfg(unix)]
 async fn handle_unix_signals() -> Result<(), Box<dyn std::error::Error>> {
     let mut sigterm = signal(SignalKind::terminate())?;
     let mut sigint = signal(SignalKind::interrupt())?;
     let mut sighup = signal(SignalKind::hangup())?;
+    let mut sigusr1 = signal(SignalKind::user_defined1())?;
+    let mut sigusr2 = signal(SignalKind::user_defined2())?;
 
-    tokio::select! {
-        _ = async {
-            loop {
-                sigterm.recv().await;
-                let triggers = get_trigger_handler();
-                info("Shutdown signal received, starting shutdown process");
-                triggers.run_trigger("shutdown").await;
-            }
-        } => {},
-        _ = async {
-            loop {
-                sigint.recv().await;
-                let triggers = get_trigger_handler();
-                info("Shutdown signal received, starting shutdown process");
-                triggers.run_trigger("shutdown").await;
-            }
-        } => {},
-        _ = async {
-            loop {
-                sighup.recv().await;
-                let triggers = get_trigger_handler();
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => {
+                info("Shutdown signal received, finishing the requests in progress before shutting down");
+                tokio::spawn(drain_and_shutdown());
+            },
+            _ = sigint.recv() => {
+                info("Interrupt signal received, finishing the requests in progress before shutting down");
+                tokio::spawn(drain_and_shutdown());
+            },
+            _ = sighup.recv() => {
                 info("Reload configuration signal received, starting reload process");
+                let triggers = get_trigger_handler();
+                triggers.run_trigger("refresh_cached_configuration").await;
                 triggers.run_trigger("reload_configuration").await;
-            }
-        } => {},
-    };
-
-    Ok(())
+            },
+            _ = sigusr1.recv() => {
+                info("Reopen logs signal received, reopening log files");
+                get_trigger_handler().run_trigger("reopen_logs").await;
+            },
+            _ = sigusr2.recv() => {
+                info("Binary upgrade signal received, starting the new binary with the current listeners");
+                if let Err(e) = begin_binary_upgrade() {
+                    error(format!("Could not start binary upgrade: {}", e));
+                }
+            },
+        }
+    }
 }
 
+// Ctrl+C, Ctrl+Break and closing the console, logging off or shutting down Windows all stop gracefully
 #[cfg(windows)]
 async fn handle_windows_signals() -> Result<(), Box<dyn std::error::Error>> {
+    let mut ctrl_c = windows::ctrl_c()?;
+    let mut ctrl_break = windows::ctrl_break()?;
+    let mut ctrl_close = windows::ctrl_close()?;
+    let mut ctrl_logoff = windows::ctrl_logoff()?;
+    let mut ctrl_shutdown = windows::ctrl_shutdown()?;
+
     loop {
-        signal::ctrl_c().await?;
-        info("Shutdown signal received, starting shutdown process");
-        let triggers = get_trigger_handler();
-        triggers.run_trigger("shutdown").await;
+        let event = tokio::select! {
+            _ = ctrl_c.recv() => "Ctrl+C",
+            _ = ctrl_break.recv() => "Ctrl+Break",
+            _ = ctrl_close.recv() => "console close",
+            _ = ctrl_logoff.recv() => "logoff",
+            _ = ctrl_shutdown.recv() => "system shutdown",
+        };
+        info(format!("Shutdown signal ({}) received, finishing the requests in progress before shutting down", event));
+        tokio::spawn(drain_and_shutdown());
     }
 }
 
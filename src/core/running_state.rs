@@ -1,17 +1,24 @@
 use crate::{
+    core::memory_budget::get_memory_budget,
     external_connections::external_system_handler::ExternalSystemHandler,
-    file::file_reader_structs::FileReaderCache,
+    file::{disk_cache::get_disk_cache_manager, file_reader_structs::FileReaderCache},
     http::{
         client::http_client::HttpClient,
+        http_util::set_server_header_mode,
+        panic_guard::get_panic_guard,
         request_handlers::{processors::processor_manager::ProcessorManager, request_handler_manager::RequestHandlerManager},
         site_match::binding_site_cache::BindingSiteCache,
     },
-    logging::syslog::{debug},
+    logging::syslog::{SysLog, debug, parse_log_levels, warn},
+    network::{dns_cache::get_dns_cache, outbound_connection::set_outbound_timeouts},
 };
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 use crate::logging::access_logging::AccessLogBuffer;
+use crate::logging::log_forwarding::get_log_forwarder;
+use crate::logging::log_sampling::get_log_sampler;
 
 pub struct RunningState {
     pub access_log_buffer: Arc<RwLock<AccessLogBuffer>>,
@@ -45,8 +52,35 @@ impl RunningState {
         let processor_manager = ProcessorManager::new().await;
         debug("Processor manager initialized");
 
-        // Initialize http clients
-        let http_client = HttpClient::new();
+        // Initialize http clients, which resolve upstream hosts through the DNS cache and use the outbound timeouts
+        let cached_configuration = crate::configuration::cached_configuration::get_cached_configuration();
+        let server_settings = cached_configuration.get_configuration().await.core.server_settings.clone();
+        get_dns_cache().set_ttl_seconds(server_settings.dns_cache_ttl_seconds);
+        set_outbound_timeouts(
+            server_settings.outbound_connect_timeout_seconds,
+            server_settings.outbound_read_timeout_seconds,
+            server_settings.outbound_write_timeout_seconds,
+        );
+        get_panic_guard().set_thresholds(server_settings.panic_storm_max_panics_per_minute, server_settings.panic_storm_maintenance_seconds);
+        get_memory_budget().set_max_bytes(server_settings.memory_budget_bytes);
+        get_disk_cache_manager().set_limits(server_settings.disk_cache_max_size_bytes, server_settings.disk_cache_max_unused_hours);
+        set_server_header_mode(&server_settings.server_header);
+        match parse_log_levels(&server_settings.log_level, &server_settings.log_module_levels) {
+            Ok((log_level_override, module_levels)) => SysLog::set_log_levels(log_level_override, &module_levels),
+            Err(errors) => warn(format!("Log levels are not applied: {}", errors.join(", "))),
+        }
+        get_log_sampler().configure(server_settings.log_sample_rate, server_settings.log_max_records_per_second);
+        get_log_forwarder().configure(&server_settings.remote_syslog_address, &server_settings.remote_syslog_level, &server_settings.windows_event_log_level);
+        // Clients for the proxy processors with their own TLS settings are built now, rather than when handling their first request
+        let http_client = {
+            let configuration = cached_configuration.get_configuration().await;
+            let upstream_tls_settings = configuration.proxy_processors.iter().map(|p| (p.verify_tls_certificates, &p.tls_settings));
+            HttpClient::new(
+                server_settings.upstream_pool_max_idle_per_host,
+                Duration::from_secs(server_settings.upstream_pool_idle_timeout_seconds),
+                upstream_tls_settings,
+            )
+        };
         debug("HTTP client initialized");
 
         // Start binding site cache
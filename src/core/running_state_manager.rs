@@ -1,22 +1,56 @@
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::configuration::configuration::Configuration;
+use crate::configuration::configuration_changes::ConfigurationChanges;
 use crate::core::running_state::RunningState;
+use crate::core::triggers::get_trigger_handler;
+use crate::http::http_server::BindingStartError;
+use crate::logging::access_logging::AccessLogBuffer;
+use crate::logging::syslog::{debug, info};
 use std::sync::Arc;
-use tokio::sync::{OnceCell, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OnceCell, RwLock, watch};
+
+// The outcome of a reload, for those waiting for it to be done, such as the admin API after saving the configuration
+#[derive(Clone, Debug)]
+pub struct ReloadReport {
+    // When the reload started, so a report can be told apart from one of an earlier reload
+    pub started_at: Instant,
+    pub completed_at: chrono::DateTime<chrono::Utc>,
+    pub binding_errors: Vec<BindingStartError>,
+}
 
 pub struct RunningStateManager {
     pub current_running_state: Arc<RwLock<RunningState>>,
+    // A copy of the configuration the running state was last built from, to compare a changed configuration with
+    applied_configuration: Mutex<Option<Configuration>>,
+    reload_reports: watch::Sender<Option<ReloadReport>>,
 }
 
 impl RunningStateManager {
     pub async fn new() -> Self {
         let current_running_state = Arc::new(RwLock::new(RunningState::new().await));
-        RunningStateManager { current_running_state }
+        let applied_configuration = Mutex::new(get_configuration_copy(&*get_cached_configuration().get_configuration().await));
+        RunningStateManager {
+            current_running_state,
+            applied_configuration,
+            reload_reports: watch::Sender::new(None),
+        }
+    }
+
+    pub fn get_last_reload_report(&self) -> Option<ReloadReport> {
+        self.reload_reports.borrow().clone()
+    }
+
+    // Gets the report of each reload once it is done
+    pub fn subscribe_reload_reports(&self) -> watch::Receiver<Option<ReloadReport>> {
+        self.reload_reports.subscribe()
     }
 
     pub fn get_running_state(&self) -> Arc<RwLock<RunningState>> {
         self.current_running_state.clone()
     }
 
-    pub async fn get_running_state_unlocked(&self)  -> tokio::sync::RwLockReadGuard<'_, RunningState> {
+    pub async fn get_running_state_unlocked(&self) -> tokio::sync::RwLockReadGuard<'_, RunningState> {
         let unlocked_running_state = self.current_running_state.read().await;
         unlocked_running_state
     }
@@ -36,6 +70,111 @@ impl RunningStateManager {
         // Setup a new running state
         *current_state = RunningState::new().await;
     }
+
+    // Brings the running state and the bindings in line with the cached configuration, given what changed when it was refreshed.
+    // Changes to sites and bindings only restart the bindings they concern, while changes to anything shared by all sites, such as
+    // the core settings, rebuild everything, as does a change that is not known
+    pub async fn reload_running_state(&self, changes: Option<ConfigurationChanges>) {
+        let started_at = Instant::now();
+        let mut applied_configuration = self.applied_configuration.lock().await;
+        let configuration = get_configuration_copy(&*get_cached_configuration().get_configuration().await);
+
+        let partial_reload = match (changes, applied_configuration.as_ref(), configuration.as_ref()) {
+            (Some(changes), Some(old), Some(new)) => {
+                if changes.is_empty() {
+                    info("Configuration is unchanged, nothing to reload");
+                    *applied_configuration = configuration;
+                    self.reload_reports.send_replace(Some(ReloadReport {
+                        started_at,
+                        completed_at: chrono::Utc::now(),
+                        binding_errors: Vec::new(),
+                    }));
+                    return;
+                }
+                get_bindings_to_restart(old, new, &changes).map(|binding_ids| (changes, binding_ids, new))
+            }
+            _ => None,
+        };
+
+        // Bindings not affected by the change keep running, with their connections, and only the others are stopped or started
+        let binding_errors = match partial_reload {
+            Some((changes, binding_ids, new)) => {
+                info(format!("Reloading the sites and {} bindings affected by the configuration change", binding_ids.len()));
+                self.reload_sites(&changes).await;
+
+                let started_bindings = new.bindings.iter().filter(|b| binding_ids.contains(&b.id)).cloned().collect();
+                crate::http::http_server::restart_bindings(&binding_ids, started_bindings).await
+            }
+            None => {
+                info("Reloading the full running state due to configuration change");
+                self.set_new_running_state().await;
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                crate::http::http_server::initialize_server().await
+            }
+        };
+
+        *applied_configuration = configuration;
+        self.reload_reports.send_replace(Some(ReloadReport {
+            started_at,
+            completed_at: chrono::Utc::now(),
+            binding_errors,
+        }));
+    }
+
+    // Updates the parts of the running state that hold the sites, without stopping the services
+    async fn reload_sites(&self, changes: &ConfigurationChanges) {
+        let running_state = self.get_running_state_unlocked().await;
+        running_state.get_binding_site_cache().init().await;
+
+        if !changes.added_site_ids.is_empty() || !changes.removed_site_ids.is_empty() || !changes.changed_site_ids.is_empty() {
+            let access_log_buffer_rwlock = running_state.get_access_log_buffer();
+            let mut access_log_buffer = access_log_buffer_rwlock.write().await;
+            for log in access_log_buffer.buffered_logs.values() {
+                log.consider_flush(true);
+            }
+            *access_log_buffer = AccessLogBuffer::new().await;
+            debug("Access log buffers reinitialized");
+        }
+    }
+}
+
+// The configuration holds the running PHP-CGI processes, so it cannot be cloned, but only the configured values are needed for comparing
+fn get_configuration_copy(configuration: &Configuration) -> Option<Configuration> {
+    serde_json::to_value(configuration).ok().and_then(|value| serde_json::from_value(value).ok())
+}
+
+// The ids of the bindings to restart for the changes, or None if the whole running state has to be rebuilt
+fn get_bindings_to_restart(old: &Configuration, new: &Configuration, changes: &ConfigurationChanges) -> Option<Vec<String>> {
+    if changes.core_changed || changes.request_handlers_changed {
+        return None;
+    }
+
+    // The certificates for automatic TLS are managed for all sites together
+    let site_ids: Vec<&String> = changes.added_site_ids.iter().chain(&changes.removed_site_ids).chain(&changes.changed_site_ids).collect();
+    let uses_automatic_tls = old.sites.iter().chain(&new.sites).any(|site| site_ids.contains(&&site.id) && site.tls_automatic_enabled);
+    if uses_automatic_tls {
+        return None;
+    }
+
+    let mut binding_ids: Vec<String> = changes
+        .added_binding_ids
+        .iter()
+        .chain(&changes.removed_binding_ids)
+        .chain(&changes.changed_binding_ids)
+        .cloned()
+        .collect();
+
+    // Plain HTTP bindings look up their sites for every request, while TLS bindings load the certificates of their sites when started
+    let tls_binding_ids: Vec<String> = new
+        .bindings
+        .iter()
+        .filter(|b| b.is_tls && !binding_ids.contains(&b.id))
+        .filter(|b| changes.bindings_with_changed_sites.contains(&b.id) || new.binding_sites.iter().any(|relation| relation.binding_id == b.id && site_ids.contains(&&relation.site_id)))
+        .map(|b| b.id.clone())
+        .collect();
+    binding_ids.extend(tls_binding_ids);
+
+    Some(binding_ids)
 }
 
 static RUNNING_STATE_MANAGER_SINGLETON: OnceCell<RunningStateManager> = OnceCell::const_new();
@@ -43,3 +182,81 @@ static RUNNING_STATE_MANAGER_SINGLETON: OnceCell<RunningStateManager> = OnceCell
 pub async fn get_running_state_manager() -> &'static RunningStateManager {
     RUNNING_STATE_MANAGER_SINGLETON.get_or_init(|| async { RunningStateManager::new().await }).await
 }
+
+// Reloads the configuration and waits for the reload to be done, for those reporting back the bindings it could not start,
+// such as the admin API and the control socket. None if the reload did not finish within the timeout
+pub async fn reload_configuration_and_wait(timeout: Duration) -> Option<ReloadReport> {
+    // Subscribed before the reload is triggered, so its report is not missed
+    let mut reload_reports = get_running_state_manager().await.subscribe_reload_reports();
+    let requested_at = Instant::now();
+
+    let triggers = get_trigger_handler();
+    triggers.run_trigger("refresh_cached_configuration").await;
+    triggers.run_trigger("reload_configuration").await;
+
+    tokio::time::timeout(timeout, reload_reports.wait_for(|report| report.as_ref().is_some_and(|r| r.started_at >= requested_at)))
+        .await
+        .ok()
+        .and_then(|report| report.ok().and_then(|report| report.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::binding::Binding;
+    use crate::configuration::binding_site_relation::BindingSiteRelationship;
+    use crate::configuration::site::Site;
+
+    fn get_test_configurations() -> (Configuration, Configuration) {
+        let old = Configuration::get_default();
+        let new = get_configuration_copy(&old).unwrap();
+        (old, new)
+    }
+
+    #[test]
+    fn test_new_site_on_http_binding_restarts_no_bindings() {
+        let (old, mut new) = get_test_configurations();
+        let http_binding_id = new.bindings.iter().find(|b| !b.is_tls).unwrap().id.clone();
+
+        let site = Site::new();
+        new.binding_sites.push(BindingSiteRelationship {
+            binding_id: http_binding_id,
+            site_id: site.id.clone(),
+        });
+        new.sites.push(site);
+
+        let changes = ConfigurationChanges::between(&old, &new);
+        assert_eq!(get_bindings_to_restart(&old, &new, &changes), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_new_site_on_tls_binding_restarts_only_that_binding() {
+        let (old, mut new) = get_test_configurations();
+        let tls_binding_id = new.bindings.iter().find(|b| b.is_tls).unwrap().id.clone();
+
+        let site = Site::new();
+        new.binding_sites.push(BindingSiteRelationship {
+            binding_id: tls_binding_id.clone(),
+            site_id: site.id.clone(),
+        });
+        new.sites.push(site);
+
+        let changes = ConfigurationChanges::between(&old, &new);
+        assert_eq!(get_bindings_to_restart(&old, &new, &changes), Some(vec![tls_binding_id]));
+    }
+
+    #[test]
+    fn test_added_binding_is_started_and_core_change_rebuilds_everything() {
+        let (old, mut new) = get_test_configurations();
+        let mut binding = Binding::new();
+        binding.port = 8080;
+        new.bindings.push(binding.clone());
+
+        let changes = ConfigurationChanges::between(&old, &new);
+        assert_eq!(get_bindings_to_restart(&old, &new, &changes), Some(vec![binding.id]));
+
+        new.core.server_settings.max_body_size += 1;
+        let changes = ConfigurationChanges::between(&old, &new);
+        assert_eq!(get_bindings_to_restart(&old, &new, &changes), None);
+    }
+}
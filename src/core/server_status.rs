@@ -0,0 +1,190 @@
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::core::control_socket::send_control_command;
+use crate::core::graceful_shutdown::{is_draining, is_in_service};
+use crate::core::monitoring::get_monitoring_state;
+use crate::core::operation_mode::get_operation_mode_as_string;
+use crate::core::running_state_manager::get_running_state_manager;
+use crate::http::request_handlers::processors::proxy_helpers::no_verifier::NoVerifier;
+use crate::tls::tls_config::tls_config;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+// The status command gives up on a server that does not answer within this
+const STATUS_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ServerStatusBinding {
+    pub address: String,
+    pub is_tls: bool,
+    pub is_admin: bool,
+    // The HTTP versions offered, see BINDING_PROTOCOLS
+    pub protocols: String,
+    pub site_count: usize,
+}
+
+// A short summary of the running server, for the startup banner and `gruxi status`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ServerStatus {
+    pub version: String,
+    pub operation_mode: String,
+    pub uptime_seconds: u64,
+    pub bindings: Vec<ServerStatusBinding>,
+    pub site_count: usize,
+    pub active_connections: usize,
+    pub requests_served: usize,
+    pub in_service: bool,
+    pub draining: bool,
+    // RFC 3339, or None if the configuration was not reloaded since the start
+    pub last_reload_at: Option<String>,
+    pub last_reload_binding_errors: usize,
+}
+
+pub async fn get_server_status() -> ServerStatus {
+    let configuration = get_cached_configuration().get_configuration().await;
+    let monitoring_state = get_monitoring_state().await;
+    let last_reload = get_running_state_manager().await.get_last_reload_report();
+
+    let bindings = configuration
+        .bindings
+        .iter()
+        .map(|binding| ServerStatusBinding {
+            address: format!("{}:{}", binding.ip, binding.port),
+            is_tls: binding.is_tls,
+            is_admin: binding.is_admin,
+            protocols: binding.protocols.clone(),
+            site_count: configuration.binding_sites.iter().filter(|relation| relation.binding_id == binding.id).count(),
+        })
+        .collect();
+
+    ServerStatus {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        operation_mode: get_operation_mode_as_string(),
+        uptime_seconds: monitoring_state.get_uptime_seconds(),
+        bindings,
+        site_count: configuration.sites.len(),
+        active_connections: monitoring_state.get_requests_in_queue(),
+        requests_served: monitoring_state.get_requests_served(),
+        in_service: is_in_service(),
+        draining: is_draining(),
+        last_reload_at: last_reload.as_ref().map(|report| report.completed_at.to_rfc3339()),
+        last_reload_binding_errors: last_reload.map(|report| report.binding_errors.len()).unwrap_or(0),
+    }
+}
+
+impl ServerStatus {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let state = if self.draining {
+            "draining"
+        } else if self.in_service {
+            "in service"
+        } else {
+            "out of service"
+        };
+        let _ = writeln!(text, "Gruxi {} ({}), up {}, {}", self.version, self.operation_mode, format_uptime(self.uptime_seconds), state);
+        let _ = writeln!(
+            text,
+            "Sites: {}, active connections: {}, requests served: {}",
+            self.site_count, self.active_connections, self.requests_served
+        );
+        let last_reload = match &self.last_reload_at {
+            Some(at) if self.last_reload_binding_errors > 0 => format!("{} ({} bindings failed to start)", at, self.last_reload_binding_errors),
+            Some(at) => at.clone(),
+            None => "never".to_string(),
+        };
+        let _ = writeln!(text, "Last configuration reload: {}", last_reload);
+        let _ = writeln!(text, "Bindings:");
+        for binding in &self.bindings {
+            let scheme = if binding.is_tls { "https" } else { "http" };
+            let admin = if binding.is_admin { ", admin portal" } else { "" };
+            let _ = writeln!(text, "  {}://{} [{}] {} sites{}", scheme, binding.address, binding.protocols, binding.site_count, admin);
+        }
+        text
+    }
+}
+
+// Such as "3d 4h 12m", or "45s" for the first minute
+fn format_uptime(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", seconds),
+        (0, 0, _) => format!("{}m", minutes),
+        (0, _, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+// Gets the status from a running server, by default the one running from this directory, through its control socket or else its admin portal.
+// The admin portal needs the session token of an admin, and uses a certificate of its own, so it is not verified
+pub async fn fetch_server_status(url: Option<String>, token: Option<String>) -> Result<ServerStatus, String> {
+    let url = match url {
+        Some(url) => url,
+        None => {
+            if let Ok(response) = send_control_command("status").await
+                && let Some(status) = response.status
+            {
+                return Ok(status);
+            }
+            get_local_status_url()?
+        }
+    };
+    let uri: hyper::Uri = url.parse().map_err(|e| format!("Invalid url '{}': {}", url, e))?;
+
+    let mut client_tls_config = tls_config();
+    client_tls_config.dangerous().set_certificate_verifier(Arc::new(NoVerifier));
+    let connector = hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(client_tls_config).https_or_http().enable_http1().build();
+    let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let mut request_builder = hyper::Request::builder().method("GET").uri(uri);
+    if let Some(token) = &token {
+        request_builder = request_builder.header(hyper::header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let request = request_builder.body(Empty::<Bytes>::new()).map_err(|e| e.to_string())?;
+    let (status, body) = tokio::time::timeout(STATUS_REQUEST_TIMEOUT, async {
+        let response = client.request(request).await.map_err(|e| format!("Could not connect to Gruxi at {}: {}", url, e))?;
+        let status = response.status();
+        let body = response.into_body().collect().await.map_err(|e| format!("Failed to read the status from {}: {}", url, e))?.to_bytes();
+        Ok::<_, String>((status, body))
+    })
+    .await
+    .map_err(|_| format!("Gruxi at {} did not answer within {} seconds", url, STATUS_REQUEST_TIMEOUT.as_secs()))??;
+
+    if status == hyper::StatusCode::UNAUTHORIZED && token.is_none() {
+        return Err(format!("Gruxi at {} requires a login, pass the session token of an admin with --token", url));
+    }
+    if !status.is_success() {
+        return Err(format!("Gruxi at {} answered {}: {}", url, status, String::from_utf8_lossy(&body)));
+    }
+    serde_json::from_slice(&body).map_err(|e| format!("Invalid status from {}: {}", url, e))
+}
+
+// The status url of the admin portal in the local configuration
+fn get_local_status_url() -> Result<String, String> {
+    crate::database::database_schema::initialize_database().map_err(|e| format!("Failed to initialize database: {}", e))?;
+    let configuration = crate::configuration::load_configuration::fetch_configuration_in_db().map_err(|e| format!("Failed to load configuration: {}", e))?;
+    let admin_portal = &configuration.core.admin_portal;
+    if !admin_portal.is_enabled {
+        return Err("The admin portal is disabled, so the status cannot be fetched. Use --url for another status url".to_string());
+    }
+    Ok(format!("https://127.0.0.1:{}/server/status", admin_portal.port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(45), "45s");
+        assert_eq!(format_uptime(125), "2m");
+        assert_eq!(format_uptime(3 * 3600 + 60), "3h 1m");
+        assert_eq!(format_uptime(2 * 86400 + 5 * 3600 + 7 * 60), "2d 5h 7m");
+    }
+}
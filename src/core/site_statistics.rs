@@ -0,0 +1,288 @@
+use crate::core::database_connection::{get_database_connection, is_database_read_only};
+use crate::logging::syslog::{debug, warn};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+// How often the cumulative statistics are written to the database
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+// Rolling windows are kept per minute, for up to a day
+const MINUTES_KEPT: usize = 1440;
+
+// The rolling windows reported for each site, by name and length in minutes
+const ROLLING_WINDOWS: [(&str, u64); 3] = [("last_5_minutes", 5), ("last_hour", 60), ("last_24_hours", 1440)];
+
+static SITE_STATISTICS: OnceLock<SiteStatistics> = OnceLock::new();
+
+pub fn get_site_statistics() -> &'static SiteStatistics {
+    SITE_STATISTICS.get_or_init(SiteStatistics::new)
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RollingWindowStatistics {
+    // Name of the window, such as "last_hour"
+    pub window: String,
+    pub requests_served: u64,
+    pub bytes_sent: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SiteStatisticsSnapshot {
+    pub site_id: String,
+    // Since the statistics were started, kept across restarts
+    pub requests_served: u64,
+    pub bytes_sent: u64,
+    pub windows: Vec<RollingWindowStatistics>,
+}
+
+struct MinuteBucket {
+    minute: AtomicU64,
+    requests_served: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+struct SiteCounters {
+    requests_served: AtomicU64,
+    bytes_sent: AtomicU64,
+    // Ring of the last day of minutes, indexed by the minute since the epoch
+    minutes: Vec<MinuteBucket>,
+}
+
+impl SiteCounters {
+    fn new(requests_served: u64, bytes_sent: u64) -> Self {
+        SiteCounters {
+            requests_served: AtomicU64::new(requests_served),
+            bytes_sent: AtomicU64::new(bytes_sent),
+            minutes: (0..MINUTES_KEPT)
+                .map(|_| MinuteBucket {
+                    minute: AtomicU64::new(0),
+                    requests_served: AtomicU64::new(0),
+                    bytes_sent: AtomicU64::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    fn record(&self, bytes_sent: u64, minute: u64) {
+        self.requests_served.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes_sent, Ordering::Relaxed);
+
+        // The first request of a minute takes over the bucket from the same minute a day earlier
+        let bucket = &self.minutes[(minute % MINUTES_KEPT as u64) as usize];
+        let bucket_minute = bucket.minute.load(Ordering::Relaxed);
+        if bucket_minute != minute && bucket.minute.compare_exchange(bucket_minute, minute, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            bucket.requests_served.store(0, Ordering::Relaxed);
+            bucket.bytes_sent.store(0, Ordering::Relaxed);
+        }
+        bucket.requests_served.fetch_add(1, Ordering::Relaxed);
+        bucket.bytes_sent.fetch_add(bytes_sent, Ordering::Relaxed);
+    }
+
+    fn get_window(&self, minutes: u64, now_minute: u64) -> (u64, u64) {
+        let oldest_minute = now_minute.saturating_sub(minutes - 1);
+        self.minutes
+            .iter()
+            .filter(|bucket| (oldest_minute..=now_minute).contains(&bucket.minute.load(Ordering::Relaxed)))
+            .fold((0, 0), |(requests_served, bytes_sent), bucket| {
+                (requests_served + bucket.requests_served.load(Ordering::Relaxed), bytes_sent + bucket.bytes_sent.load(Ordering::Relaxed))
+            })
+    }
+}
+
+// Requests served and bytes sent per site, cumulative and over rolling windows, so hosting operators can see what each site uses.
+// The cumulative counters are written to the database periodically and on shutdown, the rolling windows are kept in memory only
+pub struct SiteStatistics {
+    sites: DashMap<String, Arc<SiteCounters>>,
+    // Set when there is something new to write to the database
+    changed: AtomicBool,
+}
+
+impl SiteStatistics {
+    pub fn new() -> Self {
+        SiteStatistics {
+            sites: DashMap::new(),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record(&self, site_id: &str, bytes_sent: u64) {
+        self.record_at(site_id, bytes_sent, get_current_minute());
+    }
+
+    fn record_at(&self, site_id: &str, bytes_sent: u64, minute: u64) {
+        let counters = match self.sites.get(site_id) {
+            Some(counters) => counters.clone(),
+            None => self.sites.entry(site_id.to_string()).or_insert_with(|| Arc::new(SiteCounters::new(0, 0))).clone(),
+        };
+        counters.record(bytes_sent, minute);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn get_site(&self, site_id: &str) -> SiteStatisticsSnapshot {
+        self.get_site_at(site_id, get_current_minute())
+    }
+
+    fn get_site_at(&self, site_id: &str, now_minute: u64) -> SiteStatisticsSnapshot {
+        let counters = self.sites.get(site_id).map(|counters| counters.clone());
+        let windows = ROLLING_WINDOWS
+            .iter()
+            .map(|(window, minutes)| {
+                let (requests_served, bytes_sent) = counters.as_ref().map(|counters| counters.get_window(*minutes, now_minute)).unwrap_or((0, 0));
+                RollingWindowStatistics {
+                    window: window.to_string(),
+                    requests_served,
+                    bytes_sent,
+                }
+            })
+            .collect();
+        SiteStatisticsSnapshot {
+            site_id: site_id.to_string(),
+            requests_served: counters.as_ref().map(|counters| counters.requests_served.load(Ordering::Relaxed)).unwrap_or(0),
+            bytes_sent: counters.as_ref().map(|counters| counters.bytes_sent.load(Ordering::Relaxed)).unwrap_or(0),
+            windows,
+        }
+    }
+
+    // All sites with statistics, ordered by site id
+    pub fn get_sites(&self) -> Vec<SiteStatisticsSnapshot> {
+        let mut site_ids: Vec<String> = self.sites.iter().map(|entry| entry.key().clone()).collect();
+        site_ids.sort();
+        site_ids.iter().map(|site_id| self.get_site(site_id)).collect()
+    }
+
+    // Forgets the sites no longer in the configuration that served nothing for a day, such as deleted ones, and returns their ids.
+    // Sites served without being in the configuration, such as development sites, are kept while they get requests
+    pub fn remove_idle_sites(&self, configured_site_ids: &HashSet<String>) -> Vec<String> {
+        self.remove_idle_sites_at(configured_site_ids, get_current_minute())
+    }
+
+    fn remove_idle_sites_at(&self, configured_site_ids: &HashSet<String>, now_minute: u64) -> Vec<String> {
+        let idle_site_ids: Vec<String> = self
+            .sites
+            .iter()
+            .filter(|entry| !configured_site_ids.contains(entry.key()) && entry.value().get_window(MINUTES_KEPT as u64, now_minute).0 == 0)
+            .map(|entry| entry.key().clone())
+            .collect();
+        for site_id in &idle_site_ids {
+            self.sites.remove(site_id);
+        }
+        idle_site_ids
+    }
+
+    // Picks up the cumulative counters from the last run
+    pub fn load_from_database(&self) -> Result<(), String> {
+        let connection = get_database_connection()?;
+        let mut statement = connection
+            .prepare("SELECT site_id, requests_served, bytes_sent FROM site_statistics")
+            .map_err(|e| format!("Failed to prepare site statistics statement: {}", e))?;
+        while let Ok(sqlite::State::Row) = statement.next() {
+            let site_id: String = statement.read(0).map_err(|e| format!("Failed to read site_id: {}", e))?;
+            let requests_served: i64 = statement.read(1).map_err(|e| format!("Failed to read requests_served: {}", e))?;
+            let bytes_sent: i64 = statement.read(2).map_err(|e| format!("Failed to read bytes_sent: {}", e))?;
+            self.sites.insert(site_id, Arc::new(SiteCounters::new(requests_served.max(0) as u64, bytes_sent.max(0) as u64)));
+        }
+        Ok(())
+    }
+
+    pub fn save_to_database(&self) -> Result<(), String> {
+        if is_database_read_only() || !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let connection = get_database_connection()?;
+        let updated_at = chrono::Utc::now().to_rfc3339();
+        for entry in self.sites.iter() {
+            connection
+                .execute(format!(
+                    "INSERT INTO site_statistics (site_id, requests_served, bytes_sent, updated_at) VALUES ('{}', {}, {}, '{}') \
+                     ON CONFLICT(site_id) DO UPDATE SET requests_served = excluded.requests_served, bytes_sent = excluded.bytes_sent, updated_at = excluded.updated_at",
+                    entry.key().replace("'", "''"),
+                    entry.value().requests_served.load(Ordering::Relaxed).min(i64::MAX as u64),
+                    entry.value().bytes_sent.load(Ordering::Relaxed).min(i64::MAX as u64),
+                    updated_at
+                ))
+                .map_err(|e| {
+                    self.changed.store(true, Ordering::Relaxed);
+                    format!("Failed to save site statistics: {}", e)
+                })?;
+        }
+        Ok(())
+    }
+
+    // Loads the statistics of the last run and writes them to the database periodically from then on
+    pub fn initialize(&'static self) {
+        if let Err(e) = self.load_from_database() {
+            warn(format!("Failed to load site statistics, counting from zero: {}", e));
+        }
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(PERSIST_INTERVAL).await;
+                match self.save_to_database() {
+                    Ok(()) => debug("Site statistics saved"),
+                    Err(e) => warn(e),
+                }
+            }
+        });
+    }
+}
+
+impl Default for SiteStatistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn get_current_minute() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 60).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cumulative_and_rolling_windows() {
+        let statistics = SiteStatistics::new();
+        let now = 30_000_000;
+        statistics.record_at("site1", 1000, now - 120);
+        statistics.record_at("site1", 500, now - 3);
+        statistics.record_at("site1", 250, now);
+        statistics.record_at("site2", 10, now);
+
+        let site1 = statistics.get_site_at("site1", now);
+        assert_eq!(site1.requests_served, 3);
+        assert_eq!(site1.bytes_sent, 1750);
+        let window = |name: &str| site1.windows.iter().find(|w| w.window == name).map(|w| (w.requests_served, w.bytes_sent)).unwrap();
+        assert_eq!(window("last_5_minutes"), (2, 750));
+        assert_eq!(window("last_hour"), (2, 750));
+        assert_eq!(window("last_24_hours"), (3, 1750));
+
+        // A day later the buckets are reused, and the old minutes drop out of the windows
+        statistics.record_at("site1", 5, now + MINUTES_KEPT as u64);
+        let site1 = statistics.get_site_at("site1", now + MINUTES_KEPT as u64);
+        assert_eq!(site1.requests_served, 4);
+        assert_eq!(site1.windows[2].requests_served, 1);
+
+        // Sites without requests have empty statistics
+        assert_eq!(statistics.get_site_at("unknown", now).requests_served, 0);
+    }
+
+    #[test]
+    fn test_remove_idle_sites() {
+        let statistics = SiteStatistics::new();
+        let now = 30_000_000;
+        statistics.record_at("configured", 10, now - 2 * MINUTES_KEPT as u64);
+        statistics.record_at("deleted", 10, now - 2 * MINUTES_KEPT as u64);
+        statistics.record_at("development", 10, now - 60);
+
+        let configured_site_ids = HashSet::from(["configured".to_string()]);
+        assert_eq!(statistics.remove_idle_sites_at(&configured_site_ids, now), vec!["deleted".to_string()]);
+        assert_eq!(statistics.get_site_at("configured", now).requests_served, 1);
+        assert_eq!(statistics.get_site_at("development", now).requests_served, 1);
+    }
+}
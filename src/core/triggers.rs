@@ -1,24 +1,134 @@
+use crate::configuration::configuration_changes::ConfigurationChanges;
 use crate::logging::syslog::{trace, warn};
 use std::{
     collections::HashMap,
     sync::{Arc, OnceLock},
 };
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, broadcast};
 use tokio_util::sync::CancellationToken;
 
+// Subscribers that fall this many events behind skip the oldest ones, rather than holding up whoever publishes
+const EVENT_BUS_CAPACITY: usize = 64;
+
+// The events of the server. Each event also runs the trigger of the same name, so code waiting on the
+// cancellation token of a trigger keeps working, while subscribers of the event bus get the details
+#[derive(Debug, Clone, PartialEq)]
+pub enum GruxiEvent {
+    RefreshCachedConfiguration,
+    // Tells the caches built from the configuration to start over. The running state is rebuilt on ConfigurationChanged instead,
+    // which follows the refresh of the cached configuration
+    ReloadConfiguration,
+    // What changed compared to the previous cached configuration. None when it is not known, such as from run_trigger()
+    ConfigurationChanged(Option<ConfigurationChanges>),
+    StopServices,
+    Drain,
+    Shutdown,
+    OperationModeChanged,
+    ReopenLogs,
+    // A new certificate from ACME is in use for these domains
+    CertificateDeployed { domains: Vec<String> },
+    // The server is serving on its bindings
+    Started,
+    // A handler process, such as PHP-CGI, was restarted after it stopped or stopped responding
+    HandlerRestarted { handler_id: String, reason: String },
+    // A client is banned for a while, such as after an HTTP/2 flood
+    ClientBanned { ip: String, seconds: u64, reason: String },
+}
+
+impl GruxiEvent {
+    pub fn get_trigger_name(&self) -> &'static str {
+        match self {
+            GruxiEvent::RefreshCachedConfiguration => "refresh_cached_configuration",
+            GruxiEvent::ReloadConfiguration => "reload_configuration",
+            GruxiEvent::ConfigurationChanged(_) => "configuration_changed",
+            GruxiEvent::StopServices => "stop_services",
+            GruxiEvent::Drain => "drain",
+            GruxiEvent::Shutdown => "shutdown",
+            GruxiEvent::OperationModeChanged => "operation_mode_changed",
+            GruxiEvent::ReopenLogs => "reopen_logs",
+            GruxiEvent::CertificateDeployed { .. } => "certificate_deployed",
+            GruxiEvent::Started => "started",
+            GruxiEvent::HandlerRestarted { .. } => "handler_restarted",
+            GruxiEvent::ClientBanned { .. } => "client_banned",
+        }
+    }
+
+    // The event for a trigger name, without a payload
+    pub fn from_trigger_name(name: &str) -> Option<Self> {
+        match name {
+            "refresh_cached_configuration" => Some(GruxiEvent::RefreshCachedConfiguration),
+            "reload_configuration" => Some(GruxiEvent::ReloadConfiguration),
+            "configuration_changed" => Some(GruxiEvent::ConfigurationChanged(None)),
+            "stop_services" => Some(GruxiEvent::StopServices),
+            "drain" => Some(GruxiEvent::Drain),
+            "shutdown" => Some(GruxiEvent::Shutdown),
+            "operation_mode_changed" => Some(GruxiEvent::OperationModeChanged),
+            "reopen_logs" => Some(GruxiEvent::ReopenLogs),
+            "certificate_deployed" => Some(GruxiEvent::CertificateDeployed { domains: Vec::new() }),
+            "started" => Some(GruxiEvent::Started),
+            "handler_restarted" => Some(GruxiEvent::HandlerRestarted {
+                handler_id: String::new(),
+                reason: String::new(),
+            }),
+            "client_banned" => Some(GruxiEvent::ClientBanned {
+                ip: String::new(),
+                seconds: 0,
+                reason: String::new(),
+            }),
+            _ => None,
+        }
+    }
+}
+
 pub struct Triggers {
     pub triggers: HashMap<String, Arc<RwLock<CancellationToken>>>,
+    events: broadcast::Sender<GruxiEvent>,
 }
 
 impl Triggers {
     pub fn new() -> Self {
         let mut triggers = HashMap::new();
-        let known_triggers = vec!["refresh_cached_configuration", "reload_configuration", "configuration_changed", "stop_services", "shutdown", "operation_mode_changed"];
+        let known_triggers = vec![
+            "refresh_cached_configuration",
+            "reload_configuration",
+            "configuration_changed",
+            "stop_services",
+            "drain",
+            "shutdown",
+            "operation_mode_changed",
+            "reopen_logs",
+            "certificate_deployed",
+            "started",
+            "handler_restarted",
+            "client_banned",
+        ];
         for trigger_name in known_triggers {
             triggers.insert(trigger_name.to_string(), Arc::new(RwLock::new(CancellationToken::new())));
         }
 
-        Triggers { triggers }
+        let (events, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+
+        Triggers { triggers, events }
+    }
+
+    // Receives all events published after this call
+    pub fn subscribe(&self) -> broadcast::Receiver<GruxiEvent> {
+        self.events.subscribe()
+    }
+
+    // Sends the event to the subscribers and runs the trigger of the same name
+    pub async fn publish(&self, event: GruxiEvent) {
+        let name = event.get_trigger_name();
+        trace(format!("Publishing event: {:?}", event));
+        // Failing only means there are no subscribers right now
+        let _ = self.events.send(event);
+
+        if let Some(token_lock) = self.triggers.get(name) {
+            let token = token_lock.read().await;
+            token.cancel();
+        }
+        // When token is used, we renew it for next time
+        self.renew_trigger(name).await;
     }
 
     pub async fn get_token(&self, name: &str) -> Option<CancellationToken> {
@@ -37,17 +147,17 @@ impl Triggers {
         self.triggers.get(name).cloned()
     }
 
+    // Publishes the event of the trigger name, without a payload
     pub async fn run_trigger(&self, name: &str) {
-        if let Some(token_lock) = self.triggers.get(name) {
-            let token_clone = token_lock.clone();
-            let token = token_clone.read().await;
-            trace(format!("Running trigger: {}", name));
-            token.cancel();
-        } else {
-            warn(format!("A non-existent trigger was triggered - Please report as a bug. Trigger: {}", name));
+        match GruxiEvent::from_trigger_name(name) {
+            Some(event) => {
+                trace(format!("Running trigger: {}", name));
+                self.publish(event).await;
+            }
+            None => {
+                warn(format!("A non-existent trigger was triggered - Please report as a bug. Trigger: {}", name));
+            }
         }
-        // When token is used, we renew it for next time
-        self.renew_trigger(name).await;
     }
 
     async fn renew_trigger(&self, name: &str) {
@@ -63,3 +173,48 @@ static TRIGGERS_SINGLETON: OnceLock<Triggers> = OnceLock::new();
 pub fn get_trigger_handler() -> &'static Triggers {
     TRIGGERS_SINGLETON.get_or_init(|| Triggers::new())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_sends_event_and_runs_trigger() {
+        let triggers = Triggers::new();
+        let mut events = triggers.subscribe();
+        let token = triggers.get_token("certificate_deployed").await.unwrap();
+
+        let event = GruxiEvent::CertificateDeployed {
+            domains: vec!["example.com".to_string()],
+        };
+        triggers.publish(event.clone()).await;
+
+        assert_eq!(events.recv().await.unwrap(), event);
+        assert!(token.is_cancelled());
+        // The token is renewed for the next time
+        assert!(!triggers.get_token("certificate_deployed").await.unwrap().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_run_trigger_publishes_event_without_payload() {
+        let triggers = Triggers::new();
+        let mut events = triggers.subscribe();
+
+        triggers.run_trigger("configuration_changed").await;
+        triggers.run_trigger("not_a_trigger").await;
+        triggers.run_trigger("reload_configuration").await;
+
+        assert_eq!(events.recv().await.unwrap(), GruxiEvent::ConfigurationChanged(None));
+        assert_eq!(events.recv().await.unwrap(), GruxiEvent::ReloadConfiguration);
+    }
+
+    #[test]
+    fn test_every_trigger_has_an_event() {
+        let triggers = Triggers::new();
+        for name in triggers.triggers.keys() {
+            let event = GruxiEvent::from_trigger_name(name).unwrap();
+            assert_eq!(event.get_trigger_name(), name);
+        }
+        assert!(GruxiEvent::from_trigger_name("not_a_trigger").is_none());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::core::admin_user::cleanup_all_expired_sessions;
+use crate::core::database_connection::{get_database_connection, is_database_read_only};
+use crate::core::site_statistics::get_site_statistics;
+use crate::logging::syslog::{debug, info, warn};
+use chrono::Utc;
+use sqlite::Connection;
+use std::collections::HashSet;
+use std::time::Duration;
+
+// How often the database is maintained, the first time one interval after the start
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// The database file is compacted with VACUUM once deleted rows leave more than this percentage of its pages unused
+const VACUUM_FREE_PAGE_PERCENTAGE: i64 = 20;
+
+// Maintains the database periodically: removes expired sessions and password reset tokens and the statistics of deleted sites,
+// compacts the file when much of it is unused, and writes the WAL into the database file so it does not keep growing
+pub fn start_database_maintenance_task() {
+    if is_database_read_only() {
+        return;
+    }
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(MAINTENANCE_INTERVAL).await;
+            if let Err(e) = run_database_maintenance().await {
+                warn(format!("Database maintenance failed: {}", e));
+            }
+        }
+    });
+}
+
+pub async fn run_database_maintenance() -> Result<(), String> {
+    let (idle_timeout, configured_site_ids) = {
+        let configuration = get_cached_configuration().get_configuration().await;
+        let idle_timeout_minutes = configuration.core.admin_portal.session_idle_timeout_minutes;
+        let idle_timeout = if idle_timeout_minutes > 0 {
+            Some(chrono::Duration::minutes(idle_timeout_minutes as i64))
+        } else {
+            None
+        };
+        let configured_site_ids: HashSet<String> = configuration.sites.iter().map(|site| site.id.clone()).collect();
+        (idle_timeout, configured_site_ids)
+    };
+
+    // VACUUM may take a while on a big database, so it is kept off the request handling threads
+    tokio::task::spawn_blocking(move || maintain_database(idle_timeout, &configured_site_ids))
+        .await
+        .map_err(|e| format!("Database maintenance task failed: {}", e))?
+}
+
+fn maintain_database(idle_timeout: Option<chrono::Duration>, configured_site_ids: &HashSet<String>) -> Result<(), String> {
+    cleanup_all_expired_sessions(idle_timeout)?;
+
+    let connection = get_database_connection()?;
+    connection
+        .execute(format!("DELETE FROM password_reset_tokens WHERE expires_at < '{}'", Utc::now().to_rfc3339()))
+        .map_err(|e| format!("Failed to remove expired password reset tokens: {}", e))?;
+
+    let removed_site_ids = get_site_statistics().remove_idle_sites(configured_site_ids);
+    for site_id in &removed_site_ids {
+        connection
+            .execute(format!("DELETE FROM site_statistics WHERE site_id = '{}'", site_id.replace("'", "''")))
+            .map_err(|e| format!("Failed to remove site statistics: {}", e))?;
+    }
+    if !removed_site_ids.is_empty() {
+        info(format!("Removed the statistics of {} sites no longer in the configuration", removed_site_ids.len()));
+    }
+
+    let page_count = read_pragma(&connection, "page_count")?;
+    let free_page_count = read_pragma(&connection, "freelist_count")?;
+    if page_count > 0 && free_page_count * 100 > page_count * VACUUM_FREE_PAGE_PERCENTAGE {
+        connection.execute("VACUUM;").map_err(|e| format!("Failed to compact the database: {}", e))?;
+        info(format!("Compacted the database, {} of its {} pages were unused", free_page_count, page_count));
+    }
+
+    // Writes the WAL into the database file and empties it, which also keeps a copy of the database file up to date
+    connection
+        .execute("PRAGMA wal_checkpoint(TRUNCATE);")
+        .map_err(|e| format!("Failed to checkpoint the database: {}", e))?;
+
+    debug("Database maintenance done");
+    Ok(())
+}
+
+fn read_pragma(connection: &Connection, pragma: &str) -> Result<i64, String> {
+    let mut statement = connection.prepare(format!("PRAGMA {};", pragma)).map_err(|e| format!("Failed to read {}: {}", pragma, e))?;
+    match statement.next().map_err(|e| format!("Failed to read {}: {}", pragma, e))? {
+        sqlite::State::Row => statement.read::<i64, _>(0).map_err(|e| format!("Failed to read {}: {}", pragma, e)),
+        sqlite::State::Done => Ok(0),
+    }
+}
@@ -1,8 +1,9 @@
 use sqlite::Connection;
 
 use crate::{
-    core::database_connection::get_database_connection,
-    database::database_schema::{get_schema_version},
+    core::database_connection::{get_database_connection, get_database_path},
+    database::database_schema::get_schema_version,
+    logging::syslog::info,
 };
 
 pub fn migrate_database() -> i32 {
@@ -20,6 +21,12 @@ pub fn migrate_database() -> i32 {
         }
     };
 
+    // Keep a copy of the database as it was, so the configuration can be recovered if a migration goes wrong
+    match backup_database(&connection, schema_version) {
+        Ok(backup_path) => info(format!("Database backed up to {} before migrating from version {}", backup_path, schema_version)),
+        Err(e) => panic!("Failed to back up database before migration, not migrating: {}", e),
+    }
+
     // Migration from 2 to 3
     if schema_version == 2 {
         let result = migrate_db_helper(&connection, 2, 3, migrate_db_2_to_3);
@@ -36,10 +43,382 @@ pub fn migrate_database() -> i32 {
         }
         schema_version = 4;
     }
+    // Migration from 4 to 5
+    if schema_version == 4 {
+        let result = migrate_db_helper(&connection, 4, 5, migrate_db_4_to_5);
+        if let Err(e) = result {
+            panic!("Database migration from version 4 to 5 failed: {}", e);
+        }
+        schema_version = 5;
+    }
+    // Migration from 5 to 6
+    if schema_version == 5 {
+        let result = migrate_db_helper(&connection, 5, 6, migrate_db_5_to_6);
+        if let Err(e) = result {
+            panic!("Database migration from version 5 to 6 failed: {}", e);
+        }
+        schema_version = 6;
+    }
+    // Migration from 6 to 7
+    if schema_version == 6 {
+        let result = migrate_db_helper(&connection, 6, 7, migrate_db_6_to_7);
+        if let Err(e) = result {
+            panic!("Database migration from version 6 to 7 failed: {}", e);
+        }
+        schema_version = 7;
+    }
+    // Migration from 7 to 8
+    if schema_version == 7 {
+        let result = migrate_db_helper(&connection, 7, 8, migrate_db_7_to_8);
+        if let Err(e) = result {
+            panic!("Database migration from version 7 to 8 failed: {}", e);
+        }
+        schema_version = 8;
+    }
+    // Migration from 8 to 9
+    if schema_version == 8 {
+        let result = migrate_db_helper(&connection, 8, 9, migrate_db_8_to_9);
+        if let Err(e) = result {
+            panic!("Database migration from version 8 to 9 failed: {}", e);
+        }
+        schema_version = 9;
+    }
+    // Migration from 9 to 10
+    if schema_version == 9 {
+        let result = migrate_db_helper(&connection, 9, 10, migrate_db_9_to_10);
+        if let Err(e) = result {
+            panic!("Database migration from version 9 to 10 failed: {}", e);
+        }
+        schema_version = 10;
+    }
+    // Migration from 10 to 11
+    if schema_version == 10 {
+        let result = migrate_db_helper(&connection, 10, 11, migrate_db_10_to_11);
+        if let Err(e) = result {
+            panic!("Database migration from version 10 to 11 failed: {}", e);
+        }
+        schema_version = 11;
+    }
+    // Migration from 11 to 12
+    if schema_version == 11 {
+        let result = migrate_db_helper(&connection, 11, 12, migrate_db_11_to_12);
+        if let Err(e) = result {
+            panic!("Database migration from version 11 to 12 failed: {}", e);
+        }
+        schema_version = 12;
+    }
+    // Migration from 12 to 13
+    if schema_version == 12 {
+        let result = migrate_db_helper(&connection, 12, 13, migrate_db_12_to_13);
+        if let Err(e) = result {
+            panic!("Database migration from version 12 to 13 failed: {}", e);
+        }
+        schema_version = 13;
+    }
+    // Migration from 13 to 14
+    if schema_version == 13 {
+        let result = migrate_db_helper(&connection, 13, 14, migrate_db_13_to_14);
+        if let Err(e) = result {
+            panic!("Database migration from version 13 to 14 failed: {}", e);
+        }
+        schema_version = 14;
+    }
+    // Migration from 14 to 15
+    if schema_version == 14 {
+        let result = migrate_db_helper(&connection, 14, 15, migrate_db_14_to_15);
+        if let Err(e) = result {
+            panic!("Database migration from version 14 to 15 failed: {}", e);
+        }
+        schema_version = 15;
+    }
+    // Migration from 15 to 16
+    if schema_version == 15 {
+        let result = migrate_db_helper(&connection, 15, 16, migrate_db_15_to_16);
+        if let Err(e) = result {
+            panic!("Database migration from version 15 to 16 failed: {}", e);
+        }
+        schema_version = 16;
+    }
+    // Migration from 16 to 17
+    if schema_version == 16 {
+        let result = migrate_db_helper(&connection, 16, 17, migrate_db_16_to_17);
+        if let Err(e) = result {
+            panic!("Database migration from version 16 to 17 failed: {}", e);
+        }
+        schema_version = 17;
+    }
+    // Migration from 17 to 18
+    if schema_version == 17 {
+        let result = migrate_db_helper(&connection, 17, 18, migrate_db_17_to_18);
+        if let Err(e) = result {
+            panic!("Database migration from version 17 to 18 failed: {}", e);
+        }
+        schema_version = 18;
+    }
+    // Migration from 18 to 19
+    if schema_version == 18 {
+        let result = migrate_db_helper(&connection, 18, 19, migrate_db_18_to_19);
+        if let Err(e) = result {
+            panic!("Database migration from version 18 to 19 failed: {}", e);
+        }
+        schema_version = 19;
+    }
+    // Migration from 19 to 20
+    if schema_version == 19 {
+        let result = migrate_db_helper(&connection, 19, 20, migrate_db_19_to_20);
+        if let Err(e) = result {
+            panic!("Database migration from version 19 to 20 failed: {}", e);
+        }
+        schema_version = 20;
+    }
+    // Migration from 20 to 21
+    if schema_version == 20 {
+        let result = migrate_db_helper(&connection, 20, 21, migrate_db_20_to_21);
+        if let Err(e) = result {
+            panic!("Database migration from version 20 to 21 failed: {}", e);
+        }
+        schema_version = 21;
+    }
+    // Migration from 21 to 22
+    if schema_version == 21 {
+        let result = migrate_db_helper(&connection, 21, 22, migrate_db_21_to_22);
+        if let Err(e) = result {
+            panic!("Database migration from version 21 to 22 failed: {}", e);
+        }
+        schema_version = 22;
+    }
+    // Migration from 22 to 23
+    if schema_version == 22 {
+        let result = migrate_db_helper(&connection, 22, 23, migrate_db_22_to_23);
+        if let Err(e) = result {
+            panic!("Database migration from version 22 to 23 failed: {}", e);
+        }
+        schema_version = 23;
+    }
+    // Migration from 23 to 24
+    if schema_version == 23 {
+        let result = migrate_db_helper(&connection, 23, 24, migrate_db_23_to_24);
+        if let Err(e) = result {
+            panic!("Database migration from version 23 to 24 failed: {}", e);
+        }
+        schema_version = 24;
+    }
+    // Migration from 24 to 25
+    if schema_version == 24 {
+        let result = migrate_db_helper(&connection, 24, 25, migrate_db_24_to_25);
+        if let Err(e) = result {
+            panic!("Database migration from version 24 to 25 failed: {}", e);
+        }
+        schema_version = 25;
+    }
+    // Migration from 25 to 26
+    if schema_version == 25 {
+        let result = migrate_db_helper(&connection, 25, 26, migrate_db_25_to_26);
+        if let Err(e) = result {
+            panic!("Database migration from version 25 to 26 failed: {}", e);
+        }
+        schema_version = 26;
+    }
+    // Migration from 26 to 27
+    if schema_version == 26 {
+        let result = migrate_db_helper(&connection, 26, 27, migrate_db_26_to_27);
+        if let Err(e) = result {
+            panic!("Database migration from version 26 to 27 failed: {}", e);
+        }
+        schema_version = 27;
+    }
+    // Migration from 27 to 28
+    if schema_version == 27 {
+        let result = migrate_db_helper(&connection, 27, 28, migrate_db_27_to_28);
+        if let Err(e) = result {
+            panic!("Database migration from version 27 to 28 failed: {}", e);
+        }
+        schema_version = 28;
+    }
+    // Migration from 28 to 29
+    if schema_version == 28 {
+        let result = migrate_db_helper(&connection, 28, 29, migrate_db_28_to_29);
+        if let Err(e) = result {
+            panic!("Database migration from version 28 to 29 failed: {}", e);
+        }
+        schema_version = 29;
+    }
+    // Migration from 29 to 30
+    if schema_version == 29 {
+        let result = migrate_db_helper(&connection, 29, 30, migrate_db_29_to_30);
+        if let Err(e) = result {
+            panic!("Database migration from version 29 to 30 failed: {}", e);
+        }
+        schema_version = 30;
+    }
+    // Migration from 30 to 31
+    if schema_version == 30 {
+        let result = migrate_db_helper(&connection, 30, 31, migrate_db_30_to_31);
+        if let Err(e) = result {
+            panic!("Database migration from version 30 to 31 failed: {}", e);
+        }
+        schema_version = 31;
+    }
+
+    if schema_version == 31 {
+        let result = migrate_db_helper(&connection, 31, 32, migrate_db_31_to_32);
+        if let Err(e) = result {
+            panic!("Database migration from version 31 to 32 failed: {}", e);
+        }
+        schema_version = 32;
+    }
+
+    if schema_version == 32 {
+        let result = migrate_db_helper(&connection, 32, 33, migrate_db_32_to_33);
+        if let Err(e) = result {
+            panic!("Database migration from version 32 to 33 failed: {}", e);
+        }
+        schema_version = 33;
+    }
+
+    if schema_version == 33 {
+        let result = migrate_db_helper(&connection, 33, 34, migrate_db_33_to_34);
+        if let Err(e) = result {
+            panic!("Database migration from version 33 to 34 failed: {}", e);
+        }
+        schema_version = 34;
+    }
+
+    if schema_version == 34 {
+        let result = migrate_db_helper(&connection, 34, 35, migrate_db_34_to_35);
+        if let Err(e) = result {
+            panic!("Database migration from version 34 to 35 failed: {}", e);
+        }
+        schema_version = 35;
+    }
+
+    if schema_version == 35 {
+        let result = migrate_db_helper(&connection, 35, 36, migrate_db_35_to_36);
+        if let Err(e) = result {
+            panic!("Database migration from version 35 to 36 failed: {}", e);
+        }
+        schema_version = 36;
+    }
+
+    if schema_version == 36 {
+        let result = migrate_db_helper(&connection, 36, 37, migrate_db_36_to_37);
+        if let Err(e) = result {
+            panic!("Database migration from version 36 to 37 failed: {}", e);
+        }
+        schema_version = 37;
+    }
+
+    if schema_version == 37 {
+        let result = migrate_db_helper(&connection, 37, 38, migrate_db_37_to_38);
+        if let Err(e) = result {
+            panic!("Database migration from version 37 to 38 failed: {}", e);
+        }
+        schema_version = 38;
+    }
+
+    if schema_version == 38 {
+        let result = migrate_db_helper(&connection, 38, 39, migrate_db_38_to_39);
+        if let Err(e) = result {
+            panic!("Database migration from version 38 to 39 failed: {}", e);
+        }
+        schema_version = 39;
+    }
+
+    if schema_version == 39 {
+        let result = migrate_db_helper(&connection, 39, 40, migrate_db_39_to_40);
+        if let Err(e) = result {
+            panic!("Database migration from version 39 to 40 failed: {}", e);
+        }
+        schema_version = 40;
+    }
+
+    if schema_version == 40 {
+        let result = migrate_db_helper(&connection, 40, 41, migrate_db_40_to_41);
+        if let Err(e) = result {
+            panic!("Database migration from version 40 to 41 failed: {}", e);
+        }
+        schema_version = 41;
+    }
+
+    if schema_version == 41 {
+        let result = migrate_db_helper(&connection, 41, 42, migrate_db_41_to_42);
+        if let Err(e) = result {
+            panic!("Database migration from version 41 to 42 failed: {}", e);
+        }
+        schema_version = 42;
+    }
+
+    if schema_version == 42 {
+        let result = migrate_db_helper(&connection, 42, 43, migrate_db_42_to_43);
+        if let Err(e) = result {
+            panic!("Database migration from version 42 to 43 failed: {}", e);
+        }
+        schema_version = 43;
+    }
+
+    if schema_version == 43 {
+        let result = migrate_db_helper(&connection, 43, 44, migrate_db_43_to_44);
+        if let Err(e) = result {
+            panic!("Database migration from version 43 to 44 failed: {}", e);
+        }
+        schema_version = 44;
+    }
+
+    if schema_version == 44 {
+        let result = migrate_db_helper(&connection, 44, 45, migrate_db_44_to_45);
+        if let Err(e) = result {
+            panic!("Database migration from version 44 to 45 failed: {}", e);
+        }
+        schema_version = 45;
+    }
+
+    if schema_version == 45 {
+        let result = migrate_db_helper(&connection, 45, 46, migrate_db_45_to_46);
+        if let Err(e) = result {
+            panic!("Database migration from version 45 to 46 failed: {}", e);
+        }
+        schema_version = 46;
+    }
+
+    if schema_version == 46 {
+        let result = migrate_db_helper(&connection, 46, 47, migrate_db_46_to_47);
+        if let Err(e) = result {
+            panic!("Database migration from version 46 to 47 failed: {}", e);
+        }
+        schema_version = 47;
+    }
+
+    if schema_version == 47 {
+        let result = migrate_db_helper(&connection, 47, 48, migrate_db_47_to_48);
+        if let Err(e) = result {
+            panic!("Database migration from version 47 to 48 failed: {}", e);
+        }
+        schema_version = 48;
+    }
+
+    if schema_version == 48 {
+        let result = migrate_db_helper(&connection, 48, 49, migrate_db_48_to_49);
+        if let Err(e) = result {
+            panic!("Database migration from version 48 to 49 failed: {}", e);
+        }
+        schema_version = 49;
+    }
 
     schema_version
 }
 
+// Writes a consistent copy of the database next to it, such as gruxi.db.v12.bak, including what is still in the WAL
+fn backup_database(connection: &Connection, schema_version: i32) -> Result<String, String> {
+    let backup_path = format!("{}.v{}.bak", get_database_path(), schema_version);
+    if std::path::Path::new(&backup_path).exists() {
+        std::fs::remove_file(&backup_path).map_err(|e| format!("Failed to remove old backup {}: {}", backup_path, e))?;
+    }
+    connection
+        .execute(format!("VACUUM INTO '{}';", backup_path.replace('\'', "''")))
+        .map_err(|e| format!("Failed to write backup {}: {}", backup_path, e))?;
+    Ok(backup_path)
+}
+
 fn migrate_db_helper(connection: &Connection, from_version: i32, to_version: i32, migration_fn: fn(&Connection) -> Result<(), sqlite::Error>) -> Result<(), String> {
     if let Err(e) = connection.execute("BEGIN IMMEDIATE TRANSACTION;") {
         return Err(format!("Failed to begin transaction for database migration from version {} to {}: {}", from_version, to_version, e));
@@ -81,3 +460,335 @@ fn migrate_db_3_to_4(connection: &Connection) -> Result<(), sqlite::Error> {
     connection.execute("ALTER TABLE sites ADD COLUMN tls_automatic_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
     Ok(())
 }
+
+fn migrate_db_4_to_5(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "debug_capture_enabled" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN debug_capture_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_5_to_6(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "api_mode_enabled" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN api_mode_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_6_to_7(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "allowed_methods" to "request_handler" table
+    connection.execute("ALTER TABLE request_handler ADD COLUMN allowed_methods TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_7_to_8(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add keep-alive and pipelining limits to "bindings" table
+    connection.execute("ALTER TABLE bindings ADD COLUMN keep_alive_timeout_seconds INTEGER NOT NULL DEFAULT 60;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN max_requests_per_connection INTEGER NOT NULL DEFAULT 1000;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN pipelining_enabled BOOLEAN NOT NULL DEFAULT 1;")?;
+    Ok(())
+}
+
+fn migrate_db_8_to_9(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "max_body_size" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN max_body_size INTEGER NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_9_to_10(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add path confinement to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN path_confinement_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN path_confinement_allowed_paths TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_10_to_11(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "cache_policies" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN cache_policies TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_11_to_12(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add language negotiation to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN language_negotiation_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN default_language TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_12_to_13(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "index_files" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN index_files TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_13_to_14(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "try_files" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN try_files TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_14_to_15(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "spa_fallback_enabled" and "spa_fallback_excluded_paths" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN spa_fallback_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN spa_fallback_excluded_paths TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_15_to_16(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "php_error_log_file" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN php_error_log_file TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_16_to_17(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "last_activity_at" and "csrf_token" to "sessions" table, for idle timeouts and cookie sessions
+    connection.execute("ALTER TABLE sessions ADD COLUMN last_activity_at TEXT NOT NULL DEFAULT '';")?;
+    connection.execute("ALTER TABLE sessions ADD COLUMN csrf_token TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_17_to_18(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "access_log_tls_fingerprint_enabled" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN access_log_tls_fingerprint_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_18_to_19(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add HTTP/2 limits to "bindings" table
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2_max_concurrent_streams INTEGER NOT NULL DEFAULT 100;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2_initial_stream_window_size INTEGER NOT NULL DEFAULT 1048576;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2_initial_connection_window_size INTEGER NOT NULL DEFAULT 1048576;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2_max_header_list_size INTEGER NOT NULL DEFAULT 16384;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2_max_connection_memory INTEGER NOT NULL DEFAULT 8388608;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2_max_pending_reset_streams INTEGER NOT NULL DEFAULT 20;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2_max_local_error_reset_streams INTEGER NOT NULL DEFAULT 1024;")?;
+    Ok(())
+}
+
+fn migrate_db_19_to_20(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add HPACK dynamic table sizes to "bindings" table
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2_header_table_size INTEGER NOT NULL DEFAULT 4096;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2_max_encoder_table_size INTEGER NOT NULL DEFAULT 4096;")?;
+    Ok(())
+}
+
+fn migrate_db_20_to_21(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "middlewares" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN middlewares TEXT NOT NULL DEFAULT 'access_log,extra_headers,cache_policy,compression';")?;
+    Ok(())
+}
+
+fn migrate_db_21_to_22(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "extra_environment" to "sites" and "php_cgi_handlers" tables
+    connection.execute("ALTER TABLE sites ADD COLUMN extra_environment TEXT NOT NULL DEFAULT '';")?;
+    connection.execute("ALTER TABLE php_cgi_handlers ADD COLUMN extra_environment TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_22_to_23(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add build before serve to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN build_command TEXT NOT NULL DEFAULT '';")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN build_source_directory TEXT NOT NULL DEFAULT '';")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN build_output_directory TEXT NOT NULL DEFAULT '';")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN build_timeout_seconds INTEGER NOT NULL DEFAULT 600;")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN deploy_webhook_token TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_23_to_24(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add git deployment to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN deploy_git_repository TEXT NOT NULL DEFAULT '';")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN deploy_git_branch TEXT NOT NULL DEFAULT 'main';")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN deploy_git_webhook_secret TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_24_to_25(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "access_log_timings_enabled" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN access_log_timings_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_25_to_26(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "site_statistics" table
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS site_statistics (
+            site_id TEXT PRIMARY KEY,
+            requests_served INTEGER NOT NULL DEFAULT 0,
+            bytes_sent INTEGER NOT NULL DEFAULT 0,
+            updated_at TEXT NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+fn migrate_db_26_to_27(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add roles to "users" table, the existing users are admins
+    connection.execute("ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'admin';")?;
+    // Add "site_owners" table
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS site_owners (
+            user_id INTEGER NOT NULL,
+            site_id TEXT NOT NULL,
+            PRIMARY KEY (user_id, site_id),
+            FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+        );",
+    )?;
+    Ok(())
+}
+
+fn migrate_db_27_to_28(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add protocol selection to "bindings" table
+    connection.execute("ALTER TABLE bindings ADD COLUMN protocols TEXT NOT NULL DEFAULT 'h2+http1';")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN h2c_enabled BOOLEAN NOT NULL DEFAULT 1;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN absolute_form_enabled BOOLEAN NOT NULL DEFAULT 1;")?;
+    Ok(())
+}
+
+fn migrate_db_28_to_29(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "traffic_split" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN traffic_split TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_29_to_30(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "session_affinity" and "session_affinity_cookie_name" to "proxy_processors" table
+    connection.execute("ALTER TABLE proxy_processors ADD COLUMN session_affinity TEXT NOT NULL DEFAULT 'none';")?;
+    connection.execute("ALTER TABLE proxy_processors ADD COLUMN session_affinity_cookie_name TEXT NOT NULL DEFAULT 'gruxi_backend';")?;
+    Ok(())
+}
+
+fn migrate_db_30_to_31(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "upstreams" table
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS upstreams (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL DEFAULT '',
+            servers TEXT NOT NULL DEFAULT '[]',
+            health_check_path TEXT NOT NULL DEFAULT '',
+            health_check_interval_seconds INTEGER NOT NULL DEFAULT 60,
+            health_check_timeout_seconds INTEGER NOT NULL DEFAULT 5
+        );",
+    )?;
+    // Add "upstream_id" to "proxy_processors" table
+    connection.execute("ALTER TABLE proxy_processors ADD COLUMN upstream_id TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_31_to_32(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "header_routes" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN header_routes TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_32_to_33(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "cookie_routes" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN cookie_routes TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_33_to_34(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "body_substitutions" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN body_substitutions TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_34_to_35(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "minification_enabled" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN minification_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_35_to_36(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "robots_txt_enabled" and "sitemap_enabled" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN robots_txt_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN sitemap_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_36_to_37(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "cache_enabled" and "cache_max_size_mb" to "proxy_processors" table
+    connection.execute("ALTER TABLE proxy_processors ADD COLUMN cache_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    connection.execute("ALTER TABLE proxy_processors ADD COLUMN cache_max_size_mb INTEGER NOT NULL DEFAULT 1024;")?;
+    Ok(())
+}
+
+fn migrate_db_37_to_38(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "cache_enabled" and "cache_max_size_mb" to "php_processors" table
+    connection.execute("ALTER TABLE php_processors ADD COLUMN cache_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    connection.execute("ALTER TABLE php_processors ADD COLUMN cache_max_size_mb INTEGER NOT NULL DEFAULT 1024;")?;
+    Ok(())
+}
+
+fn migrate_db_38_to_39(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "trailing_slash_redirect" and "lowercase_redirect_enabled" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN trailing_slash_redirect TEXT NOT NULL DEFAULT 'off';")?;
+    connection.execute("ALTER TABLE sites ADD COLUMN lowercase_redirect_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_39_to_40(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "status_page_enabled" and "status_page_allowed_ips" to "bindings" table
+    connection.execute("ALTER TABLE bindings ADD COLUMN status_page_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    connection.execute("ALTER TABLE bindings ADD COLUMN status_page_allowed_ips TEXT NOT NULL DEFAULT '127.0.0.1,::1';")?;
+    Ok(())
+}
+
+fn migrate_db_40_to_41(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "tls_settings" to "proxy_processors" table
+    connection.execute("ALTER TABLE proxy_processors ADD COLUMN tls_settings TEXT NOT NULL DEFAULT '{}';")?;
+    // Add "fastcgi_tls_enabled", "fastcgi_verify_tls_certificates" and "fastcgi_tls_settings" to "php_processors" table
+    connection.execute("ALTER TABLE php_processors ADD COLUMN fastcgi_tls_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    connection.execute("ALTER TABLE php_processors ADD COLUMN fastcgi_verify_tls_certificates BOOLEAN NOT NULL DEFAULT 1;")?;
+    connection.execute("ALTER TABLE php_processors ADD COLUMN fastcgi_tls_settings TEXT NOT NULL DEFAULT '{}';")?;
+    Ok(())
+}
+
+fn migrate_db_41_to_42(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "max_retries" and "retry_budget_seconds" to "proxy_processors" table
+    connection.execute("ALTER TABLE proxy_processors ADD COLUMN max_retries INTEGER NOT NULL DEFAULT 2;")?;
+    connection.execute("ALTER TABLE proxy_processors ADD COLUMN retry_budget_seconds INTEGER NOT NULL DEFAULT 10;")?;
+    Ok(())
+}
+
+fn migrate_db_42_to_43(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "early_hints" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN early_hints TEXT NOT NULL DEFAULT '';")?;
+    Ok(())
+}
+
+fn migrate_db_43_to_44(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "preload_links_enabled" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN preload_links_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_44_to_45(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "upload_progress_enabled" to "sites" table
+    connection.execute("ALTER TABLE sites ADD COLUMN upload_progress_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+fn migrate_db_45_to_46(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Add "early_hints_enabled" to "sites" table, on for the sites that have early hints configured
+    connection.execute("ALTER TABLE sites ADD COLUMN early_hints_enabled BOOLEAN NOT NULL DEFAULT 0;")?;
+    connection.execute("UPDATE sites SET early_hints_enabled = 1 WHERE early_hints != '' AND early_hints != '[]';")?;
+    Ok(())
+}
+
+fn migrate_db_46_to_47(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Lower the old default "upstream_pool_idle_timeout_seconds" below the outbound read timeout, which closed the idle connections first
+    connection.execute("UPDATE server_settings SET setting_value = '50' WHERE setting_key = 'upstream_pool_idle_timeout_seconds' AND setting_value = '90';")?;
+    Ok(())
+}
+
+fn migrate_db_47_to_48(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Remove the HPACK table sizes from "bindings" table, the HTTP/2 implementation keeps its tables at the default of the protocol
+    connection.execute("ALTER TABLE bindings DROP COLUMN h2_header_table_size;")?;
+    connection.execute("ALTER TABLE bindings DROP COLUMN h2_max_encoder_table_size;")?;
+    Ok(())
+}
+
+fn migrate_db_48_to_49(connection: &Connection) -> Result<(), sqlite::Error> {
+    // Turn off the HTTP/2 flood bans where "http2_flood_ban_seconds" is still the old default, bans are opt-in
+    connection.execute("UPDATE server_settings SET setting_value = '0' WHERE setting_key = 'http2_flood_ban_seconds' AND setting_value = '300';")?;
+    Ok(())
+}
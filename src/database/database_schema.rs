@@ -1,8 +1,8 @@
 use sqlite::State;
 
-use crate::core::database_connection::get_database_connection;
+use crate::core::database_connection::{get_database_connection, is_database_read_only};
 
-pub const CURRENT_DB_SCHEMA_VERSION: i32 = 4;
+pub const CURRENT_DB_SCHEMA_VERSION: i32 = 49;
 
 pub struct DatabaseSchema {
     pub version: i32,
@@ -21,6 +21,17 @@ impl DatabaseSchema {
 }
 
 pub fn initialize_database() -> Result<(), String> {
+    // A read-only database cannot be created or migrated, so it must be one of the current version
+    if is_database_read_only() {
+        let schema_version = get_schema_version();
+        if schema_version != CURRENT_DB_SCHEMA_VERSION {
+            return Err(format!(
+                "The read-only database has schema version {}, but version {} is needed. Start this version of Gruxi once with write access to the database to migrate it",
+                schema_version, CURRENT_DB_SCHEMA_VERSION
+            ));
+        }
+        return Ok(());
+    }
     let connection = get_database_connection()?;
 
     // Get database schema and apply it
@@ -101,7 +112,22 @@ fn get_init_sql() -> Vec<String> {
         ip TEXT NOT NULL,
         port INTEGER NOT NULL,
         is_admin BOOLEAN NOT NULL DEFAULT 0,
-        is_tls BOOLEAN NOT NULL DEFAULT 0
+        is_tls BOOLEAN NOT NULL DEFAULT 0,
+        keep_alive_timeout_seconds INTEGER NOT NULL DEFAULT 60,
+        max_requests_per_connection INTEGER NOT NULL DEFAULT 1000,
+        pipelining_enabled BOOLEAN NOT NULL DEFAULT 1,
+        h2_max_concurrent_streams INTEGER NOT NULL DEFAULT 100,
+        h2_initial_stream_window_size INTEGER NOT NULL DEFAULT 1048576,
+        h2_initial_connection_window_size INTEGER NOT NULL DEFAULT 1048576,
+        h2_max_header_list_size INTEGER NOT NULL DEFAULT 16384,
+        h2_max_connection_memory INTEGER NOT NULL DEFAULT 8388608,
+        h2_max_pending_reset_streams INTEGER NOT NULL DEFAULT 20,
+        h2_max_local_error_reset_streams INTEGER NOT NULL DEFAULT 1024,
+        protocols TEXT NOT NULL DEFAULT 'h2+http1',
+        h2c_enabled BOOLEAN NOT NULL DEFAULT 1,
+        absolute_form_enabled BOOLEAN NOT NULL DEFAULT 1,
+        status_page_enabled BOOLEAN NOT NULL DEFAULT 0,
+        status_page_allowed_ips TEXT NOT NULL DEFAULT '127.0.0.1,::1'
     );"
         .to_string(),
         // Sites table
@@ -119,7 +145,45 @@ fn get_init_sql() -> Vec<String> {
         access_log_enabled BOOLEAN NOT NULL DEFAULT 0,
         access_log_file TEXT NOT NULL DEFAULT '',
         extra_headers TEXT NOT NULL DEFAULT '',
-        tls_automatic_enabled BOOLEAN NOT NULL DEFAULT 0
+        tls_automatic_enabled BOOLEAN NOT NULL DEFAULT 0,
+        debug_capture_enabled BOOLEAN NOT NULL DEFAULT 0,
+        api_mode_enabled BOOLEAN NOT NULL DEFAULT 0,
+        max_body_size INTEGER NOT NULL DEFAULT 0,
+        path_confinement_enabled BOOLEAN NOT NULL DEFAULT 0,
+        path_confinement_allowed_paths TEXT NOT NULL DEFAULT '',
+        cache_policies TEXT NOT NULL DEFAULT '',
+        language_negotiation_enabled BOOLEAN NOT NULL DEFAULT 0,
+        default_language TEXT NOT NULL DEFAULT '',
+        index_files TEXT NOT NULL DEFAULT '',
+        try_files TEXT NOT NULL DEFAULT '',
+        spa_fallback_enabled BOOLEAN NOT NULL DEFAULT 0,
+        spa_fallback_excluded_paths TEXT NOT NULL DEFAULT '',
+        php_error_log_file TEXT NOT NULL DEFAULT '',
+        access_log_tls_fingerprint_enabled BOOLEAN NOT NULL DEFAULT 0,
+        middlewares TEXT NOT NULL DEFAULT 'access_log,extra_headers,cache_policy,compression',
+        extra_environment TEXT NOT NULL DEFAULT '',
+        build_command TEXT NOT NULL DEFAULT '',
+        build_source_directory TEXT NOT NULL DEFAULT '',
+        build_output_directory TEXT NOT NULL DEFAULT '',
+        build_timeout_seconds INTEGER NOT NULL DEFAULT 600,
+        deploy_webhook_token TEXT NOT NULL DEFAULT '',
+        deploy_git_repository TEXT NOT NULL DEFAULT '',
+        deploy_git_branch TEXT NOT NULL DEFAULT 'main',
+        deploy_git_webhook_secret TEXT NOT NULL DEFAULT '',
+        access_log_timings_enabled BOOLEAN NOT NULL DEFAULT 0,
+        traffic_split TEXT NOT NULL DEFAULT '',
+        header_routes TEXT NOT NULL DEFAULT '',
+        cookie_routes TEXT NOT NULL DEFAULT '',
+        body_substitutions TEXT NOT NULL DEFAULT '',
+        minification_enabled BOOLEAN NOT NULL DEFAULT 0,
+        robots_txt_enabled BOOLEAN NOT NULL DEFAULT 0,
+        sitemap_enabled BOOLEAN NOT NULL DEFAULT 0,
+        trailing_slash_redirect TEXT NOT NULL DEFAULT 'off',
+        lowercase_redirect_enabled BOOLEAN NOT NULL DEFAULT 0,
+        early_hints TEXT NOT NULL DEFAULT '',
+        preload_links_enabled BOOLEAN NOT NULL DEFAULT 0,
+        upload_progress_enabled BOOLEAN NOT NULL DEFAULT 0,
+        early_hints_enabled BOOLEAN NOT NULL DEFAULT 0
     );"
         .to_string(),
         // Junction table for many-to-many relationship between bindings and sites
@@ -139,7 +203,8 @@ fn get_init_sql() -> Vec<String> {
         name TEXT NOT NULL DEFAULT '',
         processor_type TEXT NOT NULL DEFAULT '',
         processor_id TEXT NOT NULL DEFAULT '',
-        url_match TEXT NOT NULL DEFAULT ''
+        url_match TEXT NOT NULL DEFAULT '',
+        allowed_methods TEXT NOT NULL DEFAULT ''
     );"
         .to_string(),
         // Processor table
@@ -158,7 +223,12 @@ fn get_init_sql() -> Vec<String> {
         request_timeout INTEGER NOT NULL DEFAULT 30,
         local_web_root TEXT NOT NULL DEFAULT '',
         fastcgi_web_root TEXT NOT NULL DEFAULT '',
-        server_software_spoof TEXT NOT NULL DEFAULT ''
+        server_software_spoof TEXT NOT NULL DEFAULT '',
+        cache_enabled BOOLEAN NOT NULL DEFAULT 0,
+        cache_max_size_mb INTEGER NOT NULL DEFAULT 1024,
+        fastcgi_tls_enabled BOOLEAN NOT NULL DEFAULT 0,
+        fastcgi_verify_tls_certificates BOOLEAN NOT NULL DEFAULT 1,
+        fastcgi_tls_settings TEXT NOT NULL DEFAULT '{}'
     );"
         .to_string(),
         // Proxy processors table
@@ -174,7 +244,25 @@ fn get_init_sql() -> Vec<String> {
         url_rewrites TEXT NOT NULL DEFAULT '',
         preserve_host_header BOOLEAN NOT NULL DEFAULT 0,
         forced_host_header TEXT NOT NULL DEFAULT '',
-        verify_tls_certificates BOOLEAN NOT NULL DEFAULT 1
+        verify_tls_certificates BOOLEAN NOT NULL DEFAULT 1,
+        session_affinity TEXT NOT NULL DEFAULT 'none',
+        session_affinity_cookie_name TEXT NOT NULL DEFAULT 'gruxi_backend',
+        upstream_id TEXT NOT NULL DEFAULT '',
+        cache_enabled BOOLEAN NOT NULL DEFAULT 0,
+        cache_max_size_mb INTEGER NOT NULL DEFAULT 1024,
+        tls_settings TEXT NOT NULL DEFAULT '{}',
+        max_retries INTEGER NOT NULL DEFAULT 2,
+        retry_budget_seconds INTEGER NOT NULL DEFAULT 10
+    );"
+        .to_string(),
+        // Upstream pools table
+        "CREATE TABLE IF NOT EXISTS upstreams (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL DEFAULT '',
+        servers TEXT NOT NULL DEFAULT '[]',
+        health_check_path TEXT NOT NULL DEFAULT '',
+        health_check_interval_seconds INTEGER NOT NULL DEFAULT 60,
+        health_check_timeout_seconds INTEGER NOT NULL DEFAULT 5
     );"
         .to_string(),
         // PHP-CGI handlers table
@@ -183,7 +271,8 @@ fn get_init_sql() -> Vec<String> {
         name TEXT NOT NULL DEFAULT '',
         request_timeout INTEGER NOT NULL DEFAULT 30,
         concurrent_threads INTEGER NOT NULL DEFAULT 0,
-        executable TEXT NOT NULL DEFAULT ''
+        executable TEXT NOT NULL DEFAULT '',
+        extra_environment TEXT NOT NULL DEFAULT ''
     );"
         .to_string(),
         // Users table for admin portal
@@ -193,7 +282,16 @@ fn get_init_sql() -> Vec<String> {
                 password_hash TEXT NOT NULL,
                 created_at TEXT NOT NULL,
                 last_login TEXT,
-                is_active BOOLEAN NOT NULL DEFAULT 1
+                is_active BOOLEAN NOT NULL DEFAULT 1,
+                role TEXT NOT NULL DEFAULT 'admin'
+            )"
+        .to_string(),
+        // Sites owned by site owner users, who only get admin access to these
+        "CREATE TABLE IF NOT EXISTS site_owners (
+                user_id INTEGER NOT NULL,
+                site_id TEXT NOT NULL,
+                PRIMARY KEY (user_id, site_id),
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
             )"
         .to_string(),
         // User session table
@@ -204,8 +302,26 @@ fn get_init_sql() -> Vec<String> {
                 token TEXT NOT NULL UNIQUE,
                 expires_at TEXT NOT NULL,
                 created_at TEXT NOT NULL,
+                last_activity_at TEXT NOT NULL DEFAULT '',
+                csrf_token TEXT NOT NULL DEFAULT '',
                 FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
             )"
         .to_string(),
+        // One-time password reset tokens, created from the command line
+        "CREATE TABLE IF NOT EXISTS password_reset_tokens (
+                token TEXT PRIMARY KEY,
+                username TEXT NOT NULL,
+                expires_at TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )"
+        .to_string(),
+        // Cumulative requests served and bytes sent per site, written periodically while running
+        "CREATE TABLE IF NOT EXISTS site_statistics (
+                site_id TEXT PRIMARY KEY,
+                requests_served INTEGER NOT NULL DEFAULT 0,
+                bytes_sent INTEGER NOT NULL DEFAULT 0,
+                updated_at TEXT NOT NULL
+            )"
+        .to_string(),
     ]
 }
@@ -1,2 +1,3 @@
+pub mod database_maintenance;
+pub mod database_migration;
 pub mod database_schema;
-pub mod database_migration;
\ No newline at end of file
@@ -0,0 +1,128 @@
+use crate::configuration::site::Site;
+use crate::deployment::site_builder::constant_time_equals;
+use hyper::HeaderMap;
+use ring::hmac;
+
+// GitHub signs the body with the secret, as "sha256=<hex>" in this header
+const GITHUB_SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+const GITHUB_EVENT_HEADER: &str = "X-GitHub-Event";
+// GitLab sends the secret itself in this header
+const GITLAB_TOKEN_HEADER: &str = "X-Gitlab-Token";
+const GITLAB_EVENT_HEADER: &str = "X-Gitlab-Event";
+
+// What a push webhook of a git host asks for
+#[derive(Debug, PartialEq)]
+pub enum GitWebhookEvent {
+    // A push to the branch, such as "main"
+    Push(String),
+    // Sent by GitHub when the webhook is added, to check it is reachable
+    Ping,
+    // Any other event, such as issues or tags, which do not deploy anything
+    Other(String),
+}
+
+// Checks the webhook is sent by the git host, with the signature of GitHub or the token of GitLab made from the webhook secret of the site
+pub fn verify_git_webhook(site: &Site, headers: &HeaderMap, body: &[u8]) -> Result<(), String> {
+    if site.deploy_git_repository.is_empty() || site.deploy_git_webhook_secret.is_empty() {
+        return Err("The site has no git webhook".to_string());
+    }
+
+    if let Some(signature) = headers.get(GITHUB_SIGNATURE_HEADER).and_then(|v| v.to_str().ok()) {
+        let signature = signature.strip_prefix("sha256=").and_then(decode_hex).ok_or("The signature is malformed")?;
+        let key = hmac::Key::new(hmac::HMAC_SHA256, site.deploy_git_webhook_secret.as_bytes());
+        return hmac::verify(&key, body, &signature).map_err(|_| "The signature does not match".to_string());
+    }
+    if let Some(token) = headers.get(GITLAB_TOKEN_HEADER).and_then(|v| v.to_str().ok()) {
+        if constant_time_equals(token.as_bytes(), site.deploy_git_webhook_secret.as_bytes()) {
+            return Ok(());
+        }
+        return Err("The token does not match".to_string());
+    }
+    Err(format!("Neither {} nor {} is sent", GITHUB_SIGNATURE_HEADER, GITLAB_TOKEN_HEADER))
+}
+
+// Reads the event from a verified webhook. Both GitHub and GitLab send the pushed ref as "ref" in the body
+pub fn parse_git_webhook_event(headers: &HeaderMap, body: &[u8]) -> Result<GitWebhookEvent, String> {
+    let event = headers
+        .get(GITHUB_EVENT_HEADER)
+        .or_else(|| headers.get(GITLAB_EVENT_HEADER))
+        .and_then(|v| v.to_str().ok())
+        .ok_or(format!("Neither {} nor {} is sent", GITHUB_EVENT_HEADER, GITLAB_EVENT_HEADER))?;
+
+    match event {
+        "ping" => Ok(GitWebhookEvent::Ping),
+        "push" | "Push Hook" => {
+            let payload: serde_json::Value = serde_json::from_slice(body).map_err(|e| format!("The push payload is not valid JSON: {}", e))?;
+            let pushed_ref = payload["ref"].as_str().ok_or("The push payload has no ref")?;
+            match pushed_ref.strip_prefix("refs/heads/") {
+                Some(branch) => Ok(GitWebhookEvent::Push(branch.to_string())),
+                None => Ok(GitWebhookEvent::Other(format!("push of {}", pushed_ref))),
+            }
+        }
+        other => Ok(GitWebhookEvent::Other(other.to_string())),
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_site() -> Site {
+        let mut site = Site::new();
+        site.deploy_git_repository = "https://github.com/example/site.git".to_string();
+        site.deploy_git_webhook_secret = "0123456789abcdef".to_string();
+        site
+    }
+
+    fn create_headers(headers: &[(&'static str, &str)]) -> HeaderMap {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.insert(*name, value.parse().unwrap());
+        }
+        header_map
+    }
+
+    #[test]
+    fn test_github_signature() {
+        let site = create_site();
+        let body = br#"{"ref":"refs/heads/main"}"#;
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"0123456789abcdef");
+        let signature: String = hmac::sign(&key, body).as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+
+        let headers = create_headers(&[("X-Hub-Signature-256", &format!("sha256={}", signature)), ("X-GitHub-Event", "push")]);
+        assert!(verify_git_webhook(&site, &headers, body).is_ok());
+        assert_eq!(parse_git_webhook_event(&headers, body).unwrap(), GitWebhookEvent::Push("main".to_string()));
+        assert!(verify_git_webhook(&site, &headers, br#"{"ref":"refs/heads/evil"}"#).is_err());
+
+        let headers = create_headers(&[("X-Hub-Signature-256", "sha256=zz")]);
+        assert!(verify_git_webhook(&site, &headers, body).is_err());
+        assert!(verify_git_webhook(&site, &HeaderMap::new(), body).is_err());
+    }
+
+    #[test]
+    fn test_gitlab_token() {
+        let site = create_site();
+        let body = br#"{"ref":"refs/tags/v1.0"}"#;
+        let headers = create_headers(&[("X-Gitlab-Token", "0123456789abcdef"), ("X-Gitlab-Event", "Push Hook")]);
+        assert!(verify_git_webhook(&site, &headers, body).is_ok());
+        assert_eq!(parse_git_webhook_event(&headers, body).unwrap(), GitWebhookEvent::Other("push of refs/tags/v1.0".to_string()));
+
+        let headers = create_headers(&[("X-Gitlab-Token", "0123456789abcdeF")]);
+        assert!(verify_git_webhook(&site, &headers, body).is_err());
+
+        // Without a secret, the webhook is disabled
+        let site = Site {
+            deploy_git_webhook_secret: String::new(),
+            ..create_site()
+        };
+        let headers = create_headers(&[("X-Gitlab-Token", "")]);
+        assert!(verify_git_webhook(&site, &headers, body).is_err());
+    }
+}
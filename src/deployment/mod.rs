@@ -0,0 +1,2 @@
+pub mod git_webhook;
+pub mod site_builder;
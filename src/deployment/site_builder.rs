@@ -0,0 +1,519 @@
+use crate::configuration::site::Site;
+use crate::logging::syslog::{debug, error, info, trace, warn};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::process::Command;
+use utoipa::ToSchema;
+
+// Releases are kept in <releases directory>/<site id>/<release id>, with the release being served named in the "current" file next to them
+const RELEASES_DIRECTORY: &str = "./releases";
+const CURRENT_RELEASE_FILE: &str = "current";
+// Older releases are removed after a successful build, except for this many, so a release can still be switched back to by hand
+const RELEASES_TO_KEEP: usize = 3;
+// Only the end of the build output is kept, as that is where the errors are
+const MAX_BUILD_OUTPUT_BYTES: usize = 64 * 1024;
+// Environment variables kept from the environment of Gruxi, as the build tools need them to run. Everything else is left out
+const INHERITED_ENVIRONMENT_VARIABLES: [&str; 9] = ["PATH", "HOME", "USER", "LANG", "TMPDIR", "TEMP", "TMP", "SYSTEMROOT", "USERPROFILE"];
+
+static SITE_BUILDER_SINGLETON: OnceLock<SiteBuilder> = OnceLock::new();
+
+pub fn get_site_builder() -> &'static SiteBuilder {
+    SITE_BUILDER_SINGLETON.get_or_init(|| SiteBuilder::new(PathBuf::from(RELEASES_DIRECTORY)))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+// A build of a site, as shown in the admin API
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SiteBuild {
+    pub release_id: String,
+    pub state: BuildState,
+    // What started the build, such as "admin" or "webhook"
+    pub triggered_by: String,
+    // The commit deployed, for releases checked out from a repository
+    pub commit: Option<String>,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    // The end of the combined stdout and stderr of the build command
+    pub output: String,
+    pub error: Option<String>,
+}
+
+// Builds the releases of sites deployed by Gruxi, and serves the output of the current one. A release is a checkout of the branch
+// of the git repository of the site, or a copy of its source directory, built with the build command of the site if it has one,
+// such as "hugo" or "npm run build" for static site generators.
+//
+// Each build runs in a release directory of its own, without .git and with only the environment the build tools need plus the
+// environment variables of the site, so a build cannot change the source or the release being served.
+// When the build succeeds, the site is switched to the output of the new release at once, and a failed build leaves the site as it was
+pub struct SiteBuilder {
+    releases_directory: PathBuf,
+    // The last build of each site, by site id
+    builds: DashMap<String, SiteBuild>,
+    // The web root of the release being served for each site, by site id. None if the site has no release yet
+    active_web_roots: DashMap<String, Option<String>>,
+}
+
+impl SiteBuilder {
+    pub fn new(releases_directory: PathBuf) -> Self {
+        SiteBuilder {
+            releases_directory,
+            builds: DashMap::new(),
+            active_web_roots: DashMap::new(),
+        }
+    }
+
+    pub fn get_last_build(&self, site_id: &str) -> Option<SiteBuild> {
+        self.builds.get(site_id).map(|build| build.clone())
+    }
+
+    pub fn get_active_release_id(&self, site_id: &str) -> Option<String> {
+        std::fs::read_to_string(self.get_site_releases_directory(site_id).join(CURRENT_RELEASE_FILE))
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    }
+
+    // The web root to serve the site from, the output of its current release, if the site is built by Gruxi and has been built
+    pub fn get_active_web_root(&self, site: &Site) -> Option<String> {
+        if !site.is_deployed_in_releases() {
+            return None;
+        }
+        if let Some(web_root) = self.active_web_roots.get(&site.id) {
+            return web_root.clone();
+        }
+
+        // After a restart, the site is served from the release it was served from before
+        let web_root = self
+            .get_active_release_id(&site.id)
+            .map(|release_id| self.get_output_directory(site, &release_id))
+            .filter(|output| Path::new(output).is_dir());
+        self.active_web_roots.insert(site.id.clone(), web_root.clone());
+        web_root
+    }
+
+    // Starts a build of the site in the background, returning the id of the release it builds. Only one build per site runs at a time
+    pub fn start_build(&'static self, site: &Site, triggered_by: &str) -> Result<String, String> {
+        if !site.is_deployed_in_releases() {
+            return Err(format!("Site {} has no build command or git repository", site.id));
+        }
+
+        let release_id = format!("{}-{}", chrono::Utc::now().format("%Y%m%d%H%M%S"), &uuid::Uuid::new_v4().simple().to_string()[..8]);
+        let build = SiteBuild {
+            release_id: release_id.clone(),
+            state: BuildState::Running,
+            triggered_by: triggered_by.to_string(),
+            commit: None,
+            started_at: chrono::Utc::now().to_rfc3339(),
+            finished_at: None,
+            output: String::new(),
+            error: None,
+        };
+        match self.builds.entry(site.id.clone()) {
+            dashmap::Entry::Occupied(mut entry) => {
+                if entry.get().state == BuildState::Running {
+                    return Err(format!("A build of site {} is already running", site.id));
+                }
+                entry.insert(build);
+            }
+            dashmap::Entry::Vacant(entry) => {
+                entry.insert(build);
+            }
+        }
+
+        info(format!("Starting build {} of site {}, triggered by {}", release_id, site.id, triggered_by));
+        let site = site.clone();
+        let build_release_id = release_id.clone();
+        tokio::spawn(async move {
+            let result = self.build_release(&site, &build_release_id).await;
+            self.finish_build(&site, &build_release_id, result);
+        });
+        Ok(release_id)
+    }
+
+    // Builds the release and switches the site to it. Returns the build output, along with the error if it failed
+    async fn build_release(&self, site: &Site, release_id: &str) -> Result<String, (String, String)> {
+        let release_directory = self.get_site_releases_directory(&site.id).join(release_id);
+
+        // The release starts as a checkout of the branch of the repository, or as a copy of the source directory
+        let mut build_output = String::new();
+        if !site.deploy_git_repository.is_empty() {
+            std::fs::create_dir_all(self.get_site_releases_directory(&site.id)).map_err(|e| (String::new(), format!("Failed to create the releases directory: {}", e)))?;
+            let mut command = get_release_command("git", &release_directory);
+            command
+                .args([
+                    "clone",
+                    "--quiet",
+                    "--depth",
+                    "1",
+                    "--single-branch",
+                    "--branch",
+                    &site.deploy_git_branch,
+                    "--",
+                    &site.deploy_git_repository,
+                ])
+                .arg(&release_directory);
+            command.current_dir(self.get_site_releases_directory(&site.id)).env("GIT_TERMINAL_PROMPT", "0");
+            build_output = run_release_command(command, site.build_timeout_seconds, "git clone").await?;
+
+            let mut command = get_release_command("git", &release_directory);
+            command.args(["rev-parse", "HEAD"]);
+            let commit = run_release_command(command, site.build_timeout_seconds, "git rev-parse").await?;
+            if let Some(mut build) = self.builds.get_mut(&site.id) {
+                build.commit = Some(commit.trim().to_string());
+            }
+
+            // The repository history is not part of the site, and must not be served when the release itself is the web root
+            std::fs::remove_dir_all(release_directory.join(".git")).map_err(|e| (build_output.clone(), format!("Failed to remove .git from the release: {}", e)))?;
+        } else {
+            let source_directory = PathBuf::from(&site.build_source_directory);
+            let copy_destination = release_directory.clone();
+            tokio::task::spawn_blocking(move || copy_directory(&source_directory, &copy_destination))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result.map_err(|e| e.to_string()))
+                .map_err(|e| (String::new(), format!("Failed to copy the source directory {}: {}", site.build_source_directory, e)))?;
+        }
+
+        if !site.build_command.is_empty() {
+            let mut command = get_shell_command(&site.build_command, &release_directory);
+            command.envs(site.extra_environment.iter().map(|variable| (&variable.name, &variable.value)));
+            command.env("GRUXI_SITE_ID", &site.id).env("GRUXI_RELEASE_ID", release_id);
+            build_output = run_release_command(command, site.build_timeout_seconds, "The build command").await?;
+        }
+
+        let output_directory = self.get_output_directory(site, release_id);
+        if !Path::new(&output_directory).is_dir() {
+            return Err((build_output, format!("The release has no output directory {}", output_directory)));
+        }
+
+        self.switch_release(site, release_id).map_err(|e| (build_output.clone(), e))?;
+        Ok(build_output)
+    }
+
+    // The current file is replaced by renaming, so it always names a complete release, also if Gruxi stops halfway
+    fn switch_release(&self, site: &Site, release_id: &str) -> Result<(), String> {
+        let site_releases_directory = self.get_site_releases_directory(&site.id);
+        let temporary_file = site_releases_directory.join(format!("{}.tmp", CURRENT_RELEASE_FILE));
+        std::fs::write(&temporary_file, release_id).map_err(|e| format!("Failed to write {}: {}", temporary_file.display(), e))?;
+        std::fs::rename(&temporary_file, site_releases_directory.join(CURRENT_RELEASE_FILE)).map_err(|e| format!("Failed to switch to release {}: {}", release_id, e))?;
+
+        self.active_web_roots.insert(site.id.clone(), Some(self.get_output_directory(site, release_id)));
+        info(format!("Site {} is now served from release {}", site.id, release_id));
+        Ok(())
+    }
+
+    fn finish_build(&self, site: &Site, release_id: &str, result: Result<String, (String, String)>) {
+        let succeeded = result.is_ok();
+        if let Some(mut build) = self.builds.get_mut(&site.id) {
+            build.finished_at = Some(chrono::Utc::now().to_rfc3339());
+            match result {
+                Ok(output) => {
+                    build.state = BuildState::Succeeded;
+                    build.output = output;
+                }
+                Err((output, e)) => {
+                    error(format!("Build {} of site {} failed: {}", release_id, site.id, e));
+                    build.state = BuildState::Failed;
+                    build.output = output;
+                    build.error = Some(e);
+                }
+            }
+        }
+
+        if succeeded {
+            self.remove_old_releases(&site.id);
+        } else if let Err(e) = std::fs::remove_dir_all(self.get_site_releases_directory(&site.id).join(release_id)) {
+            debug(format!("Failed to remove the directory of failed build {}: {}", release_id, e));
+        }
+    }
+
+    // Release ids start with the time of the build, so they sort from old to new
+    fn remove_old_releases(&self, site_id: &str) {
+        let site_releases_directory = self.get_site_releases_directory(site_id);
+        let active_release_id = self.get_active_release_id(site_id);
+        let Ok(entries) = std::fs::read_dir(&site_releases_directory) else {
+            return;
+        };
+        let mut release_ids: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|release_id| Some(release_id) != active_release_id.as_ref())
+            .collect();
+        release_ids.sort();
+
+        let remove_count = release_ids.len().saturating_sub(RELEASES_TO_KEEP - 1);
+        for release_id in release_ids.into_iter().take(remove_count) {
+            trace(format!("Removing old release {} of site {}", release_id, site_id));
+            if let Err(e) = std::fs::remove_dir_all(site_releases_directory.join(&release_id)) {
+                warn(format!("Failed to remove old release {} of site {}: {}", release_id, site_id, e));
+            }
+        }
+    }
+
+    fn get_site_releases_directory(&self, site_id: &str) -> PathBuf {
+        self.releases_directory.join(site_id)
+    }
+
+    // The release itself when the site has no output directory, such as PHP sites deployed from git
+    fn get_output_directory(&self, site: &Site, release_id: &str) -> String {
+        let mut output_directory = self.get_site_releases_directory(&site.id).join(release_id);
+        if !site.build_output_directory.is_empty() {
+            output_directory = output_directory.join(&site.build_output_directory);
+        }
+        std::path::absolute(&output_directory).unwrap_or(output_directory).to_string_lossy().replace('\\', "/")
+    }
+}
+
+// Whether the token sent to the deploy webhook is the one of the site. Compared in constant time, so the token cannot be found byte by byte
+pub fn is_deploy_webhook_token_valid(site: &Site, token: &str) -> bool {
+    !site.deploy_webhook_token.is_empty() && constant_time_equals(token.as_bytes(), site.deploy_webhook_token.as_bytes())
+}
+
+// Compares secrets without returning early at the first difference, only the length can be told from the time taken
+pub fn constant_time_equals(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let difference = a.iter().zip(b).fold(0u8, |difference, (a, b)| difference | (a ^ b));
+    std::hint::black_box(difference) == 0
+}
+
+// A command run for a release, in the release directory and with only the environment the tools need
+fn get_release_command(program: &str, release_directory: &Path) -> Command {
+    let mut command = Command::new(program);
+    command.current_dir(release_directory).env_clear().kill_on_drop(true);
+    // In a process group of its own, so the processes the command starts can be killed with it
+    #[cfg(unix)]
+    command.process_group(0);
+    for name in INHERITED_ENVIRONMENT_VARIABLES {
+        if let Ok(value) = std::env::var(name) {
+            command.env(name, value);
+        }
+    }
+    command
+}
+
+// The build command runs through the shell, so it can be a script like "npm ci && npm run build"
+fn get_shell_command(build_command: &str, release_directory: &Path) -> Command {
+    if cfg!(target_os = "windows") {
+        let mut command = get_release_command("cmd", release_directory);
+        command.arg("/C").arg(build_command);
+        command
+    } else {
+        let mut command = get_release_command("sh", release_directory);
+        command.arg("-c").arg(build_command);
+        command
+    }
+}
+
+// Runs the command, returning the end of its output, along with the error if it failed or did not finish in time. A command that
+// does not finish in time is killed along with the processes it started, such as those of "npm ci && npm run build"
+async fn run_release_command(mut command: Command, timeout_seconds: u64, name: &str) -> Result<String, (String, String)> {
+    command.stdout(std::process::Stdio::piped()).stderr(std::process::Stdio::piped());
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return Err((String::new(), format!("Failed to run {}: {}", name, e))),
+    };
+    let process_id = child.id();
+    let output = match tokio::time::timeout(Duration::from_secs(timeout_seconds), child.wait_with_output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return Err((String::new(), format!("Failed to run {}: {}", name, e))),
+        Err(_) => {
+            kill_process_group(process_id);
+            return Err((String::new(), format!("{} did not finish within {} seconds", name, timeout_seconds)));
+        }
+    };
+    let mut combined_output = output.stdout;
+    combined_output.extend_from_slice(&output.stderr);
+    let command_output = get_output_tail(&combined_output);
+
+    if !output.status.success() {
+        return Err((command_output, format!("{} failed with {}", name, output.status)));
+    }
+    Ok(command_output)
+}
+
+// The command itself is killed when it is dropped, which on Windows leaves the processes it started running
+#[cfg(unix)]
+fn kill_process_group(process_id: Option<u32>) {
+    if let Some(process_id) = process_id.and_then(|process_id| libc::pid_t::try_from(process_id).ok()) {
+        unsafe {
+            libc::kill(-process_id, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_process_id: Option<u32>) {}
+
+// Copies the source into the release directory, leaving out .git and symbolic links, which could point out of the source directory
+fn copy_directory(source: &Path, destination: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(destination)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = destination.join(entry.file_name());
+        if file_type.is_dir() {
+            if entry.file_name() != ".git" {
+                copy_directory(&entry.path(), &target)?;
+            }
+        } else if file_type.is_file() {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn get_output_tail(output: &[u8]) -> String {
+    let start = output.len().saturating_sub(MAX_BUILD_OUTPUT_BYTES);
+    String::from_utf8_lossy(&output[start..]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_site(source_directory: &Path, build_command: &str) -> Site {
+        let mut site = Site::new();
+        site.build_command = build_command.to_string();
+        site.build_source_directory = source_directory.to_string_lossy().to_string();
+        site.build_output_directory = "public".to_string();
+        site
+    }
+
+    async fn wait_for_build(builder: &SiteBuilder, site_id: &str) -> SiteBuild {
+        for _ in 0..500 {
+            let build = builder.get_last_build(site_id).unwrap();
+            if build.state != BuildState::Running {
+                return build;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Build did not finish");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_build_switches_web_root_only_on_success() {
+        let directory = std::env::temp_dir().join(format!("gruxi-test-{}", uuid::Uuid::new_v4()));
+        let source_directory = directory.join("source");
+        std::fs::create_dir_all(source_directory.join(".git")).unwrap();
+        std::fs::write(source_directory.join("index.md"), "Hello").unwrap();
+        let builder: &'static SiteBuilder = Box::leak(Box::new(SiteBuilder::new(directory.join("releases"))));
+
+        let site = create_site(&source_directory, "mkdir public && cp index.md public/index.html && echo built $GRUXI_SITE_ID");
+        assert!(builder.get_active_web_root(&site).is_none());
+
+        let release_id = builder.start_build(&site, "admin").unwrap();
+        let build = wait_for_build(builder, &site.id).await;
+        assert_eq!(build.state, BuildState::Succeeded, "{:?}", build);
+        assert!(build.output.contains(&format!("built {}", site.id)));
+        assert_eq!(builder.get_active_release_id(&site.id).unwrap(), release_id);
+        let web_root = builder.get_active_web_root(&site).unwrap();
+        assert_eq!(std::fs::read_to_string(Path::new(&web_root).join("index.html")).unwrap(), "Hello");
+        assert!(!directory.join("releases").join(&site.id).join(&release_id).join(".git").exists());
+
+        // A failing build keeps the site on the previous release
+        let failing_site = Site {
+            build_command: "echo broken >&2 && exit 3".to_string(),
+            ..site.clone()
+        };
+        builder.start_build(&failing_site, "webhook").unwrap();
+        let build = wait_for_build(builder, &site.id).await;
+        assert_eq!(build.state, BuildState::Failed);
+        assert!(build.output.contains("broken"));
+        assert_eq!(builder.get_active_web_root(&site).unwrap(), web_root);
+
+        // Without a build command, the configured web root is used
+        let unbuilt_site = Site {
+            build_command: String::new(),
+            ..site.clone()
+        };
+        assert!(builder.get_active_web_root(&unbuilt_site).is_none());
+        assert!(builder.start_build(&unbuilt_site, "admin").is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_git_deployment_serves_the_checkout() {
+        let directory = std::env::temp_dir().join(format!("gruxi-test-{}", uuid::Uuid::new_v4()));
+        let repository_directory = directory.join("repository");
+        std::fs::create_dir_all(&repository_directory).unwrap();
+        std::fs::write(repository_directory.join("index.php"), "<?php echo 'Hello';").unwrap();
+        for args in [
+            vec!["init", "--quiet", "--initial-branch", "live"],
+            vec!["add", "."],
+            vec!["-c", "user.name=Gruxi", "-c", "user.email=gruxi@example.com", "commit", "--quiet", "-m", "Initial"],
+        ] {
+            let status = std::process::Command::new("git").args(&args).current_dir(&repository_directory).status().unwrap();
+            assert!(status.success());
+        }
+        let builder: &'static SiteBuilder = Box::leak(Box::new(SiteBuilder::new(directory.join("releases"))));
+
+        let mut site = Site::new();
+        site.deploy_git_repository = repository_directory.to_string_lossy().to_string();
+        site.deploy_git_branch = "live".to_string();
+        builder.start_build(&site, "git push").unwrap();
+        let build = wait_for_build(builder, &site.id).await;
+        assert_eq!(build.state, BuildState::Succeeded, "{:?}", build);
+        assert_eq!(build.commit.unwrap().len(), 40);
+
+        // Without a build command, the checkout itself is served, without the repository history
+        let web_root = builder.get_active_web_root(&site).unwrap();
+        assert!(Path::new(&web_root).join("index.php").is_file());
+        assert!(!Path::new(&web_root).join(".git").exists());
+
+        // A branch that does not exist fails, keeping the site on its release
+        site.deploy_git_branch = "missing".to_string();
+        builder.start_build(&site, "git push").unwrap();
+        assert_eq!(wait_for_build(builder, &site.id).await.state, BuildState::Failed);
+        assert_eq!(builder.get_active_web_root(&site).unwrap(), web_root);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_deploy_webhook_token() {
+        let mut site = Site::new();
+        assert!(!is_deploy_webhook_token_valid(&site, ""));
+        site.deploy_webhook_token = "0123456789abcdef".to_string();
+        assert!(is_deploy_webhook_token_valid(&site, "0123456789abcdef"));
+        assert!(!is_deploy_webhook_token_valid(&site, "0123456789abcdeF"));
+        assert!(!is_deploy_webhook_token_valid(&site, "0123456789abcde"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_timed_out_command_is_killed_with_its_processes() {
+        let directory = std::env::temp_dir().join(format!("gruxi-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let command = get_shell_command("(sleep 2 && touch started-late) & sleep 30", &directory);
+        let (_, error) = run_release_command(command, 1, "The build command").await.unwrap_err();
+        assert_eq!(error, "The build command did not finish within 1 seconds");
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(!directory.join("started-late").exists());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_output_tail_keeps_the_end() {
+        let output = vec![b'a'; MAX_BUILD_OUTPUT_BYTES + 10];
+        assert_eq!(get_output_tail(&output).len(), MAX_BUILD_OUTPUT_BYTES);
+        assert_eq!(get_output_tail(b"done"), "done");
+    }
+}
@@ -1,18 +1,30 @@
+use crate::core::operation_mode::get_operation_mode;
 use crate::error::gruxi_error_enums::*;
+use std::backtrace::Backtrace;
 
 #[derive(Debug)]
 pub struct GruxiError {
     pub kind: GruxiErrorKind,
     pub message: String,
+    // Only captured when the operation mode shows error details, as capturing is expensive
+    pub backtrace: Option<Backtrace>,
 }
 
 impl GruxiError {
     pub fn new(kind: GruxiErrorKind, message: String) -> Self {
-        Self { kind, message }
+        Self {
+            kind,
+            message,
+            backtrace: capture_backtrace(),
+        }
     }
 
     pub fn new_with_kind_only(kind: GruxiErrorKind) -> Self {
-        Self { kind, message: String::new() }
+        Self {
+            kind,
+            message: String::new(),
+            backtrace: capture_backtrace(),
+        }
     }
 
     pub fn get_http_status_code(&self) -> u16 {
@@ -22,3 +34,7 @@ impl GruxiError {
         }
     }
 }
+
+fn capture_backtrace() -> Option<Backtrace> {
+    if get_operation_mode().shows_error_details() { Some(Backtrace::force_capture()) } else { None }
+}
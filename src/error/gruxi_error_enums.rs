@@ -6,7 +6,7 @@ pub enum GruxiErrorKind {
     HttpRequestValidation(u16), // HTTP status code for request validation errors
     FastCgi(FastCgiError),
     Internal(&'static str),
-    AdminApi(AdminApiError)
+    AdminApi(AdminApiError),
 }
 
 #[derive(Debug)]
@@ -32,7 +32,9 @@ pub enum PHPProcessorError {
     Connection,
     PathError(std::io::Error),
     FileNotFound,
+    FileBlockedDueToSecurity(String),
     Timeout,
+    RequestBody(std::io::Error),
     Internal,
 }
 
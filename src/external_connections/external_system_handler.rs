@@ -3,13 +3,17 @@ use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Semaphore;
 
 use crate::{
+    configuration::site::EnvironmentVariable,
     external_connections::managed_system::php_cgi::PhpCgi,
     logging::syslog::{error, trace},
+    network::port_manager::get_port_manager,
 };
 
 pub struct ExternalSystemHandler {
     pub php_cgi_id_to_port: HashMap<String, u16>,
     pub connection_semaphore: HashMap<String, Arc<Semaphore>>,
+    // The environment of each PHP-CGI handler, which is merged with the environment of the site for each request
+    pub php_cgi_id_to_environment: HashMap<String, Vec<EnvironmentVariable>>,
 }
 
 impl ExternalSystemHandler {
@@ -21,6 +25,17 @@ impl ExternalSystemHandler {
         let config = cached_configuration.get_configuration().await;
 
         let mut php_cgi_id_to_port = HashMap::new();
+        let mut php_cgi_id_to_environment = HashMap::new();
+
+        // The ports for the processes we start
+        let server_settings = &config.core.server_settings;
+        get_port_manager()
+            .configure(
+                server_settings.managed_port_range_start,
+                server_settings.managed_port_range_end,
+                &server_settings.managed_port_exclusions,
+            )
+            .await;
 
         // Load PHP-CGI handlers from configuration
         for php_cgi_config in &config.php_cgi_handlers {
@@ -31,6 +46,7 @@ impl ExternalSystemHandler {
                 php_cgi_config.concurrent_threads,
                 php_cgi_config.executable.clone(),
             );
+            new_php_cgi.extra_environment = php_cgi_config.extra_environment.clone();
 
             let port_result = new_php_cgi.start().await;
             let port = match port_result {
@@ -48,6 +64,7 @@ impl ExternalSystemHandler {
 
             // We save the id matched to port for reference
             php_cgi_id_to_port.insert(php_cgi_config.id.clone(), port);
+            php_cgi_id_to_environment.insert(php_cgi_config.id.clone(), php_cgi_config.extra_environment.clone());
 
             // Create a connection semaphore for this PHP-CGI instance
             let connection_semaphore_value = Arc::new(Semaphore::new(php_cgi_config.get_max_children_processes() as usize));
@@ -62,6 +79,7 @@ impl ExternalSystemHandler {
         ExternalSystemHandler {
             php_cgi_id_to_port,
             connection_semaphore,
+            php_cgi_id_to_environment,
         }
     }
 
@@ -69,6 +87,10 @@ impl ExternalSystemHandler {
         self.php_cgi_id_to_port.get(php_cgi_id).cloned().ok_or(())
     }
 
+    pub fn get_environment_for_php_cgi(&self, php_cgi_id: &str) -> &[EnvironmentVariable] {
+        self.php_cgi_id_to_environment.get(php_cgi_id).map(|variables| variables.as_slice()).unwrap_or_default()
+    }
+
     pub fn get_connection_semaphore(&self, external_system_id: &str) -> Option<Arc<Semaphore>> {
         self.connection_semaphore.get(external_system_id).cloned()
     }
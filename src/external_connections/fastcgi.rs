@@ -1,16 +1,49 @@
+use crate::core::operation_mode::{OperationMode, get_operation_mode};
 use crate::error::gruxi_error_enums::FastCgiError;
 use crate::file::file_util::replace_web_root_in_path;
 use crate::file::file_util::split_path;
 use crate::http::http_util::full;
+use crate::http::request_handlers::processors::proxy_helpers::proxy_cache::CONDITIONAL_REQUEST_HEADERS;
 use crate::http::request_response::gruxi_request::GruxiRequest;
 use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::http::request_response::request_timings::{RequestPhase, record_request_phase};
+use crate::http::request_validation::get_combined_header_value;
+use crate::http::upload_manager::SpooledBody;
 use crate::logging::syslog::error;
 use crate::logging::syslog::trace;
+use crate::network::dns_cache::get_dns_cache;
+use crate::network::outbound_connection::{TimeoutStream, get_outbound_timeouts};
+use crate::tls::client_hello_fingerprint::{TLS_JA3_KEY, TLS_JA4_KEY};
+use rustls::ClientConfig;
+use rustls::pki_types::ServerName;
+use std::sync::Arc;
 use std::time::Instant;
 use std::{collections::HashMap, time::Duration};
+use tls_listener::rustls::TlsConnector;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 
+// Max content length of a single FastCGI record
+const FASTCGI_MAX_RECORD_CONTENT_LENGTH: usize = 65535;
+// STDERR output kept per request, beyond this it is cut off
+const MAX_CAPTURED_STDERR_SIZE: usize = 64 * 1024;
+// Request calculated data key holding the STDERR output of the FastCGI request
+pub const FASTCGI_STDERR_KEY: &str = "fastcgi_stderr";
+
+// Set by the PHP processor when it may cache the response, as JSON of the header names and values of the conditions revalidating
+// its stale cached response, if any. They replace the conditions of the client, and compression is left to Gruxi, so the stored
+// body serves every client
+pub const FASTCGI_CACHE_CONDITIONS_KEY: &str = "fastcgi_cache_conditions";
+
+#[cfg(test)]
+pub mod test_server;
+
+// TLS for the connection to a FastCGI server on an untrusted network, with the name to send as SNI and verify the certificate for
+pub struct FastCgiTls {
+    pub config: Arc<ClientConfig>,
+    pub server_name: ServerName<'static>,
+}
+
 pub struct FastCgi;
 
 impl FastCgi {
@@ -20,7 +53,7 @@ impl FastCgi {
 
     pub async fn send_fastcgi_keep_alive(ip_and_port: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         // Connect to the FastCGI server with a short timeout
-        let stream = tokio::time::timeout(Duration::from_secs(2), tokio::net::TcpStream::connect(ip_and_port)).await??;
+        let stream = tokio::time::timeout(Duration::from_secs(2), get_dns_cache().connect(ip_and_port)).await??;
 
         // Send a minimal FastCGI request just to test connectivity
         let mut stream = stream;
@@ -110,8 +143,33 @@ impl FastCgi {
         packet
     }
 
-    pub fn parse_fastcgi_response(buffer: &[u8]) -> Vec<u8> {
+    // Sends the request body as STDIN records, each record can hold at most 65535 bytes
+    async fn send_fastcgi_stdin<S: tokio::io::AsyncWrite + Unpin>(stream: &mut S, request_body: &SpooledBody) -> Result<(), std::io::Error> {
+        match request_body {
+            SpooledBody::Memory(bytes) => {
+                for chunk in bytes.chunks(FASTCGI_MAX_RECORD_CONTENT_LENGTH) {
+                    stream.write_all(&Self::create_fastcgi_stdin(chunk)).await?;
+                }
+            }
+            SpooledBody::File(spool_file) => {
+                let mut file = tokio::fs::File::open(&spool_file.path).await?;
+                let mut buffer = vec![0u8; FASTCGI_MAX_RECORD_CONTENT_LENGTH];
+                loop {
+                    let read = file.read(&mut buffer).await?;
+                    if read == 0 {
+                        break;
+                    }
+                    stream.write_all(&Self::create_fastcgi_stdin(&buffer[..read])).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Parses the FastCGI records into the STDOUT data, which is the HTTP response, and the STDERR output of the script
+    pub fn parse_fastcgi_response(buffer: &[u8]) -> (Vec<u8>, String) {
         let mut response = Vec::new();
+        let mut stderr_output = String::new();
         let mut i = 0;
         let mut stdout_records = 0;
 
@@ -155,10 +213,9 @@ impl FastCgi {
                     trace("Received empty FCGI_STDOUT record (stream terminator)".to_string());
                 }
             } else if record_type == 7 {
-                // FCGI_STDERR - log errors
-                if content_length > 0 {
-                    let stderr_content = String::from_utf8_lossy(&buffer[content_start..content_end]);
-                    error(format!("FastCGI STDERR: {}", stderr_content));
+                // FCGI_STDERR - collected, so it can be logged with the request it belongs to
+                if content_length > 0 && stderr_output.len() < MAX_CAPTURED_STDERR_SIZE {
+                    stderr_output.push_str(&String::from_utf8_lossy(&buffer[content_start..content_end]));
                 }
             } else if record_type == 3 {
                 // FCGI_END_REQUEST
@@ -170,10 +227,10 @@ impl FastCgi {
             i = content_end + padding_length;
         }
 
-        response
+        (response, stderr_output)
     }
 
-    fn is_fastcgi_response_complete(buffer: &[u8]) -> bool {
+    pub fn is_fastcgi_response_complete(buffer: &[u8]) -> bool {
         // Check if we have received a complete FastCGI response stream:
         // 1. Find an FCGI_STDOUT record with contentLength = 0 (stream terminator)
         // 2. Followed by an FCGI_END_REQUEST record (type 3)
@@ -207,16 +264,18 @@ impl FastCgi {
         false
     }
 
-    pub async fn process_fastcgi_request(gruxi_request: &mut GruxiRequest) -> Result<GruxiResponse, FastCgiError> {
+    pub async fn process_fastcgi_request(gruxi_request: &mut GruxiRequest, request_body: &SpooledBody, tls: Option<&FastCgiTls>) -> Result<GruxiResponse, FastCgiError> {
         // Generate FastCGI parameters
         let params_result = Self::generate_fast_cgi_params(gruxi_request);
-        let params = match params_result {
+        let mut params = match params_result {
             Ok(p) => p,
             Err(_) => {
                 error(format!("Failed to generate FastCGI parameters from request {:?}", gruxi_request));
                 return Err(FastCgiError::Initialization);
             }
         };
+        // The body has been read in full, so we know the exact length
+        params.insert("CONTENT_LENGTH".to_string(), request_body.len().to_string());
         trace(format!("Generated FastCGI parameters: {:?}", params));
 
         // Determine FastCGI server IP and port
@@ -231,6 +290,7 @@ impl FastCgi {
         // Now we work on getting a semaphore permit for the connection, if relevant
         let connection_semaphore_option = gruxi_request.get_connection_semaphore();
 
+        let mut stderr_output = String::new();
         let response = match connection_semaphore_option {
             Some(connection_semaphore) => {
                 // We only need a permit, if a connection semaphore is set
@@ -251,26 +311,72 @@ impl FastCgi {
                         return Err(FastCgiError::ConnectionPermitAcquisition);
                     }
                 };
-                Self::do_fastcgi_request_and_response(gruxi_request, &ip_and_port, &params).await
+                Self::do_fastcgi_request_and_response(&ip_and_port, &params, request_body, &mut stderr_output, tls).await
             }
-            None => Self::do_fastcgi_request_and_response(gruxi_request, &ip_and_port, &params).await,
+            None => Self::do_fastcgi_request_and_response(&ip_and_port, &params, request_body, &mut stderr_output, tls).await,
         };
 
+        // Keep the STDERR output with the request, for the debug capture and error responses in development mode
+        if !stderr_output.is_empty() {
+            error(format!(
+                "FastCGI STDERR for request '{}' to '{}': {}",
+                gruxi_request.get_request_id(),
+                gruxi_request.get_path(),
+                stderr_output.trim_end()
+            ));
+            gruxi_request.add_calculated_data(FASTCGI_STDERR_KEY, &stderr_output);
+        }
+
         response
     }
 
-    pub async fn do_fastcgi_request_and_response(gruxi_request: &mut GruxiRequest, ip_and_port: &str, params: &HashMap<String, String>) -> Result<GruxiResponse, FastCgiError> {
+    pub async fn do_fastcgi_request_and_response(
+        ip_and_port: &str,
+        params: &HashMap<String, String>,
+        request_body: &SpooledBody,
+        stderr_output: &mut String,
+        tls: Option<&FastCgiTls>,
+    ) -> Result<GruxiResponse, FastCgiError> {
         trace(format!("Connecting to FastCGI server at {}", ip_and_port));
 
-        // Connect to the FastCGI server
-        let mut stream = match tokio::net::TcpStream::connect(&ip_and_port).await {
-            Ok(stream) => stream,
+        // Connect to the FastCGI server, which may be given by host name
+        let connect_started = Instant::now();
+        let connect_result = get_dns_cache().connect(ip_and_port).await;
+        let mut stream = match connect_result {
+            Ok(stream) => TimeoutStream::new(stream, get_outbound_timeouts()),
             Err(e) => {
+                record_request_phase(RequestPhase::BackendConnect, connect_started.elapsed());
                 error(format!("FastCGI Error: Failed to connect to FastCGI server {}: {}", ip_and_port, e));
                 return Err(FastCgiError::Connection(e));
             }
         };
 
+        let Some(tls) = tls else {
+            record_request_phase(RequestPhase::BackendConnect, connect_started.elapsed());
+            return Self::exchange_fastcgi_request(&mut stream, params, request_body, stderr_output).await;
+        };
+
+        // The TLS handshake is part of connecting
+        let handshake_result = TlsConnector::from(tls.config.clone()).connect(tls.server_name.clone(), stream).await;
+        record_request_phase(RequestPhase::BackendConnect, connect_started.elapsed());
+        let mut tls_stream = match handshake_result {
+            Ok(tls_stream) => tls_stream,
+            Err(e) => {
+                error(format!("FastCGI Error: TLS handshake with FastCGI server {} failed: {}", ip_and_port, e));
+                return Err(FastCgiError::Connection(e));
+            }
+        };
+
+        Self::exchange_fastcgi_request(&mut tls_stream, params, request_body, stderr_output).await
+    }
+
+    // Sends the request over a connected stream and reads the response
+    async fn exchange_fastcgi_request<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(
+        stream: &mut S,
+        params: &HashMap<String, String>,
+        request_body: &SpooledBody,
+        stderr_output: &mut String,
+    ) -> Result<GruxiResponse, FastCgiError> {
         // Send FastCGI request
         trace(format!("Sending FastCGI request... with parameters: {:?}", params));
         let start_time = Instant::now();
@@ -297,13 +403,9 @@ impl FastCgi {
         }
 
         // Send body if present
-        let body_bytes = gruxi_request.get_body_bytes().await;
-        if body_bytes.len() > 0 {
-            let stdin_data = Self::create_fastcgi_stdin(&body_bytes);
-            if let Err(e) = stream.write_all(&stdin_data).await {
-                error(format!("FastCGI Error: Failed to send STDIN: {}", e));
-                return Err(FastCgiError::Communication(e));
-            }
+        if let Err(e) = Self::send_fastcgi_stdin(stream, request_body).await {
+            error(format!("FastCGI Error: Failed to send STDIN: {}", e));
+            return Err(FastCgiError::Communication(e));
         }
 
         // Send empty stdin to signal end
@@ -319,9 +421,10 @@ impl FastCgi {
         // Use 65535 byte buffer to match FastCGI max record size (FCGI_MAX_LENGTH)
         let mut buffer = vec![0u8; 65535];
 
-        // Read with timeout
-        let timeout_duration = Duration::from_secs(30);
-        match tokio::time::timeout(timeout_duration, async {
+        // Reading times out when the FastCGI server sends nothing within the outbound read timeout
+        let request_sent = Instant::now();
+        let mut first_byte_recorded = false;
+        let read_result = async {
             loop {
                 match stream.read(&mut buffer).await {
                     Ok(0) => {
@@ -329,6 +432,11 @@ impl FastCgi {
                         break; // Connection closed
                     }
                     Ok(n) => {
+                        // Only data counts as the first byte, not a closed connection or a failed read
+                        if !first_byte_recorded {
+                            first_byte_recorded = true;
+                            record_request_phase(RequestPhase::BackendFirstByte, request_sent.elapsed());
+                        }
                         trace(format!("Read {} bytes from FastCGI stream (total: {} bytes)", n, response_buffer.len() + n));
                         response_buffer.extend_from_slice(&buffer[..n]);
 
@@ -339,79 +447,39 @@ impl FastCgi {
                         }
                     }
                     Err(e) => {
-                        return Err(FastCgiError::Communication(e));
+                        return Err(e);
                     }
                 }
             }
-            Ok::<(), FastCgiError>(())
-        })
-        .await
-        {
+            Ok::<(), std::io::Error>(())
+        }
+        .await;
+        match read_result {
             Ok(_) => {}
-            Err(_) => {
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
                 error(format!("FastCGI response timeout after reading {} bytes", response_buffer.len()));
                 return Err(FastCgiError::Timeout);
             }
+            Err(e) => {
+                error(format!("FastCGI Error: Failed to read the response: {}", e));
+                return Err(FastCgiError::Communication(e));
+            }
         }
 
         // Parse FastCGI response and extract HTTP response
-        let http_response_bytes = Self::parse_fastcgi_response(&response_buffer);
+        let (http_response_bytes, stderr) = Self::parse_fastcgi_response(&response_buffer);
+        *stderr_output = stderr;
         if http_response_bytes.is_empty() {
             error("FastCGI - Empty response from PHP-CGI process".to_string());
             return Err(FastCgiError::InvalidResponse);
         }
 
-        // Find the end of headers to separate headers from body
-        let (headers_bytes, body_bytes) = if let Some(pos) = http_response_bytes.windows(4).position(|w| w == b"\r\n\r\n") {
-            let split_pos = pos + 4;
-            (&http_response_bytes[..pos], &http_response_bytes[split_pos..])
-        } else if let Some(pos) = http_response_bytes.windows(2).position(|w| w == b"\n\n") {
-            let split_pos = pos + 2;
-            (&http_response_bytes[..pos], &http_response_bytes[split_pos..])
-        } else {
-            // No headers separator found, treat entire response as body
-            (&[][..], &http_response_bytes[..])
-        };
-
-        // Convert headers to string for parsing (headers should always be valid UTF-8)
-        let headers_part = String::from_utf8_lossy(headers_bytes).to_string();
-
-        // Build HTTP response
-        let mut response_builder = hyper::Response::builder();
-        let mut status_code = hyper::StatusCode::OK;
-
-        // Parse headers
-        for line in headers_part.lines() {
-            if line.trim().is_empty() {
-                continue;
-            }
-            if let Some(colon_pos) = line.find(':') {
-                let (key, value) = line.split_at(colon_pos);
-                let value = value[1..].trim(); // Remove colon and trim
-
-                if key.eq_ignore_ascii_case("status") {
-                    // Parse status code
-                    if let Some(space_pos) = value.find(' ') {
-                        if let Ok(code) = value[..space_pos].parse::<u16>() {
-                            if let Ok(status) = hyper::StatusCode::from_u16(code) {
-                                status_code = status;
-                            }
-                        }
-                    }
-                } else {
-                    // Add other headers
-                    if let Ok(header_name) = hyper::header::HeaderName::from_bytes(key.as_bytes()) {
-                        if let Ok(header_value) = hyper::header::HeaderValue::from_str(&value) {
-                            response_builder = response_builder.header(header_name, header_value);
-                        }
-                    }
-                }
-            }
-        }
+        let (status_code, headers, body_bytes) = Self::parse_cgi_response(&http_response_bytes);
 
         // Build the final response with binary body
-        match response_builder.status(status_code).body(full(body_bytes.to_vec())) {
-            Ok(response) => {
+        match hyper::Response::builder().status(status_code).body(full(body_bytes.to_vec())) {
+            Ok(mut response) => {
+                *response.headers_mut() = headers;
                 let end_time = Instant::now();
                 let duration = end_time - start_time;
                 trace(format!("FastCGI response parsed successfully in {:?}", duration));
@@ -424,23 +492,71 @@ impl FastCgi {
         }
     }
 
+    // Splits the output of the script into the status, the headers and the body, as for CGI. Lines that are not
+    // valid headers are skipped, and output without a blank line after the headers is all body. Repeated headers are all kept,
+    // such as the Set-Cookie headers of a login, as they cannot be combined into one
+    pub fn parse_cgi_response(output: &[u8]) -> (hyper::StatusCode, hyper::HeaderMap, &[u8]) {
+        // The headers end at the first blank line, with either line ending
+        let crlf_end = output.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| (pos, pos + 4));
+        let lf_end = output.windows(2).position(|w| w == b"\n\n").map(|pos| (pos, pos + 2));
+        let (headers_bytes, body_bytes) = match (crlf_end, lf_end) {
+            (Some(crlf), Some(lf)) if lf.0 < crlf.0 => (&output[..lf.0], &output[lf.1..]),
+            (Some((headers_end, body_start)), _) | (None, Some((headers_end, body_start))) => (&output[..headers_end], &output[body_start..]),
+            (None, None) => (&[][..], output),
+        };
+
+        let mut status_code = hyper::StatusCode::OK;
+        let mut headers = hyper::HeaderMap::new();
+        for line in String::from_utf8_lossy(headers_bytes).lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            if key.eq_ignore_ascii_case("status") {
+                // "Status: 404 Not Found", where the reason phrase may be left out
+                let code = value.split(' ').next().unwrap_or_default();
+                if let Some(status) = code.parse::<u16>().ok().and_then(|code| hyper::StatusCode::from_u16(code).ok()) {
+                    status_code = status;
+                }
+            } else if let (Ok(header_name), Ok(header_value)) = (hyper::header::HeaderName::from_bytes(key.as_bytes()), hyper::header::HeaderValue::from_str(value)) {
+                headers.append(header_name, header_value);
+            }
+        }
+
+        (status_code, headers, body_bytes)
+    }
+
     pub fn generate_fast_cgi_params(gruxi_request: &mut GruxiRequest) -> Result<HashMap<String, String>, ()> {
         let mut params: HashMap<String, String> = HashMap::new();
 
         let uri = gruxi_request.get_path();
         let headers = gruxi_request.get_headers();
 
-        // Add HTTP headers as CGI variables, prefixed with HTTP_ and uppercased
-        for (key, value) in headers.iter() {
-            let key_str = key.to_string();
+        // Add HTTP headers as CGI variables, prefixed with HTTP_ and uppercased. Repeated headers are combined, as a variable has one value
+        for key in headers.keys() {
+            if let Some(value) = get_combined_header_value(headers, key) {
+                params.insert(Self::get_cgi_header_variable(key.as_str()), value);
+            }
+        }
 
-            // Try converting the value to a &str
-            if let Ok(value_str) = value.to_str() {
-                let key_str = format!("HTTP_{}", key_str.replace("-", "_").to_uppercase());
-                params.insert(key_str, value_str.to_string());
+        if let Some(cache_conditions) = gruxi_request.get_calculated_data(FASTCGI_CACHE_CONDITIONS_KEY) {
+            params.remove("HTTP_ACCEPT_ENCODING");
+            for header_name in &CONDITIONAL_REQUEST_HEADERS {
+                params.remove(&Self::get_cgi_header_variable(header_name.as_str()));
+            }
+            for (header_name, value) in serde_json::from_str::<Vec<(String, String)>>(&cache_conditions).unwrap_or_default() {
+                params.insert(Self::get_cgi_header_variable(&header_name), value);
             }
         }
 
+        // Environment of the site and its handler. It takes precedence over the request headers, but not over the variables set below
+        if let Some(environment) = gruxi_request.get_calculated_data("fastcgi_environment")
+            && let Ok(environment) = serde_json::from_str::<HashMap<String, String>>(&environment)
+        {
+            params.extend(environment);
+        }
+
         // Set content type and length if present
         if let Some(content_type) = headers.get("content-type") {
             if let Ok(content_type) = content_type.to_str() {
@@ -493,7 +609,9 @@ impl FastCgi {
 
         // Build FastCGI parameters (CGI environment variables)
         params.insert("REQUEST_METHOD".to_string(), gruxi_request.get_http_method());
-        params.insert("REQUEST_URI".to_string(), request_uri.clone());
+        // Front controllers route on REQUEST_URI, so it is what the client asked for, even if we rewrote the request internally (try_files)
+        let original_request_uri = gruxi_request.get_calculated_data("original_uri").unwrap_or_else(|| request_uri.clone());
+        params.insert("REQUEST_URI".to_string(), original_request_uri);
         params.insert("SCRIPT_NAME".to_string(), request_uri);
         params.insert("SCRIPT_FILENAME".to_string(), full_script_path);
         params.insert("DOCUMENT_ROOT".to_string(), script_web_root);
@@ -510,10 +628,35 @@ impl FastCgi {
         params.insert("PATH_INFO".to_string(), path_info);
         params.insert("REDIRECT_STATUS".to_string(), "200".to_string());
         params.insert("HTTP_HOST".to_string(), gruxi_request.get_hostname());
+        if let Some(ja3) = gruxi_request.get_calculated_data(TLS_JA3_KEY) {
+            params.insert("TLS_JA3".to_string(), ja3);
+        }
+        if let Some(ja4) = gruxi_request.get_calculated_data(TLS_JA4_KEY) {
+            params.insert("TLS_JA4".to_string(), ja4);
+        }
+
+        // PHP settings for this request, one per line. In development mode PHP shows its errors in the browser as well
+        let mut php_values: Vec<String> = Vec::new();
+        if get_operation_mode() == OperationMode::DEV {
+            php_values.push("display_errors=On".to_string());
+            php_values.push("error_reporting=E_ALL".to_string());
+        }
+        if let Some(php_error_log_file) = gruxi_request.get_calculated_data("fastcgi_php_error_log_file") {
+            php_values.push("log_errors=On".to_string());
+            php_values.push(format!("error_log={}", php_error_log_file));
+        }
+        if !php_values.is_empty() {
+            params.insert("PHP_VALUE".to_string(), php_values.join("\n"));
+        }
 
         Ok(params)
     }
 
+    // Such as HTTP_IF_NONE_MATCH for If-None-Match
+    fn get_cgi_header_variable(header_name: &str) -> String {
+        format!("HTTP_{}", header_name.replace("-", "_").to_uppercase())
+    }
+
     /// Compute PATH_INFO for a request given REQUEST_URI and SCRIPT_NAME
     ///
     /// # Arguments
@@ -546,10 +689,23 @@ impl FastCgi {
 #[cfg(test)]
 mod tests {
     use hyper::body::Bytes;
+    use std::collections::HashMap;
+    use std::time::Duration;
 
+    use crate::error::gruxi_error_enums::FastCgiError;
     use crate::http::request_response::gruxi_request::GruxiRequest;
+    use crate::http::request_response::gruxi_response::GruxiResponse;
+    use crate::http::upload_manager::SpooledBody;
+    use crate::network::outbound_connection::{OutboundTimeouts, TimeoutStream};
+
+    use crate::configuration::upstream_tls::UpstreamTlsSettings;
+    use crate::tls::upstream_tls::{get_certificate_fingerprint, get_server_name_override, get_upstream_tls_config};
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use std::sync::Arc;
+    use tls_listener::rustls::TlsAcceptor;
 
-    use super::FastCgi;
+    use super::test_server::{MockFastCgiResponse, MockFastCgiServer};
+    use super::{FASTCGI_CACHE_CONDITIONS_KEY, FASTCGI_STDERR_KEY, FastCgi, FastCgiTls};
 
     #[test]
     fn test_path_info() {
@@ -586,6 +742,28 @@ mod tests {
         assert_eq!(params.get("PATH_INFO").unwrap(), "");
     }
 
+    #[tokio::test]
+    async fn test_conditional_headers_are_passed_on() {
+        let request = hyper::Request::builder()
+            .uri("/api/items")
+            .header("If-None-Match", "\"client\"")
+            .header("If-Modified-Since", "Wed, 21 Oct 2026 07:28:00 GMT")
+            .header("Accept-Encoding", "gzip")
+            .body(Bytes::new())
+            .unwrap();
+        let mut gruxi_request = GruxiRequest::new(request);
+        let params = FastCgi::generate_fast_cgi_params(&mut gruxi_request).unwrap();
+        assert_eq!(params.get("HTTP_IF_NONE_MATCH").unwrap(), "\"client\"");
+        assert_eq!(params.get("HTTP_IF_MODIFIED_SINCE").unwrap(), "Wed, 21 Oct 2026 07:28:00 GMT");
+
+        // When the response is cached, the conditions revalidate the cached response instead
+        gruxi_request.add_calculated_data(FASTCGI_CACHE_CONDITIONS_KEY, r#"[["if-none-match","\"cached\""]]"#);
+        let params = FastCgi::generate_fast_cgi_params(&mut gruxi_request).unwrap();
+        assert_eq!(params.get("HTTP_IF_NONE_MATCH").unwrap(), "\"cached\"");
+        assert!(!params.contains_key("HTTP_IF_MODIFIED_SINCE"));
+        assert!(!params.contains_key("HTTP_ACCEPT_ENCODING"));
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn test_fastcgi_binary_response_parsing() {
         // Test that the parse_fastcgi_response function correctly handles binary data
@@ -617,10 +795,274 @@ mod tests {
         fastcgi_response.extend(&[0u8; 8]); // end request body
 
         // Parse the response using our updated function
-        let parsed_response = FastCgi::parse_fastcgi_response(&fastcgi_response);
+        let (parsed_response, stderr_output) = FastCgi::parse_fastcgi_response(&fastcgi_response);
 
         // Verify the binary data is preserved
         assert!(parsed_response.len() > 0);
         assert!(parsed_response.windows(binary_content.len()).any(|w| w == binary_content.as_slice()));
+        assert!(stderr_output.is_empty());
+    }
+
+    #[test]
+    fn test_fastcgi_stderr_parsing() {
+        let mut fastcgi_response = Vec::new();
+        for (record_type, content) in [(7u8, b"PHP Warning: Undefined variable".as_slice()), (6u8, b"Status: 200\r\n\r\nok".as_slice()), (6u8, b"".as_slice())] {
+            fastcgi_response.push(1); // version
+            fastcgi_response.push(record_type);
+            fastcgi_response.extend(&1u16.to_be_bytes()); // request_id
+            fastcgi_response.extend(&(content.len() as u16).to_be_bytes()); // content_length
+            fastcgi_response.push(0); // padding_length
+            fastcgi_response.push(0); // reserved
+            fastcgi_response.extend(content);
+        }
+
+        let (parsed_response, stderr_output) = FastCgi::parse_fastcgi_response(&fastcgi_response);
+        assert_eq!(parsed_response, b"Status: 200\r\n\r\nok");
+        assert_eq!(stderr_output, "PHP Warning: Undefined variable");
+    }
+
+    #[tokio::test]
+    async fn test_parse_cgi_response() {
+        let (status, headers, body) = FastCgi::parse_cgi_response(b"Status: 404\nContent-Type: text/plain\nnot a header\n\nbody with \r\n\r\n inside");
+        assert_eq!(status, hyper::StatusCode::NOT_FOUND);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers.get("content-type").unwrap(), "text/plain");
+        assert_eq!(body, b"body with \r\n\r\n inside");
+
+        let (status, headers, body) = FastCgi::parse_cgi_response(b"Status: 999999 Huge\r\nX-Bad\x01: value\r\n\r\n");
+        assert_eq!(status, hyper::StatusCode::OK);
+        assert!(headers.is_empty());
+        assert!(body.is_empty());
+
+        // Without the blank line, everything is body
+        let (_, headers, body) = FastCgi::parse_cgi_response(b"Content-Type: text/plain");
+        assert!(headers.is_empty());
+        assert_eq!(body, b"Content-Type: text/plain");
+
+        // Truncated and oversized records are not parsed beyond the data
+        assert!(!FastCgi::is_fastcgi_response_complete(&[1, 6, 0, 1, 0xff, 0xff, 0xff]));
+        assert_eq!(FastCgi::parse_fastcgi_response(&[1, 6, 0, 1, 0xff, 0xff, 0xff, 0, b'a']), (Vec::new(), String::new()));
+    }
+
+    // Like the response of wp-login.php to a successful login
+    const WORDPRESS_LOGIN_RESPONSE_HEADERS: &str = "Status: 302 Found\n\
+        Set-Cookie: wordpress_test_cookie=WP%20Cookie%20check; path=/; secure\n\
+        Set-Cookie: wordpress_sec_1a2b=admin%7C1760000000%7Ctoken%7Chash; path=/wp-content/plugins; secure; HttpOnly\n\
+        Set-Cookie: wordpress_sec_1a2b=admin%7C1760000000%7Ctoken%7Chash; path=/wp-admin; secure; HttpOnly\n\
+        set-cookie: wordpress_logged_in_1a2b=admin%7C1760000000%7Ctoken%7Chash; path=/; secure; HttpOnly\n\
+        Location: https://example.com/wp-admin/\n\
+        Content-Type: text/html; charset=UTF-8";
+
+    #[tokio::test]
+    async fn test_parse_cgi_response_keeps_every_set_cookie() {
+        let output = format!("{}\n\n", WORDPRESS_LOGIN_RESPONSE_HEADERS);
+        let (status, headers, body) = FastCgi::parse_cgi_response(output.as_bytes());
+        assert_eq!(status, hyper::StatusCode::FOUND);
+        assert!(body.is_empty());
+        let cookies: Vec<&str> = headers.get_all(hyper::header::SET_COOKIE).iter().map(|v| v.to_str().unwrap()).collect();
+        assert_eq!(cookies.len(), 4);
+        assert!(cookies[0].starts_with("wordpress_test_cookie="));
+        assert!(cookies[1].ends_with("path=/wp-content/plugins; secure; HttpOnly"));
+        assert!(cookies[2].ends_with("path=/wp-admin; secure; HttpOnly"));
+        assert!(cookies[3].starts_with("wordpress_logged_in_1a2b="));
+        assert_eq!(headers.get_all(hyper::header::LOCATION).iter().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_server_name_keeps_ipv6_host_whole() {
+        let request = hyper::Request::builder().method("GET").uri("/").header("Host", "[::1]:8080").body(Bytes::new()).unwrap();
+        let mut gruxi_request = GruxiRequest::new(request);
+        let params = FastCgi::generate_fast_cgi_params(&mut gruxi_request).unwrap();
+        assert_eq!(params.get("SERVER_NAME").unwrap(), "[::1]");
+    }
+
+    #[tokio::test]
+    async fn test_environment_is_passed_as_params() {
+        let request = hyper::Request::builder()
+            .method("GET")
+            .uri("/")
+            .header("Host", "localhost")
+            .header("App-Env", "client")
+            .body(Bytes::new())
+            .unwrap();
+        let mut gruxi_request = GruxiRequest::new(request);
+        gruxi_request.add_calculated_data("fastcgi_environment", r#"{"APP_ENV":"staging","HTTP_APP_ENV":"site","REQUEST_METHOD":"POST"}"#);
+        let params = FastCgi::generate_fast_cgi_params(&mut gruxi_request).unwrap();
+        assert_eq!(params.get("APP_ENV").unwrap(), "staging");
+        // The environment wins over the headers of the client, but cannot change the request variables
+        assert_eq!(params.get("HTTP_APP_ENV").unwrap(), "site");
+        assert_eq!(params.get("REQUEST_METHOD").unwrap(), "GET");
+    }
+
+    async fn send_to_mock_server(server: &MockFastCgiServer, params: &HashMap<String, String>, body: &[u8]) -> Result<(GruxiResponse, String), FastCgiError> {
+        let mut stderr_output = String::new();
+        let request_body = SpooledBody::Memory(Bytes::from(body.to_vec()));
+        let response = FastCgi::do_fastcgi_request_and_response(&server.get_ip_and_port(), params, &request_body, &mut stderr_output, None).await?;
+        Ok((response, stderr_output))
+    }
+
+    #[tokio::test]
+    async fn test_fastcgi_request_round_trip() {
+        let server = MockFastCgiServer::start(MockFastCgiResponse::ok("Status: 201 Created\nContent-Type: text/plain", b"created")).await;
+        let params = HashMap::from([("REQUEST_METHOD".to_string(), "POST".to_string()), ("SCRIPT_FILENAME".to_string(), "/var/www/index.php".to_string())]);
+
+        let (mut response, stderr_output) = send_to_mock_server(&server, &params, b"name=value").await.unwrap();
+        assert_eq!(response.get_status(), 201);
+        assert_eq!(response.headers().get("content-type").unwrap(), "text/plain");
+        assert_eq!(response.get_body_bytes().await, Bytes::from("created"));
+        assert!(stderr_output.is_empty());
+
+        let requests = server.get_received_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].params, params);
+        assert_eq!(requests[0].stdin, b"name=value");
+    }
+
+    // Terminates TLS in front of the mock server, like stunnel in front of PHP-FPM. Returns its address and certificate
+    async fn start_tls_terminator(server: &MockFastCgiServer) -> (String, CertificateDer<'static>) {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let certified_key = rcgen::generate_simple_self_signed(vec!["php-fpm.internal".to_string()]).unwrap();
+        let certificate = certified_key.cert.der().clone();
+        let key = PrivateKeyDer::try_from(certified_key.signing_key.serialize_der()).unwrap();
+        let server_config = rustls::ServerConfig::builder().with_no_client_auth().with_single_cert(vec![certificate.clone()], key).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ip_and_port = listener.local_addr().unwrap().to_string();
+        let backend_ip_and_port = server.get_ip_and_port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let Ok(mut tls_stream) = acceptor.accept(stream).await else {
+                    continue;
+                };
+                let mut backend_stream = tokio::net::TcpStream::connect(&backend_ip_and_port).await.unwrap();
+                tokio::spawn(async move {
+                    let _ = tokio::io::copy_bidirectional(&mut tls_stream, &mut backend_stream).await;
+                });
+            }
+        });
+        (ip_and_port, certificate)
+    }
+
+    #[tokio::test]
+    async fn test_fastcgi_request_over_tls() {
+        let server = MockFastCgiServer::start(MockFastCgiResponse::ok("Content-Type: text/plain", b"over tls")).await;
+        let (ip_and_port, certificate) = start_tls_terminator(&server).await;
+        let params = HashMap::from([("REQUEST_METHOD".to_string(), "GET".to_string())]);
+        let request_body = SpooledBody::Memory(Bytes::new());
+        let get_tls = |tls_settings: &UpstreamTlsSettings| FastCgiTls {
+            config: Arc::new(get_upstream_tls_config(false, tls_settings).unwrap()),
+            server_name: get_server_name_override(tls_settings).unwrap(),
+        };
+
+        // The self-signed certificate is trusted by its pinned fingerprint
+        let tls_settings = UpstreamTlsSettings {
+            server_name: "php-fpm.internal".to_string(),
+            pinned_certificates: vec![get_certificate_fingerprint(&certificate)],
+            ..Default::default()
+        };
+        let mut stderr_output = String::new();
+        let mut response = FastCgi::do_fastcgi_request_and_response(&ip_and_port, &params, &request_body, &mut stderr_output, Some(&get_tls(&tls_settings)))
+            .await
+            .unwrap();
+        assert_eq!(response.get_body_bytes().await, Bytes::from("over tls"));
+
+        // Any other certificate is refused before the request is sent
+        let tls_settings = UpstreamTlsSettings {
+            pinned_certificates: vec!["00".repeat(32)],
+            ..tls_settings
+        };
+        let result = FastCgi::do_fastcgi_request_and_response(&ip_and_port, &params, &request_body, &mut stderr_output, Some(&get_tls(&tls_settings))).await;
+        assert!(matches!(result, Err(FastCgiError::Connection(_))));
+        assert_eq!(server.get_received_requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fastcgi_login_response_keeps_every_set_cookie() {
+        let server = MockFastCgiServer::start(MockFastCgiResponse::ok(WORDPRESS_LOGIN_RESPONSE_HEADERS, b"")).await;
+        let params = HashMap::from([("REQUEST_METHOD".to_string(), "POST".to_string()), ("SCRIPT_FILENAME".to_string(), "/var/www/wp-login.php".to_string())]);
+
+        let (response, _) = send_to_mock_server(&server, &params, b"log=admin&pwd=secret").await.unwrap();
+        assert_eq!(response.get_status(), 302);
+        // The cookies are all sent on to the client
+        let hyper_response = response.into_hyper();
+        assert_eq!(hyper_response.headers().get_all(hyper::header::SET_COOKIE).iter().count(), 4);
+        assert_eq!(hyper_response.headers().get(hyper::header::LOCATION).unwrap(), "https://example.com/wp-admin/");
+    }
+
+    #[tokio::test]
+    async fn test_fastcgi_fragmented_records_with_stderr() {
+        let response = MockFastCgiResponse::new()
+            .stderr(b"PHP Notice: Undefined index")
+            .stdout(b"Content-Type: text/html\r\n\r\n<p>")
+            .stdout(b"fragmented</p>")
+            .end()
+            .fragmented(3);
+        let server = MockFastCgiServer::start(response).await;
+
+        let (mut response, stderr_output) = send_to_mock_server(&server, &HashMap::new(), b"").await.unwrap();
+        assert_eq!(response.get_status(), 200);
+        assert_eq!(response.get_body_bytes().await, Bytes::from("<p>fragmented</p>"));
+        assert_eq!(stderr_output, "PHP Notice: Undefined index");
+    }
+
+    #[tokio::test]
+    async fn test_fastcgi_bodies_larger_than_a_record() {
+        let response_body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let request_body: Vec<u8> = (0..150_000u32).map(|i| (i % 241) as u8).collect();
+        let server = MockFastCgiServer::start(MockFastCgiResponse::ok("Content-Type: application/octet-stream", &response_body)).await;
+
+        let (mut response, _) = send_to_mock_server(&server, &HashMap::new(), &request_body).await.unwrap();
+        assert_eq!(response.get_body_bytes().await.to_vec(), response_body);
+        assert_eq!(server.get_received_requests()[0].stdin, request_body);
+    }
+
+    #[tokio::test]
+    async fn test_fastcgi_slow_response_times_out() {
+        let response = MockFastCgiResponse::new()
+            .stdout(b"Content-Type: text/plain\r\n\r\n")
+            .delay(Duration::from_secs(5))
+            .stdout(b"late")
+            .end();
+        let server = MockFastCgiServer::start(response).await;
+
+        let timeouts = OutboundTimeouts {
+            connect: Duration::from_secs(1),
+            read: Duration::from_millis(200),
+            write: Duration::from_secs(1),
+        };
+        let stream = tokio::net::TcpStream::connect(server.get_ip_and_port()).await.unwrap();
+        let mut stream = TimeoutStream::new(stream, timeouts);
+        let mut stderr_output = String::new();
+        let result = FastCgi::exchange_fastcgi_request(&mut stream, &HashMap::new(), &SpooledBody::Memory(Bytes::new()), &mut stderr_output).await;
+        assert!(matches!(result, Err(FastCgiError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_fastcgi_closed_connection_is_invalid_response() {
+        let server = MockFastCgiServer::start(MockFastCgiResponse::new().close()).await;
+        let result = send_to_mock_server(&server, &HashMap::new(), b"").await;
+        assert!(matches!(result, Err(FastCgiError::InvalidResponse)));
+    }
+
+    #[tokio::test]
+    async fn test_process_fastcgi_request_keeps_stderr_with_request() {
+        let response = MockFastCgiResponse::new().stderr(b"PHP Warning: something").stdout(b"Status: 500 Internal Server Error\r\n\r\n").end();
+        let server = MockFastCgiServer::start(response).await;
+
+        let request = hyper::Request::builder().method("GET").uri("/index.php?page=2").header("Host", "localhost").body(Bytes::new()).unwrap();
+        let mut gruxi_request = GruxiRequest::new(request);
+        gruxi_request.add_calculated_data("fastcgi_script_file", "/var/www/public/index.php");
+        gruxi_request.add_calculated_data("fastcgi_local_web_root", "/var/www/public");
+        gruxi_request.add_calculated_data("fastcgi_connect_ip_and_port", &server.get_ip_and_port());
+
+        let response = FastCgi::process_fastcgi_request(&mut gruxi_request, &SpooledBody::Memory(Bytes::new()), None).await.unwrap();
+        assert_eq!(response.get_status(), 500);
+        assert_eq!(gruxi_request.get_calculated_data(FASTCGI_STDERR_KEY).unwrap(), "PHP Warning: something");
+
+        let params = &server.get_received_requests()[0].params;
+        assert_eq!(params.get("SCRIPT_FILENAME").unwrap(), "/var/www/public/index.php");
+        assert_eq!(params.get("QUERY_STRING").unwrap(), "page=2");
+        assert_eq!(params.get("CONTENT_LENGTH").unwrap(), "0");
     }
 }
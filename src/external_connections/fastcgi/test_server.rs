@@ -0,0 +1,254 @@
+// A scriptable FastCGI server for tests, so the FastCGI client and the PHP handler can be tested without a real php-cgi.
+// Each connection gets the scripted response, sent as records in the order of the steps, after the full request is read.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+const FCGI_BEGIN_REQUEST: u8 = 1;
+const FCGI_END_REQUEST: u8 = 3;
+const FCGI_PARAMS: u8 = 4;
+const FCGI_STDIN: u8 = 5;
+const FCGI_STDOUT: u8 = 6;
+const FCGI_STDERR: u8 = 7;
+const MAX_RECORD_CONTENT_LENGTH: usize = 65535;
+
+#[derive(Clone, Debug)]
+enum MockStep {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Delay(Duration),
+    End,
+    Close,
+}
+
+// The response a MockFastCgiServer sends, built from steps like MockFastCgiResponse::new().stdout(..).end()
+#[derive(Clone, Debug, Default)]
+pub struct MockFastCgiResponse {
+    steps: Vec<MockStep>,
+    // Writes the records in chunks of this many bytes, so records arrive split over several reads
+    fragment_size: Option<usize>,
+}
+
+impl MockFastCgiResponse {
+    pub fn new() -> Self {
+        MockFastCgiResponse::default()
+    }
+
+    // A response with the headers and body as PHP would send it, ended properly
+    pub fn ok(headers: &str, body: &[u8]) -> Self {
+        let mut output = headers.replace('\n', "\r\n").into_bytes();
+        output.extend_from_slice(b"\r\n\r\n");
+        output.extend_from_slice(body);
+        MockFastCgiResponse::new().stdout(&output).end()
+    }
+
+    // Output, split into as many STDOUT records as needed
+    pub fn stdout(mut self, data: &[u8]) -> Self {
+        self.steps.push(MockStep::Stdout(data.to_vec()));
+        self
+    }
+
+    pub fn stderr(mut self, data: &[u8]) -> Self {
+        self.steps.push(MockStep::Stderr(data.to_vec()));
+        self
+    }
+
+    // Waits before sending the next step, such as for a slow script
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.steps.push(MockStep::Delay(delay));
+        self
+    }
+
+    // The empty STDOUT record and the END_REQUEST record
+    pub fn end(mut self) -> Self {
+        self.steps.push(MockStep::End);
+        self
+    }
+
+    // Closes the connection, such as a crashing php-cgi
+    pub fn close(mut self) -> Self {
+        self.steps.push(MockStep::Close);
+        self
+    }
+
+    pub fn fragmented(mut self, fragment_size: usize) -> Self {
+        self.fragment_size = Some(fragment_size.max(1));
+        self
+    }
+}
+
+// A request as received by the MockFastCgiServer
+#[derive(Clone, Debug, Default)]
+pub struct ReceivedFastCgiRequest {
+    pub params: HashMap<String, String>,
+    pub stdin: Vec<u8>,
+}
+
+pub struct MockFastCgiServer {
+    address: SocketAddr,
+    received_requests: Arc<Mutex<Vec<ReceivedFastCgiRequest>>>,
+    accept_task: JoinHandle<()>,
+}
+
+impl MockFastCgiServer {
+    // Listens on a free port on 127.0.0.1 and answers every connection with the response
+    pub async fn start(response: MockFastCgiResponse) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind mock FastCGI server");
+        let address = listener.local_addr().expect("Failed to get address of mock FastCGI server");
+        let received_requests = Arc::new(Mutex::new(Vec::new()));
+
+        let requests = received_requests.clone();
+        let accept_task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let response = response.clone();
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let _ = handle_connection(stream, response, requests).await;
+                });
+            }
+        });
+
+        MockFastCgiServer {
+            address,
+            received_requests,
+            accept_task,
+        }
+    }
+
+    // The "ip:port" to give the FastCGI client
+    pub fn get_ip_and_port(&self) -> String {
+        self.address.to_string()
+    }
+
+    pub fn get_received_requests(&self) -> Vec<ReceivedFastCgiRequest> {
+        self.received_requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockFastCgiServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, response: MockFastCgiResponse, requests: Arc<Mutex<Vec<ReceivedFastCgiRequest>>>) -> std::io::Result<()> {
+    let (request_id, request) = read_request(&mut stream).await?;
+    requests.lock().unwrap().push(request);
+
+    for step in response.steps {
+        let bytes = match step {
+            MockStep::Stdout(data) => create_stream_records(FCGI_STDOUT, request_id, &data),
+            MockStep::Stderr(data) => create_stream_records(FCGI_STDERR, request_id, &data),
+            MockStep::Delay(delay) => {
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            MockStep::End => {
+                let mut bytes = create_record(FCGI_STDOUT, request_id, &[]);
+                bytes.extend(create_record(FCGI_END_REQUEST, request_id, &[0u8; 8]));
+                bytes
+            }
+            MockStep::Close => return stream.shutdown().await,
+        };
+
+        match response.fragment_size {
+            Some(fragment_size) => {
+                for fragment in bytes.chunks(fragment_size) {
+                    stream.write_all(fragment).await?;
+                    stream.flush().await?;
+                    tokio::task::yield_now().await;
+                }
+            }
+            None => stream.write_all(&bytes).await?,
+        }
+    }
+    stream.flush().await
+}
+
+// Reads the records of a request until the empty STDIN record that ends it
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<(u16, ReceivedFastCgiRequest)> {
+    let mut request = ReceivedFastCgiRequest::default();
+    let mut params_data = Vec::new();
+    let mut request_id = 1;
+
+    loop {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).await?;
+        let record_type = header[1];
+        let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let padding_length = header[6] as usize;
+        let mut content = vec![0u8; content_length + padding_length];
+        stream.read_exact(&mut content).await?;
+        content.truncate(content_length);
+
+        match record_type {
+            FCGI_BEGIN_REQUEST => request_id = u16::from_be_bytes([header[2], header[3]]),
+            FCGI_PARAMS => params_data.extend_from_slice(&content),
+            FCGI_STDIN if content.is_empty() => break,
+            FCGI_STDIN => request.stdin.extend_from_slice(&content),
+            _ => {}
+        }
+    }
+
+    request.params = parse_name_value_pairs(&params_data);
+    Ok((request_id, request))
+}
+
+fn parse_name_value_pairs(data: &[u8]) -> HashMap<String, String> {
+    let mut pairs = HashMap::new();
+    let mut i = 0;
+    while i < data.len() {
+        let Some((name_length, value_length, header_length)) = read_lengths(&data[i..]) else {
+            break;
+        };
+        i += header_length;
+        if i + name_length + value_length > data.len() {
+            break;
+        }
+        let name = String::from_utf8_lossy(&data[i..i + name_length]).to_string();
+        let value = String::from_utf8_lossy(&data[i + name_length..i + name_length + value_length]).to_string();
+        pairs.insert(name, value);
+        i += name_length + value_length;
+    }
+    pairs
+}
+
+// Lengths below 128 take one byte, longer ones four bytes with the high bit set
+fn read_lengths(data: &[u8]) -> Option<(usize, usize, usize)> {
+    let mut lengths = [0usize; 2];
+    let mut offset = 0;
+    for length in lengths.iter_mut() {
+        let first = *data.get(offset)?;
+        if first < 128 {
+            *length = first as usize;
+            offset += 1;
+        } else {
+            let bytes = data.get(offset..offset + 4)?;
+            *length = (u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) & 0x7fff_ffff) as usize;
+            offset += 4;
+        }
+    }
+    Some((lengths[0], lengths[1], offset))
+}
+
+fn create_stream_records(record_type: u8, request_id: u16, data: &[u8]) -> Vec<u8> {
+    data.chunks(MAX_RECORD_CONTENT_LENGTH).flat_map(|chunk| create_record(record_type, request_id, chunk)).collect()
+}
+
+// Content is padded to a multiple of 8 bytes, as recommended by the specification, so the client has to skip padding
+fn create_record(record_type: u8, request_id: u16, content: &[u8]) -> Vec<u8> {
+    let padding_length = (8 - content.len() % 8) % 8;
+    let mut record = vec![1, record_type];
+    record.extend(&request_id.to_be_bytes());
+    record.extend(&(content.len() as u16).to_be_bytes());
+    record.push(padding_length as u8);
+    record.push(0);
+    record.extend_from_slice(content);
+    record.extend(std::iter::repeat_n(0u8, padding_length));
+    record
+}
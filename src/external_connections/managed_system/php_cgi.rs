@@ -4,15 +4,17 @@ use tokio::{
     process::{Child, Command},
     select,
 };
+use utoipa::ToSchema;
 
 use crate::{
-    core::triggers::get_trigger_handler,
+    configuration::site::{EnvironmentVariable, validate_environment_variables},
+    core::triggers::{GruxiEvent, get_trigger_handler},
     external_connections::fastcgi::FastCgi,
     logging::syslog::{error, trace, warn},
     network::port_manager::{PortManager, get_port_manager},
 };
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PhpCgi {
     // Unique identifier for the external system
     pub id: String,
@@ -21,6 +23,9 @@ pub struct PhpCgi {
     pub request_timeout: u32,
     pub concurrent_threads: u32,
     pub executable: String,
+    // Environment variables for the PHP-CGI process, which the sites using it can add to and override per request
+    #[serde(default)]
+    pub extra_environment: Vec<EnvironmentVariable>,
 
     // Internal state
     #[serde(skip)]
@@ -46,6 +51,7 @@ impl PhpCgi {
             request_timeout,
             concurrent_threads,
             executable,
+            extra_environment: Vec::new(),
             process: None,
             restart_count: 0,
             assigned_port: None,
@@ -60,6 +66,10 @@ impl PhpCgi {
 
         // Clean up name
         self.name = self.name.trim().to_string();
+
+        for variable in &mut self.extra_environment {
+            variable.name = variable.name.trim().to_string();
+        }
     }
 
     pub fn validate(&self) -> Result<(), Vec<String>> {
@@ -90,6 +100,8 @@ impl PhpCgi {
             errors.push(format!("PHP-CGI executable not found at path: {}", self.executable));
         }
 
+        errors.extend(validate_environment_variables(&self.extra_environment, "PHP-CGI environment"));
+
         if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 
@@ -114,7 +126,8 @@ impl PhpCgi {
 
         // Allocate a port if we don't have one
         if self.assigned_port.is_none() {
-            self.assigned_port = self.port_manager.allocate_port("php-main-process".to_string()).await;
+            // By the id of the handler, so it keeps its port when restarted, such as on a configuration reload
+            self.assigned_port = self.port_manager.allocate_port(format!("php-cgi-{}", self.id)).await;
             if self.assigned_port.is_none() {
                 return Err("Failed to allocate port for PHP-CGI process".to_string());
             }
@@ -135,6 +148,7 @@ impl PhpCgi {
         // Set environment variable for FastCGI children
         cmd.env("PHP_FCGI_CHILDREN", self.get_max_children_processes().to_string());
         cmd.env("PHP_FCGI_MAX_REQUESTS", "10000"); // Request limit before restart the child process
+        cmd.envs(self.extra_environment.iter().map(|variable| (&variable.name, &variable.value)));
 
         match cmd.spawn() {
             Ok(child) => {
@@ -243,6 +257,7 @@ impl PhpCgi {
             // Wait a bit before restarting to avoid rapid restart loops
             tokio::time::sleep(Duration::from_millis(1000)).await;
             self.start().await?;
+            self.publish_restarted("The process had exited").await;
         } else {
             // Check if we need to send a keep-alive
             let time_since_activity = self.last_activity.elapsed();
@@ -252,12 +267,22 @@ impl PhpCgi {
                     self.stop().await;
                     tokio::time::sleep(Duration::from_millis(1000)).await;
                     self.start().await?;
+                    self.publish_restarted("The process did not answer the keep-alive request").await;
                 }
             }
         }
         Ok(())
     }
 
+    async fn publish_restarted(&self, reason: &str) {
+        get_trigger_handler()
+            .publish(GruxiEvent::HandlerRestarted {
+                handler_id: self.id.clone(),
+                reason: reason.to_string(),
+            })
+            .await;
+    }
+
     pub async fn stop(&mut self) {
         if let Some(mut process) = self.process.take() {
             trace("Stopping PHP-CGI process".to_string());
@@ -0,0 +1,497 @@
+use crate::logging::syslog::{debug, trace, warn};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use hyper::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use utoipa::ToSchema;
+
+// Directory the disk caches keep their files in, each in a directory named by the cache, such as ./cache/proxy/<processor id>
+pub const DISK_CACHE_DIRECTORY: &str = "./cache";
+
+// How often the disk caches are brought within the limits of the server settings
+const DISK_CACHE_EVICTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+static DISK_CACHE_MANAGER: OnceLock<DiskCacheManager> = OnceLock::new();
+
+pub fn get_disk_cache_manager() -> &'static DiskCacheManager {
+    DISK_CACHE_MANAGER.get_or_init(DiskCacheManager::new)
+}
+
+// What to remove of the cached entries of a site. Entries are keyed by their absolute URL, such as https://example.com/a?b=c
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiskCachePurge {
+    All,
+    PathPrefix(String),
+    Url(String),
+}
+
+impl DiskCachePurge {
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            DiskCachePurge::All => true,
+            DiskCachePurge::PathPrefix(path_prefix) => get_url_path(key).starts_with(path_prefix.as_str()),
+            DiskCachePurge::Url(url) => key == url,
+        }
+    }
+}
+
+// The path and query of an absolute URL, or the key itself if it is not one
+fn get_url_path(key: &str) -> &str {
+    match key.split_once("://") {
+        Some((_, rest)) => rest.find('/').map(|index| &rest[index..]).unwrap_or("/"),
+        None => key,
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct DiskCacheStatistics {
+    pub name: String,
+    pub entries: usize,
+    pub size_bytes: u64,
+    // The limit of the cache itself, 0 for none
+    pub max_size_bytes: u64,
+}
+
+// The first line of every cache file, telling what the rest of the file is, so the index can be read from the files on start
+#[derive(Serialize, Deserialize)]
+struct DiskCacheFileHead {
+    key: String,
+    site_id: String,
+}
+
+struct DiskCacheIndexEntry {
+    file_name: String,
+    site_id: String,
+    size: u64,
+    // Unix timestamp in milliseconds, for evicting the least recently used entries first
+    last_used: i64,
+}
+
+#[derive(Default)]
+struct DiskCacheIndex {
+    entries: HashMap<String, DiskCacheIndexEntry>,
+    total_size: u64,
+}
+
+// Entries of one cache kept on disk, in files named by the hash of their key. The index of them is kept in memory, read from the
+// files on first use, so the cache survives restarts
+pub struct DiskCache {
+    name: String,
+    directory: PathBuf,
+    max_size_bytes: AtomicU64,
+    index: Mutex<DiskCacheIndex>,
+    is_loaded: tokio::sync::OnceCell<()>,
+}
+
+impl DiskCache {
+    pub fn new(name: &str, directory: PathBuf) -> Self {
+        DiskCache {
+            name: name.to_string(),
+            directory,
+            max_size_bytes: AtomicU64::new(0),
+            index: Mutex::new(DiskCacheIndex::default()),
+            is_loaded: tokio::sync::OnceCell::new(),
+        }
+    }
+
+    // The most this cache may use on its own, 0 for no limit other than the one of all disk caches
+    pub fn set_max_size_bytes(&self, max_size_bytes: u64) {
+        self.max_size_bytes.store(max_size_bytes, Ordering::Relaxed);
+    }
+
+    async fn ensure_loaded(&self) {
+        self.is_loaded
+            .get_or_init(|| async {
+                let directory = self.directory.clone();
+                let entries = tokio::task::spawn_blocking(move || read_cache_directory(&directory)).await.unwrap_or_default();
+                let mut index = self.index.lock().unwrap();
+                for (key, entry) in entries {
+                    index.total_size += entry.size;
+                    index.entries.insert(key, entry);
+                }
+                debug(format!("Loaded {} entries of disk cache {}", index.entries.len(), self.name));
+            })
+            .await;
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Bytes> {
+        self.ensure_loaded().await;
+        let file_name = {
+            let mut index = self.index.lock().unwrap();
+            let entry = index.entries.get_mut(key)?;
+            entry.last_used = Utc::now().timestamp_millis();
+            entry.file_name.clone()
+        };
+
+        let content = tokio::fs::read(self.directory.join(&file_name)).await.ok();
+        let body = content.and_then(|content| parse_cache_file(key, content));
+        if body.is_none() {
+            // Removed or damaged on disk, so it is forgotten
+            self.remove(key).await;
+        }
+        body
+    }
+
+    pub async fn store(&self, key: &str, site_id: &str, content: &[u8]) {
+        self.ensure_loaded().await;
+        let Ok(head) = serde_json::to_vec(&DiskCacheFileHead {
+            key: key.to_string(),
+            site_id: site_id.to_string(),
+        }) else {
+            return;
+        };
+        let file_name = get_cache_file_name(key);
+        let mut file_content = Vec::with_capacity(head.len() + 1 + content.len());
+        file_content.extend_from_slice(&head);
+        file_content.push(b'\n');
+        file_content.extend_from_slice(content);
+
+        // Written to a temporary file first, so a reader never sees half an entry
+        let temporary_path = self.directory.join(format!("{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+        let written = async {
+            tokio::fs::create_dir_all(&self.directory).await?;
+            tokio::fs::write(&temporary_path, &file_content).await?;
+            tokio::fs::rename(&temporary_path, self.directory.join(&file_name)).await
+        }
+        .await;
+        if let Err(e) = written {
+            warn(format!("Failed to write to disk cache {}: {}", self.name, e));
+            let _ = tokio::fs::remove_file(&temporary_path).await;
+            return;
+        }
+        trace(format!("Stored {} in disk cache {}", key, self.name));
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            let entry = DiskCacheIndexEntry {
+                file_name,
+                site_id: site_id.to_string(),
+                size: file_content.len() as u64,
+                last_used: Utc::now().timestamp_millis(),
+            };
+            index.total_size += entry.size;
+            if let Some(previous) = index.entries.insert(key.to_string(), entry) {
+                index.total_size = index.total_size.saturating_sub(previous.size);
+            }
+            evict_least_recently_used(&mut index, self.max_size_bytes.load(Ordering::Relaxed))
+        };
+        self.delete_files(evicted).await;
+    }
+
+    pub async fn remove(&self, key: &str) {
+        let removed = {
+            let mut index = self.index.lock().unwrap();
+            let removed = index.entries.remove(key);
+            if let Some(entry) = &removed {
+                index.total_size = index.total_size.saturating_sub(entry.size);
+            }
+            removed
+        };
+        if let Some(entry) = removed {
+            let _ = tokio::fs::remove_file(self.directory.join(entry.file_name)).await;
+        }
+    }
+
+    // Removes the entries of the site matching the purge, returning how many there were
+    pub async fn purge(&self, site_id: &str, purge: &DiskCachePurge) -> usize {
+        self.ensure_loaded().await;
+        let removed = self.remove_entries(|key, entry| entry.site_id == site_id && purge.matches(key));
+        let count = removed.len();
+        self.delete_files(removed).await;
+        count
+    }
+
+    pub fn get_statistics(&self) -> DiskCacheStatistics {
+        let index = self.index.lock().unwrap();
+        DiskCacheStatistics {
+            name: self.name.clone(),
+            entries: index.entries.len(),
+            size_bytes: index.total_size,
+            max_size_bytes: self.max_size_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    // Removes the entries matching from the index, returning their files to delete
+    fn remove_entries(&self, matches: impl Fn(&str, &DiskCacheIndexEntry) -> bool) -> Vec<String> {
+        let mut index = self.index.lock().unwrap();
+        let keys: Vec<String> = index.entries.iter().filter(|(key, entry)| matches(key, entry)).map(|(key, _)| key.clone()).collect();
+        let mut removed = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(entry) = index.entries.remove(&key) {
+                index.total_size = index.total_size.saturating_sub(entry.size);
+                removed.push(entry.file_name);
+            }
+        }
+        removed
+    }
+
+    async fn delete_files(&self, file_names: Vec<String>) {
+        for file_name in file_names {
+            let _ = tokio::fs::remove_file(self.directory.join(file_name)).await;
+        }
+    }
+}
+
+// Keeps track of the disk caches, such as those of the proxy processors, and keeps all of them within the total size and the
+// time unused of the server settings
+pub struct DiskCacheManager {
+    caches: DashMap<String, Arc<DiskCache>>,
+    max_total_size_bytes: AtomicU64,
+    max_unused_seconds: AtomicU64,
+}
+
+impl DiskCacheManager {
+    fn new() -> Self {
+        DiskCacheManager {
+            caches: DashMap::new(),
+            max_total_size_bytes: AtomicU64::new(0),
+            max_unused_seconds: AtomicU64::new(0),
+        }
+    }
+
+    // The cache by its name, which is also its directory below DISK_CACHE_DIRECTORY, such as "proxy/<processor id>"
+    pub fn get_cache(&self, name: &str) -> Arc<DiskCache> {
+        self.caches
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(DiskCache::new(name, Path::new(DISK_CACHE_DIRECTORY).join(name))))
+            .clone()
+    }
+
+    // 0 for no limit on the total size or on how long entries may go unused
+    pub fn set_limits(&self, max_total_size_bytes: u64, max_unused_hours: u64) {
+        self.max_total_size_bytes.store(max_total_size_bytes, Ordering::Relaxed);
+        self.max_unused_seconds.store(max_unused_hours * 60 * 60, Ordering::Relaxed);
+    }
+
+    pub async fn purge(&self, site_id: &str, purge: &DiskCachePurge) -> usize {
+        let mut count = 0;
+        for cache in self.get_all_caches().await {
+            count += cache.purge(site_id, purge).await;
+        }
+        count
+    }
+
+    pub async fn get_statistics(&self) -> Vec<DiskCacheStatistics> {
+        let mut statistics = Vec::new();
+        for cache in self.get_all_caches().await {
+            cache.ensure_loaded().await;
+            statistics.push(cache.get_statistics());
+        }
+        statistics.sort_by(|a, b| a.name.cmp(&b.name));
+        statistics
+    }
+
+    // Removes entries unused for too long, and then the least recently used entries of all caches until they fit in the total size.
+    // Returns how many entries were removed
+    pub async fn enforce_limits(&self) -> usize {
+        let caches = self.get_all_caches().await;
+        let max_unused_seconds = self.max_unused_seconds.load(Ordering::Relaxed);
+        let max_total_size_bytes = self.max_total_size_bytes.load(Ordering::Relaxed);
+        let mut count = 0;
+
+        if max_unused_seconds > 0 {
+            let unused_before = Utc::now().timestamp_millis() - (max_unused_seconds as i64) * 1000;
+            for cache in &caches {
+                cache.ensure_loaded().await;
+                let removed = cache.remove_entries(|_, entry| entry.last_used < unused_before);
+                count += removed.len();
+                cache.delete_files(removed).await;
+            }
+        }
+
+        if max_total_size_bytes > 0 {
+            let mut total_size = 0;
+            let mut entries: Vec<(i64, usize, String)> = Vec::new();
+            for (cache_index, cache) in caches.iter().enumerate() {
+                cache.ensure_loaded().await;
+                let index = cache.index.lock().unwrap();
+                total_size += index.total_size;
+                entries.extend(index.entries.iter().map(|(key, entry)| (entry.last_used, cache_index, key.clone())));
+            }
+            if total_size > max_total_size_bytes {
+                entries.sort();
+                for (_, cache_index, key) in entries {
+                    if total_size <= max_total_size_bytes {
+                        break;
+                    }
+                    let cache = &caches[cache_index];
+                    let size = cache.index.lock().unwrap().entries.get(&key).map(|entry| entry.size).unwrap_or(0);
+                    cache.remove(&key).await;
+                    total_size = total_size.saturating_sub(size);
+                    count += 1;
+                }
+            }
+        }
+
+        if count > 0 {
+            debug(format!("Removed {} entries from the disk caches", count));
+        }
+        count
+    }
+
+    // The caches in use and those with files on disk from before, such as of processors not used since the start
+    async fn get_all_caches(&self) -> Vec<Arc<DiskCache>> {
+        let names = tokio::task::spawn_blocking(|| find_cache_names(Path::new(DISK_CACHE_DIRECTORY))).await.unwrap_or_default();
+        for name in names {
+            self.get_cache(&name);
+        }
+        self.caches.iter().map(|cache| cache.value().clone()).collect()
+    }
+}
+
+// Keeps the disk caches within the limits of the server settings, which are set on every start and reload of the running state
+pub fn start_disk_cache_eviction_task() {
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(DISK_CACHE_EVICTION_INTERVAL).await;
+            get_disk_cache_manager().enforce_limits().await;
+        }
+    });
+}
+
+// Removes the least recently used entries from the index until the cache fits in the max size, returning their files to delete
+fn evict_least_recently_used(index: &mut DiskCacheIndex, max_size_bytes: u64) -> Vec<String> {
+    let mut evicted = Vec::new();
+    if max_size_bytes == 0 {
+        return evicted;
+    }
+    while index.total_size > max_size_bytes {
+        let Some(key) = index.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone()) else {
+            break;
+        };
+        if let Some(entry) = index.entries.remove(&key) {
+            index.total_size = index.total_size.saturating_sub(entry.size);
+            evicted.push(entry.file_name);
+        }
+    }
+    evicted
+}
+
+fn get_cache_file_name(key: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, key.as_bytes());
+    let hash: String = digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}.cache", hash)
+}
+
+// The content stored for the key, after the head line of the file
+fn parse_cache_file(key: &str, content: Vec<u8>) -> Option<Bytes> {
+    let head_length = content.iter().position(|byte| *byte == b'\n')?;
+    let head: DiskCacheFileHead = serde_json::from_slice(&content[..head_length]).ok()?;
+    if head.key != key {
+        return None;
+    }
+    Some(Bytes::from(content).slice(head_length + 1..))
+}
+
+// The names of the caches with a directory on disk, which are two levels deep, such as "proxy/<processor id>"
+fn find_cache_names(directory: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+    let Ok(kinds) = std::fs::read_dir(directory) else {
+        return names;
+    };
+    for kind in kinds.flatten().filter(|entry| entry.path().is_dir()) {
+        let Ok(caches) = std::fs::read_dir(kind.path()) else {
+            continue;
+        };
+        for cache in caches.flatten().filter(|entry| entry.path().is_dir()) {
+            names.push(format!("{}/{}", kind.file_name().to_string_lossy(), cache.file_name().to_string_lossy()));
+        }
+    }
+    names
+}
+
+// The entries in the directory, by their key, read from the first line of each file. Files of writes that never finished are removed
+fn read_cache_directory(directory: &Path) -> Vec<(String, DiskCacheIndexEntry)> {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut index_entries = Vec::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name.ends_with(".tmp") {
+            let _ = std::fs::remove_file(entry.path());
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let Ok(file) = std::fs::File::open(entry.path()) else {
+            continue;
+        };
+        let mut head_line = String::new();
+        if std::io::BufReader::new(file).read_line(&mut head_line).is_err() {
+            continue;
+        }
+        let Ok(head) = serde_json::from_str::<DiskCacheFileHead>(head_line.trim_end()) else {
+            continue;
+        };
+        let last_used = metadata.modified().map(|modified| DateTime::<Utc>::from(modified).timestamp_millis()).unwrap_or(0);
+        index_entries.push((
+            head.key,
+            DiskCacheIndexEntry {
+                file_name,
+                site_id: head.site_id,
+                size: metadata.len(),
+                last_used,
+            },
+        ));
+    }
+    index_entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_cache() -> (DiskCache, PathBuf) {
+        let directory = std::env::temp_dir().join(format!("gruxi-test-disk-cache-{}", uuid::Uuid::new_v4()));
+        (DiskCache::new("test", directory.clone()), directory)
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_entries_are_evicted() {
+        let (cache, directory) = create_test_cache();
+        cache.set_max_size_bytes(1500);
+        for key in ["http://example.com/a", "http://example.com/b"] {
+            cache.store(key, "site", &[b'x'; 500]).await;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // Using /a makes /b the least recently used, which has to go for /c
+        assert_eq!(cache.get("http://example.com/a").await.unwrap().len(), 500);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        cache.store("http://example.com/c", "site", &[b'x'; 500]).await;
+        assert!(cache.get("http://example.com/a").await.is_some());
+        assert!(cache.get("http://example.com/b").await.is_none());
+        assert!(cache.get("http://example.com/c").await.is_some());
+
+        // The index is read again from the files
+        let reloaded_cache = DiskCache::new("test", directory.clone());
+        assert_eq!(reloaded_cache.get("http://example.com/c").await.unwrap(), Bytes::from(vec![b'x'; 500]));
+        assert_eq!(reloaded_cache.get_statistics().entries, 2);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[tokio::test]
+    async fn test_purge() {
+        let (cache, directory) = create_test_cache();
+        for key in ["https://example.com/", "https://example.com/blog/a", "https://example.com/blog/b?page=2", "https://example.com/shop"] {
+            cache.store(key, "site", b"content").await;
+        }
+        cache.store("https://other.example.com/blog/a", "other-site", b"content").await;
+
+        assert_eq!(cache.purge("site", &DiskCachePurge::Url("https://example.com/shop".to_string())).await, 1);
+        assert_eq!(cache.purge("site", &DiskCachePurge::PathPrefix("/blog/".to_string())).await, 2);
+        assert!(cache.get("https://example.com/").await.is_some());
+        assert_eq!(cache.purge("site", &DiskCachePurge::All).await, 1);
+        assert!(cache.get("https://other.example.com/blog/a").await.is_some());
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
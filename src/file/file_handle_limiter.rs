@@ -0,0 +1,218 @@
+use crate::logging::syslog::trace;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+// How long a request waits for a file handle before it is answered with 503
+pub const FILE_HANDLE_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct SiteFileHandleStats {
+    open: usize,
+    waiting: usize,
+    waited_total: usize,
+    timeouts_total: usize,
+}
+
+#[derive(Default)]
+struct LimiterState {
+    open: usize,
+    // Waiting requests per site, and the order in which sites get the next free handle
+    waiting: HashMap<String, VecDeque<oneshot::Sender<FileHandlePermit>>>,
+    site_turns: VecDeque<String>,
+    sites: HashMap<String, SiteFileHandleStats>,
+}
+
+// Caps the file handles kept open concurrently for serving static files, so many large downloads cannot run the process out of
+// handles. When the cap is reached, requests wait for a handle in per-site queues that take turns, so one busy site cannot
+// starve the others, and are answered with 503 if they wait longer than FILE_HANDLE_WAIT_TIMEOUT
+#[derive(Default)]
+pub struct FileHandleLimiter {
+    max_open: AtomicUsize,
+    state: Mutex<LimiterState>,
+}
+
+// A file handle slot, given back to the limiter when dropped
+pub struct FileHandlePermit {
+    limiter: &'static FileHandleLimiter,
+    site_id: String,
+    is_active: bool,
+}
+
+impl Drop for FileHandlePermit {
+    fn drop(&mut self) {
+        if self.is_active {
+            self.limiter.release(&self.site_id);
+        }
+    }
+}
+
+static FILE_HANDLE_LIMITER_SINGLETON: OnceLock<FileHandleLimiter> = OnceLock::new();
+
+pub fn get_file_handle_limiter() -> &'static FileHandleLimiter {
+    FILE_HANDLE_LIMITER_SINGLETON.get_or_init(FileHandleLimiter::default)
+}
+
+impl FileHandleLimiter {
+    // Set from the configuration when the running state is built. Lowering it lets the handles already open run to completion
+    pub fn set_max_open_file_handles(&self, max_open: usize) {
+        self.max_open.store(max_open, Ordering::Relaxed);
+    }
+
+    // Gets a file handle slot for the site, waiting in the site's queue if all are in use. None if the wait timed out
+    pub async fn acquire(&'static self, site_id: &str) -> Option<FileHandlePermit> {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.open < self.max_open.load(Ordering::Relaxed) && state.site_turns.is_empty() {
+                state.open += 1;
+                state.sites.entry(site_id.to_string()).or_default().open += 1;
+                return Some(FileHandlePermit {
+                    limiter: self,
+                    site_id: site_id.to_string(),
+                    is_active: true,
+                });
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            state.waiting.entry(site_id.to_string()).or_default().push_back(sender);
+            if !state.site_turns.iter().any(|s| s == site_id) {
+                state.site_turns.push_back(site_id.to_string());
+            }
+            let site_stats = state.sites.entry(site_id.to_string()).or_default();
+            site_stats.waiting += 1;
+            site_stats.waited_total += 1;
+            receiver
+        };
+
+        trace(format!("All file handles are in use, request for site '{}' is waiting for one", site_id));
+        let result = tokio::time::timeout(FILE_HANDLE_WAIT_TIMEOUT, receiver).await;
+
+        let mut state = self.state.lock().unwrap();
+        let site_stats = state.sites.entry(site_id.to_string()).or_default();
+        site_stats.waiting = site_stats.waiting.saturating_sub(1);
+        match result {
+            Ok(Ok(permit)) => Some(permit),
+            _ => {
+                site_stats.timeouts_total += 1;
+                None
+            }
+        }
+    }
+
+    // Hands the slot to the first waiting request of the next site in turn, or frees it if nobody is waiting
+    fn release(&'static self, site_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(site_stats) = state.sites.get_mut(site_id) {
+            site_stats.open = site_stats.open.saturating_sub(1);
+        }
+
+        if state.open <= self.max_open.load(Ordering::Relaxed) {
+            while let Some(next_site_id) = state.site_turns.pop_front() {
+                let sender = state.waiting.get_mut(&next_site_id).and_then(|queue| queue.pop_front());
+                if state.waiting.get(&next_site_id).is_some_and(|queue| !queue.is_empty()) {
+                    state.site_turns.push_back(next_site_id.clone());
+                } else {
+                    state.waiting.remove(&next_site_id);
+                }
+                let Some(sender) = sender else { continue };
+
+                // The waiting request may have timed out, in which case the permit comes back and we try the next one
+                let permit = FileHandlePermit {
+                    limiter: self,
+                    site_id: next_site_id.clone(),
+                    is_active: true,
+                };
+                match sender.send(permit) {
+                    Ok(_) => {
+                        state.sites.entry(next_site_id).or_default().open += 1;
+                        return;
+                    }
+                    Err(mut permit) => permit.is_active = false,
+                }
+            }
+        }
+        state.open = state.open.saturating_sub(1);
+    }
+
+    pub fn get_json(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let sites: serde_json::Map<String, serde_json::Value> = state
+            .sites
+            .iter()
+            .map(|(site_id, stats)| {
+                (
+                    site_id.clone(),
+                    serde_json::json!({
+                        "open": stats.open,
+                        "waiting": stats.waiting,
+                        "waited_total": stats.waited_total,
+                        "timeouts_total": stats.timeouts_total,
+                    }),
+                )
+            })
+            .collect();
+        serde_json::json!({
+            "max_open": self.max_open.load(Ordering::Relaxed),
+            "open": state.open,
+            "waiting": state.sites.values().map(|stats| stats.waiting).sum::<usize>(),
+            "sites": sites,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_limiter(max_open: usize) -> &'static FileHandleLimiter {
+        let limiter: &'static FileHandleLimiter = Box::leak(Box::default());
+        limiter.set_max_open_file_handles(max_open);
+        limiter
+    }
+
+    #[tokio::test]
+    async fn test_permits_are_capped_and_handed_over() {
+        let limiter = new_limiter(1);
+        let permit = limiter.acquire("site-a").await.unwrap();
+
+        let waiting = tokio::spawn(limiter.acquire("site-b"));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(limiter.get_json()["waiting"], 1);
+
+        drop(permit);
+        let handed_over = waiting.await.unwrap().unwrap();
+        assert_eq!(handed_over.site_id, "site-b");
+        assert_eq!(limiter.get_json()["open"], 1);
+
+        drop(handed_over);
+        assert_eq!(limiter.get_json()["open"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_sites_take_turns() {
+        let limiter = new_limiter(1);
+        let permit = limiter.acquire("busy").await.unwrap();
+
+        // Two requests from the busy site queue before the quiet site, which still gets the second handle
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        for site_id in ["busy", "busy", "quiet"] {
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let permit = limiter.acquire(site_id).await.unwrap();
+                sender.send(site_id).unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                drop(permit);
+            });
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(permit);
+        let mut order = Vec::new();
+        for _ in 0..3 {
+            order.push(receiver.recv().await.unwrap());
+        }
+        assert_eq!(order, vec!["busy", "quiet", "busy"]);
+    }
+}
@@ -3,11 +3,16 @@ use std::{
     time::{Duration, SystemTime},
 };
 
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::file::uring_file_reader::{UringFileReader, get_uring_file_reader};
 use crate::{
     compression::compression::Compression,
     configuration::cached_configuration::get_cached_configuration,
-    core::triggers::get_trigger_handler,
-    file::file_reader_structs::*,
+    core::{memory_budget::get_memory_budget, operation_mode::get_operation_mode, triggers::get_trigger_handler},
+    file::{
+        file_handle_limiter::{FileHandlePermit, get_file_handle_limiter},
+        file_reader_structs::*,
+    },
     http::request_response::{
         body_error::{BodyError, box_err},
         gruxi_request::GruxiRequest,
@@ -43,6 +48,8 @@ impl FileReaderCache {
         let cleanup_thread_interval = file_data_config.cleanup_thread_interval;
         let forced_eviction_threshold = file_data_config.forced_eviction_threshold;
 
+        get_file_handle_limiter().set_max_open_file_handles(config.core.server_settings.max_open_file_handles);
+
         let compressible_content_types = &config.core.gzip.compressible_content_types;
         let gzip_enabled = &config.core.gzip.is_enabled;
 
@@ -82,10 +89,24 @@ impl FileReaderCache {
         self.cache.len() as u64
     }
 
+    // Bytes of file content held in the cache, raw and compressed
+    pub fn get_current_content_bytes(&self) -> u64 {
+        self.cache
+            .iter()
+            .map(|entry| {
+                let content = &entry.value().content;
+                content.raw.as_ref().map_or(0, |b| b.len()) as u64 + content.gzip.as_ref().map_or(0, |b| b.len()) as u64
+            })
+            .sum()
+    }
+
     // Get file data
     pub async fn get_file(&self, file_path: &str) -> Result<Arc<FileEntry>, std::io::Error> {
+        // Files are not cached in development mode, so changes are picked up right away
+        let is_caching_enabled = self.is_caching_enabled && get_operation_mode().uses_file_cache();
+
         // Check the cache first
-        if self.is_caching_enabled {
+        if is_caching_enabled {
             if let Some(cached_entry) = self.cache.get(file_path) {
                 trace(format!("File found in cache: {}", file_path));
                 return Ok(cached_entry.value().clone());
@@ -121,7 +142,8 @@ impl FileReaderCache {
         };
 
         // Pre-fetch content of file if caching is enabled
-        if self.is_caching_enabled && !is_directory && exists && length <= self.max_file_size {
+        // Content is not cached while the memory budget has no room for it, the file is then read from disk when served
+        if is_caching_enabled && !is_directory && exists && length <= self.max_file_size && get_memory_budget().has_room_for(length) {
             match std::fs::read(file_path) {
                 Ok(file_bytes) => {
                     let raw_bytes = Arc::new(Bytes::from(file_bytes));
@@ -166,7 +188,7 @@ impl FileReaderCache {
         let file_entry_arc = Arc::new(file_entry);
 
         // Add to cache if enabled
-        if self.is_caching_enabled {
+        if is_caching_enabled {
             // Add to cache and update last checked
             trace(format!("Adding file to cache: {:?}", &file_entry_arc.meta));
 
@@ -304,12 +326,23 @@ impl FileReaderCache {
 }
 
 impl FileEntry {
-    pub async fn get_content_stream(&self, gruxi_request: &mut GruxiRequest) -> (BoxBody<Bytes, BodyError>, String) {
+    // Content that is not cached is read from the file system, which needs a file handle
+    pub fn needs_file_handle(&self) -> bool {
+        self.content.raw.is_none() && self.content.gzip.is_none()
+    }
+
+    // The permit is held until the file is read, or for as long as the stream of a large file is alive
+    pub async fn get_content_stream(&self, gruxi_request: &mut GruxiRequest, file_handle_permit: Option<FileHandlePermit>) -> (BoxBody<Bytes, BodyError>, String) {
         let accept_encoding_headers = gruxi_request.get_accepted_encodings();
 
-        if self.content.raw.is_none() && self.content.gzip.is_none() {
+        if self.needs_file_handle() {
             trace("No cached file data content is present, so we return from the filesystem instead (full if small and stream if big)".to_string());
 
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            if let Some(uring_file_reader) = get_uring_file_reader() {
+                return (self.get_uring_content_stream(uring_file_reader, file_handle_permit).await, String::new());
+            }
+
             // For smaller files (<= 64 KB), return full content, otherwise stream
             if self.meta.length <= 64 * 1024 {
                 // Small file, return full
@@ -335,7 +368,10 @@ impl FileEntry {
                 }
             };
 
-            let stream = ReaderStream::new(file).map_ok(Frame::data);
+            let stream = ReaderStream::new(file).map_ok(move |data| {
+                let _ = &file_handle_permit;
+                Frame::data(data)
+            });
             let streambody = http_body_util::BodyExt::map_err(StreamBody::new(stream), box_err);
             return (BoxBody::new(streambody), String::new());
         }
@@ -362,4 +398,31 @@ impl FileEntry {
         let empty = Full::new(Bytes::new()).map_err(|never| -> BodyError { match never {} });
         return (BoxBody::new(empty), String::new());
     }
+
+    // Same as reading from the filesystem above, but with the reads done by io_uring
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    async fn get_uring_content_stream(&self, uring_file_reader: &UringFileReader, file_handle_permit: Option<FileHandlePermit>) -> BoxBody<Bytes, BodyError> {
+        let mut receiver = uring_file_reader.read_file(&self.meta.file_path);
+
+        if self.meta.length <= 64 * 1024 {
+            let mut file_bytes = Vec::with_capacity(self.meta.length as usize);
+            while let Some(chunk) = receiver.recv().await {
+                match chunk {
+                    Ok(chunk) => file_bytes.extend_from_slice(&chunk),
+                    Err(e) => {
+                        trace(format!("Failed to read file {} for full content: {}", self.meta.file_path, e));
+                        file_bytes.clear();
+                        break;
+                    }
+                }
+            }
+            return BoxBody::new(Full::new(Bytes::from(file_bytes)).map_err(|never| -> BodyError { match never {} }));
+        }
+
+        let stream = tokio_stream::wrappers::ReceiverStream::new(receiver).map_ok(move |data| {
+            let _ = &file_handle_permit;
+            Frame::data(data)
+        });
+        BoxBody::new(http_body_util::BodyExt::map_err(StreamBody::new(stream), box_err))
+    }
 }
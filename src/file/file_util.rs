@@ -1,5 +1,6 @@
 use crate::logging::syslog::trace;
 use cached::proc_macro::cached;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// Splits `path_str` into (relative_dir, file_name) based on `base_path`.
@@ -74,6 +75,56 @@ pub async fn check_path_secure(base_path: &str, test_path: &str) -> bool {
     true
 }
 
+/// Check that the path, with symlinks resolved, is within the web root or one of the allowed paths.
+/// - Prevents both traversal and symlinks pointing out of the web root
+/// - Paths that do not exist are checked through their nearest existing parent directory
+/// - Web root and allowed paths that do not exist do not allow anything
+/// - Resolving the paths reads the file system, so it runs on the blocking thread pool
+pub async fn check_path_confined(web_root: &str, allowed_paths: &[String], test_path: &str) -> bool {
+    let roots: Vec<String> = std::iter::once(web_root.to_string()).chain(allowed_paths.iter().cloned()).collect();
+    let test_path = test_path.to_string();
+    tokio::task::spawn_blocking(move || is_path_within_roots(&roots, &test_path)).await.unwrap_or(false)
+}
+
+fn is_path_within_roots(roots: &[String], test_path: &str) -> bool {
+    let resolved_path = match resolve_existing_path(Path::new(test_path)) {
+        Some(path) => path,
+        None => {
+            trace(format!("Path is blocked by path confinement, as it could not be resolved: {}", test_path));
+            return false;
+        }
+    };
+
+    let is_confined = roots.iter().filter_map(|root| std::fs::canonicalize(root).ok()).any(|root| resolved_path.starts_with(root));
+
+    if !is_confined {
+        trace(format!(
+            "Path is blocked by path confinement, as it resolves outside the allowed paths: {} resolved: {}",
+            test_path,
+            resolved_path.display()
+        ));
+    }
+    is_confined
+}
+
+// Canonicalizes the nearest existing part of the path and appends the rest, so symlinks are resolved for paths that do not exist yet
+fn resolve_existing_path(path: &Path) -> Option<PathBuf> {
+    let mut current = path;
+    let mut remaining_parts = Vec::new();
+    loop {
+        if let Ok(canonical_path) = std::fs::canonicalize(current) {
+            let mut resolved_path = canonical_path;
+            for part in remaining_parts.iter().rev() {
+                resolved_path.push(part);
+            }
+            return Some(resolved_path);
+        }
+        // file_name is None for "..", so such paths are never resolved
+        remaining_parts.push(current.file_name()?);
+        current = current.parent()?;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,6 +144,44 @@ mod tests {
         assert!(!check_path_secure("/var/www", "/var/www/index.pem").await);
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_check_path_confined_with_symlinks() {
+        let base_dir = std::env::current_dir().unwrap().join("temp_test_data").join("path_confinement");
+        let _ = std::fs::remove_dir_all(&base_dir);
+        let web_root = base_dir.join("www");
+        let outside = base_dir.join("outside");
+        let shared = base_dir.join("shared");
+        for dir in [&web_root, &outside, &shared] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        std::fs::write(web_root.join("index.php"), "").unwrap();
+        std::fs::write(outside.join("secret.txt"), "").unwrap();
+        std::fs::write(shared.join("lib.php"), "").unwrap();
+        std::os::unix::fs::symlink(&outside, web_root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(&shared, web_root.join("shared")).unwrap();
+
+        let web_root_str = web_root.to_string_lossy().to_string();
+        let path_in_web_root = |p: &str| format!("{}{}", web_root_str, p);
+
+        // Files in the web root, also ones that do not exist yet
+        assert!(check_path_confined(&web_root_str, &[], &path_in_web_root("/index.php")).await);
+        assert!(check_path_confined(&web_root_str, &[], &path_in_web_root("/missing/file.php")).await);
+
+        // Symlinks out of the web root are only allowed if they point into an allowed path
+        assert!(!check_path_confined(&web_root_str, &[], &path_in_web_root("/escape/secret.txt")).await);
+        assert!(!check_path_confined(&web_root_str, &[], &path_in_web_root("/shared/lib.php")).await);
+        let allowed_paths = vec![shared.to_string_lossy().to_string()];
+        assert!(check_path_confined(&web_root_str, &allowed_paths, &path_in_web_root("/shared/lib.php")).await);
+        assert!(!check_path_confined(&web_root_str, &allowed_paths, &path_in_web_root("/escape/secret.txt")).await);
+
+        // Traversal and sibling directories sharing a prefix with the web root
+        assert!(!check_path_confined(&web_root_str, &[], &path_in_web_root("/../outside/secret.txt")).await);
+        assert!(!check_path_confined(&web_root_str, &[], &format!("{}2/index.php", web_root_str)).await);
+
+        let _ = std::fs::remove_dir_all(&base_dir);
+    }
+
     #[test]
     fn test_split_path_unix_path() {
         let (dir, file) = split_path("/path1/path2", "/path1/path2/index.php");
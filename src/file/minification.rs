@@ -0,0 +1,445 @@
+use crate::file::file_reader_structs::FileEntry;
+use crate::logging::syslog::trace;
+use dashmap::DashMap;
+use hyper::body::Bytes;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+// Larger files are served as they are, as they are rarely hand written
+const MAX_MINIFIED_FILE_SIZE: u64 = 2 * 1024 * 1024;
+
+// Minified files are kept until the total reaches this, after which new ones are minified on every request
+const MAX_MINIFIED_CACHE_BYTES: u64 = 64 * 1024 * 1024;
+
+// HTML elements whose content is kept as it is, as whitespace matters in it or it is not HTML
+const RAW_HTML_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+static MINIFIED_FILE_CACHE: OnceLock<MinifiedFileCache> = OnceLock::new();
+
+pub fn get_minified_file_cache() -> &'static MinifiedFileCache {
+    MINIFIED_FILE_CACHE.get_or_init(MinifiedFileCache::new)
+}
+
+struct MinifiedFile {
+    modified: SystemTime,
+    content: Bytes,
+}
+
+// Minified static files, by path. An entry is used for as long as the file has the modification time it was minified at,
+// so minifying a file is only paid for once per change of it
+pub struct MinifiedFileCache {
+    files: DashMap<String, MinifiedFile>,
+    cached_bytes: AtomicU64,
+}
+
+impl MinifiedFileCache {
+    pub fn new() -> Self {
+        MinifiedFileCache {
+            files: DashMap::new(),
+            cached_bytes: AtomicU64::new(0),
+        }
+    }
+
+    // The minified content of the file, or None if it is not an HTML, CSS or JavaScript file, is too large or cannot be read
+    pub async fn get(&self, file_entry: &FileEntry) -> Option<Bytes> {
+        let meta = &file_entry.meta;
+        if !meta.exists || meta.is_directory || meta.length > MAX_MINIFIED_FILE_SIZE || get_minifier(&meta.mime_type).is_none() {
+            return None;
+        }
+        let modified = tokio::fs::metadata(&meta.file_path).await.ok()?.modified().ok()?;
+        if let Some(minified_file) = self.files.get(&meta.file_path)
+            && minified_file.modified == modified
+        {
+            return Some(minified_file.content.clone());
+        }
+
+        // Read from disk, as the file cache may not have seen the change yet
+        let original = tokio::fs::read(&meta.file_path).await.ok()?;
+        let content = match minify(&meta.mime_type, &original) {
+            Some(minified) if minified.len() < original.len() => Bytes::from(minified),
+            _ => Bytes::from(original),
+        };
+        trace(format!("Minified {} to {} bytes", meta.file_path, content.len()));
+
+        let previous_length = self.files.get(&meta.file_path).map(|f| f.content.len() as u64).unwrap_or(0);
+        if self.cached_bytes.load(Ordering::Relaxed).saturating_sub(previous_length) + content.len() as u64 <= MAX_MINIFIED_CACHE_BYTES {
+            let replaced = self.files.insert(meta.file_path.clone(), MinifiedFile { modified, content: content.clone() });
+            self.cached_bytes.fetch_add(content.len() as u64, Ordering::Relaxed);
+            if let Some(replaced) = replaced {
+                self.cached_bytes.fetch_sub(replaced.content.len() as u64, Ordering::Relaxed);
+            }
+        }
+        Some(content)
+    }
+}
+
+impl Default for MinifiedFileCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn get_minifier(mime_type: &str) -> Option<fn(&str) -> String> {
+    match mime_type.split(';').next().unwrap_or("").trim() {
+        "text/html" => Some(minify_html),
+        "text/css" => Some(minify_css),
+        "text/javascript" | "application/javascript" | "application/x-javascript" => Some(minify_javascript),
+        _ => None,
+    }
+}
+
+// Minifies HTML, CSS or JavaScript. None for other types and content that is not UTF-8
+pub fn minify(mime_type: &str, content: &[u8]) -> Option<Vec<u8>> {
+    let minifier = get_minifier(mime_type)?;
+    let text = std::str::from_utf8(content).ok()?;
+    Some(minifier(text).into_bytes())
+}
+
+// Copies a quoted string starting at position, returning the position after it
+fn copy_quoted(chars: &[char], position: usize, output: &mut String) -> usize {
+    let quote = chars[position];
+    output.push(quote);
+    let mut i = position + 1;
+    while i < chars.len() {
+        let c = chars[i];
+        output.push(c);
+        i += 1;
+        if c == '\\' && i < chars.len() {
+            output.push(chars[i]);
+            i += 1;
+        } else if c == quote {
+            break;
+        }
+    }
+    i
+}
+
+fn starts_with_at(chars: &[char], position: usize, text: &str) -> bool {
+    (position..).zip(text.chars()).all(|(i, expected)| chars.get(i) == Some(&expected))
+}
+
+fn find_from(chars: &[char], position: usize, text: &str) -> Option<usize> {
+    (position..chars.len()).find(|&i| starts_with_at(chars, i, text))
+}
+
+// Removes comments, except /*! ones which are usually licenses, and the whitespace that is not needed around braces, semicolons and commas
+fn minify_css(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut output = String::with_capacity(input.len());
+    let mut pending_space = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let end = find_from(&chars, i + 2, "*/").map(|end| end + 2).unwrap_or(chars.len());
+            if chars.get(i + 2) == Some(&'!') {
+                output.extend(&chars[i..end]);
+            } else {
+                // A comment separates what is around it
+                pending_space = true;
+            }
+            i = end;
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+        if pending_space {
+            let after_separator = output.chars().last().is_none_or(|last| "{};,".contains(last)) || output.ends_with("*/");
+            if !after_separator && !"{};,".contains(c) {
+                output.push(' ');
+            }
+            pending_space = false;
+        }
+        if c == '"' || c == '\'' {
+            i = copy_quoted(&chars, i, &mut output);
+            continue;
+        }
+        if c == '}' && output.ends_with(';') {
+            output.pop();
+        }
+        output.push(c);
+        i += 1;
+    }
+    output
+}
+
+// Whether a / starts a regular expression rather than being a division, by what comes before it
+fn is_regex_start(last_significant: Option<char>, last_word: &str) -> bool {
+    match last_significant {
+        None => true,
+        Some(c) if "(,=:[!&|?{};+-*%<>~^".contains(c) => true,
+        Some(c) if c.is_alphanumeric() || c == '_' || c == '$' => {
+            matches!(
+                last_word,
+                "return" | "typeof" | "instanceof" | "in" | "of" | "new" | "delete" | "void" | "throw" | "case" | "do" | "else" | "yield" | "await"
+            )
+        }
+        _ => false,
+    }
+}
+
+// Copies a template literal starting at position, including the expressions in it, returning the position after it
+fn copy_template_literal(chars: &[char], position: usize, output: &mut String) -> usize {
+    output.push('`');
+    let mut i = position + 1;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\\' {
+            output.extend(&chars[i..(i + 2).min(chars.len())]);
+            i += 2;
+            continue;
+        }
+        if c == '`' {
+            output.push(c);
+            return i + 1;
+        }
+        if c == '$' && chars.get(i + 1) == Some(&'{') {
+            output.push_str("${");
+            i += 2;
+            let mut depth = 1;
+            while i < chars.len() && depth > 0 {
+                match chars[i] {
+                    '`' => i = copy_template_literal(chars, i, output),
+                    '"' | '\'' => i = copy_quoted(chars, i, output),
+                    c => {
+                        if c == '{' {
+                            depth += 1;
+                        } else if c == '}' {
+                            depth -= 1;
+                        }
+                        output.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            continue;
+        }
+        output.push(c);
+        i += 1;
+    }
+    i
+}
+
+// Copies a regular expression literal starting at position, returning the position after its closing /
+fn copy_regex(chars: &[char], position: usize, output: &mut String) -> usize {
+    output.push('/');
+    let mut i = position + 1;
+    let mut in_class = false;
+    while i < chars.len() {
+        let c = chars[i];
+        output.push(c);
+        i += 1;
+        match c {
+            '\\' if i < chars.len() => {
+                output.push(chars[i]);
+                i += 1;
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => break,
+            '\n' => break,
+            _ => {}
+        }
+    }
+    i
+}
+
+// Removes comments, except /*! ones, indentation, blank lines and runs of spaces. Line breaks are kept, so automatic
+// semicolon insertion works as before, and strings, template literals and regular expressions are left as they are
+fn minify_javascript(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut output = String::with_capacity(input.len());
+    let mut pending_newline = false;
+    let mut pending_space = false;
+    let mut last_significant: Option<char> = None;
+    let mut last_word = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' || c == '\r' {
+            pending_newline = !output.is_empty();
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            i = find_from(&chars, i, "\n").unwrap_or(chars.len());
+            continue;
+        }
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let end = find_from(&chars, i + 2, "*/").map(|end| end + 2).unwrap_or(chars.len());
+            if chars.get(i + 2) == Some(&'!') {
+                if pending_newline {
+                    output.push('\n');
+                }
+                output.extend(&chars[i..end]);
+                pending_newline = true;
+            } else if chars[i..end].contains(&'\n') {
+                pending_newline = !output.is_empty();
+            } else {
+                pending_space = true;
+            }
+            i = end;
+            continue;
+        }
+
+        if pending_newline {
+            output.push('\n');
+        } else if pending_space && !output.is_empty() {
+            output.push(' ');
+        }
+        pending_newline = false;
+        pending_space = false;
+
+        match c {
+            '"' | '\'' => i = copy_quoted(&chars, i, &mut output),
+            '`' => i = copy_template_literal(&chars, i, &mut output),
+            '/' if is_regex_start(last_significant, &last_word) => i = copy_regex(&chars, i, &mut output),
+            _ => {
+                output.push(c);
+                i += 1;
+            }
+        }
+        if c.is_alphanumeric() || c == '_' || c == '$' {
+            if !last_significant.is_some_and(|last| last.is_alphanumeric() || last == '_' || last == '$') || output.ends_with(char::is_whitespace) {
+                last_word.clear();
+            }
+            last_word.push(c);
+        } else {
+            last_word.clear();
+        }
+        last_significant = Some(if c == '/' || c == '"' || c == '\'' || c == '`' { 'a' } else { c });
+    }
+    output
+}
+
+// Removes comments, except conditional ones, and collapses whitespace between tags and in text to a single space or line break.
+// Attribute values and the content of pre, textarea, script and style elements are left as they are
+fn minify_html(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut output = String::with_capacity(input.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            let start = i;
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            // Whitespace on both sides of a removed comment becomes one
+            let has_line_break = chars[start..i].contains(&'\n');
+            if output.ends_with(' ') && has_line_break {
+                output.pop();
+            }
+            if !output.is_empty() && !output.ends_with(char::is_whitespace) && i < chars.len() {
+                output.push(if has_line_break { '\n' } else { ' ' });
+            }
+            continue;
+        }
+        if starts_with_at(&chars, i, "<!--") {
+            let end = find_from(&chars, i + 4, "-->").map(|end| end + 3).unwrap_or(chars.len());
+            if starts_with_at(&chars, i, "<!--[if") || starts_with_at(&chars, i, "<!--<!") {
+                output.extend(&chars[i..end]);
+            }
+            i = end;
+            continue;
+        }
+        if c == '<' && chars.get(i + 1).is_some_and(|next| next.is_ascii_alphabetic() || *next == '/') {
+            let tag_start = i;
+            i = copy_tag(&chars, i, &mut output);
+            // The content of raw elements is copied up to their end tag
+            let tag_name: String = chars[tag_start + 1..i].iter().take_while(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase();
+            if RAW_HTML_ELEMENTS.contains(&tag_name.as_str()) {
+                let end_tag = format!("</{}", tag_name);
+                let end = (i..chars.len())
+                    .find(|&j| chars[j..].iter().take(end_tag.len()).collect::<String>().to_lowercase() == end_tag)
+                    .unwrap_or(chars.len());
+                output.extend(&chars[i..end]);
+                i = end;
+            }
+            continue;
+        }
+        output.push(c);
+        i += 1;
+    }
+    output
+}
+
+// Copies a tag starting at position, with whitespace between attributes collapsed and their quoted values kept as they are
+fn copy_tag(chars: &[char], position: usize, output: &mut String) -> usize {
+    let mut i = position;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '"' | '\'' => {
+                let end = (i + 1..chars.len()).find(|&j| chars[j] == c).map(|end| end + 1).unwrap_or(chars.len());
+                output.extend(&chars[i..end]);
+                i = end;
+            }
+            c if c.is_whitespace() => {
+                while i < chars.len() && chars[i].is_whitespace() {
+                    i += 1;
+                }
+                if !matches!(chars.get(i), Some('>') | Some('/') | Some('=')) && !output.ends_with('=') {
+                    output.push(' ');
+                }
+            }
+            '>' => {
+                output.push(c);
+                return i + 1;
+            }
+            _ => {
+                output.push(c);
+                i += 1;
+            }
+        }
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify_css() {
+        let css = "/* Layout */\nbody {\n    margin: 0;\n    font-family: \"Open  Sans\", sans-serif;\n}\n\n/*! License */\n.a > .b , .c:hover {\n  width: calc(100% - 2px);\n}\n@media screen and (max-width: 600px) { .a { display: none; } }\n";
+        assert_eq!(
+            minify_css(css),
+            "body{margin: 0;font-family: \"Open  Sans\",sans-serif}/*! License */.a > .b,.c:hover{width: calc(100% - 2px)}@media screen and (max-width: 600px){.a{display: none}}"
+        );
+    }
+
+    #[test]
+    fn test_minify_javascript() {
+        let js = "// Greeting\nfunction greet(name) {\n    /* say hi */\n    const text = `Hi  ${name} // not a comment`;\n    return text.replace(/\\/\\/+/g, '//  ');\n}\n\n\nlet a = 4 / 2 / 1\nlet b = a\n++a\n";
+        assert_eq!(
+            minify_javascript(js),
+            "function greet(name) {\nconst text = `Hi  ${name} // not a comment`;\nreturn text.replace(/\\/\\/+/g, '//  ');\n}\nlet a = 4 / 2 / 1\nlet b = a\n++a\n".trim_end()
+        );
+    }
+
+    #[test]
+    fn test_minify_html() {
+        let html = "<!DOCTYPE html>\n<html>\n  <!-- Navigation -->\n  <body   class=\"a  b\"  id = 'main' >\n    <p>Hello,\n       <b>world</b> !</p>\n    <pre>  keep\n   this </pre>\n    <script>\n  if (a  <  b) {}\n    </script>\n    <!--[if IE]><p>Old</p><![endif]-->\n  </body>\n</html>\n";
+        assert_eq!(
+            minify_html(html),
+            "<!DOCTYPE html>\n<html>\n<body class=\"a  b\" id='main'>\n<p>Hello,\n<b>world</b> !</p>\n<pre>  keep\n   this </pre>\n<script>\n  if (a  <  b) {}\n    </script>\n<!--[if IE]><p>Old</p><![endif]-->\n</body>\n</html>"
+        );
+    }
+
+    #[test]
+    fn test_minify_only_known_types() {
+        assert!(minify("image/png", b"  ").is_none());
+        assert!(minify("text/css", &[0xff, 0xfe]).is_none());
+        assert_eq!(minify("text/css; charset=utf-8", b"a { b: c; }").unwrap(), b"a{b: c}");
+    }
+}
@@ -1,4 +1,10 @@
 pub mod file_util;
 pub mod file_reader_cache;
 pub mod file_reader_structs;
-pub mod normalized_path;
\ No newline at end of file
+pub mod normalized_path;
+pub mod file_handle_limiter;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring_file_reader;
+pub mod minification;
+pub mod preload_links;
+pub mod disk_cache;
\ No newline at end of file
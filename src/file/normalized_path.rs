@@ -3,10 +3,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use unicode_general_category::{GeneralCategory, get_general_category};
-use unicode_normalization::UnicodeNormalization;
-use urlencoding::decode;
-
+use crate::http::url_canonicalization::decode_url_path;
 use crate::logging::syslog::debug;
 
 #[derive(Clone, Debug)]
@@ -81,71 +78,9 @@ impl NormalizedPath {
         self.path.to_string()
     }
 
-    fn decode_string_until_no_percentage(path: &str) -> Result<String, ()> {
-        let mut decoded = path.to_string();
-
-        let max_rounds = 10; // Prevent infinite loops
-
-        for _ in 0..max_rounds {
-            let decoded_result = decode(&decoded);
-            let new_decoded = match decoded_result {
-                Ok(d) => d.to_string(),
-                Err(_) => return Err(()),
-            };
-
-            if new_decoded == decoded {
-                return Ok(decoded);
-            }
-            decoded = new_decoded;
-        }
-
-        Err(())
-    }
-
     fn clean_url_path(path: &str) -> Result<String, String> {
-        // First, decode percent-encoded characters
-        let decoded_path_result = Self::decode_string_until_no_percentage(path);
-        let path = match decoded_path_result {
-            Ok(p) => p,
-            Err(_) => return Err("Failed to decode percent-encoded characters".to_string()),
-        };
-
-        // Handle unicode normalization
-        let mut buf: String = path.nfc().collect();
-        for ch in buf.chars() {
-            // Reject Unicode format characters (Cf)
-            let gc = get_general_category(ch);
-            if gc == GeneralCategory::Format {
-                return Err("Path contains forbidden Unicode format characters".to_string());
-            }
-            if gc == GeneralCategory::Control {
-                return Err("Path contains forbidden Unicode control characters".to_string());
-            }
-
-            // Reject confusable slashes or dots
-            if matches!(
-                ch,
-                // Slash-like
-                '\u{2215}' | // ∕ division slash
-                '\u{2044}' | // ⁄ fraction slash
-                '\u{FF0F}' | // ／ fullwidth solidus
-                '\u{29F8}' | // ⧸ big solidus
-                '\u{FE68}' | // ﹨ small reverse solidus
-
-                // Dot-like
-                '\u{FF0E}' | // ． fullwidth full stop
-                '\u{3002}' | // 。 ideographic full stop
-                '\u{2219}' | // ∙ bullet operator
-                '\u{22C5}' // ⋅ dot operator
-            ) {
-                return Err("Path contains confusable slash or dot characters".to_string());
-            }
-        }
-
-        // Return error on ascii control characters and NUL characters
-        if buf.chars().any(|c| c.is_control() || c == '\0') {
-            return Err("Path contains ASCII control characters or NUL characters".to_string());
-        }
+        // Decode the same way as the canonical request path, so the checks below see what the file system will
+        let buf = decode_url_path(path)?;
 
         // If last characters is dot, we call error (to avoid trailing dots)
         if buf.ends_with('.') {
@@ -157,16 +92,6 @@ impl NormalizedPath {
             return Err("Path cannot contain colon characters".to_string());
         }
 
-        // Remove duplicate slashes (// → /)
-        while buf.contains("//") {
-            buf = buf.replace("//", "/");
-        }
-
-        // Remove backward slashes (\ → '')
-        while buf.contains("\\") {
-            buf = buf.replace("\\", "");
-        }
-
         // Split by slash and process each part
         let mut parts = Vec::new();
         for part in buf.split('/') {
@@ -0,0 +1,264 @@
+use crate::file::file_reader_structs::FileEntry;
+use crate::logging::syslog::trace;
+use dashmap::DashMap;
+use hyper::header::HeaderValue;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use tokio::io::AsyncReadExt;
+
+// Only the start of a page is scanned, where the head with its stylesheets and scripts is
+const MAX_SCANNED_BYTES: u64 = 64 * 1024;
+
+// Preloading more than a handful of files competes with the page itself
+const MAX_PRELOAD_LINKS: usize = 8;
+
+// Pages are scanned once per change until this many are known, after which new ones are scanned on every request
+const MAX_CACHED_PAGES: usize = 10_000;
+
+static PRELOAD_LINK_CACHE: OnceLock<PreloadLinkCache> = OnceLock::new();
+
+pub fn get_preload_link_cache() -> &'static PreloadLinkCache {
+    PRELOAD_LINK_CACHE.get_or_init(PreloadLinkCache::new)
+}
+
+struct PreloadLinks {
+    modified: SystemTime,
+    links: Vec<HeaderValue>,
+}
+
+// Link preload headers for the stylesheets and scripts in the head of static HTML pages, by path, so browsers can start loading them
+// before they have parsed the page, or from a 103 Early Hints response. An entry is used for as long as the page has the modification
+// time it was scanned at. hyper has no HTTP/2 server push, which browsers have dropped as well, so the links are only sent as headers
+pub struct PreloadLinkCache {
+    pages: DashMap<String, PreloadLinks>,
+}
+
+impl PreloadLinkCache {
+    pub fn new() -> Self {
+        PreloadLinkCache { pages: DashMap::new() }
+    }
+
+    // The Link header values for the page, empty if it is not an HTML file or cannot be read
+    pub async fn get(&self, file_entry: &FileEntry) -> Vec<HeaderValue> {
+        let meta = &file_entry.meta;
+        if !meta.exists || meta.is_directory || meta.mime_type.split(';').next().unwrap_or("").trim() != "text/html" {
+            return Vec::new();
+        }
+        let Some(modified) = tokio::fs::metadata(&meta.file_path).await.ok().and_then(|metadata| metadata.modified().ok()) else {
+            return Vec::new();
+        };
+        if let Some(preload_links) = self.pages.get(&meta.file_path)
+            && preload_links.modified == modified
+        {
+            return preload_links.links.clone();
+        }
+
+        // Read from disk, as the file cache may not have seen the change yet
+        let Ok(file) = tokio::fs::File::open(&meta.file_path).await else {
+            return Vec::new();
+        };
+        let mut content = Vec::new();
+        if file.take(MAX_SCANNED_BYTES).read_to_end(&mut content).await.is_err() {
+            return Vec::new();
+        }
+        let links = find_preload_links(&content);
+        trace(format!("Found {} preload links in {}", links.len(), meta.file_path));
+
+        if self.pages.len() < MAX_CACHED_PAGES || self.pages.contains_key(&meta.file_path) {
+            self.pages.insert(meta.file_path.clone(), PreloadLinks { modified, links: links.clone() });
+        }
+        links
+    }
+}
+
+impl Default for PreloadLinkCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Finds the stylesheets and scripts in the head of an HTML page and returns Link header values preloading them. Files on other sites,
+// alternate and print stylesheets and scripts with an integrity check are left to the browser, as a preload that does not match how
+// the page requests the file is loaded twice
+pub fn find_preload_links(content: &[u8]) -> Vec<HeaderValue> {
+    // A page cut off by the scan limit may end within a character
+    let text = match std::str::from_utf8(content) {
+        Ok(text) => text,
+        Err(e) => std::str::from_utf8(&content[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    // Lowercasing ASCII keeps the positions the same in both
+    let lowercase = text.to_ascii_lowercase();
+    let head_end = lowercase.find("</head").unwrap_or(lowercase.len());
+
+    let mut links = Vec::new();
+    let mut position = 0;
+    while links.len() < MAX_PRELOAD_LINKS
+        && position < head_end
+        && let Some(offset) = lowercase[position..head_end].find('<')
+    {
+        let start = position + offset;
+        if lowercase[start..].starts_with("<!--") {
+            position = lowercase[start..].find("-->").map(|end| start + end + 3).unwrap_or(head_end);
+            continue;
+        }
+        let Some(end) = lowercase[start..].find('>').map(|end| start + end) else {
+            break;
+        };
+        position = end + 1;
+
+        let tag = &text[start + 1..end];
+        let tag_name = tag.split(|c: char| c.is_ascii_whitespace() || c == '/').next().unwrap_or("").to_ascii_lowercase();
+        let attributes = parse_attributes(tag);
+        let get_attribute = |name: &str| attributes.iter().find(|(attribute_name, _)| attribute_name == name).map(|(_, value)| value.as_str());
+        let preload = match tag_name.as_str() {
+            "link" => {
+                let rel = get_attribute("rel").unwrap_or("").to_ascii_lowercase();
+                let is_stylesheet = rel.split_ascii_whitespace().any(|token| token == "stylesheet") && !rel.split_ascii_whitespace().any(|token| token == "alternate");
+                let is_print = get_attribute("media").is_some_and(|media| media.trim().eq_ignore_ascii_case("print"));
+                if is_stylesheet && !is_print { get_attribute("href").map(|href| (href, "rel=preload; as=style")) } else { None }
+            }
+            "script" => {
+                // The script content is not HTML, so it is skipped to its end tag
+                position = lowercase[position..].find("</script").map(|end| position + end).unwrap_or(head_end).min(head_end);
+                let is_module = get_attribute("type").is_some_and(|script_type| script_type.trim().eq_ignore_ascii_case("module"));
+                if get_attribute("nomodule").is_some() {
+                    None
+                } else if is_module {
+                    get_attribute("src").map(|src| (src, "rel=modulepreload"))
+                } else {
+                    get_attribute("src").map(|src| (src, "rel=preload; as=script"))
+                }
+            }
+            _ => None,
+        };
+
+        let Some((url, parameters)) = preload.map(|(url, parameters)| (url.trim(), parameters)) else {
+            continue;
+        };
+        if get_attribute("integrity").is_some() || !is_same_site_url(url) {
+            continue;
+        }
+        let mut link = format!("<{}>; {}", url, parameters);
+        // The preload must be requested the same way as the file, or the browser cannot use it. Module scripts always are with CORS
+        match get_attribute("crossorigin").map(|value| value.trim().to_ascii_lowercase()) {
+            Some(value) if value == "use-credentials" => link.push_str("; crossorigin=use-credentials"),
+            Some(_) if parameters != "rel=modulepreload" => link.push_str("; crossorigin"),
+            _ => {}
+        }
+        if let Ok(header_value) = HeaderValue::from_str(&link)
+            && !links.contains(&header_value)
+        {
+            links.push(header_value);
+        }
+    }
+    links
+}
+
+// Relative and root-relative URLs, without a scheme such as https: or data:, and without the characters that would end the URL in the
+// Link header
+fn is_same_site_url(url: &str) -> bool {
+    !url.is_empty() && !url.starts_with("//") && !url.contains([':', '<', '>', '"']) && !url.contains(char::is_whitespace)
+}
+
+// The attributes of a tag, with their names lowercased. Attributes without a value, such as async, have an empty one
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let chars: Vec<char> = tag.chars().collect();
+    let mut attributes = Vec::new();
+    // Skip the tag name
+    let mut i = chars.iter().position(|c| c.is_ascii_whitespace()).unwrap_or(chars.len());
+    while i < chars.len() {
+        while i < chars.len() && (chars[i].is_ascii_whitespace() || chars[i] == '/') {
+            i += 1;
+        }
+        let name_start = i;
+        while i < chars.len() && !chars[i].is_ascii_whitespace() && chars[i] != '=' && chars[i] != '/' {
+            i += 1;
+        }
+        if name_start == i {
+            break;
+        }
+        let name: String = chars[name_start..i].iter().collect::<String>().to_ascii_lowercase();
+        while i < chars.len() && chars[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let mut value = String::new();
+        if i < chars.len() && chars[i] == '=' {
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == '"' || chars[i] == '\'') {
+                let quote = chars[i];
+                let value_start = i + 1;
+                i = (value_start..chars.len()).find(|&j| chars[j] == quote).unwrap_or(chars.len());
+                value = chars[value_start..i].iter().collect();
+                i += 1;
+            } else {
+                let value_start = i;
+                while i < chars.len() && !chars[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                value = chars[value_start..i].iter().collect();
+            }
+        }
+        attributes.push((name, value));
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_links(html: &str) -> Vec<String> {
+        find_preload_links(html.as_bytes()).iter().map(|link| link.to_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn test_find_preload_links() {
+        let html = r#"<!DOCTYPE html>
+<html>
+<head>
+  <!-- <link rel="stylesheet" href="/commented.css"> -->
+  <link rel="stylesheet" href="/css/app.css">
+  <LINK REL='Stylesheet' HREF=css/theme.css media=screen>
+  <link rel="alternate stylesheet" href="/css/dark.css">
+  <link rel="stylesheet" href="/css/print.css" media="print">
+  <link rel="stylesheet" href="https://cdn.example.com/lib.css">
+  <link rel="icon" href="/favicon.ico">
+  <script src="/js/app.js" defer></script>
+  <script>if (a < b) { document.write('<script src="/js/inline.js"></script>'); }</script>
+  <script type="module" src="/js/main.mjs"></script>
+  <script nomodule src="/js/legacy.js"></script>
+  <script src="/js/checked.js" integrity="sha384-abc"></script>
+  <script src="/js/fonts.js" crossorigin="anonymous"></script>
+  <link rel="stylesheet" href="/css/app.css">
+</head>
+<body>
+  <script src="/js/body.js"></script>
+</body>
+</html>"#;
+        assert_eq!(
+            find_links(html),
+            vec![
+                "</css/app.css>; rel=preload; as=style",
+                "<css/theme.css>; rel=preload; as=style",
+                "</js/app.js>; rel=preload; as=script",
+                "</js/main.mjs>; rel=modulepreload",
+                "</js/fonts.js>; rel=preload; as=script; crossorigin",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preload_links_are_bounded() {
+        let html: String = (0..20).map(|i| format!("<link rel=stylesheet href=/css/{}.css>", i)).collect();
+        assert_eq!(find_links(&html).len(), MAX_PRELOAD_LINKS);
+
+        // A page cut off within a character or a tag still gives the links before it
+        let mut content = b"<link rel=stylesheet href=/a.css><title>\xc3".to_vec();
+        assert_eq!(find_preload_links(&content).len(), 1);
+        content.extend_from_slice(b"<link rel=stylesheet href=/b.css");
+        assert_eq!(find_preload_links(&content).len(), 1);
+    }
+}
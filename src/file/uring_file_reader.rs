@@ -0,0 +1,142 @@
+use crate::logging::syslog::{info, trace, warn};
+use hyper::body::Bytes;
+use std::io;
+use std::sync::OnceLock;
+use tokio::sync::mpsc;
+
+// Size of each read submitted to io_uring, and so of each chunk of the response body
+const URING_READ_CHUNK_SIZE: usize = 64 * 1024;
+
+// Chunks read ahead of what the client has received, which bounds the memory used per streamed file
+const URING_READ_AHEAD_CHUNKS: usize = 4;
+
+struct ReadRequest {
+    path: String,
+    sender: mpsc::Sender<io::Result<Bytes>>,
+}
+
+// Reads files with io_uring, for Linux builds with the "io-uring" feature. tokio-uring needs a runtime of its own, so reads are
+// sent to a dedicated thread running one, and the chunks come back over a channel the response body streams from
+pub struct UringFileReader {
+    requests: mpsc::UnboundedSender<ReadRequest>,
+}
+
+static URING_FILE_READER_SINGLETON: OnceLock<Option<UringFileReader>> = OnceLock::new();
+
+// None if io_uring is not available, such as on older kernels or where it is blocked by seccomp, in which case files are read the standard way
+pub fn get_uring_file_reader() -> Option<&'static UringFileReader> {
+    URING_FILE_READER_SINGLETON.get_or_init(UringFileReader::start).as_ref()
+}
+
+impl UringFileReader {
+    fn start() -> Option<Self> {
+        let (request_sender, mut request_receiver) = mpsc::unbounded_channel::<ReadRequest>();
+        let (ready_sender, ready_receiver) = std::sync::mpsc::channel::<io::Result<()>>();
+
+        let spawn_result = std::thread::Builder::new().name("gruxi-io-uring".to_string()).spawn(move || {
+            let runtime = match tokio_uring::Runtime::new(&tokio_uring::builder()) {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    let _ = ready_sender.send(Err(e));
+                    return;
+                }
+            };
+            let _ = ready_sender.send(Ok(()));
+            runtime.block_on(async move {
+                while let Some(request) = request_receiver.recv().await {
+                    tokio_uring::spawn(read_file(request));
+                }
+            });
+        });
+        if let Err(e) = spawn_result {
+            warn(format!("Failed to start the io_uring file reader thread, reading files the standard way: {}", e));
+            return None;
+        }
+
+        match ready_receiver.recv() {
+            Ok(Ok(())) => {
+                info("Static files are read with io_uring");
+                Some(UringFileReader { requests: request_sender })
+            }
+            Ok(Err(e)) => {
+                warn(format!("io_uring is not available, reading files the standard way: {}", e));
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    // Reads the file in chunks. The receiver gets an error and no more chunks if the file cannot be opened or read
+    pub fn read_file(&self, path: &str) -> mpsc::Receiver<io::Result<Bytes>> {
+        let (sender, receiver) = mpsc::channel(URING_READ_AHEAD_CHUNKS);
+        let request = ReadRequest { path: path.to_string(), sender };
+        if let Err(mpsc::error::SendError(request)) = self.requests.send(request) {
+            let _ = request.sender.try_send(Err(io::Error::other("The io_uring file reader thread has stopped")));
+        }
+        receiver
+    }
+}
+
+async fn read_file(request: ReadRequest) {
+    let file = match tokio_uring::fs::File::open(&request.path).await {
+        Ok(file) => file,
+        Err(e) => {
+            trace(format!("Failed to open file {} with io_uring: {}", request.path, e));
+            let _ = request.sender.send(Err(e)).await;
+            return;
+        }
+    };
+
+    let mut position: u64 = 0;
+    loop {
+        let (result, mut buffer) = file.read_at(Vec::with_capacity(URING_READ_CHUNK_SIZE), position).await;
+        match result {
+            Ok(0) => break,
+            Ok(read) => {
+                buffer.truncate(read);
+                position += read as u64;
+                // The client went away, so there is no reason to read the rest
+                if request.sender.send(Ok(Bytes::from(buffer))).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                trace(format!("Failed to read file {} with io_uring: {}", request.path, e));
+                let _ = request.sender.send(Err(e)).await;
+                break;
+            }
+        }
+    }
+    let _ = file.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_file_in_chunks() {
+        let Some(reader) = get_uring_file_reader() else {
+            // Nothing to test where the kernel does not allow io_uring
+            return;
+        };
+
+        let path = std::env::temp_dir().join(format!("gruxi-uring-test-{}", uuid::Uuid::new_v4()));
+        let content: Vec<u8> = (0..URING_READ_CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let mut receiver = reader.read_file(&path.to_string_lossy());
+        let mut read_content = Vec::new();
+        let mut chunks = 0;
+        while let Some(chunk) = receiver.recv().await {
+            read_content.extend_from_slice(&chunk.unwrap());
+            chunks += 1;
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_content, content);
+        assert_eq!(chunks, 3);
+
+        let mut receiver = reader.read_file("/does/not/exist");
+        assert!(receiver.recv().await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,68 @@
+// Entry points for fuzzing the parsers that take untrusted input, used by the cargo-fuzz targets in the fuzz directory.
+// Each function accepts any bytes and must never panic, whatever the input. Run a target with, for example:
+//   cargo +nightly fuzz run fastcgi_records
+
+use crate::external_connections::fastcgi::FastCgi;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_validation::{RequestValidationLevel, validate_request};
+use hyper::body::Bytes;
+use std::sync::OnceLock;
+
+// Most headers a fuzzed request is parsed with, hyper allows 100 by default
+const MAX_HEADERS: usize = 100;
+
+static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+
+// The parsers log through the system log, which needs a tokio runtime to start its flushing task
+fn enter_runtime() -> tokio::runtime::EnterGuard<'static> {
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build the tokio runtime for fuzzing")
+        })
+        .enter()
+}
+
+// The records from a FastCGI server, and the CGI response of the script in them
+pub fn fuzz_fastcgi_records(data: &[u8]) {
+    let _runtime = enter_runtime();
+    let _ = FastCgi::is_fastcgi_response_complete(data);
+    let (output, _stderr) = FastCgi::parse_fastcgi_response(data);
+    let _ = FastCgi::parse_cgi_response(&output);
+}
+
+// The request line and headers of an HTTP/1.1 request. hyper parses them with httparse, so they are parsed the same way
+// here, after which they go through the request validation and the request data that is derived from them
+pub fn fuzz_http1_request_head(data: &[u8]) {
+    let _runtime = enter_runtime();
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parsed = httparse::Request::new(&mut headers);
+    let Ok(httparse::Status::Complete(_)) = parsed.parse(data) else {
+        return;
+    };
+
+    let mut builder = hyper::Request::builder().method(parsed.method.unwrap_or_default()).uri(parsed.path.unwrap_or_default());
+    for header in parsed.headers.iter() {
+        builder = builder.header(header.name, header.value);
+    }
+    let Ok(request) = builder.body(Bytes::new()) else {
+        return;
+    };
+
+    for level in [RequestValidationLevel::Standard, RequestValidationLevel::Strict] {
+        let _ = validate_request(&request, level);
+    }
+
+    let mut gruxi_request = GruxiRequest::new(request);
+    gruxi_request.get_hostname();
+    gruxi_request.get_path_and_query();
+    gruxi_request.get_canonical_path();
+    gruxi_request.get_server_port();
+    gruxi_request.get_cookie("session");
+    gruxi_request.get_accepted_encodings();
+    let _ = FastCgi::generate_fast_cgi_params(&mut gruxi_request);
+    gruxi_request.clean_hop_by_hop_headers();
+    gruxi_request.add_forwarded_headers();
+}
@@ -0,0 +1,287 @@
+use crate::configuration::site::Site;
+use crate::http::request_response::body_error::BodyError;
+use crate::http::request_response::gruxi_body::GruxiBody;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use aho_corasick::{AhoCorasick, Input, MatchKind};
+use http_body_util::BodyExt;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, HeaderValue};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// Replaces the search texts of the body substitutions of the site in the response body, for responses of their content types.
+// Buffered bodies are changed right away, streamed bodies as they are sent, holding back no more than the longest search text
+pub fn apply_body_substitutions(gruxi_request: &mut GruxiRequest, site: &Site, response: &mut GruxiResponse) {
+    if site.body_substitutions.is_empty() {
+        return;
+    }
+    // Partial and bodyless responses are left alone, and so are compressed ones, such as from a backend, which cannot be searched
+    let status = response.get_status();
+    if status == 204 || status == 206 || status == 304 {
+        return;
+    }
+    if response
+        .get_header(CONTENT_ENCODING.as_str())
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|encoding| !encoding.eq_ignore_ascii_case("identity"))
+    {
+        return;
+    }
+
+    let media_type = response
+        .get_header(CONTENT_TYPE.as_str())
+        .and_then(|v| v.to_str().ok())
+        .map(|content_type| content_type.split(';').next().unwrap_or("").trim().to_lowercase())
+        .unwrap_or_default();
+    let replacements: Vec<(Vec<u8>, Vec<u8>)> = site
+        .body_substitutions
+        .iter()
+        .filter(|substitution| substitution.content_types.contains(&media_type))
+        .map(|substitution| (substitution.search.as_bytes().to_vec(), expand_replacement(gruxi_request, &substitution.replacement).into_bytes()))
+        .collect();
+    if replacements.is_empty() {
+        return;
+    }
+
+    // The body no longer has the length or the exact bytes the handler gave it
+    let headers = response.headers_mut();
+    headers.remove(CONTENT_LENGTH);
+    headers.remove(ACCEPT_RANGES);
+    if let Some(etag) = headers.get(ETAG).and_then(|v| v.to_str().ok()).filter(|etag| !etag.starts_with("W/"))
+        && let Ok(weak_etag) = HeaderValue::from_str(&format!("W/{}", etag))
+    {
+        headers.insert(ETAG, weak_etag);
+    }
+
+    let mut substituter = Substituter::new(replacements);
+    match response.take_body() {
+        GruxiBody::Buffered(bytes) => response.set_body(GruxiBody::Buffered(Bytes::from(substituter.process(&bytes, true)))),
+        GruxiBody::Streaming(incoming) => response.set_body(GruxiBody::StreamingBoxed(BoxBody::new(SubstitutionBody::new(
+            BoxBody::new(incoming.map_err(crate::http::request_response::body_error::box_err)),
+            substituter,
+        )))),
+        GruxiBody::StreamingBoxed(boxed_body) => response.set_body(GruxiBody::StreamingBoxed(BoxBody::new(SubstitutionBody::new(boxed_body, substituter)))),
+    }
+}
+
+fn expand_replacement(gruxi_request: &mut GruxiRequest, replacement: &str) -> String {
+    if !replacement.contains("${") {
+        return replacement.to_string();
+    }
+    // The hostname comes from the client, so it is escaped before it goes into a page
+    replacement
+        .replace("${request_id}", &gruxi_request.get_request_id())
+        .replace("${remote_ip}", &gruxi_request.get_remote_ip())
+        .replace("${hostname}", &escape_html(&gruxi_request.get_hostname()))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+// Search and replace over a body given in pieces. Text that could be the start of a match is kept until the next piece shows
+// whether it is one, so a match is found even when it is split across pieces
+struct Substituter {
+    replacements: Vec<(Vec<u8>, Vec<u8>)>,
+    // Finds the earliest match of any of the search texts, and of those starting at the same place the first configured one
+    searcher: Option<AhoCorasick>,
+    longest_search: usize,
+    pending: Vec<u8>,
+}
+
+impl Substituter {
+    fn new(replacements: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        let longest_search = replacements.iter().map(|(search, _)| search.len()).max().unwrap_or(1).max(1);
+        let searcher = AhoCorasick::builder().match_kind(MatchKind::LeftmostFirst).build(replacements.iter().map(|(search, _)| search)).ok();
+        Substituter {
+            replacements,
+            searcher,
+            longest_search,
+            pending: Vec::new(),
+        }
+    }
+
+    // Returns the part of the body that is done. With is_end, the rest of the body is given back as well
+    fn process(&mut self, input: &[u8], is_end: bool) -> Vec<u8> {
+        self.pending.extend_from_slice(input);
+        // Matches may only start before the limit, from where every search text fits in what we have, so the earliest match is
+        // not missed for a later one
+        let limit = if is_end { self.pending.len() } else { self.pending.len().saturating_sub(self.longest_search - 1) };
+
+        let mut output = Vec::with_capacity(self.pending.len());
+        let mut position = 0;
+        while let Some((start, index)) = self.find_earliest_match(position, limit) {
+            let (search, replacement) = &self.replacements[index];
+            output.extend_from_slice(&self.pending[position..start]);
+            output.extend_from_slice(replacement);
+            position = start + search.len();
+        }
+        let done = limit.max(position);
+        output.extend_from_slice(&self.pending[position..done]);
+        self.pending.drain(..done);
+        output
+    }
+
+    // The earliest match starting from position and before limit, as its start and the index of the replacement.
+    // Of matches starting at the same place, the first configured one is used
+    fn find_earliest_match(&self, position: usize, limit: usize) -> Option<(usize, usize)> {
+        let found = self.searcher.as_ref()?.find(Input::new(&self.pending).range(position..))?;
+        (found.start() < limit).then(|| (found.start(), found.pattern().as_usize()))
+    }
+}
+
+// Response body with the substitutions done as it is streamed
+struct SubstitutionBody {
+    inner: BoxBody<Bytes, BodyError>,
+    substituter: Substituter,
+    // Trailers wait until the rest of the body is sent before them
+    trailers: Option<Frame<Bytes>>,
+    finished: bool,
+}
+
+impl SubstitutionBody {
+    fn new(inner: BoxBody<Bytes, BodyError>, substituter: Substituter) -> Self {
+        SubstitutionBody {
+            inner,
+            substituter,
+            trailers: None,
+            finished: false,
+        }
+    }
+}
+
+impl Body for SubstitutionBody {
+    type Data = Bytes;
+    type Error = BodyError;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        loop {
+            if let Some(trailers) = self.trailers.take() {
+                return Poll::Ready(Some(Ok(trailers)));
+            }
+            if self.finished {
+                return Poll::Ready(None);
+            }
+            match Pin::new(&mut self.inner).poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+                    Ok(data) => {
+                        let output = self.substituter.process(&data, false);
+                        // Nothing is done yet when all of the piece could be the start of a match
+                        if !output.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(Bytes::from(output)))));
+                        }
+                    }
+                    Err(trailers) => {
+                        self.finished = true;
+                        self.trailers = Some(trailers);
+                        let output = self.substituter.process(&[], true);
+                        if !output.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(Bytes::from(output)))));
+                        }
+                    }
+                },
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => {
+                    self.finished = true;
+                    let output = self.substituter.process(&[], true);
+                    if !output.is_empty() {
+                        return Poll::Ready(Some(Ok(Frame::data(Bytes::from(output)))));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.finished && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::site::BodySubstitution;
+    use http_body_util::{BodyExt, StreamBody};
+
+    fn create_substituter(replacements: &[(&str, &str)]) -> Substituter {
+        Substituter::new(replacements.iter().map(|(search, replacement)| (search.as_bytes().to_vec(), replacement.as_bytes().to_vec())).collect())
+    }
+
+    fn substitute_in_pieces(replacements: &[(&str, &str)], pieces: &[&str]) -> String {
+        let mut substituter = create_substituter(replacements);
+        let mut output = Vec::new();
+        for piece in pieces {
+            output.extend(substituter.process(piece.as_bytes(), false));
+        }
+        output.extend(substituter.process(&[], true));
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_substitution_across_pieces() {
+        let replacements = [("</body>", "<script src=\"/a.js\"></script></body>")];
+        let expected = "<html><body>Hi<script src=\"/a.js\"></script></body></html>";
+        assert_eq!(substitute_in_pieces(&replacements, &["<html><body>Hi</body></html>"]), expected);
+        assert_eq!(substitute_in_pieces(&replacements, &["<html><body>Hi</bo", "dy></html>"]), expected);
+        let single_bytes: Vec<String> = "<html><body>Hi</body></html>".chars().map(|c| c.to_string()).collect();
+        assert_eq!(substitute_in_pieces(&replacements, &single_bytes.iter().map(|s| s.as_str()).collect::<Vec<_>>()), expected);
+
+        // The earliest match wins, also when a shorter search text is complete before a longer one is
+        let replacements = [("b", "B"), ("abc", "X")];
+        assert_eq!(substitute_in_pieces(&replacements, &["ab", "c b"]), "X B");
+        assert_eq!(substitute_in_pieces(&replacements, &["ab"]), "aB");
+        assert_eq!(substitute_in_pieces(&[("aa", "b")], &["aaa", "a"]), "bb");
+
+        // Of matches starting at the same place, the first configured one wins
+        assert_eq!(substitute_in_pieces(&[("ab", "1"), ("abc", "2")], &["a", "bc"]), "1c");
+        assert_eq!(substitute_in_pieces(&[("abc", "2"), ("ab", "1")], &["a", "bc"]), "2");
+    }
+
+    #[test]
+    fn test_hostname_is_escaped() {
+        let mut gruxi_request = GruxiRequest::new(hyper::Request::builder().uri("/").header("Host", "a\"><script>").body(Bytes::new()).unwrap());
+        assert_eq!(expand_replacement(&mut gruxi_request, "<p>${hostname}</p>"), "<p>a&quot;&gt;&lt;script&gt;</p>");
+    }
+
+    #[test]
+    fn test_pending_text_is_bounded() {
+        let mut substituter = create_substituter(&[("{{GRUX_REQUEST_ID}}", "1")]);
+        let output = substituter.process(&[b'x'; 10000], false);
+        assert_eq!(output.len(), 10000 - ("{{GRUX_REQUEST_ID}}".len() - 1));
+        assert!(substituter.pending.len() < "{{GRUX_REQUEST_ID}}".len());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_response_is_substituted() {
+        let mut site = Site::new();
+        site.body_substitutions = vec![BodySubstitution {
+            search: "{{GRUX_REQUEST_ID}}".to_string(),
+            replacement: "${request_id}".to_string(),
+            content_types: vec!["text/html".to_string()],
+        }];
+        let mut gruxi_request = GruxiRequest::new(hyper::Request::builder().uri("/").body(Bytes::new()).unwrap());
+        let request_id = gruxi_request.get_request_id();
+
+        let pieces: Vec<Result<Frame<Bytes>, BodyError>> = vec![Ok(Frame::data(Bytes::from("<p>{{GRUX_REQ"))), Ok(Frame::data(Bytes::from("UEST_ID}}</p>")))];
+        let mut response = GruxiResponse::new_with_body(200, BoxBody::new(StreamBody::new(futures::stream::iter(pieces))));
+        response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+        response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from_static("26"));
+        apply_body_substitutions(&mut gruxi_request, &site, &mut response);
+        assert!(response.get_header("Content-Length").is_none());
+        let body = response.into_hyper().into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, format!("<p>{}</p>", request_id));
+
+        // Other content types are left alone
+        let mut response = GruxiResponse::new_with_bytes(200, "{{GRUX_REQUEST_ID}}");
+        response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        apply_body_substitutions(&mut gruxi_request, &site, &mut response);
+        assert_eq!(response.get_body_bytes().await, "{{GRUX_REQUEST_ID}}");
+    }
+}
@@ -0,0 +1,127 @@
+use crate::configuration::site::{CachePolicy, Site};
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use chrono::Utc;
+use hyper::header::HeaderValue;
+
+// Finds the first cache policy of the site matching the path. Patterns are either:
+// - "*" for all paths
+// - "*.ext" for files with that extension (case insensitive)
+// - "/prefix/" for paths starting with the prefix
+pub fn find_cache_policy<'a>(policies: &'a [CachePolicy], path: &str) -> Option<&'a CachePolicy> {
+    policies.iter().find(|policy| cache_policy_matches(&policy.pattern, path))
+}
+
+fn cache_policy_matches(pattern: &str, path: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    if let Some(extension) = pattern.strip_prefix("*.") {
+        let file_name = path.rsplit('/').next().unwrap_or("");
+        return file_name.to_lowercase().ends_with(&format!(".{}", extension.to_lowercase()));
+    }
+    path.starts_with(pattern)
+}
+
+// Applies the site's cache policy to successful responses. Responses that already carry a Cache-Control header,
+// such as those from PHP or proxied backends, are only changed if the policy is set to override them
+pub fn apply_cache_policy(response: &mut GruxiResponse, site: &Site, path: &str) {
+    let status = response.get_status();
+    if !(200..300).contains(&status) && status != hyper::StatusCode::NOT_MODIFIED.as_u16() {
+        return;
+    }
+
+    let policy = match find_cache_policy(&site.cache_policies, path) {
+        Some(policy) => policy,
+        None => return,
+    };
+
+    if response.get_header("Cache-Control").is_some() && !policy.override_backend {
+        return;
+    }
+
+    if !policy.cache_control.is_empty()
+        && let Ok(header_value) = HeaderValue::from_str(&policy.cache_control)
+    {
+        response.headers_mut().insert(hyper::header::CACHE_CONTROL, header_value);
+    }
+
+    if policy.expires_seconds > 0 {
+        let expires = Utc::now() + chrono::Duration::seconds(policy.expires_seconds as i64);
+        if let Ok(header_value) = HeaderValue::from_str(&expires.format("%a, %d %b %Y %H:%M:%S GMT").to_string()) {
+            response.headers_mut().insert(hyper::header::EXPIRES, header_value);
+        }
+    } else if policy.override_backend {
+        // A backend Expires header would contradict the Cache-Control we just set
+        response.headers_mut().remove(hyper::header::EXPIRES);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_policy(pattern: &str, cache_control: &str, expires_seconds: u64, override_backend: bool) -> CachePolicy {
+        CachePolicy {
+            pattern: pattern.to_string(),
+            cache_control: cache_control.to_string(),
+            expires_seconds,
+            override_backend,
+        }
+    }
+
+    fn create_site() -> Site {
+        let mut site = Site::new();
+        site.cache_policies = vec![
+            create_policy("/static/", "public, max-age=31536000, immutable", 0, false),
+            create_policy("*.html", "no-cache", 0, true),
+            create_policy("*.css", "public, max-age=3600", 3600, false),
+        ];
+        site
+    }
+
+    #[test]
+    fn test_find_cache_policy_matching() {
+        let site = create_site();
+        assert_eq!(find_cache_policy(&site.cache_policies, "/static/app.js").unwrap().pattern, "/static/");
+        assert_eq!(find_cache_policy(&site.cache_policies, "/static/page.html").unwrap().pattern, "/static/");
+        assert_eq!(find_cache_policy(&site.cache_policies, "/about/INDEX.HTML").unwrap().pattern, "*.html");
+        assert_eq!(find_cache_policy(&site.cache_policies, "/css/site.css").unwrap().pattern, "*.css");
+        assert!(find_cache_policy(&site.cache_policies, "/css.d/site").is_none());
+        assert!(find_cache_policy(&site.cache_policies, "/index.php").is_none());
+        assert!(find_cache_policy(&[create_policy("*", "no-store", 0, false)], "/anything").is_some());
+    }
+
+    #[test]
+    fn test_apply_cache_policy() {
+        let site = create_site();
+
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        apply_cache_policy(&mut response, &site, "/css/site.css");
+        assert_eq!(response.get_header("Cache-Control").unwrap(), "public, max-age=3600");
+        assert!(response.get_header("Expires").unwrap().to_str().unwrap().ends_with(" GMT"));
+
+        // Error responses are not cached
+        let mut response = GruxiResponse::new_empty_with_status(404);
+        apply_cache_policy(&mut response, &site, "/css/site.css");
+        assert!(response.get_header("Cache-Control").is_none());
+    }
+
+    #[test]
+    fn test_apply_cache_policy_backend_override() {
+        let site = create_site();
+
+        // Kept, as the policy does not override the backend
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        response.headers_mut().insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("private"));
+        apply_cache_policy(&mut response, &site, "/static/app.js");
+        assert_eq!(response.get_header("Cache-Control").unwrap(), "private");
+
+        // Overridden, and the backend Expires is removed
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        response.headers_mut().insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("max-age=600"));
+        response.headers_mut().insert(hyper::header::EXPIRES, HeaderValue::from_static("Thu, 01 Dec 2033 16:00:00 GMT"));
+        apply_cache_policy(&mut response, &site, "/index.html");
+        assert_eq!(response.get_header("Cache-Control").unwrap(), "no-cache");
+        assert!(response.get_header("Expires").is_none());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::configuration::site::Site;
+use crate::core::running_state::RunningState;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::http::try_files::find_in_web_roots;
+use crate::logging::syslog::trace;
+use hyper::header::HeaderValue;
+
+// Redirects GET and HEAD requests to the canonical URL of the site with a 301, so search engines and PHP routing only ever see one
+// form of each path: lowercase when the site enforces it, and with or without the trailing slash as the site wants it. The query is kept
+pub async fn get_canonical_redirect(gruxi_request: &mut GruxiRequest, site: &Site, running_state: &RunningState) -> Option<GruxiResponse> {
+    if site.trailing_slash_redirect == "off" && !site.lowercase_redirect_enabled {
+        return None;
+    }
+    let http_method = gruxi_request.get_http_method();
+    if http_method != "GET" && http_method != "HEAD" {
+        return None;
+    }
+
+    let path = gruxi_request.get_path();
+    let web_roots = running_state.get_request_handler_manager().get_site_web_roots(site, running_state.get_processor_manager()).await;
+    let canonical_path = get_canonical_path(&path, site, &web_roots).await?;
+
+    let query = gruxi_request.get_query();
+    let location = if query.is_empty() { canonical_path } else { format!("{}?{}", canonical_path, query) };
+    let location = HeaderValue::from_str(&location).ok()?;
+    trace(format!("[canonical_redirect] Redirecting request path '{}' to '{:?}'", path, location));
+
+    let mut response = GruxiResponse::new_empty_with_status(hyper::StatusCode::MOVED_PERMANENTLY.as_u16());
+    response.headers_mut().insert(hyper::header::LOCATION, location);
+    Some(response)
+}
+
+// The canonical form of the request path, or None if it already is. Whether the path is a directory is looked up in the web roots,
+// only when the trailing slash depends on it. Paths starting with // or /\ are left alone, as browsers take a Location such as
+// //evil.com for another host
+async fn get_canonical_path(path: &str, site: &Site, web_roots: &[String]) -> Option<String> {
+    if path.starts_with("//") || path.starts_with("/\\") {
+        return None;
+    }
+    let mut canonical_path = if site.lowercase_redirect_enabled { lowercase_path(path) } else { path.to_string() };
+
+    if canonical_path != "/" {
+        let has_trailing_slash = canonical_path.ends_with('/');
+        match site.trailing_slash_redirect.as_str() {
+            "add" if !has_trailing_slash && find_in_web_roots(web_roots, &canonical_path).await == Some(true) => {
+                canonical_path.push('/');
+            }
+            "remove" if has_trailing_slash && find_in_web_roots(web_roots, &canonical_path).await != Some(true) => {
+                canonical_path = canonical_path.trim_end_matches('/').to_string();
+                if canonical_path.is_empty() {
+                    canonical_path = "/".to_string();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if canonical_path == path { None } else { Some(canonical_path) }
+}
+
+// Lowercases the letters of a path as sent, leaving percent-encodings alone, as "%2F" and "%2f" are the same character anyway
+fn lowercase_path(path: &str) -> String {
+    let mut lowercased = String::with_capacity(path.len());
+    let mut encoding_digits_left = 0;
+    for ch in path.chars() {
+        if encoding_digits_left > 0 {
+            encoding_digits_left -= 1;
+            lowercased.push(ch);
+        } else if ch == '%' {
+            encoding_digits_left = 2;
+            lowercased.push(ch);
+        } else {
+            lowercased.push(ch.to_ascii_lowercase());
+        }
+    }
+    lowercased
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_site(trailing_slash_redirect: &str, lowercase_redirect_enabled: bool) -> Site {
+        let mut site = Site::new();
+        site.trailing_slash_redirect = trailing_slash_redirect.to_string();
+        site.lowercase_redirect_enabled = lowercase_redirect_enabled;
+        site
+    }
+
+    #[test]
+    fn test_lowercase_path() {
+        assert_eq!(lowercase_path("/About/Team.HTML"), "/about/team.html");
+        assert_eq!(lowercase_path("/Caf%C3%A9/A%2Fb"), "/caf%C3%A9/a%2Fb");
+        assert_eq!(lowercase_path("/already/lowercase"), "/already/lowercase");
+    }
+
+    #[tokio::test]
+    async fn test_canonical_path() {
+        let web_root = std::env::temp_dir().join(format!("gruxi-test-canonical-redirect-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(web_root.join("docs")).unwrap();
+        std::fs::write(web_root.join("about.html"), "<html></html>").unwrap();
+        let web_roots = vec![web_root.to_string_lossy().to_string()];
+
+        // Directories get their trailing slash, other paths are left alone
+        let site = create_site("add", false);
+        assert_eq!(get_canonical_path("/docs", &site, &web_roots).await, Some("/docs/".to_string()));
+        assert_eq!(get_canonical_path("/docs/", &site, &web_roots).await, None);
+        assert_eq!(get_canonical_path("/about.html", &site, &web_roots).await, None);
+        assert_eq!(get_canonical_path("/users/5", &site, &web_roots).await, None);
+
+        // Paths that are not directories lose their trailing slash, such as the routes of a PHP application
+        let site = create_site("remove", false);
+        assert_eq!(get_canonical_path("/users/5/", &site, &web_roots).await, Some("/users/5".to_string()));
+        assert_eq!(get_canonical_path("/docs/", &site, &web_roots).await, None);
+        assert_eq!(get_canonical_path("/", &site, &web_roots).await, None);
+
+        // Lowercasing and the trailing slash end up in one redirect
+        let site = create_site("add", true);
+        assert_eq!(get_canonical_path("/DOCS", &site, &web_roots).await, Some("/docs/".to_string()));
+        assert_eq!(get_canonical_path("/About.html", &site, &web_roots).await, Some("/about.html".to_string()));
+        assert_eq!(get_canonical_path("/about.html", &site, &web_roots).await, None);
+
+        // Paths a browser would take for another host are never redirected to
+        let site = create_site("remove", false);
+        assert_eq!(get_canonical_path("//evil.com/", &site, &web_roots).await, None);
+        assert_eq!(get_canonical_path("/\\evil.com/", &site, &web_roots).await, None);
+        let site = create_site("off", true);
+        assert_eq!(get_canonical_path("//EVIL.com/x", &site, &web_roots).await, None);
+        assert_eq!(get_canonical_path("/\\EVIL.com/x", &site, &web_roots).await, None);
+        let _ = std::fs::remove_dir_all(&web_root);
+    }
+}
@@ -1,62 +1,258 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
 
-use hyper_rustls::HttpsConnector;
+use hyper_rustls::{FixedServerNameResolver, HttpsConnector};
 use hyper_util::client::legacy::Client;
 use hyper_util::client::legacy::connect::HttpConnector;
-use hyper_util::rt::TokioExecutor;
+use hyper_util::client::legacy::connect::dns::Name;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
 
 use http_body_util::combinators::BoxBody;
 use hyper::body::Bytes;
 
+use crate::configuration::upstream_tls::UpstreamTlsSettings;
+use crate::core::monitoring::get_monitoring_state;
 use crate::http::request_handlers::processors::proxy_helpers::no_verifier::NoVerifier;
+use crate::http::request_response::body_error::BodyError;
+use crate::http::request_response::request_timings::{RequestPhase, get_current_request_timings};
+use crate::logging::syslog::error;
+use crate::network::dns_cache::get_dns_cache;
+use crate::network::outbound_connection::{HAPPY_EYEBALLS_DELAY, OutboundTimeouts, TimeoutStream, get_outbound_timeouts};
 use crate::tls::tls_config::tls_config;
+use crate::tls::upstream_tls::{get_server_name_override, get_upstream_tls_config};
+use rustls::ClientConfig;
+use rustls::pki_types::ServerName;
+
+type GruxiConnector = HttpsConnector<TimeoutConnector>;
+type GruxiClient = Client<GruxiConnector, GruxiRequestBody>;
 
 pub struct HttpClient {
-    client_with_tls_verify: Client<HttpsConnector<HttpConnector>, GruxiRequestBody>,
-    client_without_tls_verify: Client<HttpsConnector<HttpConnector>, GruxiRequestBody>
+    client_with_tls_verify: GruxiClient,
+    client_without_tls_verify: GruxiClient,
+    // Clients for upstreams with their own TLS settings, built with the HTTP client, so reading a CA bundle or the system certificates
+    // does not block request handling, and kept, so their connections are pooled as well. Settings that failed keep their error
+    clients_with_tls_settings: HashMap<(bool, UpstreamTlsSettings), Result<GruxiClient, String>>,
 }
 
 // Request body type used by Gruxi's outbound HTTP client.
 // Note: responses are still Response<hyper::body::Incoming>.
-type GruxiRequestBody = BoxBody<Bytes, hyper::Error>;
+type GruxiRequestBody = BoxBody<Bytes, BodyError>;
 
+// Connections to upstreams are kept alive and reused by later requests to the same scheme, host and port. HTTP/1.1 connections
+// take one request at a time, so up to max_idle_per_host are kept when idle, while HTTP/2 upstreams, negotiated with ALPN, get
+// all requests multiplexed over a single connection. Idle connections are closed after the idle timeout
 impl HttpClient {
-    pub fn new() -> Self {
+    pub fn new<'a>(pool_max_idle_per_host: usize, pool_idle_timeout: Duration, upstream_tls_settings: impl IntoIterator<Item = (bool, &'a UpstreamTlsSettings)>) -> Self {
         // Client with TLS certificate verification, for streaming bodies
-        let tls_config_with_verify = tls_config();
-        let https_with_verify = hyper_rustls::HttpsConnectorBuilder::new()
-            .with_tls_config(tls_config_with_verify)
-            .https_or_http()
-            .enable_http1()
-            .enable_http2()
-            .build();
-
-        let client_with_tls_verify: Client<_, GruxiRequestBody> = Client::builder(TokioExecutor::new()).build(https_with_verify);
+        let client_with_tls_verify = build_client(tls_config(), None, pool_max_idle_per_host, pool_idle_timeout, get_outbound_timeouts());
 
         // Client without TLS certificate verification, for streaming bodies
         let mut tls_config_with_no_verify = tls_config();
         tls_config_with_no_verify.dangerous().set_certificate_verifier(Arc::new(NoVerifier));
+        let client_without_tls_verify = build_client(tls_config_with_no_verify, None, pool_max_idle_per_host, pool_idle_timeout, get_outbound_timeouts());
 
-        let https_without_verify = hyper_rustls::HttpsConnectorBuilder::new()
-            .with_tls_config(tls_config_with_no_verify)
-            .https_or_http()
-            .enable_http1()
-            .enable_http2()
-            .build();
-
-        let client_without_tls_verify: Client<_, GruxiRequestBody> = Client::builder(TokioExecutor::new()).build(https_without_verify);
+        let mut clients_with_tls_settings = HashMap::new();
+        for (verify_tls, tls_settings) in upstream_tls_settings {
+            if tls_settings.is_default() || clients_with_tls_settings.contains_key(&(verify_tls, tls_settings.clone())) {
+                continue;
+            }
+            let client = get_upstream_tls_config(verify_tls, tls_settings)
+                .map(|tls_config| build_client(tls_config, get_server_name_override(tls_settings), pool_max_idle_per_host, pool_idle_timeout, get_outbound_timeouts()));
+            if let Err(e) = &client {
+                error(format!("Failed to set up TLS for upstream servers: {}", e));
+            }
+            clients_with_tls_settings.insert((verify_tls, tls_settings.clone()), client);
+        }
 
         Self {
             client_with_tls_verify,
-            client_without_tls_verify
+            client_without_tls_verify,
+            clients_with_tls_settings,
         }
     }
 
-    pub fn get_client(&self, verify_tls: bool) -> Client<HttpsConnector<HttpConnector>, GruxiRequestBody> {
+    pub fn get_client(&self, verify_tls: bool) -> GruxiClient {
         if verify_tls {
             self.client_with_tls_verify.clone()
         } else {
             self.client_without_tls_verify.clone()
         }
     }
+
+    // The client for an upstream with its TLS settings, such as a CA bundle, SNI override or pinned certificates. Fails when the
+    // TLS configuration could not be built, such as for a CA bundle that cannot be read, or the settings are not in the configuration
+    pub fn get_client_with_tls_settings(&self, verify_tls: bool, tls_settings: &UpstreamTlsSettings) -> Result<GruxiClient, String> {
+        if tls_settings.is_default() {
+            return Ok(self.get_client(verify_tls));
+        }
+
+        match self.clients_with_tls_settings.get(&(verify_tls, tls_settings.clone())) {
+            Some(client) => client.clone(),
+            None => Err("No client was set up for these TLS settings, they are not in the loaded configuration".to_string()),
+        }
+    }
+}
+
+fn build_client(tls_config: ClientConfig, server_name: Option<ServerName<'static>>, pool_max_idle_per_host: usize, pool_idle_timeout: Duration, timeouts: OutboundTimeouts) -> GruxiClient {
+    let mut https_builder = hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(tls_config).https_or_http();
+    if let Some(server_name) = server_name {
+        https_builder = https_builder.with_server_name_resolver(FixedServerNameResolver::new(server_name));
+    }
+    let https = https_builder.enable_http1().enable_http2().wrap_connector(get_timeout_connector(timeouts));
+
+    let mut client_builder = Client::builder(TokioExecutor::new());
+    client_builder
+        .pool_timer(TokioTimer::new())
+        .pool_max_idle_per_host(pool_max_idle_per_host)
+        .pool_idle_timeout(pool_idle_timeout);
+    client_builder.build(https)
+}
+
+// Upstream hosts are resolved through the DNS cache, rather than once per connection, and connections get the outbound timeouts
+fn get_timeout_connector(timeouts: OutboundTimeouts) -> TimeoutConnector {
+    let mut http = HttpConnector::new_with_resolver(CachedResolver);
+    // The HTTPS connector handles https:// itself
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(timeouts.connect));
+    // Addresses of the other IP family are tried when the first one is slow to connect, to not hang on broken IPv6
+    http.set_happy_eyeballs_timeout(Some(HAPPY_EYEBALLS_DELAY));
+    TimeoutConnector { http, timeouts }
+}
+
+// Wraps the connections of the HTTP connector, to time out reads and writes to the upstream servers
+#[derive(Clone)]
+pub struct TimeoutConnector {
+    http: HttpConnector<CachedResolver>,
+    timeouts: OutboundTimeouts,
+}
+
+impl tower_service::Service<hyper::Uri> for TimeoutConnector {
+    type Response = TimeoutStream<TokioIo<TcpStream>>;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: hyper::Uri) -> Self::Future {
+        // Only called when no pooled connection can be reused, so this counts the connections opened to each upstream
+        let upstream = uri.authority().map(|authority| authority.to_string()).unwrap_or_default();
+        let connecting = self.http.call(uri);
+        let timeouts = self.timeouts;
+        // The connection may be made outside of the request task, so the timings are taken along
+        let request_timings = get_current_request_timings();
+        Box::pin(async move {
+            let connect_started = Instant::now();
+            let stream = connecting.await?;
+            get_monitoring_state().await.increment_upstream_connections_opened(&upstream);
+            if let Some(request_timings) = request_timings {
+                request_timings.add(RequestPhase::BackendConnect, connect_started.elapsed());
+            }
+            Ok(TimeoutStream::new(stream, timeouts))
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct CachedResolver;
+
+impl tower_service::Service<Name> for CachedResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        Box::pin(async move {
+            // The connector sets the port of the addresses itself
+            let addresses = get_dns_cache().resolve(name.as_str()).await?;
+            Ok(addresses.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect::<Vec<_>>().into_iter())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Empty, Full};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Serves keep-alive HTTP/1.1 connections on a local port, counting the connections accepted
+    async fn spawn_counting_server() -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(|_request| async { Ok::<_, std::convert::Infallible>(hyper::Response::new(Full::new(Bytes::from_static(b"ok")))) });
+                    let _ = hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+        (address, connections)
+    }
+
+    async fn get(client: &GruxiClient, address: SocketAddr) -> Result<Bytes, String> {
+        let request = hyper::Request::get(format!("http://{}/", address)).body(Empty::new().map_err(|never| match never {}).boxed()).unwrap();
+        let response = client.request(request).await.map_err(|e| e.to_string())?;
+        Ok(response.into_body().collect().await.map_err(|e| e.to_string())?.to_bytes())
+    }
+
+    #[tokio::test]
+    async fn test_connection_idle_longer_than_read_timeout_is_not_reused() {
+        let (address, connections) = spawn_counting_server().await;
+        let timeouts = OutboundTimeouts {
+            connect: Duration::from_secs(1),
+            read: Duration::from_millis(300),
+            write: Duration::from_secs(1),
+        };
+        let client = build_client(tls_config(), None, 4, Duration::from_millis(200), timeouts);
+
+        // A request right after the first reuses its connection
+        assert_eq!(get(&client, address).await.unwrap(), Bytes::from_static(b"ok"));
+        assert_eq!(get(&client, address).await.unwrap(), Bytes::from_static(b"ok"));
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        // A connection idle for longer than the read timeout has left the pool before its read deadline ended it, so the request gets a new one
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(get(&client, address).await.unwrap(), Bytes::from_static(b"ok"));
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_clients_with_tls_settings_are_built_up_front() {
+        let with_server_name = UpstreamTlsSettings {
+            server_name: "backend.internal".to_string(),
+            ..Default::default()
+        };
+        let with_missing_ca_bundle = UpstreamTlsSettings {
+            ca_bundle_file: "/nonexistent/gruxi-ca.pem".to_string(),
+            ..Default::default()
+        };
+        let http_client = HttpClient::new(4, Duration::from_secs(10), [(true, &with_server_name), (true, &with_missing_ca_bundle), (true, &with_server_name)]);
+
+        assert!(http_client.get_client_with_tls_settings(true, &UpstreamTlsSettings::default()).is_ok());
+        assert!(http_client.get_client_with_tls_settings(true, &with_server_name).is_ok());
+        // The error of the CA bundle is kept, rather than the bundle being read again by each request
+        assert!(
+            http_client
+                .get_client_with_tls_settings(true, &with_missing_ca_bundle)
+                .unwrap_err()
+                .contains("Failed to read CA bundle")
+        );
+        assert!(http_client.get_client_with_tls_settings(false, &with_server_name).is_err());
+    }
 }
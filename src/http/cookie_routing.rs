@@ -0,0 +1,96 @@
+use crate::configuration::site::{CookieRoute, Site};
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use hyper::header::HeaderValue;
+
+// How long a client stays opted in to a cookie route
+const ROUTE_COOKIE_MAX_AGE_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+// Answers a request to the opt-in path of a cookie route, by setting its cookie, or removing it with ?opt_out=1, and sending the client
+// on to the start page of the site, which it then gets from the request handlers of the route
+pub fn handle_cookie_route_opt_in(gruxi_request: &mut GruxiRequest, site: &Site) -> Option<GruxiResponse> {
+    if site.cookie_routes.is_empty() {
+        return None;
+    }
+    let path = gruxi_request.get_path();
+    let cookie_route = site.cookie_routes.iter().find(|route| !route.opt_in_path.is_empty() && route.opt_in_path == path)?;
+
+    let opt_out = gruxi_request.get_query().split('&').any(|parameter| parameter == "opt_out=1");
+    let set_cookie = if opt_out {
+        format!("{}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax", cookie_route.cookie_name)
+    } else {
+        format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+            cookie_route.cookie_name, cookie_route.value, ROUTE_COOKIE_MAX_AGE_SECONDS
+        )
+    };
+
+    let mut response = GruxiResponse::new_empty_with_status(hyper::StatusCode::FOUND.as_u16());
+    let headers = response.headers_mut();
+    headers.insert(hyper::header::LOCATION, HeaderValue::from_static("/"));
+    headers.insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if let Ok(header_value) = HeaderValue::from_str(&set_cookie) {
+        headers.append(hyper::header::SET_COOKIE, header_value);
+    }
+    Some(response)
+}
+
+// The first cookie route of the site the client has the cookie of
+pub fn get_cookie_route<'a>(gruxi_request: &GruxiRequest, site: &'a Site) -> Option<&'a CookieRoute> {
+    site.cookie_routes
+        .iter()
+        .find(|route| gruxi_request.get_cookie(&route.cookie_name).is_some_and(|value| value == route.value))
+}
+
+// Responses of a site routing by cookies differ by the cookies, which caches must know
+pub fn add_cookie_routing_vary(response: &mut GruxiResponse, site: &Site) {
+    if !site.cookie_routes.is_empty() {
+        crate::http::http_util::add_vary_field(response, "Cookie");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_request(uri: &str, cookie: Option<&str>) -> GruxiRequest {
+        let mut builder = hyper::Request::builder().uri(uri);
+        if let Some(cookie) = cookie {
+            builder = builder.header("Cookie", cookie);
+        }
+        GruxiRequest::new(builder.body(hyper::body::Bytes::new()).unwrap())
+    }
+
+    fn create_site() -> Site {
+        let mut site = Site::new();
+        site.cookie_routes = vec![CookieRoute {
+            cookie_name: "canary".to_string(),
+            value: "1".to_string(),
+            request_handlers: vec!["staging".to_string()],
+            opt_in_path: "/_canary".to_string(),
+        }];
+        site
+    }
+
+    #[test]
+    fn test_cookie_route_matches_cookie_value() {
+        let site = create_site();
+        assert!(get_cookie_route(&create_request("/", Some("session=abc; canary=1")), &site).is_some());
+        assert!(get_cookie_route(&create_request("/", Some("canary=0")), &site).is_none());
+        assert!(get_cookie_route(&create_request("/", None), &site).is_none());
+    }
+
+    #[test]
+    fn test_opt_in_path_sets_and_removes_cookie() {
+        let site = create_site();
+        let response = handle_cookie_route_opt_in(&mut create_request("/_canary", None), &site).unwrap();
+        assert_eq!(response.get_status(), 302);
+        assert_eq!(response.get_header("Location").unwrap(), "/");
+        assert!(response.get_header("Set-Cookie").unwrap().to_str().unwrap().starts_with("canary=1; Path=/; Max-Age=2592000;"));
+
+        let response = handle_cookie_route_opt_in(&mut create_request("/_canary?opt_out=1", Some("canary=1")), &site).unwrap();
+        assert!(response.get_header("Set-Cookie").unwrap().to_str().unwrap().starts_with("canary=; Path=/; Max-Age=0;"));
+
+        assert!(handle_cookie_route_opt_in(&mut create_request("/other", None), &site).is_none());
+    }
+}
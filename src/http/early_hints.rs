@@ -0,0 +1,233 @@
+use crate::configuration::site::Site;
+use crate::http::keep_alive::ConnectionTracker;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::logging::syslog::trace;
+use hyper::header::{HeaderValue, LINK};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+tokio::task_local! {
+    // Early hints of the request being handled by the current task, so handlers can send them wherever they learn about them
+    pub static CURRENT_EARLY_HINTS: EarlyHints;
+}
+
+// The Link headers, such as </css/app.css>; rel=preload; as=style, sent to the client in 103 Early Hints responses before the final
+// response, so browsers can start loading them while the backend is still working. hyper has no way to send interim responses, so
+// they are written to HTTP/1.1 connections directly, see ConnectionTracker::write_interim_response. The links sent are also added to
+// the final response, which is how HTTP/2 clients get them
+#[derive(Clone)]
+pub struct EarlyHints {
+    sender: mpsc::UnboundedSender<Vec<HeaderValue>>,
+    links: Arc<Mutex<Vec<HeaderValue>>>,
+}
+
+impl EarlyHints {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Vec<HeaderValue>>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (
+            EarlyHints {
+                sender,
+                links: Arc::new(Mutex::new(Vec::new())),
+            },
+            receiver,
+        )
+    }
+
+    // Sends the links not sent before in a 103 response, if the connection allows one
+    pub fn send(&self, links: impl IntoIterator<Item = HeaderValue>) {
+        let mut sent_links = self.links.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut new_links = Vec::new();
+        for link in links {
+            if !sent_links.contains(&link) && !new_links.contains(&link) {
+                new_links.push(link);
+            }
+        }
+        if new_links.is_empty() {
+            return;
+        }
+        sent_links.extend(new_links.iter().cloned());
+        // Nothing receives the hints when the connection cannot take interim responses
+        let _ = self.sender.send(new_links);
+    }
+
+    // Adds the links sent as early hints to a successful final response, unless it has them already
+    pub fn add_links_to_response(&self, response: &mut GruxiResponse) {
+        if !(200..300).contains(&response.get_status()) {
+            return;
+        }
+        let sent_links = self.links.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for link in sent_links.iter() {
+            if !response.headers().get_all(LINK).iter().any(|existing| existing == link) {
+                response.headers_mut().append(LINK, link.clone());
+            }
+        }
+    }
+}
+
+pub fn get_current_early_hints() -> Option<EarlyHints> {
+    CURRENT_EARLY_HINTS.try_with(|early_hints| early_hints.clone()).ok()
+}
+
+// Sends early hints for the request being handled, for handlers knowing what the final response will need
+pub fn send_early_hints(links: Vec<HeaderValue>) {
+    if let Some(early_hints) = get_current_early_hints() {
+        early_hints.send(links);
+    }
+}
+
+// Sends the early hints configured for the site, for requests of browsers navigating to a page
+pub fn send_site_early_hints(gruxi_request: &mut GruxiRequest, site: &Site) {
+    if !site.early_hints_enabled || site.early_hints.is_empty() || !is_navigation_request(gruxi_request) {
+        return;
+    }
+    send_early_hints(site.early_hints.iter().filter_map(|link| HeaderValue::from_str(link).ok()).collect());
+}
+
+// Passes the 103 responses of the upstream on to the client, as they come before the final response of the upstream. Only HTTP/1
+// upstreams report them
+pub fn forward_upstream_early_hints<B>(upstream_request: &mut hyper::Request<B>) {
+    let Some(early_hints) = get_current_early_hints() else {
+        return;
+    };
+    hyper::ext::on_informational(upstream_request, move |response| {
+        if response.status() == hyper::StatusCode::EARLY_HINTS {
+            early_hints.send(response.headers().get_all(LINK).iter().cloned());
+        }
+    });
+}
+
+// Browsers loading a page ask for HTML, while the requests for its assets, which early hints are of no use for, do not
+fn is_navigation_request(gruxi_request: &mut GruxiRequest) -> bool {
+    if gruxi_request.get_http_method() != "GET" {
+        return false;
+    }
+    let headers = gruxi_request.get_headers();
+    if let Some(fetch_dest) = headers.get("sec-fetch-dest").and_then(|v| v.to_str().ok()) {
+        return fetch_dest.eq_ignore_ascii_case("document");
+    }
+    headers.get(hyper::header::ACCEPT).and_then(|v| v.to_str().ok()).is_some_and(|accept| accept.contains("text/html"))
+}
+
+fn format_early_hints_response(links: &[HeaderValue]) -> Vec<u8> {
+    let mut response = b"HTTP/1.1 103 Early Hints\r\n".to_vec();
+    for link in links {
+        response.extend_from_slice(b"Link: ");
+        response.extend_from_slice(link.as_bytes());
+        response.extend_from_slice(b"\r\n");
+    }
+    response.extend_from_slice(b"\r\n");
+    response
+}
+
+// Runs the handling of a request, writing the early hints sent meanwhile to the connection as 103 responses. Writing stops once the
+// final response is ready and the hints sent until then are written, as hyper writes the final response next. A write taking longer
+// than the timeout, to a client that does not read, drops the rest of the hints rather than holding up the final response
+pub async fn write_early_hints_while<F: Future>(handling: F, mut receiver: mpsc::UnboundedReceiver<Vec<HeaderValue>>, connection_tracker: &ConnectionTracker, write_timeout: Duration) -> F::Output {
+    let handling_done = CancellationToken::new();
+    let handling = async {
+        let output = handling.await;
+        handling_done.cancel();
+        output
+    };
+    let writing = async {
+        loop {
+            // The hints sent before the final response was ready are written first, even by handlers answering without waiting
+            let links = tokio::select! {
+                biased;
+                links = receiver.recv() => match links {
+                    Some(links) => links,
+                    None => break,
+                },
+                _ = handling_done.cancelled() => break,
+            };
+            // A write is never cancelled halfway, as the final response would be written into it
+            if let Err(e) = connection_tracker.write_interim_response(&format_early_hints_response(&links), write_timeout).await {
+                trace(format!("Failed to write early hints to the connection: {}", e));
+                break;
+            }
+        }
+    };
+    let (output, _) = tokio::join!(handling, writing);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_request(headers: &[(&str, &str)]) -> GruxiRequest {
+        let mut builder = hyper::Request::builder().method("GET").uri("/");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        GruxiRequest::new(builder.body(hyper::body::Bytes::new()).unwrap())
+    }
+
+    #[test]
+    fn test_navigation_requests() {
+        assert!(is_navigation_request(&mut create_request(&[("accept", "text/html,application/xhtml+xml,*/*;q=0.8")])));
+        assert!(is_navigation_request(&mut create_request(&[("sec-fetch-dest", "document"), ("accept", "*/*")])));
+        assert!(!is_navigation_request(&mut create_request(&[("sec-fetch-dest", "style"), ("accept", "text/html")])));
+        assert!(!is_navigation_request(&mut create_request(&[("accept", "image/avif,image/webp,*/*")])));
+    }
+
+    #[tokio::test]
+    async fn test_early_hints_are_sent_once_and_added_to_the_response() {
+        let (early_hints, mut receiver) = EarlyHints::new();
+        let style = HeaderValue::from_static("</app.css>; rel=preload; as=style");
+        let script = HeaderValue::from_static("</app.js>; rel=preload; as=script");
+        early_hints.send(vec![style.clone(), style.clone()]);
+        early_hints.send(vec![style.clone(), script.clone()]);
+        early_hints.send(vec![script.clone()]);
+        assert_eq!(receiver.recv().await.unwrap(), vec![style.clone()]);
+        assert_eq!(receiver.recv().await.unwrap(), vec![script.clone()]);
+        assert!(receiver.try_recv().is_err());
+
+        let mut response = GruxiResponse::new_with_bytes(200, "<html></html>");
+        response.headers_mut().append(LINK, style.clone());
+        early_hints.add_links_to_response(&mut response);
+        let links: Vec<_> = response.headers().get_all(LINK).iter().cloned().collect();
+        assert_eq!(links, vec![style, script]);
+
+        let mut not_found = GruxiResponse::new_empty_with_status(404);
+        early_hints.add_links_to_response(&mut not_found);
+        assert!(not_found.get_header("link").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_early_hints_are_forwarded() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await.unwrap();
+            let response = "HTTP/1.1 103 Early Hints\r\nLink: </app.css>; rel=preload; as=style\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let (early_hints, mut receiver) = EarlyHints::new();
+        let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new()).build_http::<http_body_util::Empty<hyper::body::Bytes>>();
+        let mut request = hyper::Request::get(format!("http://{}/", upstream_address)).body(http_body_util::Empty::new()).unwrap();
+        let response = CURRENT_EARLY_HINTS
+            .scope(early_hints, async move {
+                forward_upstream_early_hints(&mut request);
+                client.request(request).await
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(receiver.recv().await.unwrap(), vec![HeaderValue::from_static("</app.css>; rel=preload; as=style")]);
+    }
+
+    #[test]
+    fn test_format_early_hints_response() {
+        let response = format_early_hints_response(&[HeaderValue::from_static("</app.css>; rel=preload; as=style")]);
+        assert_eq!(response, b"HTTP/1.1 103 Early Hints\r\nLink: </app.css>; rel=preload; as=style\r\n\r\n");
+    }
+}
@@ -0,0 +1,79 @@
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use hyper::header::HeaderValue;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// Computes a weak ETag from the body. It is weak because the body may be compressed afterwards, and the compressed
+// variants are semantically the same response. The hash only needs to be stable while the server is running
+pub fn compute_weak_etag(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("W/\"{:x}-{:016x}\"", body.len(), hasher.finish())
+}
+
+// Checks if any of the entity tags in an If-None-Match header matches the ETag, using weak comparison as RFC 9110 requires for If-None-Match
+pub fn if_none_match_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match.split(',').map(|tag| tag.trim()).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+// Adds an ETag to successful GET responses with a buffered body, and answers 304 Not Modified if the client already has it.
+// This lets clients that poll, like the admin portal dashboards, do so cheaply with If-None-Match
+pub async fn apply_etag(gruxi_request: &mut GruxiRequest, response: &mut GruxiResponse) {
+    if gruxi_request.get_http_method() != "GET" || response.get_status() != hyper::StatusCode::OK.as_u16() || !response.is_body_buffered() {
+        return;
+    }
+
+    let etag = compute_weak_etag(&response.get_body_bytes().await);
+    let etag_header_value = match HeaderValue::from_str(&etag) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+
+    let is_not_modified = gruxi_request
+        .get_headers()
+        .get(hyper::header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|if_none_match| if_none_match_matches(if_none_match, &etag));
+
+    if is_not_modified {
+        let mut not_modified_response = GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_MODIFIED.as_u16());
+        for header_name in [hyper::header::CACHE_CONTROL, hyper::header::VARY] {
+            if let Some(value) = response.get_header(header_name.as_str()) {
+                not_modified_response.headers_mut().insert(header_name, value.clone());
+            }
+        }
+        *response = not_modified_response;
+    }
+
+    response.headers_mut().insert(hyper::header::ETAG, etag_header_value);
+    // The client must revalidate every time, as the content can change at any moment
+    if response.get_header("Cache-Control").is_none() {
+        response.headers_mut().insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_weak_etag() {
+        let etag = compute_weak_etag(b"{\"requests\": 1}");
+        assert!(etag.starts_with("W/\"f-"));
+        assert_eq!(etag, compute_weak_etag(b"{\"requests\": 1}"));
+        assert_ne!(etag, compute_weak_etag(b"{\"requests\": 2}"));
+    }
+
+    #[test]
+    fn test_if_none_match_matches() {
+        let etag = compute_weak_etag(b"hello");
+        assert!(if_none_match_matches(&etag, &etag));
+        assert!(if_none_match_matches(&format!("\"other\", {}", etag), &etag));
+        assert!(if_none_match_matches(etag.trim_start_matches("W/"), &etag));
+        assert!(if_none_match_matches("*", &etag));
+        assert!(!if_none_match_matches("\"other\"", &etag));
+        assert!(!if_none_match_matches("", &etag));
+    }
+}
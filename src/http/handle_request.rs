@@ -1,16 +1,50 @@
 use crate::admin_portal::http_admin_api::*;
-use crate::compression::compression::Compression;
 use crate::configuration::binding::Binding;
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::configuration::site::Site;
+use crate::core::graceful_shutdown::get_health_check_response;
+use crate::core::memory_budget::get_memory_budget;
+use crate::core::monitoring::get_monitoring_state;
+use crate::core::running_state::RunningState;
 use crate::core::running_state_manager::get_running_state_manager;
+use crate::core::site_statistics::get_site_statistics;
 use crate::error::gruxi_error::GruxiError;
 use crate::error::gruxi_error_enums::{AdminApiError, GruxiErrorKind};
+use crate::external_connections::fastcgi::FASTCGI_STDERR_KEY;
+use crate::http::body_substitution::apply_body_substitutions;
+use crate::http::canonical_redirect::get_canonical_redirect;
+use crate::http::cookie_routing::{add_cookie_routing_vary, get_cookie_route, handle_cookie_route_opt_in};
+use crate::http::early_hints::send_site_early_hints;
+use crate::http::header_routing::{add_header_routing_vary, get_header_route};
 use crate::http::http_util::*;
+use crate::http::middleware::middleware_chain::MiddlewareChain;
+use crate::http::middleware::middleware_trait::MiddlewareContext;
+use crate::http::problem_details::{convert_to_problem_details, create_problem_details_response};
+use crate::http::request_handlers::request_handler_manager::set_allow_header;
 use crate::http::request_response::gruxi_request::GruxiRequest;
 use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::http::request_response::request_timings::get_current_request_timings;
+use crate::http::site_match::dev_sites::{DEV_SITE_WEB_ROOT_KEY, find_dev_site, handle_dev_site_request};
 use crate::http::site_match::site_matcher::find_best_match_site;
+use crate::http::sitemap::get_generated_site_file;
+use crate::http::status_page::{STATUS_PAGE_PATH, handle_status_page_request};
+use crate::http::traffic_split::{TrafficSplitVariant, assign_traffic_split_variant};
+use crate::http::try_files::{apply_spa_fallback, apply_try_files};
+use crate::http::upload_manager::{UPLOAD_PROGRESS_PATH, handle_upload_progress_request};
+use crate::logging::debug_capture::{CapturedBody, CapturedExchange, get_capture_timestamp, get_debug_capture, headers_to_vec};
 use crate::logging::syslog::{debug, trace};
-use chrono::Local;
+use crate::tls::external_acme::{ACME_CHALLENGE_PATH_PREFIX, handle_acme_challenge_request};
 use hyper::header::HeaderValue;
+use std::time::Instant;
+
+// Set on requests answered by the admin API, whose JSON is compressed regardless of the gzip settings
+pub const ADMIN_API_RESPONSE_KEY: &str = "admin_api_response";
+
+// Request and streaming response bodies are only buffered for debug capture if they are known to be at most this size
+const DEBUG_CAPTURE_MAX_BUFFERED_BODY_BYTES: u64 = 1024 * 1024;
+
+// Retry-After for requests turned away because the memory budget is exhausted
+const MEMORY_BUDGET_RETRY_AFTER_SECONDS: u64 = 5;
 
 // Entry point to handle request, as we need to do post-processing, like access logging etc
 pub async fn handle_request(mut gruxi_request: GruxiRequest, binding: Binding) -> Result<GruxiResponse, GruxiError> {
@@ -25,6 +59,11 @@ pub async fn handle_request(mut gruxi_request: GruxiRequest, binding: Binding) -
         gruxi_request.get_headers()
     ));
 
+    // The status page belongs to the binding rather than a site, so it is answered before the site is matched
+    if binding.status_page_enabled && gruxi_request.get_path() == STATUS_PAGE_PATH && matches!(gruxi_request.get_http_method().as_str(), "GET" | "HEAD") {
+        return Ok(handle_status_page_request(&mut gruxi_request, &binding).await);
+    }
+
     // Get the running state
     let running_state = get_running_state_manager().await.get_running_state_unlocked().await;
 
@@ -36,9 +75,13 @@ pub async fn handle_request(mut gruxi_request: GruxiRequest, binding: Binding) -
         return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16()));
     }
 
-    // Get the hostname and figure out which site matches
+    // Get the hostname and figure out which site matches, including the automatically provisioned sites in development mode
     let hostname = gruxi_request.get_hostname();
-    let site = match find_best_match_site(&sites, &hostname) {
+    let dev_site = if binding.is_admin { None } else { find_dev_site(&sites, &hostname).await };
+    if let Some((_, web_root)) = &dev_site {
+        gruxi_request.add_calculated_data(DEV_SITE_WEB_ROOT_KEY, web_root);
+    }
+    let site = match dev_site.as_ref().map(|(site, _)| site).or_else(|| find_best_match_site(&sites, &hostname)) {
         Some(site) => site,
         None => {
             if hostname.is_empty() {
@@ -52,27 +95,74 @@ pub async fn handle_request(mut gruxi_request: GruxiRequest, binding: Binding) -
     };
     trace(format!("Matched site with request: {:?}", &site));
 
+    let mut response = handle_request_for_site(&mut gruxi_request, &binding, site, &running_state).await?;
+
+    // Sites in API mode get their error responses as RFC 7807 problem details instead of empty bodies
+    if site.api_mode_enabled {
+        convert_to_problem_details(&mut response, &mut gruxi_request);
+    }
+
+    Ok(response)
+}
+
+// Handles the request for the matched site, including post-processing such as compression and access logging by its middlewares
+async fn handle_request_for_site(gruxi_request: &mut GruxiRequest, binding: &Binding, site: &Site, running_state: &RunningState) -> Result<GruxiResponse, GruxiError> {
+    get_monitoring_state().await.increment_site_requests_served(&site.id);
+
+    // The bytes sent are known once the response has been written to the client
+    match get_current_request_timings() {
+        Some(request_timings) => {
+            let site_id = site.id.clone();
+            request_timings.add_on_complete(move |request_timings| get_site_statistics().record(&site_id, request_timings.get_bytes_sent()));
+        }
+        None => get_site_statistics().record(&site.id, 0),
+    }
+
+    // Sites can have their own max body size, otherwise the server wide one applies
+    let max_body_size = if site.max_body_size > 0 {
+        site.max_body_size
+    } else {
+        get_cached_configuration().get_configuration().await.core.server_settings.max_body_size
+    };
+
     // Validate the request
-    if let Err(gruxi_error) = validate_request(&mut gruxi_request).await {
+    if let Err(gruxi_error) = validate_request(gruxi_request, max_body_size).await {
         debug(format!("Request validation failed: {:?}", gruxi_error));
-        let status_code = match &gruxi_error.kind {
-            GruxiErrorKind::HttpRequestValidation(code) => *code,
-            _ => 500, // Default for other errors
-        };
-        let response = GruxiResponse::new_empty_with_status(status_code);
-        return Ok(response);
+        return Ok(rejected_request_response(gruxi_request, site, &gruxi_error, max_body_size).await);
     }
 
+    // Large bodies are turned away while the memory budget is exhausted. Bodies count with their full size for the whole request,
+    // as whether they are held in memory depends on the handler. Bodies without a known size are counted as they are read
+    let body_reservation_bytes = if gruxi_request.is_body_size_known() { gruxi_request.get_body_size() } else { 0 };
+    let body_reservation = match get_memory_budget().try_reserve(body_reservation_bytes) {
+        Some(reservation) => reservation,
+        None => {
+            debug(format!("Memory budget exhausted, rejecting request with a body of {} bytes", body_reservation_bytes));
+            return Ok(memory_budget_exhausted_response());
+        }
+    };
+
+    // Bodies without a Content-Length, such as chunked bodies and HTTP/2 requests, are checked against the max body size and the memory budget
+    // as they are read. [HTTP1.1] Malformed chunks are answered with a 400 once they are read
+    gruxi_request.limit_body(max_body_size, body_reservation);
+
     // Handle special case for OPTIONS * request, which is stupid but valid
     if gruxi_request.get_http_method() == "OPTIONS" && gruxi_request.get_path() == "*" {
         // Special case for OPTIONS * request
+        let allowed_methods = running_state.get_request_handler_manager().get_server_allowed_methods().await;
         let mut resp = GruxiResponse::new_empty_with_status(hyper::StatusCode::OK.as_u16());
-        resp.headers_mut()
-            .insert("Allow", HeaderValue::from_static("GET, HEAD, POST, PUT, DELETE, OPTIONS, TRACE, CONNECT, PATCH"));
+        set_allow_header(&mut resp, &allowed_methods);
         add_standard_headers_to_response(&mut resp);
         return Ok(resp);
     }
 
+    // Reject paths that cannot be canonicalized, such as traversals above the root or invalid encodings, before anything routes on them
+    if gruxi_request.get_canonical_path().is_none() {
+        trace(format!("Rejecting request with invalid path: {}", &gruxi_request.get_path()));
+        get_monitoring_state().await.increment_requests_rejected_invalid("invalid_path");
+        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::BAD_REQUEST.as_u16()));
+    }
+
     // Handle EXPECT: 100-continue header
     if let Some(expect_header) = gruxi_request.get_headers().get("expect") {
         if expect_header.to_str().unwrap_or("").eq_ignore_ascii_case("100-continue") {
@@ -83,10 +173,87 @@ pub async fn handle_request(mut gruxi_request: GruxiRequest, binding: Binding) -
         }
     }
 
+    // Upload progress for spooled uploads can be polled on sites that enable it
+    if site.upload_progress_enabled && gruxi_request.get_path() == UPLOAD_PROGRESS_PATH && gruxi_request.get_http_method() == "GET" {
+        return Ok(handle_upload_progress_request(gruxi_request, site));
+    }
+
+    // The health check of load balancers is answered for every site, so it can be pointed at any binding
+    if matches!(gruxi_request.get_http_method().as_str(), "GET" | "HEAD") {
+        let health_check_path = get_cached_configuration().get_configuration().await.core.server_settings.health_check_path.clone();
+        if !health_check_path.is_empty() && gruxi_request.get_path() == health_check_path {
+            return Ok(get_health_check_response());
+        }
+    }
+
+    // Challenges of an external ACME client are answered for every site, whatever its web root and handlers
+    if gruxi_request.get_path().starts_with(ACME_CHALLENGE_PATH_PREFIX) && matches!(gruxi_request.get_http_method().as_str(), "GET" | "HEAD") {
+        let challenge_directory = get_cached_configuration().get_configuration().await.core.tls_settings.acme_challenge_directory.clone();
+        if !challenge_directory.is_empty() {
+            return Ok(handle_acme_challenge_request(gruxi_request, &challenge_directory).await);
+        }
+    }
+
+    // Browsers can start loading what the page needs while the request handlers are working on it
+    send_site_early_hints(gruxi_request, site);
+
+    // Capture the request if debug capture is enabled for the site, which requires the body to be read into memory
+    let mut debug_capture: Option<(Instant, CapturedExchange)> = None;
+    if site.debug_capture_enabled {
+        debug_capture = Some((Instant::now(), capture_request(gruxi_request).await));
+    }
+
+    // The request is handled inside the site's middleware chain, which does the likes of compression and access logging
+    let context = MiddlewareContext { site, binding, running_state };
+    let middleware_chain = MiddlewareChain::new(&site.middlewares);
+    let response = middleware_chain
+        .run(gruxi_request, &context, async |gruxi_request: &mut GruxiRequest| {
+            let result = handle_request_with_handlers(gruxi_request, binding, site, running_state).await;
+
+            // Bodies of unknown size the handlers did not read are read to the end, so one over the limit is answered with a 413 all the same.
+            // Tunnels are left alone, as their body is the tunneled data
+            let is_tunnel = gruxi_request.get_http_method() == "CONNECT" || result.as_ref().is_ok_and(|response| response.get_status() == hyper::StatusCode::SWITCHING_PROTOCOLS.as_u16());
+            if !is_tunnel {
+                gruxi_request.discard_unread_body().await;
+            }
+
+            // A body cut off while the handlers read it is answered as such, whatever the handlers made of the missing data
+            if let Some(gruxi_error) = gruxi_request.get_body_rejection() {
+                debug(format!("Request body rejected: {:?}", gruxi_error));
+                return Ok(rejected_request_response(gruxi_request, site, &gruxi_error, max_body_size).await);
+            }
+            let mut response = result?;
+            apply_body_substitutions(gruxi_request, site, &mut response);
+
+            // Capture the response body before the middlewares compress it
+            if let Some((_, exchange)) = debug_capture.as_mut() {
+                exchange.response_body = capture_response_body(&mut response).await;
+            }
+            Ok(response)
+        })
+        .await?;
+
+    // Store the debug capture, now that the response is final
+    if let Some((started, mut exchange)) = debug_capture {
+        exchange.response_status = response.get_status();
+        exchange.response_headers = headers_to_vec(response.headers());
+        exchange.duration_ms = started.elapsed().as_millis();
+        exchange.fastcgi_stderr = gruxi_request.get_calculated_data(FASTCGI_STDERR_KEY).unwrap_or_default();
+        get_debug_capture().add_capture(&site.id, exchange);
+    }
+
+    Ok(response)
+}
+
+// Handles the request with the admin API, the development site or the request handlers of the site
+async fn handle_request_with_handlers(gruxi_request: &mut GruxiRequest, binding: &Binding, site: &Site, running_state: &RunningState) -> Result<GruxiResponse, GruxiError> {
     // Check if the request is for the admin portal - handle these first
-    let admin_response = if binding.is_admin {
-        match handle_api_routes(&mut gruxi_request, site).await {
-            Ok(response) => Some(response),
+    if binding.is_admin {
+        match handle_api_routes(gruxi_request, site).await {
+            Ok(response) => {
+                gruxi_request.add_calculated_data(ADMIN_API_RESPONSE_KEY, "true");
+                return Ok(response);
+            }
             Err(e) => {
                 // If the error is NoRouteMatched, we continue to normal processing
                 match e.kind {
@@ -97,122 +264,144 @@ pub async fn handle_request(mut gruxi_request: GruxiRequest, binding: Binding) -
                         // Current no other admin API errors are defined, but in case we add some later, we handle them here
                     }
                 }
-                None
             }
         }
-    } else {
-        None
-    };
-
-    let mut response = if let Some(admin_response) = admin_response {
-        admin_response
-    } else {
-        // If no handler wants it, we return 404
-        if site.request_handlers.is_empty() {
-            return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16()));
-        }
+    }
 
-        // Now we let the request handler manager process the request in the order defined by the site's request_handlers list.
-        let request_handler_manager = running_state.get_request_handler_manager();
-        let response_result = request_handler_manager.handle_request(&mut gruxi_request, &site).await;
-        if response_result.is_err() {
-            trace(format!("No request handler matched for URL path: {}", &gruxi_request.get_path_and_query()));
-            return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16()));
-        }
+    if let Some(web_root) = gruxi_request.get_calculated_data(DEV_SITE_WEB_ROOT_KEY) {
+        return Ok(handle_dev_site_request(gruxi_request, site, &web_root).await);
+    }
 
-        match response_result {
-            Ok(response) => response,
-            Err(_) => {
-                trace(format!("No request handler matched for URL path: {}", &gruxi_request.get_path_and_query()));
-                return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16()));
-            }
-        }
-    };
+    // Clients opted in to a cookie route, such as testers of a canary build, are served by its request handlers, before any other routing
+    if let Some(opt_in_response) = handle_cookie_route_opt_in(gruxi_request, site) {
+        return Ok(opt_in_response);
+    }
+    if let Some(cookie_route) = get_cookie_route(gruxi_request, site) {
+        let mut response = handle_request_with_site_handlers(gruxi_request, &site.with_request_handlers(&cookie_route.request_handlers), running_state).await?;
+        add_cookie_routing_vary(&mut response, site);
+        return Ok(response);
+    }
 
-    // Consider gzipping content if not already gzipped
-    let content_length = response.get_body_size();
-    let content_type_header_option = response.get_header("Content-Type");
-    let content_type_header = if let Some(cth) = content_type_header_option {
-        cth.to_str().unwrap_or("").to_string()
-    } else {
-        "".to_string()
-    };
+    // Requests matching a header route are served by its request handlers, which takes precedence over a traffic split
+    if let Some(header_route) = get_header_route(gruxi_request, site) {
+        let mut response = handle_request_with_site_handlers(gruxi_request, &site.with_request_handlers(&header_route.request_handlers), running_state).await?;
+        add_cookie_routing_vary(&mut response, site);
+        add_header_routing_vary(&mut response, site);
+        return Ok(response);
+    }
 
-    let content_encoding_header_option = response.get_header("Content-Encoding");
-    let content_encoding_header = if let Some(ceh) = content_encoding_header_option {
-        ceh.to_str().unwrap_or("").to_string()
-    } else {
-        "".to_string()
+    // Sites splitting their traffic serve the clients on variant B with the request handlers of the traffic split
+    let traffic_split_assignment = assign_traffic_split_variant(gruxi_request, site);
+    let mut response = match &traffic_split_assignment {
+        Some(assignment) if assignment.variant == TrafficSplitVariant::B => {
+            handle_request_with_site_handlers(gruxi_request, &site.with_request_handlers(&site.traffic_split.variant_b_request_handlers), running_state).await?
+        }
+        _ => handle_request_with_site_handlers(gruxi_request, site, running_state).await?,
     };
-
-    let file_reader_cache = running_state.get_file_reader_cache();
-
-    // Only gzip if not already gzipped and if we should compress based on config and sizes
-    if content_encoding_header.to_lowercase() != "gzip" && file_reader_cache.should_compress(&content_type_header, content_length) {
-        let accepted_encodings = gruxi_request.get_accepted_encodings();
-        let compression = Compression::new();
-        compression.compress_response(&mut response, accepted_encodings, content_encoding_header).await;
+    if let Some(assignment) = &traffic_split_assignment {
+        assignment.apply_to_response(&mut response);
     }
+    add_cookie_routing_vary(&mut response, site);
+    add_header_routing_vary(&mut response, site);
+    Ok(response)
+}
 
-    // Vector for additional headers to set
-    let mut additional_headers: Vec<(&str, &str)> = vec![];
+async fn handle_request_with_site_handlers(gruxi_request: &mut GruxiRequest, site: &Site, running_state: &RunningState) -> Result<GruxiResponse, GruxiError> {
+    // If no handler wants it, we return 404
+    if site.request_handlers.is_empty() {
+        return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16()));
+    }
 
-    // If method is OPTIONS, we add the Allow header if not already present
-    if gruxi_request.get_http_method() == "OPTIONS" {
-        if !response.headers().iter().any(|(k, _)| k.as_str().to_lowercase() == "allow") {
-            additional_headers.push(("Allow", "GET, HEAD, POST, PUT, DELETE, OPTIONS, TRACE, CONNECT, PATCH"));
-        }
+    // Send clients to the canonical URL first, so the handlers only see one form of each path
+    if let Some(redirect_response) = get_canonical_redirect(gruxi_request, site, running_state).await {
+        return Ok(redirect_response);
     }
 
-    // Set any additional headers
-    for (key, value) in additional_headers {
-        let header_value_result = HeaderValue::from_str(value);
-        match header_value_result {
-            Ok(header_value) => {
-                response.headers_mut().insert(key, header_value);
-            }
-            Err(e) => debug(format!("Failed to create header value for key '{}', value '{}': {}", key, value, e)),
-        }
+    // Fall back through the site's try_files entries, which may rewrite the request or answer it with a status directly,
+    // and serve client-side routes of single-page apps
+    if let Some(try_files_response) = apply_try_files(gruxi_request, site, running_state).await {
+        return Ok(try_files_response);
     }
+    apply_spa_fallback(gruxi_request, site, running_state).await;
 
-    // Apply site-specific extra headers
-    for kv in &site.extra_headers {
-        if let Ok(key_name) = hyper::http::HeaderName::from_bytes(kv.key.as_bytes()) {
-            if let Ok(val) = HeaderValue::from_str(kv.value.as_str()) {
-                response.headers_mut().insert(key_name, val);
-            }
+    // Now we let the request handler manager process the request in the order defined by the site's request_handlers list.
+    let request_handler_manager = running_state.get_request_handler_manager();
+    let response = match request_handler_manager.handle_request(gruxi_request, site).await {
+        Ok(response) => response,
+        Err(_) => {
+            trace(format!("No request handler matched for URL path: {}", &gruxi_request.get_path_and_query()));
+            GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16())
         }
+    };
+
+    // Sites may have their robots.txt and sitemap.xml generated, when the request handlers have none
+    if response.get_status() == hyper::StatusCode::NOT_FOUND.as_u16()
+        && let Some(generated_response) = get_generated_site_file(gruxi_request, site, running_state).await
+    {
+        return Ok(generated_response);
     }
+    Ok(response)
+}
 
-    // Handle access logging
-    if site.access_log_enabled {
-        // Get current date and time in CLF format, which is like 10/Oct/2000:13:55:36 -0700
-        let now = Local::now();
-        let clf_date = now.format("%d/%b/%Y:%H:%M:%S %z").to_string();
-        let log_entry = format!(
-            "{} - - [{}] \"{} {} {}\" {} {}",
-            gruxi_request.get_remote_ip(),
-            clf_date,
-            gruxi_request.get_http_method(),
-            gruxi_request.get_path_and_query(),
-            gruxi_request.get_http_version(),
-            response.get_status(),
-            response.get_body_size()
-        );
+async fn capture_request(gruxi_request: &mut GruxiRequest) -> CapturedExchange {
+    // Like streaming responses, bodies are only buffered when the size is known and reasonable
+    let body_size = gruxi_request.get_body_size();
+    let request_body = if !gruxi_request.is_body_size_known() {
+        CapturedBody::not_captured(0)
+    } else if body_size > DEBUG_CAPTURE_MAX_BUFFERED_BODY_BYTES {
+        CapturedBody::not_captured(body_size)
+    } else {
+        CapturedBody::from_bytes(&gruxi_request.buffer_body().await)
+    };
+    CapturedExchange {
+        timestamp: get_capture_timestamp(),
+        remote_ip: gruxi_request.get_remote_ip(),
+        method: gruxi_request.get_http_method(),
+        uri: gruxi_request.get_path_and_query(),
+        http_version: gruxi_request.get_http_version(),
+        request_headers: headers_to_vec(gruxi_request.get_headers()),
+        request_body,
+        response_status: 0,
+        response_headers: Vec::new(),
+        response_body: CapturedBody::not_captured(0),
+        duration_ms: 0,
+        fastcgi_stderr: String::new(),
+    }
+}
 
-        let access_log_buffer_rwlock = running_state.get_access_log_buffer();
-        let access_log_buffer = access_log_buffer_rwlock.read().await;
-        access_log_buffer.add_log(site.id.to_string(), log_entry);
+async fn capture_response_body(response: &mut GruxiResponse) -> CapturedBody {
+    if response.is_body_buffered() {
+        return CapturedBody::from_bytes(&response.buffer_body().await);
     }
 
-    Ok(response)
+    // Streaming bodies are only buffered when the size is known and reasonable, as they could be endless (such as server-sent events)
+    let content_length = response.get_header("Content-Length").and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+    match content_length {
+        Some(length) if length <= DEBUG_CAPTURE_MAX_BUFFERED_BODY_BYTES => CapturedBody::from_bytes(&response.buffer_body().await),
+        Some(length) => CapturedBody::not_captured(length),
+        None => CapturedBody::not_captured(0),
+    }
 }
 
-async fn validate_request(gruxi_request: &mut GruxiRequest) -> Result<(), GruxiError> {
-    // Here we can add any request validation logic if needed
-    let cached_configuration = crate::configuration::cached_configuration::get_cached_configuration();
-    let configuration = cached_configuration.get_configuration().await;
+// Tells the client the limit it exceeded. The rest of the body is not read, so the connection is closed afterwards
+fn payload_too_large_response(gruxi_request: &mut GruxiRequest, site: &Site, max_body_size: u64) -> GruxiResponse {
+    let status = hyper::StatusCode::PAYLOAD_TOO_LARGE.as_u16();
+    let detail = format!("The request body is larger than the limit of {} bytes.", max_body_size);
+    let problem_response = if site.api_mode_enabled {
+        create_problem_details_response(status, Some(detail.clone()), gruxi_request)
+    } else {
+        None
+    };
+    let mut response = problem_response.unwrap_or_else(|| {
+        let mut response = GruxiResponse::new_with_bytes(status, detail);
+        response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+        response
+    });
+    response.headers_mut().insert(hyper::header::CONNECTION, HeaderValue::from_static("close"));
+    response
+}
 
+async fn validate_request(gruxi_request: &mut GruxiRequest, max_body_size: u64) -> Result<(), GruxiError> {
     // Validation for HTTP/1.1 only
     if gruxi_request.get_http_version() == "HTTP/1.1" {
         // [HTTP1.1] Requires a Host header
@@ -234,16 +423,7 @@ async fn validate_request(gruxi_request: &mut GruxiRequest) -> Result<(), GruxiE
 
     // [HTTP1.1 and later] Basic validation: check for valid method
     let http_method = gruxi_request.get_http_method();
-    if http_method != "GET"
-        && http_method != "POST"
-        && http_method != "HEAD"
-        && http_method != "PUT"
-        && http_method != "DELETE"
-        && http_method != "OPTIONS"
-        && http_method != "TRACE"
-        && http_method != "CONNECT"
-        && http_method != "PATCH"
-    {
+    if !SUPPORTED_HTTP_METHODS.contains(&http_method.as_str()) {
         // Return a error for unsupported method
         return Err(GruxiError::new(
             GruxiErrorKind::HttpRequestValidation(hyper::StatusCode::NOT_IMPLEMENTED.as_u16()),
@@ -251,9 +431,8 @@ async fn validate_request(gruxi_request: &mut GruxiRequest) -> Result<(), GruxiE
         ));
     }
 
-    // Protect our server from overly large bodies
-    let max_body_size = configuration.core.server_settings.max_body_size;
-    if max_body_size > 0 && (http_method == "POST" || http_method == "PUT") {
+    // Protect our server from overly large bodies, rejecting them before any of the body is read
+    if max_body_size > 0 {
         // Check Content-Length header if present
         if let Some(content_length_header) = gruxi_request.get_headers().get("Content-Length") {
             if let Ok(content_length_str) = content_length_header.to_str() {
@@ -261,7 +440,7 @@ async fn validate_request(gruxi_request: &mut GruxiRequest) -> Result<(), GruxiE
                     if content_length > max_body_size {
                         return Err(GruxiError::new(
                             GruxiErrorKind::HttpRequestValidation(hyper::StatusCode::PAYLOAD_TOO_LARGE.as_u16()),
-                            format!("Payload too large for request, based on content-length header: {:?}", gruxi_request),
+                            format!("Payload too large for request, based on content-length header (limit {} bytes): {:?}", max_body_size, gruxi_request),
                         ));
                     }
                 }
@@ -269,7 +448,7 @@ async fn validate_request(gruxi_request: &mut GruxiRequest) -> Result<(), GruxiE
         }
 
         // Also check the expected body size
-        if gruxi_request.get_body_size() > max_body_size.try_into().unwrap_or(0) {
+        if gruxi_request.get_body_size() > max_body_size {
             return Err(GruxiError::new(
                 GruxiErrorKind::HttpRequestValidation(hyper::StatusCode::PAYLOAD_TOO_LARGE.as_u16()),
                 format!("Payload too large for request, based on actual body size: {:?}", gruxi_request),
@@ -279,3 +458,25 @@ async fn validate_request(gruxi_request: &mut GruxiRequest) -> Result<(), GruxiE
 
     Ok(())
 }
+
+// The response to a request rejected by the validation or while its body was read
+async fn rejected_request_response(gruxi_request: &mut GruxiRequest, site: &Site, gruxi_error: &GruxiError, max_body_size: u64) -> GruxiResponse {
+    let status_code = match &gruxi_error.kind {
+        GruxiErrorKind::HttpRequestValidation(code) => *code,
+        _ => 500, // Default for other errors
+    };
+    if status_code == hyper::StatusCode::PAYLOAD_TOO_LARGE.as_u16() {
+        get_monitoring_state().await.increment_requests_rejected_body_too_large();
+        return payload_too_large_response(gruxi_request, site, max_body_size);
+    }
+    if status_code == hyper::StatusCode::SERVICE_UNAVAILABLE.as_u16() {
+        return memory_budget_exhausted_response();
+    }
+    GruxiResponse::new_empty_with_status(status_code)
+}
+
+fn memory_budget_exhausted_response() -> GruxiResponse {
+    let mut response = GruxiResponse::new_empty_with_status(hyper::StatusCode::SERVICE_UNAVAILABLE.as_u16());
+    response.headers_mut().insert(hyper::header::RETRY_AFTER, HeaderValue::from(MEMORY_BUDGET_RETRY_AFTER_SECONDS));
+    response
+}
@@ -0,0 +1,100 @@
+use crate::configuration::site::{HeaderRoute, Site};
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use dashmap::DashMap;
+use regex::Regex;
+use std::sync::OnceLock;
+
+static HEADER_ROUTE_REGEXES: OnceLock<DashMap<String, Option<Regex>>> = OnceLock::new();
+
+// The regular expressions of the header routes are compiled once, on the first request using them. The configuration is validated
+// before it is used, so one that does not compile never matches instead of failing the request
+fn header_route_regex_matches(pattern: &str, value: &str) -> bool {
+    let regexes = HEADER_ROUTE_REGEXES.get_or_init(DashMap::new);
+    if let Some(regex) = regexes.get(pattern) {
+        return regex.as_ref().is_some_and(|regex| regex.is_match(value));
+    }
+    let regex = Regex::new(pattern).ok();
+    let is_match = regex.as_ref().is_some_and(|regex| regex.is_match(value));
+    regexes.insert(pattern.to_string(), regex);
+    is_match
+}
+
+// The first header route of the site matching the request. A header sent more than once matches if any of its values does
+pub fn get_header_route<'a>(gruxi_request: &GruxiRequest, site: &'a Site) -> Option<&'a HeaderRoute> {
+    site.header_routes.iter().find(|route| {
+        gruxi_request
+            .get_headers()
+            .get_all(route.header_name.as_str())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| header_route_matches(route, value))
+    })
+}
+
+fn header_route_matches(route: &HeaderRoute, value: &str) -> bool {
+    match route.match_type.as_str() {
+        "exact" => value == route.value,
+        "prefix" => value.starts_with(&route.value),
+        "regex" => header_route_regex_matches(&route.value, value),
+        _ => false,
+    }
+}
+
+// Responses of a site routing by headers differ by those headers, which caches must know
+pub fn add_header_routing_vary(response: &mut GruxiResponse, site: &Site) {
+    for route in &site.header_routes {
+        crate::http::http_util::add_vary_field(response, &route.header_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_request(headers: &[(&str, &str)]) -> GruxiRequest {
+        let mut builder = hyper::Request::builder().uri("/");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        GruxiRequest::new(builder.body(hyper::body::Bytes::new()).unwrap())
+    }
+
+    fn create_route(header_name: &str, match_type: &str, value: &str, handler: &str) -> HeaderRoute {
+        HeaderRoute {
+            header_name: header_name.to_string(),
+            match_type: match_type.to_string(),
+            value: value.to_string(),
+            request_handlers: vec![handler.to_string()],
+        }
+    }
+
+    #[test]
+    fn test_first_matching_header_route_is_used() {
+        let mut site = Site::new();
+        site.header_routes = vec![
+            create_route("x-api-version", "exact", "2", "api-v2"),
+            create_route("user-agent", "regex", "(?i)android|iphone", "mobile"),
+            create_route("user-agent", "prefix", "curl/", "tools"),
+        ];
+
+        let route_of = |headers: &[(&str, &str)]| get_header_route(&create_request(headers), &site).map(|route| route.request_handlers[0].clone());
+        assert_eq!(route_of(&[("X-API-Version", "2")]).as_deref(), Some("api-v2"));
+        assert_eq!(route_of(&[("X-API-Version", "20")]), None);
+        assert_eq!(route_of(&[("User-Agent", "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0)")]).as_deref(), Some("mobile"));
+        assert_eq!(route_of(&[("User-Agent", "curl/8.5.0")]).as_deref(), Some("tools"));
+        assert_eq!(route_of(&[("X-API-Version", "2"), ("User-Agent", "curl/8.5.0")]).as_deref(), Some("api-v2"));
+        assert_eq!(route_of(&[("X-API-Version", "1"), ("X-API-Version", "2")]).as_deref(), Some("api-v2"));
+        assert_eq!(route_of(&[]), None);
+    }
+
+    #[test]
+    fn test_header_routing_vary() {
+        let mut site = Site::new();
+        site.header_routes = vec![create_route("x-api-version", "exact", "2", "api-v2"), create_route("user-agent", "prefix", "curl/", "tools")];
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        add_header_routing_vary(&mut response, &site);
+        add_header_routing_vary(&mut response, &site);
+        assert_eq!(response.get_header("Vary").unwrap().to_str().unwrap(), "x-api-version, user-agent");
+    }
+}
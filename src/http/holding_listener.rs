@@ -0,0 +1,191 @@
+use crate::http::http_util::add_standard_headers_to_response;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::logging::syslog::trace;
+use hyper::Request;
+use hyper::body::Incoming;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder as HttpAutoBuilder;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use tls_listener::rustls::TlsAcceptor;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+// How long clients are asked to wait before retrying, while the server is starting or reloading
+pub const HOLDING_RETRY_AFTER_SECONDS: u64 = 5;
+
+// How a held listener treats new connections
+pub enum HoldingMode {
+    // Answer 503 over plain HTTP
+    Http,
+    // Answer 503 over TLS, with the acceptor of the binding before the reload
+    Https(TlsAcceptor),
+    // Leave connections in the listen backlog until the binding takes the listener back, used for TLS bindings at startup where no acceptor exists yet
+    Backlog,
+}
+
+struct HeldListener {
+    listener: Arc<TcpListener>,
+    release_token: CancellationToken,
+}
+
+// Listeners of bindings kept open while the running state is started or rebuilt after a configuration change.
+// Instead of refusing connections in that window, they are answered with 503 and Retry-After, so load balancers retry them
+#[derive(Default)]
+pub struct HoldingListeners {
+    held: Mutex<HashMap<SocketAddr, HeldListener>>,
+}
+
+impl HoldingListeners {
+    // Keeps the listener open and answering until it is taken back by the binding for the address
+    pub fn hold(&self, address: SocketAddr, listener: Arc<TcpListener>, mode: HoldingMode) {
+        let release_token = CancellationToken::new();
+        tokio::spawn(run_holding_loop(address, listener.clone(), mode, release_token.clone()));
+        trace(format!("Holding listener on {} until the server is ready", address));
+
+        let previous = self.held.lock().unwrap().insert(address, HeldListener { listener, release_token });
+        if let Some(previous) = previous {
+            previous.release_token.cancel();
+        }
+    }
+
+    // Takes the listener back from holding, for the binding to serve on it again
+    pub fn take(&self, address: &SocketAddr) -> Option<Arc<TcpListener>> {
+        let held_listener = self.held.lock().unwrap().remove(address)?;
+        held_listener.release_token.cancel();
+        trace(format!("Listener on {} taken back from holding", address));
+        Some(held_listener.listener)
+    }
+
+    // Closes held listeners of bindings that are no longer in the configuration
+    pub fn release_all_except(&self, addresses: &[SocketAddr]) {
+        self.held.lock().unwrap().retain(|address, held_listener| {
+            let keep = addresses.contains(address);
+            if !keep {
+                held_listener.release_token.cancel();
+                trace(format!("Released held listener on {}, as the binding no longer exists", address));
+            }
+            keep
+        });
+    }
+
+    pub fn is_holding(&self, address: &SocketAddr) -> bool {
+        self.held.lock().unwrap().contains_key(address)
+    }
+}
+
+static HOLDING_LISTENERS_SINGLETON: OnceLock<HoldingListeners> = OnceLock::new();
+
+pub fn get_holding_listeners() -> &'static HoldingListeners {
+    HOLDING_LISTENERS_SINGLETON.get_or_init(HoldingListeners::default)
+}
+
+async fn run_holding_loop(address: SocketAddr, listener: Arc<TcpListener>, mode: HoldingMode, release_token: CancellationToken) {
+    if let HoldingMode::Backlog = mode {
+        release_token.cancelled().await;
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            _ = release_token.cancelled() => break,
+            result = listener.accept() => {
+                let tcp_stream = match result {
+                    Ok((tcp_stream, _)) => tcp_stream,
+                    Err(err) => {
+                        trace(format!("Failed to accept connection on held listener {}: {:?}", address, err));
+                        continue;
+                    }
+                };
+                match &mode {
+                    HoldingMode::Https(tls_acceptor) => {
+                        let tls_acceptor = tls_acceptor.clone();
+                        tokio::spawn(async move {
+                            match tls_acceptor.accept(tcp_stream).await {
+                                Ok(tls_stream) => serve_holding_connection(tls_stream).await,
+                                Err(err) => trace(format!("TLS handshake error on held listener: {:?}", err)),
+                            }
+                        });
+                    }
+                    _ => {
+                        tokio::spawn(serve_holding_connection(tcp_stream));
+                    }
+                }
+            }
+        }
+    }
+    trace(format!("Holding loop on {} stopped", address));
+}
+
+async fn serve_holding_connection<S>(stream: S)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let svc = service_fn(|req: Request<Incoming>| async move {
+        let mut response = get_holding_response();
+        // HTTP/2 has no Connection header, the connection ends when the client is done with it
+        if req.version() != hyper::Version::HTTP_2 {
+            response.headers_mut().insert(hyper::header::CONNECTION, hyper::header::HeaderValue::from_static("close"));
+        }
+        Ok::<_, std::convert::Infallible>(response.into_hyper())
+    });
+    if let Err(err) = HttpAutoBuilder::new(TokioExecutor::new()).serve_connection(TokioIo::new(stream), svc).await {
+        trace(format!("Connection error on held listener: {:?}", err));
+    }
+}
+
+fn get_holding_response() -> GruxiResponse {
+    let mut response = GruxiResponse::new_with_bytes(
+        hyper::StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+        hyper::body::Bytes::from("The server is starting or reloading its configuration, please retry shortly"),
+    );
+    let headers = response.headers_mut();
+    headers.insert(hyper::header::RETRY_AFTER, hyper::header::HeaderValue::from(HOLDING_RETRY_AFTER_SECONDS));
+    headers.insert(hyper::header::CONTENT_TYPE, hyper::header::HeaderValue::from_static("text/plain; charset=utf-8"));
+    add_standard_headers_to_response(&mut response);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_held_listener_answers_503_until_taken() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let holding_listeners = HoldingListeners::default();
+        holding_listeners.hold(address, Arc::new(listener), HoldingMode::Http);
+        assert!(holding_listeners.is_holding(&address));
+
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"));
+        assert!(response.to_lowercase().contains(&format!("retry-after: {}", HOLDING_RETRY_AFTER_SECONDS)));
+
+        assert!(holding_listeners.take(&address).is_some());
+        assert!(!holding_listeners.is_holding(&address));
+        assert!(holding_listeners.take(&address).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_release_all_except() {
+        let holding_listeners = HoldingListeners::default();
+        let mut addresses = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            holding_listeners.hold(address, Arc::new(listener), HoldingMode::Backlog);
+            addresses.push(address);
+        }
+
+        holding_listeners.release_all_except(&addresses[..1]);
+        assert!(holding_listeners.is_holding(&addresses[0]));
+        assert!(!holding_listeners.is_holding(&addresses[1]));
+    }
+}
@@ -0,0 +1,513 @@
+use crate::configuration::binding::Binding;
+use crate::configuration::server_settings::ServerSettings;
+use crate::core::monitoring::get_monitoring_state;
+use crate::core::triggers::{GruxiEvent, get_trigger_handler};
+use crate::http::status_page::is_ip_allowed;
+use crate::logging::syslog::{debug, warn};
+use dashmap::DashMap;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const FRAME_HEADER_SIZE: usize = 9;
+
+const FRAME_TYPE_HEADERS: u8 = 0x1;
+const FRAME_TYPE_RST_STREAM: u8 = 0x3;
+const FRAME_TYPE_SETTINGS: u8 = 0x4;
+const FRAME_TYPE_PING: u8 = 0x6;
+const FRAME_TYPE_GOAWAY: u8 = 0x7;
+const FRAME_TYPE_CONTINUATION: u8 = 0x9;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const ERROR_CODE_ENHANCE_YOUR_CALM: u32 = 0xb;
+
+// Limits on what an HTTP/2 client may send, beyond the limits of the binding that the HTTP/2 implementation enforces itself
+#[derive(Debug, Clone)]
+pub struct Http2GuardSettings {
+    pub max_resets_per_second: u32,
+    pub max_settings_per_second: u32,
+    pub max_pings_per_second: u32,
+    pub max_continuation_frames: u32,
+    // Compressed size of a header block. HPACK hardly makes headers larger, so a block of twice the max header list size is an attack
+    // rather than a client with too many headers, which the HTTP/2 implementation answers with 431
+    pub max_header_block_size: u32,
+    // 0 closes the connection without banning the client, as for trusted proxies and on the admin binding
+    pub ban_seconds: u64,
+}
+
+impl Http2GuardSettings {
+    pub fn new(server_settings: &ServerSettings, binding: &Binding, remote_ip: &str) -> Self {
+        // Banning a proxy would cut off all clients behind it, and banning on the admin binding could lock the admin out
+        let is_ban_exempt = binding.is_admin || is_ip_allowed(remote_ip, &server_settings.trusted_proxy_ips);
+        Http2GuardSettings {
+            max_resets_per_second: server_settings.http2_max_resets_per_second,
+            max_settings_per_second: server_settings.http2_max_settings_per_second,
+            max_pings_per_second: server_settings.http2_max_pings_per_second,
+            max_continuation_frames: server_settings.http2_max_continuation_frames,
+            max_header_block_size: binding.http2.max_header_list_size.saturating_mul(2),
+            ban_seconds: if is_ban_exempt { 0 } else { server_settings.http2_flood_ban_seconds },
+        }
+    }
+}
+
+// Clients banned after an HTTP/2 flood, by IP address. Their connections are closed right after they are accepted, except on
+// the admin binding
+#[derive(Default)]
+pub struct TemporaryBans {
+    banned_until: DashMap<String, Instant>,
+}
+
+impl TemporaryBans {
+    pub fn ban(&self, ip: &str, duration: Duration) {
+        self.banned_until.insert(ip.to_string(), Instant::now() + duration);
+    }
+
+    pub fn is_banned(&self, ip: &str) -> bool {
+        let is_banned = self.banned_until.get(ip).is_some_and(|until| *until > Instant::now());
+        if !is_banned {
+            self.banned_until.remove_if(ip, |_, until| *until <= Instant::now());
+        }
+        is_banned
+    }
+
+    pub fn get_banned_count(&self) -> usize {
+        let now = Instant::now();
+        self.banned_until.iter().filter(|entry| *entry.value() > now).count()
+    }
+}
+
+static TEMPORARY_BANS_SINGLETON: OnceLock<TemporaryBans> = OnceLock::new();
+
+pub fn get_temporary_bans() -> &'static TemporaryBans {
+    TEMPORARY_BANS_SINGLETON.get_or_init(TemporaryBans::default)
+}
+
+// Follows the HTTP/2 frames a client sends and tells when it goes over the thresholds.
+// Connections that do not start with the HTTP/2 preface are HTTP/1 and are not inspected further
+struct FrameInspector {
+    guard_settings: Http2GuardSettings,
+    is_http2: Option<bool>,
+    preface_matched: usize,
+    frame_header: [u8; FRAME_HEADER_SIZE],
+    frame_header_length: usize,
+    payload_remaining: usize,
+    header_block_size: usize,
+    continuation_frames: u32,
+    last_stream_id: u32,
+    window_start: Instant,
+    resets: u32,
+    settings: u32,
+    pings: u32,
+}
+
+impl FrameInspector {
+    fn new(guard_settings: Http2GuardSettings) -> Self {
+        FrameInspector {
+            guard_settings,
+            is_http2: None,
+            preface_matched: 0,
+            frame_header: [0; FRAME_HEADER_SIZE],
+            frame_header_length: 0,
+            payload_remaining: 0,
+            header_block_size: 0,
+            continuation_frames: 0,
+            last_stream_id: 0,
+            window_start: Instant::now(),
+            resets: 0,
+            settings: 0,
+            pings: 0,
+        }
+    }
+
+    // Returns the reason if the data puts the client over a threshold
+    fn inspect(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let mut position = 0;
+        if self.is_http2.is_none() {
+            let length = data.len().min(HTTP2_PREFACE.len() - self.preface_matched);
+            if data[..length] != HTTP2_PREFACE[self.preface_matched..self.preface_matched + length] {
+                self.is_http2 = Some(false);
+                return Ok(());
+            }
+            self.preface_matched += length;
+            position = length;
+            if self.preface_matched < HTTP2_PREFACE.len() {
+                return Ok(());
+            }
+            self.is_http2 = Some(true);
+        }
+        if self.is_http2 == Some(false) {
+            return Ok(());
+        }
+
+        while position < data.len() {
+            if self.payload_remaining > 0 {
+                let skipped = (data.len() - position).min(self.payload_remaining);
+                self.payload_remaining -= skipped;
+                position += skipped;
+                continue;
+            }
+
+            let length = (data.len() - position).min(FRAME_HEADER_SIZE - self.frame_header_length);
+            self.frame_header[self.frame_header_length..self.frame_header_length + length].copy_from_slice(&data[position..position + length]);
+            self.frame_header_length += length;
+            position += length;
+            if self.frame_header_length == FRAME_HEADER_SIZE {
+                self.frame_header_length = 0;
+                if let Some(reason) = self.on_frame_header() {
+                    return Err(reason);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_frame_header(&mut self) -> Option<&'static str> {
+        let header = self.frame_header;
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let (frame_type, flags) = (header[3], header[4]);
+        let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        self.payload_remaining = length;
+
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.resets = 0;
+            self.settings = 0;
+            self.pings = 0;
+        }
+
+        match frame_type {
+            FRAME_TYPE_HEADERS => {
+                self.last_stream_id = self.last_stream_id.max(stream_id);
+                self.header_block_size = length;
+                self.continuation_frames = 0;
+            }
+            FRAME_TYPE_CONTINUATION => {
+                self.header_block_size += length;
+                self.continuation_frames += 1;
+                if self.continuation_frames > self.guard_settings.max_continuation_frames {
+                    return Some("continuation_flood");
+                }
+            }
+            FRAME_TYPE_RST_STREAM => {
+                self.resets += 1;
+                if self.resets > self.guard_settings.max_resets_per_second {
+                    return Some("rapid_reset");
+                }
+            }
+            FRAME_TYPE_SETTINGS if flags & FLAG_ACK == 0 => {
+                self.settings += 1;
+                if self.settings > self.guard_settings.max_settings_per_second {
+                    return Some("settings_flood");
+                }
+            }
+            FRAME_TYPE_PING if flags & FLAG_ACK == 0 => {
+                self.pings += 1;
+                if self.pings > self.guard_settings.max_pings_per_second {
+                    return Some("ping_flood");
+                }
+            }
+            _ => {}
+        }
+
+        if matches!(frame_type, FRAME_TYPE_HEADERS | FRAME_TYPE_CONTINUATION) {
+            if self.header_block_size > self.guard_settings.max_header_block_size as usize {
+                return Some("header_bomb");
+            }
+            if flags & FLAG_END_HEADERS != 0 {
+                self.continuation_frames = 0;
+            }
+        }
+        None
+    }
+}
+
+// Keeps track of where the frames we write begin, so a GOAWAY is never written into the middle of another frame
+#[derive(Default)]
+struct WrittenFrames {
+    frame_header: [u8; FRAME_HEADER_SIZE],
+    frame_header_length: usize,
+    payload_remaining: usize,
+}
+
+impl WrittenFrames {
+    fn track(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.payload_remaining > 0 {
+                let skipped = data.len().min(self.payload_remaining);
+                self.payload_remaining -= skipped;
+                data = &data[skipped..];
+                continue;
+            }
+            let length = data.len().min(FRAME_HEADER_SIZE - self.frame_header_length);
+            self.frame_header[self.frame_header_length..self.frame_header_length + length].copy_from_slice(&data[..length]);
+            self.frame_header_length += length;
+            data = &data[length..];
+            if self.frame_header_length == FRAME_HEADER_SIZE {
+                self.frame_header_length = 0;
+                self.payload_remaining = u32::from_be_bytes([0, self.frame_header[0], self.frame_header[1], self.frame_header[2]]) as usize;
+            }
+        }
+    }
+
+    fn is_at_frame_boundary(&self) -> bool {
+        self.frame_header_length == 0 && self.payload_remaining == 0
+    }
+}
+
+fn build_goaway_frame(last_stream_id: u32, error_code: u32) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + 8);
+    frame.extend_from_slice(&[0, 0, 8, FRAME_TYPE_GOAWAY, 0, 0, 0, 0, 0]);
+    frame.extend_from_slice(&last_stream_id.to_be_bytes());
+    frame.extend_from_slice(&error_code.to_be_bytes());
+    frame
+}
+
+// Wraps the connection stream and closes HTTP/2 connections of clients that flood us with resets, SETTINGS, PINGs or huge header
+// blocks, with a GOAWAY with ENHANCE_YOUR_CALM. The client is banned for a while, so it cannot just reconnect and carry on
+pub struct Http2FloodGuard<S> {
+    inner: S,
+    inspector: FrameInspector,
+    written_frames: WrittenFrames,
+    remote_ip: String,
+    binding_address: String,
+    goaway: Option<(Vec<u8>, usize)>,
+}
+
+impl<S> Http2FloodGuard<S> {
+    pub fn new(inner: S, guard_settings: Http2GuardSettings, remote_ip: &str, binding_address: &str) -> Self {
+        Http2FloodGuard {
+            inner,
+            inspector: FrameInspector::new(guard_settings),
+            written_frames: WrittenFrames::default(),
+            remote_ip: remote_ip.to_string(),
+            binding_address: binding_address.to_string(),
+            goaway: None,
+        }
+    }
+
+    fn on_flood(&mut self, reason: &'static str) {
+        warn(format!("HTTP/2 {} from {} on binding {}, closing connection", reason, self.remote_ip, self.binding_address));
+        let ban_seconds = self.inspector.guard_settings.ban_seconds;
+        if ban_seconds > 0 {
+            get_temporary_bans().ban(&self.remote_ip, Duration::from_secs(ban_seconds));
+            debug(format!("Banned {} for {} seconds", self.remote_ip, ban_seconds));
+        }
+        let reason = reason.to_string();
+        let remote_ip = self.remote_ip.clone();
+        tokio::spawn(async move {
+            get_monitoring_state().await.increment_http2_floods(&reason);
+            if ban_seconds > 0 {
+                let ban_reason = format!("HTTP/2 {}", reason);
+                get_trigger_handler()
+                    .publish(GruxiEvent::ClientBanned {
+                        ip: remote_ip,
+                        seconds: ban_seconds,
+                        reason: ban_reason,
+                    })
+                    .await;
+            }
+        });
+        self.goaway = Some((build_goaway_frame(self.inspector.last_stream_id, ERROR_CODE_ENHANCE_YOUR_CALM), 0));
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Http2FloodGuard<S> {
+    // Writes the GOAWAY if we are between frames, and fails the read, so the connection is closed
+    fn poll_close_with_goaway(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if self.written_frames.is_at_frame_boundary()
+            && let Some((frame, written)) = self.goaway.as_mut()
+        {
+            while *written < frame.len() {
+                match Pin::new(&mut self.inner).poll_write(cx, &frame[*written..]) {
+                    Poll::Ready(Ok(0)) | Poll::Ready(Err(_)) => break,
+                    Poll::Ready(Ok(n)) => *written += n,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            let _ = Pin::new(&mut self.inner).poll_flush(cx);
+        }
+        Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "HTTP/2 flood from client")))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for Http2FloodGuard<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        if self.goaway.is_some() {
+            return self.poll_close_with_goaway(cx);
+        }
+
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result
+            && self.inspector.is_http2 != Some(false)
+            && let Err(reason) = self.inspector.inspect(&buf.filled()[filled_before..])
+        {
+            self.on_flood(reason);
+            return self.poll_close_with_goaway(cx);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Http2FloodGuard<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result
+            && self.inspector.is_http2 == Some(true)
+        {
+            self.written_frames.track(&buf[..written]);
+        }
+        result
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[std::io::IoSlice<'_>]) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = result
+            && self.inspector.is_http2 == Some(true)
+        {
+            let mut remaining = written;
+            for buf in bufs {
+                if remaining == 0 {
+                    break;
+                }
+                let length = buf.len().min(remaining);
+                self.written_frames.track(&buf[..length]);
+                remaining -= length;
+            }
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_guard_settings() -> Http2GuardSettings {
+        Http2GuardSettings {
+            max_resets_per_second: 3,
+            max_settings_per_second: 2,
+            max_pings_per_second: 2,
+            max_continuation_frames: 2,
+            max_header_block_size: 100,
+            ban_seconds: 60,
+        }
+    }
+
+    fn frame(frame_type: u8, flags: u8, stream_id: u32, payload_length: usize) -> Vec<u8> {
+        let length = (payload_length as u32).to_be_bytes();
+        let mut frame = vec![length[1], length[2], length[3], frame_type, flags];
+        frame.extend_from_slice(&stream_id.to_be_bytes());
+        frame.extend(std::iter::repeat_n(0u8, payload_length));
+        frame
+    }
+
+    #[test]
+    fn test_rapid_reset_is_detected_across_reads() {
+        let mut inspector = FrameInspector::new(get_guard_settings());
+        let mut data = HTTP2_PREFACE.to_vec();
+        data.extend(frame(FRAME_TYPE_SETTINGS, 0, 0, 6));
+        for stream_id in [1, 3, 5, 7] {
+            data.extend(frame(FRAME_TYPE_HEADERS, FLAG_END_HEADERS, stream_id, 20));
+            data.extend(frame(FRAME_TYPE_RST_STREAM, 0, stream_id, 4));
+        }
+
+        // Fed in small pieces, as the frames may be split over reads
+        let reason = data.chunks(7).find_map(|chunk| inspector.inspect(chunk).err());
+        assert_eq!(reason, Some("rapid_reset"));
+        assert_eq!(inspector.last_stream_id, 7);
+    }
+
+    #[test]
+    fn test_floods_and_header_bombs_are_detected() {
+        let cases: [(Vec<Vec<u8>>, &str); 4] = [
+            (vec![frame(FRAME_TYPE_PING, 0, 0, 8); 3], "ping_flood"),
+            (vec![frame(FRAME_TYPE_SETTINGS, 0, 0, 0); 3], "settings_flood"),
+            (
+                vec![
+                    frame(FRAME_TYPE_HEADERS, 0, 1, 10),
+                    frame(FRAME_TYPE_CONTINUATION, 0, 1, 10),
+                    frame(FRAME_TYPE_CONTINUATION, 0, 1, 10),
+                    frame(FRAME_TYPE_CONTINUATION, 0, 1, 10),
+                ],
+                "continuation_flood",
+            ),
+            (vec![frame(FRAME_TYPE_HEADERS, 0, 1, 60), frame(FRAME_TYPE_CONTINUATION, 0, 1, 60)], "header_bomb"),
+        ];
+        for (frames, expected) in cases {
+            let mut inspector = FrameInspector::new(get_guard_settings());
+            assert_eq!(inspector.inspect(HTTP2_PREFACE), Ok(()));
+            let reason = frames.iter().find_map(|f| inspector.inspect(f).err());
+            assert_eq!(reason, Some(expected));
+        }
+
+        // Acknowledgements do not count
+        let mut inspector = FrameInspector::new(get_guard_settings());
+        inspector.inspect(HTTP2_PREFACE).unwrap();
+        for _ in 0..5 {
+            assert_eq!(inspector.inspect(&frame(FRAME_TYPE_PING, FLAG_ACK, 0, 8)), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_http1_is_not_inspected() {
+        let mut inspector = FrameInspector::new(get_guard_settings());
+        assert_eq!(inspector.inspect(b"GET / HTTP/1.1\r\n\r\n"), Ok(()));
+        assert_eq!(inspector.is_http2, Some(false));
+    }
+
+    #[test]
+    fn test_bans_are_opt_in_and_exempt_proxies_and_admin() {
+        let mut server_settings = crate::configuration::configuration::Configuration::get_default().core.server_settings;
+        let mut binding = Binding::new();
+        assert_eq!(Http2GuardSettings::new(&server_settings, &binding, "203.0.113.5").ban_seconds, 0);
+
+        server_settings.http2_flood_ban_seconds = 300;
+        server_settings.trusted_proxy_ips = vec!["10.0.0.0/8".to_string()];
+        assert_eq!(Http2GuardSettings::new(&server_settings, &binding, "203.0.113.5").ban_seconds, 300);
+        assert_eq!(Http2GuardSettings::new(&server_settings, &binding, "10.1.2.3").ban_seconds, 0);
+
+        binding.is_admin = true;
+        assert_eq!(Http2GuardSettings::new(&server_settings, &binding, "203.0.113.5").ban_seconds, 0);
+    }
+
+    #[test]
+    fn test_temporary_bans() {
+        let bans = TemporaryBans::default();
+        bans.ban("10.0.0.1", Duration::from_secs(60));
+        bans.ban("10.0.0.2", Duration::from_secs(0));
+        assert!(bans.is_banned("10.0.0.1"));
+        assert!(!bans.is_banned("10.0.0.2"));
+        assert!(!bans.is_banned("10.0.0.3"));
+        assert_eq!(bans.get_banned_count(), 1);
+    }
+
+    #[test]
+    fn test_written_frames_boundary() {
+        let mut written_frames = WrittenFrames::default();
+        let data = frame(FRAME_TYPE_HEADERS, FLAG_END_HEADERS, 1, 30);
+        written_frames.track(&data[..5]);
+        assert!(!written_frames.is_at_frame_boundary());
+        written_frames.track(&data[5..20]);
+        assert!(!written_frames.is_at_frame_boundary());
+        written_frames.track(&data[20..]);
+        assert!(written_frames.is_at_frame_boundary());
+        assert_eq!(build_goaway_frame(7, ERROR_CODE_ENHANCE_YOUR_CALM).len(), 17);
+    }
+}
@@ -1,25 +1,59 @@
-use crate::configuration::binding::Binding;
+use crate::configuration::binding::{Binding, HTTP2_MAX_FRAME_SIZE};
+use crate::core::binary_upgrade::register_active_listener;
 use crate::core::monitoring::get_monitoring_state;
+use crate::core::running_state_manager::get_running_state_manager;
+use crate::http::early_hints::{CURRENT_EARLY_HINTS, EarlyHints, write_early_hints_while};
 use crate::http::handle_request::handle_request;
-use crate::http::http_tls::build_unified_tls_acceptor;
+use crate::http::holding_listener::{HoldingMode, get_holding_listeners};
+use crate::http::http_tls::{build_unified_tls_acceptor, get_tls_handshake_failure_reason};
 use crate::http::http_util::add_standard_headers_to_response;
+use crate::http::http2_flood_guard::{Http2FloodGuard, Http2GuardSettings, get_temporary_bans};
+use crate::http::keep_alive::{ConnectionTracker, TrackedIo, apply_keep_alive_headers};
+use crate::http::panic_guard::{catch_request_panic, get_panic_guard};
 use crate::http::request_response::gruxi_request::GruxiRequest;
 use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::http::request_response::request_timings::{CURRENT_REQUEST_TIMINGS, RequestTimings, TimedBody};
+use crate::http::request_validation::{RequestValidationLevel, validate_request};
 use crate::logging::syslog::{debug, error, info, trace, warn};
+use crate::tls::client_hello_fingerprint::{ClientHelloCapture, TLS_JA3_KEY, TLS_JA4_KEY, TlsFingerprint};
 use crate::tls::shared_acme_manager::initialize_shared_acme_manager;
+use dashmap::DashMap;
 use futures::FutureExt;
+use http_body_util::combinators::BoxBody;
 use hyper::Request;
 use hyper::body::Incoming;
 use hyper::service::service_fn;
-use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
 use hyper_util::server::conn::auto::Builder as HttpAutoBuilder;
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::select;
 use tokio_util::sync::CancellationToken;
+use utoipa::ToSchema;
+
+// The stop token of each running binding, by binding id, so a configuration change can restart only the bindings it concerns
+static RUNNING_BINDINGS: OnceLock<DashMap<String, CancellationToken>> = OnceLock::new();
+
+fn get_running_bindings() -> &'static DashMap<String, CancellationToken> {
+    RUNNING_BINDINGS.get_or_init(DashMap::new)
+}
+
+// How long to wait for the bindings to report whether they started. Bindings still starting after this are not reported
+const BINDING_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A binding that could not be started, such as when its port is in use by another program
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BindingStartError {
+    pub binding_id: String,
+    pub address: String,
+    pub error: String,
+}
 
 // Starting all the Gruxi magic
-pub async fn initialize_server() {
+pub async fn initialize_server() -> Vec<BindingStartError> {
     // Get configuration from the current configuration
     let cached_configuration = crate::configuration::cached_configuration::get_cached_configuration();
     let config = cached_configuration.get_configuration().await;
@@ -31,6 +65,9 @@ pub async fn initialize_server() {
     }
 
     // Starting listening on all configured bindings
+    let mut addresses = Vec::new();
+    let mut started_bindings = Vec::new();
+    get_running_bindings().retain(|binding_id, _| config.bindings.iter().any(|b| &b.id == binding_id));
     for binding in &config.bindings {
         let ip_result = binding.ip.parse::<std::net::IpAddr>();
         let ip = match ip_result {
@@ -42,6 +79,7 @@ pub async fn initialize_server() {
         };
         let port = binding.port;
         let addr = SocketAddr::new(ip, port);
+        addresses.push(addr);
 
         // Enforce admin bindings are TLS-only
         if binding.is_admin && !binding.is_tls {
@@ -49,28 +87,132 @@ pub async fn initialize_server() {
         }
 
         info(format!("Starting server on {}", addr));
+        started_bindings.push(binding.clone());
+    }
+
+    // Start listening on the specified addresses - spawn each binding as a separate task
+    let start_errors = start_bindings(started_bindings).await;
+
+    // Listeners held for bindings that were removed by the configuration change are closed
+    get_holding_listeners().release_all_except(&addresses);
+
+    start_errors
+}
+
+// Stops the bindings and starts the given ones, leaving all other bindings and their connections alone.
+// A binding started on the address of a stopped one takes over its listener, the listeners of removed bindings are closed
+pub async fn restart_bindings(stopped_binding_ids: &[String], started_bindings: Vec<Binding>) -> Vec<BindingStartError> {
+    for binding_id in stopped_binding_ids {
+        if let Some((_, stop_token)) = get_running_bindings().remove(binding_id) {
+            stop_token.cancel();
+        }
+    }
+
+    // Give a small delay for the stopped bindings to hand over their listeners
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for binding in &started_bindings {
+        info(format!("Starting server on {}:{}", binding.ip, binding.port));
+    }
+    let start_errors = start_bindings(started_bindings).await;
+
+    let cached_configuration = crate::configuration::cached_configuration::get_cached_configuration();
+    let addresses: Vec<SocketAddr> = cached_configuration
+        .get_configuration()
+        .await
+        .bindings
+        .iter()
+        .filter_map(|b| b.ip.parse::<std::net::IpAddr>().ok().map(|ip| SocketAddr::new(ip, b.port)))
+        .collect();
+    get_holding_listeners().release_all_except(&addresses);
+
+    start_errors
+}
+
+// Spawns a task for each binding and waits for them to either serve or give up, returning the bindings that could not be started
+async fn start_bindings(bindings: Vec<Binding>) -> Vec<BindingStartError> {
+    let starting: Vec<_> = bindings
+        .into_iter()
+        .map(|binding| {
+            let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+            let start_error = BindingStartError {
+                binding_id: binding.id.clone(),
+                address: format!("{}:{}", binding.ip, binding.port),
+                error: String::new(),
+            };
+            tokio::spawn(start_server_binding(binding, started_tx));
+            started_rx.map(move |result| match result {
+                Ok(Ok(())) => None,
+                Ok(Err(error)) => Some(BindingStartError { error, ..start_error }),
+                Err(_) => Some(BindingStartError {
+                    error: "The binding stopped while starting".to_string(),
+                    ..start_error
+                }),
+            })
+        })
+        .collect();
+
+    let mut start_errors = Vec::new();
+    let all_started = tokio::time::timeout(BINDING_START_TIMEOUT, async {
+        for started in starting {
+            if let Some(start_error) = started.await {
+                start_errors.push(start_error);
+            }
+        }
+    })
+    .await;
+    if all_started.is_err() {
+        warn(format!("Not all bindings reported being started within {} seconds", BINDING_START_TIMEOUT.as_secs()));
+    }
+    start_errors
+}
+
+// Opens the listeners of the bindings before the running state is started, so connections in the startup window are answered with 503
+// instead of being refused. TLS bindings have no certificates loaded yet, so their connections wait in the backlog until the binding starts
+pub async fn hold_bindings_during_startup() {
+    let cached_configuration = crate::configuration::cached_configuration::get_cached_configuration();
+    let config = cached_configuration.get_configuration().await;
 
-        // Start listening on the specified address - spawn each binding as a separate task
-        let binding_clone = binding.clone();
-        tokio::spawn(start_server_binding(binding_clone));
+    for binding in &config.bindings {
+        let Ok(ip) = binding.ip.parse::<std::net::IpAddr>() else {
+            continue;
+        };
+        let addr = SocketAddr::new(ip, binding.port);
+        // Listeners inherited from the previous process in a binary upgrade are already held
+        if get_holding_listeners().is_holding(&addr) {
+            continue;
+        }
+        match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                let mode = if binding.is_tls { HoldingMode::Backlog } else { HoldingMode::Http };
+                get_holding_listeners().hold(addr, Arc::new(listener), mode);
+            }
+            // The binding reports the error when it is started
+            Err(e) => debug(format!("Could not open listener on {} during startup: {}", addr, e)),
+        }
     }
 }
 
-async fn start_listener_with_retry(addr: SocketAddr) -> TcpListener {
+// Gets the listener for the address, taking it back from holding if it was kept open during startup or a reload
+async fn start_listener_with_retry(addr: SocketAddr) -> Result<Arc<TcpListener>, String> {
     // Implement a simple retry mechanism
     let mut attempts = 0;
     let max_attempts = 5;
     let retry_delay = std::time::Duration::from_millis(100);
 
     loop {
+        // The binding stopped by a reload may only just be handing over its listener, so holding is checked on every attempt
+        if let Some(listener) = get_holding_listeners().take(&addr) {
+            return Ok(listener);
+        }
         match TcpListener::bind(addr).await {
             Ok(listener) => {
-                return listener;
+                return Ok(Arc::new(listener));
             }
             Err(e) => {
                 attempts += 1;
                 if attempts >= max_attempts {
-                    panic!("Failed to bind to {} after {} attempts: {}", addr, attempts, e);
+                    return Err(format!("Failed to bind to {} after {} attempts: {}", addr, attempts, e));
                 }
                 error(format!("Failed to bind to {}: {}. Retrying in {:?}...", addr, e, retry_delay));
                 tokio::time::sleep(retry_delay).await;
@@ -79,19 +221,29 @@ async fn start_listener_with_retry(addr: SocketAddr) -> TcpListener {
     }
 }
 
-async fn start_server_binding(binding: Binding) {
+// Reports on started whether the binding is serving, or why it could not be started
+async fn start_server_binding(binding: Binding, started: tokio::sync::oneshot::Sender<Result<(), String>>) {
     let ip_result = binding.ip.parse::<std::net::IpAddr>();
     let ip = match ip_result {
         Ok(ip_addr) => ip_addr,
         Err(e) => {
             error(format!("Invalid IP address for binding {}: {}. Skipping this binding.", binding.ip, e));
+            let _ = started.send(Err(format!("Invalid IP address: {}", e)));
             return;
         }
     };
     let port = binding.port;
     let addr = SocketAddr::new(ip, port);
 
-    let listener = start_listener_with_retry(addr).await;
+    let listener = match start_listener_with_retry(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error(e.clone());
+            let _ = started.send(Err(e));
+            return;
+        }
+    };
+    register_active_listener(addr, &listener);
     trace(format!("Listening on binding: {:?}", binding));
 
     let triggers = crate::core::triggers::get_trigger_handler();
@@ -107,12 +259,23 @@ async fn start_server_binding(binding: Binding) {
 
     let stop_services_token_option = triggers.get_token("stop_services").await;
     let stop_services_token = match stop_services_token_option {
-        Some(token) => token,
+        // Also cancelled when only this binding is restarted
+        Some(token) => token.child_token(),
         None => {
             error("Failed to get stop_services token - Could not start server binding. Please report a bug".to_string());
             return;
         }
     };
+    get_running_bindings().insert(binding.id.clone(), stop_services_token.clone());
+
+    let drain_token_option = triggers.get_token("drain").await;
+    let drain_token = match drain_token_option {
+        Some(token) => token,
+        None => {
+            error("Failed to get drain token - Could not start server binding. Please report a bug".to_string());
+            return;
+        }
+    };
 
     if binding.is_tls {
         // Build unified TLS acceptor that handles both ACME and manual certificates
@@ -121,9 +284,11 @@ async fn start_server_binding(binding: Binding) {
             Ok(result) => result,
             Err(e) => {
                 error(format!("TLS setup failed for {}:{} => {}", binding.ip, binding.port, e));
+                let _ = started.send(Err(format!("TLS setup failed: {}", e)));
                 return;
             }
         };
+        let _ = started.send(Ok(()));
 
         // Unified TLS accept loop
         loop {
@@ -134,6 +299,11 @@ async fn start_server_binding(binding: Binding) {
                 },
                 _ = stop_services_token.cancelled() => {
                     trace(format!("Service cancellation signal received, stopping server on {}:{}", binding.ip, binding.port));
+                    get_holding_listeners().hold(addr, listener, HoldingMode::Https(tls_acceptor));
+                    break;
+                },
+                _ = drain_token.cancelled() => {
+                    trace(format!("Drain signal received, no longer accepting connections on {}:{}", binding.ip, binding.port));
                     break;
                 },
                 result = listener.accept() => {
@@ -143,20 +313,39 @@ async fn start_server_binding(binding: Binding) {
                                 .map(|addr| addr.ip().to_string())
                                 .unwrap_or_else(|_| "<unknown>".to_string());
 
+                            // Clients banned for flooding us are dropped before any TLS handshake or request parsing, except on the admin binding
+                            let monitoring_state = get_monitoring_state().await;
+                            monitoring_state.increment_connections_accepted();
+                            if !binding.is_admin && get_temporary_bans().is_banned(&remote_addr_ip) {
+                                trace(format!("Dropping connection from temporarily banned {} on {}:{}", remote_addr_ip, binding.ip, binding.port));
+                                monitoring_state.increment_connections_rejected_banned();
+                                continue;
+                            }
+                            let active_connection = monitoring_state.track_active_connection();
+
                             let acceptor = tls_acceptor.clone();
                             let binding = binding.clone();
                             let shutdown_token = shutdown_token.clone();
                             let stop_services_token = stop_services_token.clone();
+                            let drain_token = drain_token.clone();
 
                             tokio::spawn(async move {
-                                match acceptor.accept(tcp_stream).await {
-                                    Ok(tls_stream) => {
-                                        let io = TokioIo::new(tls_stream);
+                                let _active_connection = active_connection;
+                                let handshake_result = {
+                                    let _reading = monitoring_state.track_connection_reading();
+                                    acceptor.accept(ClientHelloCapture::new(tcp_stream)).await
+                                };
+                                match handshake_result {
+                                    Ok(mut tls_stream) => {
+                                        let tls_handshake = TlsHandshake {
+                                            fingerprint: tls_stream.get_mut().0.take_fingerprint(),
+                                            is_http2: tls_stream.get_ref().1.alpn_protocol() == Some(b"h2".as_slice()),
+                                        };
+
                                         // Increment requests in queue when connection is ready to be served
-                                        let monitoring_state = get_monitoring_state().await;
                                         monitoring_state.increment_requests_in_queue();
 
-                                        if let Err(panic) = std::panic::AssertUnwindSafe(serve_connection(io, binding, remote_addr_ip, shutdown_token, stop_services_token)).catch_unwind().await {
+                                        if let Err(panic) = std::panic::AssertUnwindSafe(serve_connection(tls_stream, binding, remote_addr_ip, Some(tls_handshake), shutdown_token, stop_services_token, drain_token)).catch_unwind().await {
                                             debug(format!("Panic occurred while serving TLS connection: {:?}", panic));
                                         }
 
@@ -164,7 +353,9 @@ async fn start_server_binding(binding: Binding) {
                                         monitoring_state.decrement_requests_in_queue();
                                     }
                                     Err(err) => {
-                                        trace(format!("TLS handshake error: {:?}", err));
+                                        let reason = get_tls_handshake_failure_reason(&err);
+                                        debug(format!("TLS handshake failed on {}:{} from {}: {} ({})", binding.ip, binding.port, remote_addr_ip, reason, err));
+                                        monitoring_state.increment_tls_handshake_failures(&format!("{}:{}", binding.ip, binding.port), reason);
                                     }
                                 }
                             });
@@ -177,6 +368,7 @@ async fn start_server_binding(binding: Binding) {
             };
         }
     } else {
+        let _ = started.send(Ok(()));
         loop {
             select! {
                 _ = shutdown_token.cancelled() => {
@@ -185,6 +377,11 @@ async fn start_server_binding(binding: Binding) {
                 },
                 _ = stop_services_token.cancelled() => {
                     trace(format!("Service stop signal received, stopping server on {}:{}", binding.ip, binding.port));
+                    get_holding_listeners().hold(addr, listener, HoldingMode::Http);
+                    break;
+                },
+                _ = drain_token.cancelled() => {
+                    trace(format!("Drain signal received, no longer accepting connections on {}:{}", binding.ip, binding.port));
                     break;
                 },
                 result = listener.accept() => {
@@ -194,17 +391,28 @@ async fn start_server_binding(binding: Binding) {
                                 .map(|addr| addr.ip().to_string())
                                 .unwrap_or_else(|_| "<unknown>".to_string());
 
-                            let io = TokioIo::new(tcp_stream);
+                            // Clients banned for flooding us are dropped before any TLS handshake or request parsing, except on the admin binding
+                            let monitoring_state = get_monitoring_state().await;
+                            monitoring_state.increment_connections_accepted();
+                            if !binding.is_admin && get_temporary_bans().is_banned(&remote_addr_ip) {
+                                trace(format!("Dropping connection from temporarily banned {} on {}:{}", remote_addr_ip, binding.ip, binding.port));
+                                monitoring_state.increment_connections_rejected_banned();
+                                continue;
+                            }
+                            let active_connection = monitoring_state.track_active_connection();
+
                             let binding = binding.clone();
                             let shutdown_token = shutdown_token.clone();
                             let stop_services_token = stop_services_token.clone();
+                            let drain_token = drain_token.clone();
 
                             tokio::spawn(async move {
+                                let _active_connection = active_connection;
+
                                 // Increment requests in queue when connection is ready to be served
-                                let monitoring_state = get_monitoring_state().await;
                                 monitoring_state.increment_requests_in_queue();
 
-                                if let Err(panic) = std::panic::AssertUnwindSafe(serve_connection(io, binding, remote_addr_ip, shutdown_token, stop_services_token)).catch_unwind().await {
+                                if let Err(panic) = std::panic::AssertUnwindSafe(serve_connection(tcp_stream, binding, remote_addr_ip, None, shutdown_token, stop_services_token, drain_token)).catch_unwind().await {
                                     debug(format!("Panic occurred while serving connection: {:?}", panic));
                                 }
 
@@ -222,54 +430,275 @@ async fn start_server_binding(binding: Binding) {
     }
 }
 
+// What the TLS handshake of a connection told about the client
+struct TlsHandshake {
+    fingerprint: Option<TlsFingerprint>,
+    // The client picked HTTP/2 with ALPN
+    is_http2: bool,
+}
+
 // Helper function to serve a connection (works for both TLS and non-TLS)
-async fn serve_connection<S>(io: TokioIo<S>, binding: Binding, remote_addr_ip: String, shutdown_token: CancellationToken, stop_services_token: CancellationToken)
-where
+async fn serve_connection<S>(
+    stream: S,
+    binding: Binding,
+    remote_addr_ip: String,
+    tls_handshake: Option<TlsHandshake>,
+    shutdown_token: CancellationToken,
+    stop_services_token: CancellationToken,
+    drain_token: CancellationToken,
+) where
     S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
 {
+    let tls_fingerprint = tls_handshake.as_ref().and_then(|tls_handshake| tls_handshake.fingerprint.clone());
+    let is_http2 = tls_handshake.is_some_and(|tls_handshake| tls_handshake.is_http2);
     let shutdown_token_conn = shutdown_token.clone();
     let stop_services_token_conn = stop_services_token.clone();
 
+    // Track the connection, so we can enforce the keep-alive and pipelining limits for the binding
+    let connection_tracker = Arc::new(ConnectionTracker::default());
+    let close_connection_token = connection_tracker.get_close_token();
+
+    // A configuration change restarts the bindings and their connections, so the settings are read once per connection
+    let (request_validation_level, http2_guard_settings, early_hints_write_timeout) = {
+        let cached_configuration = crate::configuration::cached_configuration::get_cached_configuration();
+        let configuration = cached_configuration.get_configuration().await;
+        (
+            RequestValidationLevel::from_setting(&configuration.core.server_settings.request_validation_level),
+            Http2GuardSettings::new(&configuration.core.server_settings, &binding, &remote_addr_ip),
+            Duration::from_secs(configuration.core.server_settings.outbound_write_timeout_seconds),
+        )
+    };
+    let binding_address = format!("{}:{}", binding.ip, binding.port);
+
+    // Early hints are written to the stream besides hyper, which needs the stream to be shared. Only HTTP/1.1 connections to bindings
+    // with a site sending early hints take the cost of that
+    let sends_early_hints = !is_http2
+        && binding.offers_http1()
+        && get_running_state_manager()
+            .await
+            .get_running_state_unlocked()
+            .await
+            .get_binding_site_cache()
+            .get_sites_for_binding(&binding.id)
+            .iter()
+            .any(|site| site.early_hints_enabled);
+    let tracked_io = if sends_early_hints {
+        TrackedIo::with_interim_responses(stream, connection_tracker.clone())
+    } else {
+        TrackedIo::new(stream, connection_tracker.clone())
+    };
+    let io = TokioIo::new(Http2FloodGuard::new(tracked_io, http2_guard_settings, &remote_addr_ip, &binding_address));
+
+    let mut connection = HttpAutoBuilder::new(TokioExecutor::new());
+    connection.http1().timer(TokioTimer::new()).header_read_timeout(Duration::from_secs(binding.keep_alive_timeout_seconds));
+
+    // Clients going over the reset stream limits get a GOAWAY with ENHANCE_YOUR_CALM from the HTTP/2 implementation
+    let http2_limits = &binding.http2;
+    connection
+        .http2()
+        .max_concurrent_streams(http2_limits.max_concurrent_streams)
+        .initial_stream_window_size(http2_limits.initial_stream_window_size)
+        .initial_connection_window_size(http2_limits.initial_connection_window_size)
+        .max_header_list_size(http2_limits.max_header_list_size)
+        .max_frame_size(HTTP2_MAX_FRAME_SIZE)
+        .max_send_buf_size(http2_limits.get_max_send_buffer_size())
+        .max_pending_accept_reset_streams(http2_limits.max_pending_reset_streams as usize)
+        .max_local_error_reset_streams(http2_limits.max_local_error_reset_streams as usize);
+
+    // Only the protocols offered by the binding are spoken. Over TLS the client has picked one of them with ALPN already, and
+    // without TLS this keeps clients from starting HTTP/2 with prior knowledge when h2c is turned off
+    let connection = match (binding.offers_http1(), binding.offers_http2()) {
+        (true, false) => connection.http1_only(),
+        (false, true) => connection.http2_only(),
+        _ => connection,
+    };
+
     let svc = service_fn(move |req: Request<Incoming>| {
         let binding = binding.clone();
         let remote_ip = remote_addr_ip.clone();
+        let tls_fingerprint = tls_fingerprint.clone();
+        let connection_tracker = connection_tracker.clone();
 
         async move {
+            let monitoring_state = get_monitoring_state().await;
+
             // Count the request in monitoring
-            get_monitoring_state().await.increment_requests_served();
+            monitoring_state.increment_requests_served();
+
+            let http_version = req.version();
+            let request_connection_header = req.headers().get(hyper::header::CONNECTION).and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+            let has_expect_header = req.headers().contains_key(hyper::header::EXPECT);
+            let request_number = connection_tracker.start_request();
+            let is_http1 = http_version == hyper::Version::HTTP_11 || http_version == hyper::Version::HTTP_10;
+            let is_pipelined = is_http1 && connection_tracker.is_pipelined_request(request_number);
+            if is_http1 && request_number > 1 {
+                monitoring_state.increment_keep_alive_reused_requests();
+                if is_pipelined {
+                    monitoring_state.increment_pipelined_requests();
+                    if !binding.pipelining_enabled {
+                        // The connection is dropped without answering, so the client retries the request on a new connection
+                        trace(format!("Pipelined request from {} rejected on binding {}:{}, closing connection", &remote_ip, binding.ip, binding.port));
+                        monitoring_state.increment_pipelined_requests_rejected();
+                        connection_tracker.close_connection();
+                        return std::future::pending().await;
+                    }
+                }
+            }
+
+            // Requests that proxies in front of or behind us could read differently are rejected, and the connection is closed after the response
+            if let Err(reason) = validate_request(&req, request_validation_level) {
+                debug(format!("Rejected request from {} on binding {}:{}: {}", &remote_ip, binding.ip, binding.port, reason));
+                monitoring_state.increment_requests_rejected_invalid(reason);
+                let mut response = GruxiResponse::new_empty_with_status(hyper::StatusCode::BAD_REQUEST.as_u16());
+                add_standard_headers_to_response(&mut response);
+                if is_http1 {
+                    response.headers_mut().insert(hyper::header::CONNECTION, hyper::header::HeaderValue::from_static("close"));
+                }
+                return Ok(response.into_hyper());
+            }
+
+            // Requests meant for a proxy, with an absolute URI, are refused on plain bindings that do not accept them
+            if is_http1 && !binding.is_tls && !binding.absolute_form_enabled && req.uri().scheme().is_some() {
+                debug(format!("Rejected request with an absolute URI from {} on binding {}:{}", &remote_ip, binding.ip, binding.port));
+                monitoring_state.increment_requests_rejected_invalid("absolute_form");
+                let mut response = GruxiResponse::new_empty_with_status(hyper::StatusCode::BAD_REQUEST.as_u16());
+                add_standard_headers_to_response(&mut response);
+                return Ok(response.into_hyper());
+            }
+
+            // After a panic storm, requests are answered with 503 until maintenance mode ends, except on the admin bindings
+            if !binding.is_admin
+                && let Some(remaining) = get_panic_guard().get_maintenance_remaining()
+            {
+                let mut response = GruxiResponse::new_empty_with_status(hyper::StatusCode::SERVICE_UNAVAILABLE.as_u16());
+                add_standard_headers_to_response(&mut response);
+                response.headers_mut().insert(hyper::header::RETRY_AFTER, hyper::header::HeaderValue::from(remaining.as_secs().max(1)));
+                return Ok(response.into_hyper());
+            }
+
+            // Counted as writing until the response body has been sent, or the request is dropped
+            let request_writing = monitoring_state.track_request_writing();
 
             let mut gruxi_request = GruxiRequest::from_hyper(req);
             gruxi_request.add_calculated_data("remote_ip", &remote_ip);
-            let gruxi_response_result = handle_request(gruxi_request, binding).await;
+            if binding.is_tls {
+                gruxi_request.add_calculated_data("is_https", "true");
+                gruxi_request.add_calculated_data("scheme", "https");
+            }
+            if let Some(tls_fingerprint) = &tls_fingerprint {
+                gruxi_request.add_calculated_data(TLS_JA3_KEY, &tls_fingerprint.ja3);
+                gruxi_request.add_calculated_data(TLS_JA4_KEY, &tls_fingerprint.ja4);
+            }
+            let http_method = gruxi_request.get_http_method();
+            let request_path = gruxi_request.get_path();
+            // A panic in the handling is answered with 500 instead of taking down the connection without a response
+            // Phases are recorded into the timings of the request wherever they happen during the handling
+            let request_timings = Arc::new(RequestTimings::new());
+            // Early hints are written to the connection as 103 responses where that is safe: HTTP/1.1 only knows interim responses from
+            // version 1.1, and hyper could still be writing the previous response of pipelined requests or a 100 Continue
+            let (early_hints, early_hints_receiver) = EarlyHints::new();
+            let can_write_early_hints = http_version == hyper::Version::HTTP_11 && !is_pipelined && !has_expect_header && connection_tracker.can_write_interim_responses();
+            let handling = CURRENT_EARLY_HINTS.scope(
+                early_hints.clone(),
+                CURRENT_REQUEST_TIMINGS.scope(request_timings.clone(), catch_request_panic(handle_request(gruxi_request, binding.clone()))),
+            );
+            let gruxi_response_result = if can_write_early_hints {
+                write_early_hints_while(handling, early_hints_receiver, &connection_tracker, early_hints_write_timeout).await
+            } else {
+                handling.await
+            };
             let mut response = match gruxi_response_result {
-                Err(err) => {
+                Err(request_panic) => {
+                    error(format!(
+                        "Panic while handling {} {} from {}: {} at {}\n{}",
+                        &http_method, &request_path, &remote_ip, request_panic.message, request_panic.location, request_panic.backtrace
+                    ));
+                    monitoring_state.increment_requests_panicked();
+                    if get_panic_guard().record_panic() {
+                        error("Too many requests panicked, entering maintenance mode - Requests are answered with 503 until it ends");
+                    }
+                    let mut response = GruxiResponse::new_empty_with_status(hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16());
+                    if is_http1 {
+                        response.headers_mut().insert(hyper::header::CONNECTION, hyper::header::HeaderValue::from_static("close"));
+                    }
+                    response
+                }
+                Ok(Err(err)) => {
                     error(format!("Error handling request from {}: {:?}", &remote_ip, err));
                     let response = GruxiResponse::new_empty_with_status(hyper::StatusCode::INTERNAL_SERVER_ERROR.as_u16());
                     response
                 }
-                Ok(response) => response,
+                Ok(Ok(response)) => response,
             };
 
             // Add standard headers
             add_standard_headers_to_response(&mut response);
+            early_hints.add_links_to_response(&mut response);
+
+            // Make sure body and Content-Length are consistent for HEAD requests and bodiless status codes
+            response.finalize_for_request_method(&http_method);
+
+            // Tell the client how long the connection is kept alive, or close it if the max requests is reached
+            if apply_keep_alive_headers(&mut response, &binding, http_version, &request_connection_header, request_number) {
+                monitoring_state.increment_keep_alive_max_requests_reached();
+            }
 
             debug(format!("Responding with: {:?}", response));
 
-            // Convert gruxi_response to hyper response
-            Ok::<_, std::convert::Infallible>(response.into_hyper())
+            // Convert gruxi_response to hyper response, timing how long the body takes to reach the client
+            let hyper_response = response.into_hyper().map(|body| {
+                BoxBody::new(TimedBody::new(body, move |client_write, bytes_sent| {
+                    drop(request_writing);
+                    request_timings.complete(client_write, bytes_sent)
+                }))
+            });
+            Ok::<_, std::convert::Infallible>(hyper_response)
         }
     });
 
-    let connection = HttpAutoBuilder::new(TokioExecutor::new());
-
     // Serve the connection and listen for shutdown signals
+    let serving_connection = connection.serve_connection_with_upgrades(io, svc);
+    tokio::pin!(serving_connection);
     let result = tokio::select! {
-        res = connection.serve_connection_with_upgrades(io, svc) => res,
+        res = serving_connection.as_mut() => res,
+        // When draining, the requests in progress are finished before the connection is closed
+        _ = drain_token.cancelled() => {
+            serving_connection.as_mut().graceful_shutdown();
+            tokio::select! {
+                res = serving_connection.as_mut() => res,
+                _ = shutdown_token_conn.cancelled() => Ok(()),
+            }
+        },
         _ = shutdown_token_conn.cancelled() => Ok(()),
         _ = stop_services_token_conn.cancelled() => Ok(()),
+        _ = close_connection_token.cancelled() => Ok(()),
     };
 
     if let Err(err) = result {
-        trace(format!("Connection error: {:?}", err));
+        // Idle keep-alive connections end with a header read timeout
+        if err.downcast_ref::<hyper::Error>().is_some_and(|e| e.is_timeout()) {
+            trace("Connection closed after keep-alive timeout".to_string());
+            get_monitoring_state().await.increment_keep_alive_timeouts();
+        } else {
+            trace(format!("Connection error: {:?}", err));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_binding_on_port_in_use_is_reported() {
+        let other_program = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut binding = Binding::new();
+        binding.ip = "127.0.0.1".to_string();
+        binding.port = other_program.local_addr().unwrap().port();
+
+        let start_errors = start_bindings(vec![binding.clone()]).await;
+        assert_eq!(start_errors.len(), 1);
+        assert_eq!(start_errors[0].binding_id, binding.id);
+        assert!(start_errors[0].error.contains("Failed to bind"), "{}", start_errors[0].error);
     }
 }
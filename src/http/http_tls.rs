@@ -2,8 +2,8 @@ use crate::core::running_state_manager::get_running_state_manager;
 use crate::logging::syslog::{debug, warn};
 use crate::tls::shared_acme_manager::{get_shared_acme_domains, get_shared_acme_manager_async};
 use rand;
-use rustls_acme::ResolvesServerCertAcme;
 use rustls::crypto::aws_lc_rs;
+use rustls_acme::ResolvesServerCertAcme;
 use rustls_pki_types::{CertificateDer, PrivateKeyDer};
 use std::io::BufReader;
 use tls_listener::rustls as tokio_rustls;
@@ -228,10 +228,7 @@ pub async fn get_acme_domains_for_binding(binding: &Binding) -> std::collections
 
 /// Build a unified certificate resolver that handles both ACME and manual certificates.
 /// Uses the shared ACME manager if available.
-pub async fn build_unified_cert_resolver(
-    binding: &Binding,
-    acme_resolver: Option<std::sync::Arc<ResolvesServerCertAcme>>,
-) -> Result<UnifiedCertResolver, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn build_unified_cert_resolver(binding: &Binding, acme_resolver: Option<std::sync::Arc<ResolvesServerCertAcme>>) -> Result<UnifiedCertResolver, Box<dyn std::error::Error + Send + Sync>> {
     // Get ACME domains from the shared manager if available, otherwise use binding-specific lookup
     let acme_domains = {
         let shared_domains = get_shared_acme_domains().await;
@@ -242,10 +239,7 @@ pub async fn build_unified_cert_resolver(
         }
     };
 
-    debug(format!(
-        "Building unified cert resolver for {}:{} with {} ACME domains",
-        binding.ip, binding.port, acme_domains.len()
-    ));
+    debug(format!("Building unified cert resolver for {}:{} with {} ACME domains", binding.ip, binding.port, acme_domains.len()));
 
     let mut resolver = UnifiedCertResolver::new(acme_resolver, acme_domains.clone());
     let mut fallback_certificate: Option<std::sync::Arc<RustlsCertifiedKey>> = None;
@@ -260,19 +254,12 @@ pub async fn build_unified_cert_resolver(
         // Skip sites that have ACME enabled - they'll be handled by the ACME resolver
         if site.tls_automatic_enabled {
             // For ACME-enabled sites with no manual cert, we still need to log it
-            debug(format!(
-                "Site '{}' has ACME enabled, will use ACME resolver for its domains",
-                site.id
-            ));
+            debug(format!("Site '{}' has ACME enabled, will use ACME resolver for its domains", site.id));
             continue;
         }
 
         // Determine SANs for this site
-        let mut sans: Vec<String> = site.hostnames
-            .iter()
-            .cloned()
-            .filter(|h| !h.trim().is_empty() && h != "*")
-            .collect();
+        let mut sans: Vec<String> = site.hostnames.iter().cloned().filter(|h| !h.trim().is_empty() && h != "*").collect();
         let has_wildcard = site.hostnames.contains(&"*".to_string());
 
         if sans.is_empty() || has_wildcard {
@@ -291,10 +278,8 @@ pub async fn build_unified_cert_resolver(
         // Load or generate certificate
         let (cert_chain, priv_key) = if !site.tls_cert_path.is_empty() && !site.tls_key_path.is_empty() {
             // Load from PEM files
-            let cert_file = std::fs::File::open(&site.tls_cert_path)
-                .map_err(|e| format!("Failed to open TLS cert file {}: {}", site.tls_cert_path, e))?;
-            let key_file = std::fs::File::open(&site.tls_key_path)
-                .map_err(|e| format!("Failed to open TLS key file {}: {}", site.tls_key_path, e))?;
+            let cert_file = std::fs::File::open(&site.tls_cert_path).map_err(|e| format!("Failed to open TLS cert file {}: {}", site.tls_cert_path, e))?;
+            let key_file = std::fs::File::open(&site.tls_key_path).map_err(|e| format!("Failed to open TLS key file {}: {}", site.tls_key_path, e))?;
 
             let mut cert_reader = BufReader::new(cert_file);
             let mut key_reader = BufReader::new(key_file);
@@ -302,8 +287,7 @@ pub async fn build_unified_cert_resolver(
             let certs: Result<Vec<CertificateDer<'static>>, _> = rustls_pemfile::certs(&mut cert_reader).collect();
             let cert_chain = certs.map_err(|e| format!("Failed to parse TLS cert file {}: {}", site.tls_cert_path, e))?;
 
-            let key_result = rustls_pemfile::private_key(&mut key_reader)
-                .map_err(|e| format!("Failed to parse TLS key file {}: {}", site.tls_key_path, e))?;
+            let key_result = rustls_pemfile::private_key(&mut key_reader).map_err(|e| format!("Failed to parse TLS key file {}: {}", site.tls_key_path, e))?;
             let priv_key = key_result.ok_or_else(|| format!("No private key found in {}", site.tls_key_path))?;
 
             (cert_chain, priv_key)
@@ -315,16 +299,14 @@ pub async fn build_unified_cert_resolver(
             let certs: Result<Vec<CertificateDer<'static>>, _> = rustls_pemfile::certs(&mut cert_cursor).collect();
             let cert_chain = certs.map_err(|e| format!("Failed to parse TLS cert PEM content: {}", e))?;
 
-            let key_result = rustls_pemfile::private_key(&mut key_cursor)
-                .map_err(|e| format!("Failed to parse TLS key PEM content: {}", e))?;
+            let key_result = rustls_pemfile::private_key(&mut key_cursor).map_err(|e| format!("Failed to parse TLS key PEM content: {}", e))?;
             let priv_key = key_result.ok_or_else(|| "No private key found in PEM content".to_string())?;
 
             (cert_chain, priv_key)
         } else {
             // Generate self-signed certificate
             debug(format!("Generating self-signed certificate for site with hostnames: {:?}", sans));
-            let rcgen::CertifiedKey { cert, signing_key } = rcgen::generate_simple_self_signed(sans.clone())
-                .map_err(|e| format!("Failed to generate self-signed cert: {}", e))?;
+            let rcgen::CertifiedKey { cert, signing_key } = rcgen::generate_simple_self_signed(sans.clone()).map_err(|e| format!("Failed to generate self-signed cert: {}", e))?;
             let cert_pem = cert.pem();
             let key_pem = signing_key.serialize_pem();
 
@@ -334,8 +316,7 @@ pub async fn build_unified_cert_resolver(
             let certs: Result<Vec<CertificateDer<'static>>, _> = rustls_pemfile::certs(&mut cert_cursor).collect();
             let cert_chain = certs.map_err(|e| format!("Failed to parse generated TLS cert PEM content: {}", e))?;
 
-            let key_result = rustls_pemfile::private_key(&mut key_cursor)
-                .map_err(|e| format!("Failed to parse generated TLS key PEM content: {}", e))?;
+            let key_result = rustls_pemfile::private_key(&mut key_cursor).map_err(|e| format!("Failed to parse generated TLS key PEM content: {}", e))?;
             let priv_key = key_result.ok_or_else(|| "No private key found in generated PEM content".to_string())?;
 
             // Persist generated cert/key to disk
@@ -357,8 +338,7 @@ pub async fn build_unified_cert_resolver(
         }
 
         // Build certified key
-        let signing_key = aws_lc_rs::sign::any_supported_type(&priv_key)
-            .map_err(|e| format!("Unsupported private key type: {}", e))?;
+        let signing_key = aws_lc_rs::sign::any_supported_type(&priv_key).map_err(|e| format!("Unsupported private key type: {}", e))?;
         let certified = RustlsCertifiedKey::new(cert_chain, signing_key);
         let certified_arc = std::sync::Arc::new(certified);
 
@@ -397,13 +377,10 @@ pub async fn build_unified_cert_resolver(
     if !cert_added && acme_domains.is_empty() {
         // Generate a fallback self-signed cert
         let rcgen::CertifiedKey { cert, signing_key } =
-            rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
-                .map_err(|e| format!("Failed to generate fallback self-signed cert: {}", e))?;
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).map_err(|e| format!("Failed to generate fallback self-signed cert: {}", e))?;
         let cert_der = CertificateDer::from(cert.der().to_vec());
-        let key_der = PrivateKeyDer::try_from(signing_key.serialize_der())
-            .map_err(|e| format!("Invalid key DER: {}", e))?;
-        let signing_key = aws_lc_rs::sign::any_supported_type(&key_der)
-            .map_err(|e| format!("Unsupported private key type: {}", e))?;
+        let key_der = PrivateKeyDer::try_from(signing_key.serialize_der()).map_err(|e| format!("Invalid key DER: {}", e))?;
+        let signing_key = aws_lc_rs::sign::any_supported_type(&key_der).map_err(|e| format!("Unsupported private key type: {}", e))?;
         let certified = RustlsCertifiedKey::new(vec![cert_der], signing_key);
         let certified_arc = std::sync::Arc::new(certified);
 
@@ -427,9 +404,7 @@ pub async fn build_unified_cert_resolver(
 /// Build a unified TLS acceptor that handles both ACME and manual certificates.
 /// Uses the shared ACME manager if available, ensuring only one ACME client exists globally.
 /// Returns the TlsAcceptor only (ACME polling is handled by the shared manager).
-pub async fn build_unified_tls_acceptor(
-    binding: &Binding,
-) -> Result<TlsAcceptor, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn build_unified_tls_acceptor(binding: &Binding) -> Result<TlsAcceptor, Box<dyn std::error::Error + Send + Sync>> {
     let provider = rustls::crypto::aws_lc_rs::default_provider();
 
     // Get the shared ACME resolver if available (already initialized during server startup)
@@ -446,8 +421,8 @@ pub async fn build_unified_tls_acceptor(
         .with_no_client_auth()
         .with_cert_resolver(std::sync::Arc::new(unified_resolver));
 
-    // Enable ALPN for HTTP/2 and HTTP/1.1, and add ACME TLS-ALPN-01 protocol if ACME is enabled
-    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    // Enable ALPN for the HTTP versions offered by the binding, and add ACME TLS-ALPN-01 protocol if ACME is enabled
+    server_config.alpn_protocols = binding.get_alpn_protocols();
     if has_acme {
         // TLS-ALPN-01 protocol identifier for ACME challenges
         server_config.alpn_protocols.push(b"acme-tls/1".to_vec());
@@ -646,8 +621,50 @@ pub async fn build_tls_acceptor(binding: &Binding) -> Result<TlsAcceptor, Box<dy
         .with_no_client_auth()
         .with_cert_resolver(std::sync::Arc::new(fallback_resolver));
 
-    // Enable ALPN for HTTP/2 and HTTP/1.1 (prefer h2)
-    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    // Enable ALPN for the HTTP versions offered by the binding (prefer h2)
+    server_config.alpn_protocols = binding.get_alpn_protocols();
 
     Ok(TlsAcceptor::from(std::sync::Arc::new(server_config)))
 }
+
+// Sorts a failed TLS handshake into a reason, for logging and the per binding counts in monitoring
+pub fn get_tls_handshake_failure_reason(err: &std::io::Error) -> &'static str {
+    if let Some(tls_error) = err.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+        return match tls_error {
+            rustls::Error::General(message) if message.contains("no server certificate") => "no_certificate_for_sni",
+            rustls::Error::UnsupportedNameType => "invalid_sni",
+            rustls::Error::PeerIncompatible(_) | rustls::Error::NoApplicationProtocol => "protocol_mismatch",
+            rustls::Error::NoCertificatesPresented | rustls::Error::InvalidCertificate(_) => "client_certificate",
+            rustls::Error::AlertReceived(_) => "alert_received",
+            rustls::Error::InvalidMessage(_) | rustls::Error::InappropriateMessage { .. } | rustls::Error::InappropriateHandshakeMessage { .. } => "invalid_message",
+            rustls::Error::PeerMisbehaved(_) => "peer_misbehaved",
+            _ => "other",
+        };
+    }
+    match err.kind() {
+        std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::BrokenPipe => "connection_closed",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_tls_handshake_failure_reason() {
+        let tls_error = |e: rustls::Error| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        assert_eq!(
+            get_tls_handshake_failure_reason(&tls_error(rustls::Error::General("no server certificate chain resolved".to_string()))),
+            "no_certificate_for_sni"
+        );
+        assert_eq!(get_tls_handshake_failure_reason(&tls_error(rustls::Error::NoApplicationProtocol)), "protocol_mismatch");
+        assert_eq!(get_tls_handshake_failure_reason(&tls_error(rustls::Error::NoCertificatesPresented)), "client_certificate");
+        assert_eq!(
+            get_tls_handshake_failure_reason(&tls_error(rustls::Error::InvalidMessage(rustls::InvalidMessage::InvalidContentType))),
+            "invalid_message"
+        );
+        assert_eq!(get_tls_handshake_failure_reason(&std::io::Error::from(std::io::ErrorKind::UnexpectedEof)), "connection_closed");
+        assert_eq!(get_tls_handshake_failure_reason(&std::io::Error::other("something else")), "other");
+    }
+}
@@ -1,14 +1,24 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
 
+use chrono::Utc;
 use http::HeaderValue;
 use http_body_util::{BodyExt, Full, combinators::BoxBody};
 use hyper::body::Bytes;
 
+use crate::configuration::server_settings::SERVER_HEADER_MODES;
+use crate::core::operation_mode::get_operation_mode;
 use crate::core::running_state_manager::get_running_state_manager;
+use crate::error::gruxi_error::GruxiError;
+use crate::external_connections::fastcgi::FASTCGI_STDERR_KEY;
 use crate::file::file_reader_structs::FileEntry;
 use crate::file::normalized_path::NormalizedPath;
+use crate::http::request_response::gruxi_request::GruxiRequest;
 use crate::http::request_response::gruxi_response::GruxiResponse;
 
+// The methods Gruxi handles, others are answered with a 501 before any request handler sees them
+pub const SUPPORTED_HTTP_METHODS: [&str; 9] = ["GET", "POST", "HEAD", "PUT", "DELETE", "OPTIONS", "TRACE", "CONNECT", "PATCH"];
+
 pub fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
     Full::new(chunk.into()).map_err(|never| match never {}).boxed()
 }
@@ -27,19 +37,89 @@ pub fn empty_response_with_status(status: hyper::StatusCode) -> GruxiResponse {
     resp
 }
 
+// Error response for a failed request. When the operation mode shows error details, such as in development, the body describes the error
+// and where it happened. Otherwise the body is left empty, so nothing about the internals is exposed
+pub fn error_response_with_details(status: hyper::StatusCode, gruxi_error: &GruxiError, gruxi_request: &GruxiRequest) -> GruxiResponse {
+    if !get_operation_mode().shows_error_details() {
+        return GruxiResponse::new_empty_with_status(status.as_u16());
+    }
+
+    let mut details = format!("{} {}\n\nError: {:?}\n", status.as_u16(), status.canonical_reason().unwrap_or(""), gruxi_error.kind);
+    if !gruxi_error.message.is_empty() {
+        details.push_str(&format!("Message: {}\n", gruxi_error.message));
+    }
+    if let Some(stderr_output) = gruxi_request.get_calculated_data(FASTCGI_STDERR_KEY) {
+        details.push_str(&format!("\nFastCGI STDERR:\n{}\n", stderr_output.trim_end()));
+    }
+    if let Some(backtrace) = &gruxi_error.backtrace {
+        details.push_str(&format!("\nBacktrace:\n{}\n", backtrace));
+    }
+
+    let mut resp = GruxiResponse::new_with_bytes(status.as_u16(), details);
+    resp.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+    resp
+}
+
 const VARY_ACCEPT_ENCODING_VALUE: HeaderValue = HeaderValue::from_static("Accept-Encoding");
 const SERVER_HEADER_VALUE: HeaderValue = HeaderValue::from_static("Gruxi");
+const SERVER_HEADER_VERSION_VALUE: HeaderValue = HeaderValue::from_static(concat!("Gruxi/", env!("CARGO_PKG_VERSION")));
 const CONTENT_TYPE_OCTET_STREAM: HeaderValue = HeaderValue::from_static("application/octet-stream");
 const CONTENT_TYPE_TEXT_HTML: HeaderValue = HeaderValue::from_static("text/html");
 
+// The index of the server header mode in SERVER_HEADER_MODES, set from the server settings
+static SERVER_HEADER_MODE: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_server_header_mode(mode: &str) {
+    let index = SERVER_HEADER_MODES.iter().position(|m| *m == mode).unwrap_or(0);
+    SERVER_HEADER_MODE.store(index as u8, Ordering::Relaxed);
+}
+
+fn get_server_header_mode() -> &'static str {
+    SERVER_HEADER_MODES.get(SERVER_HEADER_MODE.load(Ordering::Relaxed) as usize).copied().unwrap_or(SERVER_HEADER_MODES[0])
+}
+
+// The Via header Gruxi adds as a proxy, by RFC 9110, with the protocol version of the hop such as "1.1" or "2". Gruxi is named
+// by a pseudonym when the Server header is off, so it is not given away there either
+pub fn get_via_header_value(http_version: &str) -> Option<HeaderValue> {
+    let protocol_version = match http_version.trim_start_matches("HTTP/") {
+        "2.0" => "2",
+        "3.0" => "3",
+        version => version,
+    };
+    let received_by = if get_server_header_mode() == "off" { "proxy" } else { "gruxi" };
+    HeaderValue::from_str(&format!("{} {}", protocol_version, received_by)).ok()
+}
+
+// The current time as an HTTP date, such as "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn get_http_date_now() -> String {
+    Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
 pub fn add_standard_headers_to_response(resp: &mut GruxiResponse) {
     // Default Vary header to Accept-Encoding
     if !resp.headers().contains_key("Vary") {
         resp.headers_mut().insert("Vary", VARY_ACCEPT_ENCODING_VALUE.clone());
     }
 
-    // Always set server header
-    resp.headers_mut().insert("Server", SERVER_HEADER_VALUE.clone());
+    // The server header of Gruxi replaces that of backends, so they are not given away when it is off
+    match get_server_header_mode() {
+        "off" => {
+            resp.headers_mut().remove(hyper::header::SERVER);
+        }
+        "version" => {
+            resp.headers_mut().insert(hyper::header::SERVER, SERVER_HEADER_VERSION_VALUE.clone());
+        }
+        _ => {
+            resp.headers_mut().insert(hyper::header::SERVER, SERVER_HEADER_VALUE.clone());
+        }
+    }
+
+    // Every response is dated, by RFC 9110. A Date from a backend is kept, as it tells when the response was created
+    if !resp.headers().contains_key(hyper::header::DATE)
+        && let Ok(date) = HeaderValue::from_str(&get_http_date_now())
+    {
+        resp.headers_mut().insert(hyper::header::DATE, date);
+    }
 
     // Make sure we always a content type header, also when empty, then set octet-stream
     let is_content_type_empty_option = resp.headers().get("Content-Type");
@@ -63,9 +143,29 @@ pub fn add_standard_headers_to_response(resp: &mut GruxiResponse) {
     }
 }
 
+// Adds a field to the Vary header, keeping the fields that are already there
+pub fn add_vary_field(resp: &mut GruxiResponse, field: &str) {
+    let current = resp.get_header("Vary").and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+    if current.split(',').any(|existing| existing.trim().eq_ignore_ascii_case(field) || existing.trim() == "*") {
+        return;
+    }
+    let new_value = if current.trim().is_empty() { field.to_string() } else { format!("{}, {}", current, field) };
+    if let Ok(value) = HeaderValue::from_str(&new_value) {
+        resp.headers_mut().insert("Vary", value);
+    }
+}
+
 pub fn get_list_of_hop_by_hop_headers(is_websocket_upgrade: bool) -> Vec<String> {
     // Remove hop-by-hop headers as per RFC 2616 Section 13.5.1
-    let mut hop_by_hop_headers = vec!["Keep-Alive".to_string(), "Proxy-Authenticate".to_string(), "Proxy-Authorization".to_string(), "TE".to_string(), "Trailers".to_string(), "Transfer-Encoding".to_string(), "Content-Length".to_string()];
+    let mut hop_by_hop_headers = vec![
+        "Keep-Alive".to_string(),
+        "Proxy-Authenticate".to_string(),
+        "Proxy-Authorization".to_string(),
+        "TE".to_string(),
+        "Trailers".to_string(),
+        "Transfer-Encoding".to_string(),
+        "Content-Length".to_string(),
+    ];
 
     if !is_websocket_upgrade {
         // Also remove Connection and Upgrade headers if not a websocket upgrade
@@ -75,3 +175,33 @@ pub fn get_list_of_hop_by_hop_headers(is_websocket_upgrade: bool) -> Vec<String>
 
     hop_by_hop_headers
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_headers_date_and_server() {
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        add_standard_headers_to_response(&mut response);
+        assert_eq!(response.get_header("Server").unwrap(), "Gruxi");
+        let date = response.get_header("Date").unwrap().to_str().unwrap().to_string();
+        assert!(date.ends_with(" GMT"));
+        assert!(chrono::DateTime::parse_from_rfc2822(&date).is_ok());
+
+        // The Date of a backend is kept, its Server is not
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        response.headers_mut().insert(hyper::header::DATE, HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"));
+        response.headers_mut().insert(hyper::header::SERVER, HeaderValue::from_static("Apache"));
+        add_standard_headers_to_response(&mut response);
+        assert_eq!(response.get_header("Date").unwrap(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(response.get_header("Server").unwrap(), "Gruxi");
+    }
+
+    #[test]
+    fn test_via_header_value() {
+        assert_eq!(get_via_header_value("HTTP/1.1").unwrap(), "1.1 gruxi");
+        assert_eq!(get_via_header_value("HTTP/1.0").unwrap(), "1.0 gruxi");
+        assert_eq!(get_via_header_value("HTTP/2.0").unwrap(), "2 gruxi");
+    }
+}
@@ -0,0 +1,354 @@
+use crate::configuration::binding::Binding;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use hyper::header::HeaderValue;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::sync::CancellationToken;
+
+// Per-connection state, shared between the IO wrapper and the request service
+#[derive(Default)]
+pub struct ConnectionTracker {
+    requests: AtomicU64,
+    io_sequence: AtomicU64,
+    last_read_sequence: AtomicU64,
+    last_write_sequence: AtomicU64,
+    close_token: CancellationToken,
+    // Writes to the connection outside of hyper, for interim responses such as 103 Early Hints
+    interim_writer: OnceLock<Arc<dyn InterimWrite>>,
+}
+
+impl ConnectionTracker {
+    // Registers a new request on the connection and returns its number, starting at 1
+    pub fn start_request(&self) -> u64 {
+        self.requests.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    // A request is pipelined when its bytes were already read before we started writing the previous response,
+    // as a client waiting for the response would only send the next request after it
+    pub fn is_pipelined_request(&self, request_number: u64) -> bool {
+        request_number > 1 && self.last_read_sequence.load(Ordering::Relaxed) < self.last_write_sequence.load(Ordering::Relaxed)
+    }
+
+    fn record_read(&self) {
+        let sequence = self.io_sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        self.last_read_sequence.store(sequence, Ordering::Relaxed);
+    }
+
+    fn record_write(&self) {
+        let sequence = self.io_sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        self.last_write_sequence.store(sequence, Ordering::Relaxed);
+    }
+
+    // Drops the connection without answering any further requests
+    pub fn close_connection(&self) {
+        self.close_token.cancel();
+    }
+
+    pub fn get_close_token(&self) -> CancellationToken {
+        self.close_token.clone()
+    }
+
+    // Whether interim responses can be written to the connection, see TrackedIo::with_interim_responses
+    pub fn can_write_interim_responses(&self) -> bool {
+        self.interim_writer.get().is_some()
+    }
+
+    // Writes an interim response, such as 103 Early Hints, to the client while the request is being handled. This is only safe on
+    // HTTP/1.1 connections while hyper is not writing, which is the case until the final response of a request that was not pipelined.
+    // A client not reading it within the timeout would hold up the final response, so it is given up on, and the connection is
+    // closed if part of it was written already, as the final response would follow a broken one
+    pub async fn write_interim_response(&self, response: &[u8], timeout: Duration) -> std::io::Result<()> {
+        let Some(interim_writer) = self.interim_writer.get() else {
+            return Err(std::io::Error::other("No interim writer for the connection"));
+        };
+        let mut written = 0;
+        let writing = async {
+            while written < response.len() {
+                let bytes = std::future::poll_fn(|cx| interim_writer.poll_write_interim(cx, &response[written..])).await?;
+                if bytes == 0 {
+                    return Err(std::io::ErrorKind::WriteZero.into());
+                }
+                written += bytes;
+                self.record_write();
+            }
+            std::future::poll_fn(|cx| interim_writer.poll_flush_interim(cx)).await
+        };
+        let result = tokio::time::timeout(timeout, writing).await;
+        match result {
+            Ok(result) => result,
+            Err(_) => {
+                if written > 0 {
+                    self.close_connection();
+                }
+                Err(std::io::ErrorKind::TimedOut.into())
+            }
+        }
+    }
+}
+
+trait InterimWrite: Send + Sync {
+    fn poll_write_interim(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>>;
+    fn poll_flush_interim(&self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>>;
+}
+
+impl<S: AsyncWrite + Unpin + Send> InterimWrite for Mutex<S> {
+    fn poll_write_interim(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut *lock_stream(self)).poll_write(cx, buf)
+    }
+
+    fn poll_flush_interim(&self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *lock_stream(self)).poll_flush(cx)
+    }
+}
+
+// The lock is only held within a single poll, so a poisoned lock still holds a usable stream
+fn lock_stream<S>(stream: &Mutex<S>) -> MutexGuard<'_, S> {
+    stream.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+enum TrackedStream<S> {
+    Owned(S),
+    // Shared with the tracker, which writes interim responses to it
+    Shared(Arc<Mutex<S>>),
+}
+
+// Wraps the connection stream so we can tell when data was read and written
+pub struct TrackedIo<S> {
+    inner: TrackedStream<S>,
+    tracker: Arc<ConnectionTracker>,
+}
+
+impl<S: AsyncWrite + Unpin + Send + 'static> TrackedIo<S> {
+    pub fn new(inner: S, tracker: Arc<ConnectionTracker>) -> Self {
+        TrackedIo {
+            inner: TrackedStream::Owned(inner),
+            tracker,
+        }
+    }
+
+    // Lets the tracker write interim responses to the stream as well, which puts a lock around every read and write, so only
+    // connections that can get interim responses are wrapped this way
+    pub fn with_interim_responses(inner: S, tracker: Arc<ConnectionTracker>) -> Self {
+        let inner = Arc::new(Mutex::new(inner));
+        let _ = tracker.interim_writer.set(inner.clone());
+        TrackedIo {
+            inner: TrackedStream::Shared(inner),
+            tracker,
+        }
+    }
+}
+
+impl<S: Unpin> TrackedIo<S> {
+    fn poll_stream<R>(&mut self, poll: impl FnOnce(Pin<&mut S>) -> R) -> R {
+        match &mut self.inner {
+            TrackedStream::Owned(stream) => poll(Pin::new(stream)),
+            TrackedStream::Shared(stream) => poll(Pin::new(&mut *lock_stream(stream))),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TrackedIo<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let filled_before = buf.filled().len();
+        let result = this.poll_stream(|stream| stream.poll_read(cx, buf));
+        if let Poll::Ready(Ok(())) = result
+            && buf.filled().len() > filled_before
+        {
+            this.tracker.record_read();
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TrackedIo<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = this.poll_stream(|stream| stream.poll_write(cx, buf));
+        if let Poll::Ready(Ok(written)) = result
+            && written > 0
+        {
+            this.tracker.record_write();
+        }
+        result
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[std::io::IoSlice<'_>]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = this.poll_stream(|stream| stream.poll_write_vectored(cx, bufs));
+        if let Poll::Ready(Ok(written)) = result
+            && written > 0
+        {
+            this.tracker.record_write();
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match &self.inner {
+            TrackedStream::Owned(stream) => stream.is_write_vectored(),
+            TrackedStream::Shared(stream) => lock_stream(stream).is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().poll_stream(|stream| stream.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().poll_stream(|stream| stream.poll_shutdown(cx))
+    }
+}
+
+// Sets the Connection/Keep-Alive headers for HTTP/1.x responses according to the binding limits.
+// Returns true if the connection is closed after this response because the max requests per connection is reached
+pub fn apply_keep_alive_headers(response: &mut GruxiResponse, binding: &Binding, http_version: hyper::Version, request_connection_header: &str, request_number: u64) -> bool {
+    if http_version != hyper::Version::HTTP_11 && http_version != hyper::Version::HTTP_10 {
+        return false;
+    }
+
+    // If the client closes the connection anyway, hyper takes care of it and there is nothing to announce.
+    // HTTP/1.0 clients only keep the connection open if they ask for it
+    let request_connection_header = request_connection_header.to_lowercase();
+    let client_closes = if http_version == hyper::Version::HTTP_10 {
+        !request_connection_header.contains("keep-alive")
+    } else {
+        request_connection_header.contains("close")
+    };
+    let response_closes = response.get_header("Connection").and_then(|v| v.to_str().ok()).is_some_and(|v| v.to_lowercase().contains("close"));
+    if client_closes || response_closes {
+        return false;
+    }
+
+    let max_requests = binding.max_requests_per_connection;
+    if max_requests > 0 && request_number >= max_requests {
+        response.headers_mut().insert(hyper::header::CONNECTION, HeaderValue::from_static("close"));
+        return true;
+    }
+
+    let keep_alive_value = if max_requests > 0 {
+        format!("timeout={}, max={}", binding.keep_alive_timeout_seconds, max_requests - request_number)
+    } else {
+        format!("timeout={}", binding.keep_alive_timeout_seconds)
+    };
+    if let Ok(header_value) = HeaderValue::from_str(&keep_alive_value) {
+        response.headers_mut().insert("Keep-Alive", header_value);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_binding(max_requests_per_connection: u64) -> Binding {
+        let mut binding = Binding::new();
+        binding.keep_alive_timeout_seconds = 5;
+        binding.max_requests_per_connection = max_requests_per_connection;
+        binding
+    }
+
+    fn get_header(response: &GruxiResponse, name: &str) -> Option<String> {
+        response.get_header(name).map(|v| v.to_str().unwrap_or("").to_string())
+    }
+
+    #[test]
+    fn test_pipelining_detection() {
+        let tracker = ConnectionTracker::default();
+
+        // Client sends the first request and waits for the response before sending the next
+        tracker.record_read();
+        assert!(!tracker.is_pipelined_request(tracker.start_request()));
+        tracker.record_write();
+        tracker.record_read();
+        assert!(!tracker.is_pipelined_request(tracker.start_request()));
+
+        // Client sends two requests at once, so the third is already read when the second response is written
+        tracker.record_write();
+        assert!(tracker.is_pipelined_request(tracker.start_request()));
+    }
+
+    #[tokio::test]
+    async fn test_interim_response_is_written_before_the_response() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (client, server) = tokio::io::duplex(1024);
+        let tracker = Arc::new(ConnectionTracker::default());
+        let mut io = TrackedIo::with_interim_responses(server, tracker.clone());
+        tracker.write_interim_response(b"HTTP/1.1 103 Early Hints\r\n\r\n", Duration::from_secs(5)).await.unwrap();
+        io.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+        drop(io);
+        drop(tracker);
+
+        let mut received = String::new();
+        let mut client = client;
+        client.read_to_string(&mut received).await.unwrap();
+        assert_eq!(received, "HTTP/1.1 103 Early Hints\r\n\r\nHTTP/1.1 200 OK\r\n\r\n");
+
+        // Connections without interim responses have no shared stream to write them to
+        let (_client, server) = tokio::io::duplex(1024);
+        let tracker = Arc::new(ConnectionTracker::default());
+        let _io = TrackedIo::new(server, tracker.clone());
+        assert!(!tracker.can_write_interim_responses());
+        assert!(tracker.write_interim_response(b"HTTP/1.1 103 Early Hints\r\n\r\n", Duration::from_secs(5)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_interim_response_to_a_client_not_reading_times_out() {
+        // The client does not read, so only part of the interim response fits in the buffer
+        let (_client, server) = tokio::io::duplex(8);
+        let tracker = Arc::new(ConnectionTracker::default());
+        let _io = TrackedIo::with_interim_responses(server, tracker.clone());
+        let result = tracker.write_interim_response(b"HTTP/1.1 103 Early Hints\r\n\r\n", Duration::from_millis(50)).await;
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert!(tracker.get_close_token().is_cancelled());
+    }
+
+    #[test]
+    fn test_keep_alive_headers_announce_limits() {
+        let binding = create_binding(10);
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        assert!(!apply_keep_alive_headers(&mut response, &binding, hyper::Version::HTTP_11, "", 3));
+        assert_eq!(get_header(&response, "Keep-Alive"), Some("timeout=5, max=7".to_string()));
+        assert_eq!(get_header(&response, "Connection"), None);
+
+        let binding = create_binding(0);
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        assert!(!apply_keep_alive_headers(&mut response, &binding, hyper::Version::HTTP_11, "keep-alive", 3));
+        assert_eq!(get_header(&response, "Keep-Alive"), Some("timeout=5".to_string()));
+    }
+
+    #[test]
+    fn test_keep_alive_headers_close_at_max_requests() {
+        let binding = create_binding(3);
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        assert!(apply_keep_alive_headers(&mut response, &binding, hyper::Version::HTTP_11, "", 3));
+        assert_eq!(get_header(&response, "Connection"), Some("close".to_string()));
+        assert_eq!(get_header(&response, "Keep-Alive"), None);
+    }
+
+    #[test]
+    fn test_keep_alive_headers_skipped_when_not_kept_alive() {
+        let binding = create_binding(10);
+
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        assert!(!apply_keep_alive_headers(&mut response, &binding, hyper::Version::HTTP_11, "close", 1));
+        assert_eq!(get_header(&response, "Keep-Alive"), None);
+
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        assert!(!apply_keep_alive_headers(&mut response, &binding, hyper::Version::HTTP_10, "", 1));
+        assert_eq!(get_header(&response, "Keep-Alive"), None);
+
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        assert!(!apply_keep_alive_headers(&mut response, &binding, hyper::Version::HTTP_10, "Keep-Alive", 1));
+        assert_eq!(get_header(&response, "Keep-Alive"), Some("timeout=5, max=9".to_string()));
+
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        assert!(!apply_keep_alive_headers(&mut response, &binding, hyper::Version::HTTP_2, "", 1));
+        assert_eq!(get_header(&response, "Keep-Alive"), None);
+    }
+}
@@ -0,0 +1,77 @@
+// Language tags longer than this are ignored, as no real language tag is that long (RFC 5646 allows 35 characters for the common forms)
+const MAX_LANGUAGE_TAG_LENGTH: usize = 35;
+
+// Only letters, digits and hyphens are valid in language tags, which also keeps them safe to use in file names
+pub fn is_valid_language_tag(tag: &str) -> bool {
+    !tag.is_empty() && tag.len() <= MAX_LANGUAGE_TAG_LENGTH && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+// Parses an Accept-Language header into language tags, lowercased and ordered by quality value.
+// Tags with q=0, the "*" wildcard and invalid tags are left out
+pub fn parse_accept_language(header_value: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header_value
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim().to_lowercase();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .next()
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            if quality <= 0.0 || !is_valid_language_tag(&tag) {
+                return None;
+            }
+            Some((tag, quality))
+        })
+        .collect();
+
+    // Stable sort, so tags with equal quality keep the order the client gave them in
+    languages.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+// Languages to try, in order: the client's languages, each followed by its primary language ("de-ch" then "de"), and lastly the default language
+pub fn get_language_candidates(accept_language: &str, default_language: &str) -> Vec<String> {
+    let mut candidates: Vec<String> = Vec::new();
+    let mut add_candidate = |tag: String| {
+        if !candidates.contains(&tag) {
+            candidates.push(tag);
+        }
+    };
+
+    for tag in parse_accept_language(accept_language) {
+        let primary_language = tag.split('-').next().unwrap_or("").to_string();
+        add_candidate(tag);
+        if !primary_language.is_empty() {
+            add_candidate(primary_language);
+        }
+    }
+
+    let default_language = default_language.trim().to_lowercase();
+    if is_valid_language_tag(&default_language) {
+        add_candidate(default_language);
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_accept_language() {
+        assert_eq!(parse_accept_language("da, en-GB;q=0.8, en;q=0.7"), vec!["da", "en-gb", "en"]);
+        assert_eq!(parse_accept_language("en;q=0.5, de"), vec!["de", "en"]);
+        assert_eq!(parse_accept_language("fr;q=0, *;q=0.1, ../etc;q=0.9"), Vec::<String>::new());
+        assert_eq!(parse_accept_language(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_get_language_candidates() {
+        assert_eq!(get_language_candidates("de-CH, fr;q=0.5", "en"), vec!["de-ch", "de", "fr", "en"]);
+        assert_eq!(get_language_candidates("en-US, en;q=0.9", "en"), vec!["en-us", "en"]);
+        assert_eq!(get_language_candidates("", "EN"), vec!["en"]);
+        assert_eq!(get_language_candidates("", ""), Vec::<String>::new());
+    }
+}
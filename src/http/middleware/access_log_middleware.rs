@@ -0,0 +1,63 @@
+use crate::http::middleware::middleware_trait::{MiddlewareContext, MiddlewareTrait};
+use crate::http::request_response::request_timings::get_current_request_timings;
+use crate::http::request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse};
+use crate::tls::client_hello_fingerprint::{TLS_JA3_KEY, TLS_JA4_KEY};
+use chrono::Local;
+
+// Writes the response to the access log of the site in Common Log Format, if the site has access logging enabled
+pub struct AccessLogMiddleware;
+
+impl MiddlewareTrait for AccessLogMiddleware {
+    fn get_name(&self) -> &'static str {
+        "access_log"
+    }
+
+    async fn handle_response(&self, gruxi_request: &mut GruxiRequest, context: &MiddlewareContext<'_>, response: &mut GruxiResponse) {
+        let site = context.site;
+        if !site.access_log_enabled {
+            return;
+        }
+
+        // Get current date and time in CLF format, which is like 10/Oct/2000:13:55:36 -0700
+        let now = Local::now();
+        let clf_date = now.format("%d/%b/%Y:%H:%M:%S %z").to_string();
+        let mut log_entry = format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            gruxi_request.get_remote_ip(),
+            clf_date,
+            gruxi_request.get_http_method(),
+            gruxi_request.get_path_and_query(),
+            gruxi_request.get_http_version(),
+            response.get_status(),
+            response.get_body_size()
+        );
+        if site.access_log_tls_fingerprint_enabled
+            && let (Some(ja3), Some(ja4)) = (gruxi_request.get_calculated_data(TLS_JA3_KEY), gruxi_request.get_calculated_data(TLS_JA4_KEY))
+        {
+            log_entry.push_str(&format!(" ja3={} ja4={}", ja3, ja4));
+        }
+
+        let access_log_buffer_rwlock = context.running_state.get_access_log_buffer();
+
+        // With timings, the entry is written once the response is sent, so the time writing it to the client is included
+        if site.access_log_timings_enabled
+            && let Some(request_timings) = get_current_request_timings()
+        {
+            let site_id = site.id.to_string();
+            request_timings.add_on_complete(move |request_timings| {
+                log_entry.push_str(&request_timings.format_for_access_log());
+                if let Ok(access_log_buffer) = access_log_buffer_rwlock.try_read() {
+                    access_log_buffer.add_log(site_id, log_entry);
+                } else if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                    runtime.spawn(async move {
+                        access_log_buffer_rwlock.read().await.add_log(site_id, log_entry);
+                    });
+                }
+            });
+            return;
+        }
+
+        let access_log_buffer = access_log_buffer_rwlock.read().await;
+        access_log_buffer.add_log(site.id.to_string(), log_entry);
+    }
+}
@@ -0,0 +1,16 @@
+use crate::http::cache_policy::apply_cache_policy;
+use crate::http::middleware::middleware_trait::{MiddlewareContext, MiddlewareTrait};
+use crate::http::request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse};
+
+// Applies the cache policy of the site matching the request path, see apply_cache_policy()
+pub struct CachePolicyMiddleware;
+
+impl MiddlewareTrait for CachePolicyMiddleware {
+    fn get_name(&self) -> &'static str {
+        "cache_policy"
+    }
+
+    async fn handle_response(&self, gruxi_request: &mut GruxiRequest, context: &MiddlewareContext<'_>, response: &mut GruxiResponse) {
+        apply_cache_policy(response, context.site, &gruxi_request.get_path());
+    }
+}
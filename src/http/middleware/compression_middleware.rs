@@ -0,0 +1,34 @@
+use crate::compression::compression::Compression;
+use crate::core::memory_budget::get_memory_budget;
+use crate::http::handle_request::ADMIN_API_RESPONSE_KEY;
+use crate::http::middleware::middleware_trait::{MiddlewareContext, MiddlewareTrait};
+use crate::http::request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse};
+
+// Compresses the response body for clients accepting it, based on the gzip settings
+pub struct CompressionMiddleware;
+
+impl MiddlewareTrait for CompressionMiddleware {
+    fn get_name(&self) -> &'static str {
+        "compression"
+    }
+
+    async fn handle_response(&self, gruxi_request: &mut GruxiRequest, context: &MiddlewareContext<'_>, response: &mut GruxiResponse) {
+        let content_length = response.get_body_size();
+        let content_type_header = response.get_header("Content-Type").map(|v| v.to_str().unwrap_or("").to_string()).unwrap_or_default();
+        let content_encoding_header = response.get_header("Content-Encoding").map(|v| v.to_str().unwrap_or("").to_string()).unwrap_or_default();
+
+        // Only compress if we should based on config and sizes. Admin API JSON, such as the monitoring data polled by the admin portal, is always
+        // compressed when large enough, regardless of the gzip settings for the sites
+        let file_reader_cache = context.running_state.get_file_reader_cache();
+        let should_compress_admin_api_response = gruxi_request.get_calculated_data(ADMIN_API_RESPONSE_KEY).is_some() && content_length > 1000 && content_type_header.starts_with("application/json");
+        if should_compress_admin_api_response || file_reader_cache.should_compress(&content_type_header, content_length) {
+            // The body is held in memory while it is compressed, so it is sent uncompressed when the memory budget has no room for it
+            let Some(_body_reservation) = get_memory_budget().try_reserve(content_length) else {
+                return;
+            };
+            let accepted_encodings = gruxi_request.get_accepted_encodings();
+            let compression = Compression::new();
+            compression.compress_response(response, accepted_encodings, content_encoding_header).await;
+        }
+    }
+}
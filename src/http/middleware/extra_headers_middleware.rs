@@ -0,0 +1,22 @@
+use crate::http::middleware::middleware_trait::{MiddlewareContext, MiddlewareTrait};
+use crate::http::request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse};
+use hyper::header::HeaderValue;
+
+// Sets the extra headers of the site on the response, replacing headers of the same name
+pub struct ExtraHeadersMiddleware;
+
+impl MiddlewareTrait for ExtraHeadersMiddleware {
+    fn get_name(&self) -> &'static str {
+        "extra_headers"
+    }
+
+    async fn handle_response(&self, _gruxi_request: &mut GruxiRequest, context: &MiddlewareContext<'_>, response: &mut GruxiResponse) {
+        for kv in &context.site.extra_headers {
+            if let Ok(key_name) = hyper::http::HeaderName::from_bytes(kv.key.as_bytes())
+                && let Ok(val) = HeaderValue::from_str(kv.value.as_str())
+            {
+                response.headers_mut().insert(key_name, val);
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::gruxi_error::GruxiError;
+use crate::http::middleware::access_log_middleware::AccessLogMiddleware;
+use crate::http::middleware::cache_policy_middleware::CachePolicyMiddleware;
+use crate::http::middleware::compression_middleware::CompressionMiddleware;
+use crate::http::middleware::extra_headers_middleware::ExtraHeadersMiddleware;
+use crate::http::middleware::middleware_trait::{MiddlewareContext, MiddlewareTrait};
+use crate::http::request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse};
+use crate::logging::syslog::trace;
+
+// Supported middlewares, which sites list in the order they should run
+pub static MIDDLEWARES: &[&str] = &["access_log", "extra_headers", "cache_policy", "compression"];
+
+// The middleware list of new sites, and of sites from before there was one. The access log comes first, so it logs the final
+// response, and compression last, so the other middlewares see the uncompressed response
+pub fn get_default_middlewares() -> Vec<String> {
+    MIDDLEWARES.iter().map(|m| m.to_string()).collect()
+}
+
+pub enum Middleware {
+    AccessLog(AccessLogMiddleware),
+    ExtraHeaders(ExtraHeadersMiddleware),
+    CachePolicy(CachePolicyMiddleware),
+    Compression(CompressionMiddleware),
+}
+
+impl Middleware {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "access_log" => Some(Middleware::AccessLog(AccessLogMiddleware)),
+            "extra_headers" => Some(Middleware::ExtraHeaders(ExtraHeadersMiddleware)),
+            "cache_policy" => Some(Middleware::CachePolicy(CachePolicyMiddleware)),
+            "compression" => Some(Middleware::Compression(CompressionMiddleware)),
+            _ => None,
+        }
+    }
+}
+
+impl MiddlewareTrait for Middleware {
+    fn get_name(&self) -> &'static str {
+        match self {
+            Middleware::AccessLog(m) => m.get_name(),
+            Middleware::ExtraHeaders(m) => m.get_name(),
+            Middleware::CachePolicy(m) => m.get_name(),
+            Middleware::Compression(m) => m.get_name(),
+        }
+    }
+
+    async fn handle_request(&self, gruxi_request: &mut GruxiRequest, context: &MiddlewareContext<'_>) -> Option<GruxiResponse> {
+        match self {
+            Middleware::AccessLog(m) => m.handle_request(gruxi_request, context).await,
+            Middleware::ExtraHeaders(m) => m.handle_request(gruxi_request, context).await,
+            Middleware::CachePolicy(m) => m.handle_request(gruxi_request, context).await,
+            Middleware::Compression(m) => m.handle_request(gruxi_request, context).await,
+        }
+    }
+
+    async fn handle_response(&self, gruxi_request: &mut GruxiRequest, context: &MiddlewareContext<'_>, response: &mut GruxiResponse) {
+        match self {
+            Middleware::AccessLog(m) => m.handle_response(gruxi_request, context, response).await,
+            Middleware::ExtraHeaders(m) => m.handle_response(gruxi_request, context, response).await,
+            Middleware::CachePolicy(m) => m.handle_response(gruxi_request, context, response).await,
+            Middleware::Compression(m) => m.handle_response(gruxi_request, context, response).await,
+        }
+    }
+}
+
+// The middlewares of a site, in order, wrapped around the handling of its requests
+pub struct MiddlewareChain {
+    middlewares: Vec<Middleware>,
+}
+
+impl MiddlewareChain {
+    // Unknown names are skipped, as the site validation reports them
+    pub fn new(middleware_names: &[String]) -> Self {
+        MiddlewareChain {
+            middlewares: middleware_names.iter().filter_map(|name| Middleware::from_name(name)).collect(),
+        }
+    }
+
+    // Runs the request through the middlewares, handles it with the handler unless a middleware answered it, and runs the
+    // response back through the middlewares that saw the request
+    pub async fn run<F>(&self, gruxi_request: &mut GruxiRequest, context: &MiddlewareContext<'_>, handler: F) -> Result<GruxiResponse, GruxiError>
+    where
+        F: AsyncFnOnce(&mut GruxiRequest) -> Result<GruxiResponse, GruxiError>,
+    {
+        let mut response = None;
+        let mut entered = 0;
+        for middleware in &self.middlewares {
+            entered += 1;
+            if let Some(middleware_response) = middleware.handle_request(gruxi_request, context).await {
+                trace(format!("Middleware '{}' answered the request for {}", middleware.get_name(), gruxi_request.get_path()));
+                response = Some(middleware_response);
+                break;
+            }
+        }
+
+        let mut response = match response {
+            Some(response) => response,
+            None => handler(gruxi_request).await?,
+        };
+
+        for middleware in self.middlewares[..entered].iter().rev() {
+            middleware.handle_response(gruxi_request, context, &mut response).await;
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_from_names() {
+        let chain = MiddlewareChain::new(&get_default_middlewares());
+        let names: Vec<&str> = chain.middlewares.iter().map(|m| m.get_name()).collect();
+        assert_eq!(names, MIDDLEWARES);
+
+        let chain = MiddlewareChain::new(&["compression".to_string(), "unknown".to_string(), "access_log".to_string()]);
+        let names: Vec<&str> = chain.middlewares.iter().map(|m| m.get_name()).collect();
+        assert_eq!(names, vec!["compression", "access_log"]);
+    }
+}
@@ -0,0 +1,27 @@
+use crate::configuration::{binding::Binding, site::Site};
+use crate::core::running_state::RunningState;
+use crate::http::request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse};
+
+// What middlewares get to know about the request, besides the request itself
+pub struct MiddlewareContext<'a> {
+    pub site: &'a Site,
+    pub binding: &'a Binding,
+    pub running_state: &'a RunningState,
+}
+
+// Trait that middlewares must implement. Middlewares run in the order of the site's middleware list on the way in, and in reverse
+// order on the way out, so the first middleware sees the final response
+#[allow(async_fn_in_trait)]
+pub trait MiddlewareTrait {
+    // Returns the name of the middleware, as used in the middleware list of sites, e.g. "compression"
+    fn get_name(&self) -> &'static str;
+
+    // Called before the request is handled. Returning a response answers the request without the later middlewares and the
+    // request handlers, and only the middlewares before this one see the response
+    async fn handle_request(&self, _gruxi_request: &mut GruxiRequest, _context: &MiddlewareContext<'_>) -> Option<GruxiResponse> {
+        None
+    }
+
+    // Called with the response, which the middleware may change
+    async fn handle_response(&self, _gruxi_request: &mut GruxiRequest, _context: &MiddlewareContext<'_>, _response: &mut GruxiResponse) {}
+}
@@ -0,0 +1,6 @@
+pub mod access_log_middleware;
+pub mod cache_policy_middleware;
+pub mod compression_middleware;
+pub mod extra_headers_middleware;
+pub mod middleware_chain;
+pub mod middleware_trait;
@@ -1,8 +1,29 @@
+pub mod body_substitution;
+pub mod cache_policy;
+pub mod canonical_redirect;
+pub mod client;
+pub mod cookie_routing;
+pub mod early_hints;
+pub mod etag;
 pub mod handle_request;
-pub mod http_util;
-pub mod http_tls;
+pub mod header_routing;
+pub mod holding_listener;
+pub mod http2_flood_guard;
 pub mod http_server;
+pub mod http_tls;
+pub mod http_util;
+pub mod keep_alive;
+pub mod language_negotiation;
+pub mod middleware;
+pub mod panic_guard;
+pub mod problem_details;
 pub mod request_handlers;
 pub mod request_response;
-pub mod client;
-pub mod site_match;
\ No newline at end of file
+pub mod request_validation;
+pub mod site_match;
+pub mod sitemap;
+pub mod status_page;
+pub mod traffic_split;
+pub mod try_files;
+pub mod upload_manager;
+pub mod url_canonicalization;
@@ -0,0 +1,203 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, Once, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+// Panics counted towards a panic storm are the ones within this window
+const PANIC_STORM_WINDOW: Duration = Duration::from_secs(60);
+
+thread_local! {
+    // Set while a request is polled, so the panic hook knows the panic is caught by CatchRequestPanic
+    static POLLING_REQUEST: Cell<bool> = const { Cell::new(false) };
+    // Where the last request panic on this thread happened and its backtrace, taken by CatchRequestPanic after unwinding
+    static LAST_REQUEST_PANIC: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+}
+
+static INSTALL_PANIC_HOOK: Once = Once::new();
+static PANIC_GUARD_SINGLETON: OnceLock<PanicGuard> = OnceLock::new();
+
+pub fn get_panic_guard() -> &'static PanicGuard {
+    PANIC_GUARD_SINGLETON.get_or_init(|| PanicGuard::new(0, 60))
+}
+
+// A panic in the handling of a request, with the backtrace captured when it happened
+#[derive(Debug, Clone)]
+pub struct RequestPanic {
+    pub message: String,
+    pub location: String,
+    pub backtrace: String,
+}
+
+// Runs the handling of a request, returning the panic instead of unwinding into the connection task, so the connection
+// can still be answered with a 500. The backtrace is captured by a panic hook, as it is gone once the panic is caught
+pub fn catch_request_panic<F: Future>(future: F) -> CatchRequestPanic<F> {
+    install_panic_hook();
+    CatchRequestPanic { inner: Box::pin(future) }
+}
+
+pub struct CatchRequestPanic<F> {
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchRequestPanic<F> {
+    type Output = Result<F::Output, RequestPanic>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let was_polling_request = POLLING_REQUEST.with(|polling| polling.replace(true));
+        let result = catch_unwind(AssertUnwindSafe(|| self.inner.as_mut().poll(cx)));
+        POLLING_REQUEST.with(|polling| polling.set(was_polling_request));
+
+        match result {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => {
+                let (location, backtrace) = LAST_REQUEST_PANIC.with(|last| last.borrow_mut().take()).unwrap_or_default();
+                Poll::Ready(Err(RequestPanic {
+                    message: get_panic_message(payload.as_ref()),
+                    location,
+                    backtrace,
+                }))
+            }
+        }
+    }
+}
+
+// Panics outside of request handling still go to the previous hook, which prints them as usual
+fn install_panic_hook() {
+    INSTALL_PANIC_HOOK.call_once(|| {
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if POLLING_REQUEST.with(|polling| polling.get()) {
+                let location = info.location().map(|l| l.to_string()).unwrap_or_default();
+                let backtrace = Backtrace::force_capture().to_string();
+                LAST_REQUEST_PANIC.with(|last| *last.borrow_mut() = Some((location, backtrace)));
+            } else {
+                previous_hook(info);
+            }
+        }));
+    });
+}
+
+fn get_panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic payload".to_string()
+    }
+}
+
+// Puts the server in maintenance mode when requests panic in a storm, such as a bug hit by every request to a site, so the
+// server answers with 503 and Retry-After for a while instead of failing on every request. Admin bindings stay available
+pub struct PanicGuard {
+    // 0 to never enter maintenance mode
+    max_panics_per_minute: AtomicU64,
+    maintenance_seconds: AtomicU64,
+    recent_panics: Mutex<VecDeque<Instant>>,
+    maintenance_until: Mutex<Option<Instant>>,
+    maintenance_trips: AtomicUsize,
+}
+
+impl PanicGuard {
+    pub fn new(max_panics_per_minute: u64, maintenance_seconds: u64) -> Self {
+        PanicGuard {
+            max_panics_per_minute: AtomicU64::new(max_panics_per_minute),
+            maintenance_seconds: AtomicU64::new(maintenance_seconds),
+            recent_panics: Mutex::new(VecDeque::new()),
+            maintenance_until: Mutex::new(None),
+            maintenance_trips: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn set_thresholds(&self, max_panics_per_minute: u64, maintenance_seconds: u64) {
+        self.max_panics_per_minute.store(max_panics_per_minute, Ordering::Relaxed);
+        self.maintenance_seconds.store(maintenance_seconds, Ordering::Relaxed);
+    }
+
+    // Counts a panic, returning true when it starts maintenance mode
+    pub fn record_panic(&self) -> bool {
+        let max_panics_per_minute = self.max_panics_per_minute.load(Ordering::Relaxed);
+        if max_panics_per_minute == 0 {
+            return false;
+        }
+
+        let now = Instant::now();
+        let mut recent_panics = self.recent_panics.lock().unwrap();
+        recent_panics.push_back(now);
+        while recent_panics.front().is_some_and(|t| now.duration_since(*t) > PANIC_STORM_WINDOW) {
+            recent_panics.pop_front();
+        }
+        if (recent_panics.len() as u64) < max_panics_per_minute || self.get_maintenance_remaining().is_some() {
+            return false;
+        }
+
+        recent_panics.clear();
+        *self.maintenance_until.lock().unwrap() = Some(now + Duration::from_secs(self.maintenance_seconds.load(Ordering::Relaxed)));
+        self.maintenance_trips.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    // How long maintenance mode lasts, None when the server is not in maintenance mode
+    pub fn get_maintenance_remaining(&self) -> Option<Duration> {
+        let maintenance_until = *self.maintenance_until.lock().unwrap();
+        maintenance_until.map(|until| until.saturating_duration_since(Instant::now())).filter(|remaining| !remaining.is_zero())
+    }
+
+    pub fn get_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "max_panics_per_minute": self.max_panics_per_minute.load(Ordering::Relaxed),
+            "maintenance_trips": self.maintenance_trips.load(Ordering::Relaxed),
+            "maintenance_remaining_seconds": self.get_maintenance_remaining().map(|remaining| remaining.as_secs().max(1)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panic_in_request_is_caught_with_backtrace() {
+        let result = catch_request_panic(async {
+            tokio::task::yield_now().await;
+            if std::hint::black_box(true) {
+                panic!("handler failed for {}", "/index.php");
+            }
+            200
+        })
+        .await;
+
+        let request_panic = result.unwrap_err();
+        assert_eq!(request_panic.message, "handler failed for /index.php");
+        assert!(request_panic.location.contains("panic_guard.rs"));
+        assert!(!request_panic.backtrace.is_empty());
+
+        assert_eq!(catch_request_panic(async { 200 }).await.unwrap(), 200);
+    }
+
+    #[test]
+    fn test_panic_storm_trips_maintenance_mode() {
+        let guard = PanicGuard::new(3, 60);
+        assert!(!guard.record_panic());
+        assert!(!guard.record_panic());
+        assert!(guard.get_maintenance_remaining().is_none());
+
+        assert!(guard.record_panic());
+        assert!(guard.get_maintenance_remaining().is_some());
+        assert_eq!(guard.get_json()["maintenance_trips"], 1);
+
+        // Disabled by default
+        let guard = PanicGuard::new(0, 60);
+        for _ in 0..100 {
+            assert!(!guard.record_panic());
+        }
+        assert!(guard.get_maintenance_remaining().is_none());
+    }
+}
@@ -0,0 +1,145 @@
+use hyper::header::HeaderValue;
+use serde::Serialize;
+
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+
+const PROBLEM_JSON_HEADER_VALUE: HeaderValue = HeaderValue::from_static("application/problem+json");
+
+// RFC 7807 problem details body
+#[derive(Debug, Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub instance: String,
+    pub request_id: String,
+}
+
+impl ProblemDetails {
+    pub fn new(status: u16, instance: &str, request_id: &str) -> Self {
+        let title = hyper::StatusCode::from_u16(status).ok().and_then(|s| s.canonical_reason()).unwrap_or("Error").to_string();
+        ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            detail: get_problem_detail(status).to_string(),
+            title,
+            status,
+            instance: instance.to_string(),
+            request_id: request_id.to_string(),
+        }
+    }
+}
+
+fn get_problem_detail(status: u16) -> &'static str {
+    match status {
+        400 => "The request could not be understood by the server.",
+        401 => "Authentication is required to access this resource.",
+        403 => "Access to this resource is forbidden.",
+        404 => "The requested resource was not found.",
+        405 => "The request method is not allowed for this resource.",
+        408 => "The server timed out waiting for the request.",
+        413 => "The request body is larger than the server is willing to process.",
+        414 => "The request URI is longer than the server is willing to process.",
+        429 => "Too many requests have been sent in a given amount of time.",
+        500 => "The server encountered an internal error.",
+        501 => "The request method is not supported by the server.",
+        502 => "The server received an invalid response from an upstream server.",
+        503 => "The service is temporarily unavailable.",
+        504 => "The server did not receive a timely response from an upstream server.",
+        _ => "The request could not be completed.",
+    }
+}
+
+// Replaces empty error responses with a problem+json body. Error responses that already have a body are left as is,
+// as those are produced by the application behind the site.
+pub fn convert_to_problem_details(response: &mut GruxiResponse, gruxi_request: &mut GruxiRequest) {
+    let status = response.get_status();
+    if status < 400 || !response.is_body_empty() {
+        return;
+    }
+
+    let mut problem_response = match create_problem_details_response(status, None, gruxi_request) {
+        Some(problem_response) => problem_response,
+        None => return,
+    };
+    for (key, value) in response.headers() {
+        if key != hyper::header::CONTENT_TYPE && key != hyper::header::CONTENT_LENGTH && key != "X-Request-Id" {
+            problem_response.headers_mut().append(key.clone(), value.clone());
+        }
+    }
+    *response = problem_response;
+}
+
+// Creates a problem+json response, using the standard detail for the status unless a more specific detail is given
+pub fn create_problem_details_response(status: u16, detail: Option<String>, gruxi_request: &mut GruxiRequest) -> Option<GruxiResponse> {
+    let request_id = gruxi_request.get_request_id();
+    let mut problem = ProblemDetails::new(status, &gruxi_request.get_path(), &request_id);
+    if let Some(detail) = detail {
+        problem.detail = detail;
+    }
+    let body = serde_json::to_vec(&problem).ok()?;
+
+    let mut problem_response = GruxiResponse::new_with_bytes(status, body);
+    problem_response.headers_mut().insert(hyper::header::CONTENT_TYPE, PROBLEM_JSON_HEADER_VALUE);
+    if let Ok(request_id_value) = HeaderValue::from_str(&request_id) {
+        problem_response.headers_mut().insert("X-Request-Id", request_id_value);
+    }
+    Some(problem_response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problem_details_serialization() {
+        let problem = ProblemDetails::new(404, "/missing", "abc-123");
+        let json = serde_json::to_value(&problem).unwrap();
+        assert_eq!(json["type"], "about:blank");
+        assert_eq!(json["title"], "Not Found");
+        assert_eq!(json["status"], 404);
+        assert_eq!(json["instance"], "/missing");
+        assert_eq!(json["request_id"], "abc-123");
+    }
+
+    #[test]
+    fn test_convert_to_problem_details() {
+        let request = hyper::Request::builder().uri("/api/items").body(hyper::body::Bytes::new()).unwrap();
+        let mut gruxi_request = GruxiRequest::new(request);
+
+        let mut response = GruxiResponse::new_empty_with_status(404);
+        convert_to_problem_details(&mut response, &mut gruxi_request);
+        assert_eq!(response.get_header("Content-Type").unwrap(), "application/problem+json");
+        assert_eq!(response.get_header("X-Request-Id").unwrap().to_str().unwrap(), gruxi_request.get_request_id());
+        assert!(!response.is_body_empty());
+
+        // Responses with a body from the application are kept
+        let mut response = GruxiResponse::new_with_bytes(500, "application error");
+        convert_to_problem_details(&mut response, &mut gruxi_request);
+        assert!(response.get_header("Content-Type").is_none());
+
+        // Non-error responses are kept
+        let mut response = GruxiResponse::new_empty_with_status(204);
+        convert_to_problem_details(&mut response, &mut gruxi_request);
+        assert!(response.is_body_empty());
+    }
+
+    #[test]
+    fn test_create_problem_details_response_with_detail() {
+        let request = hyper::Request::builder().uri("/upload").body(hyper::body::Bytes::new()).unwrap();
+        let mut gruxi_request = GruxiRequest::new(request);
+
+        let response = create_problem_details_response(413, Some("The request body is larger than the limit of 100 bytes.".to_string()), &mut gruxi_request).unwrap();
+        assert_eq!(response.get_status(), 413);
+        assert_eq!(response.get_header("Content-Type").unwrap(), "application/problem+json");
+    }
+
+    #[test]
+    fn test_problem_details_unknown_status() {
+        let problem = ProblemDetails::new(599, "/", "id");
+        assert_eq!(problem.title, "Error");
+        assert_eq!(problem.detail, "The request could not be completed.");
+    }
+}
@@ -9,15 +9,46 @@ use crate::core::running_state_manager;
 use crate::core::triggers::get_trigger_handler;
 use crate::logging::syslog::{debug, error};
 
-// Commands sent to a load balancer task
+// Commands sent to a load balancer task. The excluded servers are not chosen, such as those a request already failed to reach
 pub enum LoadBalancerCommand {
-    GetNextServer { respond_to: oneshot::Sender<Option<String>> },
+    GetNextServer {
+        excluded: Vec<String>,
+        respond_to: oneshot::Sender<Option<String>>,
+    },
+    GetServerById {
+        server_id: String,
+        excluded: Vec<String>,
+        respond_to: oneshot::Sender<Option<String>>,
+    },
+    GetServerByHash {
+        key: String,
+        excluded: Vec<String>,
+        respond_to: oneshot::Sender<Option<String>>,
+    },
     Shutdown,
 }
 
 // Trait implemented by concrete load balancer algorithms
 pub trait LoadBalancerImpl: Send + 'static {
-    fn get_next_server(&mut self) -> Option<String>;
+    fn get_next_server(&mut self, excluded: &[String]) -> Option<String>;
+    fn get_servers(&self) -> &[String];
+    fn is_server_healthy(&self, server: &str) -> bool;
+    // The server with the id, such as from a session affinity cookie, as long as it is healthy and not excluded
+    fn get_server_by_id(&self, server_id: &str, excluded: &[String]) -> Option<String> {
+        self.get_servers()
+            .iter()
+            .find(|server| get_server_id(server) == server_id && self.is_server_healthy(server) && !excluded.contains(server))
+            .cloned()
+    }
+    // Rendezvous hashing of the key over the healthy servers, so the same key keeps getting the same server, and only the keys
+    // of a server that goes down or is excluded move to another one
+    fn get_server_by_hash(&self, key: &str, excluded: &[String]) -> Option<String> {
+        self.get_servers()
+            .iter()
+            .filter(|server| self.is_server_healthy(server) && !excluded.contains(server))
+            .max_by_key(|server| mix_hash(fnv1a_hash(server.bytes().chain([0]).chain(key.bytes()))))
+            .cloned()
+    }
     fn check_health(&mut self);
     fn check_uri_health(&self, uri: &str, health_register: Arc<AtomicBool>, request_timeout_secs: u64) {
         let uri_parsed_result: Result<Uri, _> = uri.parse();
@@ -86,8 +117,14 @@ async fn load_balancer_task<T: LoadBalancerImpl>(mut lb: T, mut rx: mpsc::Receiv
             }
             Some(cmd) = rx.recv() => {
                 match cmd {
-                    LoadBalancerCommand::GetNextServer { respond_to } => {
-                        let _ = respond_to.send(lb.get_next_server());
+                    LoadBalancerCommand::GetNextServer { excluded, respond_to } => {
+                        let _ = respond_to.send(lb.get_next_server(&excluded));
+                    }
+                    LoadBalancerCommand::GetServerById { server_id, excluded, respond_to } => {
+                        let _ = respond_to.send(lb.get_server_by_id(&server_id, &excluded));
+                    }
+                    LoadBalancerCommand::GetServerByHash { key, excluded, respond_to } => {
+                        let _ = respond_to.send(lb.get_server_by_hash(&key, &excluded));
                     }
                     LoadBalancerCommand::Shutdown => {
                         break;
@@ -117,10 +154,41 @@ impl LoadBalancerRegistry {
         self.inner.write().await.insert(id, tx);
     }
 
-    pub async fn get_next_server(&self, id: &str) -> Option<String> {
+    pub async fn get_next_server(&self, id: &str, excluded: &[String]) -> Option<String> {
+        let tx = self.inner.read().await.get(id)?.clone();
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let _ = tx
+            .send(LoadBalancerCommand::GetNextServer {
+                excluded: excluded.to_vec(),
+                respond_to: resp_tx,
+            })
+            .await;
+        resp_rx.await.ok().flatten()
+    }
+
+    pub async fn get_server_by_id(&self, id: &str, server_id: &str, excluded: &[String]) -> Option<String> {
         let tx = self.inner.read().await.get(id)?.clone();
         let (resp_tx, resp_rx) = oneshot::channel();
-        let _ = tx.send(LoadBalancerCommand::GetNextServer { respond_to: resp_tx }).await;
+        let _ = tx
+            .send(LoadBalancerCommand::GetServerById {
+                server_id: server_id.to_string(),
+                excluded: excluded.to_vec(),
+                respond_to: resp_tx,
+            })
+            .await;
+        resp_rx.await.ok().flatten()
+    }
+
+    pub async fn get_server_by_hash(&self, id: &str, key: &str, excluded: &[String]) -> Option<String> {
+        let tx = self.inner.read().await.get(id)?.clone();
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let _ = tx
+            .send(LoadBalancerCommand::GetServerByHash {
+                key: key.to_string(),
+                excluded: excluded.to_vec(),
+                respond_to: resp_tx,
+            })
+            .await;
         resp_rx.await.ok().flatten()
     }
 
@@ -130,3 +198,71 @@ impl LoadBalancerRegistry {
         }
     }
 }
+
+// Identifies an upstream server in a session affinity cookie without giving away its address
+pub fn get_server_id(server: &str) -> String {
+    format!("{:016x}", fnv1a_hash(server.bytes()))
+}
+
+// FNV-1a, stable across restarts and versions, so clients keep their servers
+fn fnv1a_hash(bytes: impl Iterator<Item = u8>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// Spreads the last bytes hashed over all bits (the SplitMix64 finalizer), as the weights of rendezvous hashing are compared as a whole
+fn mix_hash(mut hash: u64) -> u64 {
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request_handlers::processors::load_balancer::round_robin::RoundRobin;
+
+    #[test]
+    fn test_session_affinity_fails_over() {
+        let servers: Vec<String> = (1..=4).map(|i| format!("http://backend{}:8080", i)).collect();
+        let mut lb = RoundRobin::new(servers.clone(), "/health".to_string(), 5, 60);
+        assert_eq!(lb.get_server_by_id(&get_server_id(&servers[2]), &[]), Some(servers[2].clone()));
+        assert_eq!(lb.get_server_by_id("unknown", &[]), None);
+
+        let pinned: Vec<String> = (0..100).map(|i| lb.get_server_by_hash(&format!("10.0.0.{}", i), &[]).unwrap()).collect();
+        assert!(servers.iter().all(|server| pinned.contains(server)));
+
+        // Only the clients of the server going down move, and the cookie no longer finds it
+        lb.set_server_healthy(&servers[1], false);
+        assert_eq!(lb.get_server_by_id(&get_server_id(&servers[1]), &[]), None);
+        for (i, server) in pinned.iter().enumerate() {
+            let now = lb.get_server_by_hash(&format!("10.0.0.{}", i), &[]).unwrap();
+            if *server == servers[1] {
+                assert_ne!(now, servers[1]);
+            } else {
+                assert_eq!(now, *server);
+            }
+        }
+        assert_ne!(lb.get_next_server(&[]), Some(servers[1].clone()));
+    }
+
+    #[test]
+    fn test_excluded_servers_are_not_chosen() {
+        let servers: Vec<String> = (1..=2).map(|i| format!("http://backend{}:8080", i)).collect();
+        let mut lb = RoundRobin::new(servers.clone(), "/health".to_string(), 5, 60);
+
+        let pinned = lb.get_server_by_hash("10.0.0.1", &[]).unwrap();
+        let other = servers.iter().find(|server| **server != pinned).unwrap();
+        assert_eq!(lb.get_server_by_hash("10.0.0.1", std::slice::from_ref(&pinned)), Some(other.clone()));
+        assert_eq!(lb.get_server_by_id(&get_server_id(&pinned), std::slice::from_ref(&pinned)), None);
+        for _ in 0..4 {
+            assert_eq!(lb.get_next_server(std::slice::from_ref(&pinned)), Some(other.clone()));
+        }
+        assert_eq!(lb.get_next_server(&servers), None);
+        assert_eq!(lb.get_server_by_hash("10.0.0.1", &servers), None);
+    }
+}
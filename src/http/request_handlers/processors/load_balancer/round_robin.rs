@@ -32,10 +32,17 @@ impl RoundRobin {
             health_check_interval_secs,
         }
     }
+
+    #[cfg(test)]
+    pub fn set_server_healthy(&self, server: &str, is_healthy: bool) {
+        if let Some(health) = self.health_state.get(server) {
+            health.store(is_healthy, Ordering::SeqCst);
+        }
+    }
 }
 
 impl LoadBalancerImpl for RoundRobin {
-    fn get_next_server(&mut self) -> Option<String> {
+    fn get_next_server(&mut self, excluded: &[String]) -> Option<String> {
         let total = self.servers.len();
         if total == 0 {
             return None;
@@ -44,6 +51,9 @@ impl LoadBalancerImpl for RoundRobin {
         for _ in 0..total {
             let server = &self.servers[self.current_index];
             self.current_index = (self.current_index + 1) % total;
+            if excluded.contains(server) {
+                continue;
+            }
 
             match self.health_state.get(server) {
                 None => continue,
@@ -58,15 +68,19 @@ impl LoadBalancerImpl for RoundRobin {
         None
     }
 
+    fn get_servers(&self) -> &[String] {
+        &self.servers
+    }
+
+    fn is_server_healthy(&self, server: &str) -> bool {
+        self.health_state.get(server).map(|health| health.load(Ordering::SeqCst)).unwrap_or(false)
+    }
+
     fn check_health(&mut self) {
-        for server in &self.servers {
+        // Servers with a weight are listed more than once, but checked once
+        for (server, healthy_state) in &self.health_state {
             let server_uri = server.clone() + &self.health_url_path;
-            let healthy_state_option = self.health_state.get(server);
-            let healthy_state = match healthy_state_option {
-                Some(s) => s.clone(),
-                None => continue,
-            };
-            self.check_uri_health(&server_uri, healthy_state, self.health_timeout_secs);
+            self.check_uri_health(&server_uri, healthy_state.clone(), self.health_timeout_secs);
         }
     }
 
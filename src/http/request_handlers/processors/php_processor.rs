@@ -1,21 +1,42 @@
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::configuration::upstream_tls::UpstreamTlsSettings;
+use crate::deployment::site_builder::get_site_builder;
 use crate::error::gruxi_error::GruxiError;
 use crate::error::gruxi_error_enums::{GruxiErrorKind, PHPProcessorError};
-use crate::external_connections::fastcgi::FastCgi;
+use crate::external_connections::fastcgi::{FASTCGI_CACHE_CONDITIONS_KEY, FastCgi, FastCgiTls};
 use crate::file::normalized_path::NormalizedPath;
 use crate::http::http_util::resolve_web_root_and_path_and_get_file;
+use crate::http::request_handlers::processors::proxy_helpers::proxy_cache::{get_cache_key, get_php_cache, get_revalidation_headers, is_cacheable_request};
 use crate::http::request_response::gruxi_response::GruxiResponse;
-use crate::logging::syslog::{debug, error, trace};
+use crate::http::upload_manager::get_upload_manager;
+use crate::logging::syslog::{debug, error, trace, warn};
+use crate::tls::upstream_tls::{check_ca_bundle, get_server_name_override, get_upstream_tls_config};
 use crate::{
-    configuration::site::Site,
+    configuration::site::{Site, merge_environment_variables},
     core::running_state_manager::get_running_state_manager,
-    http::{http_util::empty_response_with_status, request_handlers::processor_trait::ProcessorTrait, request_response::gruxi_request::GruxiRequest},
+    http::{request_handlers::processor_trait::ProcessorTrait, request_response::gruxi_request::GruxiRequest},
 };
+use rustls::ClientConfig;
+use rustls::pki_types::ServerName;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+// Index file used when the site does not define its own index files
+const DEFAULT_PHP_INDEX_FILE: &str = "index.php";
+
+// How PHP can be served, by a PHP-CGI executable managed by Gruxi (Windows only) or by an external FastCGI server such as PHP-FPM
+pub const PHP_SERVED_BY_TYPES: [&str; 2] = ["win-php-cgi", "php-fpm"];
+
+// PHP index files for the site in priority order. Only PHP scripts are of interest, other index files are left for the static file processor
+fn get_php_index_files(site: &Site) -> Vec<String> {
+    let default_index_files = [DEFAULT_PHP_INDEX_FILE.to_string()];
+    site.get_index_files(&default_index_files).iter().filter(|f| f.to_lowercase().ends_with(".php")).cloned().collect()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct PHPProcessor {
     pub id: String, // Unique identifier for the processor
     // Can either be served by a local PHP-CGI executable or via FastCGI (PHP-FPM or similar)
@@ -28,13 +49,38 @@ pub struct PHPProcessor {
     pub local_web_root: String,   // local location for the web root
     pub fastcgi_web_root: String, // Relevant for "php-fpm" type, for web-root rewriting when passing to FastCGI handler
     // Server software spoofing [fastcgi:SERVER_SOFTWARE] (some PHP frameworks check for this in stupid ways - Looking at you, WordPress!)
+    #[serde(default)]
     pub server_software_spoof: String, // Spoofed server software string
+    // Disk cache of the responses the scripts allow shared caches to store, by Cache-Control or Expires with an ETag or Last-Modified.
+    // Stale responses are revalidated with the scripts, which can answer 304 Not Modified instead of building the body again
+    #[serde(default)]
+    pub cache_enabled: bool,
+    #[serde(default = "get_default_cache_max_size_mb")]
+    pub cache_max_size_mb: u32,
+    // TLS to the FastCGI server, for PHP-FPM on an untrusted network behind a TLS terminating proxy such as stunnel. Turning
+    // verification off is logged loudly, pinning the certificate is the safe way to trust a self-signed one
+    #[serde(default)]
+    pub fastcgi_tls_enabled: bool,
+    #[serde(default = "get_default_fastcgi_verify_tls_certificates")]
+    pub fastcgi_verify_tls_certificates: bool,
+    #[serde(default)]
+    pub fastcgi_tls_settings: UpstreamTlsSettings,
 
     // Calculated fields (not serialized)
     #[serde(skip)]
     normalized_local_web_root: Option<NormalizedPath>,
     #[serde(skip)]
     normalized_fastcgi_web_root: Option<NormalizedPath>,
+    #[serde(skip)]
+    fastcgi_tls_config: Option<Arc<ClientConfig>>,
+}
+
+fn get_default_cache_max_size_mb() -> u32 {
+    1024
+}
+
+fn get_default_fastcgi_verify_tls_certificates() -> bool {
+    true
 }
 
 impl PHPProcessor {
@@ -48,10 +94,39 @@ impl PHPProcessor {
             local_web_root: String::new(),
             fastcgi_web_root: String::new(),
             server_software_spoof: "".to_string(),
+            cache_enabled: false,
+            cache_max_size_mb: get_default_cache_max_size_mb(),
+            fastcgi_tls_enabled: false,
+            fastcgi_verify_tls_certificates: get_default_fastcgi_verify_tls_certificates(),
+            fastcgi_tls_settings: UpstreamTlsSettings::default(),
             normalized_local_web_root: None,
             normalized_fastcgi_web_root: None,
+            fastcgi_tls_config: None,
         }
     }
+
+    // The normalized local web root, available once the processor is initialized
+    pub fn get_local_web_root(&self) -> Option<String> {
+        self.normalized_local_web_root.as_ref().map(|web_root| web_root.get_full_path())
+    }
+
+    // TLS for the connection to the FastCGI server, if enabled. The certificate is verified for the server name of the TLS settings,
+    // or else for the host the FastCGI server is connected to
+    fn get_fastcgi_tls(&self, connect_ip_and_port: &str) -> Result<Option<FastCgiTls>, String> {
+        if !self.fastcgi_tls_enabled || self.served_by_type != "php-fpm" {
+            return Ok(None);
+        }
+        let config = self.fastcgi_tls_config.clone().ok_or("The TLS configuration could not be loaded")?;
+        let server_name = match get_server_name_override(&self.fastcgi_tls_settings) {
+            Some(server_name) => server_name,
+            None => {
+                let host = connect_ip_and_port.rsplit_once(':').map_or(connect_ip_and_port, |(host, _)| host);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                ServerName::try_from(host.to_string()).map_err(|e| format!("'{}' cannot be used as TLS server name: {}", host, e))?
+            }
+        };
+        Ok(Some(FastCgiTls { config, server_name }))
+    }
 }
 
 impl ProcessorTrait for PHPProcessor {
@@ -77,6 +152,18 @@ impl ProcessorTrait for PHPProcessor {
                 }
             };
         }
+        if self.fastcgi_tls_enabled && self.fastcgi_tls_config.is_none() {
+            if !self.fastcgi_verify_tls_certificates && self.fastcgi_tls_settings.pinned_certificates.is_empty() {
+                warn(format!(
+                    "PHP processor {}: TLS certificate verification of the FastCGI server is DISABLED - connections to it can be intercepted. Pin the certificate or add a CA bundle instead",
+                    self.id
+                ));
+            }
+            match get_upstream_tls_config(self.fastcgi_verify_tls_certificates, &self.fastcgi_tls_settings) {
+                Ok(config) => self.fastcgi_tls_config = Some(Arc::new(config)),
+                Err(e) => error(format!("PHP processor {}: Failed to set up TLS for the FastCGI server: {}", self.id, e)),
+            }
+        }
     }
 
     fn sanitize(&mut self) {
@@ -88,6 +175,7 @@ impl ProcessorTrait for PHPProcessor {
         self.local_web_root = self.local_web_root.trim().to_string();
         self.fastcgi_web_root = self.fastcgi_web_root.trim().to_string();
         self.server_software_spoof = self.server_software_spoof.trim().to_string();
+        self.fastcgi_tls_settings.sanitize();
     }
 
     fn validate(&self) -> Result<(), Vec<String>> {
@@ -99,7 +187,7 @@ impl ProcessorTrait for PHPProcessor {
         }
 
         // served_by_type should be either "win-php-cgi" or "php-fpm"
-        if self.served_by_type != "win-php-cgi" && self.served_by_type != "php-fpm" {
+        if !PHP_SERVED_BY_TYPES.contains(&self.served_by_type.as_str()) {
             errors.push(format!("PHP Processor: Invalid served_by_type, must be either 'win-php-cgi' or 'php-fpm': {}", self.served_by_type));
         }
 
@@ -118,6 +206,20 @@ impl ProcessorTrait for PHPProcessor {
             errors.push("PHP Processor: Request timeout must be greater than 0.".to_string());
         }
 
+        if self.cache_enabled && self.cache_max_size_mb < 1 {
+            errors.push("PHP Processor: Max cache size must be at least 1 MB.".to_string());
+        }
+
+        if self.fastcgi_tls_enabled && self.served_by_type != "php-fpm" {
+            errors.push("PHP Processor: TLS to the FastCGI server is only available when served by PHP-FPM.".to_string());
+        }
+        errors.extend(self.fastcgi_tls_settings.validate().into_iter().map(|e| format!("PHP Processor: {}", e)));
+        if self.fastcgi_tls_enabled
+            && let Err(e) = check_ca_bundle(self.fastcgi_verify_tls_certificates, &self.fastcgi_tls_settings)
+        {
+            errors.push(format!("PHP Processor: {}", e));
+        }
+
         // Local web root must be set
         if self.local_web_root.is_empty() {
             errors.push("PHP Processor: Local web root must be set.".to_string());
@@ -162,7 +264,17 @@ impl ProcessorTrait for PHPProcessor {
             }
         };
 
-        let mut path = gruxi_request.get_path().clone();
+        // Sites deployed by Gruxi are served from their current release. A PHP-FPM web root mapping to another path is kept, as PHP-FPM cannot see the releases there
+        let (local_web_root, fastcgi_web_root) = match get_site_builder().get_active_web_root(site) {
+            Some(active_web_root) if fastcgi_web_root.is_empty() => (active_web_root, fastcgi_web_root),
+            Some(active_web_root) if fastcgi_web_root == local_web_root => (active_web_root.clone(), active_web_root),
+            _ => (local_web_root, fastcgi_web_root),
+        };
+
+        let mut path = match gruxi_request.get_canonical_path() {
+            Some(path) => path,
+            None => return Err(GruxiError::new_with_kind_only(GruxiErrorKind::PHPProcessor(PHPProcessorError::FileNotFound))),
+        };
 
         // Get the file, if it exists
         let normalized_path_result = NormalizedPath::new(&local_web_root, &path);
@@ -187,8 +299,9 @@ impl ProcessorTrait for PHPProcessor {
             trace(format!("File does not exist: {}", file_path));
             if site.get_rewrite_functions_hashmap().contains_key("OnlyWebRootIndexForSubdirs") {
                 trace(format!("[OnlyWebRootIndexForSubdirs] Rewriting request path {} to root dir due to rewrite function", path));
-                // We rewrite the path to just "/" which will make it serve the index file
-                path = "/index.php".to_string();
+                // We rewrite the path to the index file of the web root
+                let index_file = get_php_index_files(site).into_iter().next().unwrap_or(DEFAULT_PHP_INDEX_FILE.to_string());
+                path = format!("/{}", index_file);
 
                 // Check if the index file exists
                 let normalized_path_result = NormalizedPath::new(&local_web_root, &path);
@@ -214,28 +327,29 @@ impl ProcessorTrait for PHPProcessor {
 
         let mut uri_is_a_dir_with_index_file_inside = false;
         if file_data.meta.is_directory {
-            // If it's a directory, we will try to check if there is an index.php file inside
+            // If it's a directory, we will try to check if there is an index file inside, in the priority order of the site
             trace(format!("File is a directory: {}", file_path));
 
-            let normalized_path_result = NormalizedPath::new(&file_path, "/index.php");
-            let normalized_path = match normalized_path_result {
-                Ok(path) => path,
-                Err(_) => {
-                    return Err(GruxiError::new_with_kind_only(GruxiErrorKind::PHPProcessor(PHPProcessorError::FileNotFound)));
-                }
-            };
+            let mut found_index = false;
+            for index_file in get_php_index_files(site) {
+                let normalized_path = match NormalizedPath::new(&file_path, &format!("/{}", index_file)) {
+                    Ok(path) => path,
+                    Err(_) => continue,
+                };
 
-            let file_data_result = resolve_web_root_and_path_and_get_file(&normalized_path).await;
-            file_data = match file_data_result {
-                Ok(data) => data,
-                Err(_) => {
-                    return Ok(empty_response_with_status(hyper::StatusCode::NOT_FOUND));
+                if let Ok(index_file_data) = resolve_web_root_and_path_and_get_file(&normalized_path).await
+                    && index_file_data.meta.exists
+                {
+                    file_data = index_file_data;
+                    found_index = true;
+                    break;
                 }
-            };
+            }
 
-            if file_data.meta.exists == false {
+            // Without a PHP index file, the next request handler gets a chance, such as a static file processor with an index.html
+            if !found_index {
                 trace(format!("Index files in dir does not exist: {}", file_path));
-                return Ok(empty_response_with_status(hyper::StatusCode::NOT_FOUND));
+                return Err(GruxiError::new_with_kind_only(GruxiErrorKind::PHPProcessor(PHPProcessorError::FileNotFound)));
             }
 
             file_path = file_data.meta.file_path.clone();
@@ -243,6 +357,37 @@ impl ProcessorTrait for PHPProcessor {
             uri_is_a_dir_with_index_file_inside = true;
         }
 
+        // Make sure the script resolves within the paths the site allows, so symlinks cannot be used to escape the web root
+        if !site.is_path_confined(&local_web_root, &file_path).await {
+            debug(format!("PHP script path is blocked by path confinement for site '{}': {}", site.id, file_path));
+            return Err(GruxiError::new_with_kind_only(GruxiErrorKind::PHPProcessor(PHPProcessorError::FileBlockedDueToSecurity(file_path))));
+        }
+
+        // Fresh cached responses are served without running the script, stale ones are revalidated with it
+        let php_cache = if self.cache_enabled && is_cacheable_request(gruxi_request) {
+            Some(get_php_cache(&self.id, self.cache_max_size_mb))
+        } else {
+            None
+        };
+        let cache_key = get_cache_key(gruxi_request);
+        let cached_response = match &php_cache {
+            Some(php_cache) => php_cache.get(&cache_key).await,
+            None => None,
+        };
+        if let Some(cached_response) = &cached_response
+            && cached_response.is_fresh()
+        {
+            trace(format!("Serving cached PHP response for {}", cache_key));
+            return Ok(cached_response.to_response(gruxi_request, "HIT"));
+        }
+        if php_cache.is_some() {
+            let cache_conditions: Vec<(String, String)> = get_revalidation_headers(cached_response.as_ref())
+                .iter()
+                .filter_map(|(header_name, value)| Some((header_name.to_string(), value.to_str().ok()?.to_string())))
+                .collect();
+            gruxi_request.add_calculated_data(FASTCGI_CACHE_CONDITIONS_KEY, &serde_json::to_string(&cache_conditions).unwrap_or_default());
+        }
+
         // Now get the IP and port to connect to
         let connect_ip_and_port_result = self.get_ip_and_port().await;
         let connect_ip_and_port = match connect_ip_and_port_result {
@@ -254,10 +399,20 @@ impl ProcessorTrait for PHPProcessor {
             }
         };
 
+        let fastcgi_tls = match self.get_fastcgi_tls(&connect_ip_and_port) {
+            Ok(fastcgi_tls) => fastcgi_tls,
+            Err(e) => {
+                error(format!("PHP Processor: Cannot set up TLS to the FastCGI server for processor ID {}: {}", self.id, e));
+                return Err(GruxiError::new_with_kind_only(GruxiErrorKind::PHPProcessor(PHPProcessorError::Connection)));
+            }
+        };
+
         // Figure out if we have a connection semaphore to use
+        let mut handler_environment = Vec::new();
         if !self.php_cgi_handler_id.trim().is_empty() {
             let running_state = get_running_state_manager().await.get_running_state_unlocked().await;
             let external_system_handler = running_state.get_external_system_handler();
+            handler_environment = external_system_handler.get_environment_for_php_cgi(&self.php_cgi_handler_id).to_vec();
 
             let semaphore_option = external_system_handler.get_connection_semaphore(&self.php_cgi_handler_id);
             let connection_semaphore = match semaphore_option {
@@ -279,12 +434,37 @@ impl ProcessorTrait for PHPProcessor {
         gruxi_request.add_calculated_data("fastcgi_local_web_root", &local_web_root);
         gruxi_request.add_calculated_data("fastcgi_web_root", &fastcgi_web_root);
         gruxi_request.add_calculated_data("fastcgi_override_server_software", &self.server_software_spoof);
+        if let Some(php_error_log_path) = site.get_php_error_log_path() {
+            gruxi_request.add_calculated_data("fastcgi_php_error_log_file", &php_error_log_path.to_string_lossy());
+        }
+        let environment = merge_environment_variables(&handler_environment, &site.extra_environment);
+        if !environment.is_empty() {
+            gruxi_request.add_calculated_data("fastcgi_environment", &serde_json::to_string(&environment).unwrap_or_default());
+        }
+
+        // Read the body before talking to PHP, so slow uploads do not hold a PHP worker or count towards the timeout. Large bodies are spooled to disk
+        let request_body = match get_upload_manager().spool_request_body(gruxi_request, site).await {
+            Ok(request_body) => request_body,
+            Err(e) => {
+                debug(format!("PHP Processor: Failed to read request body: {}", e));
+                return Err(GruxiError::new_with_kind_only(GruxiErrorKind::PHPProcessor(PHPProcessorError::RequestBody(e))));
+            }
+        };
+        gruxi_request.set_read_body_size(request_body.len());
 
         // Process the FastCGI request with timeout
-        match tokio::time::timeout(Duration::from_secs(self.request_timeout as u64), FastCgi::process_fastcgi_request(gruxi_request)).await {
+        match tokio::time::timeout(
+            Duration::from_secs(self.request_timeout as u64),
+            FastCgi::process_fastcgi_request(gruxi_request, &request_body, fastcgi_tls.as_ref()),
+        )
+        .await
+        {
             Ok(response) => match response {
                 Ok(resp) => {
                     trace("PHP Request completed successfully".to_string());
+                    if let Some(php_cache) = &php_cache {
+                        return Ok(php_cache.handle_buffered_response(&cache_key, cached_response, resp, gruxi_request, &site.id).await);
+                    }
                     return Ok(resp);
                 }
                 Err(err) => {
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 
 use crate::http::request_handlers::processors::{
-    load_balancer::load_balancer::LoadBalancerRegistry, php_processor::PHPProcessor, proxy_processor::ProxyProcessor, static_files_processor::StaticFileProcessor,
+    load_balancer::{load_balancer::LoadBalancerRegistry, round_robin::RoundRobin},
+    php_processor::PHPProcessor,
+    proxy_processor::ProxyProcessor,
+    static_files_processor::StaticFileProcessor,
 };
 
 pub struct ProcessorManager {
@@ -40,8 +43,19 @@ impl ProcessorManager {
             processor_manager.proxy_processors.insert(p.id.clone(), p.clone());
         });
 
-        // Create load balancers for proxy processors
-        for proxy_processor in processor_manager.proxy_processors.values() {
+        // Create a load balancer for each upstream pool, shared by the proxy processors using it
+        for upstream in &config.upstreams {
+            let lb = RoundRobin::new(
+                upstream.get_weighted_servers(),
+                upstream.health_check_path.clone(),
+                upstream.health_check_timeout_seconds as u64,
+                upstream.health_check_interval_seconds as u64,
+            );
+            processor_manager.load_balancer_registry.create(upstream.id.clone(), lb).await;
+        }
+
+        // Create load balancers for proxy processors with servers of their own
+        for proxy_processor in processor_manager.proxy_processors.values().filter(|p| p.upstream_id.is_empty()) {
             let lb = proxy_processor.get_load_balancer_service();
             processor_manager.load_balancer_registry.create(proxy_processor.id.clone(), lb).await;
         }
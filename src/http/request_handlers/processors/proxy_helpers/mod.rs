@@ -1 +1,2 @@
-pub mod no_verifier;
\ No newline at end of file
+pub mod no_verifier;
+pub mod proxy_cache;
@@ -0,0 +1,455 @@
+use crate::file::disk_cache::{DiskCache, get_disk_cache_manager};
+use crate::http::etag::if_none_match_matches;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use chrono::{DateTime, Utc};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use http_body_util::BodyExt;
+use hyper::Response;
+use hyper::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// The disk caches of the proxy and PHP processors are named by these and the id of the processor
+const PROXY_CACHE_NAME: &str = "proxy";
+const PHP_CACHE_NAME: &str = "php";
+
+// Responses larger than this, or of unknown length, are passed through without being cached
+const MAX_PROXY_CACHE_ENTRY_BYTES: u64 = 16 * 1024 * 1024;
+
+// Statuses cacheable by default by RFC 9110, which a shared cache may store when the response allows it
+const CACHEABLE_STATUSES: [u16; 6] = [200, 203, 301, 308, 404, 410];
+
+// Responses with a Last-Modified but no explicit lifetime are fresh for a tenth of their age, by RFC 9111, up to this many seconds
+const MAX_HEURISTIC_FRESHNESS_SECONDS: i64 = 24 * 60 * 60;
+
+// Request headers asking the upstream for something other than the full response to store
+pub const CONDITIONAL_REQUEST_HEADERS: [HeaderName; 4] = [
+    hyper::header::IF_NONE_MATCH,
+    hyper::header::IF_MODIFIED_SINCE,
+    hyper::header::IF_MATCH,
+    hyper::header::IF_UNMODIFIED_SINCE,
+];
+
+// The cache of a proxy processor, by its id. The size limit is updated, as it may have been changed in the configuration
+pub fn get_proxy_cache(processor_id: &str, max_size_mb: u32) -> ProxyCache {
+    get_response_cache(PROXY_CACHE_NAME, processor_id, max_size_mb)
+}
+
+// The cache of a PHP processor, for the responses its scripts allow shared caches to store
+pub fn get_php_cache(processor_id: &str, max_size_mb: u32) -> ProxyCache {
+    get_response_cache(PHP_CACHE_NAME, processor_id, max_size_mb)
+}
+
+fn get_response_cache(cache_name: &str, processor_id: &str, max_size_mb: u32) -> ProxyCache {
+    let disk_cache = get_disk_cache_manager().get_cache(&format!("{}/{}", cache_name, processor_id));
+    disk_cache.set_max_size_bytes(max_size_mb as u64 * 1024 * 1024);
+    ProxyCache { disk_cache }
+}
+
+// Whether the response to the request may come from the cache. Requests with credentials or for parts of a response always go to the upstream
+pub fn is_cacheable_request(gruxi_request: &mut GruxiRequest) -> bool {
+    let method = gruxi_request.get_http_method();
+    if method != "GET" && method != "HEAD" {
+        return false;
+    }
+    let headers = gruxi_request.get_headers();
+    !headers.contains_key(hyper::header::AUTHORIZATION) && !headers.contains_key(hyper::header::RANGE) && !headers.contains_key(hyper::header::UPGRADE)
+}
+
+// Responses are cached by scheme, host and path with query, as one processor may serve several hostnames
+pub fn get_cache_key(gruxi_request: &mut GruxiRequest) -> String {
+    let scheme = if gruxi_request.is_https() { "https" } else { "http" };
+    format!("{}://{}{}", scheme, gruxi_request.get_hostname(), gruxi_request.get_path_and_query())
+}
+
+// Prepares the request to the upstream for a cached request. Compression is left to Gruxi, so the stored body serves every client,
+// and the conditions of the client are replaced by those of the stale cached response, if any, to revalidate it
+pub fn prepare_upstream_request(headers: &mut HeaderMap, stale_response: Option<&CachedResponse>) {
+    headers.remove(hyper::header::ACCEPT_ENCODING);
+    for header_name in &CONDITIONAL_REQUEST_HEADERS {
+        headers.remove(header_name);
+    }
+    for (header_name, value) in get_revalidation_headers(stale_response) {
+        headers.insert(header_name, value);
+    }
+}
+
+// The conditions asking the backend if the stale cached response is still the current one, by its validators
+pub fn get_revalidation_headers(stale_response: Option<&CachedResponse>) -> Vec<(HeaderName, HeaderValue)> {
+    let Some(stale_response) = stale_response else {
+        return Vec::new();
+    };
+    let mut headers = Vec::new();
+    if let Some(etag) = stale_response.get_header("etag").and_then(|v| HeaderValue::from_str(v).ok()) {
+        headers.push((hyper::header::IF_NONE_MATCH, etag));
+    }
+    if let Some(last_modified) = stale_response.get_header("last-modified").and_then(|v| HeaderValue::from_str(v).ok()) {
+        headers.push((hyper::header::IF_MODIFIED_SINCE, last_modified));
+    }
+    headers
+}
+
+// How many seconds a response may be served from the cache, or None if it may not be stored at all. Follows RFC 9111 for shared
+// caches, except that responses varying by anything but Accept-Encoding, which the cache asks the upstream not to use, are not stored
+fn get_freshness_lifetime(status: u16, headers: &HeaderMap, now: DateTime<Utc>) -> Option<i64> {
+    if !CACHEABLE_STATUSES.contains(&status) || headers.contains_key(hyper::header::SET_COOKIE) {
+        return None;
+    }
+    let varies = headers
+        .get_all(hyper::header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|field| !field.trim().is_empty() && !field.trim().eq_ignore_ascii_case("accept-encoding"));
+    if varies {
+        return None;
+    }
+
+    let directives: Vec<String> = headers
+        .get_all(hyper::header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|directive| directive.trim().to_lowercase())
+        .collect();
+    if directives
+        .iter()
+        .any(|directive| directive == "no-store" || directive == "private" || directive.starts_with("private="))
+    {
+        return None;
+    }
+    let get_seconds = |name: &str| {
+        directives
+            .iter()
+            .find_map(|directive| directive.strip_prefix(name)?.strip_prefix('=')?.trim_matches('"').parse::<i64>().ok())
+    };
+    let has_validator = headers.contains_key(hyper::header::ETAG) || headers.contains_key(hyper::header::LAST_MODIFIED);
+
+    let lifetime = if directives.iter().any(|directive| directive == "no-cache") {
+        // Stored, but revalidated with the upstream every time
+        0
+    } else if let Some(seconds) = get_seconds("s-maxage").or_else(|| get_seconds("max-age")) {
+        seconds
+    } else if let Some(expires) = get_header_date(headers, hyper::header::EXPIRES) {
+        let date = get_header_date(headers, hyper::header::DATE).unwrap_or(now);
+        (expires - date).num_seconds()
+    } else if let Some(last_modified) = get_header_date(headers, hyper::header::LAST_MODIFIED) {
+        let date = get_header_date(headers, hyper::header::DATE).unwrap_or(now);
+        ((date - last_modified).num_seconds() / 10).min(MAX_HEURISTIC_FRESHNESS_SECONDS)
+    } else {
+        return None;
+    };
+
+    // A response that is stale right away is only worth keeping if it can be revalidated
+    if lifetime <= 0 && !has_validator {
+        return None;
+    }
+    Some(lifetime.max(0))
+}
+
+fn get_header_date(headers: &HeaderMap, name: HeaderName) -> Option<DateTime<Utc>> {
+    let value = headers.get(name)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value).ok().map(|date| date.with_timezone(&Utc))
+}
+
+// What is stored of a response, on the first line of its cache file, followed by the body
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedResponseHead {
+    key: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    // When the upstream created the response and until when it is fresh, as unix timestamps
+    created_at: i64,
+    fresh_until: i64,
+}
+
+pub struct CachedResponse {
+    head: CachedResponseHead,
+    body: Bytes,
+}
+
+impl CachedResponse {
+    pub fn is_fresh(&self) -> bool {
+        Utc::now().timestamp() < self.head.fresh_until
+    }
+
+    // Whether the response may stand in for the upstream when it cannot be reached, which the upstream forbids for it with
+    // no-cache, must-revalidate or proxy-revalidate
+    pub fn may_serve_stale(&self) -> bool {
+        !self
+            .head
+            .headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("cache-control"))
+            .flat_map(|(_, value)| value.split(','))
+            .map(|directive| directive.trim().to_lowercase())
+            .any(|directive| directive == "no-cache" || directive.starts_with("no-cache=") || directive == "must-revalidate" || directive == "proxy-revalidate")
+    }
+
+    fn get_header(&self, name: &str) -> Option<&str> {
+        self.head
+            .headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The cached response for the client, with its age, or 304 Not Modified when the client already has it
+    pub fn to_response(&self, gruxi_request: &GruxiRequest, cache_status: &'static str) -> GruxiResponse {
+        let is_not_modified = self.head.status == 200
+            && gruxi_request
+                .get_headers()
+                .get(hyper::header::IF_NONE_MATCH)
+                .and_then(|v| v.to_str().ok())
+                .zip(self.get_header("etag"))
+                .is_some_and(|(if_none_match, etag)| if_none_match_matches(if_none_match, etag));
+
+        let mut response = if is_not_modified {
+            GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_MODIFIED.as_u16())
+        } else {
+            GruxiResponse::new_with_bytes(self.head.status, self.body.clone())
+        };
+        let headers = response.headers_mut();
+        for (name, value) in &self.head.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                headers.append(name, value);
+            }
+        }
+        let age = (Utc::now().timestamp() - self.head.created_at).max(0);
+        headers.insert(hyper::header::AGE, HeaderValue::from(age));
+        headers.insert(HeaderName::from_static("x-cache"), HeaderValue::from_static(cache_status));
+        response
+    }
+}
+
+// Responses of one proxy or PHP processor, kept in its disk cache with the head of the response on the first line, followed by the body
+pub struct ProxyCache {
+    disk_cache: Arc<DiskCache>,
+}
+
+impl ProxyCache {
+    pub async fn get(&self, key: &str) -> Option<CachedResponse> {
+        let content = self.disk_cache.get(key).await?;
+        let cached_response = parse_cached_response(content).filter(|cached_response| cached_response.head.key == key);
+        if cached_response.is_none() {
+            self.disk_cache.remove(key).await;
+        }
+        cached_response
+    }
+
+    // Stores the response of the upstream if it may be cached, and gives it back for the client. A 304 Not Modified for a stale cached
+    // response makes it fresh again, with the headers of the 304
+    pub async fn handle_upstream_response(
+        &self,
+        key: &str,
+        stale_response: Option<CachedResponse>,
+        response: Response<hyper::body::Incoming>,
+        gruxi_request: &GruxiRequest,
+        site_id: &str,
+    ) -> Result<GruxiResponse, hyper::Error> {
+        let now = Utc::now();
+        if response.status() == hyper::StatusCode::NOT_MODIFIED
+            && let Some(stale_response) = stale_response
+        {
+            return Ok(self.revalidate(key, stale_response, response.headers(), gruxi_request, site_id, now).await);
+        }
+
+        let content_length = response.headers().get(hyper::header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+        let lifetime = get_freshness_lifetime(response.status().as_u16(), response.headers(), now);
+        let (Some(lifetime), Some(content_length)) = (lifetime, content_length.filter(|length| *length <= MAX_PROXY_CACHE_ENTRY_BYTES)) else {
+            let mut gruxi_response = GruxiResponse::from_hyper(response);
+            gruxi_response.headers_mut().insert(HeaderName::from_static("x-cache"), HeaderValue::from_static("MISS"));
+            return Ok(gruxi_response);
+        };
+
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await?.to_bytes();
+        let cached_response = CachedResponse {
+            head: create_head(key, parts.status.as_u16(), &parts.headers, lifetime, now),
+            body,
+        };
+        if cached_response.body.len() as u64 == content_length {
+            self.store(site_id, &cached_response).await;
+        }
+        Ok(cached_response.to_response(gruxi_request, "MISS"))
+    }
+
+    // As handle_upstream_response, for backends whose responses are read in full, such as FastCGI
+    pub async fn handle_buffered_response(&self, key: &str, stale_response: Option<CachedResponse>, mut response: GruxiResponse, gruxi_request: &GruxiRequest, site_id: &str) -> GruxiResponse {
+        let now = Utc::now();
+        let status = response.get_status();
+        if status == hyper::StatusCode::NOT_MODIFIED.as_u16()
+            && let Some(stale_response) = stale_response
+        {
+            return self.revalidate(key, stale_response, response.headers(), gruxi_request, site_id, now).await;
+        }
+
+        // The body of a buffered response stays in it when read, so it can still be sent if it is not stored
+        let lifetime = get_freshness_lifetime(status, response.headers(), now).filter(|_| response.is_body_buffered());
+        let body = if lifetime.is_some() { response.get_body_bytes().await } else { Bytes::new() };
+        let Some(lifetime) = lifetime.filter(|_| body.len() as u64 <= MAX_PROXY_CACHE_ENTRY_BYTES) else {
+            response.headers_mut().insert(HeaderName::from_static("x-cache"), HeaderValue::from_static("MISS"));
+            return response;
+        };
+
+        let cached_response = CachedResponse {
+            head: create_head(key, status, response.headers(), lifetime, now),
+            body,
+        };
+        self.store(site_id, &cached_response).await;
+        cached_response.to_response(gruxi_request, "MISS")
+    }
+
+    // A 304 Not Modified for the stale cached response makes it fresh again, with the headers of the 304
+    async fn revalidate(&self, key: &str, stale_response: CachedResponse, not_modified_headers: &HeaderMap, gruxi_request: &GruxiRequest, site_id: &str, now: DateTime<Utc>) -> GruxiResponse {
+        let mut headers = get_header_map(&stale_response.head.headers);
+        for name in not_modified_headers.keys() {
+            headers.remove(name);
+        }
+        for (name, value) in not_modified_headers {
+            headers.append(name, value.clone());
+        }
+        let Some(lifetime) = get_freshness_lifetime(stale_response.head.status, &headers, now) else {
+            // No longer allowed to be cached, but the stale body is still the current one
+            self.disk_cache.remove(key).await;
+            return CachedResponse {
+                head: CachedResponseHead {
+                    headers: get_header_pairs(&headers),
+                    ..stale_response.head
+                },
+                body: stale_response.body,
+            }
+            .to_response(gruxi_request, "REVALIDATED");
+        };
+        let cached_response = CachedResponse {
+            head: create_head(key, stale_response.head.status, &headers, lifetime, now),
+            body: stale_response.body,
+        };
+        self.store(site_id, &cached_response).await;
+        cached_response.to_response(gruxi_request, "REVALIDATED")
+    }
+
+    async fn store(&self, site_id: &str, cached_response: &CachedResponse) {
+        let Ok(head) = serde_json::to_vec(&cached_response.head) else {
+            return;
+        };
+        let mut content = Vec::with_capacity(head.len() + 1 + cached_response.body.len());
+        content.extend_from_slice(&head);
+        content.push(b'\n');
+        content.extend_from_slice(&cached_response.body);
+        self.disk_cache.store(&cached_response.head.key, site_id, &content).await;
+    }
+}
+
+fn create_head(key: &str, status: u16, headers: &HeaderMap, lifetime: i64, now: DateTime<Utc>) -> CachedResponseHead {
+    // The response may already have been in another cache on the way, for as long as its Age
+    let age = headers.get(hyper::header::AGE).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+    let created_at = now.timestamp() - age.max(0);
+    let mut headers = headers.clone();
+    headers.remove(hyper::header::AGE);
+    CachedResponseHead {
+        key: key.to_string(),
+        status,
+        headers: get_header_pairs(&headers),
+        created_at,
+        fresh_until: created_at + lifetime,
+    }
+}
+
+fn get_header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers.iter().filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string()))).collect()
+}
+
+fn get_header_map(header_pairs: &[(String, String)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in header_pairs {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            headers.append(name, value);
+        }
+    }
+    headers
+}
+
+fn parse_cached_response(content: Bytes) -> Option<CachedResponse> {
+    let head_length = content.iter().position(|byte| *byte == b'\n')?;
+    let head: CachedResponseHead = serde_json::from_slice(&content[..head_length]).ok()?;
+    Some(CachedResponse {
+        head,
+        body: content.slice(head_length + 1..),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_headers(headers: &[(&str, &str)]) -> HeaderMap {
+        get_header_map(&headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_freshness_lifetime() {
+        let now = Utc::now();
+        assert_eq!(get_freshness_lifetime(200, &create_headers(&[("Cache-Control", "public, max-age=600")]), now), Some(600));
+        assert_eq!(get_freshness_lifetime(200, &create_headers(&[("Cache-Control", "max-age=600, s-maxage=60")]), now), Some(60));
+        assert_eq!(
+            get_freshness_lifetime(200, &create_headers(&[("Date", "Wed, 21 Oct 2026 07:28:00 GMT"), ("Expires", "Wed, 21 Oct 2026 08:28:00 GMT")]), now),
+            Some(3600)
+        );
+        assert_eq!(get_freshness_lifetime(200, &create_headers(&[("Cache-Control", "no-cache"), ("ETag", "\"a\"")]), now), Some(0));
+
+        // Not to be stored by a shared cache
+        assert_eq!(get_freshness_lifetime(200, &create_headers(&[("Cache-Control", "private, max-age=600")]), now), None);
+        assert_eq!(get_freshness_lifetime(200, &create_headers(&[("Cache-Control", "no-store")]), now), None);
+        assert_eq!(get_freshness_lifetime(200, &create_headers(&[("Cache-Control", "no-cache")]), now), None);
+        assert_eq!(get_freshness_lifetime(200, &create_headers(&[("Cache-Control", "max-age=600"), ("Set-Cookie", "a=b")]), now), None);
+        assert_eq!(get_freshness_lifetime(200, &create_headers(&[("Cache-Control", "max-age=600"), ("Vary", "Cookie")]), now), None);
+        assert_eq!(
+            get_freshness_lifetime(200, &create_headers(&[("Cache-Control", "max-age=600"), ("Vary", "Accept-Encoding")]), now),
+            Some(600)
+        );
+        assert_eq!(get_freshness_lifetime(500, &create_headers(&[("Cache-Control", "max-age=600")]), now), None);
+        assert_eq!(get_freshness_lifetime(200, &create_headers(&[]), now), None);
+    }
+
+    #[tokio::test]
+    async fn test_cached_response_round_trip() {
+        let directory = std::env::temp_dir().join(format!("gruxi-test-proxy-cache-{}", uuid::Uuid::new_v4()));
+        let proxy_cache = ProxyCache {
+            disk_cache: Arc::new(DiskCache::new("proxy/test", directory.clone())),
+        };
+        let headers = create_headers(&[("Cache-Control", "max-age=600"), ("Content-Type", "text/html"), ("ETag", "\"v1\""), ("Age", "30")]);
+        let cached_response = CachedResponse {
+            head: create_head("http://example.com/a", 200, &headers, 600, Utc::now()),
+            body: Bytes::from("<p>Hi</p>"),
+        };
+        proxy_cache.store("site", &cached_response).await;
+
+        let cached_response = proxy_cache.get("http://example.com/a").await.unwrap();
+        assert!(cached_response.is_fresh());
+        assert_eq!(cached_response.body, "<p>Hi</p>");
+        let mut gruxi_request = GruxiRequest::new(hyper::Request::builder().uri("/a").body(Bytes::new()).unwrap());
+        let mut response = cached_response.to_response(&gruxi_request, "HIT");
+        assert_eq!(response.get_header("Content-Type").unwrap(), "text/html");
+        assert_eq!(response.get_header("X-Cache").unwrap(), "HIT");
+        assert!(response.get_header("Age").unwrap().to_str().unwrap().parse::<i64>().unwrap() >= 30);
+        assert_eq!(response.get_body_bytes().await, "<p>Hi</p>");
+
+        // A client having the response already gets a 304
+        gruxi_request = GruxiRequest::new(hyper::Request::builder().uri("/a").header("If-None-Match", "\"v1\"").body(Bytes::new()).unwrap());
+        assert_eq!(cached_response.to_response(&gruxi_request, "HIT").get_status(), 304);
+        assert!(cached_response.may_serve_stale());
+
+        // The upstream may forbid serving the response once it is stale
+        for cache_control in ["no-cache", "max-age=600, must-revalidate", "public, Proxy-Revalidate"] {
+            let headers = create_headers(&[("Cache-Control", cache_control), ("ETag", "\"v1\"")]);
+            let cached_response = CachedResponse {
+                head: create_head("http://example.com/b", 200, &headers, 600, Utc::now()),
+                body: Bytes::new(),
+            };
+            assert!(!cached_response.may_serve_stale());
+        }
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
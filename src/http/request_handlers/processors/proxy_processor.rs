@@ -1,43 +1,74 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::{
-    configuration::site::Site,
-    core::running_state_manager,
+    configuration::{site::Site, upstream::validate_upstream_address, upstream_tls::UpstreamTlsSettings},
+    core::{monitoring::get_monitoring_state, running_state_manager},
     error::{
         gruxi_error::GruxiError,
         gruxi_error_enums::{GruxiErrorKind, ProxyProcessorError},
     },
     http::{
+        early_hints::forward_upstream_early_hints,
+        http_util::get_via_header_value,
         request_handlers::{
             processor_trait::ProcessorTrait,
-            processors::load_balancer::{load_balancer::LoadBalancerImpl, round_robin::RoundRobin},
+            processors::load_balancer::{
+                load_balancer::{LoadBalancerImpl, LoadBalancerRegistry, get_server_id},
+                round_robin::RoundRobin,
+            },
+            processors::proxy_helpers::proxy_cache::{get_cache_key, get_proxy_cache, is_cacheable_request, prepare_upstream_request},
         },
-        request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse},
+        request_response::{
+            body_error::BodyError,
+            gruxi_request::GruxiRequest,
+            gruxi_response::GruxiResponse,
+            request_timings::{RequestPhase, get_current_request_timings},
+        },
+        request_validation::combine_cookie_headers,
     },
-    logging::syslog::{error, trace},
+    logging::syslog::{error, trace, warn},
+    network::dns_cache::get_dns_cache,
+    tls::upstream_tls::check_ca_bundle,
 };
 use http::HeaderValue;
-use hyper::Response;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::{Body, Bytes};
+use hyper::{Request, Response};
 use hyper_util::rt::TokioIo;
 use serde::{Deserialize, Serialize};
 use tokio::time::timeout;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+// Supported proxy types and load balancing strategies, only one of each for now
+pub const PROXY_TYPES: [&str; 1] = ["http"];
+pub const LOAD_BALANCING_STRATEGIES: [&str; 1] = ["round_robin"];
+// How a client is kept on the same upstream server, for applications keeping sessions on the server itself
+pub const SESSION_AFFINITY_MODES: [&str; 3] = ["none", "cookie", "client_ip"];
+// Requests of these methods are retried when connecting to the upstream fails, other requests only when marked idempotent by the
+// client with the Idempotency-Key header
+const RETRYABLE_METHODS: [hyper::Method; 3] = [hyper::Method::GET, hyper::Method::HEAD, hyper::Method::OPTIONS];
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const MAX_RETRIES: u32 = 10;
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ProxyProcessorRewrite {
     pub from: String,
     pub to: String,
     pub is_case_insensitive: bool,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ProxyProcessor {
     pub id: String,         // Unique identifier for the processor
     pub proxy_type: String, // e.g., "http", for further extension
     // HTTP Proxy specific settings
-    pub upstream_servers: Vec<String>,   // List of upstream servers e.g., ["http://server1:8080", "https://server2:8080"]
+    pub upstream_servers: Vec<String>, // List of upstream servers e.g., ["http://server1:8080", "https://server2:8080"]
+    #[serde(default)]
+    pub upstream_id: String, // Named upstream pool to use instead of the upstream servers and health check settings here, if set
     pub load_balancing_strategy: String, // e.g., "round_robin" only for now
-    pub timeout_seconds: u16,            // Timeout for upstream requests, in seconds
+    pub timeout_seconds: u16,          // Timeout for upstream requests, in seconds
     // Health check settings
     pub health_check_path: String,          // Path to use for health checks, if empty, we dont do health checks
     pub health_check_interval_seconds: u32, // Interval between health checks, in seconds
@@ -49,6 +80,62 @@ pub struct ProxyProcessor {
     pub forced_host_header: String, // If set, this host header will be used instead of the original request's Host header, disregarding preserve_host_header - normally not recommended for normal use
     // SSL/TLS settings
     pub verify_tls_certificates: bool, // Whether to verify TLS certificates (set to false for self-signed certs)
+    // CA bundle, SNI override and pinned certificates for HTTPS upstreams. Pinning is the safe way to trust a self-signed certificate,
+    // turning verification off is logged loudly, as anyone on the network between Gruxi and the upstream can intercept the traffic
+    #[serde(default)]
+    pub tls_settings: UpstreamTlsSettings,
+    // Session affinity, one of SESSION_AFFINITY_MODES. A client moves to another upstream server only when its own fails the health check
+    #[serde(default = "get_default_session_affinity")]
+    pub session_affinity: String,
+    #[serde(default = "get_default_session_affinity_cookie_name")]
+    pub session_affinity_cookie_name: String, // Cookie holding the upstream server of the client, for the "cookie" mode
+    // Disk cache of the responses the upstream allows shared caches to store, making the processor a simple CDN node for a remote origin
+    #[serde(default)]
+    pub cache_enabled: bool,
+    #[serde(default = "get_default_cache_max_size_mb")]
+    pub cache_max_size_mb: u32,
+    // Retries on upstream connect failures, for requests that are safe to send again, within a budget for the time spent on the
+    // request, so an upstream outage does not make every request wait for a series of connect timeouts
+    #[serde(default = "get_default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "get_default_retry_budget_seconds")]
+    pub retry_budget_seconds: u32,
+}
+
+fn get_default_session_affinity() -> String {
+    SESSION_AFFINITY_MODES[0].to_string()
+}
+
+fn get_default_session_affinity_cookie_name() -> String {
+    "gruxi_backend".to_string()
+}
+
+fn get_default_cache_max_size_mb() -> u32 {
+    1024
+}
+
+fn get_default_max_retries() -> u32 {
+    2
+}
+
+fn get_default_retry_budget_seconds() -> u32 {
+    10
+}
+
+// Whether the request may be sent again after connecting to the upstream failed: a retryable method or marked idempotent, and without
+// a body, as a streamed body is handed to the failed attempt and is never sent again
+fn is_retryable_request(request: &Request<BoxBody<Bytes, BodyError>>) -> bool {
+    let is_idempotent = RETRYABLE_METHODS.contains(request.method()) || request.headers().contains_key(IDEMPOTENCY_KEY_HEADER);
+    is_idempotent && request.body().is_end_stream()
+}
+
+fn copy_request_without_body(request: &Request<BoxBody<Bytes, BodyError>>) -> Request<BoxBody<Bytes, BodyError>> {
+    let mut copy = Request::new(Empty::new().map_err(|never| match never {}).boxed());
+    *copy.method_mut() = request.method().clone();
+    *copy.uri_mut() = request.uri().clone();
+    *copy.version_mut() = request.version();
+    *copy.headers_mut() = request.headers().clone();
+    copy
 }
 
 impl ProxyProcessor {
@@ -57,6 +144,7 @@ impl ProxyProcessor {
             id: Uuid::new_v4().to_string(),
             proxy_type: "http".to_string(),
             upstream_servers: Vec::new(),
+            upstream_id: String::new(),
             load_balancing_strategy: "round_robin".to_string(),
             timeout_seconds: 30,
             health_check_path: "/health".to_string(),
@@ -66,9 +154,56 @@ impl ProxyProcessor {
             preserve_host_header: false,
             forced_host_header: "".to_string(),
             verify_tls_certificates: true,
+            tls_settings: UpstreamTlsSettings::default(),
+            session_affinity: get_default_session_affinity(),
+            session_affinity_cookie_name: get_default_session_affinity_cookie_name(),
+            cache_enabled: false,
+            cache_max_size_mb: get_default_cache_max_size_mb(),
+            max_retries: get_default_max_retries(),
+            retry_budget_seconds: get_default_retry_budget_seconds(),
+        }
+    }
+
+    // Processors using a named upstream pool share its load balancer, and with it the health of its servers
+    pub fn get_load_balancer_id(&self) -> &str {
+        if self.upstream_id.is_empty() { &self.id } else { &self.upstream_id }
+    }
+
+    // Picks the upstream server for the request, keeping the client on its server with session affinity. Returns the server and,
+    // for the cookie mode, the cookie to set when the client has none yet or its server is no longer available. The excluded
+    // servers, those a retried request already failed to reach, are never picked, so the client moves to another server instead
+    async fn choose_upstream_server(&self, gruxi_request: &mut GruxiRequest, load_balancer_registry: &LoadBalancerRegistry, excluded: &[String]) -> Option<(String, Option<String>)> {
+        match self.session_affinity.as_str() {
+            "client_ip" => {
+                let server = load_balancer_registry.get_server_by_hash(self.get_load_balancer_id(), &gruxi_request.get_remote_ip(), excluded).await?;
+                Some((server, None))
+            }
+            "cookie" => {
+                if let Some(server_id) = gruxi_request.get_cookie(&self.session_affinity_cookie_name)
+                    && let Some(server) = load_balancer_registry.get_server_by_id(self.get_load_balancer_id(), &server_id, excluded).await
+                {
+                    return Some((server, None));
+                }
+                let server = load_balancer_registry.get_next_server(self.get_load_balancer_id(), excluded).await?;
+                let set_cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Lax", self.session_affinity_cookie_name, get_server_id(&server));
+                Some((server, Some(set_cookie)))
+            }
+            _ => Some((load_balancer_registry.get_next_server(self.get_load_balancer_id(), excluded).await?, None)),
         }
     }
 
+    // The URL of the request on the upstream server, after the URL rewrites
+    fn get_upstream_uri(&self, server: &str, original_uri: &str) -> Result<hyper::Uri, GruxiError> {
+        let rewritten_url = self.apply_url_rewrites(&format!("{}{}", server, original_uri));
+        rewritten_url.parse().map_err(|e| {
+            error(format!(
+                "Could not parse a rewritten URL '{}' for proxy processor with id: {} with error: {:?}",
+                rewritten_url, self.id, e
+            ));
+            GruxiError::new_with_kind_only(GruxiErrorKind::ProxyProcessor(ProxyProcessorError::Internal))
+        })
+    }
+
     pub fn apply_url_rewrites(&self, original_url: &str) -> String {
         // Process the URI through the rewrite rules
         let mut url = original_url.to_string();
@@ -140,12 +275,21 @@ impl ProxyProcessor {
 }
 
 impl ProcessorTrait for ProxyProcessor {
-    fn initialize(&mut self) {}
+    fn initialize(&mut self) {
+        if !self.verify_tls_certificates && self.tls_settings.pinned_certificates.is_empty() {
+            warn(format!(
+                "Proxy processor {}: TLS certificate verification of the upstream servers is DISABLED - connections to them can be intercepted. Pin the certificates or add a CA bundle instead",
+                self.id
+            ));
+        }
+    }
 
     fn sanitize(&mut self) {
         // Clean up upstream server URLs
         self.upstream_servers = self.upstream_servers.iter().map(|url| url.trim().to_string()).filter(|url| !url.is_empty()).collect();
 
+        self.upstream_id = self.upstream_id.trim().to_string();
+
         // Load balancing strategy trim
         self.load_balancing_strategy = self.load_balancing_strategy.trim().to_string();
 
@@ -160,39 +304,46 @@ impl ProcessorTrait for ProxyProcessor {
 
         // Forced host header trim
         self.forced_host_header = self.forced_host_header.trim().to_string();
+
+        // Session affinity cleanup
+        self.session_affinity = self.session_affinity.trim().to_lowercase();
+        self.session_affinity_cookie_name = self.session_affinity_cookie_name.trim().to_string();
+
+        self.tls_settings.sanitize();
     }
 
     fn validate(&self) -> Result<(), Vec<String>> {
         let mut errors = Vec::new();
 
-        if self.proxy_type != "http" {
+        if !PROXY_TYPES.contains(&self.proxy_type.as_str()) {
             errors.push("Unsupported proxy type. Only 'http' is supported.".to_string());
         }
 
-        // There needs to be at least one upstream server
-        if self.upstream_servers.is_empty() {
-            errors.push("At least one upstream server must be specified.".to_string());
+        // There needs to be at least one upstream server, unless a named upstream pool is used. The pool is checked with the configuration
+        if self.upstream_servers.is_empty() && self.upstream_id.is_empty() {
+            errors.push("At least one upstream server or an upstream pool must be specified.".to_string());
         }
 
         // All upstream servers must be valid URLs, starting with http:// or https://
         for server in &self.upstream_servers {
-            if !server.starts_with("http://") && !server.starts_with("https://") {
-                errors.push(format!("Upstream server '{}' is not a valid upstream URL. It must start with 'http://' or 'https://'.", server));
-            }
-            if server.ends_with("/") {
-                errors.push(format!("Upstream server '{}' should not end with a trailing slash '/'.", server));
-            }
-
-            // Try to parse the URL
-            if let Err(_) = server.parse::<hyper::Uri>() {
-                errors.push(format!("Upstream server '{}' is not a valid URL.", server));
-            }
+            errors.extend(validate_upstream_address(server));
         }
 
-        if self.load_balancing_strategy != "round_robin" {
+        if !LOAD_BALANCING_STRATEGIES.contains(&self.load_balancing_strategy.as_str()) {
             errors.push("Unsupported load balancing strategy. Only 'Round Robin' is supported.".to_string());
         }
 
+        if !SESSION_AFFINITY_MODES.contains(&self.session_affinity.as_str()) {
+            errors.push(format!("Session affinity must be one of {}: {}", SESSION_AFFINITY_MODES.join(", "), self.session_affinity));
+        }
+        let is_valid_cookie_name = !self.session_affinity_cookie_name.is_empty() && self.session_affinity_cookie_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if self.session_affinity == "cookie" && !is_valid_cookie_name {
+            errors.push(format!(
+                "Session affinity cookie name '{}' may only contain letters, digits, '_' and '-'",
+                self.session_affinity_cookie_name
+            ));
+        }
+
         // Url rewrites validation, must never have empty to or from fields
         for rewrite in &self.url_rewrites {
             if rewrite.from.is_empty() {
@@ -207,6 +358,22 @@ impl ProcessorTrait for ProxyProcessor {
             errors.push("Timeout seconds must be greater than zero.".to_string());
         }
 
+        if self.cache_enabled && self.cache_max_size_mb < 1 {
+            errors.push("Max cache size must be at least 1 MB.".to_string());
+        }
+
+        errors.extend(self.tls_settings.validate());
+        if let Err(e) = check_ca_bundle(self.verify_tls_certificates, &self.tls_settings) {
+            errors.push(e);
+        }
+
+        if self.max_retries > MAX_RETRIES {
+            errors.push(format!("Max retries cannot be more than {}.", MAX_RETRIES));
+        }
+        if self.max_retries > 0 && self.retry_budget_seconds < 1 {
+            errors.push("Retry budget seconds must be greater than zero when retries are enabled.".to_string());
+        }
+
         if !self.health_check_path.is_empty() {
             if !self.health_check_path.starts_with('/') {
                 errors.push("Health check path must start with '/', such as '/health' or '/healthcheck/'.".to_string());
@@ -224,7 +391,7 @@ impl ProcessorTrait for ProxyProcessor {
         if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 
-    async fn handle_request(&self, gruxi_request: &mut GruxiRequest, _site: &Site) -> Result<GruxiResponse, GruxiError> {
+    async fn handle_request(&self, gruxi_request: &mut GruxiRequest, site: &Site) -> Result<GruxiResponse, GruxiError> {
         trace(format!("ProxyProcessor handling request - {:?}", &self));
 
         // We determine which upstream server to use based on the load balancing strategy.
@@ -233,56 +400,76 @@ impl ProcessorTrait for ProxyProcessor {
         let running_state_read_lock = running_state.read().await;
         let processor_manager = running_state_read_lock.get_processor_manager();
 
-        let server_to_handle_request_option = processor_manager.load_balancer_registry.get_next_server(self.id.as_str()).await;
-        let server_to_handle_request = match server_to_handle_request_option {
+        // Fresh cached responses are served without asking the upstream, stale ones are revalidated with it
+        let proxy_cache = if self.cache_enabled && is_cacheable_request(gruxi_request) {
+            Some(get_proxy_cache(&self.id, self.cache_max_size_mb))
+        } else {
+            None
+        };
+        let cache_key = get_cache_key(gruxi_request);
+        let cached_response = match &proxy_cache {
+            Some(proxy_cache) => proxy_cache.get(&cache_key).await,
+            None => None,
+        };
+        if let Some(cached_response) = &cached_response
+            && cached_response.is_fresh()
+        {
+            trace(format!("Serving cached response for {}", cache_key));
+            return Ok(cached_response.to_response(gruxi_request, "HIT"));
+        }
+
+        let server_to_handle_request_option = self.choose_upstream_server(gruxi_request, &processor_manager.load_balancer_registry, &[]).await;
+        let (mut server_to_handle_request, mut session_affinity_cookie) = match server_to_handle_request_option {
             Some(s) => s,
             None => {
                 error(format!("No upstream servers are currently available for proxy processor with id: {}", self.id));
+                // A stale response is better than none while the upstream is down
+                if let Some(cached_response) = &cached_response
+                    && cached_response.may_serve_stale()
+                {
+                    return Ok(cached_response.to_response(gruxi_request, "STALE"));
+                }
                 return Err(GruxiError::new_with_kind_only(GruxiErrorKind::ProxyProcessor(ProxyProcessorError::UpstreamUnavailable)));
             }
         };
 
         // Rewrite the request URL to point to the upstream server
         let original_uri = gruxi_request.get_uri();
-        let new_uri = format!("{}{}", server_to_handle_request, original_uri);
+        let upstream_uri = self.get_upstream_uri(&server_to_handle_request, &original_uri)?;
+        let mut upstream_host = upstream_uri.host().unwrap_or_default().to_string();
 
-        // Apply any URL rewrites
-        let rewritten_url = self.apply_url_rewrites(&new_uri);
-
-        // Parse the full upstream URL
-        let upstream_uri: hyper::Uri = match rewritten_url.parse() {
-            Ok(uri) => uri,
+        // Get the client appropriate for TLS verification settings
+        let client = match running_state_read_lock.get_http_client().get_client_with_tls_settings(self.verify_tls_certificates, &self.tls_settings) {
+            Ok(client) => client,
             Err(e) => {
-                error(format!(
-                    "Could not parse a rewritten URL '{}' for proxy processor with id: {} with error: {:?}",
-                    rewritten_url, self.id, e
-                ));
+                error(format!("Failed to set up TLS for the upstream servers of proxy processor {}: {}", self.id, e));
                 return Err(GruxiError::new_with_kind_only(GruxiErrorKind::ProxyProcessor(ProxyProcessorError::Internal)));
             }
         };
 
-        // Get the client appropriate for TLS verification settings
-        let client = running_state_read_lock.get_http_client().get_client(self.verify_tls_certificates);
-
         // Get the client-side upgrade on the request side
         let client_upgrade = gruxi_request.take_upgrade();
+        let is_head_request = gruxi_request.get_http_method() == "HEAD";
 
         // Clean any hop by hop headers from the request and add forwarded headers
         gruxi_request.clean_hop_by_hop_headers();
         gruxi_request.add_forwarded_headers();
 
         // Get the original request to extract headers and body
-        let mut proxy_request = match gruxi_request.get_streaming_http_request() {
-            Ok(req) => req,
-            Err(_) => {
-                error(format!("Failed to get streaming HTTP request for request: {:?}", gruxi_request));
-                return Err(GruxiError::new_with_kind_only(GruxiErrorKind::ProxyProcessor(ProxyProcessorError::Internal)));
-            }
-        };
+        let mut proxy_request = gruxi_request.get_streaming_http_request();
 
         // Update the URI to point to the upstream server (with full URL including scheme/host/port)
         *proxy_request.uri_mut() = upstream_uri;
 
+        if proxy_cache.is_some() {
+            prepare_upstream_request(proxy_request.headers_mut(), cached_response.as_ref());
+        }
+        // HTTP/2 clients may send a Cookie header per cookie, which HTTP/1.1 upstreams do not expect
+        combine_cookie_headers(proxy_request.headers_mut());
+        if let Some(via) = get_via_header_value(&gruxi_request.get_http_version()) {
+            proxy_request.headers_mut().append(hyper::header::VIA, via);
+        }
+
         // Check if we should preserve the host header or remote it to let hyper set it
         if self.forced_host_header.is_empty() {
             // Header is there already, so we only remove it if we are not preserving it
@@ -299,61 +486,155 @@ impl ProcessorTrait for ProxyProcessor {
 
         trace(format!("Forwarding request to upstream server: {:?}", proxy_request));
 
+        // Copy of the request, to send again if connecting to the upstream fails
+        let retry_request = if self.max_retries > 0 && is_retryable_request(&proxy_request) {
+            Some(copy_request_without_body(&proxy_request))
+        } else {
+            None
+        };
+        let mut retries = 0;
+        let mut tried_servers: Vec<String> = Vec::new();
+
         let timeout_duration = Duration::from_secs(self.timeout_seconds as u64);
-        match timeout(timeout_duration, client.request(proxy_request)).await {
-            Ok(Ok(mut resp)) => {
-                // Check if this is a protocol upgrade
-                let mut is_websocket_upgrade = false;
-                if resp.status() == hyper::StatusCode::SWITCHING_PROTOCOLS {
-                    trace("Detected WebSocket/protocol upgrade (HTTP 101)");
-
-                    // Get the upstream upgrade from the response extensions
-                    let upstream_upgrade = resp.extensions_mut().remove::<hyper::upgrade::OnUpgrade>();
-
-                    if let (Some(client_upgrade), Some(upstream_upgrade)) = (client_upgrade, upstream_upgrade) {
-                        // Spawn task to bridge the connections
-                        tokio::spawn(async move {
-                            match tokio::try_join!(client_upgrade, upstream_upgrade) {
-                                Ok((client, upstream)) => {
-                                    trace("WebSocket upgrade successful, bridging connections");
-                                    // Wrap the upgraded connections with TokioIo to make them compatible with tokio::io
-                                    let mut client = TokioIo::new(client);
-                                    let mut upstream = TokioIo::new(upstream);
-                                    match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
-                                        Ok((from_client, from_server)) => {
-                                            trace(format!("WebSocket closed. Client→Server: {} bytes, Server→Client: {} bytes", from_client, from_server));
-                                        }
-                                        Err(e) => {
-                                            error(format!("WebSocket proxy error: {}", e));
-                                        }
-                                    }
+        let retry_budget = Duration::from_secs(self.retry_budget_seconds as u64);
+        let mut attempt_timeout = timeout_duration;
+        let request_timings = get_current_request_timings();
+        let first_attempt_started = Instant::now();
+        let mut connect_before;
+        let mut request_started;
+        let mut resp = loop {
+            connect_before = request_timings.as_ref().and_then(|t| t.get(RequestPhase::BackendConnect)).unwrap_or_default();
+            request_started = Instant::now();
+            if site.early_hints_enabled {
+                forward_upstream_early_hints(&mut proxy_request);
+            }
+            match timeout(attempt_timeout, client.request(proxy_request)).await {
+                Ok(Ok(resp)) => break resp,
+                Ok(Err(e)) => {
+                    error(format!("Failed to send request to upstream server: {:?}", e));
+                    // The upstream may have moved to another address, so it is resolved again for the next request
+                    if e.is_connect() {
+                        get_dns_cache().invalidate(&upstream_host);
+                    }
+
+                    // Nothing reached the upstream when connecting failed, so the request can be sent again, possibly to another
+                    // upstream server, as long as the retry budget is not spent
+                    let remaining_budget = retry_budget.saturating_sub(first_attempt_started.elapsed());
+                    tried_servers.push(server_to_handle_request.clone());
+                    if e.is_connect()
+                        && retries < self.max_retries
+                        && !remaining_budget.is_zero()
+                        && let Some(retry_request) = &retry_request
+                        && let Some((server, cookie)) = self.choose_upstream_server(gruxi_request, &processor_manager.load_balancer_registry, &tried_servers).await
+                    {
+                        let upstream_uri = self.get_upstream_uri(&server, &original_uri)?;
+                        trace(format!("Retrying request to upstream server '{}' on '{}'", server_to_handle_request, server));
+                        get_monitoring_state().await.increment_upstream_retries(&server_to_handle_request);
+                        retries += 1;
+                        attempt_timeout = timeout_duration.min(remaining_budget);
+                        upstream_host = upstream_uri.host().unwrap_or_default().to_string();
+                        server_to_handle_request = server;
+                        session_affinity_cookie = cookie;
+                        proxy_request = copy_request_without_body(retry_request);
+                        *proxy_request.uri_mut() = upstream_uri;
+                        continue;
+                    }
+
+                    if let Some(cached_response) = &cached_response
+                        && cached_response.may_serve_stale()
+                    {
+                        return Ok(cached_response.to_response(gruxi_request, "STALE"));
+                    }
+                    return Err(GruxiError::new_with_kind_only(GruxiErrorKind::ProxyProcessor(ProxyProcessorError::ConnectionFailed)));
+                }
+                Err(_) => {
+                    error(format!(
+                        "Request to upstream server '{}' timed out after {} seconds",
+                        server_to_handle_request,
+                        attempt_timeout.as_secs()
+                    ));
+                    if let Some(cached_response) = &cached_response
+                        && cached_response.may_serve_stale()
+                    {
+                        return Ok(cached_response.to_response(gruxi_request, "STALE"));
+                    }
+                    return Err(GruxiError::new_with_kind_only(GruxiErrorKind::ProxyProcessor(ProxyProcessorError::UpstreamTimeout)));
+                }
+            }
+        };
+
+        // The time to the response headers, without a new connection to the upstream if one was made
+        if let Some(request_timings) = &request_timings {
+            let connect = request_timings.get(RequestPhase::BackendConnect).unwrap_or_default().saturating_sub(connect_before);
+            request_timings.add(RequestPhase::BackendFirstByte, request_started.elapsed().saturating_sub(connect));
+        }
+        // Check if this is a protocol upgrade
+        let mut is_websocket_upgrade = false;
+        if resp.status() == hyper::StatusCode::SWITCHING_PROTOCOLS {
+            trace("Detected WebSocket/protocol upgrade (HTTP 101)");
+
+            // Get the upstream upgrade from the response extensions
+            let upstream_upgrade = resp.extensions_mut().remove::<hyper::upgrade::OnUpgrade>();
+
+            if let (Some(client_upgrade), Some(upstream_upgrade)) = (client_upgrade, upstream_upgrade) {
+                // Spawn task to bridge the connections
+                tokio::spawn(async move {
+                    match tokio::try_join!(client_upgrade, upstream_upgrade) {
+                        Ok((client, upstream)) => {
+                            trace("WebSocket upgrade successful, bridging connections");
+                            // Wrap the upgraded connections with TokioIo to make them compatible with tokio::io
+                            let mut client = TokioIo::new(client);
+                            let mut upstream = TokioIo::new(upstream);
+                            match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+                                Ok((from_client, from_server)) => {
+                                    trace(format!("WebSocket closed. Client→Server: {} bytes, Server→Client: {} bytes", from_client, from_server));
                                 }
                                 Err(e) => {
-                                    error(format!("Failed to upgrade connections: {}", e));
+                                    error(format!("WebSocket proxy error: {}", e));
                                 }
                             }
-                        });
-                        is_websocket_upgrade = true;
+                        }
+                        Err(e) => {
+                            error(format!("Failed to upgrade connections: {}", e));
+                        }
                     }
-                }
+                });
+                is_websocket_upgrade = true;
+            }
+        }
 
-                // In the response, we make sure to update/clean the headers as needed
-                Self::clean_hop_by_hop_headers_in_response(&mut resp, is_websocket_upgrade);
+        // The Content-Length of a HEAD response describes the body a GET would return, so it is kept
+        let head_content_length = if is_head_request { resp.headers().get(hyper::header::CONTENT_LENGTH).cloned() } else { None };
 
-                // Wrap response in GruxiResponse
-                let gruxi_response = GruxiResponse::from_hyper(resp);
+        // In the response, we make sure to update/clean the headers as needed
+        Self::clean_hop_by_hop_headers_in_response(&mut resp, is_websocket_upgrade);
+        if let Some(content_length) = head_content_length {
+            resp.headers_mut().insert(hyper::header::CONTENT_LENGTH, content_length);
+        }
+        if let Some(via) = get_via_header_value(&format!("{:?}", resp.version())) {
+            resp.headers_mut().append(hyper::header::VIA, via);
+        }
 
-                return Ok(gruxi_response);
-            }
-            Ok(Err(e)) => {
-                error(format!("Failed to send request to upstream server: {:?}", e));
-                return Err(GruxiError::new_with_kind_only(GruxiErrorKind::ProxyProcessor(ProxyProcessorError::ConnectionFailed)));
-            }
-            Err(_) => {
-                error(format!("Request to upstream server '{}' timed out after {} seconds", server_to_handle_request, self.timeout_seconds));
-                return Err(GruxiError::new_with_kind_only(GruxiErrorKind::ProxyProcessor(ProxyProcessorError::UpstreamTimeout)));
-            }
+        // Wrap response in GruxiResponse, through the cache if it may keep it
+        let mut gruxi_response = match &proxy_cache {
+            Some(proxy_cache) if !is_websocket_upgrade => match proxy_cache.handle_upstream_response(&cache_key, cached_response, resp, gruxi_request, &site.id).await {
+                Ok(gruxi_response) => gruxi_response,
+                Err(e) => {
+                    error(format!("Failed to read response from upstream server '{}' for caching: {:?}", server_to_handle_request, e));
+                    return Err(GruxiError::new_with_kind_only(GruxiErrorKind::ProxyProcessor(ProxyProcessorError::ConnectionFailed)));
+                }
+            },
+            _ => GruxiResponse::from_hyper(resp),
+        };
+
+        // Keep the client on this upstream server from now on
+        if let Some(cookie) = session_affinity_cookie
+            && let Ok(header_value) = HeaderValue::from_str(&cookie)
+        {
+            gruxi_response.headers_mut().append(hyper::header::SET_COOKIE, header_value);
         }
+
+        Ok(gruxi_response)
     }
 
     fn get_type(&self) -> String {
@@ -364,3 +645,81 @@ impl ProcessorTrait for ProxyProcessor {
         "Proxy Processor".to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+
+    #[test]
+    fn test_is_retryable_request() {
+        let get_request = |method: &str, headers: &[(&str, &str)], body: &'static str| {
+            let mut builder = Request::builder().method(method).uri("http://backend:8080/api");
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            builder.body(Full::new(Bytes::from(body)).map_err(|never| match never {}).boxed()).unwrap()
+        };
+
+        assert!(is_retryable_request(&get_request("GET", &[], "")));
+        assert!(is_retryable_request(&get_request("OPTIONS", &[], "")));
+        assert!(!is_retryable_request(&get_request("POST", &[], "")));
+        assert!(!is_retryable_request(&get_request("DELETE", &[], "")));
+        assert!(is_retryable_request(&get_request("DELETE", &[("Idempotency-Key", "8e03978e")], "")));
+        // A body is never sent twice
+        assert!(!is_retryable_request(&get_request("POST", &[("Idempotency-Key", "8e03978e")], "name=value")));
+
+        let request = get_request("GET", &[("Accept", "application/json")], "");
+        let copy = copy_request_without_body(&request);
+        assert_eq!(copy.method(), request.method());
+        assert_eq!(copy.uri(), request.uri());
+        assert_eq!(copy.headers(), request.headers());
+    }
+
+    #[tokio::test]
+    async fn test_retries_with_session_affinity_choose_another_server() {
+        // The load balancer checks the health of the servers right away, so they answer its checks
+        let mut upstream_servers = Vec::new();
+        for _ in 0..2 {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            upstream_servers.push(format!("http://{}", listener.local_addr().unwrap()));
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let service = hyper::service::service_fn(|_request| async { Ok::<_, std::convert::Infallible>(Response::new(Empty::<Bytes>::new())) });
+                    tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+                }
+            });
+        }
+        let mut processor = ProxyProcessor::new();
+        processor.id = "affinity-retry-test".to_string();
+        processor.upstream_servers = upstream_servers;
+        let load_balancer_registry = LoadBalancerRegistry::new();
+        load_balancer_registry.create(processor.id.clone(), processor.get_load_balancer_service()).await;
+
+        let mut gruxi_request = GruxiRequest::new(Request::builder().uri("/").header("Cookie", "other=1").body(Bytes::new()).unwrap());
+        gruxi_request.add_calculated_data("remote_ip", "10.0.0.1");
+
+        for session_affinity in ["client_ip", "cookie"] {
+            processor.session_affinity = session_affinity.to_string();
+            let (first, _) = processor.choose_upstream_server(&mut gruxi_request, &load_balancer_registry, &[]).await.unwrap();
+            let (retry, _) = processor
+                .choose_upstream_server(&mut gruxi_request, &load_balancer_registry, std::slice::from_ref(&first))
+                .await
+                .unwrap();
+            assert_ne!(first, retry, "{}", session_affinity);
+            assert!(processor.choose_upstream_server(&mut gruxi_request, &load_balancer_registry, &[first, retry]).await.is_none());
+        }
+
+        // A client pinned by its cookie moves to another server when its own server failed
+        processor.session_affinity = "cookie".to_string();
+        let pinned = processor.upstream_servers[0].clone();
+        let cookie = format!("{}={}", processor.session_affinity_cookie_name, get_server_id(&pinned));
+        let mut gruxi_request = GruxiRequest::new(Request::builder().uri("/").header("Cookie", cookie).body(Bytes::new()).unwrap());
+        let (retry, set_cookie) = processor
+            .choose_upstream_server(&mut gruxi_request, &load_balancer_registry, std::slice::from_ref(&pinned))
+            .await
+            .unwrap();
+        assert_eq!(retry, processor.upstream_servers[1]);
+        assert!(set_cookie.unwrap().contains(&get_server_id(&retry)));
+    }
+}
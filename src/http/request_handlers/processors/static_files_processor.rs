@@ -1,22 +1,35 @@
 use crate::{
     configuration::site::Site,
+    deployment::site_builder::get_site_builder,
     error::{
         gruxi_error::GruxiError,
         gruxi_error_enums::{GruxiErrorKind, StaticFileProcessorError},
     },
-    file::{file_util::check_path_secure, normalized_path::NormalizedPath},
+    file::{
+        file_handle_limiter::{FILE_HANDLE_WAIT_TIMEOUT, get_file_handle_limiter},
+        file_reader_structs::FileEntry,
+        file_util::check_path_secure,
+        minification::get_minified_file_cache,
+        normalized_path::NormalizedPath,
+        preload_links::get_preload_link_cache,
+    },
     http::{
-        http_util::resolve_web_root_and_path_and_get_file,
+        http_util::{add_vary_field, resolve_web_root_and_path_and_get_file},
+        language_negotiation::get_language_candidates,
         request_handlers::processor_trait::ProcessorTrait,
-        request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse},
+        request_response::{body_error::BodyError, gruxi_request::GruxiRequest, gruxi_response::GruxiResponse},
     },
-    logging::syslog::{error, trace},
+    logging::syslog::{debug, error, trace},
 };
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::body::Body;
 use hyper::header::HeaderValue;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct StaticFileProcessor {
     pub id: String,                            // Unique identifier for the processor
     pub web_root: String,                      // Web root directory for static files
@@ -37,6 +50,30 @@ impl StaticFileProcessor {
             normalized_web_root: None,
         }
     }
+
+    // The normalized web root, available once the processor is initialized
+    pub fn get_web_root(&self) -> Option<String> {
+        self.normalized_web_root.as_ref().map(|web_root| web_root.get_full_path())
+    }
+
+    // Finds the language variant of a file, such as /page.html.de for /page.html, based on the Accept-Language header and the site default language
+    async fn find_language_variant(&self, gruxi_request: &GruxiRequest, site: &Site, file_path: &str) -> Option<(Arc<FileEntry>, String)> {
+        let accept_language = gruxi_request.get_headers().get(hyper::header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok()).unwrap_or("");
+        for language in get_language_candidates(accept_language, &site.default_language) {
+            let normalized_path = match NormalizedPath::new(&format!("{}.{}", file_path, language), "") {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            if let Ok(file_data) = resolve_web_root_and_path_and_get_file(&normalized_path).await
+                && file_data.meta.exists
+                && !file_data.meta.is_directory
+            {
+                trace(format!("Found language variant '{}' for file: {}", language, file_path));
+                return Some((file_data, language));
+            }
+        }
+        None
+    }
 }
 
 impl ProcessorTrait for StaticFileProcessor {
@@ -112,8 +149,13 @@ impl ProcessorTrait for StaticFileProcessor {
             }
             Some(web_root) => web_root.get_full_path(),
         };
+        // Sites deployed by Gruxi are served from the output of their current release, once there is one
+        let web_root = get_site_builder().get_active_web_root(site).unwrap_or(web_root);
 
-        let mut path = gruxi_request.get_path().clone();
+        let mut path = match gruxi_request.get_canonical_path() {
+            Some(path) => path,
+            None => return Err(GruxiError::new_with_kind_only(GruxiErrorKind::StaticFileProcessor(StaticFileProcessorError::FileNotFound))),
+        };
 
         // Get the file, if it exists
         let normalized_path_result = NormalizedPath::new(&web_root, &path);
@@ -144,6 +186,17 @@ impl ProcessorTrait for StaticFileProcessor {
         };
         let mut file_path = file_data.meta.file_path.clone();
 
+        // Language variants are served in place of the file, with the content type of the file, which does not need to exist itself
+        let mut language_variant: Option<(String, String)> = None;
+        if site.language_negotiation_enabled
+            && !file_data.meta.is_directory
+            && let Some((variant_file_data, language)) = self.find_language_variant(gruxi_request, site, &file_path).await
+        {
+            language_variant = Some((language, mime_guess::from_path(&file_path).first_or_octet_stream().to_string()));
+            file_data = variant_file_data;
+            file_path = file_data.meta.file_path.clone();
+        }
+
         // If the file/dir does not exist, we check if we have a rewrite function that allows us to rewrite to the index file
         if !file_data.meta.exists {
             trace(format!("File does not exist: {}", file_path));
@@ -186,7 +239,7 @@ impl ProcessorTrait for StaticFileProcessor {
 
             // Check if we can find a index file in the directory
             let mut found_index = false;
-            for file in &self.web_root_index_file_list {
+            for file in site.get_index_files(&self.web_root_index_file_list) {
                 // Get the file, if it exists
                 let normalized_path_result = NormalizedPath::new(&file_path, &file);
                 let normalized_path = match normalized_path_result {
@@ -217,6 +270,15 @@ impl ProcessorTrait for StaticFileProcessor {
                 break;
             }
 
+            if found_index
+                && site.language_negotiation_enabled
+                && let Some((variant_file_data, language)) = self.find_language_variant(gruxi_request, site, &file_path).await
+            {
+                language_variant = Some((language, file_data.meta.mime_type.clone()));
+                file_data = variant_file_data;
+                file_path = file_data.meta.file_path.clone();
+            }
+
             if !found_index {
                 trace(format!("Did not find index file: {}", file_path));
                 return Err(GruxiError::new_with_kind_only(GruxiErrorKind::StaticFileProcessor(StaticFileProcessorError::FileNotFound)));
@@ -232,25 +294,70 @@ impl ProcessorTrait for StaticFileProcessor {
             ))));
         }
 
-        // Get a stream of the file content, based on the accept-encoding header
-        let (stream, compression) = file_data.get_content_stream(gruxi_request).await;
+        // With path confinement enabled, symlinks must also resolve within the paths the site allows
+        if !site.is_path_confined(&web_root, &file_path).await {
+            debug(format!("File path is blocked by path confinement for site '{}': {}", site.id, file_path));
+            return Err(GruxiError::new_with_kind_only(GruxiErrorKind::StaticFileProcessor(StaticFileProcessorError::FileBlockedDueToSecurity(
+                file_path,
+            ))));
+        }
+
+        // Files that are not cached need a file handle, which may mean waiting for one when many files are being served
+        let mut file_handle_permit = None;
+        if file_data.needs_file_handle() {
+            file_handle_permit = get_file_handle_limiter().acquire(&site.id).await;
+            if file_handle_permit.is_none() {
+                debug(format!("Timed out waiting for a file handle to serve {} for site '{}'", file_path, site.id));
+                let mut response = GruxiResponse::new_empty_with_status(hyper::StatusCode::SERVICE_UNAVAILABLE.as_u16());
+                response.headers_mut().insert(hyper::header::RETRY_AFTER, HeaderValue::from(FILE_HANDLE_WAIT_TIMEOUT.as_secs()));
+                return Ok(response);
+            }
+        }
+
+        // Get a stream of the file content, based on the accept-encoding header. Minified files are compressed by the compression middleware
+        let minified_content = if site.minification_enabled { get_minified_file_cache().get(&file_data).await } else { None };
+        let (stream, compression) = match minified_content {
+            Some(content) => (BoxBody::new(Full::new(content).map_err(|never| -> BodyError { match never {} })), String::new()),
+            None => file_data.get_content_stream(gruxi_request, file_handle_permit).await,
+        };
 
+        // Large files are streamed with unknown length, so we give the length from the file metadata when serving the raw content
+        let unknown_stream_length = stream.size_hint().exact().is_none();
         let mut response = GruxiResponse::new_with_body(hyper::StatusCode::OK.as_u16(), stream);
+        if unknown_stream_length && compression.is_empty() {
+            response.headers_mut().insert(hyper::header::CONTENT_LENGTH, HeaderValue::from(file_data.meta.length));
+        }
 
-        // Set content type
-        let header_value = HeaderValue::from_str(&file_data.meta.mime_type);
+        // Set content type, which for language variants comes from the requested file, as the variant extension is the language
+        let mime_type = language_variant.as_ref().map(|(_, mime_type)| mime_type.as_str()).unwrap_or(&file_data.meta.mime_type);
+        let header_value = HeaderValue::from_str(mime_type);
         match header_value {
             Err(e) => {
-                error(format!(
-                    "Failed to set content type header for file: {} with mime type: {}. Error: {}",
-                    file_path, file_data.meta.mime_type, e
-                ));
+                error(format!("Failed to set content type header for file: {} with mime type: {}. Error: {}", file_path, mime_type, e));
             }
             Ok(value) => {
                 response.headers_mut().insert(hyper::header::CONTENT_TYPE, value);
             }
         }
 
+        // Caches must keep the variants apart, so the response varies on the language as well as the encoding
+        if let Some((language, _)) = &language_variant {
+            if let Ok(value) = HeaderValue::from_str(language) {
+                response.headers_mut().insert(hyper::header::CONTENT_LANGUAGE, value);
+            }
+            add_vary_field(&mut response, "Accept-Encoding");
+            add_vary_field(&mut response, "Accept-Language");
+        }
+
+        // Browsers can start loading the stylesheets and scripts of the page before they have parsed it
+        if site.preload_links_enabled {
+            for link in get_preload_link_cache().get(&file_data).await {
+                if !response.headers().get_all(hyper::header::LINK).iter().any(|existing| existing == link) {
+                    response.headers_mut().append(hyper::header::LINK, link);
+                }
+            }
+        }
+
         // Set content encoding if gzipped
         if compression == "gzip" {
             let header_value = HeaderValue::from_str("gzip");
@@ -1,10 +1,22 @@
-use std::{collections::HashMap, sync::Arc};
+use hyper::header::HeaderValue;
+use std::{collections::HashMap, sync::Arc, time::Instant};
 use tokio::sync::RwLock;
 
 use crate::{
-    configuration::{request_handler::RequestHandler, site::Site},
+    configuration::{
+        request_handler::{RequestHandler, STATIC_ALLOWED_METHODS},
+        site::Site,
+    },
+    deployment::site_builder::get_site_builder,
     error::gruxi_error::GruxiError,
-    http::request_response::{gruxi_request::GruxiRequest, gruxi_response::GruxiResponse},
+    http::{
+        request_handlers::processors::processor_manager::ProcessorManager,
+        request_response::{
+            gruxi_request::GruxiRequest,
+            gruxi_response::GruxiResponse,
+            request_timings::{RequestPhase, get_current_request_timings},
+        },
+    },
     logging::syslog::trace,
 };
 
@@ -36,6 +48,19 @@ impl RequestHandlerManager {
 
     pub async fn handle_request(&self, gruxi_request: &mut GruxiRequest, site: &Site) -> Result<GruxiResponse, GruxiError> {
         let request_handler_read_lock = self.request_handlers.read().await;
+        let http_method = gruxi_request.get_http_method();
+
+        // Handlers match on the canonical path, so "/app/../admin/" and "/admin%2f" are routed like "/admin/"
+        let canonical_path = match gruxi_request.get_canonical_path() {
+            Some(canonical_path) => canonical_path,
+            None => {
+                trace(format!("Request path '{}' cannot be canonicalized, answering 404", &gruxi_request.get_path()));
+                return Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16()));
+            }
+        };
+
+        // Methods allowed by the matching handlers that did not accept this method, used for the Allow header on OPTIONS and 405
+        let mut allowed_methods: Vec<String> = Vec::new();
 
         for request_handler_id in site.request_handlers.iter() {
             if let Some(handler) = request_handler_read_lock.get(request_handler_id) {
@@ -45,19 +70,106 @@ impl RequestHandlerManager {
                 }
 
                 // Check that it matches
-                if handler.matches_url(&gruxi_request.get_path_and_query()) {
+                if handler.matches_url(&canonical_path) {
+                    // OPTIONS is answered by us, unless the handler passes it on to its backend
+                    let handles_method = if http_method == "OPTIONS" {
+                        handler.forwards_options_requests()
+                    } else {
+                        handler.is_method_allowed(&http_method)
+                    };
+                    if !handles_method {
+                        add_allowed_methods(&mut allowed_methods, handler);
+                        continue;
+                    }
+
                     // We call the handle request. If we get an error, we continue to the next one
+                    let request_timings = get_current_request_timings();
+                    if let Some(request_timings) = &request_timings {
+                        request_timings.mark_routed();
+                    }
+                    let handler_started = Instant::now();
                     let response_result = handler.handle_request(gruxi_request, site).await;
-                    if response_result.is_err() {
+                    if let Some(request_timings) = &request_timings
+                        && matches!(handler.processor_type.as_str(), "php" | "proxy")
+                    {
+                        request_timings.add(RequestPhase::BackendTotal, handler_started.elapsed());
+                    }
+                    let mut response = match response_result {
+                        Ok(response) => response,
                         // Some of the errors are not critical, so we just log and continue
-                        continue;
+                        Err(_) => continue,
+                    };
+
+                    // Backends answering OPTIONS do not always tell what they allow, so we add it if missing
+                    if http_method == "OPTIONS" && !response.headers().contains_key(hyper::header::ALLOW) {
+                        set_allow_header(&mut response, &handler.get_allowed_methods());
                     }
-                    return response_result;
+                    return Ok(response);
                 }
             }
         }
 
+        if !allowed_methods.is_empty() {
+            let status = if http_method == "OPTIONS" { hyper::StatusCode::OK } else { hyper::StatusCode::METHOD_NOT_ALLOWED };
+            trace(format!(
+                "Answering {} for method '{}' on request path '{}'",
+                status.as_u16(),
+                &http_method,
+                &gruxi_request.get_path_and_query()
+            ));
+            let mut response = GruxiResponse::new_empty_with_status(status.as_u16());
+            set_allow_header(&mut response, &allowed_methods);
+            return Ok(response);
+        }
+
         trace(format!("No request handler found for request path '{}'", &gruxi_request.get_path_and_query()));
         Ok(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16()))
     }
+
+    // Local web roots of the site's enabled request handlers, in the order the handlers are tried
+    pub async fn get_site_web_roots(&self, site: &Site, processor_manager: &ProcessorManager) -> Vec<String> {
+        let request_handler_read_lock = self.request_handlers.read().await;
+        let mut web_roots: Vec<String> = Vec::new();
+        let active_build_web_root = get_site_builder().get_active_web_root(site);
+        for handler in site.request_handlers.iter().filter_map(|id| request_handler_read_lock.get(id)).filter(|h| h.is_enabled) {
+            // Sites deployed by Gruxi are served from the current release instead
+            let local_web_root = match (&active_build_web_root, handler.processor_type.as_str()) {
+                (Some(active_build_web_root), "static" | "php") => Some(active_build_web_root.clone()),
+                _ => handler.get_local_web_root(processor_manager),
+            };
+            if let Some(web_root) = local_web_root
+                && !web_roots.contains(&web_root)
+            {
+                web_roots.push(web_root);
+            }
+        }
+        web_roots
+    }
+
+    // Methods allowed across all enabled request handlers, used to answer "OPTIONS *" for the server as a whole
+    pub async fn get_server_allowed_methods(&self) -> Vec<String> {
+        let request_handler_read_lock = self.request_handlers.read().await;
+        let mut allowed_methods: Vec<String> = Vec::new();
+        for handler in request_handler_read_lock.values().filter(|h| h.is_enabled) {
+            add_allowed_methods(&mut allowed_methods, handler);
+        }
+        if allowed_methods.is_empty() {
+            allowed_methods = STATIC_ALLOWED_METHODS.iter().map(|m| m.to_string()).collect();
+        }
+        allowed_methods
+    }
+}
+
+fn add_allowed_methods(allowed_methods: &mut Vec<String>, handler: &RequestHandler) {
+    for method in handler.get_allowed_methods() {
+        if !allowed_methods.contains(&method) {
+            allowed_methods.push(method);
+        }
+    }
+}
+
+pub fn set_allow_header(response: &mut GruxiResponse, allowed_methods: &[String]) {
+    if let Ok(header_value) = HeaderValue::from_str(&allowed_methods.join(", ")) {
+        response.headers_mut().insert(hyper::header::ALLOW, header_value);
+    }
 }
@@ -1,7 +1,9 @@
 use http::HeaderValue;
-use http::header::HOST;
+use http::header::{CONTENT_LENGTH, HOST, TRANSFER_ENCODING};
 use http::request::Parts;
 use http_body_util::BodyExt;
+use http_body_util::Collected;
+use http_body_util::Full;
 use http_body_util::combinators::BoxBody;
 use hyper::HeaderMap;
 use hyper::Request;
@@ -10,9 +12,17 @@ use hyper::body::Bytes;
 use std::collections::HashMap;
 use std::mem;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Semaphore;
 
+use crate::core::memory_budget::MemoryReservation;
+use crate::error::gruxi_error::GruxiError;
+use crate::error::gruxi_error_enums::GruxiErrorKind;
+use crate::http::request_response::body_error::{BodyError, box_err};
 use crate::http::request_response::gruxi_body::GruxiBody;
+use crate::http::request_response::limited_body::{BodyLimit, LimitedBody};
+use crate::http::request_response::request_timings::{RequestPhase, record_request_phase};
+use crate::http::url_canonicalization::canonicalize_url_path;
 
 // Wrapper around hyper Request to add calculated data and serve as a request in Gruxi
 #[derive(Debug)]
@@ -26,6 +36,10 @@ pub struct GruxiRequest {
     pub connection_semaphore: Option<Arc<Semaphore>>,
     // Upgrade future for handling protocol upgrades
     upgrade_future: Option<hyper::upgrade::OnUpgrade>,
+    // Trailer fields received after a chunked request body, once the body has been read
+    trailers: HeaderMap,
+    // The limit the body is read under, once limit_body has been called
+    body_limit: Option<Arc<BodyLimit>>,
 }
 
 impl GruxiRequest {
@@ -46,6 +60,8 @@ impl GruxiRequest {
             calculated_data,
             connection_semaphore: None,
             upgrade_future,
+            trailers: HeaderMap::new(),
+            body_limit: None,
         }
     }
 
@@ -68,6 +84,8 @@ impl GruxiRequest {
             calculated_data,
             connection_semaphore: None,
             upgrade_future,
+            trailers: HeaderMap::new(),
+            body_limit: None,
         }
     }
 
@@ -111,9 +129,13 @@ impl GruxiRequest {
             hostname = authority.as_str().to_string();
         }
 
-        // Remove any ports if present
-        if let Some(colon_index) = hostname.find(':') {
-            hostname = hostname[..colon_index].to_string();
+        // Remove any ports if present, keeping IPv6 addresses such as [::1] whole
+        if hostname.starts_with('[') {
+            if let Some(end_index) = hostname.find(']') {
+                hostname.truncate(end_index + 1);
+            }
+        } else if let Some(colon_index) = hostname.find(':') {
+            hostname.truncate(colon_index);
         }
 
         self.add_calculated_data("hostname", &hostname);
@@ -180,6 +202,16 @@ impl GruxiRequest {
         path
     }
 
+    // The decoded and canonicalized path, see canonicalize_url_path(). None if the path is invalid or tries to climb above the root
+    pub fn get_canonical_path(&mut self) -> Option<String> {
+        if let Some(canonical_path) = self.calculated_data.get("canonical_path") {
+            return Some(canonical_path.to_string());
+        }
+        let canonical_path = canonicalize_url_path(self.parts.uri.path()).ok()?;
+        self.add_calculated_data("canonical_path", &canonical_path);
+        Some(canonical_path)
+    }
+
     pub fn get_query(&mut self) -> String {
         if let Some(query) = self.calculated_data.get("query") {
             return query.to_string();
@@ -201,6 +233,41 @@ impl GruxiRequest {
         path_and_query
     }
 
+    // Internally rewrites the request to another path and query, keeping scheme and authority. The URI the client sent is kept
+    // as "original_uri", so backends can still be told what was requested. Returns false if the new path and query is invalid
+    pub fn rewrite_uri(&mut self, new_path_and_query: &str) -> bool {
+        let path_and_query = match new_path_and_query.parse::<http::uri::PathAndQuery>() {
+            Ok(path_and_query) => path_and_query,
+            Err(_) => return false,
+        };
+        let mut uri_parts = self.parts.uri.clone().into_parts();
+        uri_parts.path_and_query = Some(path_and_query);
+        let new_uri = match http::Uri::from_parts(uri_parts) {
+            Ok(uri) => uri,
+            Err(_) => return false,
+        };
+
+        if !self.calculated_data.contains_key("original_uri") {
+            let original_uri = self.get_path_and_query();
+            self.add_calculated_data("original_uri", &original_uri);
+        }
+        self.parts.uri = new_uri;
+        for key in ["uri", "path", "canonical_path", "query", "path_and_query"] {
+            self.calculated_data.remove(key);
+        }
+        true
+    }
+
+    // Unique ID for this request, generated on first use
+    pub fn get_request_id(&mut self) -> String {
+        if let Some(request_id) = self.calculated_data.get("request_id") {
+            return request_id.to_string();
+        }
+        let request_id = uuid::Uuid::new_v4().to_string();
+        self.add_calculated_data("request_id", &request_id);
+        request_id
+    }
+
     pub fn get_remote_ip(&mut self) -> String {
         if let Some(remote_ip) = self.calculated_data.get("remote_ip") {
             return remote_ip.to_string();
@@ -213,35 +280,149 @@ impl GruxiRequest {
         match &mut self.body {
             GruxiBody::Buffered(bytes) => bytes.clone(),
             GruxiBody::Streaming(incoming_body) => {
+                let read_started = Instant::now();
                 let body = incoming_body.collect().await;
+                record_request_phase(RequestPhase::ReadRequest, read_started.elapsed());
                 match body {
-                    Ok(bytes) => bytes.to_bytes(),
+                    Ok(collected) => self.keep_trailers_and_get_bytes(collected),
                     Err(_) => Bytes::new(),
                 }
             }
             GruxiBody::StreamingBoxed(boxed_body) => {
+                let read_started = Instant::now();
                 let body = boxed_body.collect().await;
+                record_request_phase(RequestPhase::ReadRequest, read_started.elapsed());
                 match body {
-                    Ok(bytes) => bytes.to_bytes(),
+                    Ok(collected) => self.keep_trailers_and_get_bytes(collected),
                     Err(_) => Bytes::new(),
                 }
             }
         }
     }
 
-    pub fn get_streaming_http_request(&mut self) -> Result<Request<BoxBody<Bytes, hyper::Error>>, ()> {
-        match mem::replace(&mut self.body, GruxiBody::Buffered(Bytes::new())) {
-            GruxiBody::Streaming(incoming_body) => {
-                let request = Request::from_parts(self.parts.clone(), incoming_body.boxed());
-                Ok(request)
-            }
-            other => {
-                self.body = other;
-                Err(())
+    // Reads the next chunk of body data, for handlers that process the body as it arrives. Returns None when the body is done
+    pub async fn read_body_chunk(&mut self) -> Option<Result<Bytes, String>> {
+        loop {
+            let read_started = Instant::now();
+            let frame_result = match &mut self.body {
+                GruxiBody::Buffered(bytes) => {
+                    let bytes = mem::take(bytes);
+                    return if bytes.is_empty() { None } else { Some(Ok(bytes)) };
+                }
+                GruxiBody::Streaming(incoming_body) => incoming_body.frame().await?.map_err(|e| e.to_string()),
+                GruxiBody::StreamingBoxed(boxed_body) => boxed_body.frame().await?.map_err(|e| e.to_string()),
+            };
+
+            record_request_phase(RequestPhase::ReadRequest, read_started.elapsed());
+            match frame_result {
+                // Trailer frames carry no body data, so we keep their fields and read on
+                Ok(frame) => match frame.into_data() {
+                    Ok(data) => return Some(Ok(data)),
+                    Err(frame) => {
+                        if let Ok(trailers) = frame.into_trailers() {
+                            self.trailers.extend(trailers);
+                        }
+                    }
+                },
+                Err(e) => return Some(Err(e)),
             }
         }
     }
 
+    // Reads what is left of a body of unknown size without keeping it, so reading it runs into its limit even when no handler wanted the body
+    pub async fn discard_unread_body(&mut self) {
+        if self.is_body_size_known() {
+            return;
+        }
+        while let Some(Ok(_)) = self.read_body_chunk().await {}
+    }
+
+    // Reads the full body into memory and keeps it in the request, so it can still be used by the request handlers
+    pub async fn buffer_body(&mut self) -> Bytes {
+        let bytes = self.get_body_bytes().await;
+        self.body = GruxiBody::Buffered(bytes.clone());
+        self.set_read_body_size(bytes.len() as u64);
+        bytes
+    }
+
+    // For handlers that have read the body in full, such as to hand it to a backend as a whole. From then on the body is described by its
+    // length, so a chunked body gets a plain Content-Length instead of its Transfer-Encoding
+    pub fn set_read_body_size(&mut self, body_size: u64) {
+        if self.is_chunked() {
+            self.parts.headers.remove(TRANSFER_ENCODING);
+        }
+        if self.parts.headers.contains_key(CONTENT_LENGTH) || body_size > 0 {
+            self.parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(body_size));
+        }
+        self.calculated_data.insert("body_size_hint".to_string(), body_size.to_string());
+    }
+
+    fn keep_trailers_and_get_bytes(&mut self, collected: Collected<Bytes>) -> Bytes {
+        if let Some(trailers) = collected.trailers() {
+            self.trailers.extend(trailers.clone());
+        }
+        collected.to_bytes()
+    }
+
+    // True for HTTP/1.1 requests where chunked is the final transfer coding
+    pub fn is_chunked(&self) -> bool {
+        if self.parts.version != hyper::Version::HTTP_11 {
+            return false;
+        }
+        self.parts
+            .headers
+            .get_all(TRANSFER_ENCODING)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|coding| coding.trim())
+            .rfind(|coding| !coding.is_empty())
+            .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+    }
+
+    pub fn get_trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    pub fn get_streaming_http_request(&mut self) -> Request<BoxBody<Bytes, BodyError>> {
+        let body = match mem::replace(&mut self.body, GruxiBody::Buffered(Bytes::new())) {
+            GruxiBody::Streaming(incoming_body) => incoming_body.map_err(box_err).boxed(),
+            GruxiBody::StreamingBoxed(boxed_body) => boxed_body,
+            // Body has already been read into memory (such as for debug capture), so we send it as is
+            GruxiBody::Buffered(bytes) => Full::new(bytes).map_err(|never| match never {}).boxed(),
+        };
+        Request::from_parts(self.parts.clone(), body)
+    }
+
+    // Puts the body under the max body size (0 for no limit), counting the data as it is actually read, whoever reads it, and growing the
+    // memory reservation for the body along with it. Reading fails once the body goes over either, and get_body_rejection tells why
+    pub fn limit_body(&mut self, max_body_size: u64, body_reservation: MemoryReservation<'static>) {
+        let body_limit = Arc::new(BodyLimit::new(max_body_size, body_reservation));
+        self.body = match mem::replace(&mut self.body, GruxiBody::Buffered(Bytes::new())) {
+            GruxiBody::Streaming(incoming_body) => GruxiBody::StreamingBoxed(LimitedBody::new(incoming_body, body_limit.clone()).boxed()),
+            other => other,
+        };
+        self.body_limit = Some(body_limit);
+    }
+
+    // The error to answer the request with when reading its body was cut off by limit_body
+    pub fn get_body_rejection(&self) -> Option<GruxiError> {
+        let status = self.body_limit.as_ref()?.get_rejection_status()?;
+        Some(GruxiError::new(
+            GruxiErrorKind::HttpRequestValidation(status),
+            format!("Request body rejected with status {} while it was read", status),
+        ))
+    }
+
+    // Whether get_body_size is the actual size of the body, which it is not for chunked bodies that have not been read yet
+    pub fn is_body_size_known(&self) -> bool {
+        match &self.body {
+            GruxiBody::Buffered(_) => true,
+            GruxiBody::Streaming(incoming) => incoming.size_hint().exact().is_some(),
+            GruxiBody::StreamingBoxed(boxed) => boxed.size_hint().exact().is_some(),
+        }
+    }
+
     pub fn get_body_size(&mut self) -> u64 {
         if let Some(body_size_hint) = self.calculated_data.get("body_size_hint") {
             return body_size_hint.parse().unwrap_or(0);
@@ -349,6 +530,20 @@ impl GruxiRequest {
         self.parts.headers.insert("X-Forwarded-Host", HeaderValue::from_str(&hostname).unwrap_or(HeaderValue::from_static("")));
     }
 
+    // Gets the value of a cookie. HTTP/2 clients may split cookies over several Cookie headers, so all of them are searched
+    pub fn get_cookie(&self, name: &str) -> Option<String> {
+        self.parts
+            .headers
+            .get_all(hyper::header::COOKIE)
+            .iter()
+            .filter_map(|header_value| header_value.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .find_map(|cookie| {
+                let (cookie_name, value) = cookie.trim().split_once('=')?;
+                if cookie_name == name { Some(value.trim_matches('"').to_string()) } else { None }
+            })
+    }
+
     pub fn get_accepted_encodings(&self) -> Vec<String> {
         if let Some(accept_encoding_header) = self.parts.headers.get("Accept-Encoding") {
             if let Ok(accept_encoding_str) = accept_encoding_header.to_str() {
@@ -1,6 +1,8 @@
-use crate::http::request_response::gruxi_body::GruxiBody;
 use crate::http::request_response::body_error::{BodyError, box_err};
-use crate::logging::syslog::{error};
+use crate::http::request_response::gruxi_body::GruxiBody;
+use crate::logging::syslog::error;
+use http::HeaderValue;
+use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
 use http::response::Parts;
 use http_body_util::BodyExt;
 use http_body_util::Full;
@@ -30,7 +32,6 @@ impl GruxiResponse {
                 let mut response = Response::new(Bytes::new());
                 *response.status_mut() = http::StatusCode::INTERNAL_SERVER_ERROR;
                 response
-
             }
         };
 
@@ -47,13 +48,16 @@ impl GruxiResponse {
 
     pub fn new_with_bytes<T: Into<Bytes>>(status_code: u16, body_bytes: T) -> Self {
         let mut response = GruxiResponse::new_empty_with_status(status_code);
-        response.body = GruxiBody::Buffered(body_bytes.into());
+        response.set_body(GruxiBody::Buffered(body_bytes.into()));
         response
     }
 
     pub fn new_with_body(status_code: u16, body: BoxBody<hyper::body::Bytes, BodyError>) -> Self {
         let mut response = GruxiResponse::new_empty_with_status(status_code);
+        // Bodies with a known length, such as content served from the file cache, can be considered for compression
+        let body_size_hint = body.size_hint().exact().unwrap_or(0);
         response.body = GruxiBody::StreamingBoxed(body);
+        response.calculated_data.insert("body_size_hint".to_string(), body_size_hint.to_string());
         response
     }
 
@@ -110,6 +114,15 @@ impl GruxiResponse {
         0
     }
 
+    pub fn is_body_buffered(&self) -> bool {
+        matches!(self.body, GruxiBody::Buffered(_))
+    }
+
+    // Only true when we know the body is empty, streaming bodies are never considered empty
+    pub fn is_body_empty(&self) -> bool {
+        matches!(&self.body, GruxiBody::Buffered(bytes) if bytes.is_empty())
+    }
+
     pub fn get_status(&self) -> u16 {
         self.parts.status.as_u16()
     }
@@ -135,12 +148,17 @@ impl GruxiResponse {
         }
     }
 
+    // Reads the full body into memory and keeps it in the response
+    pub async fn buffer_body(&mut self) -> Bytes {
+        let bytes = self.get_body_bytes().await;
+        self.set_body(GruxiBody::Buffered(bytes.clone()));
+        bytes
+    }
+
     // Convert GruxiResponse back into a hyper Response
     pub fn into_hyper(self) -> Response<BoxBody<Bytes, BodyError>> {
         let body: BoxBody<Bytes, BodyError> = match self.body {
-            GruxiBody::Buffered(bytes) => BoxBody::new(
-                Full::new(bytes).map_err(|never| -> BodyError { match never {} }),
-            ),
+            GruxiBody::Buffered(bytes) => BoxBody::new(Full::new(bytes).map_err(|never| -> BodyError { match never {} })),
             GruxiBody::Streaming(incoming) => BoxBody::new(incoming.map_err(box_err)),
             GruxiBody::StreamingBoxed(boxed_body) => boxed_body,
         };
@@ -149,6 +167,46 @@ impl GruxiResponse {
         response
     }
 
+    // Makes body and Content-Length consistent with the request method and status, as the last step before the response is sent
+    pub fn finalize_for_request_method(&mut self, http_method: &str) {
+        let status = self.get_status();
+
+        // 1xx, including 101 Switching Protocols, 204 and 304 responses never have a body, so no Content-Length is added to them
+        if (100..200).contains(&status) || status == 204 || status == 304 {
+            self.set_body(GruxiBody::Buffered(Bytes::new()));
+            self.parts.headers.remove(TRANSFER_ENCODING);
+            // A 304 may carry the Content-Length of the full representation, so we keep one given by a backend
+            if status != 304 {
+                self.parts.headers.remove(CONTENT_LENGTH);
+            }
+            return;
+        }
+
+        let known_length = match &self.body {
+            GruxiBody::Buffered(bytes) => Some(bytes.len() as u64),
+            GruxiBody::Streaming(incoming) => incoming.size_hint().exact(),
+            GruxiBody::StreamingBoxed(boxed_body) => boxed_body.size_hint().exact(),
+        };
+
+        if http_method == "HEAD" {
+            // HEAD carries the Content-Length a GET would have had, so a length given by a backend is kept as is
+            if !self.parts.headers.contains_key(CONTENT_LENGTH)
+                && let Some(length) = known_length.filter(|length| *length > 0)
+            {
+                self.parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+            }
+            self.set_body(GruxiBody::Buffered(Bytes::new()));
+        } else if let Some(length) = known_length {
+            // The body may have been changed after the Content-Length was set, such as by compression
+            self.parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        }
+    }
+
+    // Takes the body out of the response, to be replaced with set_body
+    pub fn take_body(&mut self) -> GruxiBody {
+        std::mem::replace(&mut self.body, GruxiBody::Buffered(Bytes::new()))
+    }
+
     pub fn set_body(&mut self, body: GruxiBody) {
         self.body = body;
         let length = match &self.body {
@@ -159,3 +217,67 @@ impl GruxiResponse {
         self.calculated_data.insert("body_size_hint".to_string(), length.to_string());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finalize_head_keeps_content_length_without_body() {
+        let mut response = GruxiResponse::new_with_bytes(200, "hello world");
+        response.finalize_for_request_method("HEAD");
+        assert_eq!(response.get_header("Content-Length").unwrap(), "11");
+        assert!(response.is_body_empty());
+    }
+
+    #[test]
+    fn test_finalize_head_keeps_backend_content_length() {
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(1234u64));
+        response.finalize_for_request_method("HEAD");
+        assert_eq!(response.get_header("Content-Length").unwrap(), "1234");
+    }
+
+    #[test]
+    fn test_finalize_get_corrects_stale_content_length() {
+        let mut response = GruxiResponse::new_with_bytes(200, "abc");
+        response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(1000u64));
+        response.finalize_for_request_method("GET");
+        assert_eq!(response.get_header("Content-Length").unwrap(), "3");
+        assert!(!response.is_body_empty());
+    }
+
+    #[test]
+    fn test_finalize_strips_body_for_204_and_304() {
+        let mut response = GruxiResponse::new_with_bytes(204, "should not be sent");
+        response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(18u64));
+        response.finalize_for_request_method("GET");
+        assert!(response.is_body_empty());
+        assert!(response.get_header("Content-Length").is_none());
+
+        let mut response = GruxiResponse::new_with_bytes(304, "should not be sent");
+        response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(500u64));
+        response.finalize_for_request_method("GET");
+        assert!(response.is_body_empty());
+        assert_eq!(response.get_header("Content-Length").unwrap(), "500");
+    }
+
+    #[test]
+    fn test_finalize_adds_no_content_length_to_1xx_and_304() {
+        for status in [100, 101, 103] {
+            let mut response = GruxiResponse::new_with_bytes(status, "should not be sent");
+            response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(18u64));
+            response.headers_mut().insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+            response.finalize_for_request_method("GET");
+            assert!(response.is_body_empty());
+            assert!(response.get_header("Content-Length").is_none(), "status {}", status);
+            assert!(response.get_header("Transfer-Encoding").is_none(), "status {}", status);
+        }
+
+        for http_method in ["GET", "HEAD"] {
+            let mut response = GruxiResponse::new_empty_with_status(304);
+            response.finalize_for_request_method(http_method);
+            assert!(response.get_header("Content-Length").is_none(), "{}", http_method);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use hyper::body::{Body, Bytes, Frame, Incoming, SizeHint};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+
+use crate::core::memory_budget::MemoryReservation;
+use crate::http::request_response::body_error::{BodyError, box_err};
+
+// The limit of a request body, shared by the request and its body, so the request knows why its body was cut off
+// once the handlers are done with it, whoever read it
+pub struct BodyLimit {
+    // 0 for no limit
+    max_body_size: u64,
+    read_bytes: AtomicU64,
+    // The status to answer the request with when the body was cut off, 0 while it was not
+    rejection_status: AtomicU16,
+    // Reserved up front for the known size of the body, the reservation only grows for the data read beyond it
+    reserved_bytes: u64,
+    body_reservation: Mutex<MemoryReservation<'static>>,
+}
+
+impl std::fmt::Debug for BodyLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BodyLimit(max_body_size={}, read_bytes={})", self.max_body_size, self.read_bytes.load(Ordering::Relaxed))
+    }
+}
+
+impl BodyLimit {
+    pub fn new(max_body_size: u64, body_reservation: MemoryReservation<'static>) -> Self {
+        BodyLimit {
+            max_body_size,
+            read_bytes: AtomicU64::new(0),
+            rejection_status: AtomicU16::new(0),
+            reserved_bytes: body_reservation.get_bytes(),
+            body_reservation: Mutex::new(body_reservation),
+        }
+    }
+
+    pub fn get_rejection_status(&self) -> Option<u16> {
+        match self.rejection_status.load(Ordering::Relaxed) {
+            0 => None,
+            status => Some(status),
+        }
+    }
+
+    // Counts the data read from the body, failing once there is more of it than the max body size or than fits in the memory budget
+    fn count_read_bytes(&self, bytes: u64) -> Result<(), BodyError> {
+        let previous_read_bytes = self.read_bytes.fetch_add(bytes, Ordering::Relaxed);
+        let read_bytes = previous_read_bytes.saturating_add(bytes);
+        if self.max_body_size > 0 && read_bytes > self.max_body_size {
+            self.reject(hyper::StatusCode::PAYLOAD_TOO_LARGE);
+            return Err(box_err(std::io::Error::other(format!("Request body exceeds the max body size of {} bytes", self.max_body_size))));
+        }
+
+        let unreserved_bytes = read_bytes.saturating_sub(previous_read_bytes.max(self.reserved_bytes));
+        if unreserved_bytes > 0 {
+            let has_grown = match self.body_reservation.lock() {
+                Ok(mut body_reservation) => body_reservation.try_grow(unreserved_bytes),
+                Err(_) => false,
+            };
+            if !has_grown {
+                self.reject(hyper::StatusCode::SERVICE_UNAVAILABLE);
+                return Err(box_err(std::io::Error::other(format!(
+                    "Memory budget exhausted while reading a request body of {} bytes so far",
+                    previous_read_bytes
+                ))));
+            }
+        }
+        Ok(())
+    }
+
+    fn reject(&self, status: hyper::StatusCode) {
+        // The first reason the body was cut off is the one the request is answered with
+        let _ = self.rejection_status.compare_exchange(0, status.as_u16(), Ordering::Relaxed, Ordering::Relaxed);
+    }
+}
+
+// A streaming request body that counts the data actually read against its limit, as the Content-Length is not always there to
+// check up front, such as for HTTP/2 requests without it. Reading fails as soon as the limit is passed, instead of after the whole body
+pub struct LimitedBody {
+    inner: Incoming,
+    limit: Arc<BodyLimit>,
+}
+
+impl LimitedBody {
+    pub fn new(inner: Incoming, limit: Arc<BodyLimit>) -> Self {
+        LimitedBody { inner, limit }
+    }
+}
+
+impl Body for LimitedBody {
+    type Data = Bytes;
+    type Error = BodyError;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = match ready!(Pin::new(&mut self.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            // Such as malformed chunks of a chunked body
+            Some(Err(e)) => {
+                self.limit.reject(hyper::StatusCode::BAD_REQUEST);
+                return Poll::Ready(Some(Err(box_err(e))));
+            }
+            None => return Poll::Ready(None),
+        };
+
+        if let Some(data) = frame.data_ref()
+            && let Err(e) = self.limit.count_read_bytes(data.len() as u64)
+        {
+            return Poll::Ready(Some(Err(e)));
+        }
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::memory_budget::{MEMORY_BUDGET_SMALL_BODY_BYTES, MemoryBudget};
+
+    fn get_test_reservation(max_bytes: u64, bytes: u64) -> (&'static MemoryBudget, MemoryReservation<'static>) {
+        let memory_budget: &'static MemoryBudget = Box::leak(Box::new(MemoryBudget::new(max_bytes)));
+        (memory_budget, memory_budget.try_reserve(bytes).unwrap())
+    }
+
+    #[test]
+    fn test_body_limit_rejects_once_passed() {
+        let (_, body_reservation) = get_test_reservation(0, 0);
+        let limit = BodyLimit::new(10, body_reservation);
+        assert!(limit.count_read_bytes(6).is_ok());
+        assert!(limit.count_read_bytes(4).is_ok());
+        assert_eq!(limit.get_rejection_status(), None);
+        assert!(limit.count_read_bytes(1).is_err());
+        assert_eq!(limit.get_rejection_status(), Some(413));
+    }
+
+    #[test]
+    fn test_body_limit_without_max_body_size() {
+        let (_, body_reservation) = get_test_reservation(0, 0);
+        let limit = BodyLimit::new(0, body_reservation);
+        assert!(limit.count_read_bytes(1 << 40).is_ok());
+        assert!(limit.count_read_bytes(1).is_ok());
+        assert_eq!(limit.get_rejection_status(), None);
+    }
+
+    #[test]
+    fn test_body_limit_grows_the_reservation_as_the_body_is_read() {
+        let (memory_budget, body_reservation) = get_test_reservation(2 * MEMORY_BUDGET_SMALL_BODY_BYTES, 0);
+        let limit = BodyLimit::new(0, body_reservation);
+        assert!(limit.count_read_bytes(MEMORY_BUDGET_SMALL_BODY_BYTES).is_ok());
+        assert_eq!(memory_budget.get_used_bytes(), MEMORY_BUDGET_SMALL_BODY_BYTES);
+        assert!(limit.count_read_bytes(MEMORY_BUDGET_SMALL_BODY_BYTES).is_ok());
+        assert_eq!(memory_budget.get_used_bytes(), 2 * MEMORY_BUDGET_SMALL_BODY_BYTES);
+
+        assert!(limit.count_read_bytes(1).is_err());
+        assert_eq!(limit.get_rejection_status(), Some(503));
+
+        drop(limit);
+        assert_eq!(memory_budget.get_used_bytes(), 0);
+    }
+
+    #[test]
+    fn test_body_limit_does_not_grow_the_reservation_for_a_known_size() {
+        let (memory_budget, body_reservation) = get_test_reservation(0, 1000);
+        let limit = BodyLimit::new(0, body_reservation);
+        assert!(limit.count_read_bytes(600).is_ok());
+        assert!(limit.count_read_bytes(400).is_ok());
+        assert_eq!(memory_budget.get_used_bytes(), 1000);
+    }
+}
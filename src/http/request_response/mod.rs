@@ -1,4 +1,7 @@
+pub mod body_error;
 pub mod gruxi_body;
 pub mod gruxi_request;
 pub mod gruxi_response;
-pub mod body_error;
+pub mod limited_body;
+
+pub mod request_timings;
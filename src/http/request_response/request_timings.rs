@@ -0,0 +1,316 @@
+use crate::http::request_response::body_error::BodyError;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    // Timings of the request being handled by the current task, so the phases can be recorded wherever they happen
+    pub static CURRENT_REQUEST_TIMINGS: Arc<RequestTimings>;
+}
+
+// Marks a phase that was not part of the request, such as connecting to a backend for a static file
+const NOT_MEASURED: u64 = u64::MAX;
+
+// The phases of a request, in the order they happen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestPhase {
+    // Reading the request body from the client
+    ReadRequest,
+    // From the request arriving until it is handed to the request handler, without reading the body
+    Routing,
+    // Connecting to the backend (PHP-FPM or the proxied upstream)
+    BackendConnect,
+    // From the request being sent to the backend until the first byte of its response
+    BackendFirstByte,
+    // The whole time spent in the backend request handler
+    BackendTotal,
+    // Sending the response body to the client
+    ClientWrite,
+}
+
+impl RequestPhase {
+    pub const ALL: [RequestPhase; 6] = [
+        RequestPhase::ReadRequest,
+        RequestPhase::Routing,
+        RequestPhase::BackendConnect,
+        RequestPhase::BackendFirstByte,
+        RequestPhase::BackendTotal,
+        RequestPhase::ClientWrite,
+    ];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            RequestPhase::ReadRequest => "read",
+            RequestPhase::Routing => "routing",
+            RequestPhase::BackendConnect => "connect",
+            RequestPhase::BackendFirstByte => "first_byte",
+            RequestPhase::BackendTotal => "backend",
+            RequestPhase::ClientWrite => "write",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+type CompletionHook = Box<dyn FnOnce(&RequestTimings) + Send>;
+
+// Time spent in each phase of a single request. Phases happening more than once, such as reading the body in chunks, add up
+pub struct RequestTimings {
+    started: Instant,
+    phases: [AtomicU64; 6],
+    // Bytes of response body sent to the client
+    bytes_sent: AtomicU64,
+    // Called when the response is fully sent, such as for the access log entry that waits for the client write time
+    on_complete: Mutex<Vec<CompletionHook>>,
+}
+
+impl RequestTimings {
+    pub fn new() -> Self {
+        RequestTimings {
+            started: Instant::now(),
+            phases: std::array::from_fn(|_| AtomicU64::new(NOT_MEASURED)),
+            bytes_sent: AtomicU64::new(0),
+            on_complete: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn add(&self, phase: RequestPhase, duration: Duration) {
+        let micros = duration.as_micros().min((NOT_MEASURED - 1) as u128) as u64;
+        let _ = self.phases[phase.index()].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            Some(if current == NOT_MEASURED { micros } else { current.saturating_add(micros).min(NOT_MEASURED - 1) })
+        });
+    }
+
+    pub fn get(&self, phase: RequestPhase) -> Option<Duration> {
+        match self.phases[phase.index()].load(Ordering::Relaxed) {
+            NOT_MEASURED => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    pub fn get_elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // Routing is the time until the request is first handed to a request handler, without the time spent reading the body
+    pub fn mark_routed(&self) {
+        if self.get(RequestPhase::Routing).is_none() {
+            let read = self.get(RequestPhase::ReadRequest).unwrap_or_default();
+            self.add(RequestPhase::Routing, self.get_elapsed().saturating_sub(read));
+        }
+    }
+
+    pub fn get_bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn add_on_complete<F: FnOnce(&RequestTimings) + Send + 'static>(&self, on_complete: F) {
+        if let Ok(mut hooks) = self.on_complete.lock() {
+            hooks.push(Box::new(on_complete));
+        }
+    }
+
+    // Called once the response body is sent, with the time it took and its size
+    pub fn complete(&self, client_write: Duration, bytes_sent: u64) {
+        self.add(RequestPhase::ClientWrite, client_write);
+        self.bytes_sent.store(bytes_sent, Ordering::Relaxed);
+        get_request_timing_stats().record(self);
+        let hooks = self.on_complete.lock().map(|mut hooks| std::mem::take(&mut *hooks)).unwrap_or_default();
+        for hook in hooks {
+            hook(self);
+        }
+    }
+
+    // Phases in milliseconds as appended to the access log entry, with "-" for phases that were not part of the request
+    pub fn format_for_access_log(&self) -> String {
+        let mut formatted = String::new();
+        for phase in RequestPhase::ALL {
+            match self.get(phase) {
+                Some(duration) => formatted.push_str(&format!(" {}_ms={:.3}", phase.get_name(), duration.as_secs_f64() * 1000.0)),
+                None => formatted.push_str(&format!(" {}_ms=-", phase.get_name())),
+            }
+        }
+        formatted.push_str(&format!(" total_ms={:.3}", self.get_elapsed().as_secs_f64() * 1000.0));
+        formatted
+    }
+}
+
+impl Default for RequestTimings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Adds to a phase of the request handled by the current task, if any
+pub fn record_request_phase(phase: RequestPhase, duration: Duration) {
+    let _ = CURRENT_REQUEST_TIMINGS.try_with(|timings| timings.add(phase, duration));
+}
+
+// The timings of the request handled by the current task, for phases that end outside of it, such as in a connector future
+pub fn get_current_request_timings() -> Option<Arc<RequestTimings>> {
+    CURRENT_REQUEST_TIMINGS.try_with(|timings| timings.clone()).ok()
+}
+
+static REQUEST_TIMING_STATS: OnceLock<RequestTimingStats> = OnceLock::new();
+
+pub fn get_request_timing_stats() -> &'static RequestTimingStats {
+    REQUEST_TIMING_STATS.get_or_init(RequestTimingStats::new)
+}
+
+// Running totals of the phase timings of all requests, for the monitoring data
+pub struct RequestTimingStats {
+    counts: [AtomicU64; 6],
+    total_micros: [AtomicU64; 6],
+}
+
+impl RequestTimingStats {
+    pub fn new() -> Self {
+        RequestTimingStats {
+            counts: std::array::from_fn(|_| AtomicU64::new(0)),
+            total_micros: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    pub fn record(&self, timings: &RequestTimings) {
+        for phase in RequestPhase::ALL {
+            if let Some(duration) = timings.get(phase) {
+                self.counts[phase.index()].fetch_add(1, Ordering::Relaxed);
+                self.total_micros[phase.index()].fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn get_json(&self) -> serde_json::Value {
+        let mut json = serde_json::Map::new();
+        for phase in RequestPhase::ALL {
+            let count = self.counts[phase.index()].load(Ordering::Relaxed);
+            let total_micros = self.total_micros[phase.index()].load(Ordering::Relaxed);
+            let avg_ms = if count > 0 { total_micros as f64 / count as f64 / 1000.0 } else { 0.0 };
+            json.insert(phase.get_name().to_string(), serde_json::json!({ "count": count, "avg_ms": avg_ms }));
+        }
+        serde_json::Value::Object(json)
+    }
+}
+
+impl Default for RequestTimingStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type BodySentHook = Box<dyn FnOnce(Duration, u64) + Send + Sync>;
+
+// Response body that tells how long it took to send and how many bytes were sent, from when it was created until the end
+// of the body, or until hyper drops it when the client goes away
+pub struct TimedBody {
+    inner: BoxBody<Bytes, BodyError>,
+    started: Instant,
+    bytes_sent: u64,
+    on_sent: Option<BodySentHook>,
+}
+
+impl TimedBody {
+    pub fn new<F: FnOnce(Duration, u64) + Send + Sync + 'static>(inner: BoxBody<Bytes, BodyError>, on_sent: F) -> Self {
+        TimedBody {
+            inner,
+            started: Instant::now(),
+            bytes_sent: 0,
+            on_sent: Some(Box::new(on_sent)),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(on_sent) = self.on_sent.take() {
+            on_sent(self.started.elapsed(), self.bytes_sent);
+        }
+    }
+}
+
+impl Body for TimedBody {
+    type Data = Bytes;
+    type Error = BodyError;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let result = Pin::new(&mut self.inner).poll_frame(cx);
+        match &result {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    self.bytes_sent += data.len() as u64;
+                }
+            }
+            Poll::Ready(None) | Poll::Ready(Some(Err(_))) => self.finish(),
+            Poll::Pending => {}
+        }
+        result
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for TimedBody {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Full};
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_phases_add_up_and_format() {
+        let timings = RequestTimings::new();
+        timings.add(RequestPhase::ReadRequest, Duration::from_micros(1500));
+        timings.add(RequestPhase::ReadRequest, Duration::from_micros(500));
+        timings.add(RequestPhase::Routing, Duration::from_micros(250));
+        assert_eq!(timings.get(RequestPhase::ReadRequest), Some(Duration::from_millis(2)));
+        assert_eq!(timings.get(RequestPhase::BackendConnect), None);
+
+        let formatted = timings.format_for_access_log();
+        assert!(formatted.starts_with(" read_ms=2.000 routing_ms=0.250 connect_ms=- first_byte_ms=- backend_ms=- write_ms=- total_ms="));
+    }
+
+    #[tokio::test]
+    async fn test_phases_recorded_in_task_scope() {
+        let timings = Arc::new(RequestTimings::new());
+        CURRENT_REQUEST_TIMINGS
+            .scope(timings.clone(), async {
+                record_request_phase(RequestPhase::BackendConnect, Duration::from_millis(3));
+            })
+            .await;
+        // Outside of a request there is nothing to record to
+        record_request_phase(RequestPhase::BackendConnect, Duration::from_millis(3));
+        assert_eq!(timings.get(RequestPhase::BackendConnect), Some(Duration::from_millis(3)));
+    }
+
+    #[tokio::test]
+    async fn test_timed_body_reports_when_sent() {
+        let sent = Arc::new(AtomicU64::new(0));
+        let sent_clone = sent.clone();
+        let inner = Full::new(Bytes::from_static(b"hello")).map_err(|never| -> BodyError { match never {} }).boxed();
+        let body = TimedBody::new(inner, move |_, bytes_sent| sent_clone.store(bytes_sent, Ordering::Relaxed));
+        assert_eq!(body.collect().await.unwrap().to_bytes(), Bytes::from_static(b"hello"));
+        assert_eq!(sent.load(Ordering::Relaxed), 5);
+
+        // A body dropped before the end, such as when the client goes away, is reported too
+        let dropped = Arc::new(AtomicBool::new(false));
+        let dropped_clone = dropped.clone();
+        let inner = Full::new(Bytes::from_static(b"hello")).map_err(|never| -> BodyError { match never {} }).boxed();
+        drop(TimedBody::new(inner, move |_, _| dropped_clone.store(true, Ordering::Relaxed)));
+        assert!(dropped.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,180 @@
+use hyper::Request;
+use hyper::header::{
+    AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, HeaderMap, HeaderName, HeaderValue, IF_MODIFIED_SINCE, IF_UNMODIFIED_SINCE, PROXY_AUTHORIZATION, RANGE, TRANSFER_ENCODING,
+};
+
+// Headers whose value is not a list, so repeats cannot be combined into one and receivers could each pick a different one
+const SINGLETON_HEADERS: [HeaderName; 6] = [CONTENT_TYPE, AUTHORIZATION, PROXY_AUTHORIZATION, IF_MODIFIED_SINCE, IF_UNMODIFIED_SINCE, RANGE];
+
+// How strictly requests are checked for ambiguities that front or back proxies could read differently, which is what request smuggling relies on.
+// The HTTP parser already rejects obs-fold and most malformed headers, these checks cover what it lets through
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestValidationLevel {
+    // No checks beyond the HTTP parser
+    Off,
+    // Reject conflicting Content-Length and Transfer-Encoding, repeated Host, Content-Length or other headers that may only appear once,
+    // and NUL, CR or LF in header values
+    Standard,
+    // As standard, and also reject Transfer-Encoding other than a single "chunked", any control character in header values
+    // and absolute-form request targets with another host than the Host header
+    Strict,
+}
+
+impl RequestValidationLevel {
+    pub fn from_setting(value: &str) -> Self {
+        match value {
+            "off" => RequestValidationLevel::Off,
+            "strict" => RequestValidationLevel::Strict,
+            _ => RequestValidationLevel::Standard,
+        }
+    }
+}
+
+// Checks the request line and headers, returning the reason for rejecting the request, which is also the monitoring counter
+pub fn validate_request<B>(request: &Request<B>, level: RequestValidationLevel) -> Result<(), &'static str> {
+    if level == RequestValidationLevel::Off {
+        return Ok(());
+    }
+    let headers = request.headers();
+
+    if headers.contains_key(CONTENT_LENGTH) && headers.contains_key(TRANSFER_ENCODING) {
+        return Err("content_length_with_transfer_encoding");
+    }
+    if headers.get_all(CONTENT_LENGTH).iter().count() > 1 {
+        return Err("multiple_content_length");
+    }
+    if headers.get_all(HOST).iter().count() > 1 {
+        return Err("multiple_host");
+    }
+    if SINGLETON_HEADERS.iter().any(|name| headers.get_all(name).iter().count() > 1) {
+        return Err("multiple_singleton_header");
+    }
+
+    let is_strict = level == RequestValidationLevel::Strict;
+    if headers.values().any(|value| is_invalid_header_value(value.as_bytes(), is_strict)) {
+        return Err("invalid_header_value");
+    }
+
+    if is_strict {
+        let mut transfer_encodings = headers.get_all(TRANSFER_ENCODING).iter();
+        if let Some(transfer_encoding) = transfer_encodings.next()
+            && (transfer_encodings.next().is_some() || !transfer_encoding.as_bytes().eq_ignore_ascii_case(b"chunked"))
+        {
+            return Err("invalid_transfer_encoding");
+        }
+
+        if let (Some(authority), Some(host)) = (request.uri().authority(), headers.get(HOST))
+            && !authority.as_str().as_bytes().eq_ignore_ascii_case(host.as_bytes())
+        {
+            return Err("host_mismatch");
+        }
+    }
+
+    Ok(())
+}
+
+// The values of a repeated request header as one, the way RFC 9110 allows list-based headers to be combined. Cookie is combined with
+// "; " instead, as RFC 9113 requires when the cookies of an HTTP/2 request, which may come one per header, are passed on. Values that
+// are not visible ASCII are left out
+pub fn get_combined_header_value(headers: &HeaderMap, name: &HeaderName) -> Option<String> {
+    let separator = if name == COOKIE { "; " } else { ", " };
+    let values: Vec<&str> = headers.get_all(name).iter().filter_map(|value| value.to_str().ok()).collect();
+    if values.is_empty() { None } else { Some(values.join(separator)) }
+}
+
+// Combines repeated Cookie headers into one, as HTTP/1.1 servers expect a single Cookie header by RFC 6265
+pub fn combine_cookie_headers(headers: &mut HeaderMap) {
+    if headers.get_all(COOKIE).iter().count() < 2 {
+        return;
+    }
+    if let Some(cookie) = get_combined_header_value(headers, &COOKIE).and_then(|cookie| HeaderValue::from_str(&cookie).ok()) {
+        headers.insert(COOKIE, cookie);
+    }
+}
+
+// NUL, CR and LF are never valid in header values. Strict also rejects the other control characters, except horizontal tab
+fn is_invalid_header_value(value: &[u8], is_strict: bool) -> bool {
+    value.iter().any(|b| match b {
+        b'\0' | b'\r' | b'\n' => true,
+        b'\t' => false,
+        0x01..=0x1f | 0x7f => is_strict,
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_request(uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut request = Request::builder().uri(uri).body(()).unwrap();
+        for (name, value) in headers {
+            request.headers_mut().append(hyper::header::HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn test_validate_request_standard() {
+        let level = RequestValidationLevel::Standard;
+        assert!(validate_request(&build_request("/", &[("host", "example.com"), ("content-length", "5")]), level).is_ok());
+        assert_eq!(
+            validate_request(&build_request("/", &[("content-length", "5"), ("transfer-encoding", "chunked")]), level),
+            Err("content_length_with_transfer_encoding")
+        );
+        assert_eq!(validate_request(&build_request("/", &[("host", "a.com"), ("host", "b.com")]), level), Err("multiple_host"));
+        assert_eq!(
+            validate_request(&build_request("/", &[("authorization", "Basic YTpi"), ("authorization", "Bearer c")]), level),
+            Err("multiple_singleton_header")
+        );
+        assert!(validate_request(&build_request("/", &[("accept", "text/html"), ("accept", "*/*"), ("cookie", "a=1"), ("cookie", "b=2")]), level).is_ok());
+
+        // Only strict rejects this
+        assert!(validate_request(&build_request("/", &[("transfer-encoding", "gzip, chunked")]), level).is_ok());
+
+        // Nothing is checked when off
+        assert!(validate_request(&build_request("/", &[("host", "a.com"), ("host", "b.com")]), RequestValidationLevel::Off).is_ok());
+    }
+
+    #[test]
+    fn test_combined_header_values() {
+        let mut headers = build_request("/", &[("accept", "text/html"), ("accept", "*/*"), ("cookie", "a=1"), ("cookie", "b=2")])
+            .headers()
+            .clone();
+        assert_eq!(get_combined_header_value(&headers, &hyper::header::ACCEPT).unwrap(), "text/html, */*");
+        assert_eq!(get_combined_header_value(&headers, &COOKIE).unwrap(), "a=1; b=2");
+        assert_eq!(get_combined_header_value(&headers, &HOST), None);
+
+        combine_cookie_headers(&mut headers);
+        assert_eq!(headers.get_all(COOKIE).iter().collect::<Vec<_>>(), vec!["a=1; b=2"]);
+        assert_eq!(headers.get_all(hyper::header::ACCEPT).iter().count(), 2);
+    }
+
+    #[test]
+    fn test_is_invalid_header_value() {
+        // The HTTP parsers reject these already, so they can only be tested on the bytes
+        for value in [&b"a\0b"[..], b"a\rb", b"a\nb"] {
+            assert!(is_invalid_header_value(value, false));
+        }
+        assert!(!is_invalid_header_value(b"a\x01b", false));
+        assert!(is_invalid_header_value(b"a\x01b", true));
+        assert!(is_invalid_header_value(b"a\x7fb", true));
+        assert!(!is_invalid_header_value(b"a\tb \xc3\xa6", true));
+    }
+
+    #[test]
+    fn test_validate_request_strict() {
+        let level = RequestValidationLevel::Strict;
+        assert!(validate_request(&build_request("/", &[("transfer-encoding", "Chunked"), ("x-test", "a\t")]), level).is_ok());
+        assert_eq!(
+            validate_request(&build_request("/", &[("transfer-encoding", "gzip, chunked")]), level),
+            Err("invalid_transfer_encoding")
+        );
+        assert_eq!(
+            validate_request(&build_request("/", &[("transfer-encoding", "chunked"), ("transfer-encoding", "chunked")]), level),
+            Err("invalid_transfer_encoding")
+        );
+        assert_eq!(validate_request(&build_request("http://a.com/", &[("host", "b.com")]), level), Err("host_mismatch"));
+        assert!(validate_request(&build_request("http://a.com/", &[("host", "A.com")]), level).is_ok());
+    }
+}
@@ -21,12 +21,12 @@ impl BindingSiteCache {
     }
 
     fn populate_cache(&self, bindings: &Vec<Binding>, sites: &Vec<Site>, binding_sites: &Vec<BindingSiteRelationship>) {
-        // Clear existing cache
-        self.binding_to_sites.clear();
-
         // Build a map of binding ID to sites
         let unique_binding_ids: Vec<String> = bindings.iter().map(|b| b.id.clone()).collect();
 
+        // Entries are replaced rather than cleared first, as the cache is also refreshed while serving requests
+        self.binding_to_sites.retain(|binding_id, _| unique_binding_ids.contains(binding_id));
+
         // Generate hashmap with site id to Site for quick lookup
         let site_map: HashMap<String, Site> = sites.iter().filter(|site| site.is_enabled).map(|site| (site.id.clone(), site.clone())).collect();
 
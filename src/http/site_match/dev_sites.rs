@@ -0,0 +1,74 @@
+use crate::configuration::site::Site;
+use crate::core::operation_mode::{OperationMode, get_operation_mode};
+use crate::http::request_handlers::processor_trait::ProcessorTrait;
+use crate::http::request_handlers::processors::static_files_processor::StaticFileProcessor;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::logging::syslog::trace;
+
+// Directory holding the folders that are served automatically as <folder>.localhost in development mode
+pub const DEV_SITES_DIRECTORY: &str = "./sites";
+pub const DEV_SITES_HOSTNAME_SUFFIX: &str = ".localhost";
+// Request calculated data key holding the web root of the automatically provisioned site
+pub const DEV_SITE_WEB_ROOT_KEY: &str = "dev_site_web_root";
+
+// Gets the folder name from a "<folder>.localhost" hostname. Only a single label of letters, digits, hyphens and underscores is accepted,
+// so the folder is always directly within the sites directory
+pub fn get_dev_site_folder(hostname: &str) -> Option<String> {
+    let hostname = hostname.to_lowercase();
+    let folder = hostname.strip_suffix(DEV_SITES_HOSTNAME_SUFFIX)?;
+    if folder.is_empty() || !folder.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    Some(folder.to_string())
+}
+
+// In development mode, requests for <folder>.localhost are served from ./sites/<folder> without any configuration, as long as the folder
+// exists and no configured site has the hostname. Returns the site to use and its web root
+pub async fn find_dev_site(sites: &[Site], hostname: &str) -> Option<(Site, String)> {
+    if get_operation_mode() != OperationMode::DEV {
+        return None;
+    }
+
+    let folder = get_dev_site_folder(hostname)?;
+    let hostname = hostname.to_lowercase();
+    if sites.iter().any(|s| s.is_enabled && s.hostnames.contains(&hostname)) {
+        return None;
+    }
+
+    let web_root = format!("{}/{}", DEV_SITES_DIRECTORY, folder);
+    if !tokio::fs::metadata(&web_root).await.is_ok_and(|metadata| metadata.is_dir()) {
+        return None;
+    }
+
+    trace(format!("Serving hostname '{}' from automatically provisioned development site '{}'", hostname, web_root));
+    let mut site = Site::new();
+    site.id = format!("dev-site-{}", folder);
+    site.hostnames = vec![hostname];
+    Some((site, web_root))
+}
+
+// Serves the request as static files from the web root of the automatically provisioned site
+pub async fn handle_dev_site_request(gruxi_request: &mut GruxiRequest, site: &Site, web_root: &str) -> GruxiResponse {
+    let mut processor = StaticFileProcessor::new(web_root.to_string(), vec!["index.html".to_string(), "index.htm".to_string()]);
+    processor.initialize();
+    match processor.handle_request(gruxi_request, site).await {
+        Ok(response) => response,
+        Err(_) => GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_dev_site_folder() {
+        assert_eq!(get_dev_site_folder("myapp.localhost"), Some("myapp".to_string()));
+        assert_eq!(get_dev_site_folder("My-App_2.LOCALHOST"), Some("my-app_2".to_string()));
+        assert_eq!(get_dev_site_folder("localhost"), None);
+        assert_eq!(get_dev_site_folder(".localhost"), None);
+        assert_eq!(get_dev_site_folder("a.b.localhost"), None);
+        assert_eq!(get_dev_site_folder("myapp.example.com"), None);
+    }
+}
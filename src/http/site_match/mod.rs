@@ -1,2 +1,3 @@
+pub mod binding_site_cache;
+pub mod dev_sites;
 pub mod site_matcher;
-pub mod binding_site_cache;
\ No newline at end of file
@@ -1,8 +1,12 @@
-use crate::{configuration::site::Site, logging::syslog::trace};
+use crate::{
+    configuration::site::{Site, hostname_to_ascii},
+    logging::syslog::trace,
+};
 
-// Find a best match site for the requested hostname, comparing case-insensitively
+// Find a best match site for the requested hostname, comparing case-insensitively. Sites hold their hostnames in punycode, so
+// internationalized hostnames sent as UTF-8 in the Host header are converted to it
 pub fn find_best_match_site<'a>(sites: &'a Vec<Site>, requested_hostname: &str) -> Option<&'a Site> {
-    let requested_hostname_lower = requested_hostname.to_lowercase();
+    let requested_hostname_lower = hostname_to_ascii(requested_hostname).unwrap_or_else(|_| requested_hostname.to_lowercase());
     let mut site = sites.iter().find(|s| s.hostnames.iter().any(|h| h.to_string() == requested_hostname_lower) && s.is_enabled);
 
     // We check for star hostnames
@@ -69,6 +73,29 @@ mod tests {
         assert_eq!(matched_site.id, site2.id);
     }
 
+    #[test]
+    fn test_site_matcher_internationalized_hostnames() {
+        let mut site1 = Site::new();
+        site1.hostnames = vec!["bücher.example".to_string()];
+        site1.is_default = false;
+        site1.is_enabled = true;
+        site1.sanitize();
+
+        let mut site2 = Site::new();
+        site2.hostnames = vec!["example.com".to_string()];
+        site2.is_default = true;
+        site2.is_enabled = true;
+
+        // Clients send the punycode form in SNI and the Host header, though some send UTF-8
+        let sites = vec![site1.clone(), site2.clone()];
+        let matched_site = find_best_match_site(&sites, "xn--bcher-kva.example").unwrap();
+        assert_eq!(matched_site.id, site1.id);
+        let matched_site = find_best_match_site(&sites, "BÜCHER.example").unwrap();
+        assert_eq!(matched_site.id, site1.id);
+        let matched_site = find_best_match_site(&sites, "buecher.example").unwrap();
+        assert_eq!(matched_site.id, site2.id);
+    }
+
     #[test]
     fn test_site_matcher_partial_match() {
         let mut site1 = Site::new();
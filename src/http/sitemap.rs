@@ -0,0 +1,245 @@
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::configuration::site::Site;
+use crate::core::operation_mode::get_operation_mode;
+use crate::core::running_state::RunningState;
+use crate::core::running_state_manager::get_running_state_manager;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::logging::syslog::{debug, trace};
+use chrono::{DateTime, SecondsFormat, Utc};
+use dashmap::DashMap;
+use hyper::header::HeaderValue;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+pub const ROBOTS_TXT_PATH: &str = "/robots.txt";
+pub const SITEMAP_PATH: &str = "/sitemap.xml";
+
+// How often the sitemaps are built again from the files in the web roots
+const SITEMAP_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// The most URLs a sitemap may have, by the sitemap protocol
+const MAX_SITEMAP_URLS: usize = 50_000;
+
+// Index files are listed by their directory, when the site has no index files of its own
+const DEFAULT_SITEMAP_INDEX_FILES: [&str; 2] = ["index.html", "index.htm"];
+
+static SITEMAPS: OnceLock<DashMap<String, Arc<Sitemap>>> = OnceLock::new();
+
+fn get_sitemaps() -> &'static DashMap<String, Arc<Sitemap>> {
+    SITEMAPS.get_or_init(DashMap::new)
+}
+
+struct SitemapUrl {
+    path: String,
+    last_modified: DateTime<Utc>,
+}
+
+// The pages of a site, as found in its web roots. The URLs are made absolute when served, with the host of the request
+struct Sitemap {
+    web_roots: Vec<String>,
+    urls: Vec<SitemapUrl>,
+    built_at: Instant,
+}
+
+// Answers requests for robots.txt and sitemap.xml of sites having them generated. Only used when the request handlers of the site
+// found no such file, so a real robots.txt or sitemap.xml in the web root is served instead
+pub async fn get_generated_site_file(gruxi_request: &mut GruxiRequest, site: &Site, running_state: &RunningState) -> Option<GruxiResponse> {
+    if !site.robots_txt_enabled && !site.sitemap_enabled {
+        return None;
+    }
+    let method = gruxi_request.get_http_method();
+    if method != "GET" && method != "HEAD" {
+        return None;
+    }
+
+    let path = gruxi_request.get_path();
+    let (content_type, body) = if site.robots_txt_enabled && path == ROBOTS_TXT_PATH {
+        let sitemap_url = site.sitemap_enabled.then(|| format!("{}{}", get_base_url(gruxi_request), SITEMAP_PATH));
+        ("text/plain; charset=utf-8", build_robots_txt(get_operation_mode().allows_crawlers(), sitemap_url.as_deref()))
+    } else if site.sitemap_enabled && path == SITEMAP_PATH {
+        let web_roots = running_state.get_request_handler_manager().get_site_web_roots(site, running_state.get_processor_manager()).await;
+        let sitemap = get_or_build_sitemap(site, web_roots).await;
+        ("application/xml; charset=utf-8", build_sitemap_xml(&get_base_url(gruxi_request), &sitemap.urls))
+    } else {
+        return None;
+    };
+
+    trace(format!("Serving generated {} for site '{}'", path, site.id));
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), body);
+    response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    Some(response)
+}
+
+// Such as https://www.example.com:8443, from the request, as sites may answer to several hostnames
+fn get_base_url(gruxi_request: &mut GruxiRequest) -> String {
+    let host = gruxi_request.get_headers().get(hyper::header::HOST).and_then(|v| v.to_str().ok()).map(|host| host.to_string());
+    let host = host.unwrap_or_else(|| gruxi_request.get_hostname());
+    format!("{}://{}", if gruxi_request.is_https() { "https" } else { "http" }, host)
+}
+
+fn build_robots_txt(allows_crawlers: bool, sitemap_url: Option<&str>) -> String {
+    if !allows_crawlers {
+        return "User-agent: *\nDisallow: /\n".to_string();
+    }
+    let mut robots_txt = "User-agent: *\nDisallow:\n".to_string();
+    if let Some(sitemap_url) = sitemap_url {
+        let _ = writeln!(robots_txt, "\nSitemap: {}", sitemap_url);
+    }
+    robots_txt
+}
+
+fn build_sitemap_xml(base_url: &str, urls: &[SitemapUrl]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for url in urls {
+        let _ = writeln!(
+            xml,
+            "  <url><loc>{}</loc><lastmod>{}</lastmod></url>",
+            escape_xml(&format!("{}{}", base_url, url.path)),
+            url.last_modified.to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+// The sitemap of the site, built now if it has none yet or its web roots changed, such as by a new release
+async fn get_or_build_sitemap(site: &Site, web_roots: Vec<String>) -> Arc<Sitemap> {
+    if let Some(sitemap) = get_sitemaps().get(&site.id)
+        && sitemap.web_roots == web_roots
+    {
+        return sitemap.clone();
+    }
+    build_and_store_sitemap(site, web_roots).await
+}
+
+async fn build_and_store_sitemap(site: &Site, web_roots: Vec<String>) -> Arc<Sitemap> {
+    let index_files = if site.index_files.is_empty() {
+        DEFAULT_SITEMAP_INDEX_FILES.iter().map(|f| f.to_string()).collect()
+    } else {
+        site.index_files.clone()
+    };
+    let roots = web_roots.clone();
+    let urls = tokio::task::spawn_blocking(move || find_sitemap_urls(&roots, &index_files)).await.unwrap_or_default();
+    debug(format!("Built the sitemap of site '{}' with {} URLs", site.id, urls.len()));
+    let sitemap = Arc::new(Sitemap {
+        web_roots,
+        urls,
+        built_at: Instant::now(),
+    });
+    get_sitemaps().insert(site.id.clone(), sitemap.clone());
+    sitemap
+}
+
+// Builds the sitemaps again every interval, for the sites still having one, and forgets those of the other sites
+pub fn start_sitemap_refresh_task() {
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(SITEMAP_REFRESH_INTERVAL).await;
+            let sites: Vec<Site> = get_cached_configuration().get_configuration().await.sites.iter().filter(|site| site.sitemap_enabled).cloned().collect();
+            get_sitemaps().retain(|site_id, _| sites.iter().any(|site| &site.id == site_id));
+
+            for site in &sites {
+                let is_stale = get_sitemaps().get(&site.id).is_none_or(|sitemap| sitemap.built_at.elapsed() >= SITEMAP_REFRESH_INTERVAL);
+                if !is_stale {
+                    continue;
+                }
+                let web_roots = {
+                    let running_state_manager = get_running_state_manager().await;
+                    let running_state = running_state_manager.get_running_state_unlocked().await;
+                    running_state.get_request_handler_manager().get_site_web_roots(site, running_state.get_processor_manager()).await
+                };
+                build_and_store_sitemap(site, web_roots).await;
+            }
+        }
+    });
+}
+
+// The HTML pages in the web roots, by their URL path. Hidden files and directories, such as .git, are left out, and symlinks are
+// not followed. Index files are listed as their directory
+fn find_sitemap_urls(web_roots: &[String], index_files: &[String]) -> Vec<SitemapUrl> {
+    let mut urls: Vec<SitemapUrl> = Vec::new();
+    for web_root in web_roots {
+        let mut directories = vec![(Path::new(web_root).to_path_buf(), "/".to_string())];
+        while let Some((directory, url_path)) = directories.pop() {
+            let Ok(entries) = std::fs::read_dir(&directory) else {
+                continue;
+            };
+            let mut entries: Vec<_> = entries.flatten().collect();
+            entries.sort_by_key(|entry| entry.file_name());
+            for entry in entries {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                if name.starts_with('.') || file_type.is_symlink() {
+                    continue;
+                }
+                if file_type.is_dir() {
+                    directories.push((entry.path(), format!("{}{}/", url_path, urlencoding::encode(&name))));
+                    continue;
+                }
+                let extension = Path::new(&name).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+                if extension != "html" && extension != "htm" {
+                    continue;
+                }
+                let path = if index_files.contains(&name) {
+                    url_path.clone()
+                } else {
+                    format!("{}{}", url_path, urlencoding::encode(&name))
+                };
+                // A page in an earlier web root hides the same page in the later ones, like when serving
+                if urls.iter().any(|url| url.path == path) {
+                    continue;
+                }
+                let last_modified = entry.metadata().and_then(|m| m.modified()).map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now());
+                urls.push(SitemapUrl { path, last_modified });
+                if urls.len() >= MAX_SITEMAP_URLS {
+                    return urls;
+                }
+            }
+        }
+    }
+    urls.sort_by(|a, b| a.path.cmp(&b.path));
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_robots_txt() {
+        assert_eq!(build_robots_txt(false, Some("https://example.com/sitemap.xml")), "User-agent: *\nDisallow: /\n");
+        assert_eq!(build_robots_txt(true, None), "User-agent: *\nDisallow:\n");
+        assert_eq!(
+            build_robots_txt(true, Some("https://example.com/sitemap.xml")),
+            "User-agent: *\nDisallow:\n\nSitemap: https://example.com/sitemap.xml\n"
+        );
+    }
+
+    #[test]
+    fn test_sitemap_from_web_root() {
+        let web_root = std::env::temp_dir().join(format!("gruxi-test-sitemap-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(web_root.join("docs/getting started")).unwrap();
+        std::fs::create_dir_all(web_root.join(".git")).unwrap();
+        for file in ["index.html", "about.htm", "style.css", "docs/index.html", "docs/getting started/a&b.html", ".git/secret.html"] {
+            std::fs::write(web_root.join(file), "<html></html>").unwrap();
+        }
+
+        let urls = find_sitemap_urls(&[web_root.to_string_lossy().to_string()], &["index.html".to_string()]);
+        let paths: Vec<&str> = urls.iter().map(|url| url.path.as_str()).collect();
+        assert_eq!(paths, vec!["/", "/about.htm", "/docs/", "/docs/getting%20started/a%26b.html"]);
+
+        let xml = build_sitemap_xml("https://example.com", &urls[..1]);
+        assert!(xml.contains("<url><loc>https://example.com/</loc><lastmod>"));
+        assert!(xml.ends_with("</urlset>\n"));
+        let _ = std::fs::remove_dir_all(&web_root);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::configuration::binding::Binding;
+use crate::core::monitoring::{ConnectionStats, get_monitoring_state};
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::logging::syslog::trace;
+use hyper::header::HeaderValue;
+use std::net::IpAddr;
+
+// Answered on the bindings with the status page enabled, whatever site the hostname belongs to
+pub const STATUS_PAGE_PATH: &str = "/gruxi-status";
+
+// Answers the status page with the counts of the nginx stub_status page, as plain text in its exact format so scripts scraping
+// nginx keep working, or as JSON with ?format=json. Clients not in the allowed IPs of the binding get 403
+pub async fn handle_status_page_request(gruxi_request: &mut GruxiRequest, binding: &Binding) -> GruxiResponse {
+    let remote_ip = gruxi_request.get_calculated_data("remote_ip").unwrap_or_default();
+    if !is_ip_allowed(&remote_ip, &binding.status_page_allowed_ips) {
+        trace(format!("Status page request from {} on binding {}:{} is not allowed", remote_ip, binding.ip, binding.port));
+        return GruxiResponse::new_empty_with_status(hyper::StatusCode::FORBIDDEN.as_u16());
+    }
+
+    let connection_stats = get_monitoring_state().await.get_connection_stats();
+    let as_json = gruxi_request.get_query().split('&').any(|parameter| parameter == "format=json");
+    let (body, content_type) = if as_json {
+        (serde_json::to_string(&connection_stats).unwrap_or_default(), "application/json")
+    } else {
+        (get_status_text(&connection_stats), "text/plain")
+    };
+
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), body);
+    response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response.headers_mut().insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+// The stub_status format, trailing spaces included
+fn get_status_text(stats: &ConnectionStats) -> String {
+    format!(
+        "Active connections: {} \nserver accepts handled requests\n {} {} {} \nReading: {} Writing: {} Waiting: {} \n",
+        stats.active, stats.accepts, stats.handled, stats.requests, stats.reading, stats.writing, stats.waiting
+    )
+}
+
+// Parses an IP address, such as "127.0.0.1", or a range in CIDR notation, such as "10.0.0.0/8" or "fd00::/8", into the address
+// and prefix length
+pub fn parse_ip_range(ip_range: &str) -> Option<(IpAddr, u8)> {
+    let (ip, prefix_length) = match ip_range.split_once('/') {
+        Some((ip, prefix_length)) => (ip.parse::<IpAddr>().ok()?, Some(prefix_length.parse::<u8>().ok()?)),
+        None => (ip_range.parse::<IpAddr>().ok()?, None),
+    };
+    let max_prefix_length = if ip.is_ipv4() { 32 } else { 128 };
+    match prefix_length {
+        Some(prefix_length) if prefix_length > max_prefix_length => None,
+        Some(prefix_length) => Some((ip, prefix_length)),
+        None => Some((ip, max_prefix_length)),
+    }
+}
+
+// Whether the address is in any of the IP addresses or ranges. IPv4 clients connecting to an IPv6 socket match IPv4 ranges
+pub fn is_ip_allowed(remote_ip: &str, allowed_ips: &[String]) -> bool {
+    let remote_ip = match remote_ip.parse::<IpAddr>() {
+        Ok(ip) => ip.to_canonical(),
+        Err(_) => return false,
+    };
+    allowed_ips.iter().filter_map(|allowed_ip| parse_ip_range(allowed_ip)).any(|(ip, prefix_length)| match (ip, remote_ip) {
+        (IpAddr::V4(ip), IpAddr::V4(remote_ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_length as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(remote_ip) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(remote_ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_length as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(remote_ip) & mask
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ip_range() {
+        assert_eq!(parse_ip_range("127.0.0.1"), Some(("127.0.0.1".parse().unwrap(), 32)));
+        assert_eq!(parse_ip_range("10.0.0.0/8"), Some(("10.0.0.0".parse().unwrap(), 8)));
+        assert_eq!(parse_ip_range("fd00::/8"), Some(("fd00::".parse().unwrap(), 8)));
+        assert_eq!(parse_ip_range("10.0.0.0/33"), None);
+        assert_eq!(parse_ip_range("10.0.0.0/"), None);
+        assert_eq!(parse_ip_range("localhost"), None);
+    }
+
+    #[test]
+    fn test_is_ip_allowed() {
+        let allowed_ips = vec!["127.0.0.1".to_string(), "10.1.0.0/16".to_string(), "fd00::/8".to_string(), "0.0.0.0/0".to_string()];
+        assert!(is_ip_allowed("127.0.0.1", &allowed_ips[..1]));
+        assert!(is_ip_allowed("::ffff:127.0.0.1", &allowed_ips[..1]));
+        assert!(!is_ip_allowed("127.0.0.2", &allowed_ips[..1]));
+        assert!(is_ip_allowed("10.1.200.3", &allowed_ips[..2]));
+        assert!(!is_ip_allowed("10.2.0.1", &allowed_ips[..2]));
+        assert!(is_ip_allowed("fd12::1", &allowed_ips[..3]));
+        assert!(!is_ip_allowed("fe80::1", &allowed_ips[..3]));
+        assert!(is_ip_allowed("192.0.2.1", &allowed_ips));
+        assert!(!is_ip_allowed("<unknown>", &allowed_ips));
+    }
+
+    #[test]
+    fn test_status_text() {
+        let stats = ConnectionStats {
+            active: 291,
+            accepts: 16630948,
+            handled: 16630948,
+            requests: 31070465,
+            reading: 6,
+            writing: 179,
+            waiting: 106,
+        };
+        assert_eq!(
+            get_status_text(&stats),
+            "Active connections: 291 \nserver accepts handled requests\n 16630948 16630948 31070465 \nReading: 6 Writing: 179 Waiting: 106 \n"
+        );
+    }
+}
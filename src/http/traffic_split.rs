@@ -0,0 +1,281 @@
+use crate::configuration::site::Site;
+use crate::http::http_util::add_vary_field;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use dashmap::DashMap;
+use hyper::header::HeaderValue;
+use std::sync::OnceLock;
+
+// How long the cookie keeps a client on the variant it got
+const VARIANT_COOKIE_MAX_AGE_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+static TRAFFIC_SPLIT_PERCENTAGES: OnceLock<TrafficSplitPercentages> = OnceLock::new();
+
+pub fn get_traffic_split_percentages() -> &'static TrafficSplitPercentages {
+    TRAFFIC_SPLIT_PERCENTAGES.get_or_init(TrafficSplitPercentages::new)
+}
+
+// Percentages of clients getting variant B set through the admin API. They override the percentage in the configuration of the site
+// until the server restarts, so a test can be ramped up or stopped without reloading the configuration
+pub struct TrafficSplitPercentages {
+    overrides: DashMap<String, u32>,
+}
+
+impl TrafficSplitPercentages {
+    pub fn new() -> Self {
+        TrafficSplitPercentages { overrides: DashMap::new() }
+    }
+
+    pub fn get(&self, site: &Site) -> u32 {
+        self.get_override(&site.id).unwrap_or(site.traffic_split.variant_b_percentage)
+    }
+
+    pub fn get_override(&self, site_id: &str) -> Option<u32> {
+        self.overrides.get(site_id).map(|percentage| *percentage)
+    }
+
+    // Sets the percentage for the site, or goes back to the one in the configuration with None
+    pub fn set(&self, site_id: &str, percentage: Option<u32>) {
+        match percentage {
+            Some(percentage) => {
+                self.overrides.insert(site_id.to_string(), percentage.min(100));
+            }
+            None => {
+                self.overrides.remove(site_id);
+            }
+        }
+    }
+}
+
+impl Default for TrafficSplitPercentages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrafficSplitVariant {
+    // Served by the request handlers of the site
+    A,
+    // Served by the request handlers of the traffic split
+    B,
+}
+
+impl TrafficSplitVariant {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            TrafficSplitVariant::A => "a",
+            TrafficSplitVariant::B => "b",
+        }
+    }
+
+    fn parse(value: &str) -> Option<TrafficSplitVariant> {
+        match value {
+            "a" => Some(TrafficSplitVariant::A),
+            "b" => Some(TrafficSplitVariant::B),
+            _ => None,
+        }
+    }
+
+    // A variant nobody should get any more, such as B after its percentage is set to 0 to stop a test, is not kept for anyone
+    fn is_available(&self, percentage: u32) -> bool {
+        match self {
+            TrafficSplitVariant::A => percentage < 100,
+            TrafficSplitVariant::B => percentage > 0,
+        }
+    }
+}
+
+// The variant a request is served, and the cookie to keep the client on it, if it has none yet
+pub struct TrafficSplitAssignment {
+    pub variant: TrafficSplitVariant,
+    set_cookie: Option<String>,
+    is_sticky_by_cookie: bool,
+}
+
+impl TrafficSplitAssignment {
+    // Besides the cookie, shared caches are told the response depends on the variant, so one client's variant is not served to others
+    pub fn apply_to_response(&self, response: &mut GruxiResponse) {
+        if let Some(cookie) = &self.set_cookie
+            && let Ok(header_value) = HeaderValue::from_str(cookie)
+        {
+            response.headers_mut().append(hyper::header::SET_COOKIE, header_value);
+        }
+        if self.is_sticky_by_cookie {
+            add_vary_field(response, "Cookie");
+        } else {
+            // No request header tells the variant apart when it is picked by IP address, so the response is kept out of shared caches
+            make_cache_control_private(response);
+        }
+    }
+}
+
+// Adds private to the Cache-Control of the response, dropping what only applies to shared caches
+fn make_cache_control_private(response: &mut GruxiResponse) {
+    let current = response.get_header(hyper::header::CACHE_CONTROL.as_str()).and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+    let directives: Vec<&str> = current.split(',').map(|directive| directive.trim()).filter(|directive| !directive.is_empty()).collect();
+    if directives
+        .iter()
+        .any(|directive| directive.eq_ignore_ascii_case("private") || directive.eq_ignore_ascii_case("no-store"))
+    {
+        return;
+    }
+    let shared_cache_directives = ["public", "s-maxage", "proxy-revalidate"];
+    let kept = directives.into_iter().filter(|directive| {
+        let name = directive.split('=').next().unwrap_or("").trim();
+        !shared_cache_directives.iter().any(|shared| name.eq_ignore_ascii_case(shared))
+    });
+    let new_value = std::iter::once("private").chain(kept).collect::<Vec<&str>>().join(", ");
+    if let Ok(value) = HeaderValue::from_str(&new_value) {
+        response.headers_mut().insert(hyper::header::CACHE_CONTROL, value);
+    }
+}
+
+// Picks the variant for a request to a site splitting its traffic. Clients stay on their variant, by the cookie or their IP address
+pub fn assign_traffic_split_variant(gruxi_request: &mut GruxiRequest, site: &Site) -> Option<TrafficSplitAssignment> {
+    let traffic_split = &site.traffic_split;
+    if !traffic_split.enabled {
+        return None;
+    }
+    let percentage = get_traffic_split_percentages().get(site);
+
+    if traffic_split.sticky_by == "client_ip" {
+        let variant = choose_variant(get_client_ip_bucket(&site.id, &gruxi_request.get_remote_ip()), percentage);
+        return Some(TrafficSplitAssignment {
+            variant,
+            set_cookie: None,
+            is_sticky_by_cookie: false,
+        });
+    }
+
+    let cookie_variant = gruxi_request.get_cookie(&traffic_split.cookie_name).and_then(|value| TrafficSplitVariant::parse(&value));
+    if let Some(variant) = cookie_variant.filter(|variant| variant.is_available(percentage)) {
+        return Some(TrafficSplitAssignment {
+            variant,
+            set_cookie: None,
+            is_sticky_by_cookie: true,
+        });
+    }
+    let variant = choose_variant(rand::random_range(0..100), percentage);
+    let secure = if gruxi_request.is_https() { "; Secure" } else { "" };
+    let set_cookie = format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly{}; SameSite=Lax",
+        traffic_split.cookie_name,
+        variant.get_name(),
+        VARIANT_COOKIE_MAX_AGE_SECONDS,
+        secure
+    );
+    Some(TrafficSplitAssignment {
+        variant,
+        set_cookie: Some(set_cookie),
+        is_sticky_by_cookie: true,
+    })
+}
+
+// Buckets are 0 to 99, and the first percentage of them get variant B
+fn choose_variant(bucket: u32, percentage: u32) -> TrafficSplitVariant {
+    if bucket < percentage { TrafficSplitVariant::B } else { TrafficSplitVariant::A }
+}
+
+// FNV-1a of the site and IP address, so a client gets the same bucket across restarts and versions, but not the same one on every site
+fn get_client_ip_bucket(site_id: &str, client_ip: &str) -> u32 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in site_id.bytes().chain([0]).chain(client_ip.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % 100) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_request(cookie: Option<&str>) -> GruxiRequest {
+        let mut builder = hyper::Request::builder().uri("/");
+        if let Some(cookie) = cookie {
+            builder = builder.header("Cookie", cookie);
+        }
+        let mut request = GruxiRequest::new(builder.body(hyper::body::Bytes::new()).unwrap());
+        request.add_calculated_data("remote_ip", "192.0.2.10");
+        request
+    }
+
+    fn create_site(percentage: u32, sticky_by: &str) -> Site {
+        let mut site = Site::new();
+        site.traffic_split.enabled = true;
+        site.traffic_split.variant_b_request_handlers = vec!["handler-b".to_string()];
+        site.traffic_split.variant_b_percentage = percentage;
+        site.traffic_split.sticky_by = sticky_by.to_string();
+        site
+    }
+
+    #[test]
+    fn test_cookie_keeps_client_on_variant() {
+        let site = create_site(100, "cookie");
+        let assignment = assign_traffic_split_variant(&mut create_request(None), &site).unwrap();
+        assert_eq!(assignment.variant, TrafficSplitVariant::B);
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        assignment.apply_to_response(&mut response);
+        let set_cookie = response.get_header("Set-Cookie").unwrap().to_str().unwrap().to_string();
+        assert!(set_cookie.starts_with("gruxi_variant=b;") && !set_cookie.contains("Secure"));
+        assert_eq!(response.get_header("Vary").unwrap(), "Cookie");
+
+        // Over HTTPS the cookie is only sent back over HTTPS
+        let mut https_request = create_request(None);
+        https_request.add_calculated_data("is_https", "true");
+        let assignment = assign_traffic_split_variant(&mut https_request, &site).unwrap();
+        assert!(assignment.set_cookie.unwrap().contains("; Secure;"));
+
+        // A client already on variant B stays on it, without a new cookie
+        let site = create_site(10, "cookie");
+        let assignment = assign_traffic_split_variant(&mut create_request(Some("gruxi_variant=b")), &site).unwrap();
+        assert_eq!(assignment.variant, TrafficSplitVariant::B);
+        assert!(assignment.set_cookie.is_none());
+
+        // Until variant B is stopped
+        let site = create_site(0, "cookie");
+        assert_eq!(
+            assign_traffic_split_variant(&mut create_request(Some("gruxi_variant=b")), &site).unwrap().variant,
+            TrafficSplitVariant::A
+        );
+
+        assert!(assign_traffic_split_variant(&mut create_request(None), &Site::new()).is_none());
+    }
+
+    #[test]
+    fn test_client_ip_split() {
+        let site = create_site(50, "client_ip");
+        let first = assign_traffic_split_variant(&mut create_request(None), &site).unwrap().variant;
+        for _ in 0..10 {
+            assert_eq!(assign_traffic_split_variant(&mut create_request(None), &site).unwrap().variant, first);
+        }
+
+        // Shared caches are kept from serving the variant to clients on other addresses
+        let assignment = assign_traffic_split_variant(&mut create_request(None), &site).unwrap();
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        response
+            .headers_mut()
+            .insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=60, s-maxage=600"));
+        assignment.apply_to_response(&mut response);
+        assert_eq!(response.get_header("Cache-Control").unwrap(), "private, max-age=60");
+        let mut response = GruxiResponse::new_empty_with_status(200);
+        assignment.apply_to_response(&mut response);
+        assert_eq!(response.get_header("Cache-Control").unwrap(), "private");
+
+        // Roughly the percentage of the addresses get variant B
+        let on_b = (0..1000).filter(|i| get_client_ip_bucket("site", &format!("10.0.{}.{}", i / 256, i % 256)) < 30).count();
+        assert!((250..350).contains(&on_b), "{} of 1000 on variant B", on_b);
+    }
+
+    #[test]
+    fn test_runtime_percentage_overrides_configuration() {
+        let percentages = TrafficSplitPercentages::new();
+        let site = create_site(10, "cookie");
+        assert_eq!(percentages.get(&site), 10);
+        percentages.set(&site.id, Some(150));
+        assert_eq!(percentages.get(&site), 100);
+        percentages.set(&site.id, None);
+        assert_eq!(percentages.get(&site), 10);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::configuration::site::Site;
+use crate::core::running_state::RunningState;
+use crate::file::normalized_path::NormalizedPath;
+use crate::http::http_util::resolve_web_root_and_path_and_get_file;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::logging::syslog::trace;
+
+// Placeholder for the request path in try_files entries
+pub const TRY_FILES_URI_PLACEHOLDER: &str = "$uri";
+// Placeholder for the query string in try_files entries
+pub const TRY_FILES_QUERY_PLACEHOLDER: &str = "$query";
+// The entry point of single-page apps, served for client-side routes when SPA fallback is enabled
+pub const SPA_FALLBACK_PATH: &str = "/index.html";
+
+// Replaces the placeholders of a try_files entry with the values from the request
+pub fn expand_try_files_entry(entry: &str, path: &str, query: &str) -> String {
+    entry.replace(TRY_FILES_URI_PLACEHOLDER, path).replace(TRY_FILES_QUERY_PLACEHOLDER, query)
+}
+
+// Parses the final try_files entry if it is a status code, like "=404"
+pub fn parse_try_files_status(entry: &str) -> Option<u16> {
+    let status = entry.strip_prefix('=')?.parse::<u16>().ok()?;
+    if (400..600).contains(&status) { Some(status) } else { None }
+}
+
+// Works like nginx try_files: every entry but the last is tried as a file (or as a directory if it ends with "/") in the web roots
+// of the site's request handlers, and the request is rewritten to the first that exists. If none exist, the last entry is used as
+// the fallback, which is either a URI to rewrite to, like "/index.php?$query", or a status code to answer with, like "=404"
+pub async fn apply_try_files(gruxi_request: &mut GruxiRequest, site: &Site, running_state: &RunningState) -> Option<GruxiResponse> {
+    let (fallback, candidates) = site.try_files.split_last()?;
+    let path = gruxi_request.get_path();
+    let query = gruxi_request.get_query();
+    let web_roots = running_state.get_request_handler_manager().get_site_web_roots(site, running_state.get_processor_manager()).await;
+
+    for candidate in candidates {
+        let expanded = expand_try_files_entry(candidate, &path, &query);
+        let (candidate_path, _) = split_path_and_query(&expanded);
+        if candidate_exists(&web_roots, candidate_path).await {
+            trace(format!("[try_files] Request path '{}' matched '{}'", path, expanded));
+            if candidate_path != path {
+                gruxi_request.rewrite_uri(&expanded);
+            }
+            return None;
+        }
+    }
+
+    if let Some(status) = parse_try_files_status(fallback) {
+        trace(format!("[try_files] No entries matched request path '{}', answering {}", path, status));
+        return Some(GruxiResponse::new_empty_with_status(status));
+    }
+
+    let expanded = expand_try_files_entry(fallback, &path, &query);
+    trace(format!("[try_files] No entries matched request path '{}', rewriting to fallback '{}'", path, expanded));
+    if !gruxi_request.rewrite_uri(&expanded) {
+        trace(format!("[try_files] Fallback '{}' is not a valid request path, answering 404", expanded));
+        return Some(GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16()));
+    }
+    None
+}
+
+// History API fallback for single-page apps: GET and HEAD requests for paths that are not files or directories in the web roots
+// are rewritten to /index.html, so client-side routers can handle them. Paths that look like files (a dot in the last segment),
+// such as a missing /app.js, and the excluded path prefixes of the site, such as "/api/", are left untouched
+pub async fn apply_spa_fallback(gruxi_request: &mut GruxiRequest, site: &Site, running_state: &RunningState) {
+    if !site.spa_fallback_enabled {
+        return;
+    }
+
+    let http_method = gruxi_request.get_http_method();
+    if http_method != "GET" && http_method != "HEAD" {
+        return;
+    }
+
+    let path = gruxi_request.get_path();
+    if !is_spa_route(&path, &site.spa_fallback_excluded_paths) {
+        return;
+    }
+
+    let web_roots = running_state.get_request_handler_manager().get_site_web_roots(site, running_state.get_processor_manager()).await;
+    if find_in_web_roots(&web_roots, &path).await.is_some() {
+        return;
+    }
+
+    trace(format!("[spa_fallback] Request path '{}' is not a file, rewriting to '{}'", path, SPA_FALLBACK_PATH));
+    let query = gruxi_request.get_query();
+    let fallback = if query.is_empty() {
+        SPA_FALLBACK_PATH.to_string()
+    } else {
+        format!("{}?{}", SPA_FALLBACK_PATH, query)
+    };
+    gruxi_request.rewrite_uri(&fallback);
+}
+
+fn is_spa_route(path: &str, excluded_paths: &[String]) -> bool {
+    let last_segment = path.rsplit('/').next().unwrap_or("");
+    !last_segment.contains('.') && !excluded_paths.iter().any(|excluded| path.starts_with(excluded.as_str()))
+}
+
+fn split_path_and_query(uri: &str) -> (&str, &str) {
+    match uri.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (uri, ""),
+    }
+}
+
+async fn candidate_exists(web_roots: &[String], candidate_path: &str) -> bool {
+    let wants_directory = candidate_path.ends_with('/');
+    find_in_web_roots(web_roots, candidate_path).await == Some(wants_directory)
+}
+
+// Looks up the path in the web roots in order, returning whether the first match is a directory
+pub async fn find_in_web_roots(web_roots: &[String], path: &str) -> Option<bool> {
+    for web_root in web_roots {
+        let normalized_path = match NormalizedPath::new(web_root, path) {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        if let Ok(file_data) = resolve_web_root_and_path_and_get_file(&normalized_path).await
+            && file_data.meta.exists
+        {
+            return Some(file_data.meta.is_directory);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_try_files_entry() {
+        assert_eq!(expand_try_files_entry("$uri", "/about", "a=1"), "/about");
+        assert_eq!(expand_try_files_entry("$uri/", "/about", ""), "/about/");
+        assert_eq!(expand_try_files_entry("/index.php?$query", "/users/5", "page=2"), "/index.php?page=2");
+        assert_eq!(split_path_and_query("/index.php?page=2"), ("/index.php", "page=2"));
+        assert_eq!(split_path_and_query("/about"), ("/about", ""));
+    }
+
+    #[test]
+    fn test_parse_try_files_status() {
+        assert_eq!(parse_try_files_status("=404"), Some(404));
+        assert_eq!(parse_try_files_status("=503"), Some(503));
+        assert_eq!(parse_try_files_status("=200"), None);
+        assert_eq!(parse_try_files_status("/index.html"), None);
+        assert_eq!(parse_try_files_status("=abc"), None);
+    }
+
+    #[test]
+    fn test_is_spa_route() {
+        let excluded_paths = vec!["/api/".to_string()];
+        assert!(is_spa_route("/", &excluded_paths));
+        assert!(is_spa_route("/users/5/edit", &excluded_paths));
+        assert!(!is_spa_route("/assets/app.js", &excluded_paths));
+        assert!(!is_spa_route("/api/users", &excluded_paths));
+        assert!(is_spa_route("/apidocs", &excluded_paths));
+    }
+}
@@ -0,0 +1,377 @@
+use crate::configuration::site::Site;
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::logging::syslog::{debug, trace};
+use hyper::body::Bytes;
+use hyper::header::HeaderValue;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::AsyncWriteExt;
+
+// Request bodies larger than this are spooled to a temporary file instead of being kept in memory
+pub const UPLOAD_SPOOL_THRESHOLD_BYTES: usize = 1024 * 1024;
+// Path where the progress of uploads can be polled on sites that enable it, like /gruxi-upload-progress?X-Progress-ID=<id>
+pub const UPLOAD_PROGRESS_PATH: &str = "/gruxi-upload-progress";
+// Query parameter or header that identifies an upload, same naming as the nginx upload progress module
+pub const UPLOAD_PROGRESS_ID_PARAMETER: &str = "X-Progress-ID";
+// How long progress for finished uploads can still be polled
+const FINISHED_UPLOAD_RETENTION: Duration = Duration::from_secs(60);
+// Spool files of other processes not written to for this long were left behind by a crash
+const STALE_SPOOL_FILE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadState {
+    Uploading,
+    Done,
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UploadProgress {
+    pub state: UploadState,
+    pub received: u64,
+    pub size: u64,
+    #[serde(skip)]
+    finished_at: Option<Instant>,
+}
+
+// Request body as read from the client, either in memory or spooled to disk
+pub enum SpooledBody {
+    Memory(Bytes),
+    File(SpoolFile),
+}
+
+impl SpooledBody {
+    pub fn len(&self) -> u64 {
+        match self {
+            SpooledBody::Memory(bytes) => bytes.len() as u64,
+            SpooledBody::File(spool_file) => spool_file.size,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Temporary file holding a request body, removed when dropped, so both completed and aborted requests clean up
+pub struct SpoolFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            debug(format!("Failed to remove upload spool file {}: {}", self.path.display(), e));
+        }
+    }
+}
+
+pub struct UploadManager {
+    uploads: Mutex<HashMap<String, UploadProgress>>,
+    spool_directory: PathBuf,
+    spool_threshold: usize,
+}
+
+impl UploadManager {
+    // Bodies are spooled to a directory of this process within the spool directory, as another Gruxi, such as the one taking over
+    // in a binary upgrade, may be spooling uploads next to it
+    pub fn new(spool_base_directory: PathBuf, spool_threshold: usize) -> Self {
+        let spool_directory = spool_base_directory.join(std::process::id().to_string());
+        remove_stale_spool_files(&spool_base_directory, &spool_directory);
+
+        UploadManager {
+            uploads: Mutex::new(HashMap::new()),
+            spool_directory,
+            spool_threshold,
+        }
+    }
+
+    pub fn start_upload(&self, upload_id: &str, size: u64) {
+        if let Ok(mut uploads) = self.uploads.lock() {
+            // Clean up progress for uploads that finished a while ago
+            uploads.retain(|_, progress| progress.finished_at.is_none_or(|finished_at| finished_at.elapsed() < FINISHED_UPLOAD_RETENTION));
+            uploads.insert(
+                upload_id.to_string(),
+                UploadProgress {
+                    state: UploadState::Uploading,
+                    received: 0,
+                    size,
+                    finished_at: None,
+                },
+            );
+        }
+    }
+
+    pub fn update_upload(&self, upload_id: &str, received: u64) {
+        if let Ok(mut uploads) = self.uploads.lock()
+            && let Some(progress) = uploads.get_mut(upload_id)
+        {
+            progress.received = received;
+        }
+    }
+
+    pub fn finish_upload(&self, upload_id: &str, success: bool) {
+        if let Ok(mut uploads) = self.uploads.lock()
+            && let Some(progress) = uploads.get_mut(upload_id)
+        {
+            progress.state = if success { UploadState::Done } else { UploadState::Error };
+            progress.finished_at = Some(Instant::now());
+        }
+    }
+
+    pub fn get_progress(&self, upload_id: &str) -> Option<UploadProgress> {
+        self.uploads.lock().ok().and_then(|uploads| uploads.get(upload_id).cloned())
+    }
+
+    // Progress as returned by the progress endpoint. Unknown uploads are reported as "starting", as the poll may arrive before the upload
+    pub fn get_progress_json(&self, upload_id: &str) -> serde_json::Value {
+        match self.get_progress(upload_id) {
+            Some(progress) => serde_json::to_value(progress).unwrap_or_else(|_| serde_json::json!({ "state": "error" })),
+            None => serde_json::json!({ "state": "starting" }),
+        }
+    }
+
+    // Reads the whole request body, keeping small bodies in memory and spooling larger ones to a temporary file.
+    // Progress is tracked if the site has upload progress enabled and the client supplied an upload id
+    pub async fn spool_request_body(&self, gruxi_request: &mut GruxiRequest, site: &Site) -> Result<SpooledBody, std::io::Error> {
+        let upload_id = if site.upload_progress_enabled {
+            get_upload_id(gruxi_request).map(|upload_id| get_site_upload_id(site, &upload_id))
+        } else {
+            None
+        };
+        let expected_size = gruxi_request.get_body_size();
+
+        if let Some(upload_id) = &upload_id {
+            self.start_upload(upload_id, expected_size);
+        }
+
+        let result = self.read_body(gruxi_request, upload_id.as_deref()).await;
+
+        if let Some(upload_id) = &upload_id {
+            self.finish_upload(upload_id, result.is_ok());
+        }
+        result
+    }
+
+    async fn read_body(&self, gruxi_request: &mut GruxiRequest, upload_id: Option<&str>) -> Result<SpooledBody, std::io::Error> {
+        let mut memory_buffer: Vec<u8> = Vec::new();
+        let mut spool: Option<(tokio::fs::File, SpoolFile)> = None;
+        let mut received: u64 = 0;
+
+        while let Some(chunk) = gruxi_request.read_body_chunk().await {
+            let chunk = chunk.map_err(std::io::Error::other)?;
+            received += chunk.len() as u64;
+
+            if let Some(upload_id) = upload_id {
+                self.update_upload(upload_id, received);
+            }
+
+            match spool.as_mut() {
+                Some((file, _)) => file.write_all(&chunk).await?,
+                None => {
+                    memory_buffer.extend_from_slice(&chunk);
+                    if memory_buffer.len() > self.spool_threshold {
+                        let (mut file, spool_file) = self.create_spool_file().await?;
+                        file.write_all(&memory_buffer).await?;
+                        memory_buffer = Vec::new();
+                        spool = Some((file, spool_file));
+                    }
+                }
+            }
+        }
+
+        match spool {
+            Some((mut file, mut spool_file)) => {
+                file.flush().await?;
+                spool_file.size = received;
+                trace(format!("Spooled request body of {} bytes to {}", received, spool_file.path.display()));
+                Ok(SpooledBody::File(spool_file))
+            }
+            None => Ok(SpooledBody::Memory(Bytes::from(memory_buffer))),
+        }
+    }
+
+    async fn create_spool_file(&self) -> Result<(tokio::fs::File, SpoolFile), std::io::Error> {
+        tokio::fs::create_dir_all(&self.spool_directory).await?;
+        let path = self.spool_directory.join(format!("{}.upload", uuid::Uuid::new_v4()));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok((file, SpoolFile { path, size: 0 }))
+    }
+}
+
+// Answers a poll on the upload progress path with the progress of the upload to the site given by the X-Progress-ID
+pub fn handle_upload_progress_request(gruxi_request: &mut GruxiRequest, site: &Site) -> GruxiResponse {
+    let upload_id = match get_upload_id(gruxi_request) {
+        Some(upload_id) => upload_id,
+        None => return GruxiResponse::new_empty_with_status(hyper::StatusCode::BAD_REQUEST.as_u16()),
+    };
+
+    let json = get_upload_manager().get_progress_json(&get_site_upload_id(site, &upload_id));
+    let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), Bytes::from(json.to_string()));
+    response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response.headers_mut().insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+// Gets the upload id from the query string or the header, if the client wants progress tracked
+pub fn get_upload_id(gruxi_request: &mut GruxiRequest) -> Option<String> {
+    let query = gruxi_request.get_query();
+    let from_query = query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key.eq_ignore_ascii_case(UPLOAD_PROGRESS_ID_PARAMETER) {
+            urlencoding::decode(value).ok().map(|v| v.to_string())
+        } else {
+            None
+        }
+    });
+    let upload_id = from_query.or_else(|| gruxi_request.get_headers().get(UPLOAD_PROGRESS_ID_PARAMETER).and_then(|v| v.to_str().ok()).map(|v| v.to_string()))?;
+
+    let upload_id = upload_id.trim().to_string();
+    if upload_id.is_empty() || upload_id.len() > 128 { None } else { Some(upload_id) }
+}
+
+// Uploads are tracked per site, so an upload id guessed on one site tells nothing about the uploads to another
+fn get_site_upload_id(site: &Site, upload_id: &str) -> String {
+    format!("{}/{}", site.id, upload_id)
+}
+
+// Removes the spool directory of a process that had the id of this one before, and the files of other processes that have not been
+// written to for a long time, along with their directories once empty
+fn remove_stale_spool_files(spool_base_directory: &std::path::Path, spool_directory: &std::path::Path) {
+    if spool_directory.exists() {
+        let _ = std::fs::remove_dir_all(spool_directory);
+    }
+    let Ok(process_directories) = std::fs::read_dir(spool_base_directory) else {
+        return;
+    };
+    for process_directory in process_directories.flatten().map(|entry| entry.path()).filter(|path| path.is_dir()) {
+        for spool_file in std::fs::read_dir(&process_directory).into_iter().flatten().flatten() {
+            let is_stale = spool_file
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| SystemTime::now().duration_since(modified).is_ok_and(|age| age > STALE_SPOOL_FILE_AGE));
+            if is_stale && let Err(e) = std::fs::remove_file(spool_file.path()) {
+                debug(format!("Failed to remove stale upload spool file {}: {}", spool_file.path().display(), e));
+            }
+        }
+        // Fails while the directory still has files
+        let _ = std::fs::remove_dir(&process_directory);
+    }
+}
+
+static UPLOAD_MANAGER_SINGLETON: OnceLock<UploadManager> = OnceLock::new();
+
+pub fn get_upload_manager() -> &'static UploadManager {
+    UPLOAD_MANAGER_SINGLETON.get_or_init(|| UploadManager::new(std::env::temp_dir().join("gruxi-uploads"), UPLOAD_SPOOL_THRESHOLD_BYTES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_request(body: &'static [u8], uri: &str, content_type: &str) -> GruxiRequest {
+        let request = hyper::Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("Host", "localhost")
+            .header("Content-Type", content_type)
+            .body(Bytes::from_static(body))
+            .unwrap();
+        GruxiRequest::new(request)
+    }
+
+    fn create_manager(name: &str, threshold: usize) -> UploadManager {
+        UploadManager::new(PathBuf::from("./temp_test_data").join(name), threshold)
+    }
+
+    #[tokio::test]
+    async fn test_small_body_stays_in_memory() {
+        let manager = create_manager("upload_memory", 1024);
+        let mut request = create_request(b"hello", "/upload.php", "text/plain");
+
+        let body = manager.spool_request_body(&mut request, &Site::new()).await.unwrap();
+        assert!(matches!(body, SpooledBody::Memory(ref bytes) if bytes.as_ref() == b"hello"));
+        assert_eq!(body.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_large_body_is_spooled_and_removed() {
+        let manager = create_manager("upload_spool", 4);
+        let mut request = create_request(b"more than four bytes", "/upload.php?X-Progress-ID=abc", "text/plain");
+        let mut site = Site::new();
+        site.upload_progress_enabled = true;
+
+        let body = manager.spool_request_body(&mut request, &site).await.unwrap();
+        let path = match &body {
+            SpooledBody::File(spool_file) => spool_file.path.clone(),
+            SpooledBody::Memory(_) => panic!("Expected body to be spooled to disk"),
+        };
+        assert_eq!(std::fs::read(&path).unwrap(), b"more than four bytes");
+        assert_eq!(body.len(), 20);
+
+        let progress = manager.get_progress(&get_site_upload_id(&site, "abc")).unwrap();
+        assert_eq!(progress.state, UploadState::Done);
+        assert_eq!(progress.received, 20);
+
+        drop(body);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_progress_is_only_tracked_for_sites_enabling_it() {
+        let manager = create_manager("upload_progress_disabled", 1024);
+        let site = Site::new();
+        let mut request = create_request(b"hello", "/upload.php?X-Progress-ID=abc", "text/plain");
+
+        manager.spool_request_body(&mut request, &site).await.unwrap();
+        assert!(manager.get_progress(&get_site_upload_id(&site, "abc")).is_none());
+    }
+
+    #[test]
+    fn test_only_stale_spool_files_of_other_processes_are_removed() {
+        let spool_base_directory = PathBuf::from("./temp_test_data").join(format!("upload_stale_{}", uuid::Uuid::new_v4()));
+        let other_process_directory = spool_base_directory.join("1");
+        std::fs::create_dir_all(&other_process_directory).unwrap();
+        let in_flight_path = other_process_directory.join("in-flight.upload");
+        std::fs::write(&in_flight_path, b"data").unwrap();
+        let stale_path = other_process_directory.join("stale.upload");
+        let stale_file = std::fs::File::create(&stale_path).unwrap();
+        stale_file.set_modified(SystemTime::now() - STALE_SPOOL_FILE_AGE * 2).unwrap();
+        drop(stale_file);
+
+        let manager = UploadManager::new(spool_base_directory.clone(), 1024);
+        assert_eq!(manager.spool_directory, spool_base_directory.join(std::process::id().to_string()));
+        assert!(in_flight_path.exists());
+        assert!(!stale_path.exists());
+        let _ = std::fs::remove_dir_all(&spool_base_directory);
+    }
+
+    #[test]
+    fn test_progress_json_has_no_file_names() {
+        let manager = create_manager("upload_json", 1024);
+        manager.start_upload("abc", 10);
+        manager.update_upload("abc", 4);
+        assert_eq!(manager.get_progress_json("abc"), serde_json::json!({ "state": "uploading", "received": 4, "size": 10 }));
+    }
+
+    #[test]
+    fn test_progress_json_for_unknown_upload() {
+        let manager = create_manager("upload_unknown", 1024);
+        assert_eq!(manager.get_progress_json("nope"), serde_json::json!({ "state": "starting" }));
+    }
+
+    #[test]
+    fn test_get_upload_id() {
+        let mut request = create_request(b"", "/upload.php?a=1&X-Progress-ID=my%20id", "text/plain");
+        assert_eq!(get_upload_id(&mut request), Some("my id".to_string()));
+
+        let mut request = create_request(b"", "/upload.php", "text/plain");
+        assert_eq!(get_upload_id(&mut request), None);
+    }
+}
@@ -0,0 +1,154 @@
+use unicode_general_category::{GeneralCategory, get_general_category};
+use unicode_normalization::UnicodeNormalization;
+use urlencoding::decode;
+
+// Percent-encoding is decoded repeatedly, so double encoded input like "%252e" cannot sneak dot segments past the checks
+const MAX_DECODE_ROUNDS: usize = 10;
+
+// Decodes a URL path into the characters it stands for: percent-decoding until nothing changes, NFC normalization and
+// backslashes as slashes. Invalid UTF-8 (including overlong encodings like "%c0%ae"), Unicode format and control characters
+// and characters that look like slashes or dots are rejected. Segments are left as they are, see canonicalize_url_path()
+pub fn decode_url_path(path: &str) -> Result<String, String> {
+    let mut decoded = path.to_string();
+    let mut is_stable = false;
+    for _ in 0..MAX_DECODE_ROUNDS {
+        // Decoding fails on byte sequences that are not valid UTF-8, which is where overlong encodings end up
+        let new_decoded = match decode(&decoded) {
+            Ok(d) => d.to_string(),
+            Err(_) => return Err("Failed to decode percent-encoded characters".to_string()),
+        };
+        if new_decoded == decoded {
+            is_stable = true;
+            break;
+        }
+        decoded = new_decoded;
+    }
+    if !is_stable {
+        return Err("Path is percent-encoded too many times".to_string());
+    }
+
+    // Handle unicode normalization
+    let buf: String = decoded.nfc().collect();
+    for ch in buf.chars() {
+        // Reject Unicode format characters (Cf)
+        let gc = get_general_category(ch);
+        if gc == GeneralCategory::Format {
+            return Err("Path contains forbidden Unicode format characters".to_string());
+        }
+        if gc == GeneralCategory::Control {
+            return Err("Path contains forbidden Unicode control characters".to_string());
+        }
+
+        // Reject confusable slashes or dots
+        if matches!(
+            ch,
+            // Slash-like
+            '\u{2215}' | // ∕ division slash
+            '\u{2044}' | // ⁄ fraction slash
+            '\u{FF0F}' | // ／ fullwidth solidus
+            '\u{29F8}' | // ⧸ big solidus
+            '\u{FE68}' | // ﹨ small reverse solidus
+
+            // Dot-like
+            '\u{FF0E}' | // ． fullwidth full stop
+            '\u{3002}' | // 。 ideographic full stop
+            '\u{2219}' | // ∙ bullet operator
+            '\u{22C5}' // ⋅ dot operator
+        ) {
+            return Err("Path contains confusable slash or dot characters".to_string());
+        }
+    }
+
+    // Return error on ascii control characters and NUL characters
+    if buf.chars().any(|c| c.is_control() || c == '\0') {
+        return Err("Path contains ASCII control characters or NUL characters".to_string());
+    }
+
+    // Windows treats backslashes as separators, so they are treated as such everywhere
+    Ok(buf.replace('\\', "/"))
+}
+
+// The canonical form of a URL path, which routing, static files and PHP all work on: decoded with decode_url_path(), duplicate
+// slashes collapsed and "." and ".." segments removed. A path that climbs above the root is rejected rather than clamped.
+// The result always starts with "/" and keeps a trailing slash, as that tells directories apart
+pub fn canonicalize_url_path(path: &str) -> Result<String, String> {
+    let decoded = decode_url_path(path)?;
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => {
+                if segments.pop().is_none() {
+                    return Err("Path traversal above the root is not allowed".to_string());
+                }
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut canonical = format!("/{}", segments.join("/"));
+    let last_segment = decoded.rsplit('/').next().unwrap_or("");
+    let is_directory = decoded.ends_with('/') || last_segment == "." || last_segment == "..";
+    if is_directory && !segments.is_empty() {
+        canonical.push('/');
+    }
+    Ok(canonical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize_url_path() {
+        assert_eq!(canonicalize_url_path("/"), Ok("/".to_string()));
+        assert_eq!(canonicalize_url_path(""), Ok("/".to_string()));
+        assert_eq!(canonicalize_url_path("/images//css///style.css"), Ok("/images/css/style.css".to_string()));
+        assert_eq!(canonicalize_url_path("/images/./css/../js/app.js"), Ok("/images/js/app.js".to_string()));
+        assert_eq!(canonicalize_url_path("/images/css/.."), Ok("/images/".to_string()));
+        assert_eq!(canonicalize_url_path("/docs/"), Ok("/docs/".to_string()));
+        assert_eq!(canonicalize_url_path("/my%20file.txt"), Ok("/my file.txt".to_string()));
+        assert_eq!(canonicalize_url_path("/images\\css\\style.css"), Ok("/images/css/style.css".to_string()));
+        assert_eq!(canonicalize_url_path("/style\u{0301}.css"), Ok("/stylé.css".to_string()));
+        assert_eq!(canonicalize_url_path("/a/%252e%252e/b"), Ok("/b".to_string()));
+    }
+
+    #[test]
+    fn test_canonicalize_url_path_traversal_attempts() {
+        for path in [
+            "/..",
+            "/../etc/passwd",
+            "/images/../../etc/passwd",
+            "/%2e%2e%2f%2e%2e%2fetc/passwd",
+            "/%2E%2E/",
+            "/images/%2e%2e%2f%2e%2e%2fetc%2fpasswd",
+            "/%252e%252e%252fetc/passwd",
+            "/images/..\\..\\windows\\system.ini",
+            "/images/%5c..%5c..%5cwindows",
+        ] {
+            assert!(canonicalize_url_path(path).is_err(), "Expected {} to be rejected", path);
+        }
+    }
+
+    #[test]
+    fn test_decode_url_path_rejects_invalid_encodings() {
+        // Overlong UTF-8 for "." and "/", which some decoders have turned into traversals
+        assert!(decode_url_path("/%c0%ae%c0%ae%c0%af").is_err());
+        assert!(decode_url_path("/%e0%80%ae%e0%80%ae/").is_err());
+        assert!(decode_url_path("/%c0%2e%c0%2e/").is_err());
+        // Invalid UTF-8 and control characters
+        assert!(decode_url_path("/%ff").is_err());
+        assert!(decode_url_path("/a%00b").is_err());
+        assert!(decode_url_path("/a%0d%0ab").is_err());
+        // Unicode format characters and lookalikes of dots and slashes
+        assert!(decode_url_path("/a%E2%80%AEb").is_err());
+        assert!(decode_url_path("/%EF%BC%8E%EF%BC%8E/secret").is_err());
+        // Encoded more times than we decode
+        let mut encoded = "%2e".to_string();
+        for _ in 0..MAX_DECODE_ROUNDS {
+            encoded = encoded.replacen('%', "%25", 1);
+        }
+        assert!(decode_url_path(&encoded).is_err());
+    }
+}
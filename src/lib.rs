@@ -1,12 +1,18 @@
-pub mod file;
-pub mod external_connections;
-pub mod network;
+pub mod admin_portal;
+pub mod compression;
+pub mod configuration;
 pub mod core;
+pub mod database;
+pub mod deployment;
+pub mod error;
+pub mod external_connections;
+pub mod file;
 pub mod http;
-pub mod admin_portal;
 pub mod logging;
-pub mod configuration;
+pub mod network;
 pub mod tls;
-pub mod error;
-pub mod compression;
-pub mod database;
+
+pub mod test_support;
+
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
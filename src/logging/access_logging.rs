@@ -102,6 +102,15 @@ impl AccessLogBuffer {
             }
         };
 
+        let reopen_logs_token_option = triggers.get_token("reopen_logs").await;
+        let mut reopen_logs_token = match reopen_logs_token_option {
+            Some(token) => token,
+            None => {
+                error("Failed to get reopen_logs token - Could not start flushing thread for access logging. Please report a bug".to_string());
+                return;
+            }
+        };
+
         let running_state = get_running_state_manager().await.get_running_state_unlocked().await;
 
         loop {
@@ -130,6 +139,24 @@ impl AccessLogBuffer {
                     }
                     break;
                 },
+                _ = reopen_logs_token.cancelled() => {
+                    let access_log_buffer_rwlock = running_state.get_access_log_buffer();
+                    let access_log_buffer = access_log_buffer_rwlock.read().await;
+
+                    for (_site_id, log) in access_log_buffer.buffered_logs.iter() {
+                        log.reopen();
+                    }
+
+                    // Get new token for next time
+                    let reopen_logs_token_option = triggers.get_token("reopen_logs").await;
+                    reopen_logs_token = match reopen_logs_token_option {
+                        Some(token) => token,
+                        None => {
+                            error("Failed to get reopen_logs token - Could not continue flushing thread for access logging. Please report a bug".to_string());
+                            return;
+                        }
+                    };
+                },
                 _ = stop_services_token.cancelled() => {
                     trace("Access log write thread received stop services signal, so flushing remaining logs and exiting".to_string());
                     let access_log_buffer_rwlock = running_state.get_access_log_buffer();
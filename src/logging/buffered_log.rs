@@ -53,7 +53,16 @@ impl BufferedLog {
         let buffered_log_lock = self.buffered_log.lock();
         match buffered_log_lock {
             Ok(mut guard) => guard.push(log),
-            Err(_) => {}, // We silently fail to add log if we cant get the lock
+            Err(_) => {} // We silently fail to add log if we cant get the lock
+        }
+    }
+
+    // The log file is opened for each flush, so after an external rotation the next flush writes to a new file at the path.
+    // Reopening flushes right away and creates the new file, so it is there as soon as the rotation is done
+    pub fn reopen(&self) {
+        self.consider_flush(true);
+        if let Err(e) = std::fs::OpenOptions::new().create(true).append(true).open(&self.log_file_path) {
+            eprintln!("Failed to reopen log file {}: {}", &self.log_file_path, e);
         }
     }
 
@@ -81,7 +90,7 @@ impl BufferedLog {
                     if elapsed < self.seconds_before_force_flush && log_buffer.len() < self.log_count_flush {
                         return;
                     }
-                },
+                }
                 Err(_) => return, // If we cant get the lock, we skip flushing
             }
         }
@@ -101,8 +110,8 @@ impl BufferedLog {
         match last_flush_lock {
             Ok(mut guard) => {
                 *guard = Instant::now();
-            },
-            Err(_) => {}, // If we cant get the lock, we skip updating last flush time
+            }
+            Err(_) => {} // If we cant get the lock, we skip updating last flush time
         }
     }
 }
@@ -128,4 +137,21 @@ mod tests {
             Err(e) => panic!("Failed to read created log file: {}", e),
         }
     }
+
+    #[test]
+    fn test_buffered_log_reopen_after_rotation() {
+        let log = BufferedLog::new("test_log".to_string(), "./temp_test_data/test_rotated.log".to_string());
+        log.buffered_log.lock().unwrap().push("before rotation".to_string());
+        log.consider_flush(true);
+
+        // Rotate the file away, like logrotate does before signalling
+        std::fs::rename(&log.log_file_path, "./temp_test_data/test_rotated.log.1").unwrap();
+        log.buffered_log.lock().unwrap().push("after rotation".to_string());
+        log.reopen();
+
+        assert_eq!(std::fs::read_to_string("./temp_test_data/test_rotated.log.1").unwrap(), "before rotation\n");
+        assert_eq!(std::fs::read_to_string(&log.log_file_path).unwrap(), "after rotation\n");
+        let _ = std::fs::remove_file("./temp_test_data/test_rotated.log.1");
+        let _ = std::fs::remove_file(&log.log_file_path);
+    }
 }
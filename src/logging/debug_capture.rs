@@ -0,0 +1,213 @@
+use chrono::Utc;
+use hyper::HeaderMap;
+use hyper::body::Bytes;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use utoipa::ToSchema;
+
+// Number of request/response pairs we keep per site, oldest are dropped first
+pub const DEBUG_CAPTURE_MAX_ENTRIES_PER_SITE: usize = 100;
+// Bodies are truncated to this size in the capture
+pub const DEBUG_CAPTURE_MAX_BODY_BYTES: usize = 16 * 1024;
+// Headers carrying credentials, which are captured without their values as captures are served back by the admin API
+const DEBUG_CAPTURE_REDACTED_HEADERS: [&str; 4] = ["authorization", "proxy-authorization", "cookie", "set-cookie"];
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct CapturedBody {
+    pub content: String,
+    pub size: u64,
+    pub truncated: bool,
+    pub captured: bool,
+}
+
+impl CapturedBody {
+    pub fn from_bytes(bytes: &Bytes) -> Self {
+        let truncated = bytes.len() > DEBUG_CAPTURE_MAX_BODY_BYTES;
+        let captured_bytes = if truncated { &bytes[..DEBUG_CAPTURE_MAX_BODY_BYTES] } else { &bytes[..] };
+        CapturedBody {
+            content: String::from_utf8_lossy(captured_bytes).to_string(),
+            size: bytes.len() as u64,
+            truncated,
+            captured: true,
+        }
+    }
+
+    // Used for bodies we do not buffer, such as large or unbounded streams
+    pub fn not_captured(size: u64) -> Self {
+        CapturedBody {
+            content: String::new(),
+            size,
+            truncated: false,
+            captured: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct CapturedExchange {
+    pub timestamp: String,
+    pub remote_ip: String,
+    pub method: String,
+    pub uri: String,
+    pub http_version: String,
+    #[schema(value_type = Vec<Vec<String>>)]
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: CapturedBody,
+    pub response_status: u16,
+    #[schema(value_type = Vec<Vec<String>>)]
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: CapturedBody,
+    #[schema(value_type = u64)]
+    pub duration_ms: u128,
+    // Output of PHP and other FastCGI backends on STDERR, such as warnings and notices
+    pub fastcgi_stderr: String,
+}
+
+// Ring buffer of captured request/response pairs, keyed by site ID
+pub struct DebugCapture {
+    captures: Mutex<HashMap<String, VecDeque<CapturedExchange>>>,
+    max_entries_per_site: usize,
+}
+
+impl DebugCapture {
+    pub fn new(max_entries_per_site: usize) -> Self {
+        DebugCapture {
+            captures: Mutex::new(HashMap::new()),
+            max_entries_per_site,
+        }
+    }
+
+    pub fn add_capture(&self, site_id: &str, exchange: CapturedExchange) {
+        let mut captures = match self.captures.lock() {
+            Ok(guard) => guard,
+            Err(_) => return, // We silently skip the capture if we cant get the lock
+        };
+
+        let site_captures = captures.entry(site_id.to_string()).or_default();
+        while site_captures.len() >= self.max_entries_per_site {
+            site_captures.pop_front();
+        }
+        site_captures.push_back(exchange);
+    }
+
+    // Returns the captures for a site, newest first
+    pub fn get_captures(&self, site_id: &str) -> Vec<CapturedExchange> {
+        match self.captures.lock() {
+            Ok(captures) => captures.get(site_id).map(|c| c.iter().rev().cloned().collect()).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // Returns site ID and number of captures held for each site
+    pub fn get_capture_counts(&self) -> Vec<(String, usize)> {
+        match self.captures.lock() {
+            Ok(captures) => captures.iter().map(|(site_id, c)| (site_id.clone(), c.len())).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn clear_captures(&self, site_id: &str) {
+        if let Ok(mut captures) = self.captures.lock() {
+            captures.remove(site_id);
+        }
+    }
+}
+
+pub fn headers_to_vec(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(k, v)| {
+            let value = if DEBUG_CAPTURE_REDACTED_HEADERS.contains(&k.as_str()) {
+                "[redacted]".to_string()
+            } else {
+                String::from_utf8_lossy(v.as_bytes()).to_string()
+            };
+            (k.as_str().to_string(), value)
+        })
+        .collect()
+}
+
+pub fn get_capture_timestamp() -> String {
+    Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+static DEBUG_CAPTURE_SINGLETON: OnceLock<DebugCapture> = OnceLock::new();
+
+pub fn get_debug_capture() -> &'static DebugCapture {
+    DEBUG_CAPTURE_SINGLETON.get_or_init(|| DebugCapture::new(DEBUG_CAPTURE_MAX_ENTRIES_PER_SITE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_exchange(uri: &str) -> CapturedExchange {
+        CapturedExchange {
+            timestamp: get_capture_timestamp(),
+            remote_ip: "127.0.0.1".to_string(),
+            method: "GET".to_string(),
+            uri: uri.to_string(),
+            http_version: "HTTP/1.1".to_string(),
+            request_headers: vec![],
+            request_body: CapturedBody::from_bytes(&Bytes::new()),
+            response_status: 200,
+            response_headers: vec![],
+            response_body: CapturedBody::not_captured(0),
+            fastcgi_stderr: String::new(),
+            duration_ms: 1,
+        }
+    }
+
+    #[test]
+    fn test_debug_capture_ring_buffer_drops_oldest() {
+        let capture = DebugCapture::new(3);
+        for i in 0..5 {
+            capture.add_capture("site1", create_exchange(&format!("/{}", i)));
+        }
+
+        let captures = capture.get_captures("site1");
+        assert_eq!(captures.len(), 3);
+        assert_eq!(captures[0].uri, "/4");
+        assert_eq!(captures[2].uri, "/2");
+        assert!(capture.get_captures("site2").is_empty());
+    }
+
+    #[test]
+    fn test_credential_headers_are_redacted() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer abc".parse().unwrap());
+        headers.insert("cookie", "session=abc".parse().unwrap());
+        headers.insert("set-cookie", "session=abc; HttpOnly".parse().unwrap());
+        headers.insert("content-type", "text/html".parse().unwrap());
+
+        let captured = headers_to_vec(&headers);
+        assert!(captured.iter().filter(|(name, _)| name != "content-type").all(|(_, value)| value == "[redacted]"));
+        assert!(captured.contains(&("content-type".to_string(), "text/html".to_string())));
+    }
+
+    #[test]
+    fn test_debug_capture_clear() {
+        let capture = DebugCapture::new(10);
+        capture.add_capture("site1", create_exchange("/"));
+        capture.add_capture("site2", create_exchange("/"));
+        capture.clear_captures("site1");
+
+        assert!(capture.get_captures("site1").is_empty());
+        assert_eq!(capture.get_capture_counts(), vec![("site2".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_captured_body_truncation() {
+        let bytes = Bytes::from(vec![b'a'; DEBUG_CAPTURE_MAX_BODY_BYTES + 10]);
+        let body = CapturedBody::from_bytes(&bytes);
+        assert!(body.truncated);
+        assert!(body.captured);
+        assert_eq!(body.size, (DEBUG_CAPTURE_MAX_BODY_BYTES + 10) as u64);
+        assert_eq!(body.content.len(), DEBUG_CAPTURE_MAX_BODY_BYTES);
+
+        let small = CapturedBody::from_bytes(&Bytes::from_static(b"hello"));
+        assert!(!small.truncated);
+        assert_eq!(small.content, "hello");
+    }
+}
@@ -0,0 +1,458 @@
+use crate::logging::syslog::{LogType, info, warn};
+use crate::network::dns_cache::get_dns_cache;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tls_listener::rustls as tokio_rustls;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+// Records waiting to be forwarded. When the target cannot keep up, new records are dropped rather than holding up the logging
+const FORWARDING_QUEUE_SIZE: usize = 10_000;
+// How long to wait before connecting again to a syslog server that could not be reached, records logged meanwhile are dropped
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+// RFC 5426 receivers should take datagrams of 2048 bytes, longer messages are cut
+const MAX_UDP_MESSAGE_SIZE: usize = 2048;
+// The records are sent with the daemon facility
+const SYSLOG_FACILITY: u8 = 3;
+const SYSLOG_APP_NAME: &str = "gruxi";
+
+static LOG_FORWARDER: OnceLock<LogForwarder> = OnceLock::new();
+
+pub fn get_log_forwarder() -> &'static LogForwarder {
+    LOG_FORWARDER.get_or_init(LogForwarder::new)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyslogTransport {
+    Udp,
+    Tcp,
+    Tls,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteSyslogTarget {
+    pub transport: SyslogTransport,
+    pub host: String,
+    pub port: u16,
+}
+
+// Parses a syslog server address, such as "udp://logs.example.com:514". The port defaults to 514, or 6514 for TLS
+pub fn parse_remote_syslog_address(address: &str) -> Result<RemoteSyslogTarget, String> {
+    let (transport, host_and_port) = if let Some(rest) = address.strip_prefix("udp://") {
+        (SyslogTransport::Udp, rest)
+    } else if let Some(rest) = address.strip_prefix("tcp://") {
+        (SyslogTransport::Tcp, rest)
+    } else if let Some(rest) = address.strip_prefix("tls://") {
+        (SyslogTransport::Tls, rest)
+    } else {
+        return Err(format!("Remote syslog address must start with udp://, tcp:// or tls://: {}", address));
+    };
+    let default_port = if transport == SyslogTransport::Tls { 6514 } else { 514 };
+
+    // IPv6 addresses are given in brackets, as "[::1]:514"
+    let (host, port) = match host_and_port.rfind(':') {
+        Some(idx) if !host_and_port[idx..].contains(']') => {
+            let port = host_and_port[idx + 1..].parse::<u16>().map_err(|_| format!("Remote syslog address has an invalid port: {}", address))?;
+            (&host_and_port[..idx], port)
+        }
+        _ => (host_and_port, default_port),
+    };
+    if host.is_empty() || host.contains('/') || port == 0 {
+        return Err(format!("Remote syslog address must be given as host:port: {}", address));
+    }
+
+    Ok(RemoteSyslogTarget {
+        transport,
+        host: host.to_string(),
+        port,
+    })
+}
+
+// A log record on its way to the targets whose threshold it meets
+struct ForwardedRecord {
+    level: u8,
+    timestamp: String,
+    message: String,
+    is_for_remote_syslog: bool,
+    is_for_event_log: bool,
+}
+
+// Sends the log records, alongside the log file, to a remote syslog server and to the Windows Event Log, each with its own lowest
+// severity. The records are queued and sent from a task of their own, so logging never waits on the network
+pub struct LogForwarder {
+    // Thresholds as LogType values, where 0 (Off) sends nothing
+    remote_syslog_level: AtomicU8,
+    event_log_level: AtomicU8,
+    // The syslog address the running task sends to, along with the queue of the task
+    sender: Mutex<Option<(String, mpsc::Sender<ForwardedRecord>)>>,
+    dropped_records: AtomicU64,
+}
+
+impl LogForwarder {
+    pub fn new() -> Self {
+        LogForwarder {
+            remote_syslog_level: AtomicU8::new(LogType::Off as u8),
+            event_log_level: AtomicU8::new(LogType::Off as u8),
+            sender: Mutex::new(None),
+            dropped_records: AtomicU64::new(0),
+        }
+    }
+
+    // Applies the forwarding settings, starting a new forwarding task when the syslog address changes. Must be called within the runtime
+    pub fn configure(&self, remote_syslog_address: &str, remote_syslog_level: &str, event_log_level: &str) {
+        let remote_syslog_level = if remote_syslog_address.is_empty() { LogType::Off as u8 } else { parse_level(remote_syslog_level) };
+        let event_log_level = if cfg!(windows) { parse_level(event_log_level) } else { LogType::Off as u8 };
+        let is_enabled = remote_syslog_level != LogType::Off as u8 || event_log_level != LogType::Off as u8;
+
+        // Nothing is logged while the lock is held, as logging forwards through it
+        let mut started_for = None;
+        if let Ok(mut sender) = self.sender.lock() {
+            let is_running_for_address = sender.as_ref().is_some_and(|(address, _)| address == remote_syslog_address);
+            if !is_enabled {
+                *sender = None;
+            } else if !is_running_for_address {
+                let target = parse_remote_syslog_address(remote_syslog_address).ok();
+                let (records_sender, records_receiver) = mpsc::channel(FORWARDING_QUEUE_SIZE);
+                tokio::spawn(run_forwarding(target, records_receiver));
+                // Replacing the sender ends the task sending to the previous address
+                *sender = Some((remote_syslog_address.to_string(), records_sender));
+                started_for = Some(remote_syslog_address.to_string());
+            }
+        }
+        self.remote_syslog_level.store(remote_syslog_level, Ordering::Relaxed);
+        self.event_log_level.store(event_log_level, Ordering::Relaxed);
+
+        if let Some(address) = started_for
+            && !address.is_empty()
+        {
+            info(format!("Sending the log to syslog server {}", address));
+        }
+    }
+
+    // Whether records of the level go to any of the targets
+    pub fn accepts(&self, log_type: &LogType) -> bool {
+        let level = log_type.clone() as u8;
+        level <= self.remote_syslog_level.load(Ordering::Relaxed) || level <= self.event_log_level.load(Ordering::Relaxed)
+    }
+
+    pub fn forward(&self, log_type: &LogType, timestamp: &str, message: &str) {
+        let level = log_type.clone() as u8;
+        let record = ForwardedRecord {
+            level,
+            timestamp: timestamp.to_string(),
+            message: message.to_string(),
+            is_for_remote_syslog: level <= self.remote_syslog_level.load(Ordering::Relaxed),
+            is_for_event_log: level <= self.event_log_level.load(Ordering::Relaxed),
+        };
+        if !record.is_for_remote_syslog && !record.is_for_event_log {
+            return;
+        }
+
+        let Ok(sender) = self.sender.lock() else {
+            return;
+        };
+        if let Some((_, sender)) = sender.as_ref()
+            && sender.try_send(record).is_err()
+        {
+            self.dropped_records.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Records not forwarded because the queue was full
+    pub fn get_dropped_records(&self) -> u64 {
+        self.dropped_records.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for LogForwarder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_level(level: &str) -> u8 {
+    LogType::from_name(level).unwrap_or(LogType::Off) as u8
+}
+
+// The syslog severity of a LogType value, error being 3 and debug 7. Trace has no severity of its own and is sent as debug
+fn get_syslog_severity(level: u8) -> u8 {
+    match level {
+        1 => 3,
+        2 => 4,
+        3 => 6,
+        _ => 7,
+    }
+}
+
+// Formats a record as an RFC 5424 syslog message, without structured data or message id
+pub fn format_rfc5424(level: u8, timestamp: &str, hostname: &str, message: &str) -> String {
+    let priority = SYSLOG_FACILITY * 8 + get_syslog_severity(level);
+    format!("<{}>1 {} {} {} {} - - {}", priority, timestamp, hostname, SYSLOG_APP_NAME, std::process::id(), message)
+}
+
+// Frames a message for TCP and TLS with octet counting (RFC 6587 and RFC 5425), as the messages may span lines
+pub fn frame_octet_counted(message: &str) -> Vec<u8> {
+    format!("{} {}", message.len(), message).into_bytes()
+}
+
+// The host name as sent in the messages, "-" when it is unknown or not printable ASCII
+fn get_hostname() -> String {
+    #[cfg(unix)]
+    let hostname = {
+        let mut buffer = [0u8; 256];
+        // SAFETY: The buffer is valid for its length, and gethostname writes at most that many bytes
+        let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+        if result == 0 {
+            let length = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+            String::from_utf8_lossy(&buffer[..length]).to_string()
+        } else {
+            String::new()
+        }
+    };
+    #[cfg(not(unix))]
+    let hostname = std::env::var("COMPUTERNAME").unwrap_or_default();
+
+    if hostname.is_empty() || hostname.len() > 255 || !hostname.bytes().all(|b| b.is_ascii_graphic()) {
+        "-".to_string()
+    } else {
+        hostname
+    }
+}
+
+enum SyslogConnection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl SyslogConnection {
+    async fn connect(target: &RemoteSyslogTarget) -> std::io::Result<Self> {
+        let host_and_port = format!("{}:{}", target.host, target.port);
+        match target.transport {
+            SyslogTransport::Udp => {
+                let host = target.host.trim_start_matches('[').trim_end_matches(']');
+                let address = *get_dns_cache().resolve(host).await?.first().ok_or(std::io::Error::other("The host has no addresses"))?;
+                let socket = UdpSocket::bind(if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }).await?;
+                socket.connect((address, target.port)).await?;
+                Ok(SyslogConnection::Udp(socket))
+            }
+            SyslogTransport::Tcp => Ok(SyslogConnection::Tcp(get_dns_cache().connect(&host_and_port).await?)),
+            SyslogTransport::Tls => {
+                let server_name = ServerName::try_from(target.host.trim_start_matches('[').trim_end_matches(']').to_string()).map_err(std::io::Error::other)?;
+                let stream = get_dns_cache().connect(&host_and_port).await?;
+                let connector = TlsConnector::from(std::sync::Arc::new(crate::tls::tls_config::tls_config()));
+                Ok(SyslogConnection::Tls(Box::new(connector.connect(server_name, stream).await?)))
+            }
+        }
+    }
+
+    async fn send(&mut self, message: &str) -> std::io::Result<()> {
+        match self {
+            SyslogConnection::Udp(socket) => {
+                let mut length = message.len().min(MAX_UDP_MESSAGE_SIZE);
+                while !message.is_char_boundary(length) {
+                    length -= 1;
+                }
+                socket.send(&message.as_bytes()[..length]).await.map(|_| ())
+            }
+            SyslogConnection::Tcp(stream) => stream.write_all(&frame_octet_counted(message)).await,
+            SyslogConnection::Tls(stream) => {
+                stream.write_all(&frame_octet_counted(message)).await?;
+                stream.flush().await
+            }
+        }
+    }
+}
+
+// Sends the queued records until the queue is replaced or the forwarding is turned off. Failures are only logged when the
+// syslog server goes away and comes back, as the log of each failure would be forwarded as well
+async fn run_forwarding(target: Option<RemoteSyslogTarget>, mut records: mpsc::Receiver<ForwardedRecord>) {
+    let hostname = get_hostname();
+    let mut connection: Option<SyslogConnection> = None;
+    let mut reconnect_at: Option<Instant> = None;
+    let mut is_failing = false;
+    #[cfg(windows)]
+    let event_log = windows_event_log::EventLog::open();
+
+    while let Some(record) = records.recv().await {
+        #[cfg(windows)]
+        if record.is_for_event_log
+            && let Some(event_log) = &event_log
+        {
+            event_log.report(record.level, &record.message);
+        }
+
+        let Some(target) = &target else {
+            continue;
+        };
+        if !record.is_for_remote_syslog {
+            continue;
+        }
+
+        if connection.is_none() {
+            if reconnect_at.is_some_and(|at| Instant::now() < at) {
+                continue;
+            }
+            match tokio::time::timeout(SEND_TIMEOUT, SyslogConnection::connect(target)).await {
+                Ok(Ok(new_connection)) => connection = Some(new_connection),
+                result => {
+                    reconnect_at = Some(Instant::now() + RECONNECT_DELAY);
+                    if !is_failing {
+                        is_failing = true;
+                        let error = result.map_or_else(|_| "timed out".to_string(), |r| r.err().map(|e| e.to_string()).unwrap_or_default());
+                        warn(format!("Could not connect to syslog server {}:{}, the log is not sent to it: {}", target.host, target.port, error));
+                    }
+                    continue;
+                }
+            }
+        }
+        let Some(open_connection) = connection.as_mut() else {
+            continue;
+        };
+
+        let message = format_rfc5424(record.level, &record.timestamp, &hostname, &record.message);
+        match tokio::time::timeout(SEND_TIMEOUT, open_connection.send(&message)).await {
+            Ok(Ok(())) => {
+                if is_failing {
+                    is_failing = false;
+                    info(format!("Sending the log to syslog server {}:{} again", target.host, target.port));
+                }
+            }
+            _ => {
+                connection = None;
+                reconnect_at = Some(Instant::now() + RECONNECT_DELAY);
+                if !is_failing {
+                    is_failing = true;
+                    warn(format!("Lost the connection to syslog server {}:{}, the log is not sent to it", target.host, target.port));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows_event_log {
+    use std::ffi::c_void;
+
+    const EVENTLOG_ERROR_TYPE: u16 = 0x0001;
+    const EVENTLOG_WARNING_TYPE: u16 = 0x0002;
+    const EVENTLOG_INFORMATION_TYPE: u16 = 0x0004;
+    const EVENT_SOURCE: &str = "Gruxi";
+
+    #[link(name = "advapi32")]
+    unsafe extern "system" {
+        fn RegisterEventSourceW(server_name: *const u16, source_name: *const u16) -> *mut c_void;
+        fn ReportEventW(
+            event_log: *mut c_void,
+            event_type: u16,
+            category: u16,
+            event_id: u32,
+            user_sid: *const c_void,
+            num_strings: u16,
+            data_size: u32,
+            strings: *const *const u16,
+            raw_data: *const c_void,
+        ) -> i32;
+        fn DeregisterEventSource(event_log: *mut c_void) -> i32;
+    }
+
+    // The Application log, with Gruxi as the source. Without a message file registered for the source, Event Viewer shows
+    // the message as the inserted string of the event
+    pub struct EventLog {
+        handle: *mut c_void,
+    }
+
+    // SAFETY: The event log handle may be used from any thread
+    unsafe impl Send for EventLog {}
+
+    impl EventLog {
+        pub fn open() -> Option<Self> {
+            let source_name: Vec<u16> = EVENT_SOURCE.encode_utf16().chain(std::iter::once(0)).collect();
+            // SAFETY: The source name is NUL terminated and outlives the call
+            let handle = unsafe { RegisterEventSourceW(std::ptr::null(), source_name.as_ptr()) };
+            if handle.is_null() { None } else { Some(EventLog { handle }) }
+        }
+
+        pub fn report(&self, level: u8, message: &str) {
+            let event_type = match level {
+                1 => EVENTLOG_ERROR_TYPE,
+                2 => EVENTLOG_WARNING_TYPE,
+                _ => EVENTLOG_INFORMATION_TYPE,
+            };
+            let message: Vec<u16> = message.encode_utf16().chain(std::iter::once(0)).collect();
+            let strings = [message.as_ptr()];
+            // SAFETY: The handle is open, and the single string is NUL terminated and outlives the call
+            unsafe {
+                ReportEventW(self.handle, event_type, 0, 0, std::ptr::null(), 1, 0, strings.as_ptr(), std::ptr::null());
+            }
+        }
+    }
+
+    impl Drop for EventLog {
+        fn drop(&mut self) {
+            // SAFETY: The handle is open and not used after this
+            unsafe {
+                DeregisterEventSource(self.handle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote_syslog_address() {
+        let target = parse_remote_syslog_address("udp://logs.example.com").unwrap();
+        assert_eq!(target.transport, SyslogTransport::Udp);
+        assert_eq!(target.port, 514);
+        assert_eq!(parse_remote_syslog_address("tls://logs.example.com").unwrap().port, 6514);
+
+        let target = parse_remote_syslog_address("tcp://[::1]:1514").unwrap();
+        assert_eq!(target.host, "[::1]");
+        assert_eq!(target.port, 1514);
+
+        assert!(parse_remote_syslog_address("logs.example.com:514").is_err());
+        assert!(parse_remote_syslog_address("udp://:514").is_err());
+        assert!(parse_remote_syslog_address("udp://logs.example.com:port").is_err());
+    }
+
+    #[test]
+    fn test_rfc5424_format() {
+        let message = format_rfc5424(LogType::Warn as u8, "2026-01-02T03:04:05.000006Z", "web1", "Disk is almost full");
+        assert_eq!(message, format!("<28>1 2026-01-02T03:04:05.000006Z web1 gruxi {} - - Disk is almost full", std::process::id()));
+        assert!(format_rfc5424(LogType::Error as u8, "-", "-", "").starts_with("<27>1 "));
+        assert!(format_rfc5424(LogType::Trace as u8, "-", "-", "").starts_with("<31>1 "));
+
+        assert_eq!(frame_octet_counted("<28>1 æ"), b"8 <28>1 \xc3\xa6".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_forward_over_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("udp://127.0.0.1:{}", receiver.local_addr().unwrap().port());
+
+        let forwarder = LogForwarder::new();
+        forwarder.configure(&address, "warn", "off");
+        assert!(forwarder.accepts(&LogType::Error));
+        assert!(!forwarder.accepts(&LogType::Info));
+
+        forwarder.forward(&LogType::Info, "2026-01-02T03:04:05Z", "Not sent");
+        forwarder.forward(&LogType::Error, "2026-01-02T03:04:05Z", "Upstream is down");
+        let mut buffer = [0u8; 2048];
+        let length = tokio::time::timeout(Duration::from_secs(5), receiver.recv(&mut buffer)).await.unwrap().unwrap();
+        let message = String::from_utf8_lossy(&buffer[..length]).to_string();
+        assert!(message.starts_with("<27>1 2026-01-02T03:04:05Z "), "{}", message);
+        assert!(message.ends_with(" - - Upstream is down"), "{}", message);
+
+        forwarder.configure("", "warn", "off");
+        assert!(!forwarder.accepts(&LogType::Error));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::logging::log_forwarding::get_log_forwarder;
+use dashmap::DashMap;
+use std::panic::Location;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Records a call site may log each second before sampling starts, so occasional repeats are always logged in full
+pub const SAMPLING_BURST: u64 = 10;
+
+static LOG_SAMPLER: OnceLock<LogSampler> = OnceLock::new();
+
+pub fn get_log_sampler() -> &'static LogSampler {
+    LOG_SAMPLER.get_or_init(LogSampler::new)
+}
+
+// What to do with a log record
+#[derive(Debug, PartialEq)]
+pub enum SamplingDecision {
+    // Log it, with a note on the records of the call site left out since the last one logged, if any
+    Log(Option<String>),
+    Drop,
+}
+
+struct CallSiteWindow {
+    second: u64,
+    count: u64,
+    suppressed: u64,
+}
+
+// Keeps logging from taking the server down under load. Records logged over and over from the same place in the code are
+// sampled, writing 1 in N once a call site passes SAMPLING_BURST records in a second, and all records are capped at a number
+// per second. What is left out is counted, and the records dropped by the cap are reported once the next second starts
+pub struct LogSampler {
+    // 1 in how many records of a busy call site are logged, 0 or 1 to log all of them
+    sample_rate: AtomicU64,
+    // Records logged per second, 0 for no cap
+    max_records_per_second: AtomicU64,
+    call_sites: DashMap<(&'static str, u32), CallSiteWindow>,
+    current_second: AtomicU64,
+    records_in_second: AtomicU64,
+    capped_in_second: AtomicU64,
+    total_sampled_out: AtomicU64,
+    total_capped: AtomicU64,
+}
+
+impl LogSampler {
+    pub fn new() -> Self {
+        LogSampler {
+            sample_rate: AtomicU64::new(0),
+            max_records_per_second: AtomicU64::new(0),
+            call_sites: DashMap::new(),
+            current_second: AtomicU64::new(0),
+            records_in_second: AtomicU64::new(0),
+            capped_in_second: AtomicU64::new(0),
+            total_sampled_out: AtomicU64::new(0),
+            total_capped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn configure(&self, sample_rate: u64, max_records_per_second: u64) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.max_records_per_second.store(max_records_per_second, Ordering::Relaxed);
+    }
+
+    // Decides whether to log a record from the call site. When a new second starts after records were dropped by the cap,
+    // the report of how many is returned as well, to be logged first
+    pub fn check(&self, location: &'static Location<'static>) -> (SamplingDecision, Option<String>) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.check_at(location, now)
+    }
+
+    fn check_at(&self, location: &'static Location<'static>, now: u64) -> (SamplingDecision, Option<String>) {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        let max_records_per_second = self.max_records_per_second.load(Ordering::Relaxed);
+        if sample_rate <= 1 && max_records_per_second == 0 {
+            return (SamplingDecision::Log(None), None);
+        }
+
+        // Only one caller gets to start the new second, and with it the report of the second before
+        let mut cap_report = None;
+        let previous_second = self.current_second.load(Ordering::Relaxed);
+        if previous_second != now && self.current_second.compare_exchange(previous_second, now, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            self.records_in_second.store(0, Ordering::Relaxed);
+            let capped = self.capped_in_second.swap(0, Ordering::Relaxed);
+            if capped > 0 {
+                cap_report = Some(format!("Dropped {} log records over the cap of {} per second", capped, max_records_per_second));
+            }
+        }
+
+        let note = if sample_rate > 1 {
+            let mut window = self
+                .call_sites
+                .entry((location.file(), location.line()))
+                .or_insert(CallSiteWindow { second: now, count: 0, suppressed: 0 });
+            if window.second != now {
+                window.second = now;
+                window.count = 0;
+            }
+            window.count += 1;
+            if window.count > SAMPLING_BURST && !(window.count - SAMPLING_BURST).is_multiple_of(sample_rate) {
+                window.suppressed += 1;
+                self.total_sampled_out.fetch_add(1, Ordering::Relaxed);
+                return (SamplingDecision::Drop, cap_report);
+            }
+            match std::mem::take(&mut window.suppressed) {
+                0 => None,
+                suppressed => Some(format!("[{} similar messages suppressed]", suppressed)),
+            }
+        } else {
+            None
+        };
+
+        if max_records_per_second > 0 && self.records_in_second.fetch_add(1, Ordering::Relaxed) >= max_records_per_second {
+            self.capped_in_second.fetch_add(1, Ordering::Relaxed);
+            self.total_capped.fetch_add(1, Ordering::Relaxed);
+            return (SamplingDecision::Drop, cap_report);
+        }
+        (SamplingDecision::Log(note), cap_report)
+    }
+
+    pub fn get_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "sample_rate": self.sample_rate.load(Ordering::Relaxed),
+            "max_records_per_second": self.max_records_per_second.load(Ordering::Relaxed),
+            "sampled_out": self.total_sampled_out.load(Ordering::Relaxed),
+            "dropped_over_cap": self.total_capped.load(Ordering::Relaxed),
+            "dropped_forwarding": get_log_forwarder().get_dropped_records(),
+        })
+    }
+}
+
+impl Default for LogSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling_of_call_site() {
+        let sampler = LogSampler::new();
+        sampler.configure(5, 0);
+        let location = Location::caller();
+        let mut logged = 0;
+        let mut notes = Vec::new();
+        for _ in 0..SAMPLING_BURST + 20 {
+            if let (SamplingDecision::Log(note), _) = sampler.check_at(location, 100) {
+                logged += 1;
+                notes.extend(note);
+            }
+        }
+        // The burst, then 1 in 5 of the other 20
+        assert_eq!(logged, SAMPLING_BURST + 4);
+        assert_eq!(notes, vec!["[4 similar messages suppressed]"; 4]);
+
+        // A new second starts with a full burst again
+        assert_eq!(sampler.check_at(location, 101).0, SamplingDecision::Log(None));
+        assert_eq!(sampler.get_json()["sampled_out"], 16);
+    }
+
+    #[test]
+    fn test_rate_cap() {
+        let sampler = LogSampler::new();
+        sampler.configure(0, 3);
+        let location = Location::caller();
+        let decisions: Vec<SamplingDecision> = (0..5).map(|_| sampler.check_at(location, 200).0).collect();
+        assert_eq!(decisions[2], SamplingDecision::Log(None));
+        assert_eq!(decisions[3], SamplingDecision::Drop);
+
+        let (decision, cap_report) = sampler.check_at(location, 201);
+        assert_eq!(decision, SamplingDecision::Log(None));
+        assert_eq!(cap_report.unwrap(), "Dropped 2 log records over the cap of 3 per second");
+        assert_eq!(sampler.get_json()["dropped_over_cap"], 2);
+    }
+}
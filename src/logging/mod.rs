@@ -1,3 +1,6 @@
 pub mod access_logging;
 pub mod buffered_log;
-pub mod syslog;
\ No newline at end of file
+pub mod debug_capture;
+pub mod log_forwarding;
+pub mod log_sampling;
+pub mod syslog;
@@ -1,15 +1,22 @@
 use chrono::Utc;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::panic::Location;
 use std::sync::{LazyLock, RwLock};
 use tokio::select;
 
 use crate::core::operation_mode::OperationMode;
 use crate::logging::buffered_log::BufferedLog;
+use crate::logging::log_forwarding::get_log_forwarder;
+use crate::logging::log_sampling::{SamplingDecision, get_log_sampler};
 
 pub struct SysLog {
     pub buffered_log: BufferedLog,
-    // Log level for writing log
+    // Log level for writing log, from the operation mode
     log_level: LogType,
+    // Configured log level replacing the one of the operation mode, and the levels of modules that differ from it
+    log_level_override: Option<LogType>,
+    module_log_levels: [Option<LogType>; LOG_MODULES.len()],
     // Enabled levels for both logs
     error_enabled: bool,
     info_enabled: bool,
@@ -27,7 +34,7 @@ pub struct SysLog {
 }
 
 #[repr(u8)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum LogType {
     Off = 0,
     Error = 1,
@@ -50,11 +57,88 @@ impl fmt::Display for LogType {
     }
 }
 
+impl LogType {
+    // Parses a level as given in the configuration, such as "debug"
+    pub fn from_name(name: &str) -> Option<LogType> {
+        match name {
+            "off" => Some(LogType::Off),
+            "error" => Some(LogType::Error),
+            "warn" => Some(LogType::Warn),
+            "info" => Some(LogType::Info),
+            "debug" => Some(LogType::Debug),
+            "trace" => Some(LogType::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            LogType::Off => "off",
+            LogType::Error => "error",
+            LogType::Warn => "warn",
+            LogType::Info => "info",
+            LogType::Debug => "debug",
+            LogType::Trace => "trace",
+        }
+    }
+}
+
+// The subsystems that can have a log level of their own. Everything else logs at the global level
+pub const LOG_MODULES: [&str; 4] = ["http", "fastcgi", "admin", "tls"];
+
+// Tells the module of a log record from the source file it is logged from, such as "src/admin_portal/http_admin_api.rs"
+pub fn get_log_module_index(source_file: &str) -> Option<usize> {
+    let directory = source_file.split(['/', '\\']).skip_while(|c| *c != "src").nth(1).unwrap_or("");
+    let file_name = source_file.rsplit(['/', '\\']).next().unwrap_or("");
+    let module = if file_name.contains("fastcgi") || file_name.contains("php") {
+        "fastcgi"
+    } else {
+        match directory {
+            "admin_portal" => "admin",
+            "tls" => "tls",
+            "http" if file_name == "http_tls.rs" => "tls",
+            "http" => "http",
+            _ => return None,
+        }
+    };
+    LOG_MODULES.iter().position(|m| *m == module)
+}
+
+// The global level replacing the one of the operation mode, if any, and the levels of modules by their index in LOG_MODULES
+pub type LogLevels = (Option<LogType>, Vec<(usize, LogType)>);
+
+// Parses the configured global level, empty to follow the operation mode, and the levels of the modules
+pub fn parse_log_levels(log_level: &str, module_log_levels: &BTreeMap<String, String>) -> Result<LogLevels, Vec<String>> {
+    let mut errors = Vec::new();
+    let log_level_override = if log_level.is_empty() {
+        None
+    } else {
+        let parsed = LogType::from_name(log_level);
+        if parsed.is_none() {
+            errors.push(format!("Log level must be empty or one of off, error, warn, info, debug, trace: {}", log_level));
+        }
+        parsed
+    };
+
+    let mut module_levels = Vec::new();
+    for (module, level) in module_log_levels {
+        match (LOG_MODULES.iter().position(|m| m == module), LogType::from_name(level)) {
+            (Some(module_index), Some(level)) => module_levels.push((module_index, level)),
+            (None, _) => errors.push(format!("Log level is given for an unknown module '{}', must be one of {}", module, LOG_MODULES.join(", "))),
+            (_, None) => errors.push(format!("Log level of module {} must be one of off, error, warn, info, debug, trace: {}", module, level)),
+        }
+    }
+
+    if errors.is_empty() { Ok((log_level_override, module_levels)) } else { Err(errors) }
+}
+
 impl SysLog {
     pub fn new(log_level: LogType, stdout_log_level: LogType) -> Self {
         let mut sys_log = SysLog {
             buffered_log: BufferedLog::new("syslog".to_string(), "./logs/gruxi.log".to_string()),
             log_level: log_level.clone(),
+            log_level_override: None,
+            module_log_levels: [const { None }; LOG_MODULES.len()],
             error_enabled: false,
             info_enabled: false,
             warn_enabled: false,
@@ -74,7 +158,7 @@ impl SysLog {
     }
 
     pub fn calculate_enabled_levels(&mut self) {
-        let log_level = self.log_level.clone();
+        let log_level = self.log_level_override.clone().unwrap_or(self.log_level.clone());
         let stdout_log_level = self.stdout_log_level.clone();
         // Log file levels enabled
         self.error_enabled = log_level.clone() as u8 >= LogType::Error as u8;
@@ -94,18 +178,51 @@ impl SysLog {
         tokio::spawn(Self::start_flushing_thread());
     }
 
-    pub fn add_log(&self, log_type: LogType, log: String) {
-        // Match the logtype against the enabled levels
-        match log_type {
-            LogType::Error if !self.error_enabled => return,
-            LogType::Warn if !self.warn_enabled => return,
-            LogType::Info if !self.info_enabled => return,
-            LogType::Debug if !self.debug_enabled => return,
-            LogType::Trace if !self.trace_enabled => return,
-            _ => {}
+    pub fn add_log(&self, log_type: LogType, location: &'static Location<'static>, log: String) {
+        let source_file = location.file();
+        // Match the logtype against the enabled levels, or the level of the module logging it if it has one
+        let module_log_level = match self.module_log_levels.iter().any(Option::is_some) {
+            true => get_log_module_index(source_file).and_then(|module_index| self.module_log_levels[module_index].as_ref()),
+            false => None,
+        };
+        let is_enabled = match (module_log_level, &log_type) {
+            (Some(module_log_level), _) => log_type.clone() as u8 <= module_log_level.clone() as u8,
+            (None, LogType::Error) => self.error_enabled,
+            (None, LogType::Warn) => self.warn_enabled,
+            (None, LogType::Info) => self.info_enabled,
+            (None, LogType::Debug) => self.debug_enabled,
+            (None, LogType::Trace) => self.trace_enabled,
+            _ => true,
+        };
+        // The remote syslog server and Windows Event Log have levels of their own, so may take records not written to the file
+        let log_forwarder = get_log_forwarder();
+        let is_forwarded = log_forwarder.accepts(&log_type);
+        if !is_enabled && !is_forwarded {
+            return;
+        }
+
+        // Busy call sites are sampled and the records per second are capped, reporting the records dropped by the cap in the second before
+        let (decision, cap_report) = get_log_sampler().check(location);
+        if let Some(cap_report) = cap_report {
+            self.write_log(LogType::Warn, self.warn_enabled, log_forwarder.accepts(&LogType::Warn), cap_report);
         }
+        let log = match decision {
+            SamplingDecision::Drop => return,
+            SamplingDecision::Log(Some(note)) => format!("{} {}", log, note),
+            SamplingDecision::Log(None) => log,
+        };
+        self.write_log(log_type, is_enabled, is_forwarded, log);
+    }
 
+    fn write_log(&self, log_type: LogType, is_enabled: bool, is_forwarded: bool, log: String) {
+        let log_forwarder = get_log_forwarder();
         let ts = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        if is_forwarded {
+            log_forwarder.forward(&log_type, &ts, &log);
+        }
+        if !is_enabled {
+            return;
+        }
         let log_entry = format!("{} - [{}] {}", &ts, &log_type, &log);
 
         // Also print to stdout right away if enabled
@@ -145,6 +262,15 @@ impl SysLog {
             }
         };
 
+        let reopen_logs_token_option = triggers.get_token("reopen_logs").await;
+        let mut reopen_logs_token = match reopen_logs_token_option {
+            Some(token) => token,
+            None => {
+                error("Failed to get reopen_logs token - Could not start flushing thread for syslog. Please report a bug".to_string());
+                return;
+            }
+        };
+
         loop {
             select! {
                 // Ideally, this would be adjustable according to the work load (such as elapsed time to do a flush in average)
@@ -176,6 +302,26 @@ impl SysLog {
                     };
 
                 },
+                _ = reopen_logs_token.cancelled() => {
+                    match SYS_LOG.read() {
+                        Err(_) => {
+                            debug("Failed to acquire read lock for syslog when reopening the log file".to_string());
+                        },
+                        Ok(sys_log) => {
+                            sys_log.buffered_log.reopen();
+                        }
+                    }
+
+                    // Get new token for next time
+                    let reopen_logs_token_option = triggers.get_token("reopen_logs").await;
+                    reopen_logs_token = match reopen_logs_token_option {
+                        Some(token) => token,
+                        None => {
+                            error("Failed to get reopen_logs token - Could not start flushing thread for syslog. Please report a bug".to_string());
+                            return;
+                        }
+                    };
+                },
                 _ = shutdown_token.cancelled() => {
                     // Shutdown in progress, we force flush the logs
                     match SYS_LOG.read() {
@@ -206,6 +352,40 @@ impl SysLog {
         }
     }
 
+    // Applies the configured log levels, at startup and when the configuration is reloaded, or through the admin API
+    pub fn set_log_levels(log_level_override: Option<LogType>, module_levels: &[(usize, LogType)]) {
+        match SYS_LOG.write() {
+            Err(_) => {
+                error("Failed to acquire write lock for syslog when setting log levels".to_string());
+            }
+            Ok(mut guard) => {
+                guard.buffered_log.consider_flush(true);
+                guard.log_level_override = log_level_override;
+                guard.module_log_levels = [const { None }; LOG_MODULES.len()];
+                for (module_index, level) in module_levels {
+                    guard.module_log_levels[*module_index] = Some(level.clone());
+                }
+                guard.calculate_enabled_levels();
+            }
+        }
+    }
+
+    // The configured log level, if any, the level in effect and the levels of the modules that have one
+    pub fn get_log_levels() -> (Option<LogType>, LogType, BTreeMap<String, String>) {
+        match SYS_LOG.read() {
+            Err(_) => (None, LogType::Info, BTreeMap::new()),
+            Ok(guard) => {
+                let module_levels = LOG_MODULES
+                    .iter()
+                    .zip(guard.module_log_levels.iter())
+                    .filter_map(|(module, level)| level.as_ref().map(|level| (module.to_string(), level.get_name().to_string())))
+                    .collect();
+                let effective_level = guard.log_level_override.clone().unwrap_or(guard.log_level.clone());
+                (guard.log_level_override.clone(), effective_level, module_levels)
+            }
+        }
+    }
+
     pub fn set_new_stdout_log_level(new_log_level: LogType) {
         match SYS_LOG.write() {
             Err(_) => {
@@ -248,47 +428,81 @@ fn init_log() -> SysLog {
     sys_log
 }
 
+#[track_caller]
 pub fn error<S: Into<String>>(log: S) {
     match SYS_LOG.read() {
         Err(_) => {}
         Ok(sys_log) => {
-            sys_log.add_log(LogType::Error, log.into());
+            sys_log.add_log(LogType::Error, Location::caller(), log.into());
         }
     }
 }
 
+#[track_caller]
 pub fn warn<S: Into<String>>(log: S) {
     match SYS_LOG.read() {
         Err(_) => {}
         Ok(sys_log) => {
-            sys_log.add_log(LogType::Warn, log.into());
+            sys_log.add_log(LogType::Warn, Location::caller(), log.into());
         }
     }
 }
 
+#[track_caller]
 pub fn info<S: Into<String>>(log: S) {
     match SYS_LOG.read() {
         Err(_) => {}
         Ok(sys_log) => {
-            sys_log.add_log(LogType::Info, log.into());
+            sys_log.add_log(LogType::Info, Location::caller(), log.into());
         }
     }
 }
 
+#[track_caller]
 pub fn debug<S: Into<String>>(log: S) {
     match SYS_LOG.read() {
         Err(_) => {}
         Ok(sys_log) => {
-            sys_log.add_log(LogType::Debug, log.into());
+            sys_log.add_log(LogType::Debug, Location::caller(), log.into());
         }
     }
 }
 
+#[track_caller]
 pub fn trace<S: Into<String>>(log: S) {
     match SYS_LOG.read() {
         Err(_) => {}
         Ok(sys_log) => {
-            sys_log.add_log(LogType::Trace, log.into());
+            sys_log.add_log(LogType::Trace, Location::caller(), log.into());
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_modules() {
+        let get_module = |file: &str| get_log_module_index(file).map(|index| LOG_MODULES[index]);
+        assert_eq!(get_module("src/http/handle_request.rs"), Some("http"));
+        assert_eq!(get_module("src/http/http_tls.rs"), Some("tls"));
+        assert_eq!(get_module("src/tls/shared_acme_manager.rs"), Some("tls"));
+        assert_eq!(get_module("src/admin_portal/http_admin_api.rs"), Some("admin"));
+        assert_eq!(get_module("src/external_connections/fastcgi.rs"), Some("fastcgi"));
+        assert_eq!(get_module("src\\http\\request_handlers\\processors\\php_processor.rs"), Some("fastcgi"));
+        assert_eq!(get_module("src/core/grux_server.rs"), None);
+    }
+
+    #[test]
+    fn test_parse_log_levels() {
+        let modules = BTreeMap::from([("fastcgi".to_string(), "trace".to_string()), ("http".to_string(), "off".to_string())]);
+        let (log_level_override, module_levels) = parse_log_levels("warn", &modules).unwrap();
+        assert_eq!(log_level_override, Some(LogType::Warn));
+        assert_eq!(module_levels, vec![(1, LogType::Trace), (0, LogType::Off)]);
+        assert_eq!(parse_log_levels("", &BTreeMap::new()).unwrap().0, None);
+
+        let modules = BTreeMap::from([("database".to_string(), "debug".to_string()), ("tls".to_string(), "loud".to_string())]);
+        assert_eq!(parse_log_levels("verbose", &modules).unwrap_err().len(), 3);
+    }
+}
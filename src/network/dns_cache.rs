@@ -0,0 +1,262 @@
+use crate::logging::syslog::{debug, trace, warn};
+use crate::network::outbound_connection::{connect_happy_eyeballs, get_outbound_timeouts};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use utoipa::ToSchema;
+
+static DNS_CACHE_SINGLETON: OnceLock<DnsCache> = OnceLock::new();
+
+pub fn get_dns_cache() -> &'static DnsCache {
+    DNS_CACHE_SINGLETON.get_or_init(|| DnsCache::new(60))
+}
+
+/// Caches the addresses of the upstream hosts Gruxi connects to, such as proxy and FastCGI backends given by hostname,
+/// so they are not resolved for every request, while still following changes to the DNS records.
+///
+/// Features:
+/// - Resolution runs on the tokio blocking pool, so requests are never blocked on the system resolver
+/// - Entries expire after a configurable time. The system resolver does not give the TTL of the records,
+///   so the cache time should be at most the TTL of the records it is used for
+/// - When resolving fails, the previous addresses are kept in use until resolving succeeds again
+/// - When connecting to all cached addresses fails, the host is resolved again, as the backend may have moved
+/// - IP addresses are used as they are, without going through the cache
+pub struct DnsCache {
+    /// Cached entries by lowercase host name
+    entries: DashMap<String, DnsCacheEntry>,
+    /// How long an entry is used before resolving the host again, 0 to resolve on every use
+    ttl_seconds: AtomicU64,
+}
+
+#[derive(Clone, Debug)]
+struct DnsCacheEntry {
+    addresses: Vec<IpAddr>,
+    resolved_at: Option<Instant>,
+    expires_at: Instant,
+    last_error: Option<String>,
+}
+
+/// A cached host as shown in the admin API
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct DnsCacheEntryInfo {
+    pub host: String,
+    pub addresses: Vec<String>,
+    // Seconds since the host was last resolved successfully, None if it never was
+    pub resolved_seconds_ago: Option<u64>,
+    // Seconds until the host is resolved again, 0 if it is resolved on the next use
+    pub expires_in_seconds: u64,
+    // The error of the last failed resolution, cleared when resolving succeeds again
+    pub last_error: Option<String>,
+}
+
+impl DnsCache {
+    pub fn new(ttl_seconds: u64) -> Self {
+        DnsCache {
+            entries: DashMap::new(),
+            ttl_seconds: AtomicU64::new(ttl_seconds),
+        }
+    }
+
+    /// Sets how long entries are cached. Entries already cached keep their expiry
+    pub fn set_ttl_seconds(&self, ttl_seconds: u64) {
+        self.ttl_seconds.store(ttl_seconds, Ordering::Relaxed);
+    }
+
+    pub fn get_ttl_seconds(&self) -> u64 {
+        self.ttl_seconds.load(Ordering::Relaxed)
+    }
+
+    /// Returns the addresses of the host, from the cache if the entry has not expired
+    pub async fn resolve(&self, host: &str) -> std::io::Result<Vec<IpAddr>> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+
+        let key = host.to_lowercase();
+        if let Some(entry) = self.entries.get(&key)
+            && entry.expires_at > Instant::now()
+            && !entry.addresses.is_empty()
+        {
+            return Ok(entry.addresses.clone());
+        }
+
+        self.resolve_and_store(&key).await
+    }
+
+    /// Marks the host to be resolved again on the next use, such as when connecting to its addresses failed.
+    /// The addresses are kept, to fall back to if resolving fails
+    pub fn invalidate(&self, host: &str) {
+        let key = host.trim_start_matches('[').trim_end_matches(']').to_lowercase();
+        if let Some(mut entry) = self.entries.get_mut(&key) {
+            trace(format!("Invalidating cached addresses of {}", key));
+            entry.expires_at = Instant::now();
+        }
+    }
+
+    /// Connects to a "host:port" address, resolving the host through the cache. The addresses are tried with
+    /// Happy Eyeballs, and if none of them can be connected to, the host is resolved again and any new addresses are tried
+    pub async fn connect(&self, host_and_port: &str) -> std::io::Result<TcpStream> {
+        let (host, port) = split_host_and_port(host_and_port)?;
+
+        let addresses = self.resolve(host).await?;
+        let first_error = match connect_happy_eyeballs(&addresses, port, get_outbound_timeouts().connect).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => e,
+        };
+        if host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok() {
+            return Err(first_error);
+        }
+
+        debug(format!("Could not connect to the cached addresses of {}, resolving it again: {}", host_and_port, first_error));
+        self.invalidate(host);
+        let new_addresses: Vec<IpAddr> = self.resolve(host).await?.into_iter().filter(|a| !addresses.contains(a)).collect();
+        if new_addresses.is_empty() {
+            return Err(first_error);
+        }
+        connect_happy_eyeballs(&new_addresses, port, get_outbound_timeouts().connect).await
+    }
+
+    /// The cached hosts, sorted by name
+    pub fn get_entries(&self) -> Vec<DnsCacheEntryInfo> {
+        let now = Instant::now();
+        let mut entries: Vec<DnsCacheEntryInfo> = self
+            .entries
+            .iter()
+            .map(|entry| DnsCacheEntryInfo {
+                host: entry.key().clone(),
+                addresses: entry.addresses.iter().map(|a| a.to_string()).collect(),
+                resolved_seconds_ago: entry.resolved_at.map(|t| now.duration_since(t).as_secs()),
+                expires_in_seconds: entry.expires_at.saturating_duration_since(now).as_secs(),
+                last_error: entry.last_error.clone(),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.host.cmp(&b.host));
+        entries
+    }
+
+    async fn resolve_and_store(&self, key: &str) -> std::io::Result<Vec<IpAddr>> {
+        trace(format!("Resolving host {}", key));
+        let result = tokio::net::lookup_host((key, 0)).await.map(|addresses| {
+            let mut ips: Vec<IpAddr> = Vec::new();
+            for address in addresses {
+                if !ips.contains(&address.ip()) {
+                    ips.push(address.ip());
+                }
+            }
+            ips
+        });
+        let expires_at = Instant::now() + Duration::from_secs(self.get_ttl_seconds());
+
+        match result {
+            Ok(ips) if !ips.is_empty() => {
+                self.entries.insert(
+                    key.to_string(),
+                    DnsCacheEntry {
+                        addresses: ips.clone(),
+                        resolved_at: Some(Instant::now()),
+                        expires_at,
+                        last_error: None,
+                    },
+                );
+                Ok(ips)
+            }
+            other => {
+                let error = match other {
+                    Err(e) => e,
+                    Ok(_) => std::io::Error::new(std::io::ErrorKind::NotFound, format!("No addresses found for {}", key)),
+                };
+
+                // Keep using the previous addresses until the host can be resolved again
+                let mut entry = self.entries.entry(key.to_string()).or_insert_with(|| DnsCacheEntry {
+                    addresses: Vec::new(),
+                    resolved_at: None,
+                    expires_at,
+                    last_error: None,
+                });
+                entry.last_error = Some(error.to_string());
+                if entry.addresses.is_empty() {
+                    return Err(error);
+                }
+                warn(format!("Failed to resolve {}, using the previously resolved addresses: {}", key, error));
+                entry.expires_at = expires_at;
+                Ok(entry.addresses.clone())
+            }
+        }
+    }
+}
+
+// Splits "host:port" or "[ipv6]:port"
+fn split_host_and_port(host_and_port: &str) -> std::io::Result<(&str, u16)> {
+    host_and_port
+        .rsplit_once(':')
+        .and_then(|(host, port)| port.parse::<u16>().ok().map(|port| (host, port)))
+        .filter(|(host, _)| !host.is_empty())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Expected host:port, got '{}'", host_and_port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ip_addresses_are_not_cached() {
+        let cache = DnsCache::new(60);
+        assert_eq!(cache.resolve("127.0.0.1").await.unwrap(), vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(cache.resolve("[::1]").await.unwrap(), vec!["::1".parse::<IpAddr>().unwrap()]);
+        assert!(cache.get_entries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolved_hosts_are_cached_until_invalidated() {
+        let cache = DnsCache::new(60);
+        let addresses = cache.resolve("LOCALHOST").await.unwrap();
+        assert!(!addresses.is_empty());
+
+        let entries = cache.get_entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].host, "localhost");
+        assert!(entries[0].expires_in_seconds > 0);
+
+        cache.invalidate("localhost");
+        assert_eq!(cache.get_entries()[0].expires_in_seconds, 0);
+        assert_eq!(cache.resolve("localhost").await.unwrap(), addresses);
+        assert!(cache.get_entries()[0].expires_in_seconds > 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_resolution_keeps_previous_addresses() {
+        let cache = DnsCache::new(60);
+        let previous = vec!["192.0.2.10".parse::<IpAddr>().unwrap()];
+        cache.entries.insert(
+            "backend.invalid".to_string(),
+            DnsCacheEntry {
+                addresses: previous.clone(),
+                resolved_at: Some(Instant::now()),
+                expires_at: Instant::now(),
+                last_error: None,
+            },
+        );
+
+        assert_eq!(cache.resolve("backend.invalid").await.unwrap(), previous);
+        assert!(cache.get_entries()[0].last_error.is_some());
+
+        // Without previous addresses, the failure is returned
+        assert!(cache.resolve("other.invalid").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_by_host_name() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let cache = DnsCache::new(60);
+        assert!(cache.connect(&format!("127.0.0.1:{}", port)).await.is_ok());
+        assert!(cache.connect("127.0.0.1").await.is_err());
+        assert_eq!(split_host_and_port("[::1]:9000").unwrap(), ("[::1]", 9000));
+    }
+}
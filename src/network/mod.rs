@@ -1 +1,4 @@
-pub mod port_manager;
\ No newline at end of file
+pub mod dns_cache;
+pub mod outbound_connection;
+pub mod port_manager;
+pub mod smtp_client;
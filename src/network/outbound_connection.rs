@@ -0,0 +1,288 @@
+use crate::logging::syslog::trace;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::Sleep;
+
+/// How long to wait for a connection attempt before also trying the next address, as in Happy Eyeballs (RFC 8305)
+pub const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+
+static CONNECT_TIMEOUT_SECONDS: AtomicU64 = AtomicU64::new(10);
+static READ_TIMEOUT_SECONDS: AtomicU64 = AtomicU64::new(60);
+static WRITE_TIMEOUT_SECONDS: AtomicU64 = AtomicU64::new(60);
+
+/// Timeouts of the connections Gruxi makes to backends, such as proxy upstreams, FastCGI servers and health checks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutboundTimeouts {
+    /// How long connecting to a single address may take
+    pub connect: Duration,
+    /// How long to wait for data from the backend, counted from the last data sent or received
+    pub read: Duration,
+    /// How long sending data to the backend may be stalled
+    pub write: Duration,
+}
+
+pub fn set_outbound_timeouts(connect_seconds: u64, read_seconds: u64, write_seconds: u64) {
+    CONNECT_TIMEOUT_SECONDS.store(connect_seconds, Ordering::Relaxed);
+    READ_TIMEOUT_SECONDS.store(read_seconds, Ordering::Relaxed);
+    WRITE_TIMEOUT_SECONDS.store(write_seconds, Ordering::Relaxed);
+}
+
+pub fn get_outbound_timeouts() -> OutboundTimeouts {
+    OutboundTimeouts {
+        connect: Duration::from_secs(CONNECT_TIMEOUT_SECONDS.load(Ordering::Relaxed)),
+        read: Duration::from_secs(READ_TIMEOUT_SECONDS.load(Ordering::Relaxed)),
+        write: Duration::from_secs(WRITE_TIMEOUT_SECONDS.load(Ordering::Relaxed)),
+    }
+}
+
+/// Orders the addresses so IPv6 and IPv4 alternate, starting with the family of the first address, so a
+/// broken family is only waited on for one attempt before the other family is tried
+pub fn interleave_address_families(addresses: &[IpAddr]) -> Vec<IpAddr> {
+    let Some(first) = addresses.first() else {
+        return Vec::new();
+    };
+    let (preferred, fallback): (Vec<IpAddr>, Vec<IpAddr>) = addresses.iter().partition(|a| a.is_ipv6() == first.is_ipv6());
+
+    let mut ordered = Vec::with_capacity(addresses.len());
+    let mut preferred = preferred.into_iter();
+    let mut fallback = fallback.into_iter();
+    loop {
+        match (preferred.next(), fallback.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+/// Connects to the first address that accepts the connection. The next address is tried when an attempt fails or has
+/// not finished within the Happy Eyeballs delay, while earlier attempts keep going. Each attempt times out after the connect timeout
+pub async fn connect_happy_eyeballs(addresses: &[IpAddr], port: u16, connect_timeout: Duration) -> io::Result<TcpStream> {
+    let mut pending = interleave_address_families(addresses).into_iter();
+    let mut attempts: JoinSet<io::Result<TcpStream>> = JoinSet::new();
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "No addresses to connect to");
+
+    loop {
+        if let Some(address) = pending.next() {
+            let socket_address = SocketAddr::new(address, port);
+            attempts.spawn(async move {
+                match tokio::time::timeout(connect_timeout, TcpStream::connect(socket_address)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, format!("Connecting to {} timed out", socket_address))),
+                }
+            });
+        }
+        if attempts.is_empty() {
+            return Err(last_error);
+        }
+
+        // Wait for an attempt to finish, or for the delay before the next address, if any
+        let has_next_address = pending.len() > 0;
+        let finished = tokio::select! {
+            finished = attempts.join_next() => finished,
+            _ = tokio::time::sleep(HAPPY_EYEBALLS_DELAY), if has_next_address => continue,
+        };
+        match finished {
+            Some(Ok(Ok(stream))) => return Ok(stream),
+            Some(Ok(Err(e))) => {
+                trace(format!("Connection attempt to port {} failed: {}", port, e));
+                last_error = e;
+            }
+            Some(Err(e)) => last_error = io::Error::other(e),
+            None => return Err(last_error),
+        }
+    }
+}
+
+/// Wraps a connection to a backend, failing reads and writes that make no progress within the timeouts.
+/// Waiting for data starts over whenever data is sent, so an idle connection that is used again gets the full read timeout
+pub struct TimeoutStream<S> {
+    inner: S,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    read_deadline: Option<Pin<Box<Sleep>>>,
+    write_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> TimeoutStream<S> {
+    pub fn new(inner: S, timeouts: OutboundTimeouts) -> Self {
+        TimeoutStream {
+            inner,
+            read_timeout: timeouts.read,
+            write_timeout: timeouts.write,
+            read_deadline: None,
+            write_deadline: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    fn after_read<T>(&mut self, cx: &mut Context<'_>, result: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        match result {
+            Poll::Ready(result) => {
+                self.read_deadline = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => match poll_deadline(&mut self.read_deadline, self.read_timeout, cx, "Reading from the backend") {
+                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                _ => Poll::Pending,
+            },
+        }
+    }
+
+    fn after_write<T>(&mut self, cx: &mut Context<'_>, result: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        match result {
+            Poll::Ready(result) => {
+                self.write_deadline = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => match poll_deadline(&mut self.write_deadline, self.write_timeout, cx, "Writing to the backend") {
+                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                _ => Poll::Pending,
+            },
+        }
+    }
+}
+
+// Returns an error if the operation has been pending for longer than the timeout, starting the timer on the first pending poll
+fn poll_deadline(deadline: &mut Option<Pin<Box<Sleep>>>, timeout: Duration, cx: &mut Context<'_>, operation: &str) -> Poll<io::Result<()>> {
+    let sleep = deadline.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+    match sleep.as_mut().poll(cx) {
+        Poll::Ready(()) => {
+            *deadline = None;
+            Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out after {} seconds", operation, timeout.as_secs()))))
+        }
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TimeoutStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.after_read(cx, result)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TimeoutStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        // Sending data is activity, so waiting for the response starts over
+        if matches!(result, Poll::Ready(Ok(n)) if n > 0) {
+            this.read_deadline = None;
+        }
+        this.after_write(cx, result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_flush(cx);
+        this.after_write(cx, result)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_shutdown(cx);
+        this.after_write(cx, result)
+    }
+}
+
+// The HTTP client uses the hyper IO traits
+impl<S: hyper::rt::Read + Unpin> hyper::rt::Read for TimeoutStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: hyper::rt::ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.after_read(cx, result)
+    }
+}
+
+impl<S: hyper::rt::Write + Unpin> hyper::rt::Write for TimeoutStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        // Sending data is activity, so waiting for the response starts over
+        if matches!(result, Poll::Ready(Ok(n)) if n > 0) {
+            this.read_deadline = None;
+        }
+        this.after_write(cx, result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_flush(cx);
+        this.after_write(cx, result)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_shutdown(cx);
+        this.after_write(cx, result)
+    }
+}
+
+impl<S: hyper_util::client::legacy::connect::Connection> hyper_util::client::legacy::connect::Connection for TimeoutStream<S> {
+    fn connected(&self) -> hyper_util::client::legacy::connect::Connected {
+        self.inner.connected()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_interleave_address_families() {
+        let addresses: Vec<IpAddr> = ["2001:db8::1", "2001:db8::2", "192.0.2.1", "192.0.2.2", "2001:db8::3"].iter().map(|a| a.parse().unwrap()).collect();
+        let ordered: Vec<String> = interleave_address_families(&addresses).iter().map(|a| a.to_string()).collect();
+        assert_eq!(ordered, vec!["2001:db8::1", "192.0.2.1", "2001:db8::2", "192.0.2.2", "2001:db8::3"]);
+        assert!(interleave_address_families(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connect_happy_eyeballs_falls_back_to_working_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // A documentation address that never answers comes first, the working address is tried after the delay
+        let addresses: Vec<IpAddr> = vec!["192.0.2.1".parse().unwrap(), "127.0.0.1".parse().unwrap()];
+        let start = std::time::Instant::now();
+        let stream = connect_happy_eyeballs(&addresses, port, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap().ip().to_string(), "127.0.0.1");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_stream_times_out_reads_without_data() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = TcpStream::connect(address).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        let timeouts = OutboundTimeouts {
+            connect: Duration::from_secs(1),
+            read: Duration::from_millis(100),
+            write: Duration::from_secs(1),
+        };
+        let mut stream = TimeoutStream::new(client, timeouts);
+        let mut buffer = [0u8; 4];
+
+        server.write_all(b"ping").await.unwrap();
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        let error = stream.read(&mut buffer).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
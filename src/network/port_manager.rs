@@ -1,4 +1,5 @@
 use crate::logging::syslog::{debug, info, trace, warn};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::{collections::HashMap, sync::OnceLock};
 use tokio::sync::Mutex;
@@ -9,6 +10,22 @@ pub fn get_port_manager() -> &'static PortManager {
     PORT_MANAGER_SINGLETON.get_or_init(|| PortManager::new(9000, 10000))
 }
 
+/// The transport a port is used for. TCP and UDP ports with the same number do not conflict, such as HTTPS and HTTP/3 (QUIC) on 443
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PortProtocol {
+    Tcp,
+    Udp,
+}
+
+impl std::fmt::Display for PortProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortProtocol::Tcp => write!(f, "TCP"),
+            PortProtocol::Udp => write!(f, "UDP"),
+        }
+    }
+}
+
 /// A generalized port manager that assigns unique ports to processes
 /// and allows reuse when processes are stopped.
 ///
@@ -16,7 +33,10 @@ pub fn get_port_manager() -> &'static PortManager {
 /// - Thread-safe port allocation and deallocation
 /// - Automatic port reuse when processes stop
 /// - Singleton pattern - only one instance exists globally
-/// - Port range: 9000-10000
+/// - Configurable port range, 9000-10000 by default, and excluded ports
+/// - Ports in use by other software are skipped, by trying to listen on them
+/// - A service gets the same port again when it is restarted, such as on a configuration reload
+/// - TCP and UDP ports are allocated separately, so a service can have the same port number for both
 /// - Support for multiple service types
 
 #[derive(Clone, Debug)]
@@ -30,12 +50,33 @@ struct PortManagerInner {
     start_port: u16,
     /// Maximum port number for allocation
     max_port: u16,
+    /// Port ranges (inclusive) within the range that are never allocated
+    excluded_ports: Vec<(u16, u16)>,
+    /// Whether to check that a port is free, by listening on it, before allocating it
+    probe_ports: bool,
     /// Currently allocated ports with their assigned process/service IDs
-    allocated_ports: HashMap<u16, String>,
+    allocated_ports: HashMap<(PortProtocol, u16), String>,
+    /// The port each service was last allocated, which it gets again if it is still free
+    previous_ports: HashMap<(String, PortProtocol), u16>,
     /// Available ports that can be reused
-    available_ports: Vec<u16>,
-    /// Next port to try for allocation
-    next_port: u16,
+    available_ports: Vec<(PortProtocol, u16)>,
+    /// Next port to try for allocation, per protocol
+    next_port: HashMap<PortProtocol, u16>,
+}
+
+impl PortManagerInner {
+    fn is_allocatable(&self, protocol: PortProtocol, port: u16) -> bool {
+        port >= self.start_port
+            && port <= self.max_port
+            && !self.allocated_ports.contains_key(&(protocol, port))
+            && !self.excluded_ports.iter().any(|(from, to)| port >= *from && port <= *to)
+            && (!self.probe_ports || is_port_free(protocol, port))
+    }
+
+    fn allocate(&mut self, protocol: PortProtocol, port: u16, service_id: &str) {
+        self.allocated_ports.insert((protocol, port), service_id.to_string());
+        self.previous_ports.insert((service_id.to_string(), protocol), port);
+    }
 }
 
 impl PortManager {
@@ -50,14 +91,41 @@ impl PortManager {
             inner: Arc::new(Mutex::new(PortManagerInner {
                 start_port,
                 max_port,
+                excluded_ports: Vec::new(),
+                probe_ports: true,
                 allocated_ports: HashMap::new(),
+                previous_ports: HashMap::new(),
                 available_ports: Vec::new(),
-                next_port: start_port,
+                next_port: HashMap::new(),
             })),
         }
     }
 
-    /// Allocate a port for the specified service/process ID
+    /// Change the port range and excluded ports. Ports already allocated are kept until released
+    ///
+    /// # Arguments
+    /// * `start_port` - The starting port number (inclusive)
+    /// * `max_port` - The maximum port number (inclusive)
+    /// * `excluded_ports` - Ports or port ranges (inclusive) not to allocate, such as "9001" or "9100-9199"
+    pub async fn configure(&self, start_port: u16, max_port: u16, excluded_ports: &[String]) {
+        let mut inner = self.inner.lock().await;
+        if inner.start_port == start_port && inner.max_port == max_port && inner.excluded_ports == parse_port_ranges(excluded_ports) {
+            return;
+        }
+
+        inner.start_port = start_port;
+        inner.max_port = max_port;
+        inner.excluded_ports = parse_port_ranges(excluded_ports);
+        inner.next_port.retain(|_, next_port| *next_port >= start_port && *next_port <= max_port);
+        info(format!("Port manager allocates ports {}-{}, excluding {:?}", start_port, max_port, excluded_ports));
+    }
+
+    /// Turn off checking whether a port is free before allocating it, for tests that expect specific ports
+    pub async fn set_port_probing(&self, probe_ports: bool) {
+        self.inner.lock().await.probe_ports = probe_ports;
+    }
+
+    /// Allocate a TCP port for the specified service/process ID
     ///
     /// # Arguments
     /// * `service_id` - Unique identifier for the service/process requesting the port
@@ -66,55 +134,75 @@ impl PortManager {
     /// * `Some(port)` - If a port was successfully allocated
     /// * `None` - If no ports are available
     pub async fn allocate_port(&self, service_id: String) -> Option<u16> {
+        self.allocate_port_for_protocol(service_id, PortProtocol::Tcp).await
+    }
+
+    /// Allocate a port of the protocol for the specified service/process ID
+    ///
+    /// # Arguments
+    /// * `service_id` - Unique identifier for the service/process requesting the port
+    /// * `protocol` - Whether the port is for TCP or UDP
+    pub async fn allocate_port_for_protocol(&self, service_id: String, protocol: PortProtocol) -> Option<u16> {
         let mut inner = self.inner.lock().await;
 
-        // First, try to reuse an available port
-        if let Some(port) = inner.available_ports.pop() {
-            inner.allocated_ports.insert(port, service_id.clone());
-            info(format!("Allocated reused port {} to service '{}'", port, service_id));
+        // A service that is restarted gets the port it had before, if it is still free
+        if let Some(port) = inner.previous_ports.get(&(service_id.clone(), protocol)).copied()
+            && inner.is_allocatable(protocol, port)
+        {
+            inner.available_ports.retain(|available| *available != (protocol, port));
+            inner.allocate(protocol, port, &service_id);
+            info(format!("Allocated previous {} port {} to service '{}'", protocol, port, service_id));
             return Some(port);
         }
 
-        // If no available ports, try to allocate a new one
-        let start_search = inner.next_port;
-        loop {
-            let port = inner.next_port;
-
-            // Check if we've exceeded the maximum port
-            if port > inner.max_port {
-                inner.next_port = inner.start_port;
-            } else {
-                inner.next_port += 1;
-            }
-
-            // Check if this port is not already allocated
-            if !inner.allocated_ports.contains_key(&port) && port <= inner.max_port {
-                inner.allocated_ports.insert(port, service_id.clone());
-                debug(format!("Allocated new port {} to service '{}'", port, service_id));
+        // Then, try to reuse an available port
+        while let Some(index) = inner.available_ports.iter().rposition(|(available_protocol, _)| *available_protocol == protocol) {
+            let (_, port) = inner.available_ports.remove(index);
+            if inner.is_allocatable(protocol, port) {
+                inner.allocate(protocol, port, &service_id);
+                info(format!("Allocated reused {} port {} to service '{}'", protocol, port, service_id));
                 return Some(port);
             }
+        }
 
-            // If we've wrapped around and checked all ports, no ports available
-            if inner.next_port == start_search || (port > inner.max_port && inner.next_port == inner.start_port) {
-                warn(format!("No available ports for service '{}'", service_id));
-                return None;
+        // If no available ports, try to allocate a new one, checking each port of the range once
+        let range_size = (inner.max_port - inner.start_port) as u32 + 1;
+        for _ in 0..range_size {
+            let start_port = inner.start_port;
+            let max_port = inner.max_port;
+            let next_port = inner.next_port.entry(protocol).or_insert(start_port);
+            let port = *next_port;
+            *next_port = if port >= max_port { start_port } else { port + 1 };
+
+            if inner.is_allocatable(protocol, port) {
+                inner.allocate(protocol, port, &service_id);
+                debug(format!("Allocated new {} port {} to service '{}'", protocol, port, service_id));
+                return Some(port);
             }
         }
+
+        warn(format!("No available {} ports for service '{}'", protocol, service_id));
+        None
     }
 
-    /// Release a port, making it available for reuse
+    /// Release a TCP port, making it available for reuse
     ///
     /// # Arguments
     /// * `port` - The port number to release
     pub async fn release_port(&self, port: u16) {
+        self.release_port_for_protocol(port, PortProtocol::Tcp).await;
+    }
+
+    /// Release a port of the protocol, making it available for reuse
+    pub async fn release_port_for_protocol(&self, port: u16, protocol: PortProtocol) {
         let mut inner = self.inner.lock().await;
 
-        if let Some(service_id) = inner.allocated_ports.remove(&port) {
-            inner.available_ports.push(port);
-            trace(format!("Released port {} from service '{}'", port, service_id));
+        if let Some(service_id) = inner.allocated_ports.remove(&(protocol, port)) {
+            inner.available_ports.push((protocol, port));
+            trace(format!("Released {} port {} from service '{}'", protocol, port, service_id));
             trace(format!("Available ports: {:?}", inner.available_ports));
         } else {
-            warn(format!("Attempted to release port {} which was not allocated", port));
+            warn(format!("Attempted to release {} port {} which was not allocated", protocol, port));
         }
     }
 
@@ -127,13 +215,13 @@ impl PortManager {
         let mut released_ports = Vec::new();
 
         // Find all ports allocated to this service
-        let ports_to_release: Vec<u16> = inner.allocated_ports.iter().filter(|(_, sid)| sid.as_str() == service_id).map(|(port, _)| *port).collect();
+        let ports_to_release: Vec<(PortProtocol, u16)> = inner.allocated_ports.iter().filter(|(_, sid)| sid.as_str() == service_id).map(|(key, _)| *key).collect();
 
         // Release each port
-        for port in ports_to_release {
-            inner.allocated_ports.remove(&port);
-            inner.available_ports.push(port);
-            released_ports.push(port);
+        for key in ports_to_release {
+            inner.allocated_ports.remove(&key);
+            inner.available_ports.push(key);
+            released_ports.push(key.1);
         }
 
         if !released_ports.is_empty() {
@@ -144,20 +232,81 @@ impl PortManager {
     }
 
     /// Get information about currently allocated ports
-    pub async fn get_allocation_info(&self) -> HashMap<u16, String> {
+    pub async fn get_allocation_info(&self) -> HashMap<(PortProtocol, u16), String> {
         let inner = self.inner.lock().await;
         inner.allocated_ports.clone()
     }
 
-    /// Get the count of available ports
+    /// Get the count of available TCP ports, not counting excluded ports or ports in use by other software
     pub async fn available_port_count(&self) -> usize {
         let inner = self.inner.lock().await;
         let total_range = (inner.max_port - inner.start_port + 1) as usize;
-        let allocated_count = inner.allocated_ports.len();
-        total_range - allocated_count
+        let excluded_count = (inner.start_port..=inner.max_port)
+            .filter(|port| inner.excluded_ports.iter().any(|(from, to)| port >= from && port <= to))
+            .count();
+        let allocated_count = inner
+            .allocated_ports
+            .keys()
+            .filter(|(protocol, port)| *protocol == PortProtocol::Tcp && *port >= inner.start_port && *port <= inner.max_port)
+            .count();
+        total_range.saturating_sub(excluded_count + allocated_count)
     }
 }
 
+/// Parses ports and port ranges, such as "9001" and "9100-9199". Invalid entries are skipped, see validate_port_ranges
+pub fn parse_port_ranges(entries: &[String]) -> Vec<(u16, u16)> {
+    entries.iter().filter_map(|entry| parse_port_range(entry).ok()).collect()
+}
+
+pub fn parse_port_range(entry: &str) -> Result<(u16, u16), String> {
+    let parse_port = |value: &str| value.trim().parse::<u16>().map_err(|_| format!("Invalid port or port range: {}", entry));
+    let (from, to) = match entry.split_once('-') {
+        Some((from, to)) => (parse_port(from)?, parse_port(to)?),
+        None => {
+            let port = parse_port(entry)?;
+            (port, port)
+        }
+    };
+    if from > to {
+        return Err(format!("Port range must go from low to high: {}", entry));
+    }
+    Ok((from, to))
+}
+
+// The managed processes listen on the loopback interface
+fn is_port_free(protocol: PortProtocol, port: u16) -> bool {
+    let address = (std::net::Ipv4Addr::LOCALHOST, port);
+    match protocol {
+        PortProtocol::Tcp => std::net::TcpListener::bind(address).is_ok(),
+        PortProtocol::Udp => std::net::UdpSocket::bind(address).is_ok(),
+    }
+}
+
+/// Whether two listeners cannot both be opened: the same protocol and port, on the same IP address or where one listens on
+/// all addresses of the IP version of the other
+pub fn is_listen_conflict(protocol_a: PortProtocol, address_a: &SocketAddr, protocol_b: PortProtocol, address_b: &SocketAddr) -> bool {
+    if protocol_a != protocol_b || address_a.port() != address_b.port() {
+        return false;
+    }
+    let (ip_a, ip_b) = (address_a.ip(), address_b.ip());
+    ip_a == ip_b || (ip_a.is_ipv4() == ip_b.is_ipv4() && (ip_a.is_unspecified() || ip_b.is_unspecified()))
+}
+
+/// The listeners of the addresses that cannot be opened together, as messages naming both
+pub fn get_listen_conflicts(listeners: &[(PortProtocol, IpAddr, u16)]) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    for (index, (protocol_a, ip_a, port_a)) in listeners.iter().enumerate() {
+        let address_a = SocketAddr::new(*ip_a, *port_a);
+        for (protocol_b, ip_b, port_b) in &listeners[index + 1..] {
+            let address_b = SocketAddr::new(*ip_b, *port_b);
+            if is_listen_conflict(*protocol_a, &address_a, *protocol_b, &address_b) {
+                conflicts.push(format!("{} port {} is used by more than one binding: {} and {}", protocol_a, port_a, address_a, address_b));
+            }
+        }
+    }
+    conflicts
+}
+
 impl Default for PortManager {
     fn default() -> Self {
         Self::new(9000, 10000)
@@ -239,4 +388,83 @@ mod tests {
         let available_count = manager.available_port_count().await;
         assert!(available_count >= 1 && available_count <= 1000);
     }
+
+    #[tokio::test]
+    async fn test_excluded_ports_are_skipped() {
+        let manager = PortManager::new(9000, 9010);
+        manager.set_port_probing(false).await;
+        manager.configure(9000, 9010, &["9000".to_string(), "9002-9009".to_string()]).await;
+
+        assert_eq!(manager.allocate_port("service1".to_string()).await, Some(9001));
+        assert_eq!(manager.allocate_port("service2".to_string()).await, Some(9010));
+        assert_eq!(manager.allocate_port("service3".to_string()).await, None);
+        assert_eq!(manager.available_port_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_restarted_service_gets_its_previous_port() {
+        let manager = PortManager::new(9000, 9005);
+        manager.set_port_probing(false).await;
+
+        let port1 = manager.allocate_port("service1".to_string()).await.unwrap();
+        let port2 = manager.allocate_port("service2".to_string()).await.unwrap();
+        manager.release_port(port1).await;
+        manager.release_port(port2).await;
+
+        // Released in the other order, they still get their own port back
+        assert_eq!(manager.allocate_port("service2".to_string()).await, Some(port2));
+        assert_eq!(manager.allocate_port("service1".to_string()).await, Some(port1));
+    }
+
+    #[tokio::test]
+    async fn test_ports_in_use_are_skipped() {
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let used_port = listener.local_addr().unwrap().port();
+        let manager = PortManager::new(used_port, used_port);
+
+        assert_eq!(manager.allocate_port("service1".to_string()).await, None);
+        drop(listener);
+        assert_eq!(manager.allocate_port("service1".to_string()).await, Some(used_port));
+    }
+
+    #[test]
+    fn test_parse_port_range() {
+        assert_eq!(parse_port_range("9001"), Ok((9001, 9001)));
+        assert_eq!(parse_port_range(" 9100 - 9199 "), Ok((9100, 9199)));
+        assert!(parse_port_range("9199-9100").is_err());
+        assert!(parse_port_range("http").is_err());
+        assert!(parse_port_range("70000").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_and_udp_ports_are_allocated_separately() {
+        let manager = PortManager::new(9000, 9001);
+        manager.set_port_probing(false).await;
+
+        assert_eq!(manager.allocate_port_for_protocol("quic".to_string(), PortProtocol::Udp).await, Some(9000));
+        assert_eq!(manager.allocate_port("service1".to_string()).await, Some(9000));
+        assert_eq!(manager.allocate_port_for_protocol("service1".to_string(), PortProtocol::Udp).await, Some(9001));
+
+        manager.release_port_for_protocol(9000, PortProtocol::Udp).await;
+        assert_eq!(manager.allocate_port("service2".to_string()).await, Some(9001));
+        assert_eq!(manager.allocate_port("service3".to_string()).await, None);
+        assert_eq!(manager.allocate_port_for_protocol("service3".to_string(), PortProtocol::Udp).await, Some(9000));
+    }
+
+    #[test]
+    fn test_listen_conflicts() {
+        let any_v4: IpAddr = "0.0.0.0".parse().unwrap();
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let any_v6: IpAddr = "::".parse().unwrap();
+
+        // HTTPS and HTTP/3 on the same port
+        assert!(get_listen_conflicts(&[(PortProtocol::Tcp, any_v4, 443), (PortProtocol::Udp, any_v4, 443)]).is_empty());
+        // All addresses and one of them
+        let conflicts = get_listen_conflicts(&[(PortProtocol::Tcp, any_v4, 443), (PortProtocol::Tcp, localhost, 443)]);
+        assert_eq!(conflicts, vec!["TCP port 443 is used by more than one binding: 0.0.0.0:443 and 127.0.0.1:443".to_string()]);
+        // Different IP versions and different ports
+        assert!(get_listen_conflicts(&[(PortProtocol::Tcp, any_v4, 443), (PortProtocol::Tcp, any_v6, 443)]).is_empty());
+        assert!(get_listen_conflicts(&[(PortProtocol::Udp, localhost, 443), (PortProtocol::Udp, localhost, 8443)]).is_empty());
+        assert_eq!(get_listen_conflicts(&[(PortProtocol::Udp, localhost, 443), (PortProtocol::Udp, localhost, 443)]).len(), 1);
+    }
 }
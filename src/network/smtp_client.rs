@@ -0,0 +1,319 @@
+use crate::configuration::server_settings::SmtpSettings;
+use crate::network::dns_cache::get_dns_cache;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use std::time::Duration;
+use tls_listener::rustls as tokio_rustls;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+// The whole conversation with the mail server, from connecting until the message is accepted
+const SMTP_TIMEOUT: Duration = Duration::from_secs(60);
+// Replies longer than this are not from a mail server
+const MAX_REPLY_LENGTH: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutgoingEmail {
+    pub to: Vec<String>,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SmtpError {
+    // Worth trying again later, such as when the server cannot be reached or answers with a 4xx reply
+    Temporary(String),
+    // The server answered with a 5xx reply, so it will not take the message
+    Permanent(String),
+}
+
+impl SmtpError {
+    pub fn get_message(&self) -> &str {
+        match self {
+            SmtpError::Temporary(message) | SmtpError::Permanent(message) => message,
+        }
+    }
+}
+
+impl From<std::io::Error> for SmtpError {
+    fn from(e: std::io::Error) -> Self {
+        SmtpError::Temporary(e.to_string())
+    }
+}
+
+// Sends the email through the mail server in the SMTP settings, securing the connection as configured
+pub async fn send_email(settings: &SmtpSettings, email: &OutgoingEmail) -> Result<(), SmtpError> {
+    if !settings.is_enabled() {
+        return Err(SmtpError::Permanent("No mail server is configured".to_string()));
+    }
+    match tokio::time::timeout(SMTP_TIMEOUT, send_email_without_timeout(settings, email)).await {
+        Ok(result) => result,
+        Err(_) => Err(SmtpError::Temporary(format!("The mail server did not finish within {} seconds", SMTP_TIMEOUT.as_secs()))),
+    }
+}
+
+async fn send_email_without_timeout(settings: &SmtpSettings, email: &OutgoingEmail) -> Result<(), SmtpError> {
+    let stream = get_dns_cache().connect(&format!("{}:{}", settings.host, settings.port)).await?;
+    let client_name = get_client_name();
+
+    match settings.security.as_str() {
+        "tls" => {
+            let mut stream = BufReader::new(connect_tls(&settings.host, stream).await?);
+            expect_reply(&mut stream, 220).await?;
+            send_command(&mut stream, &format!("EHLO {}", client_name), 250).await?;
+            send_message(&mut stream, settings, email).await
+        }
+        "starttls" => {
+            let mut plain_stream = BufReader::new(stream);
+            expect_reply(&mut plain_stream, 220).await?;
+            send_command(&mut plain_stream, &format!("EHLO {}", client_name), 250).await?;
+            send_command(&mut plain_stream, "STARTTLS", 220).await?;
+            // The server says nothing more until the TLS handshake, so nothing is left in the buffer
+            let mut stream = BufReader::new(connect_tls(&settings.host, plain_stream.into_inner()).await?);
+            send_command(&mut stream, &format!("EHLO {}", client_name), 250).await?;
+            send_message(&mut stream, settings, email).await
+        }
+        _ => {
+            let mut stream = BufReader::new(stream);
+            expect_reply(&mut stream, 220).await?;
+            send_command(&mut stream, &format!("EHLO {}", client_name), 250).await?;
+            send_message(&mut stream, settings, email).await
+        }
+    }
+}
+
+async fn connect_tls(host: &str, stream: TcpStream) -> Result<tokio_rustls::client::TlsStream<TcpStream>, SmtpError> {
+    let server_name = ServerName::try_from(host.trim_start_matches('[').trim_end_matches(']').to_string()).map_err(|e| SmtpError::Permanent(e.to_string()))?;
+    let connector = TlsConnector::from(std::sync::Arc::new(crate::tls::tls_config::tls_config()));
+    Ok(connector.connect(server_name, stream).await?)
+}
+
+// Logs in if there is a username, then hands over the message, after the server has greeted us
+async fn send_message<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut BufReader<S>, settings: &SmtpSettings, email: &OutgoingEmail) -> Result<(), SmtpError> {
+    if !settings.username.is_empty() {
+        let credentials = format!("\0{}\0{}", settings.username, settings.password);
+        send_command(stream, &format!("AUTH PLAIN {}", BASE64_STANDARD.encode(credentials.as_bytes())), 235).await?;
+    }
+
+    send_command(stream, &format!("MAIL FROM:<{}>", settings.from_address), 250).await?;
+    for recipient in &email.to {
+        send_command(stream, &format!("RCPT TO:<{}>", recipient), 250).await?;
+    }
+    send_command(stream, "DATA", 354).await?;
+
+    let message = format_email_message(&settings.from_address, email, chrono::Utc::now());
+    stream.get_mut().write_all(message.as_bytes()).await?;
+    send_command(stream, ".", 250).await?;
+
+    // The message is accepted, so a failing QUIT does not matter
+    let _ = send_command(stream, "QUIT", 221).await;
+    Ok(())
+}
+
+async fn send_command<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut BufReader<S>, command: &str, expected_code: u16) -> Result<String, SmtpError> {
+    stream.get_mut().write_all(format!("{}\r\n", command).as_bytes()).await?;
+    stream.get_mut().flush().await?;
+    expect_reply(stream, expected_code).await.map_err(|e| match e {
+        // The credentials are not repeated in the error
+        SmtpError::Temporary(message) if command.starts_with("AUTH") => SmtpError::Temporary(format!("Login failed: {}", message)),
+        SmtpError::Permanent(message) if command.starts_with("AUTH") => SmtpError::Permanent(format!("Login failed: {}", message)),
+        SmtpError::Temporary(message) => SmtpError::Temporary(format!("{}: {}", command, message)),
+        SmtpError::Permanent(message) => SmtpError::Permanent(format!("{}: {}", command, message)),
+    })
+}
+
+// Reads a reply, which may span several lines like "250-first" and "250 last", and checks its code. 4xx codes are temporary
+// failures and 5xx codes permanent ones
+async fn expect_reply<S: AsyncRead + Unpin>(stream: &mut BufReader<S>, expected_code: u16) -> Result<String, SmtpError> {
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(SmtpError::Temporary("The mail server closed the connection".to_string()));
+        }
+        reply.push_str(&line);
+        if reply.len() > MAX_REPLY_LENGTH {
+            return Err(SmtpError::Temporary("The mail server sent a reply that is too long".to_string()));
+        }
+        if line.len() < 4 || line.as_bytes()[3] != b'-' {
+            break;
+        }
+    }
+
+    let code = reply
+        .get(..3)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| SmtpError::Temporary(format!("Not an SMTP reply: {}", reply.trim())))?;
+    if code == expected_code || (expected_code == 250 && code == 251) {
+        return Ok(reply);
+    }
+    let message = format!("The mail server answered {}", reply.trim());
+    if (500..600).contains(&code) {
+        Err(SmtpError::Permanent(message))
+    } else {
+        Err(SmtpError::Temporary(message))
+    }
+}
+
+// The message as sent after DATA: the headers and the body, with lines ending in CRLF and lines starting with a dot doubled, so
+// they cannot end the message early. Line breaks are taken out of the header values, so they cannot add headers
+pub fn format_email_message(from: &str, email: &OutgoingEmail, date: chrono::DateTime<chrono::Utc>) -> String {
+    let header_value = |value: &str| value.replace(['\r', '\n'], " ");
+    let subject = header_value(&email.subject);
+    let subject = if subject.is_ascii() {
+        subject
+    } else {
+        format!("=?UTF-8?B?{}?=", BASE64_STANDARD.encode(subject.as_bytes()))
+    };
+    let domain = from.rsplit_once('@').map(|(_, domain)| domain).unwrap_or("localhost");
+
+    let mut message = String::new();
+    message.push_str(&format!("From: {}\r\n", header_value(from)));
+    message.push_str(&format!("To: {}\r\n", header_value(&email.to.join(", "))));
+    message.push_str(&format!("Subject: {}\r\n", subject));
+    message.push_str(&format!("Date: {}\r\n", date.to_rfc2822()));
+    message.push_str(&format!("Message-ID: <{}@{}>\r\n", uuid::Uuid::new_v4(), header_value(domain)));
+    message.push_str("MIME-Version: 1.0\r\n");
+    message.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    message.push_str("Content-Transfer-Encoding: 8bit\r\n");
+    message.push_str("\r\n");
+    for line in email.body.replace("\r\n", "\n").split('\n') {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message
+}
+
+// The name we greet the mail server with
+fn get_client_name() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-'))
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_message_format() {
+        let email = OutgoingEmail {
+            to: vec!["ops@example.com".to_string()],
+            subject: "Alert\r\nBcc: someone@example.com".to_string(),
+            body: "First line\n.hidden\nLast line".to_string(),
+        };
+        let message = format_email_message("gruxi@example.com", &email, chrono::Utc::now());
+        assert!(message.contains("Subject: Alert  Bcc: someone@example.com\r\n"));
+        assert!(!message.contains("\r\nBcc:"));
+        assert!(message.contains("Message-ID: <") && message.contains("@example.com>\r\n"));
+        assert!(message.ends_with("\r\n\r\nFirst line\r\n..hidden\r\nLast line\r\n"));
+
+        let email = OutgoingEmail {
+            subject: "Certifikat udløber".to_string(),
+            ..email
+        };
+        assert!(format_email_message("gruxi@example.com", &email, chrono::Utc::now()).contains("Subject: =?UTF-8?B?"));
+    }
+
+    #[tokio::test]
+    async fn test_send_email_to_mail_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // A mail server that takes one message and returns the commands it got
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(b"220 mail.example.com ESMTP\r\n").await.unwrap();
+            let mut commands = Vec::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 Queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-mail.example.com\r\n250 AUTH PLAIN\r\n"
+                } else if line.starts_with("AUTH") {
+                    b"235 Authenticated\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 Go ahead\r\n"
+                } else if line == "QUIT" {
+                    commands.push(line);
+                    stream.get_mut().write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 OK\r\n"
+                };
+                commands.push(line);
+                stream.get_mut().write_all(reply).await.unwrap();
+            }
+            commands
+        });
+
+        let settings = SmtpSettings {
+            host: "127.0.0.1".to_string(),
+            port,
+            security: "none".to_string(),
+            username: "gruxi".to_string(),
+            password: "secret".to_string(),
+            from_address: "gruxi@example.com".to_string(),
+            notification_recipients: Vec::new(),
+        };
+        let email = OutgoingEmail {
+            to: vec!["ops@example.com".to_string()],
+            subject: "Test".to_string(),
+            body: "Hello".to_string(),
+        };
+        send_email(&settings, &email).await.unwrap();
+
+        let commands = server.await.unwrap();
+        assert_eq!(commands[1], format!("AUTH PLAIN {}", BASE64_STANDARD.encode(b"\0gruxi\0secret")));
+        assert_eq!(commands[2..5], ["MAIL FROM:<gruxi@example.com>", "RCPT TO:<ops@example.com>", "DATA"]);
+        assert_eq!(commands.last().unwrap(), "QUIT");
+
+        // A recipient the server rejects is a permanent failure
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let settings = SmtpSettings {
+            port: listener.local_addr().unwrap().port(),
+            ..settings
+        };
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(b"220 mail.example.com ESMTP\r\n").await.unwrap();
+            let mut line = String::new();
+            while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                let reply: &[u8] = if line.starts_with("RCPT") {
+                    b"550 No such user\r\n"
+                } else if line.starts_with("AUTH") {
+                    b"235 Authenticated\r\n"
+                } else {
+                    b"250 OK\r\n"
+                };
+                stream.get_mut().write_all(reply).await.unwrap();
+                line.clear();
+            }
+        });
+        let email = OutgoingEmail {
+            to: vec!["nobody@example.com".to_string()],
+            ..email
+        };
+        assert!(matches!(send_email(&settings, &email).await, Err(SmtpError::Permanent(message)) if message.contains("550")));
+    }
+}
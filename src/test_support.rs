@@ -0,0 +1,205 @@
+use crate::configuration::configuration::Configuration;
+use crate::core::command_line_args::init_command_line_args_from;
+use crate::core::grux_server::GruxServer;
+use crate::core::triggers::get_trigger_handler;
+use crate::logging::syslog::{error, info};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use uuid::Uuid;
+
+// Boots the full Gruxi server inside the current process, such as an integration test, on free ports on 127.0.0.1 and with a temporary database.
+//
+// Like GruxServer, it can only be started once per process. Integration tests in the same
+// test binary should share the server, for example through a OnceLock:
+//
+//     static TEST_SERVER: OnceLock<TestServer> = OnceLock::new();
+//     let server = TEST_SERVER.get_or_init(|| spawn_test_server(test_configuration()).unwrap());
+//     let addr = server.get_http_address().unwrap();
+//
+// The server runs on its own thread and runtime, so it outlives the runtimes of the individual #[tokio::test] functions.
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct TestServer {
+    // The addresses the bindings of the configuration listen on, in the order of the bindings
+    pub bindings: Vec<TestServerBinding>,
+    pub admin_address: Option<SocketAddr>,
+    pub data_dir: PathBuf,
+    runtime_handle: tokio::runtime::Handle,
+    server_thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestServerBinding {
+    pub binding_id: String,
+    pub address: SocketAddr,
+    pub is_tls: bool,
+}
+
+impl TestServer {
+    // The address of the first plain HTTP binding
+    pub fn get_http_address(&self) -> Option<SocketAddr> {
+        self.bindings.iter().find(|b| !b.is_tls).map(|b| b.address)
+    }
+
+    // The address of the first TLS binding
+    pub fn get_https_address(&self) -> Option<SocketAddr> {
+        self.bindings.iter().find(|b| b.is_tls).map(|b| b.address)
+    }
+
+    pub fn get_http_url(&self, path: &str) -> Option<String> {
+        self.get_http_address().map(|address| format!("http://{}{}", address, path))
+    }
+
+    // Stops the server and removes its temporary data. The server cannot be started again in this process
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let Some(server_thread) = self.server_thread.take() else {
+            return;
+        };
+        self.runtime_handle.spawn(async {
+            get_trigger_handler().run_trigger("shutdown").await;
+        });
+        if server_thread.join().is_err() {
+            error("Test server thread panicked during shutdown");
+        }
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// The default configuration, without touching the command line of the host process, which Configuration::get_default() would parse
+pub fn test_configuration() -> Configuration {
+    init_command_line_args_from(&[]);
+    Configuration::get_default()
+}
+
+// Starts Gruxi with the configuration in a temporary data directory and returns once all bindings accept requests.
+// All bindings (and the admin portal, if enabled) are moved to free ports on 127.0.0.1, and TLS sites without a certificate get a self-signed one
+// from the temporary data directory, so nothing is written to the certs directory of the working directory
+pub fn spawn_test_server(mut configuration: Configuration) -> Result<TestServer, String> {
+    let data_dir = std::env::temp_dir().join(format!("gruxi-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&data_dir).map_err(|e| format!("Failed to create test data directory '{}': {}", data_dir.display(), e))?;
+
+    // Move the bindings to free ports
+    let mut bindings = Vec::new();
+    for binding in configuration.bindings.iter_mut() {
+        let address = get_free_local_address()?;
+        binding.ip = address.ip().to_string();
+        binding.port = address.port();
+        bindings.push(TestServerBinding {
+            binding_id: binding.id.clone(),
+            address,
+            is_tls: binding.is_tls,
+        });
+    }
+
+    let admin_address = if configuration.core.admin_portal.is_enabled {
+        let address = get_free_local_address()?;
+        configuration.core.admin_portal.port = address.port();
+        Some(address)
+    } else {
+        None
+    };
+
+    add_self_signed_certificates(&mut configuration, &data_dir)?;
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to build test server runtime: {}", e))?;
+    let runtime_handle = runtime.handle().clone();
+
+    let database_path = data_dir.join("gruxi.db").to_string_lossy().to_string();
+    let (started_sender, started_receiver) = std::sync::mpsc::channel::<Result<(), String>>();
+    let server_thread = std::thread::Builder::new()
+        .name("gruxi-test-server".to_string())
+        .spawn(move || {
+            runtime.block_on(async move {
+                let server = match GruxServer::builder().configuration(configuration).database_path(&database_path).start().await {
+                    Ok(server) => server,
+                    Err(e) => {
+                        let _ = started_sender.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = started_sender.send(Ok(()));
+                server.wait().await;
+            })
+        })
+        .map_err(|e| format!("Failed to start test server thread: {}", e))?;
+
+    started_receiver
+        .recv_timeout(STARTUP_TIMEOUT)
+        .map_err(|_| "Test server did not start within the startup timeout".to_string())??;
+
+    info(format!("Test server started with data directory {}", data_dir.display()));
+
+    Ok(TestServer {
+        bindings,
+        admin_address,
+        data_dir,
+        runtime_handle,
+        server_thread: Some(server_thread),
+    })
+}
+
+// Binds port 0 to have the OS pick a free port, which is released again for the server to bind
+fn get_free_local_address() -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).map_err(|e| format!("Failed to find a free port: {}", e))?;
+    listener.local_addr().map_err(|e| format!("Failed to find a free port: {}", e))
+}
+
+fn add_self_signed_certificates(configuration: &mut Configuration, data_dir: &Path) -> Result<(), String> {
+    let tls_binding_ids: Vec<&String> = configuration.bindings.iter().filter(|b| b.is_tls).map(|b| &b.id).collect();
+    let needs_certificate = |site_id: &String| {
+        configuration
+            .binding_sites
+            .iter()
+            .any(|relation| &relation.site_id == site_id && tls_binding_ids.contains(&&relation.binding_id))
+    };
+    let site_ids: Vec<String> = configuration
+        .sites
+        .iter()
+        .filter(|site| !site.tls_automatic_enabled && site.tls_cert_path.is_empty() && site.tls_cert_content.is_empty())
+        .filter(|site| needs_certificate(&site.id))
+        .map(|site| site.id.clone())
+        .collect();
+
+    let admin_portal = &configuration.core.admin_portal;
+    let admin_needs_certificate = admin_portal.is_enabled && !admin_portal.tls_automatic_enabled && admin_portal.get_tls_certificate_path().is_empty();
+
+    if site_ids.is_empty() && !admin_needs_certificate {
+        return Ok(());
+    }
+
+    let rcgen::CertifiedKey { cert, signing_key } = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).map_err(|e| format!("Failed to generate self-signed certificate: {}", e))?;
+    let cert_pem = cert.pem();
+    let key_pem = signing_key.serialize_pem();
+
+    for site in configuration.sites.iter_mut().filter(|site| site_ids.contains(&site.id)) {
+        site.tls_cert_content = cert_pem.clone();
+        site.tls_key_content = key_pem.clone();
+    }
+
+    if admin_needs_certificate {
+        let cert_path = data_dir.join("admin.crt.pem");
+        let key_path = data_dir.join("admin.key.pem");
+        std::fs::write(&cert_path, &cert_pem).map_err(|e| format!("Failed to write test certificate: {}", e))?;
+        std::fs::write(&key_path, &key_pem).map_err(|e| format!("Failed to write test key: {}", e))?;
+        configuration.core.admin_portal.tls_certificate_path = Some(cert_path.to_string_lossy().to_string());
+        configuration.core.admin_portal.tls_key_path = Some(key_path.to_string_lossy().to_string());
+    }
+
+    Ok(())
+}
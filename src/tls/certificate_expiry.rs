@@ -0,0 +1,101 @@
+use crate::configuration::cached_configuration::get_cached_configuration;
+use crate::configuration::site::Site;
+use crate::core::email::{EmailTemplate, get_email_queue};
+use crate::logging::syslog::{debug, warn};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// Certificates expiring within this many days are reported to the notification recipients, once a day until they are replaced
+const CERTIFICATE_EXPIRY_NOTICE_DAYS: i64 = 14;
+const CERTIFICATE_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Checks the certificates of the sites now and once a day, emailing a notice for those about to expire
+pub fn start_certificate_expiry_task() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(CERTIFICATE_EXPIRY_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            check_certificate_expiry().await;
+        }
+    });
+}
+
+async fn check_certificate_expiry() {
+    let sites: Vec<Site> = get_cached_configuration().get_configuration().await.sites.iter().filter(|site| site.is_enabled).cloned().collect();
+
+    // Sites sharing a certificate get one notice, with the hostnames of all of them
+    let mut certificates: BTreeMap<String, (Vec<u8>, Vec<String>)> = BTreeMap::new();
+    for site in &sites {
+        let (source, pem) = if !site.tls_cert_path.is_empty() {
+            match tokio::fs::read(&site.tls_cert_path).await {
+                Ok(pem) => (site.tls_cert_path.clone(), pem),
+                Err(e) => {
+                    warn(format!("Failed to read TLS cert file {} to check its expiry: {}", site.tls_cert_path, e));
+                    continue;
+                }
+            }
+        } else if !site.tls_cert_content.is_empty() {
+            (format!("site {}", site.id), site.tls_cert_content.as_bytes().to_vec())
+        } else {
+            continue;
+        };
+        certificates.entry(source).or_insert_with(|| (pem, Vec::new())).1.extend(site.hostnames.iter().cloned());
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    for (source, (pem, hostnames)) in certificates {
+        let not_after = match get_certificate_not_after(&pem) {
+            Ok(not_after) => not_after,
+            Err(e) => {
+                warn(format!("Failed to check the expiry of the certificate of {}: {}", source, e));
+                continue;
+            }
+        };
+        let days = get_days_until_expiry(not_after, now);
+        debug(format!("Certificate of {} expires in {} days", source, days));
+        if days > CERTIFICATE_EXPIRY_NOTICE_DAYS {
+            continue;
+        }
+
+        let expires_at = chrono::DateTime::from_timestamp(not_after, 0).map(|expires_at| expires_at.to_rfc3339()).unwrap_or_default();
+        warn(format!("Certificate of {} for {} expires in {} days, on {}", source, hostnames.join(", "), days, expires_at));
+        let values = BTreeMap::from([("domains", hostnames.join(", ")), ("days", days.to_string()), ("expires_at", expires_at)]);
+        get_email_queue().notify(EmailTemplate::CertificateExpiry, &values).await;
+    }
+}
+
+// The end of the validity of the first certificate in the PEM, the one of the server, as a Unix timestamp
+pub fn get_certificate_not_after(pem: &[u8]) -> Result<i64, String> {
+    let certificate = rustls_pemfile::certs(&mut std::io::Cursor::new(pem))
+        .next()
+        .ok_or("No certificate found")?
+        .map_err(|e| format!("Failed to parse certificate: {}", e))?;
+    let (_, certificate) = x509_parser::parse_x509_certificate(&certificate).map_err(|e| format!("Failed to parse certificate: {}", e))?;
+    Ok(certificate.validity().not_after.timestamp())
+}
+
+// Whole days left, rounded down, and negative once the certificate has expired
+fn get_days_until_expiry(not_after: i64, now: i64) -> i64 {
+    (not_after - now).div_euclid(24 * 60 * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_expiry() {
+        let mut params = rcgen::CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        params.not_after = rcgen::date_time_ymd(2030, 1, 2);
+        let certificate = params.self_signed(&rcgen::KeyPair::generate().unwrap()).unwrap();
+
+        let not_after = get_certificate_not_after(certificate.pem().as_bytes()).unwrap();
+        assert_eq!(chrono::DateTime::from_timestamp(not_after, 0).unwrap().to_rfc3339(), "2030-01-02T00:00:00+00:00");
+        assert!(get_certificate_not_after(b"not a certificate").is_err());
+
+        let day = 24 * 60 * 60;
+        assert_eq!(get_days_until_expiry(not_after, not_after - 14 * day), 14);
+        assert_eq!(get_days_until_expiry(not_after, not_after - 14 * day + 1), 13);
+        assert_eq!(get_days_until_expiry(not_after, not_after + 1), -1);
+    }
+}
@@ -0,0 +1,374 @@
+use md5::{Digest, Md5};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// Calculated data keys on the request, for the fingerprints of the TLS connection it came in on
+pub const TLS_JA3_KEY: &str = "tls_ja3";
+pub const TLS_JA4_KEY: &str = "tls_ja4";
+
+// A ClientHello fits in a single TLS record, which is at most 16 KB plus the record header
+const MAX_CLIENT_HELLO_RECORD_SIZE: usize = 5 + 16384;
+
+// Fingerprints of the ClientHello of a TLS connection. They identify the TLS library and settings of a client,
+// so clients such as scraping bots can be recognized even when they rotate IP addresses
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsFingerprint {
+    pub ja3: String,      // MD5 of the JA3 string
+    pub ja3_full: String, // The JA3 string itself: version,ciphers,extensions,curves,point formats
+    pub ja4: String,
+}
+
+// Wraps the TCP stream of a TLS connection during the handshake, keeping a copy of the first TLS record read, which is the ClientHello
+pub struct ClientHelloCapture<S> {
+    inner: S,
+    captured: Vec<u8>,
+    is_capturing: bool,
+}
+
+impl<S> ClientHelloCapture<S> {
+    pub fn new(inner: S) -> Self {
+        ClientHelloCapture {
+            inner,
+            captured: Vec::new(),
+            is_capturing: true,
+        }
+    }
+
+    // Stops capturing and returns the fingerprint of the captured ClientHello, if it could be parsed
+    pub fn take_fingerprint(&mut self) -> Option<TlsFingerprint> {
+        self.is_capturing = false;
+        let captured = std::mem::take(&mut self.captured);
+        get_tls_fingerprint(&captured)
+    }
+
+    fn is_record_complete(&self) -> bool {
+        if self.captured.len() < 5 {
+            return false;
+        }
+        let record_length = u16::from_be_bytes([self.captured[3], self.captured[4]]) as usize;
+        self.captured.len() >= 5 + record_length
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ClientHelloCapture<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if self.is_capturing && result.is_ready() {
+            let read = &buf.filled()[filled_before..];
+            let remaining = MAX_CLIENT_HELLO_RECORD_SIZE.saturating_sub(self.captured.len());
+            self.captured.extend_from_slice(&read[..read.len().min(remaining)]);
+            if read.is_empty() || self.is_record_complete() || self.captured.len() >= MAX_CLIENT_HELLO_RECORD_SIZE {
+                self.is_capturing = false;
+            }
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ClientHelloCapture<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[std::io::IoSlice<'_>]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+// The parts of a ClientHello that go into the fingerprints
+#[derive(Debug, Default)]
+struct ClientHello {
+    version: u16,
+    cipher_suites: Vec<u16>,
+    extensions: Vec<u16>,
+    supported_groups: Vec<u16>,
+    ec_point_formats: Vec<u8>,
+    signature_algorithms: Vec<u16>,
+    supported_versions: Vec<u16>,
+    alpn_protocols: Vec<Vec<u8>>,
+    has_sni: bool,
+}
+
+// Reads big endian values from the ClientHello, None when the message is cut short
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.data.len() < length {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3).map(|b| ((b[0] as usize) << 16) | ((b[1] as usize) << 8) | b[2] as usize)
+    }
+
+    fn u16_list(data: &[u8]) -> Vec<u16> {
+        data.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect()
+    }
+}
+
+fn parse_client_hello(record: &[u8]) -> Option<ClientHello> {
+    let mut reader = Reader { data: record };
+
+    // Record header, which must be a handshake
+    if reader.u8()? != 0x16 {
+        return None;
+    }
+    reader.u16()?;
+    let record_length = reader.u16()? as usize;
+    let mut reader = Reader {
+        data: reader.take(record_length.min(reader.data.len()))?,
+    };
+
+    // Handshake header, which must be a ClientHello
+    if reader.u8()? != 0x01 {
+        return None;
+    }
+    let handshake_length = reader.u24()?;
+    let mut reader = Reader { data: reader.take(handshake_length)? };
+
+    let mut client_hello = ClientHello {
+        version: reader.u16()?,
+        ..Default::default()
+    };
+    reader.take(32)?; // Random
+    let session_id_length = reader.u8()? as usize;
+    reader.take(session_id_length)?;
+    let cipher_suites_length = reader.u16()? as usize;
+    client_hello.cipher_suites = Reader::u16_list(reader.take(cipher_suites_length)?);
+    let compression_methods_length = reader.u8()? as usize;
+    reader.take(compression_methods_length)?;
+
+    // Extensions are optional in older clients
+    let Some(extensions_length) = reader.u16() else {
+        return Some(client_hello);
+    };
+    let mut extensions = Reader {
+        data: reader.take(extensions_length as usize)?,
+    };
+    while !extensions.data.is_empty() {
+        let extension_type = extensions.u16()?;
+        let extension_length = extensions.u16()? as usize;
+        let mut extension = Reader {
+            data: extensions.take(extension_length)?,
+        };
+        client_hello.extensions.push(extension_type);
+
+        match extension_type {
+            0x0000 => client_hello.has_sni = true,
+            0x000a => {
+                let length = extension.u16()? as usize;
+                client_hello.supported_groups = Reader::u16_list(extension.take(length)?);
+            }
+            0x000b => {
+                let length = extension.u8()? as usize;
+                client_hello.ec_point_formats = extension.take(length)?.to_vec();
+            }
+            0x000d => {
+                let length = extension.u16()? as usize;
+                client_hello.signature_algorithms = Reader::u16_list(extension.take(length)?);
+            }
+            0x0010 => {
+                let length = extension.u16()? as usize;
+                let mut protocols = Reader { data: extension.take(length)? };
+                while !protocols.data.is_empty() {
+                    let protocol_length = protocols.u8()? as usize;
+                    client_hello.alpn_protocols.push(protocols.take(protocol_length)?.to_vec());
+                }
+            }
+            0x002b => {
+                let length = extension.u8()? as usize;
+                client_hello.supported_versions = Reader::u16_list(extension.take(length)?);
+            }
+            _ => {}
+        }
+    }
+
+    Some(client_hello)
+}
+
+// GREASE values (RFC 8701) are random placeholders that clients add to keep servers tolerant, so they are left out of fingerprints
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn join_values<T: ToString>(values: impl Iterator<Item = T>, separator: &str) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(separator)
+}
+
+pub fn get_tls_fingerprint(record: &[u8]) -> Option<TlsFingerprint> {
+    let client_hello = parse_client_hello(record)?;
+    let ja3_full = get_ja3_string(&client_hello);
+    Some(TlsFingerprint {
+        ja3: to_hex(&Md5::digest(ja3_full.as_bytes())),
+        ja3_full,
+        ja4: get_ja4(&client_hello),
+    })
+}
+
+fn get_ja3_string(client_hello: &ClientHello) -> String {
+    format!(
+        "{},{},{},{},{}",
+        client_hello.version,
+        join_values(client_hello.cipher_suites.iter().filter(|v| !is_grease(**v)), "-"),
+        join_values(client_hello.extensions.iter().filter(|v| !is_grease(**v)), "-"),
+        join_values(client_hello.supported_groups.iter().filter(|v| !is_grease(**v)), "-"),
+        join_values(client_hello.ec_point_formats.iter(), "-")
+    )
+}
+
+// JA4, such as t13d1516h2_8daaf6152771_02713d6af862: protocol, TLS version, SNI, counts and ALPN, then truncated hashes of the sorted ciphers and extensions
+fn get_ja4(client_hello: &ClientHello) -> String {
+    let version = client_hello.supported_versions.iter().copied().filter(|v| !is_grease(*v)).max().unwrap_or(client_hello.version);
+    let version = match version {
+        0x0304 => "13",
+        0x0303 => "12",
+        0x0302 => "11",
+        0x0301 => "10",
+        0x0300 => "s3",
+        _ => "00",
+    };
+
+    let mut cipher_suites: Vec<u16> = client_hello.cipher_suites.iter().copied().filter(|v| !is_grease(*v)).collect();
+    let extensions: Vec<u16> = client_hello.extensions.iter().copied().filter(|v| !is_grease(*v)).collect();
+
+    let alpn = match client_hello.alpn_protocols.first() {
+        Some(protocol) if !protocol.is_empty() => {
+            let (first, last) = (protocol[0], protocol[protocol.len() - 1]);
+            if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                format!("{}{}", first as char, last as char)
+            } else {
+                let (first_hex, last_hex) = (format!("{:02x}", first), format!("{:02x}", last));
+                format!("{}{}", &first_hex[..1], &last_hex[1..])
+            }
+        }
+        _ => "00".to_string(),
+    };
+
+    let ja4_a = format!(
+        "t{}{}{:02}{:02}{}",
+        version,
+        if client_hello.has_sni { "d" } else { "i" },
+        cipher_suites.len().min(99),
+        extensions.len().min(99),
+        alpn
+    );
+
+    cipher_suites.sort_unstable();
+    let ja4_b = get_truncated_sha256(&join_values(cipher_suites.iter().map(|v| format!("{:04x}", v)), ","));
+
+    // The SNI and ALPN extensions are already part of the first section
+    let mut sorted_extensions: Vec<u16> = extensions.into_iter().filter(|v| *v != 0x0000 && *v != 0x0010).collect();
+    sorted_extensions.sort_unstable();
+    let mut ja4_c = join_values(sorted_extensions.iter().map(|v| format!("{:04x}", v)), ",");
+    if !client_hello.signature_algorithms.is_empty() {
+        ja4_c.push('_');
+        ja4_c.push_str(&join_values(client_hello.signature_algorithms.iter().map(|v| format!("{:04x}", v)), ","));
+    }
+    let ja4_c = if sorted_extensions.is_empty() { "000000000000".to_string() } else { get_truncated_sha256(&ja4_c) };
+
+    format!("{}_{}_{}", ja4_a, ja4_b, ja4_c)
+}
+
+fn get_truncated_sha256(value: &str) -> String {
+    if value.is_empty() {
+        return "000000000000".to_string();
+    }
+    let digest = ring::digest::digest(&ring::digest::SHA256, value.as_bytes());
+    to_hex(digest.as_ref())[..12].to_string()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a ClientHello record with TLS 1.3, the given ciphers and SNI, ALPN h2, groups, point formats, signature algorithms and supported versions
+    fn build_client_hello(cipher_suites: &[u16]) -> Vec<u8> {
+        let mut extensions = Vec::new();
+        let mut add_extension = |extension_type: u16, data: Vec<u8>| {
+            extensions.extend_from_slice(&extension_type.to_be_bytes());
+            extensions.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            extensions.extend_from_slice(&data);
+        };
+        add_extension(0x0a0a, vec![]);
+        add_extension(0x0000, vec![0, 14, 0, 0, 11, b'e', b'x', b'a', b'm', b'p', b'l', b'e', b'.', b'c', b'o', b'm']);
+        add_extension(0x000a, vec![0, 6, 0x0a, 0x0a, 0, 0x1d, 0, 0x17]);
+        add_extension(0x000b, vec![1, 0]);
+        add_extension(0x000d, vec![0, 4, 0x04, 0x03, 0x08, 0x04]);
+        add_extension(0x0010, vec![0, 3, 2, b'h', b'2']);
+        add_extension(0x002b, vec![4, 0x03, 0x04, 0x03, 0x03]);
+
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0u8; 32]);
+        body.push(0);
+        body.extend_from_slice(&((cipher_suites.len() * 2) as u16).to_be_bytes());
+        for cipher_suite in cipher_suites {
+            body.extend_from_slice(&cipher_suite.to_be_bytes());
+        }
+        body.extend_from_slice(&[1, 0]);
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(&extensions);
+
+        let mut handshake = vec![0x01];
+        handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&body);
+
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn test_tls_fingerprint() {
+        let fingerprint = get_tls_fingerprint(&build_client_hello(&[0x1a1a, 0x1301, 0xc02b])).unwrap();
+        assert_eq!(fingerprint.ja3_full, "771,4865-49195,0-10-11-13-16-43,29-23,0");
+        assert_eq!(fingerprint.ja3, "87991a9b84cb5b4bc5f84c5ecad46032");
+        assert!(fingerprint.ja4.starts_with("t13d0206h2_"));
+        assert_eq!(fingerprint.ja4.len(), "t13d0206h2_".len() + 12 + 1 + 12);
+
+        // GREASE and the order of the ciphers do not change JA4
+        let reordered = get_tls_fingerprint(&build_client_hello(&[0xc02b, 0x1301])).unwrap();
+        assert_eq!(reordered.ja4, fingerprint.ja4);
+        assert_ne!(reordered.ja3, fingerprint.ja3);
+    }
+
+    #[test]
+    fn test_tls_fingerprint_invalid() {
+        assert!(get_tls_fingerprint(b"GET / HTTP/1.1\r\n\r\n").is_none());
+        let record = build_client_hello(&[0x1301]);
+        assert!(get_tls_fingerprint(&record[..40]).is_none());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::http::request_response::gruxi_request::GruxiRequest;
+use crate::http::request_response::gruxi_response::GruxiResponse;
+use crate::logging::syslog::trace;
+use hyper::header::HeaderValue;
+use rustls::sign::CertifiedKey;
+use rustls_pki_types::CertificateDer;
+use std::path::Path;
+
+// HTTP-01 challenges are fetched by the CA from this path, followed by the token
+pub const ACME_CHALLENGE_PATH_PREFIX: &str = "/.well-known/acme-challenge/";
+
+// Key authorizations are a token and a key thumbprint, so anything bigger is not one
+const MAX_CHALLENGE_FILE_SIZE: u64 = 4096;
+
+// Tokens are base64url, see RFC 8555 section 8.3, which also keeps them from naming anything outside the challenge directory
+pub fn is_acme_challenge_token(token: &str) -> bool {
+    !token.is_empty() && token.len() <= 256 && token.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+// Serves the HTTP-01 challenge files an external ACME client, such as certbot, writes into the challenge directory of the TLS settings.
+// These are served on every site, regardless of its web root and request handlers, so certificates can be obtained for any of them
+pub async fn handle_acme_challenge_request(gruxi_request: &mut GruxiRequest, challenge_directory: &str) -> GruxiResponse {
+    let path = gruxi_request.get_path();
+    let token = path.strip_prefix(ACME_CHALLENGE_PATH_PREFIX).unwrap_or_default();
+    if !is_acme_challenge_token(token) {
+        return GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16());
+    }
+
+    let file_path = Path::new(challenge_directory).join(token);
+    let content = match tokio::fs::metadata(&file_path).await {
+        Ok(metadata) if metadata.is_file() && metadata.len() <= MAX_CHALLENGE_FILE_SIZE => tokio::fs::read(&file_path).await.ok(),
+        _ => None,
+    };
+    match content {
+        Some(content) => {
+            trace(format!("Serving ACME challenge {} from {}", token, challenge_directory));
+            let mut response = GruxiResponse::new_with_bytes(hyper::StatusCode::OK.as_u16(), content);
+            response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+            response.headers_mut().insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            response
+        }
+        None => {
+            trace(format!("No ACME challenge file for token {} in {}", token, challenge_directory));
+            GruxiResponse::new_empty_with_status(hyper::StatusCode::NOT_FOUND.as_u16())
+        }
+    }
+}
+
+// Checks that a certificate chain and private key from an external ACME client can be served, and that the key belongs to the
+// certificate. Returns the number of certificates in the chain
+pub fn validate_certificate_and_key(certificate_pem: &[u8], key_pem: &[u8]) -> Result<usize, String> {
+    let certificates: Result<Vec<CertificateDer<'static>>, _> = rustls_pemfile::certs(&mut std::io::Cursor::new(certificate_pem)).collect();
+    let certificates = certificates.map_err(|e| format!("Failed to parse certificate: {}", e))?;
+    if certificates.is_empty() {
+        return Err("No certificates found in the certificate PEM".to_string());
+    }
+    let key = rustls_pemfile::private_key(&mut std::io::Cursor::new(key_pem))
+        .map_err(|e| format!("Failed to parse private key: {}", e))?
+        .ok_or("No private key found in the key PEM")?;
+
+    let count = certificates.len();
+    CertifiedKey::from_der(certificates, key, &rustls::crypto::aws_lc_rs::default_provider()).map_err(|e| format!("The private key cannot be used with the certificate: {}", e))?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acme_challenge_tokens() {
+        assert!(is_acme_challenge_token("LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"));
+        assert!(!is_acme_challenge_token(""));
+        assert!(!is_acme_challenge_token("../secret"));
+        assert!(!is_acme_challenge_token("token.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_serve_acme_challenge() {
+        let directory = std::env::temp_dir().join(format!("gruxi-test-acme-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("abc_123"), "abc_123.thumbprint").unwrap();
+        let directory_str = directory.to_string_lossy().to_string();
+
+        let request = hyper::Request::builder().uri("/.well-known/acme-challenge/abc_123").body(hyper::body::Bytes::new()).unwrap();
+        let mut response = handle_acme_challenge_request(&mut GruxiRequest::new(request), &directory_str).await;
+        assert_eq!(response.get_status(), 200);
+        assert_eq!(response.get_body_bytes().await, "abc_123.thumbprint");
+
+        let request = hyper::Request::builder().uri("/.well-known/acme-challenge/missing").body(hyper::body::Bytes::new()).unwrap();
+        assert_eq!(handle_acme_challenge_request(&mut GruxiRequest::new(request), &directory_str).await.get_status(), 404);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_validate_certificate_and_key() {
+        let rcgen::CertifiedKey { cert, signing_key } = rcgen::generate_simple_self_signed(vec!["example.com".to_string()]).unwrap();
+        let other = rcgen::generate_simple_self_signed(vec!["example.com".to_string()]).unwrap();
+
+        assert_eq!(validate_certificate_and_key(cert.pem().as_bytes(), signing_key.serialize_pem().as_bytes()), Ok(1));
+        assert!(validate_certificate_and_key(cert.pem().as_bytes(), other.signing_key.serialize_pem().as_bytes()).is_err());
+        assert!(validate_certificate_and_key(b"", signing_key.serialize_pem().as_bytes()).is_err());
+    }
+}
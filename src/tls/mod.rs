@@ -1,2 +1,7 @@
+pub mod certificate_expiry;
+pub mod client_hello_fingerprint;
 pub mod shared_acme_manager;
 pub mod tls_config;
+pub mod upstream_tls;
+
+pub mod external_acme;
@@ -13,7 +13,7 @@
 // ============================================================================
 
 use crate::core::running_state_manager::get_running_state_manager;
-use crate::core::triggers::get_trigger_handler;
+use crate::core::triggers::{GruxiEvent, get_trigger_handler};
 use crate::logging::syslog::{debug, trace};
 use rustls_acme::caches::DirCache;
 use rustls_acme::{AcmeConfig, ResolvesServerCertAcme};
@@ -190,7 +190,7 @@ async fn create_shared_acme_manager() -> Result<Option<SharedAcmeManager>, Box<d
     let polling_cancel_token = CancellationToken::new();
 
     // Spawn a single background task to poll the ACME state for certificate updates
-    spawn_acme_polling_task(acme_state, polling_cancel_token.clone());
+    spawn_acme_polling_task(acme_state, polling_cancel_token.clone(), all_domains.iter().cloned().collect());
 
     let domains_set: std::collections::HashSet<String> = all_domains.into_iter().collect();
 
@@ -203,10 +203,7 @@ async fn create_shared_acme_manager() -> Result<Option<SharedAcmeManager>, Box<d
 
 /// Spawn a background task that polls the ACME state for certificate acquisition and renewal.
 /// The task will stop when the cancellation token is cancelled or when shutdown/stop_services triggers fire.
-fn spawn_acme_polling_task(
-    mut acme_state: rustls_acme::AcmeState<Box<dyn std::fmt::Debug>, Box<dyn std::fmt::Debug>>,
-    cancel_token: CancellationToken,
-) {
+fn spawn_acme_polling_task(mut acme_state: rustls_acme::AcmeState<Box<dyn std::fmt::Debug>, Box<dyn std::fmt::Debug>>, cancel_token: CancellationToken, domains: Vec<String>) {
     tokio::spawn(async move {
         trace("ACME background polling task started".to_string());
 
@@ -223,9 +220,7 @@ fn spawn_acme_polling_task(
 
         let stop_services_token = triggers
             .get_trigger("stop_services")
-            .map(|t| {
-                t.try_read().map(|guard| guard.clone()).unwrap_or_else(|_| CancellationToken::new())
-            })
+            .map(|t| t.try_read().map(|guard| guard.clone()).unwrap_or_else(|_| CancellationToken::new()))
             .unwrap_or_else(|| CancellationToken::new());
 
         // Poll the ACME state to handle certificate acquisition and renewal
@@ -251,6 +246,13 @@ fn spawn_acme_polling_task(
                     match event {
                         Some(Ok(ok)) => {
                             trace(format!("ACME event: {:?}", ok));
+                            // Published from its own task, as the ACME event cannot be held across an await
+                            if matches!(ok, rustls_acme::EventOk::DeployedNewCert) {
+                                let domains = domains.clone();
+                                tokio::spawn(async move {
+                                    get_trigger_handler().publish(GruxiEvent::CertificateDeployed { domains }).await;
+                                });
+                            }
                         }
                         Some(Err(err)) => {
                             debug(format!("ACME error: {:?}", err));
@@ -3,6 +3,13 @@ use rustls::{ClientConfig, RootCertStore};
 pub fn tls_config() -> ClientConfig {
     let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
 
+    let config = ClientConfig::builder().with_root_certificates(get_root_cert_store()).with_no_client_auth();
+
+    config
+}
+
+// The CA certificates of the system, extended with the webpki roots
+pub fn get_root_cert_store() -> RootCertStore {
     let mut roots = RootCertStore::empty();
 
     let native_certs_result = rustls_native_certs::load_native_certs();
@@ -13,7 +20,5 @@ pub fn tls_config() -> ClientConfig {
     // Extend with webpki-roots
     roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
 
-    let config = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
-
-    config
+    roots
 }
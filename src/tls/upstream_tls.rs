@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme};
+use rustls_pki_types::UnixTime;
+
+use crate::configuration::upstream_tls::UpstreamTlsSettings;
+use crate::http::request_handlers::processors::proxy_helpers::no_verifier::NoVerifier;
+use crate::tls::tls_config::get_root_cert_store;
+
+// Client TLS configuration for the connections to an upstream with its TLS settings. Without certificate verification only the
+// pinned certificates are checked, if any, which is how a backend with a self-signed certificate is trusted safely
+pub fn get_upstream_tls_config(verify_certificates: bool, settings: &UpstreamTlsSettings) -> Result<ClientConfig, String> {
+    let provider = rustls::crypto::aws_lc_rs::default_provider();
+    let supported_algorithms = provider.signature_verification_algorithms;
+    let _ = provider.install_default();
+
+    let mut verifier: Arc<dyn ServerCertVerifier> = if verify_certificates {
+        let roots = if settings.ca_bundle_file.is_empty() {
+            get_root_cert_store()
+        } else {
+            load_ca_bundle(&settings.ca_bundle_file)?
+        };
+        WebPkiServerVerifier::builder(Arc::new(roots))
+            .build()
+            .map_err(|e| format!("Failed to create the certificate verifier: {}", e))?
+    } else {
+        Arc::new(NoVerifier)
+    };
+
+    if !settings.pinned_certificates.is_empty() {
+        verifier = Arc::new(PinnedCertificateVerifier {
+            inner: verifier,
+            pinned_certificates: settings.pinned_certificates.clone(),
+            supported_algorithms,
+        });
+    }
+
+    Ok(ClientConfig::builder().dangerous().with_custom_certificate_verifier(verifier).with_no_client_auth())
+}
+
+// The name to send as SNI and verify the certificate for, when it is not the host of the upstream address
+pub fn get_server_name_override(settings: &UpstreamTlsSettings) -> Option<ServerName<'static>> {
+    if settings.server_name.is_empty() {
+        return None;
+    }
+    ServerName::try_from(settings.server_name.clone()).ok()
+}
+
+// The SHA-256 fingerprint of a DER encoded certificate in lowercase hex, as pinned certificates are given
+pub fn get_certificate_fingerprint(certificate: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, certificate);
+    digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Checks that the CA bundle of the settings, if used, can be loaded, so a missing or broken bundle is found when the configuration is validated
+pub fn check_ca_bundle(verify_certificates: bool, settings: &UpstreamTlsSettings) -> Result<(), String> {
+    if verify_certificates && !settings.ca_bundle_file.is_empty() {
+        load_ca_bundle(&settings.ca_bundle_file)?;
+    }
+    Ok(())
+}
+
+fn load_ca_bundle(path: &str) -> Result<RootCertStore, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("Failed to read CA bundle {}: {}", path, e))?;
+    let mut roots = RootCertStore::empty();
+    for certificate in rustls_pemfile::certs(&mut std::io::Cursor::new(pem)) {
+        let certificate = certificate.map_err(|e| format!("Failed to parse CA bundle {}: {}", path, e))?;
+        roots.add(certificate).map_err(|e| format!("Failed to add a certificate of CA bundle {}: {}", path, e))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("No certificates found in CA bundle {}", path));
+    }
+    Ok(roots)
+}
+
+// Accepts only the pinned certificates, on top of the checks of the inner verifier. Handshake signatures are always verified, so
+// the upstream has to hold the private key of the pinned certificate, even when certificate verification is turned off
+#[derive(Debug)]
+struct PinnedCertificateVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pinned_certificates: Vec<String>,
+    supported_algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertificateVerifier {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], server_name: &ServerName<'_>, ocsp: &[u8], now: UnixTime) -> Result<ServerCertVerified, Error> {
+        if !self.pinned_certificates.contains(&get_certificate_fingerprint(end_entity)) {
+            return Err(Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure));
+        }
+        self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp, now)
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.supported_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.supported_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.supported_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinned_certificate_verifier() {
+        let certified_key = rcgen::generate_simple_self_signed(vec!["backend.internal".to_string()]).unwrap();
+        let certificate = certified_key.cert.der();
+        let server_name = ServerName::try_from("backend.internal").unwrap();
+
+        let get_verifier = |pinned_certificates: Vec<String>| PinnedCertificateVerifier {
+            inner: Arc::new(NoVerifier),
+            pinned_certificates,
+            supported_algorithms: rustls::crypto::aws_lc_rs::default_provider().signature_verification_algorithms,
+        };
+
+        let verifier = get_verifier(vec![get_certificate_fingerprint(certificate)]);
+        assert!(verifier.verify_server_cert(certificate, &[], &server_name, &[], UnixTime::now()).is_ok());
+
+        let verifier = get_verifier(vec!["00".repeat(32)]);
+        assert!(verifier.verify_server_cert(certificate, &[], &server_name, &[], UnixTime::now()).is_err());
+    }
+
+    #[test]
+    fn test_missing_ca_bundle_is_an_error() {
+        let settings = UpstreamTlsSettings {
+            ca_bundle_file: "/nonexistent/gruxi-ca.pem".to_string(),
+            ..Default::default()
+        };
+        assert!(get_upstream_tls_config(true, &settings).is_err());
+        // The CA bundle is not used without certificate verification
+        assert!(get_upstream_tls_config(false, &settings).is_ok());
+        assert!(check_ca_bundle(true, &settings).is_err());
+        assert!(check_ca_bundle(false, &settings).is_ok());
+    }
+}
@@ -0,0 +1,54 @@
+use gruxi::test_support::{TestServer, spawn_test_server, test_configuration};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::sync::OnceLock;
+use tokio::net::TcpStream;
+use tokio::time::{Duration, timeout};
+
+/// HTTP/2 tests against the full Gruxi server, booted in-process with `gruxi::test_support::spawn_test_server`.
+/// The requests are sent with prior knowledge over the plain HTTP binding (h2c), with a small max body size
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+const TEST_MAX_BODY_SIZE: u64 = 2048;
+
+static TEST_SERVER: OnceLock<TestServer> = OnceLock::new();
+
+fn get_test_server() -> &'static TestServer {
+    TEST_SERVER.get_or_init(|| {
+        let mut configuration = test_configuration();
+        configuration.core.server_settings.max_body_size = TEST_MAX_BODY_SIZE;
+        spawn_test_server(configuration).expect("Failed to start the test server")
+    })
+}
+
+/// Sends a POST with the body in chunks of 1 KiB and without Content-Length, as the body has no known size, and returns the status
+async fn send_h2c_post_without_content_length(chunks: usize) -> u16 {
+    let address = get_test_server().get_http_address().expect("The test server has no HTTP binding");
+    let stream = TcpStream::connect(address).await.unwrap();
+    let (mut sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await.unwrap();
+    tokio::spawn(connection);
+
+    let frames = (0..chunks).map(|_| Ok::<_, std::convert::Infallible>(Frame::data(Bytes::from(vec![b'a'; 1024]))));
+    let body = StreamBody::new(futures::stream::iter(frames));
+    let request = hyper::Request::post(format!("http://{}/", address)).body(body).unwrap();
+    assert!(request.headers().get(hyper::header::CONTENT_LENGTH).is_none());
+
+    let response = timeout(TEST_TIMEOUT, sender.send_request(request)).await.unwrap().unwrap();
+    assert_eq!(response.version(), hyper::Version::HTTP_2);
+    let status = response.status().as_u16();
+    let _ = timeout(TEST_TIMEOUT, response.into_body().collect()).await;
+    status
+}
+
+#[tokio::test]
+async fn test_http2_body_without_content_length_over_max_body_size() {
+    let status = send_h2c_post_without_content_length(4).await;
+    assert_eq!(status, 413, "Expected 413 for a body over the max body size of {} bytes", TEST_MAX_BODY_SIZE);
+}
+
+#[tokio::test]
+async fn test_http2_body_without_content_length_within_max_body_size() {
+    let status = send_h2c_post_without_content_length(2).await;
+    assert_ne!(status, 413);
+}
@@ -0,0 +1,111 @@
+use gruxi::configuration::binding_site_relation::BindingSiteRelationship;
+use gruxi::configuration::load_configuration::fetch_configuration_in_db;
+use gruxi::configuration::save_configuration::save_configuration;
+use gruxi::configuration::site::Site;
+use gruxi::core::grux_server::{GruxServer, GruxServerEvent};
+use gruxi::test_support::test_configuration;
+use std::net::TcpListener;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, timeout};
+
+/// Runs Gruxi through the embedding API, the same way the gruxi binary does, on a free port with a temporary database
+#[tokio::test(flavor = "multi_thread")]
+async fn test_embedded_server_serves_reloads_and_stops() {
+    let data_dir = std::env::temp_dir().join(format!("gruxi-embedded-test-{}", std::process::id()));
+    std::fs::create_dir_all(&data_dir).unwrap();
+
+    // Only the plain HTTP binding, on a free port
+    let mut configuration = test_configuration();
+    configuration.core.admin_portal.is_enabled = false;
+    let tls_binding_ids: Vec<String> = configuration.bindings.iter().filter(|b| b.is_tls).map(|b| b.id.clone()).collect();
+    configuration.bindings.retain(|b| !b.is_tls);
+    configuration.binding_sites.retain(|relation| !tls_binding_ids.contains(&relation.binding_id));
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    configuration.bindings[0].ip = "127.0.0.1".to_string();
+    configuration.bindings[0].port = port;
+
+    let server = GruxServer::builder()
+        .configuration(configuration)
+        .database_path(&data_dir.join("gruxi.db").to_string_lossy())
+        .start()
+        .await
+        .expect("Failed to start the embedded server");
+    let mut events = server.subscribe();
+
+    // A second server cannot be started in the same process
+    assert!(GruxServer::builder().start().await.is_err());
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(10), stream.read_to_end(&mut response)).await.unwrap().unwrap();
+    assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 200"));
+
+    // Reloading the unchanged configuration reports no changes, in any order with the reload itself
+    server.reload_configuration().await;
+    let mut reload_events = Vec::new();
+    while reload_events.len() < 2 {
+        reload_events.push(timeout(Duration::from_secs(10), events.recv()).await.unwrap().unwrap());
+    }
+    assert!(reload_events.contains(&GruxServerEvent::ConfigurationReloaded));
+    assert!(reload_events.iter().any(|event| matches!(event, GruxServerEvent::ConfigurationChanged(changes) if changes.is_empty())));
+
+    // A new site on the binding is served without restarting the binding, so open connections stay open
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    assert!(send_keep_alive_request(&mut stream, "localhost").await.starts_with("HTTP/1.1 200"));
+
+    let mut configuration = fetch_configuration_in_db().unwrap();
+    let mut site = Site::new();
+    site.hostnames = vec!["new-site.test".to_string()];
+    configuration.binding_sites.push(BindingSiteRelationship {
+        binding_id: configuration.bindings[0].id.clone(),
+        site_id: site.id.clone(),
+    });
+    configuration.sites.push(site.clone());
+    save_configuration(&mut configuration, false).unwrap();
+
+    server.reload_configuration().await;
+    let mut reload_events = Vec::new();
+    while reload_events.len() < 2 {
+        reload_events.push(timeout(Duration::from_secs(10), events.recv()).await.unwrap().unwrap());
+    }
+    assert!(reload_events.contains(&GruxServerEvent::ConfigurationReloaded));
+    assert!(
+        reload_events
+            .iter()
+            .any(|event| matches!(event, GruxServerEvent::ConfigurationChanged(changes) if changes.added_site_ids == vec![site.id.clone()]))
+    );
+    assert!(send_keep_alive_request(&mut stream, "new-site.test").await.starts_with("HTTP/1.1 "));
+
+    server.stop().await;
+    assert_eq!(timeout(Duration::from_secs(10), events.recv()).await.unwrap().unwrap(), GruxServerEvent::Stopping);
+    assert_eq!(timeout(Duration::from_secs(10), events.recv()).await.unwrap().unwrap(), GruxServerEvent::Stopped);
+    timeout(Duration::from_secs(10), server.wait()).await.unwrap();
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+// Sends a request on the connection and reads the response, leaving the connection open
+async fn send_keep_alive_request(stream: &mut TcpStream, host: &str) -> String {
+    stream.write_all(format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host).as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let bytes_read = timeout(Duration::from_secs(10), stream.read(&mut buffer)).await.unwrap().unwrap();
+        assert!(bytes_read > 0, "Connection was closed");
+        response.extend_from_slice(&buffer[..bytes_read]);
+
+        let text = String::from_utf8_lossy(&response).to_string();
+        if let Some(header_end) = text.find("\r\n\r\n") {
+            let content_length = text[..header_end]
+                .lines()
+                .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|value| value.trim().parse::<usize>().unwrap()))
+                .unwrap_or(0);
+            if response.len() >= header_end + 4 + content_length {
+                return text;
+            }
+        }
+    }
+}
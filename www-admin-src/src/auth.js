@@ -0,0 +1,33 @@
+// Admin API authentication. With Bearer sessions the token is kept in localStorage and sent in the Authorization header.
+// With cookie sessions the browser sends the HttpOnly session cookie by itself, and only the CSRF token is kept here,
+// to be sent along with every request so state-changing ones are accepted.
+
+export const saveSession = (loginData) => {
+  if (loginData.session_cookie) {
+    localStorage.removeItem('gruxi_session_token')
+    localStorage.setItem('gruxi_csrf_token', loginData.csrf_token)
+  } else {
+    localStorage.removeItem('gruxi_csrf_token')
+    localStorage.setItem('gruxi_session_token', loginData.session_token)
+  }
+  localStorage.setItem('gruxi_username', loginData.username)
+}
+
+export const clearSession = () => {
+  localStorage.removeItem('gruxi_session_token')
+  localStorage.removeItem('gruxi_csrf_token')
+  localStorage.removeItem('gruxi_username')
+}
+
+export const hasSession = () => {
+  return !!(localStorage.getItem('gruxi_session_token') || localStorage.getItem('gruxi_csrf_token'))
+}
+
+export const getAuthHeaders = () => {
+  const token = localStorage.getItem('gruxi_session_token')
+  if (token) {
+    return { 'Authorization': `Bearer ${token}` }
+  }
+  const csrfToken = localStorage.getItem('gruxi_csrf_token')
+  return csrfToken ? { 'X-CSRF-Token': csrfToken } : {}
+}